@@ -1,10 +1,13 @@
 pub mod font;
 pub mod frame;
+pub mod lip;
 pub mod map;
 pub mod message;
+pub mod movie;
 pub mod palette;
 pub mod proto;
 pub mod script;
+pub mod sound;
 
 use linearize::Linearize;
 use enum_primitive_derive::Primitive;
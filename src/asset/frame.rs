@@ -6,7 +6,7 @@ use linearize::{static_map, StaticMap};
 use std::io::{self, prelude::*};
 
 pub use id::{FrameId, Idx};
-pub use db::FrameDb;
+pub use db::{critter_anim_codes, FrameDb};
 
 use crate::graphics::Point;
 use crate::graphics::geometry::hex::Direction;
@@ -35,6 +35,10 @@ impl FrameDb {
         })
     }
 
+    pub fn texture_factory(&self) -> &TextureFactory {
+        &self.texture_factory
+    }
+
     // art_get_name()
     /// Returns .frm or .frN file name without path.
     pub fn name(&self, fid: FrameId) -> Option<String> {
@@ -42,6 +46,12 @@ impl FrameDb {
         self.name_no_normalize(fid)
     }
 
+    // art_get_base_name()
+    /// Returns the frame set name as listed in the .lst file, e.g. `hmjmps` for critters.
+    pub fn base_name(&self, fid: FrameId) -> Option<&str> {
+        self.lst[fid.kind()].get(fid.idx() as usize).map(|e| &e.fields[0][..])
+    }
+
     //  art_exists()
     pub fn exists(&self, fid: FrameId) -> bool {
         let fid = self.normalize_fid(fid);
@@ -193,7 +203,7 @@ impl FrameDb {
     }
 }
 
-fn critter_anim_codes(weapon_kind: WeaponKind, anim: CritterAnim) -> Option<(char, char)> {
+pub fn critter_anim_codes(weapon_kind: WeaponKind, anim: CritterAnim) -> Option<(char, char)> {
     use self::WeaponKind::*;
     use self::CritterAnim::*;
     Some(match anim {
@@ -0,0 +1,121 @@
+use byteorder::{BigEndian, ReadBytesExt};
+use std::io::{self, Error, ErrorKind, prelude::*};
+
+/// Sample rate the marker positions are relative to.
+pub const SAMPLE_RATE: u32 = 22050;
+
+const VERSION: u32 = 2;
+
+/// Lip sync data for speech. Markers map positions within the speech sound (in bytes of 16-bit
+/// mono PCM) to phonemes.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Lips {
+    pub phonemes: Vec<u8>,
+    pub markers: Vec<Marker>,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Marker {
+    pub kind: u32,
+    pub pos: u32,
+}
+
+impl Marker {
+    /// Returns marker time in milliseconds since the speech start.
+    pub fn millis(self) -> u32 {
+        (self.pos as u64 / 2 * 1000 / SAMPLE_RATE as u64) as u32
+    }
+}
+
+impl Lips {
+    /// Returns phoneme that should be shown at `millis` since the speech start.
+    pub fn phoneme_at(&self, millis: u32) -> Option<u8> {
+        let i = self.markers.iter().take_while(|m| m.millis() <= millis).count();
+        if i == 0 || i >= self.markers.len() {
+            return None;
+        }
+        self.phonemes.get(i - 1).copied()
+    }
+
+    /// Returns duration of the speech in milliseconds.
+    pub fn duration_millis(&self) -> u32 {
+        self.markers.last().map(|m| m.millis()).unwrap_or(0)
+    }
+}
+
+// lips_load()
+pub fn read_lips(rd: &mut impl Read) -> io::Result<Lips> {
+    let version = rd.read_u32::<BigEndian>()?;
+    if version != VERSION {
+        return Err(Error::new(ErrorKind::InvalidData,
+            format!("unsupported LIP version: {}", version)));
+    }
+    let _unknown1 = rd.read_u32::<BigEndian>()?;
+    let _flags = rd.read_u32::<BigEndian>()?;
+    let _unknown2 = rd.read_u32::<BigEndian>()?;
+    let _unknown3 = rd.read_u32::<BigEndian>()?;
+    let _unknown4 = rd.read_u32::<BigEndian>()?;
+    let phoneme_count = rd.read_u32::<BigEndian>()?;
+    let _unknown5 = rd.read_u32::<BigEndian>()?;
+    let marker_count = rd.read_u32::<BigEndian>()?;
+    if phoneme_count > 0x10000 || marker_count > 0x10000 {
+        return Err(Error::new(ErrorKind::InvalidData, "too many phonemes or markers in LIP"));
+    }
+
+    // Sound file name and extension.
+    let mut name = [0; 8 + 4];
+    rd.read_exact(&mut name)?;
+
+    let mut phonemes = vec![0; phoneme_count as usize];
+    rd.read_exact(&mut phonemes)?;
+
+    let mut markers = Vec::with_capacity(marker_count as usize);
+    for _ in 0..marker_count {
+        let kind = rd.read_u32::<BigEndian>()?;
+        let pos = rd.read_u32::<BigEndian>()?;
+        markers.push(Marker { kind, pos });
+    }
+
+    Ok(Lips {
+        phonemes,
+        markers,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use byteorder::WriteBytesExt;
+
+    fn lip(version: u32) -> Vec<u8> {
+        let mut r = Vec::new();
+        for v in [version, 0, 0, 0, 0, 0, 2, 0, 3] {
+            r.write_u32::<BigEndian>(v).unwrap();
+        }
+        r.extend_from_slice(b"SPEECH01ACM\0");
+        r.extend_from_slice(&[5, 9]);
+        for (kind, pos) in [(1, 0), (0, 4410), (1, 8820)] {
+            r.write_u32::<BigEndian>(kind).unwrap();
+            r.write_u32::<BigEndian>(pos).unwrap();
+        }
+        r
+    }
+
+    #[test]
+    fn read() {
+        let lips = read_lips(&mut &lip(2)[..]).unwrap();
+        assert_eq!(lips.phonemes, &[5, 9]);
+        assert_eq!(lips.markers, &[
+            Marker { kind: 1, pos: 0 },
+            Marker { kind: 0, pos: 4410 },
+            Marker { kind: 1, pos: 8820 },
+        ]);
+        assert_eq!(lips.duration_millis(), 200);
+        assert_eq!(lips.phoneme_at(0), Some(5));
+        assert_eq!(lips.phoneme_at(99), Some(5));
+        assert_eq!(lips.phoneme_at(100), Some(9));
+        assert_eq!(lips.phoneme_at(200), None);
+
+        assert_eq!(read_lips(&mut &lip(1)[..]).unwrap_err().kind(), ErrorKind::InvalidData);
+    }
+}
@@ -1,11 +1,15 @@
 pub mod db;
 
-use byteorder::{BigEndian, ReadBytesExt};
+use bstring::BString;
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
 use enumflags2::{bitflags, BitFlags};
 use log::*;
 use measure_time::*;
 use num_traits::FromPrimitive;
 use std::cmp;
+use linearize::StaticMap;
+use slotmap::SecondaryMap;
+use std::collections::{HashMap, HashSet};
 use std::convert::{TryFrom, TryInto};
 use std::io::{self, Error, ErrorKind, prelude::*};
 
@@ -13,23 +17,18 @@ use crate::asset::*;
 use crate::asset::frame::{FrameId, FrameDb};
 use crate::asset::proto::{MapExit, ProtoId, ProtoDb, SubItem, TargetMap};
 use crate::asset::script::ProgramId;
+use crate::game::GameTime;
 use crate::game::object::{self, *};
 use crate::game::script::*;
 use crate::graphics::{EPoint, Point};
 use crate::graphics::geometry::hex::{Direction, TileGrid};
 use crate::graphics::sprite::OutlineStyle;
+use crate::profile::Profile;
 use crate::util::EnumExt;
 use crate::util::array2d::Array2d;
 
 pub const ELEVATION_COUNT: u32 = 3;
 
-struct ScriptInfo {
-    sid: ScriptIid,
-    program_id: ProgramId,
-    local_var_count: usize,
-    local_var_offset: usize,
-}
-
 #[bitflags]
 #[derive(Clone, Copy, Debug, Linearize, Eq, PartialEq)]
 #[repr(u32)]
@@ -49,13 +48,147 @@ pub type SqrTiles = Vec<Option<Array2d<(u16, u16)>>>;
 /// Unique map ID as defined in `maps.txt`.
 pub type MapId = u32;
 
+#[derive(Clone)]
 pub struct Map {
+    pub version: u32,
+    pub name: BString,
     pub id: MapId,
     pub savegame: bool,
+    /// Game time of the last visit. Only meaningful in savegame maps.
+    pub last_visit: GameTime,
     pub entrance: EPoint,
     pub entrance_direction: Direction,
     pub sqr_tiles: SqrTiles,
     pub map_vars: Box<[i32]>,
+    pub extra: MapExtra,
+}
+
+/// Parts of the map file that are not used by the game. `MapWriter` uses these to write back
+/// the data that hasn't been changed since it was read, so the unmodified map can be written
+/// byte-identical to the original file.
+#[derive(Clone, Default)]
+pub struct MapExtra {
+    /// Raw name bytes including anything after the terminating NUL.
+    name: [u8; 16],
+    map_program_id: i32,
+    flags: u32,
+    header_unk: i32,
+    header_padding: Vec<i32>,
+    local_vars: Vec<i32>,
+    scripts: StaticMap<ScriptKind, Option<ScriptList>>,
+    objects: SecondaryMap<Handle, ObjectExtra>,
+    /// Top-level objects in the file order grouped by elevation.
+    object_order: Vec<Vec<Handle>>,
+}
+
+impl MapExtra {
+    fn script_record(&self, sid: ScriptIid) -> Option<&ScriptRecord> {
+        self.scripts[sid.kind()].as_ref()?.nodes.iter()
+            .flat_map(|n| &n.slots)
+            .find_map(|s| match s {
+                ScriptSlot::Used(s, r) if *s == sid => Some(r),
+                _ => None,
+            })
+    }
+}
+
+#[derive(Clone)]
+struct ScriptList {
+    count: i32,
+    nodes: Vec<ScriptNode>,
+}
+
+impl ScriptList {
+    fn used_sids(&self) -> impl Iterator<Item=ScriptIid> + '_ {
+        self.nodes.iter()
+            .flat_map(|n| &n.slots)
+            .filter_map(|s| match *s {
+                ScriptSlot::Used(sid, _) => Some(sid),
+                _ => None,
+            })
+    }
+}
+
+#[derive(Clone)]
+struct ScriptNode {
+    slots: Vec<ScriptSlot>,
+    count: i32,
+    unk: i32,
+}
+
+#[derive(Clone)]
+enum ScriptSlot {
+    /// Script that was instantiated.
+    Used(ScriptIid, ScriptRecord),
+    /// Script that was ignored because it has no program or is past the node's script count.
+    Unused(ScriptIid, ScriptRecord),
+    /// Garbage in the unused slot.
+    Invalid(u32, [i32; 15]),
+}
+
+#[derive(Clone)]
+struct ScriptRecord {
+    unk1: i32,
+    /// Spatial and time scripts only.
+    elevation_and_tile: i32,
+    /// Spatial scripts only.
+    spatial_radius: i32,
+    flags: i32,
+    program_id: i32,
+    unk2: i32,
+    self_obj_id: i32,
+    local_var_offset: i32,
+    local_var_count: i32,
+    /// return_value, action, ext_param, action_num, script_overrides, unk, how_much, unk
+    tail: [i32; 8],
+}
+
+impl Default for ScriptRecord {
+    fn default() -> Self {
+        Self {
+            unk1: 0,
+            elevation_and_tile: 0,
+            spatial_radius: 0,
+            flags: 0,
+            program_id: -1,
+            unk2: 0,
+            self_obj_id: -1,
+            local_var_offset: -1,
+            local_var_count: 0,
+            tail: [0; 8],
+        }
+    }
+}
+
+/// Raw object fields that are either not used by the game or not representable in `Object`.
+#[derive(Clone)]
+struct ObjectExtra {
+    id: u32,
+    /// Screen shift is reset when the object is placed on the map.
+    screen_shift_raw: Point,
+    screen_shift: Point,
+    frame_idx: i32,
+    elevation: u32,
+    cid: u32,
+    outline_flags: u32,
+    outline: Option<Outline>,
+    script_raw: (i32, i32),
+    script: Option<(ScriptIid, ProgramId)>,
+    inventory_capacity: i32,
+    unk: u32,
+    /// damage_last_turn, combat_state, action_points
+    combat: [u32; 3],
+    item: Option<ItemExtra>,
+}
+
+/// Raw ammo fields of weapon and misc items. The reader fixes them so they're written back
+/// only if the item still has the same `ammo_count` and `ammo_pid` as it had after the read.
+#[derive(Clone)]
+struct ItemExtra {
+    ammo_count_raw: i32,
+    ammo_pid_raw: u32,
+    ammo_count: u32,
+    ammo_pid: Option<ProtoId>,
 }
 
 pub struct MapReader<'a, R: 'a> {
@@ -72,9 +205,15 @@ impl<'a, R: 'a + Read> MapReader<'a, R> {
         // header
 
         let version = self.reader.read_u32::<BigEndian>()?;
+        let profile = Profile::from_map_version(version)
+            .ok_or_else(|| Error::new(ErrorKind::InvalidData,
+                format!("unsupported map version: {}", version)))?;
+        debug!("map version {} ({:?})", version, profile);
+
+        let mut extra = MapExtra::default();
 
-        let mut name = [0; 16];
-        self.reader.read_exact(&mut name[..]).unwrap();
+        self.reader.read_exact(&mut extra.name[..])?;
+        let name = trim_name(&extra.name);
 
         let entrance_pos_lin = self.reader.read_i32::<BigEndian>()?;
         let entrance_pos = TileGrid::default().linear_to_rect_inv(entrance_pos_lin as u32);
@@ -85,19 +224,22 @@ impl<'a, R: 'a + Read> MapReader<'a, R> {
             .ok_or_else(|| Error::new(ErrorKind::InvalidData, "invalid entrance direction"))?;
         let local_var_count = cmp::max(self.reader.read_i32::<BigEndian>()?, 0) as usize;
 
-        let program_id = self.read_program_id(0)?;
+        extra.map_program_id = self.reader.read_i32::<BigEndian>()?;
+        let program_id = decode_program_id(extra.map_program_id, 0);
         debug!("map program_id: {:?}", program_id);
 
         let flags = self.reader.read_u32::<BigEndian>()?;
         debug!("flags: {:04b}", flags);
+        extra.flags = flags;
         let savegame = flags & 0x1 != 0;
 
-        let _ = self.reader.read_i32::<BigEndian>()?;
+        extra.header_unk = self.reader.read_i32::<BigEndian>()?;
         let map_var_count = cmp::max(self.reader.read_i32::<BigEndian>()?, 0) as usize;
         let id = self.reader.read_i32::<BigEndian>()?.try_into().unwrap();
-        let _time = self.reader.read_u32::<BigEndian>()?;
+        let last_visit = GameTime::from_decis(self.reader.read_u32::<BigEndian>()?);
 
-        self.reader.read_exact(&mut [0; 44 * 4][..])?;
+        extra.header_padding = vec![0; 44];
+        self.reader.read_i32_into::<BigEndian>(&mut extra.header_padding)?;
 
         // map global vars
 
@@ -114,17 +256,21 @@ impl<'a, R: 'a + Read> MapReader<'a, R> {
         }
 
         let sqr_tiles = self.read_sqr_tiles(flags)?;
-        self.read_scripts(&local_vars, savegame)?;
+        self.read_scripts(&local_vars, savegame, &mut extra)?;
+        extra.local_vars = local_vars;
 
         if let Some(program_id) = program_id {
             self.make_map_script(program_id)?;
         }
 
-        self.read_objects(version)?;
+        self.read_objects(profile, &mut extra)?;
 
         Ok(Map {
+            version,
+            name,
             id,
             savegame,
+            last_visit,
             entrance: EPoint {
                 elevation: entrance_elevation,
                 point: entrance_pos,
@@ -132,128 +278,160 @@ impl<'a, R: 'a + Read> MapReader<'a, R> {
             entrance_direction,
             sqr_tiles,
             map_vars: map_vars.into(),
+            extra,
         })
     }
 
-    fn read_scripts(&mut self, local_vars: &[i32], savegame: bool) -> io::Result<()> {
+    fn read_scripts(&mut self, local_vars: &[i32], savegame: bool, extra: &mut MapExtra)
+        -> io::Result<()>
+    {
         for script_kind in ScriptKind::iter() {
             debug!("reading {:?} scripts", script_kind);
             let script_count = self.reader.read_i32::<BigEndian>()?;
             debug!("script_count: {}", script_count);
+            let mut list = ScriptList {
+                count: script_count,
+                nodes: Vec::new(),
+            };
             if script_count > 0 {
                 let script_count = script_count as usize;
                 const NODE_LEN: usize = 16;
                 let node_count = script_count / NODE_LEN + !script_count.is_multiple_of(NODE_LEN) as usize;
                 debug!("node_count: {}", node_count);
-                let mut scripts = Vec::new();
                 for _ in 0..node_count {
-                    scripts.clear();
+                    let mut slots = Vec::with_capacity(NODE_LEN);
                     for _ in 0..NODE_LEN {
-                        if let Some(script) = self.read_script()? {
-                            scripts.push(script);
-                        }
+                        slots.push(self.read_script()?);
                     }
 
                     let node_script_count = self.reader.read_i32::<BigEndian>()?;
                     debug!("node_script_count: {}", node_script_count);
-                    let _ = self.reader.read_i32::<BigEndian>()?;
+                    let unk = self.reader.read_i32::<BigEndian>()?;
 
-                    scripts.truncate(node_script_count as usize);
+                    let mut used_count = 0;
+                    for slot in &mut slots {
+                        let ScriptSlot::Unused(sid, record) = slot else { continue };
+                        let Some(program_id) = decode_program_id(record.program_id, 1) else {
+                            continue
+                        };
+                        if used_count >= node_script_count {
+                            continue;
+                        }
+                        used_count += 1;
 
-                    for script in &scripts {
-                        let local_vars = if savegame && script.local_var_count > 0 {
-                            let end = script.local_var_offset + script.local_var_count;
-                            Some(local_vars[script.local_var_offset..end].into())
+                        let local_vars = if savegame && record.local_var_count > 0 {
+                            let offset = cmp::max(record.local_var_offset, 0) as usize;
+                            let end = offset + record.local_var_count as usize;
+                            Some(local_vars[offset..end].into())
                         } else {
                             None
                         };
-                        self.scripts.instantiate(script.sid, script.program_id, local_vars)?;
+                        self.scripts.instantiate(*sid, program_id, local_vars)?;
+                        *slot = ScriptSlot::Used(*sid, record.clone());
                     }
+
+                    list.nodes.push(ScriptNode {
+                        slots,
+                        count: node_script_count,
+                        unk,
+                    });
                 }
             }
+            extra.scripts[script_kind] = Some(list);
         }
         Ok(())
     }
 
-    fn read_script(&mut self) -> io::Result<Option<ScriptInfo>> {
+    fn read_script(&mut self) -> io::Result<ScriptSlot> {
         // Maps contain garbage in unused slots but the exact size of the data to skip depends
         // on the script kinds.
 
-        let sid = ScriptIid::read(self.reader);
-        let sid = match sid {
-            Ok(sid) => sid,
-            Err(ref e) if e.kind() == ErrorKind::InvalidData => {
-                self.reader.read_exact(&mut [0; 15 * 4][..])?;
-                return Ok(None);
-            }
-            Err(e) => return Err(e),
+        let sid_raw = self.reader.read_u32::<BigEndian>()?;
+        let Some(sid) = ScriptIid::from_packed(sid_raw) else {
+            let mut garbage = [0; 15];
+            self.reader.read_i32_into::<BigEndian>(&mut garbage)?;
+            return Ok(ScriptSlot::Invalid(sid_raw, garbage));
         };
         trace!("sid: {:?}", sid);
 
-        let _ = self.reader.read_i32::<BigEndian>()?;
+        let unk1 = self.reader.read_i32::<BigEndian>()?;
 
-        match sid.kind() {
+        let (elevation_and_tile, spatial_radius) = match sid.kind() {
             ScriptKind::Spatial => {
-                let _elevation_and_tile = self.reader.read_i32::<BigEndian>()?;
-                let _spatial_radius = self.reader.read_i32::<BigEndian>()?;
+                (self.reader.read_i32::<BigEndian>()?, self.reader.read_i32::<BigEndian>()?)
             }
-            ScriptKind::Time => {
-                let _elevation_and_tile = self.reader.read_i32::<BigEndian>()?;
-            }
-            _ => {}
-        }
+            ScriptKind::Time => (self.reader.read_i32::<BigEndian>()?, 0),
+            _ => (0, 0),
+        };
 
-        let _flags = self.reader.read_i32::<BigEndian>()?;
+        let flags = self.reader.read_i32::<BigEndian>()?;
 
-        let program_id = self.read_program_id(1)?;
-        trace!("program_id: {:?}", program_id);
+        let program_id = self.reader.read_i32::<BigEndian>()?;
+        trace!("program_id: {:?}", decode_program_id(program_id, 1));
 
-        let _ = self.reader.read_i32::<BigEndian>()?;
+        let unk2 = self.reader.read_i32::<BigEndian>()?;
         let self_obj_id = self.reader.read_i32::<BigEndian>()?;
         trace!("self_obj_id: {}", self_obj_id);
-        let local_var_offset = cmp::max(self.reader.read_i32::<BigEndian>()?, 0) as usize;
-        let local_var_count = cmp::max(self.reader.read_i32::<BigEndian>()?, 0) as usize;
-        let _return_value = self.reader.read_i32::<BigEndian>()?;
-        let _action = self.reader.read_i32::<BigEndian>()?;
-        let _ext_param = self.reader.read_i32::<BigEndian>()?;
-        let _action_num = self.reader.read_i32::<BigEndian>()?;
-        let _script_overrides = self.reader.read_i32::<BigEndian>()?;
-        let _unk1 = self.reader.read_i32::<BigEndian>()?;
-        let _how_much = self.reader.read_i32::<BigEndian>()?;
-        let _unk2 = self.reader.read_i32::<BigEndian>()?;
-
-        if let Some(program_id) = program_id {
-            Ok(Some(ScriptInfo {
-                sid,
-                program_id,
-                local_var_count,
-                local_var_offset,
-            }))
-        } else {
-            Ok(None)
-        }
+        let local_var_offset = self.reader.read_i32::<BigEndian>()?;
+        let local_var_count = self.reader.read_i32::<BigEndian>()?;
+        let mut tail = [0; 8];
+        self.reader.read_i32_into::<BigEndian>(&mut tail)?;
+
+        // Becomes `ScriptSlot::Used` once instantiated.
+        Ok(ScriptSlot::Unused(sid, ScriptRecord {
+            unk1,
+            elevation_and_tile,
+            spatial_radius,
+            flags,
+            program_id,
+            unk2,
+            self_obj_id,
+            local_var_offset,
+            local_var_count,
+            tail,
+        }))
     }
 
-    fn read_objects(&mut self, version: u32) -> io::Result<()> {
+    fn read_objects(&mut self, profile: Profile, extra: &mut MapExtra) -> io::Result<()> {
         let total_obj_count = self.reader.read_i32::<BigEndian>()?;
         debug!("object count: {}", total_obj_count);
         for elev in 0..ELEVATION_COUNT {
             let obj_count = self.reader.read_u32::<BigEndian>()?;
             debug!("object count at elevation {}: {}", elev, obj_count);
 
+            let mut order = Vec::with_capacity(obj_count as usize);
             for _ in 0..obj_count {
-                let obj = self.read_object(version != 19)?;
+                let (obj, obj_extra) = self.read_object_extra(profile, extra)?;
                 let script = obj.script;
-                let objh = self.objects.insert(obj);
+                let objh = Self::insert_object(self.objects, obj, obj_extra, extra);
                 if let Some((sid, _)) = script {
                     self.scripts.attach_to_object(sid, objh);
                 }
+                order.push(objh);
             }
+            extra.object_order.push(order);
         }
         Ok(())
     }
 
-    fn read_object(&mut self, f2: bool) -> io::Result<Object> {
+    fn insert_object(objects: &mut Objects, obj: Object, mut obj_extra: ObjectExtra,
+        extra: &mut MapExtra) -> Handle
+    {
+        let h = objects.insert(obj);
+        obj_extra.screen_shift = objects.get(h).screen_shift;
+        extra.objects.insert(h, obj_extra);
+        h
+    }
+
+    pub fn read_object(&mut self, profile: Profile) -> io::Result<Object> {
+        self.read_object_extra(profile, &mut MapExtra::default()).map(|(obj, _)| obj)
+    }
+
+    /// Reads object and its inventory. The extra data of the inventory objects goes directly into
+    /// the `extra`.
+    fn read_object_extra(&mut self, profile: Profile, extra: &mut MapExtra)
+        -> io::Result<(Object, ObjectExtra)>
+    {
         let id = self.reader.read_u32::<BigEndian>()?;
 
         trace!("object ID {}", id);
@@ -268,7 +446,8 @@ impl<'a, R: 'a + Read> MapReader<'a, R> {
         let screen_pos = Point::new(
             self.reader.read_i32::<BigEndian>()?,
             self.reader.read_i32::<BigEndian>()?);
-        let frame_idx = cmp::max(self.reader.read_i32::<BigEndian>()?, 0) as usize;
+        let frame_idx_raw = self.reader.read_i32::<BigEndian>()?;
+        let frame_idx = cmp::max(frame_idx_raw, 0) as usize;
         let direction = self.reader.read_u32::<BigEndian>()?;
         let direction = Direction::from_u32(direction)
             .ok_or_else(|| Error::new(ErrorKind::InvalidData,
@@ -287,21 +466,24 @@ impl<'a, R: 'a + Read> MapReader<'a, R> {
         let pid = ProtoId::read(self.reader)?;
         let proto = self.proto_db.proto(pid)?;
         trace!("{:?} {:?}", pid, proto.borrow().name());
-        let _cid = self.reader.read_u32::<BigEndian>()?;
+        let cid = self.reader.read_u32::<BigEndian>()?;
         let light_emitter = LightEmitter {
             radius: self.reader.read_i32::<BigEndian>()? as u32,
             intensity: self.reader.read_i32::<BigEndian>()? as u32,
         };
-        let outline = self.read_outline()?;
+        let outline_flags = self.reader.read_u32::<BigEndian>()?;
+        let outline = Self::decode_outline(outline_flags)?;
         trace!("outline: {:?}", outline);
 
-        let script = self.read_obj_script()?;
+        let sid_raw = self.reader.read_i32::<BigEndian>()?;
+        let program_id_raw = self.reader.read_i32::<BigEndian>()?;
+        let script = Self::decode_obj_script(sid_raw, program_id_raw)?;
 
         // proto update data
 
         let inventory_len = usize::try_from(self.reader.read_u32::<BigEndian>()?).unwrap();
-        let _inventory_capacity = self.reader.read_i32::<BigEndian>()? as usize;
-        let _ = self.reader.read_u32::<BigEndian>()?;
+        let inventory_capacity = self.reader.read_i32::<BigEndian>()?;
+        let unk = self.reader.read_u32::<BigEndian>()?;
 
         let updated_flags = self.reader.read_u32::<BigEndian>()?;
         let updated_flags = BitFlags::from_bits(updated_flags)
@@ -309,11 +491,11 @@ impl<'a, R: 'a + Read> MapReader<'a, R> {
                     format!("unknown updated flags: {:x}", updated_flags)))?;
         trace!("updated_flags: {:?}", updated_flags);
 
+        let mut combat = [0; 3];
+        let mut item_extra = None;
         let sub = if pid.kind() == EntityKind::Critter {
-            // combat data
-            let _damage_last_turn = self.reader.read_u32::<BigEndian>()?;
-            let _combat_state = self.reader.read_u32::<BigEndian>()?;
-            let _action_points = self.reader.read_u32::<BigEndian>()?;
+            // combat data: damage_last_turn, combat_state, action_points
+            self.reader.read_u32_into::<BigEndian>(&mut combat)?;
 
             let damage_flags = self.reader.read_u32::<BigEndian>()?;
             let damage_flags = BitFlags::from_bits(damage_flags)
@@ -336,6 +518,7 @@ impl<'a, R: 'a + Read> MapReader<'a, R> {
                     ai_packet,
                     team_id,
                     who_hit_me,
+                    fleeing: false,
                 },
                 dude: None,
             })
@@ -350,8 +533,9 @@ impl<'a, R: 'a + Read> MapReader<'a, R> {
                     let proto = proto.borrow();
                     match proto.sub.as_item().unwrap().sub {
                         SubItem::Weapon(ref proto) => {
-                            let _ammo_count = self.reader.read_i32::<BigEndian>()?;
-                            let ammo_proto_id = ProtoId::from_packed(self.reader.read_u32::<BigEndian>()?);
+                            let ammo_count_raw = self.reader.read_i32::<BigEndian>()?;
+                            let ammo_pid_raw = self.reader.read_u32::<BigEndian>()?;
+                            let ammo_proto_id = ProtoId::from_packed(ammo_pid_raw);
 
                             // object_fix_weapon_ammo()
                             let ammo_count = proto.max_ammo_count;
@@ -361,6 +545,12 @@ impl<'a, R: 'a + Read> MapReader<'a, R> {
                             } else {
                                 None
                             };
+                            item_extra = Some(ItemExtra {
+                                ammo_count_raw,
+                                ammo_pid_raw,
+                                ammo_count,
+                                ammo_pid: ammo_proto_id,
+                            });
                             SubObject::Item(object::Item { ammo_count, ammo_proto })
                         }
                         SubItem::Ammo(_) => {
@@ -368,16 +558,22 @@ impl<'a, R: 'a + Read> MapReader<'a, R> {
                             SubObject::Item(object::Item { ammo_count, ammo_proto: None })
                         }
                         SubItem::Misc(ref proto) => {
-                            let ammo_count = self.reader.read_i32::<BigEndian>()?;
+                            let ammo_count_raw = self.reader.read_i32::<BigEndian>()?;
 
                             // object_fix_weapon_ammo()
-                            let ammo_count = ammo_count.try_into()
+                            let ammo_count = ammo_count_raw.try_into()
                                 .unwrap_or(proto.max_ammo_count);
+                            item_extra = Some(ItemExtra {
+                                ammo_count_raw,
+                                ammo_pid_raw: 0,
+                                ammo_count,
+                                ammo_pid: None,
+                            });
                             SubObject::Item(object::Item { ammo_count, ammo_proto: None })
                         }
                         SubItem::Key(_) => {
-                            let _key_code = self.reader.read_i32::<BigEndian>()?;
-                            SubObject::None
+                            let id = self.reader.read_i32::<BigEndian>()?;
+                            SubObject::Key(object::Key { id })
                         }
                         _ => SubObject::None
                     }
@@ -408,7 +604,7 @@ impl<'a, R: 'a + Read> MapReader<'a, R> {
                             SubObject::Scenery(object::Scenery::Elevator(object::Elevator { kind, level }))
                         }
                         SceneryKind::LadderDown | SceneryKind::LadderUp => {
-                            let map = if f2 {
+                            let map = if profile == Profile::Fallout2 {
                                 self.reader.read_i32::<BigEndian>()?
                             } else {
                                 0
@@ -467,8 +663,8 @@ impl<'a, R: 'a + Read> MapReader<'a, R> {
             trace!("loading inventory item {}/{}", i, inventory_len);
             let count = self.reader.read_i32::<BigEndian>()?.try_into().unwrap();
             trace!("item count: {}", count);
-            let object = self.read_object(f2)?;
-            let object = self.objects.insert(object);
+            let (object, obj_extra) = self.read_object_extra(profile, extra)?;
+            let object = Self::insert_object(self.objects, object, obj_extra, extra);
             inventory.items.push(InventoryItem {
                 object,
                 count,
@@ -488,14 +684,39 @@ impl<'a, R: 'a + Read> MapReader<'a, R> {
         r.outline = outline;
         r.script = script;
 
-        Ok(r)
+        let obj_extra = ObjectExtra {
+            id,
+            screen_shift_raw: screen_shift,
+            screen_shift,
+            frame_idx: frame_idx_raw,
+            elevation,
+            cid,
+            outline_flags,
+            outline,
+            script_raw: (sid_raw, program_id_raw),
+            script,
+            inventory_capacity,
+            unk,
+            combat,
+            item: item_extra,
+        };
+
+        Ok((r, obj_extra))
     }
 
-    fn read_obj_script(&mut self) -> io::Result<Option<(ScriptIid, ProgramId)>> {
-        let sid = ScriptIid::read_opt(self.reader)?;
+    fn decode_obj_script(sid: i32, program_id: i32)
+        -> io::Result<Option<(ScriptIid, ProgramId)>>
+    {
+        let sid = if sid >= 0 {
+            Some(ScriptIid::from_packed(sid as u32)
+                .ok_or_else(|| Error::new(ErrorKind::InvalidData,
+                    format!("malformed SID: {:x}", sid)))?)
+        } else {
+            None
+        };
         trace!("sid: {:?}", sid);
 
-        let program_id = self.read_program_id(1)?;
+        let program_id = decode_program_id(program_id, 1);
         trace!("program_id: {:?}", program_id);
 
         if sid.is_some() != program_id.is_some() {
@@ -510,8 +731,7 @@ impl<'a, R: 'a + Read> MapReader<'a, R> {
         })
     }
 
-    fn read_outline(&mut self) -> io::Result<Option<Outline>> {
-        let flags_u32 = self.reader.read_u32::<BigEndian>()?;
+    fn decode_outline(flags_u32: u32) -> io::Result<Option<Outline>> {
         let flags = &mut BitFlags::from_bits(flags_u32)
             .ok().ok_or_else(|| Error::new(ErrorKind::InvalidData,
                 format!("unknown object outline flags: {:x}", flags_u32)))?;
@@ -523,7 +743,7 @@ impl<'a, R: 'a + Read> MapReader<'a, R> {
         }
 
         let translucent = take_bit(flags, OutlineFlag::Translucent);
-        let disabled = take_bit(flags, OutlineFlag::Translucent);
+        let disabled = take_bit(flags, OutlineFlag::Disabled);
 
         let style =
             if take_bit(flags, OutlineFlag::GlowingRed) { OutlineStyle::GlowingRed }
@@ -560,13 +780,6 @@ impl<'a, R: 'a + Read> MapReader<'a, R> {
         Ok(())
     }
 
-    fn read_program_id(&mut self, offset: i32) -> io::Result<Option<ProgramId>> {
-        Ok(self.reader.read_i32::<BigEndian>()?
-            .checked_add(offset)
-            .and_then(|v| v.try_into().ok())
-            .and_then(ProgramId::new))
-    }
-
     fn read_sqr_tiles(&mut self, flags: u32) -> io::Result<SqrTiles> {
         let mut sqr_tiles: Vec<Option<_>> = Vec::with_capacity(ELEVATION_COUNT as usize);
         for i in 0..ELEVATION_COUNT {
@@ -588,3 +801,954 @@ impl<'a, R: 'a + Read> MapReader<'a, R> {
         Ok(sqr_tiles)
     }
 }
+
+pub struct MapWriter<'a, W: 'a> {
+    pub writer: &'a mut W,
+    pub objects: &'a Objects,
+    pub scripts: &'a Scripts,
+}
+
+impl<'a, W: 'a + Write> MapWriter<'a, W> {
+    /// Writes `map` header and tiles followed by the current state of `scripts` and `objects`.
+    /// The dude, temporary objects and the map script object are never written, the map script
+    /// is referenced from the header only.
+    ///
+    /// The parts of the file that are not used by the game are taken from `map.extra`. Writing
+    /// the unmodified map read by `MapReader` produces byte-identical output.
+    pub fn write(&mut self, map: &Map) -> io::Result<()> {
+        debug_time!("MapWriter::write()");
+
+        let profile = Profile::from_map_version(map.version)
+            .ok_or_else(|| Error::new(ErrorKind::InvalidInput,
+                format!("unsupported map version: {}", map.version)))?;
+        let extra = &map.extra;
+        let objects = self.top_level_objects(extra);
+        let obj_ids = self.assign_obj_ids(&objects, extra);
+        let scripts = self.scripts_to_write();
+        let (local_vars, local_var_layout) = Self::local_vars(&scripts, map);
+
+        // header
+
+        self.writer.write_u32::<BigEndian>(map.version)?;
+
+        let name = if trim_name(&extra.name) == map.name {
+            extra.name
+        } else {
+            let mut name = [0; 16];
+            let len = cmp::min(map.name.len(), name.len() - 1);
+            name[..len].copy_from_slice(&map.name.as_bytes()[..len]);
+            name
+        };
+        self.writer.write_all(&name[..])?;
+
+        self.writer.write_i32::<BigEndian>(Self::tile_num(map.entrance.point))?;
+        self.writer.write_u32::<BigEndian>(map.entrance.elevation)?;
+        self.writer.write_u32::<BigEndian>(map.entrance_direction as u32)?;
+        self.writer.write_i32::<BigEndian>(local_vars.len() as i32)?;
+
+        let map_program_id = self.scripts.map_sid()
+            .map(|sid| self.scripts.get(sid).unwrap().program_id);
+        let map_program_id = if decode_program_id(extra.map_program_id, 0) == map_program_id {
+            extra.map_program_id
+        } else {
+            encode_program_id(map_program_id, 0)
+        };
+        self.writer.write_i32::<BigEndian>(map_program_id)?;
+
+        let mut flags = extra.flags & !0xf | map.savegame as u32;
+        for (i, tiles) in map.sqr_tiles.iter().enumerate() {
+            if tiles.is_none() {
+                flags |= 1 << (i + 1);
+            }
+        }
+        self.writer.write_u32::<BigEndian>(flags)?;
+
+        self.writer.write_i32::<BigEndian>(extra.header_unk)?;
+        self.writer.write_i32::<BigEndian>(map.map_vars.len() as i32)?;
+        self.writer.write_i32::<BigEndian>(map.id as i32)?;
+        self.writer.write_u32::<BigEndian>(map.last_visit.as_decis())?;
+
+        if extra.header_padding.len() == 44 {
+            for &v in &extra.header_padding {
+                self.writer.write_i32::<BigEndian>(v)?;
+            }
+        } else {
+            self.writer.write_all(&[0; 44 * 4][..])?;
+        }
+
+        for &v in map.map_vars.iter().chain(local_vars.iter()) {
+            self.writer.write_i32::<BigEndian>(v)?;
+        }
+
+        self.write_sqr_tiles(&map.sqr_tiles)?;
+        self.write_scripts(&scripts, &local_var_layout, &obj_ids, extra)?;
+        self.write_objects(&objects, profile, &obj_ids, extra)?;
+
+        Ok(())
+    }
+
+    /// Writes single object and its inventory as it's stored in the `SAVE.DAT`.
+    /// `elevation` is used for objects that are not on the map (e.g. inventory items).
+    pub fn write_object(&mut self, h: Handle, elevation: u32, profile: Profile) -> io::Result<()> {
+        self.write_object0(h, elevation, profile, &HashMap::new(), &MapExtra::default())
+    }
+
+    /// Returns top-level objects that will be written grouped by elevation. The objects that were
+    /// read from the map keep their original order.
+    fn top_level_objects(&self, extra: &MapExtra) -> Vec<Vec<Handle>> {
+        let map_obj = self.scripts.map_sid()
+            .and_then(|sid| self.scripts.get(sid).unwrap().object);
+        let mut in_inventory = HashSet::new();
+        for h in self.objects.iter() {
+            for item in &self.objects.get(h).inventory.items {
+                in_inventory.insert(item.object);
+            }
+        }
+        let is_top_level = |h: Handle| {
+            if in_inventory.contains(&h) || Some(h) == map_obj {
+                return false;
+            }
+            let obj = self.objects.get(h);
+            !obj.is_dude() && !obj.flags.contains(Flag::Temp)
+        };
+
+        let mut r = vec![Vec::new(); ELEVATION_COUNT as usize];
+        let mut done = HashSet::new();
+        for (elevation, order) in extra.object_order.iter().enumerate() {
+            for &h in order {
+                if self.objects.contains(h) && is_top_level(h)
+                    && self.elevation(h, extra) == elevation as u32
+                {
+                    r[elevation].push(h);
+                    done.insert(h);
+                }
+            }
+        }
+        for h in self.objects.iter() {
+            if !done.contains(&h) && is_top_level(h) {
+                r[self.elevation(h, extra) as usize].push(h);
+            }
+        }
+        r
+    }
+
+    fn elevation(&self, h: Handle, extra: &MapExtra) -> u32 {
+        self.objects.get(h).try_pos().map(|p| p.elevation)
+            .or_else(|| extra.objects.get(h).map(|e| e.elevation))
+            .filter(|&e| e < ELEVATION_COUNT)
+            .unwrap_or(0)
+    }
+
+    /// Objects that were read from the map keep their IDs, new objects get unused IDs.
+    fn assign_obj_ids(&self, objects: &[Vec<Handle>], extra: &MapExtra) -> HashMap<Handle, u32> {
+        fn assign(objects: &Objects, h: Handle, extra: &MapExtra, next_id: &mut u32,
+            obj_ids: &mut HashMap<Handle, u32>)
+        {
+            let id = if let Some(e) = extra.objects.get(h) {
+                e.id
+            } else {
+                *next_id += 1;
+                *next_id
+            };
+            obj_ids.insert(h, id);
+            for item in &objects.get(h).inventory.items {
+                assign(objects, item.object, extra, next_id, obj_ids);
+            }
+        }
+
+        let mut next_id = extra.objects.values().map(|e| e.id).max().unwrap_or(0);
+        let mut r = HashMap::new();
+        for &h in objects.iter().flatten() {
+            assign(self.objects, h, extra, &mut next_id, &mut r);
+        }
+        r
+    }
+
+    fn scripts_to_write(&self) -> Vec<(ScriptIid, &'a Script)> {
+        let mut r: Vec<_> = self.scripts.iter()
+            .filter(|&(sid, _)| Some(sid) != self.scripts.map_sid())
+            .collect();
+        r.sort_by_key(|&(sid, _)| (sid.kind(), sid.id()));
+        r
+    }
+
+    /// Returns local vars and `(offset, count)` of the local vars of each script.
+    /// Non-savegame maps keep the local vars as they were read since the game doesn't use them.
+    fn local_vars(scripts: &[(ScriptIid, &Script)], map: &Map)
+        -> (Vec<i32>, HashMap<ScriptIid, (i32, i32)>)
+    {
+        let mut local_vars = map.extra.local_vars.clone();
+        let mut layout = HashMap::new();
+        for &(sid, script) in scripts {
+            let record = map.extra.script_record(sid);
+            let len = script.local_vars.len();
+            let (offset, count) = if !map.savegame {
+                record.map(|r| (r.local_var_offset, r.local_var_count)).unwrap_or((-1, 0))
+            } else if let Some(r) = record.filter(|r| r.local_var_offset >= 0
+                && r.local_var_count as usize == len
+                && r.local_var_offset as usize + len <= local_vars.len())
+            {
+                (r.local_var_offset, r.local_var_count)
+            } else if len == 0 {
+                (-1, 0)
+            } else {
+                let offset = local_vars.len();
+                local_vars.resize(offset + len, 0);
+                (offset as i32, len as i32)
+            };
+            if map.savegame && len > 0 {
+                local_vars[offset as usize..offset as usize + len]
+                    .copy_from_slice(&script.local_vars);
+            }
+            layout.insert(sid, (offset, count));
+        }
+        (local_vars, layout)
+    }
+
+    fn write_scripts(&mut self,
+        scripts: &[(ScriptIid, &Script)],
+        local_var_layout: &HashMap<ScriptIid, (i32, i32)>,
+        obj_ids: &HashMap<Handle, u32>,
+        extra: &MapExtra,
+    ) -> io::Result<()> {
+        const NODE_LEN: usize = 16;
+
+        let record = |sid: ScriptIid, script: &Script| {
+            let (local_var_offset, local_var_count) = local_var_layout[&sid];
+            let self_obj_id = script.object
+                .and_then(|h| obj_ids.get(&h))
+                .map(|&id| id as i32)
+                .unwrap_or(-1);
+            let existing = extra.script_record(sid);
+            // Keep the original self object ID unless the script is attached to a new object.
+            let self_obj_id = existing
+                .filter(|_| script.object.is_none_or(|h| extra.objects.contains_key(h)))
+                .map(|r| r.self_obj_id)
+                .unwrap_or(self_obj_id);
+            ScriptRecord {
+                program_id: encode_program_id(Some(script.program_id), 1),
+                self_obj_id,
+                local_var_offset,
+                local_var_count,
+                ..existing.cloned().unwrap_or_default()
+            }
+        };
+
+        for script_kind in ScriptKind::iter() {
+            let scripts: Vec<_> = scripts.iter()
+                .filter(|(sid, _)| sid.kind() == script_kind)
+                .collect();
+
+            let list = extra.scripts[script_kind].as_ref().filter(|list| {
+                let mut used: Vec<_> = list.used_sids().map(|sid| sid.pack()).collect();
+                used.sort();
+                used.iter().copied().eq(scripts.iter().map(|(sid, _)| sid.pack()))
+            });
+            if let Some(list) = list {
+                // The same scripts as in the original map, write them in the original layout.
+                let scripts: HashMap<_, _> = scripts.iter().map(|&&(sid, s)| (sid, s)).collect();
+                self.writer.write_i32::<BigEndian>(list.count)?;
+                for node in &list.nodes {
+                    for slot in &node.slots {
+                        match slot {
+                            ScriptSlot::Used(sid, _) => {
+                                let record = record(*sid, scripts[sid]);
+                                self.write_script(sid.pack(), script_kind, &record)?;
+                            }
+                            ScriptSlot::Unused(sid, record) => {
+                                self.write_script(sid.pack(), script_kind, record)?;
+                            }
+                            &ScriptSlot::Invalid(sid, ref garbage) => {
+                                self.writer.write_u32::<BigEndian>(sid)?;
+                                for &v in garbage {
+                                    self.writer.write_i32::<BigEndian>(v)?;
+                                }
+                            }
+                        }
+                    }
+                    self.writer.write_i32::<BigEndian>(node.count)?;
+                    self.writer.write_i32::<BigEndian>(node.unk)?;
+                }
+                continue;
+            }
+
+            self.writer.write_i32::<BigEndian>(scripts.len() as i32)?;
+            for node in scripts.chunks(NODE_LEN) {
+                for i in 0..NODE_LEN {
+                    if let Some(&&(sid, script)) = node.get(i) {
+                        self.write_script(sid.pack(), script_kind, &record(sid, script))?;
+                    } else {
+                        // Unused slot.
+                        self.writer.write_i32::<BigEndian>(-1)?;
+                        self.writer.write_all(&[0; 15 * 4][..])?;
+                    }
+                }
+                self.writer.write_i32::<BigEndian>(node.len() as i32)?;
+                self.writer.write_i32::<BigEndian>(0)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn write_script(&mut self, sid: u32, kind: ScriptKind, record: &ScriptRecord)
+        -> io::Result<()>
+    {
+        self.writer.write_u32::<BigEndian>(sid)?;
+        self.writer.write_i32::<BigEndian>(record.unk1)?;
+        match kind {
+            ScriptKind::Spatial => {
+                self.writer.write_i32::<BigEndian>(record.elevation_and_tile)?;
+                self.writer.write_i32::<BigEndian>(record.spatial_radius)?;
+            }
+            ScriptKind::Time => {
+                self.writer.write_i32::<BigEndian>(record.elevation_and_tile)?;
+            }
+            _ => {}
+        }
+        self.writer.write_i32::<BigEndian>(record.flags)?;
+        self.writer.write_i32::<BigEndian>(record.program_id)?;
+        self.writer.write_i32::<BigEndian>(record.unk2)?;
+        self.writer.write_i32::<BigEndian>(record.self_obj_id)?;
+        self.writer.write_i32::<BigEndian>(record.local_var_offset)?;
+        self.writer.write_i32::<BigEndian>(record.local_var_count)?;
+        for &v in &record.tail {
+            self.writer.write_i32::<BigEndian>(v)?;
+        }
+        Ok(())
+    }
+
+    fn write_objects(&mut self, objects: &[Vec<Handle>], profile: Profile,
+        obj_ids: &HashMap<Handle, u32>, extra: &MapExtra) -> io::Result<()>
+    {
+        let total_obj_count: usize = objects.iter().map(|v| v.len()).sum();
+        self.writer.write_i32::<BigEndian>(total_obj_count as i32)?;
+        for (elevation, objects) in objects.iter().enumerate() {
+            self.writer.write_u32::<BigEndian>(objects.len() as u32)?;
+            for &h in objects {
+                self.write_object0(h, elevation as u32, profile, obj_ids, extra)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn write_object0(&mut self, h: Handle, elevation: u32, profile: Profile,
+        obj_ids: &HashMap<Handle, u32>, extra: &MapExtra) -> io::Result<()>
+    {
+        let obj = self.objects.get(h);
+        let obj_extra = extra.objects.get(h);
+
+        self.writer.write_u32::<BigEndian>(obj_ids.get(&h).copied().unwrap_or(0))?;
+        let pos = obj.try_pos();
+        self.writer.write_i32::<BigEndian>(pos.map(|p| Self::tile_num(p.point)).unwrap_or(-1))?;
+        let screen_shift = obj_extra
+            .filter(|e| e.screen_shift == obj.screen_shift)
+            .map(|e| e.screen_shift_raw)
+            .unwrap_or(obj.screen_shift);
+        self.writer.write_i32::<BigEndian>(screen_shift.x)?;
+        self.writer.write_i32::<BigEndian>(screen_shift.y)?;
+        self.writer.write_i32::<BigEndian>(obj.screen_pos.x)?;
+        self.writer.write_i32::<BigEndian>(obj.screen_pos.y)?;
+        let frame_idx = obj_extra
+            .map(|e| e.frame_idx)
+            .filter(|&v| cmp::max(v, 0) as usize == obj.frame_idx)
+            .unwrap_or(obj.frame_idx as i32);
+        self.writer.write_i32::<BigEndian>(frame_idx)?;
+        self.writer.write_u32::<BigEndian>(obj.direction as u32)?;
+        self.writer.write_u32::<BigEndian>(obj.fid.packed())?;
+        self.writer.write_u32::<BigEndian>(obj.flags.bits())?;
+        let elevation = pos.map(|p| p.elevation)
+            .or_else(|| obj_extra.map(|e| e.elevation))
+            .unwrap_or(elevation);
+        self.writer.write_u32::<BigEndian>(elevation)?;
+        let pid = obj.proto_id()
+            .ok_or_else(|| Error::new(ErrorKind::InvalidInput, "can't write object without proto"))?;
+        self.writer.write_u32::<BigEndian>(pid.pack())?;
+        self.writer.write_u32::<BigEndian>(obj_extra.map(|e| e.cid).unwrap_or(0xffff_ffff))?;
+        let light_emitter = obj.light_emitter();
+        self.writer.write_i32::<BigEndian>(light_emitter.radius as i32)?;
+        self.writer.write_i32::<BigEndian>(light_emitter.intensity as i32)?;
+        let outline_flags = obj_extra
+            .filter(|e| e.outline == obj.outline)
+            .map(|e| e.outline_flags)
+            .unwrap_or_else(|| encode_outline(obj.outline));
+        self.writer.write_u32::<BigEndian>(outline_flags)?;
+
+        let (sid, program_id) = obj_extra
+            .filter(|e| e.script == obj.script)
+            .map(|e| e.script_raw)
+            .unwrap_or_else(|| match obj.script {
+                Some((sid, program_id)) =>
+                    (sid.pack() as i32, encode_program_id(Some(program_id), 1)),
+                None => (-1, encode_program_id(None, 1)),
+            });
+        self.writer.write_i32::<BigEndian>(sid)?;
+        self.writer.write_i32::<BigEndian>(program_id)?;
+
+        // proto update data
+
+        let inventory_len = obj.inventory.items.len() as u32;
+        self.writer.write_u32::<BigEndian>(inventory_len)?;
+        let inventory_capacity = obj_extra
+            .map(|e| e.inventory_capacity)
+            .filter(|&v| v >= inventory_len as i32)
+            .unwrap_or(inventory_len as i32);
+        self.writer.write_i32::<BigEndian>(inventory_capacity)?;
+        self.writer.write_u32::<BigEndian>(obj_extra.map(|e| e.unk).unwrap_or(0))?;
+        self.writer.write_u32::<BigEndian>(obj.updated_flags.bits())?;
+
+        match &obj.sub {
+            SubObject::Critter(c) => {
+                // damage_last_turn, combat_state, action_points
+                for &v in &obj_extra.map(|e| e.combat).unwrap_or_default() {
+                    self.writer.write_u32::<BigEndian>(v)?;
+                }
+                self.writer.write_u32::<BigEndian>(c.combat.damage_flags.bits())?;
+                self.writer.write_i32::<BigEndian>(c.combat.ai_packet)?;
+                self.writer.write_i32::<BigEndian>(c.combat.team_id)?;
+                self.writer.write_i32::<BigEndian>(c.combat.who_hit_me)?;
+                self.writer.write_i32::<BigEndian>(c.hit_points)?;
+                self.writer.write_i32::<BigEndian>(c.radiation)?;
+                self.writer.write_i32::<BigEndian>(c.poison)?;
+            }
+            SubObject::Item(item) => {
+                let ammo_pid = item.ammo_proto.as_ref().map(|p| p.borrow().id());
+                let item_extra = obj_extra
+                    .and_then(|e| e.item.as_ref())
+                    .filter(|e| e.ammo_count == item.ammo_count && e.ammo_pid == ammo_pid);
+                match obj.item_kind().unwrap() {
+                    ItemKind::Weapon => {
+                        let (ammo_count, ammo_pid) = item_extra
+                            .map(|e| (e.ammo_count_raw, e.ammo_pid_raw))
+                            .unwrap_or((item.ammo_count as i32,
+                                ammo_pid.map(|v| v.pack()).unwrap_or(0xffff_ffff)));
+                        self.writer.write_i32::<BigEndian>(ammo_count)?;
+                        self.writer.write_u32::<BigEndian>(ammo_pid)?;
+                    }
+                    ItemKind::Misc => {
+                        let ammo_count = item_extra
+                            .map(|e| e.ammo_count_raw)
+                            .unwrap_or(item.ammo_count as i32);
+                        self.writer.write_i32::<BigEndian>(ammo_count)?;
+                    }
+                    ItemKind::Ammo => {
+                        self.writer.write_i32::<BigEndian>(item.ammo_count as i32)?;
+                    }
+                    _ => {}
+                }
+            }
+            SubObject::Key(key) => {
+                self.writer.write_i32::<BigEndian>(key.id)?;
+            }
+            SubObject::Scenery(scenery) => {
+                match scenery {
+                    object::Scenery::Door(door) => {
+                        self.writer.write_u32::<BigEndian>(door.flags.bits())?;
+                    }
+                    object::Scenery::Stairs(exit) => {
+                        let (map, location) = exit.encode();
+                        self.writer.write_u32::<BigEndian>(location)?;
+                        self.writer.write_i32::<BigEndian>(map)?;
+                    }
+                    object::Scenery::Elevator(elevator) => {
+                        self.writer.write_u32::<BigEndian>(elevator.kind)?;
+                        self.writer.write_u32::<BigEndian>(elevator.level)?;
+                    }
+                    object::Scenery::Ladder(exit) => {
+                        let (map, location) = exit.encode();
+                        if profile == Profile::Fallout2 {
+                            self.writer.write_i32::<BigEndian>(map)?;
+                        }
+                        self.writer.write_u32::<BigEndian>(location)?;
+                    }
+                }
+            }
+            SubObject::MapExit(exit) => {
+                self.writer.write_i32::<BigEndian>(exit.map.encode())?;
+                self.writer.write_i32::<BigEndian>(Self::tile_num(exit.pos.point))?;
+                self.writer.write_u32::<BigEndian>(exit.pos.elevation)?;
+                self.writer.write_u32::<BigEndian>(exit.direction as u32)?;
+            }
+            SubObject::None => {
+                // Misc scenery has no data. Other kinds must have been handled above.
+                if pid.kind() == EntityKind::Item {
+                    match obj.item_kind().unwrap() {
+                        ItemKind::Weapon | ItemKind::Ammo | ItemKind::Misc | ItemKind::Key =>
+                            return Err(Error::new(ErrorKind::InvalidInput,
+                                format!("{:?} object has no item data", pid))),
+                        _ => {}
+                    }
+                }
+            }
+        }
+
+        // inventory
+
+        for item in &obj.inventory.items {
+            self.writer.write_i32::<BigEndian>(item.count as i32)?;
+            self.write_object0(item.object, elevation, profile, obj_ids, extra)?;
+        }
+
+        Ok(())
+    }
+
+    fn write_sqr_tiles(&mut self, sqr_tiles: &SqrTiles) -> io::Result<()> {
+        for tiles in sqr_tiles.iter().flatten() {
+            for y in 0..tiles.height() {
+                for x in (0..tiles.width()).rev() {
+                    let &(floor_id, roof_id) = tiles.get(x, y).unwrap();
+                    self.writer.write_u16::<BigEndian>(roof_id)?;
+                    self.writer.write_u16::<BigEndian>(floor_id)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn tile_num(p: Point) -> i32 {
+        TileGrid::default().rect_to_linear_inv(p).map(|v| v as i32).unwrap_or(-1)
+    }
+}
+
+fn trim_name(name: &[u8]) -> BString {
+    BString::from(&name[..name.iter().position(|&c| c == 0).unwrap_or(name.len())])
+}
+
+/// Program IDs are stored with different `offset`s in different places.
+fn decode_program_id(v: i32, offset: i32) -> Option<ProgramId> {
+    v.checked_add(offset)
+        .and_then(|v| v.try_into().ok())
+        .and_then(ProgramId::new)
+}
+
+fn encode_program_id(program_id: Option<ProgramId>, offset: i32) -> i32 {
+    program_id.map(|v| v.val() as i32 - offset).unwrap_or(-offset)
+}
+
+fn encode_outline(outline: Option<Outline>) -> u32 {
+    let mut flags = BitFlags::empty();
+    if let Some(outline) = outline {
+        let style = match outline.style {
+            OutlineStyle::GlowingRed => Some(OutlineFlag::GlowingRed),
+            OutlineStyle::Red => Some(OutlineFlag::Red),
+            OutlineStyle::Gray => Some(OutlineFlag::Gray),
+            OutlineStyle::GlowingGreen => Some(OutlineFlag::GlowingGreen),
+            OutlineStyle::Yellow => Some(OutlineFlag::Yellow),
+            OutlineStyle::Brown => Some(OutlineFlag::Brown),
+            OutlineStyle::Purple => None,
+        };
+        if let Some(style) = style {
+            flags |= style;
+            if outline.translucent {
+                flags |= OutlineFlag::Translucent;
+            }
+            if outline.disabled {
+                flags |= OutlineFlag::Disabled;
+            }
+        }
+    }
+    flags.bits()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::game::rpg::Rpg;
+    use crate::util::test::Assets;
+
+    fn new_objects(assets: &Assets) -> Objects {
+        Objects::new(TileGrid::default(), ELEVATION_COUNT,
+            assets.frm_db.clone(), assets.proto_db.clone())
+    }
+
+    fn create(objects: &mut Objects, assets: &Assets, rpg: &Rpg, pid: u32, pos: Option<EPoint>)
+        -> Handle
+    {
+        let proto = assets.proto_db.proto(Assets::pid(pid)).unwrap();
+        objects.create(None, Some(proto), pos, Some(rpg)).handle()
+    }
+
+    fn inventory(objects: &Objects, h: Handle) -> Vec<(u32, u32)> {
+        objects.get(h).inventory.items.iter()
+            .map(|i| (objects.get(i.object).proto_id().unwrap().pack(), i.count))
+            .collect()
+    }
+
+    fn find(objects: &Objects, pid: u32) -> Handle {
+        objects.iter()
+            .find(|&h| objects.get(h).proto_id() == Some(Assets::pid(pid)))
+            .unwrap()
+    }
+
+    #[test]
+    fn write_read_roundtrip() {
+        let assets = Assets::new();
+        let rpg = &assets.rpg();
+        let mut objects = new_objects(&assets);
+        let mut scripts = assets.scripts();
+        let program_id = ProgramId::new(Assets::PROGRAM).unwrap();
+
+        let critter = create(&mut objects, &assets, rpg, Assets::CRITTER,
+            Some(Point::new(10, 20).elevated(0)));
+        {
+            let mut critter = objects.get_mut(critter);
+            critter.direction = Direction::SE;
+            critter.flags.insert(Flag::NoBlock);
+            let critter = critter.sub.as_critter_mut().unwrap();
+            critter.hit_points = 17;
+            critter.poison = 3;
+            critter.combat.who_hit_me = 5;
+        }
+        let weapon = create(&mut objects, &assets, rpg, Assets::WEAPON, None);
+        objects.move_into_inventory(critter, weapon, 1);
+        let ammo = create(&mut objects, &assets, rpg, Assets::AMMO, None);
+        objects.get_mut(ammo).sub.as_item_mut().unwrap().ammo_count = 7;
+        objects.move_into_inventory(critter, ammo, 3);
+        let bag = create(&mut objects, &assets, rpg, Assets::CONTAINER, None);
+        objects.move_into_inventory(critter, bag, 1);
+        let misc = create(&mut objects, &assets, rpg, Assets::MISC_ITEM, None);
+        objects.move_into_inventory(bag, misc, 2);
+
+        let sid = ScriptIid::new(ScriptKind::Critter, 3);
+        scripts.instantiate(sid, program_id, Some(vec![1, 2, 3].into())).unwrap();
+        scripts.attach_to_object(sid, critter);
+        objects.get_mut(critter).script = Some((sid, program_id));
+
+        let key = create(&mut objects, &assets, rpg, Assets::KEY, Some(Point::new(5, 6).elevated(1)));
+        objects.get_mut(key).sub.as_key_mut().unwrap().id = 1234;
+
+        let _door = create(&mut objects, &assets, rpg, Assets::DOOR, Some(Point::new(30, 40).elevated(0)));
+
+        let exit = objects.insert(Object::new(Assets::fid(EntityKind::Misc),
+            Some(assets.proto_db.proto(Assets::pid(Assets::EXIT_AREA)).unwrap()),
+            Some(Point::new(50, 60).elevated(2)),
+            SubObject::MapExit(MapExit {
+                map: TargetMap::Map { map_id: 7 },
+                pos: Point::new(70, 80).elevated(1),
+                direction: Direction::W,
+            })));
+        objects.get_mut(exit).flags.insert(Flag::Flat);
+
+        let mut sqr_tiles = vec![None; ELEVATION_COUNT as usize];
+        let mut tiles = Array2d::with_default(100, 100);
+        *tiles.get_mut(3, 4).unwrap() = (12, 34);
+        sqr_tiles[0] = Some(tiles);
+
+        let map = Map {
+            version: 20,
+            name: "TEST.MAP".into(),
+            id: 5,
+            savegame: true,
+            last_visit: GameTime::from_decis(12345),
+            entrance: Point::new(11, 22).elevated(0),
+            entrance_direction: Direction::SW,
+            sqr_tiles,
+            map_vars: vec![10, 20].into(),
+            extra: Default::default(),
+        };
+
+        let mut data = Vec::new();
+        MapWriter {
+            writer: &mut data,
+            objects: &objects,
+            scripts: &scripts,
+        }.write(&map).unwrap();
+
+        let mut objects = new_objects(&assets);
+        let mut scripts = assets.scripts();
+        let actual = MapReader {
+            reader: &mut &data[..],
+            objects: &mut objects,
+            proto_db: &assets.proto_db,
+            frm_db: &assets.frm_db,
+            scripts: &mut scripts,
+        }.read().unwrap();
+
+        assert_eq!(actual.version, map.version);
+        assert_eq!(actual.name, map.name);
+        assert_eq!(actual.id, map.id);
+        assert!(actual.savegame);
+        assert_eq!(actual.last_visit, map.last_visit);
+        assert_eq!(actual.entrance, map.entrance);
+        assert_eq!(actual.entrance_direction, map.entrance_direction);
+        assert_eq!(&actual.map_vars[..], &[10, 20]);
+        assert!(actual.sqr_tiles[1].is_none());
+        assert_eq!(actual.sqr_tiles[0].as_ref().unwrap().get(3, 4), Some(&(12, 34)));
+        assert_eq!(actual.sqr_tiles[0].as_ref().unwrap().get(4, 3), Some(&(0, 0)));
+
+        assert_eq!(objects.iter().count(), 8);
+
+        let critter = find(&objects, Assets::CRITTER);
+        {
+            let critter = objects.get(critter);
+            assert_eq!(critter.pos(), Point::new(10, 20).elevated(0));
+            assert_eq!(critter.direction, Direction::SE);
+            assert!(critter.flags.contains(Flag::NoBlock));
+            let c = critter.sub.as_critter().unwrap();
+            assert_eq!(c.hit_points, 17);
+            assert_eq!(c.poison, 3);
+            assert_eq!(c.combat.who_hit_me, 5);
+            assert_eq!(c.combat.team_id, 2);
+            assert_eq!(critter.script, Some((sid, program_id)));
+        }
+        assert_eq!(inventory(&objects, critter),
+            &[(Assets::CONTAINER, 1), (Assets::AMMO, 3), (Assets::WEAPON, 1)]);
+        let ammo = objects.get(critter).inventory.items[1].object;
+        assert_eq!(objects.get(ammo).sub.as_item().unwrap().ammo_count, 7);
+        let bag = objects.get(critter).inventory.items[0].object;
+        assert_eq!(inventory(&objects, bag), &[(Assets::MISC_ITEM, 2)]);
+
+        let script = scripts.get(sid).unwrap();
+        assert_eq!(script.program_id, program_id);
+        assert_eq!(&script.local_vars[..], &[1, 2, 3]);
+        assert_eq!(script.object, Some(critter));
+
+        let key = objects.get(find(&objects, Assets::KEY));
+        assert_eq!(key.pos(), Point::new(5, 6).elevated(1));
+        assert_eq!(key.sub.as_key().unwrap().id, 1234);
+
+        let door = objects.get(find(&objects, Assets::DOOR));
+        assert_eq!(door.pos(), Point::new(30, 40).elevated(0));
+        assert!(door.sub.as_scenery().unwrap().as_door().is_some());
+
+        let exit = objects.get(find(&objects, Assets::EXIT_AREA));
+        assert_eq!(exit.pos(), Point::new(50, 60).elevated(2));
+        assert!(exit.flags.contains(Flag::Flat));
+        let exit = exit.sub.as_map_exit().unwrap();
+        assert_eq!(exit.map, TargetMap::Map { map_id: 7 });
+        assert_eq!(exit.pos, Point::new(70, 80).elevated(1));
+        assert_eq!(exit.direction, Direction::W);
+    }
+
+    #[test]
+    fn dude_and_temp_objects_are_not_written() {
+        let assets = Assets::new();
+        let rpg = &assets.rpg();
+        let mut objects = new_objects(&assets);
+        let scripts = assets.scripts();
+
+        let dude = objects.create(None, Some(assets.proto_db.dude()),
+            Some(Point::new(1, 1).elevated(0)), Some(rpg)).handle();
+        assert!(objects.get(dude).is_dude());
+        let temp = create(&mut objects, &assets, rpg, Assets::MISC_ITEM, Some(Point::new(2, 2).elevated(0)));
+        objects.get_mut(temp).flags.insert(Flag::Temp);
+        create(&mut objects, &assets, rpg, Assets::MISC_ITEM, Some(Point::new(3, 3).elevated(0)));
+
+        let map = Map {
+            version: 20,
+            name: "TEST.MAP".into(),
+            id: 0,
+            savegame: true,
+            last_visit: GameTime::from_decis(0),
+            entrance: Point::new(0, 0).elevated(0),
+            entrance_direction: Direction::NE,
+            sqr_tiles: vec![None; ELEVATION_COUNT as usize],
+            map_vars: Vec::new().into(),
+            extra: Default::default(),
+        };
+        let mut data = Vec::new();
+        MapWriter {
+            writer: &mut data,
+            objects: &objects,
+            scripts: &scripts,
+        }.write(&map).unwrap();
+
+        let mut objects = new_objects(&assets);
+        MapReader {
+            reader: &mut &data[..],
+            objects: &mut objects,
+            proto_db: &assets.proto_db,
+            frm_db: &assets.frm_db,
+            scripts: &mut assets.scripts(),
+        }.read().unwrap();
+        assert_eq!(objects.iter().map(|h| objects.get(h).pos()).collect::<Vec<_>>(),
+            &[Point::new(3, 3).elevated(0)]);
+    }
+
+    /// Map with garbage in the unused fields and values that are fixed by the reader.
+    fn raw_map() -> Vec<u8> {
+        let mut w = Vec::new();
+        let w = &mut w;
+        fn i32s(w: &mut Vec<u8>, vals: &[i32]) {
+            for &v in vals {
+                w.write_i32::<BigEndian>(v).unwrap();
+            }
+        }
+
+        // header
+        i32s(w, &[20]);
+        w.extend_from_slice(b"TEST.MAP\0garbage");
+        // entrance tile, elevation, direction, local var count, map program ID
+        i32s(w, &[12345, 0, 2, 4, 0]);
+        // flags (elevations 1 and 2 are missing), unk, map var count, map ID, last visit
+        i32s(w, &[0x10c, 7, 2, 5, 1000]);
+        let padding: Vec<_> = (0..44).collect();
+        i32s(w, &padding);
+        // map vars, local vars
+        i32s(w, &[1, 2, 10, 20, 30, 40]);
+
+        for i in 0..100 * 100 {
+            w.write_u16::<BigEndian>(i as u16).unwrap();
+            w.write_u16::<BigEndian>(1).unwrap();
+        }
+
+        // System, Spatial, Time and Item scripts.
+        i32s(w, &[0, 0, 0, 0]);
+        // Critter scripts.
+        i32s(w, &[2]);
+        let sid = ScriptIid::new(ScriptKind::Critter, 1).pack() as i32;
+        // unk1, flags, program, unk2, self_obj_id, local var offset and count, tail
+        i32s(w, &[sid, 5, 0x20, 0, 0, 1, 1, 3, 0, 0, 0, 0, 0, 0, 0, 0x77]);
+        // Script without program.
+        let sid = ScriptIid::new(ScriptKind::Critter, 2).pack() as i32;
+        i32s(w, &[sid, 5, 0x20, -1, 0, 1, -1, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        for _ in 2..16 {
+            i32s(w, &[0xcccccccc_u32 as i32; 16]);
+        }
+        // node script count, unk
+        i32s(w, &[1, 0x12345]);
+
+        let critter_fid = Assets::fid(EntityKind::Critter).packed() as i32;
+        let item_fid = Assets::fid(EntityKind::Item).packed() as i32;
+
+        // total object count
+        i32s(w, &[2]);
+
+        // elevation 0
+        i32s(w, &[1]);
+        // id, tile, shift, screen pos, frame index, direction, fid, flags, elevation, pid, cid
+        i32s(w, &[1, 2020, 1, 2, 3, 4, -1, 3, critter_fid, 0, 0, Assets::CRITTER as i32, 0x12]);
+        // light radius and intensity, outline, sid, program
+        i32s(w, &[2, 0x8000, 0x40000000, sid - 1, 0]);
+        // inventory length, capacity, unk, updated flags
+        i32s(w, &[1, 5, 0xcccccccc_u32 as i32, 0]);
+        // damage last turn, combat state, AP, damage flags, AI packet, team, who hit me, HP, rad,
+        // poison
+        i32s(w, &[1, 2, 3, 0, 1, 2, -1, 30, 4, 5]);
+
+        // inventory item count
+        i32s(w, &[1]);
+        i32s(w, &[2, -1, 0, 0, 0, 0, 0, 0, item_fid, 0, 2, Assets::WEAPON as i32, -1]);
+        i32s(w, &[0, 0, 0, -1, -1]);
+        i32s(w, &[0, 0, 0, 0]);
+        // ammo count, ammo pid
+        i32s(w, &[-1, -1]);
+
+        // elevation 1
+        i32s(w, &[1]);
+        i32s(w, &[3, 3030, 0, 0, 0, 0, 0, 0, item_fid, 0, 1, Assets::MISC_ITEM as i32, -1]);
+        i32s(w, &[0, 0, 0, -1, -1]);
+        i32s(w, &[0, 0, 0, 0]);
+        // charges
+        i32s(w, &[0xcccccccc_u32 as i32]);
+
+        // elevation 2
+        i32s(w, &[0]);
+
+        w.clone()
+    }
+
+    #[test]
+    fn fallout1_ladder_has_no_map() {
+        let assets = Assets::new();
+        let scripts = assets.scripts();
+        let mut objects = new_objects(&assets);
+        let exit = MapExit {
+            map: TargetMap::Map { map_id: 3 },
+            pos: Point::new(10, 20).elevated(1),
+            direction: Direction::E,
+        };
+        objects.insert(Object::new(Assets::fid(EntityKind::Scenery),
+            Some(assets.proto_db.proto(Assets::pid(Assets::LADDER_DOWN)).unwrap()),
+            Some(Point::new(1, 2).elevated(0)),
+            SubObject::Scenery(object::Scenery::Ladder(exit.clone()))));
+
+        let write = |version| {
+            let map = Map {
+                version,
+                name: "TEST.MAP".into(),
+                id: 0,
+                savegame: false,
+                last_visit: GameTime::from_decis(0),
+                entrance: Point::new(0, 0).elevated(0),
+                entrance_direction: Direction::NE,
+                sqr_tiles: vec![None; ELEVATION_COUNT as usize],
+                map_vars: Vec::new().into(),
+                extra: Default::default(),
+            };
+            let mut data = Vec::new();
+            MapWriter {
+                writer: &mut data,
+                objects: &objects,
+                scripts: &scripts,
+            }.write(&map).map(|_| data)
+        };
+        let read = |data: &[u8]| {
+            let mut objects = new_objects(&assets);
+            let mut scripts = assets.scripts();
+            MapReader {
+                reader: &mut &data[..],
+                objects: &mut objects,
+                proto_db: &assets.proto_db,
+                frm_db: &assets.frm_db,
+                scripts: &mut scripts,
+            }.read()?;
+            let ladder = find(&objects, Assets::LADDER_DOWN);
+            let exit = objects.get(ladder).sub.as_scenery().unwrap().as_ladder().unwrap().clone();
+            io::Result::Ok((exit.map, exit.pos, exit.direction))
+        };
+
+        let fo1 = write(19).unwrap();
+        let fo2 = write(20).unwrap();
+        assert_eq!(fo2.len() - fo1.len(), 4);
+        assert_eq!(read(&fo1).unwrap(), (TargetMap::CurrentMap, exit.pos, exit.direction));
+        assert_eq!(read(&fo2).unwrap(), (exit.map, exit.pos, exit.direction));
+
+        assert_eq!(write(21).unwrap_err().kind(), ErrorKind::InvalidInput);
+        let mut bad = fo2;
+        bad[3] = 21;
+        assert_eq!(read(&bad).unwrap_err().kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn write_unmodified_byte_identical() {
+        let assets = Assets::new();
+        let mut objects = new_objects(&assets);
+        let mut scripts = assets.scripts();
+        let data = raw_map();
+
+        let map = MapReader {
+            reader: &mut &data[..],
+            objects: &mut objects,
+            proto_db: &assets.proto_db,
+            frm_db: &assets.frm_db,
+            scripts: &mut scripts,
+        }.read().unwrap();
+        assert_eq!(map.name, "TEST.MAP");
+        assert_eq!(objects.iter().count(), 3);
+        assert_eq!(scripts.iter().count(), 1);
+
+        let mut actual = Vec::new();
+        MapWriter {
+            writer: &mut actual,
+            objects: &objects,
+            scripts: &scripts,
+        }.write(&map).unwrap();
+
+        assert_eq!(actual.len(), data.len());
+        let diff = actual.iter().zip(&data).position(|(a, b)| a != b);
+        assert_eq!(diff, None);
+
+        // Changing the state affects only the relevant bytes.
+        let critter = find(&objects, Assets::CRITTER);
+        objects.get_mut(critter).sub.as_critter_mut().unwrap().hit_points = 0x1f;
+        let mut actual = Vec::new();
+        MapWriter {
+            writer: &mut actual,
+            objects: &objects,
+            scripts: &scripts,
+        }.write(&map).unwrap();
+        let diff: Vec<_> = (0..data.len()).filter(|&i| actual[i] != data[i]).collect();
+        assert_eq!(diff.len(), 1);
+        assert_eq!(&actual[diff[0] - 3..=diff[0]], &[0, 0, 0, 0x1f]);
+    }
+}
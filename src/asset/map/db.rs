@@ -24,8 +24,15 @@ pub struct MapDb {
 }
 
 impl MapDb {
+    /// Fallout 1 doesn't have `maps.txt` and gets no map definitions.
     pub fn new(fs: &FileSystem) -> io::Result<Self> {
-        Self::read(&mut fs.reader("data/maps.txt")?)
+        if fs.exists("data/maps.txt") {
+            Self::read(&mut fs.reader("data/maps.txt")?)
+        } else {
+            Ok(Self {
+                maps: Vec::new(),
+            })
+        }
     }
 
     fn read(rd: &mut impl BufRead) -> io::Result<Self> {
@@ -130,6 +137,17 @@ impl MapDb {
     pub fn get(&self, id: u32) -> Option<&MapDef> {
         self.maps.get(id as usize)
     }
+
+    pub fn get_mut(&mut self, id: u32) -> Option<&mut MapDef> {
+        self.maps.get_mut(id as usize)
+    }
+
+    /// Finds map by its `lookup_name` ignoring case.
+    pub fn find(&self, lookup_name: &str) -> Option<u32> {
+        self.maps.iter()
+            .position(|m| m.lookup_name.eq_ignore_ascii_case(lookup_name))
+            .map(|i| i as u32)
+    }
 }
 
 #[cfg(test)]
@@ -193,7 +211,9 @@ map_name=desert2";
             },
         ];
 
-        let act = MapDb::read(&mut BufReader::new(Cursor::new(inp))).unwrap().maps;
-        assert_eq!(act, exp);
+        let db = MapDb::read(&mut BufReader::new(Cursor::new(inp))).unwrap();
+        assert_eq!(db.maps, exp);
+        assert_eq!(db.find("desert encounter 2"), Some(1));
+        assert_eq!(db.find("desert2"), None);
     }
 }
@@ -0,0 +1,803 @@
+use byteorder::{LittleEndian, ReadBytesExt};
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Error, ErrorKind, prelude::*};
+use std::mem;
+use std::path::Path;
+use std::time::Duration;
+
+use crate::graphics::color::{Color8, Rgb, Rgb18};
+use super::sound::{write_wav, Pcm};
+
+const MVE_SIGNATURE: &[u8; 26] = b"Interplay MVE File\x1a\0\x1a\0\0\x01\x33\x11";
+
+const OP_END_OF_STREAM: u8 = 0x00;
+const OP_END_OF_CHUNK: u8 = 0x01;
+const OP_CREATE_TIMER: u8 = 0x02;
+const OP_INIT_AUDIO_BUFFERS: u8 = 0x03;
+const OP_INIT_VIDEO_BUFFERS: u8 = 0x05;
+const OP_SEND_BUFFER: u8 = 0x07;
+const OP_AUDIO_FRAME: u8 = 0x08;
+const OP_AUDIO_SILENCE: u8 = 0x09;
+const OP_SET_PALETTE: u8 = 0x0c;
+const OP_SET_PALETTE_COMPRESSED: u8 = 0x0d;
+const OP_SET_DECODING_MAP: u8 = 0x0f;
+const OP_VIDEO_DATA: u8 = 0x11;
+
+/// Size of the header preceding the block data in `OP_VIDEO_DATA`.
+const VIDEO_DATA_HEADER_LEN: usize = 14;
+
+const DEFAULT_FRAME_DURATION: Duration = Duration::from_micros(1_000_000 / 15);
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct AudioFormat {
+    pub channels: u16,
+    pub sample_rate: u32,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct AudioParams {
+    format: AudioFormat,
+    bits16: bool,
+    compressed: bool,
+}
+
+/// Streaming decoder of Interplay MVE movies.
+///
+/// The file is a sequence of chunks each containing a sequence of opcodes. Video frames are
+/// 8-bit palette-indexed and are made of 8x8 blocks each encoded with one of 16 block opcodes
+/// stored in the decoding map. Blocks can refer to the previous frame which is kept in the back
+/// buffer. Audio is either raw PCM or Interplay DPCM.
+pub struct MveDecoder<R> {
+    rd: R,
+    chunk_left: usize,
+    done: bool,
+    frame_duration: Duration,
+    audio: Option<AudioParams>,
+    audio_buf: Vec<i16>,
+    width: usize,
+    height: usize,
+    /// Frame being decoded and displayed.
+    front: Vec<u8>,
+    /// Previous frame.
+    back: Vec<u8>,
+    palette: [Rgb18; 256],
+    decoding_map: Vec<u8>,
+    op_buf: Vec<u8>,
+}
+
+impl<R: Read> MveDecoder<R> {
+    pub fn new(mut rd: R) -> io::Result<Self> {
+        let mut signature = [0; 26];
+        rd.read_exact(&mut signature)?;
+        if &signature != MVE_SIGNATURE {
+            return Err(Error::new(ErrorKind::InvalidData, "bad MVE signature"));
+        }
+        Ok(Self {
+            rd,
+            chunk_left: 0,
+            done: false,
+            frame_duration: DEFAULT_FRAME_DURATION,
+            audio: None,
+            audio_buf: Vec::new(),
+            width: 0,
+            height: 0,
+            front: Vec::new(),
+            back: Vec::new(),
+            palette: [Rgb::black(); 256],
+            decoding_map: Vec::new(),
+            op_buf: Vec::new(),
+        })
+    }
+
+    pub fn frame_duration(&self) -> Duration {
+        self.frame_duration
+    }
+
+    /// Returns `None` if the movie has no sound or the sound hasn't been initialized yet.
+    pub fn audio_format(&self) -> Option<AudioFormat> {
+        self.audio.map(|a| a.format)
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// Palette-indexed pixels of the current frame.
+    pub fn pixels(&self) -> &[u8] {
+        &self.front
+    }
+
+    pub fn palette(&self) -> &[Rgb18; 256] {
+        &self.palette
+    }
+
+    /// Takes the samples decoded so far. Samples are interleaved as specified by
+    /// `audio_format()`.
+    pub fn take_audio(&mut self) -> Vec<i16> {
+        mem::take(&mut self.audio_buf)
+    }
+
+    /// Decodes the stream up to the next frame.
+    /// Returns `false` when the end of the stream is reached.
+    pub fn next_frame(&mut self) -> io::Result<bool> {
+        while !self.done {
+            if self.chunk_left == 0 {
+                let len = match self.rd.read_u16::<LittleEndian>() {
+                    Ok(v) => v,
+                    Err(e) if e.kind() == ErrorKind::UnexpectedEof => {
+                        self.done = true;
+                        break;
+                    }
+                    Err(e) => return Err(e),
+                };
+                let _kind = self.rd.read_u16::<LittleEndian>()?;
+                self.chunk_left = len as usize;
+                continue;
+            }
+
+            let len = self.rd.read_u16::<LittleEndian>()? as usize;
+            let op = self.rd.read_u8()?;
+            let version = self.rd.read_u8()?;
+            if len + 4 > self.chunk_left {
+                return Err(Error::new(ErrorKind::InvalidData, "MVE opcode overruns chunk"));
+            }
+            self.chunk_left -= len + 4;
+
+            let mut data = mem::take(&mut self.op_buf);
+            data.resize(len, 0);
+            let r = self.rd.read_exact(&mut data)
+                .and_then(|_| self.handle_op(op, version, &data));
+            self.op_buf = data;
+            if r? {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    /// Returns `true` if the frame is ready to be displayed.
+    fn handle_op(&mut self, op: u8, version: u8, data: &[u8]) -> io::Result<bool> {
+        let mut rd = data;
+        match op {
+            OP_END_OF_STREAM => self.done = true,
+            OP_END_OF_CHUNK => self.chunk_left = 0,
+            OP_CREATE_TIMER => {
+                let rate = rd.read_u32::<LittleEndian>()? as u64;
+                let subdivision = rd.read_u16::<LittleEndian>()? as u64;
+                self.frame_duration = Duration::from_micros(rate * subdivision);
+            }
+            OP_INIT_AUDIO_BUFFERS => {
+                let _ = rd.read_u16::<LittleEndian>()?;
+                let flags = rd.read_u16::<LittleEndian>()?;
+                let sample_rate = rd.read_u16::<LittleEndian>()? as u32;
+                self.audio = Some(AudioParams {
+                    format: AudioFormat {
+                        channels: if flags & 1 != 0 { 2 } else { 1 },
+                        sample_rate,
+                    },
+                    bits16: flags & 2 != 0,
+                    compressed: version >= 1 && flags & 4 != 0,
+                });
+            }
+            OP_INIT_VIDEO_BUFFERS => {
+                let width = rd.read_u16::<LittleEndian>()? as usize * 8;
+                let height = rd.read_u16::<LittleEndian>()? as usize * 8;
+                if version >= 2 {
+                    let _ = rd.read_u16::<LittleEndian>()?;
+                    if rd.read_u16::<LittleEndian>()? != 0 {
+                        return Err(Error::new(ErrorKind::InvalidData,
+                            "true color MVE movies are not supported"));
+                    }
+                }
+                self.width = width;
+                self.height = height;
+                self.front = vec![0; width * height];
+                self.back = vec![0; width * height];
+                self.decoding_map.clear();
+            }
+            OP_SEND_BUFFER => {
+                if self.front.is_empty() {
+                    return Err(Error::new(ErrorKind::InvalidData,
+                        "MVE frame sent before video buffers are initialized"));
+                }
+                return Ok(true);
+            }
+            OP_AUDIO_FRAME | OP_AUDIO_SILENCE => self.decode_audio(op == OP_AUDIO_SILENCE, rd)?,
+            OP_SET_PALETTE => {
+                let start = rd.read_u16::<LittleEndian>()? as usize;
+                let count = rd.read_u16::<LittleEndian>()? as usize;
+                if start + count > 256 {
+                    return Err(Error::new(ErrorKind::InvalidData, "bad MVE palette range"));
+                }
+                for c in &mut self.palette[start..start + count] {
+                    *c = read_rgb18(&mut rd)?;
+                }
+            }
+            OP_SET_PALETTE_COMPRESSED => {
+                for i in 0..32 {
+                    let mask = rd.read_u8()?;
+                    for j in 0..8 {
+                        if mask & (1 << j) != 0 {
+                            self.palette[i * 8 + j] = read_rgb18(&mut rd)?;
+                        }
+                    }
+                }
+            }
+            OP_SET_DECODING_MAP => {
+                self.decoding_map.clear();
+                self.decoding_map.extend_from_slice(data);
+            }
+            OP_VIDEO_DATA => {
+                if data.len() < VIDEO_DATA_HEADER_LEN {
+                    return Err(Error::new(ErrorKind::InvalidData, "MVE video data is too short"));
+                }
+                let flags = u16::from_le_bytes([data[12], data[13]]);
+                if flags & 1 != 0 {
+                    mem::swap(&mut self.front, &mut self.back);
+                }
+                self.decode_video(&data[VIDEO_DATA_HEADER_LEN..])?;
+            }
+            _ => {}
+        }
+        Ok(false)
+    }
+
+    fn decode_audio(&mut self, silence: bool, mut rd: &[u8]) -> io::Result<()> {
+        let audio = if let Some(v) = self.audio {
+            v
+        } else {
+            return Ok(());
+        };
+        let _seq = rd.read_u16::<LittleEndian>()?;
+        let stream_mask = rd.read_u16::<LittleEndian>()?;
+        let len = rd.read_u16::<LittleEndian>()? as usize;
+        // Only the first stream is played.
+        if stream_mask & 1 == 0 {
+            return Ok(());
+        }
+        let sample_count = if audio.bits16 || audio.compressed { len / 2 } else { len };
+        if silence {
+            self.audio_buf.resize(self.audio_buf.len() + sample_count, 0);
+        } else if audio.compressed {
+            decode_dpcm(rd, audio.format.channels as usize, sample_count, &mut self.audio_buf)?;
+        } else if audio.bits16 {
+            for _ in 0..sample_count {
+                self.audio_buf.push(rd.read_i16::<LittleEndian>()?);
+            }
+        } else {
+            for _ in 0..sample_count {
+                self.audio_buf.push((rd.read_u8()? as i16 - 128) << 8);
+            }
+        }
+        Ok(())
+    }
+
+    fn decode_video(&mut self, data: &[u8]) -> io::Result<()> {
+        let block_width = self.width / 8;
+        let block_height = self.height / 8;
+        if self.decoding_map.len() * 2 < block_width * block_height {
+            return Err(Error::new(ErrorKind::InvalidData, "MVE decoding map is too short"));
+        }
+        let mut rd = data;
+        for by in 0..block_height {
+            for bx in 0..block_width {
+                let i = by * block_width + bx;
+                let op = (self.decoding_map[i / 2] >> ((i & 1) * 4)) & 0xf;
+                self.decode_block(op, bx * 8, by * 8, &mut rd)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn decode_block(&mut self, op: u8, x: usize, y: usize, rd: &mut &[u8]) -> io::Result<()> {
+        let mut blk = [0; 64];
+        match op {
+            // Copy from the previous frame.
+            0x0 => return self.copy_block(x, y, false, 0, 0),
+            // Leave unchanged.
+            0x1 => return Ok(()),
+            // Copy from the current frame below/right.
+            0x2 => {
+                let (dx, dy) = far_motion(rd.read_u8()?);
+                return self.copy_block(x, y, true, dx, dy);
+            }
+            // Copy from the current frame above/left.
+            0x3 => {
+                let (dx, dy) = far_motion(rd.read_u8()?);
+                return self.copy_block(x, y, true, -dx, -dy);
+            }
+            // Copy from the previous frame with short motion vector.
+            0x4 => {
+                let b = rd.read_u8()?;
+                return self.copy_block(x, y, false, (b & 0xf) as i32 - 8, (b >> 4) as i32 - 8);
+            }
+            // Copy from the previous frame with long motion vector.
+            0x5 => {
+                let dx = rd.read_i8()? as i32;
+                let dy = rd.read_i8()? as i32;
+                return self.copy_block(x, y, false, dx, dy);
+            }
+            // Not used in 8-bit movies.
+            0x6 => return Ok(()),
+            // 2 colors per pixel or per 2x2.
+            0x7 => {
+                let p = read_colors::<2>(rd)?;
+                if p[0] <= p[1] {
+                    for py in 0..8 {
+                        let flags = rd.read_u8()?;
+                        for px in 0..8 {
+                            blk[py * 8 + px] = p[(flags >> px) as usize & 1];
+                        }
+                    }
+                } else {
+                    let flags = rd.read_u16::<LittleEndian>()?;
+                    for i in 0..16 {
+                        fill_2x2(&mut blk, i, p[(flags >> i) as usize & 1]);
+                    }
+                }
+            }
+            // 2 colors per quadrant or per half.
+            0x8 => {
+                let mut p = read_colors::<2>(rd)?;
+                if p[0] <= p[1] {
+                    for q in 0..4 {
+                        if q > 0 {
+                            p = read_colors::<2>(rd)?;
+                        }
+                        let flags = rd.read_u16::<LittleEndian>()?;
+                        for i in 0..16 {
+                            blk[quadrant_pos(q, i)] = p[(flags >> i) as usize & 1];
+                        }
+                    }
+                } else {
+                    let flags = rd.read_u32::<LittleEndian>()?;
+                    let p2 = read_colors::<2>(rd)?;
+                    let vert = p2[0] <= p2[1];
+                    for i in 0..32 {
+                        blk[half_pos(vert, 0, i)] = p[(flags >> i) as usize & 1];
+                    }
+                    let flags = rd.read_u32::<LittleEndian>()?;
+                    for i in 0..32 {
+                        blk[half_pos(vert, 1, i)] = p2[(flags >> i) as usize & 1];
+                    }
+                }
+            }
+            // 4 colors per pixel, 2x2, 2x1 or 1x2.
+            0x9 => {
+                let p = read_colors::<4>(rd)?;
+                if p[0] <= p[1] {
+                    if p[2] <= p[3] {
+                        for py in 0..8 {
+                            let flags = rd.read_u16::<LittleEndian>()?;
+                            for px in 0..8 {
+                                blk[py * 8 + px] = p[(flags >> (px * 2)) as usize & 3];
+                            }
+                        }
+                    } else {
+                        let flags = rd.read_u32::<LittleEndian>()?;
+                        for i in 0..16 {
+                            fill_2x2(&mut blk, i, p[(flags >> (i * 2)) as usize & 3]);
+                        }
+                    }
+                } else {
+                    let flags = rd.read_u64::<LittleEndian>()?;
+                    for i in 0..32 {
+                        let c = p[(flags >> (i * 2)) as usize & 3];
+                        if p[2] <= p[3] {
+                            let j = i / 4 * 8 + i % 4 * 2;
+                            blk[j] = c;
+                            blk[j + 1] = c;
+                        } else {
+                            let j = i / 8 * 16 + i % 8;
+                            blk[j] = c;
+                            blk[j + 8] = c;
+                        }
+                    }
+                }
+            }
+            // 4 colors per quadrant or per half.
+            0xa => {
+                let mut p = read_colors::<4>(rd)?;
+                if p[0] <= p[1] {
+                    for q in 0..4 {
+                        if q > 0 {
+                            p = read_colors::<4>(rd)?;
+                        }
+                        let flags = rd.read_u32::<LittleEndian>()?;
+                        for i in 0..16 {
+                            blk[quadrant_pos(q, i)] = p[(flags >> (i * 2)) as usize & 3];
+                        }
+                    }
+                } else {
+                    let flags = rd.read_u64::<LittleEndian>()?;
+                    let p2 = read_colors::<4>(rd)?;
+                    let vert = p2[0] <= p2[1];
+                    for i in 0..32 {
+                        blk[half_pos(vert, 0, i)] = p[(flags >> (i * 2)) as usize & 3];
+                    }
+                    let flags = rd.read_u64::<LittleEndian>()?;
+                    for i in 0..32 {
+                        blk[half_pos(vert, 1, i)] = p2[(flags >> (i * 2)) as usize & 3];
+                    }
+                }
+            }
+            // Raw pixels.
+            0xb => rd.read_exact(&mut blk)?,
+            // Raw 2x2 blocks.
+            0xc => for i in 0..16 {
+                fill_2x2(&mut blk, i, rd.read_u8()?);
+            }
+            // Raw quadrants.
+            0xd => {
+                let p = read_colors::<4>(rd)?;
+                for (i, c) in blk.iter_mut().enumerate() {
+                    *c = p[i / 32 * 2 + i % 8 / 4];
+                }
+            }
+            // Solid color.
+            0xe => blk = [rd.read_u8()?; 64],
+            // Dithered 2 colors.
+            0xf => {
+                let p = read_colors::<2>(rd)?;
+                for (i, c) in blk.iter_mut().enumerate() {
+                    *c = p[(i / 8 + i % 8) & 1];
+                }
+            }
+            _ => unreachable!(),
+        }
+        for (row, src) in blk.chunks_exact(8).enumerate() {
+            let i = (y + row) * self.width + x;
+            self.front[i..i + 8].copy_from_slice(src);
+        }
+        Ok(())
+    }
+
+    fn copy_block(&mut self, x: usize, y: usize, from_front: bool, dx: i32, dy: i32)
+        -> io::Result<()>
+    {
+        let sx = x as i32 + dx;
+        let sy = y as i32 + dy;
+        if sx < 0 || sy < 0 || sx as usize + 8 > self.width || sy as usize + 8 > self.height {
+            return Err(Error::new(ErrorKind::InvalidData, "MVE motion vector is out of frame"));
+        }
+        let (sx, sy) = (sx as usize, sy as usize);
+        for row in 0..8 {
+            let src = (sy + row) * self.width + sx;
+            let dst = (y + row) * self.width + x;
+            if from_front {
+                self.front.copy_within(src..src + 8, dst);
+            } else {
+                self.front[dst..dst + 8].copy_from_slice(&self.back[src..src + 8]);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Decodes the whole movie writing every frame into `dir` as `NNNNN.ppm` and the sound as
+/// `audio.wav`. Returns the number of frames written.
+pub fn dump(rd: impl Read, dir: &Path) -> io::Result<usize> {
+    fs::create_dir_all(dir)?;
+    let mut dec = MveDecoder::new(rd)?;
+    let mut samples = Vec::new();
+    let mut frame_count = 0;
+    while dec.next_frame()? {
+        samples.extend(dec.take_audio());
+        let path = dir.join(format!("{:05}.ppm", frame_count));
+        let mut wr = BufWriter::new(File::create(path)?);
+        write!(wr, "P6\n{} {}\n255\n", dec.width(), dec.height())?;
+        for &i in dec.pixels() {
+            let rgb = dec.palette()[i as usize].scale::<Color8>();
+            wr.write_all(&[rgb.r(), rgb.g(), rgb.b()])?;
+        }
+        wr.flush()?;
+        frame_count += 1;
+    }
+    samples.extend(dec.take_audio());
+    if let Some(format) = dec.audio_format() {
+        let mut wr = BufWriter::new(File::create(dir.join("audio.wav"))?);
+        write_wav(&mut wr, &Pcm {
+            channels: format.channels,
+            sample_rate: format.sample_rate,
+            samples,
+        })?;
+        wr.flush()?;
+    }
+    Ok(frame_count)
+}
+
+/// Interplay DPCM. The stream starts with the initial sample of each channel followed by the
+/// per-sample deltas. Channels are interleaved.
+fn decode_dpcm(mut rd: &[u8], channels: usize, sample_count: usize, out: &mut Vec<i16>)
+    -> io::Result<()>
+{
+    let mut pred = [0i32; 2];
+    for p in pred.iter_mut().take(channels) {
+        *p = rd.read_i16::<LittleEndian>()? as i32;
+        out.push(*p as i16);
+    }
+    let mut ch = 0;
+    for _ in channels..sample_count {
+        let p = &mut pred[ch];
+        *p = (*p + DPCM_DELTAS[rd.read_u8()? as usize] as i32)
+            .clamp(i16::MIN as i32, i16::MAX as i32);
+        out.push(*p as i16);
+        ch = (ch + 1) % channels;
+    }
+    Ok(())
+}
+
+fn read_rgb18(rd: &mut impl Read) -> io::Result<Rgb18> {
+    let r = rd.read_u8()?;
+    let g = rd.read_u8()?;
+    let b = rd.read_u8()?;
+    Ok(Rgb::new(r & 0x3f, g & 0x3f, b & 0x3f))
+}
+
+fn read_colors<const N: usize>(rd: &mut impl Read) -> io::Result<[u8; N]> {
+    let mut r = [0; N];
+    rd.read_exact(&mut r)?;
+    Ok(r)
+}
+
+/// Motion vector of the block opcodes 0x2 and 0x3.
+fn far_motion(b: u8) -> (i32, i32) {
+    let b = b as i32;
+    if b < 56 {
+        (8 + b % 7, b / 7)
+    } else {
+        (-14 + (b - 56) % 29, 8 + (b - 56) / 29)
+    }
+}
+
+/// Fills `i`-th 2x2 block of the 8x8 block in raster order.
+fn fill_2x2(blk: &mut [u8; 64], i: usize, c: u8) {
+    let j = i / 4 * 16 + i % 4 * 2;
+    blk[j] = c;
+    blk[j + 1] = c;
+    blk[j + 8] = c;
+    blk[j + 9] = c;
+}
+
+/// Position of the `i`-th pixel of the `q`-th 4x4 quadrant. Quadrants are ordered top-left,
+/// bottom-left, top-right, bottom-right.
+fn quadrant_pos(q: usize, i: usize) -> usize {
+    (q % 2 * 4 + i / 4) * 8 + q / 2 * 4 + i % 4
+}
+
+/// Position of the `i`-th pixel of the `h`-th half which is either left/right (`vert`) or
+/// top/bottom half.
+fn half_pos(vert: bool, h: usize, i: usize) -> usize {
+    if vert {
+        i / 4 * 8 + h * 4 + i % 4
+    } else {
+        h * 32 + i
+    }
+}
+
+static DPCM_DELTAS: [i16; 256] = [
+         0,      1,      2,      3,      4,      5,      6,      7,
+         8,      9,     10,     11,     12,     13,     14,     15,
+        16,     17,     18,     19,     20,     21,     22,     23,
+        24,     25,     26,     27,     28,     29,     30,     31,
+        32,     33,     34,     35,     36,     37,     38,     39,
+        40,     41,     42,     43,     47,     51,     56,     61,
+        66,     72,     79,     86,     94,    102,    112,    122,
+       133,    145,    158,    173,    189,    206,    225,    245,
+       267,    292,    318,    348,    379,    414,    452,    493,
+       538,    587,    640,    699,    763,    832,    908,    991,
+      1081,   1180,   1288,   1405,   1534,   1673,   1826,   1993,
+      2175,   2373,   2590,   2826,   3084,   3365,   3672,   4008,
+      4373,   4772,   5208,   5683,   6202,   6767,   7385,   8059,
+      8794,   9597,  10472,  11428,  12471,  13609,  14851,  16206,
+     17685,  19298,  21060,  22981,  25078,  27367,  29864,  32589,
+    -29973, -26728, -23186, -19322, -15105, -10503,  -5481,     -1,
+         1,      1,   5481,  10503,  15105,  19322,  23186,  26728,
+     29973, -32589, -29864, -27367, -25078, -22981, -21060, -19298,
+    -17685, -16206, -14851, -13609, -12471, -11428, -10472,  -9597,
+     -8794,  -8059,  -7385,  -6767,  -6202,  -5683,  -5208,  -4772,
+     -4373,  -4008,  -3672,  -3365,  -3084,  -2826,  -2590,  -2373,
+     -2175,  -1993,  -1826,  -1673,  -1534,  -1405,  -1288,  -1180,
+     -1081,   -991,   -908,   -832,   -763,   -699,   -640,   -587,
+      -538,   -493,   -452,   -414,   -379,   -348,   -318,   -292,
+      -267,   -245,   -225,   -206,   -189,   -173,   -158,   -145,
+      -133,   -122,   -112,   -102,    -94,    -86,    -79,    -72,
+       -66,    -61,    -56,    -51,    -47,    -43,    -42,    -41,
+       -40,    -39,    -38,    -37,    -36,    -35,    -34,    -33,
+       -32,    -31,    -30,    -29,    -28,    -27,    -26,    -25,
+       -24,    -23,    -22,    -21,    -20,    -19,    -18,    -17,
+       -16,    -15,    -14,    -13,    -12,    -11,    -10,     -9,
+        -8,     -7,     -6,     -5,     -4,     -3,     -2,     -1,
+];
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn op(op: u8, version: u8, data: &[u8]) -> Vec<u8> {
+        let mut r = (data.len() as u16).to_le_bytes().to_vec();
+        r.extend([op, version]);
+        r.extend(data);
+        r
+    }
+
+    fn u16s(v: &[u16]) -> Vec<u8> {
+        v.iter().flat_map(|v| v.to_le_bytes()).collect()
+    }
+
+    fn video_data(flags: u16, blocks: &[u8]) -> Vec<u8> {
+        op(OP_VIDEO_DATA, 0, &[&u16s(&[0, 0, 0, 0, 0, 0, flags])[..], blocks].concat())
+    }
+
+    fn mve(chunks: &[&[Vec<u8>]]) -> Vec<u8> {
+        let mut r = MVE_SIGNATURE.to_vec();
+        for ops in chunks {
+            let mut data = ops.concat();
+            data.extend(op(OP_END_OF_CHUNK, 0, &[]));
+            r.extend(u16s(&[data.len() as u16, 0]));
+            r.extend(data);
+        }
+        r
+    }
+
+    fn decode_block(block_op: u8, blocks: &[u8]) -> Vec<u8> {
+        let data = mve(&[&[
+            op(OP_INIT_VIDEO_BUFFERS, 0, &u16s(&[1, 1])),
+            op(OP_SET_DECODING_MAP, 0, &[block_op]),
+            video_data(0, blocks),
+            op(OP_SEND_BUFFER, 0, &[0; 4]),
+        ]]);
+        let mut dec = MveDecoder::new(&data[..]).unwrap();
+        assert!(dec.next_frame().unwrap());
+        dec.pixels().to_vec()
+    }
+
+    #[test]
+    fn frames() {
+        let raw: Vec<u8> = (0..64).map(|i| i % 3).collect();
+        let data = mve(&[
+            &[
+                op(OP_CREATE_TIMER, 0, &[&1000u32.to_le_bytes()[..], &u16s(&[2])].concat()),
+                op(OP_INIT_VIDEO_BUFFERS, 0, &u16s(&[2, 1])),
+                op(OP_SET_PALETTE, 0, &[1, 0, 2, 0, 63, 0, 0, 0, 0x7f, 0]),
+            ],
+            &[
+                op(OP_SET_DECODING_MAP, 0, &[0xbe]),
+                video_data(0, &[&[1][..], &raw].concat()),
+                op(OP_SEND_BUFFER, 0, &[0; 4]),
+            ],
+            &[
+                op(OP_SET_DECODING_MAP, 0, &[0x31]),
+                video_data(0, &[0]),
+                op(OP_SEND_BUFFER, 0, &[0; 4]),
+            ],
+            &[
+                op(OP_SET_DECODING_MAP, 0, &[0x51]),
+                video_data(1, &[0xf8, 0]),
+                op(OP_SEND_BUFFER, 0, &[0; 4]),
+                op(OP_END_OF_STREAM, 0, &[]),
+            ],
+        ]);
+        let mut dec = MveDecoder::new(&data[..]).unwrap();
+
+        assert!(dec.next_frame().unwrap());
+        assert_eq!(dec.frame_duration(), Duration::from_millis(2));
+        assert_eq!((dec.width(), dec.height()), (16, 8));
+        assert_eq!(dec.palette()[0], Rgb::black());
+        assert_eq!(dec.palette()[1], Rgb::new(63, 0, 0));
+        assert_eq!(dec.palette()[2], Rgb::new(0, 63, 0));
+        for (i, row) in dec.pixels().chunks(16).enumerate() {
+            assert_eq!(&row[..8], &[1; 8]);
+            assert_eq!(&row[8..], &raw[i * 8..i * 8 + 8]);
+        }
+
+        assert!(dec.next_frame().unwrap());
+        assert_eq!(dec.pixels(), &[1; 128][..]);
+
+        // Buffers are swapped: left block is left from the frame before the previous one,
+        // right block is copied from the left block of the previous frame.
+        assert!(dec.next_frame().unwrap());
+        for row in dec.pixels().chunks(16) {
+            assert_eq!(&row[..8], &[0; 8]);
+            assert_eq!(&row[8..], &[1; 8]);
+        }
+
+        assert!(!dec.next_frame().unwrap());
+        assert!(!dec.next_frame().unwrap());
+    }
+
+    #[test]
+    fn block_patterns() {
+        let b = decode_block(0x7, &[1, 2, 1, 1, 1, 1, 1, 1, 1, 1]);
+        for row in b.chunks(8) {
+            assert_eq!(row, &[2, 1, 1, 1, 1, 1, 1, 1]);
+        }
+
+        let b = decode_block(0x7, &[2, 1, 1, 0]);
+        assert_eq!(&b[..4], &[1, 1, 2, 2]);
+        assert_eq!(&b[8..12], &[1, 1, 2, 2]);
+        assert!(b[16..].iter().all(|&c| c == 2));
+
+        let b = decode_block(0x8,
+            &[1, 2, 0xff, 0xff, 3, 4, 0, 0, 5, 6, 0xff, 0xff, 7, 8, 0, 0]);
+        for (i, row) in b.chunks(8).enumerate() {
+            if i < 4 {
+                assert_eq!(row, &[2, 2, 2, 2, 6, 6, 6, 6]);
+            } else {
+                assert_eq!(row, &[3, 3, 3, 3, 7, 7, 7, 7]);
+            }
+        }
+
+        let b = decode_block(0x9, &[2, 1, 4, 3, 1, 0, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(&b[..2], &[1, 2]);
+        assert_eq!(&b[8..10], &[1, 2]);
+        assert!(b[16..].iter().all(|&c| c == 2));
+
+        let b = decode_block(0xc, &(0..16).collect::<Vec<_>>());
+        assert_eq!(&b[..8], &[0, 0, 1, 1, 2, 2, 3, 3]);
+        assert_eq!(&b[8..16], &[0, 0, 1, 1, 2, 2, 3, 3]);
+        assert_eq!(&b[56..], &[12, 12, 13, 13, 14, 14, 15, 15]);
+
+        let b = decode_block(0xd, &[1, 2, 3, 4]);
+        assert_eq!(&b[..8], &[1, 1, 1, 1, 2, 2, 2, 2]);
+        assert_eq!(&b[56..], &[3, 3, 3, 3, 4, 4, 4, 4]);
+
+        let b = decode_block(0xf, &[1, 2]);
+        assert_eq!(&b[..8], &[1, 2, 1, 2, 1, 2, 1, 2]);
+        assert_eq!(&b[8..16], &[2, 1, 2, 1, 2, 1, 2, 1]);
+    }
+
+    #[test]
+    fn audio() {
+        let data = mve(&[&[
+            op(OP_INIT_AUDIO_BUFFERS, 1, &u16s(&[0, 1 | 2 | 4, 22050, 0, 0])),
+            op(OP_AUDIO_FRAME, 0, &[&u16s(&[0, 1, 12, 100, -100i16 as u16])[..],
+                &[1, 255, 43, 213]].concat()),
+            op(OP_AUDIO_FRAME, 0, &[&u16s(&[0, 2, 4, 1, 1])[..]].concat()),
+            op(OP_AUDIO_SILENCE, 0, &u16s(&[1, 1, 4])),
+        ]]);
+        let mut dec = MveDecoder::new(&data[..]).unwrap();
+        assert!(!dec.next_frame().unwrap());
+        assert_eq!(dec.audio_format(), Some(AudioFormat { channels: 2, sample_rate: 22050 }));
+        assert_eq!(dec.take_audio(), &[100, -100, 101, -101, 144, -144, 0, 0]);
+        assert!(dec.take_audio().is_empty());
+    }
+
+    #[test]
+    fn dump_frames() {
+        let data = mve(&[&[
+            op(OP_INIT_VIDEO_BUFFERS, 0, &u16s(&[1, 1])),
+            op(OP_SET_PALETTE, 0, &[1, 0, 1, 0, 63, 32, 0]),
+            op(OP_SET_DECODING_MAP, 0, &[0xe]),
+            video_data(0, &[1]),
+            op(OP_SEND_BUFFER, 0, &[0; 4]),
+            op(OP_SEND_BUFFER, 0, &[0; 4]),
+        ]]);
+        let dir = std::env::temp_dir().join(format!("vault13-movie-dump-{}", std::process::id()));
+        assert_eq!(dump(&data[..], &dir).unwrap(), 2);
+        let frame = fs::read(dir.join("00001.ppm")).unwrap();
+        assert!(!dir.join("audio.wav").exists());
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(&frame[..11], b"P6\n8 8\n255\n");
+        assert_eq!(frame.len(), 11 + 64 * 3);
+        assert_eq!(&frame[11..14], &[252, 128, 0]);
+    }
+
+    #[test]
+    fn bad_data() {
+        assert!(MveDecoder::new(&b"Interplay MVE File\x1a\0\0\0\0\0\0\0"[..]).is_err());
+
+        let data = mve(&[&[
+            op(OP_INIT_VIDEO_BUFFERS, 0, &u16s(&[1, 1])),
+            op(OP_SET_DECODING_MAP, 0, &[0x5]),
+            video_data(0, &[0, 1]),
+        ]]);
+        let e = MveDecoder::new(&data[..]).unwrap().next_frame().err().unwrap();
+        assert_eq!(e.kind(), ErrorKind::InvalidData);
+    }
+}
@@ -3,7 +3,7 @@ mod id;
 
 use bstring::{bstr, BString};
 use enumflags2::{bitflags, BitFlags};
-use linearize::StaticMap;
+use linearize::{Linearize, StaticMap};
 use num_traits::cast::FromPrimitive;
 
 pub use id::ProtoId;
@@ -23,6 +23,9 @@ pub const MSG_DOORWAY_SEEMS_TO_BE_BLOCKED: MessageId = 597;
 /// "You see: %s."
 pub const MSG_YOU_SEE_X: MessageId = 480;
 
+/// "It is locked."
+pub const MSG_IT_IS_LOCKED: MessageId = 487;
+
 pub type ProtoRef = std::rc::Rc<std::cell::RefCell<Proto>>;
 
 #[derive(Debug)]
@@ -321,7 +324,7 @@ pub struct Critter {
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
 #[repr(u32)]
 pub enum CritterFlag {
-    NoBarter        = 0x00000002, // Can barter with.
+    Barter          = 0x00000002, // Can barter with.
     NoSteal         = 0x00000020, // Can't steal from.
     NoDrop          = 0x00000040, // Doesn't drop items.
     NoLoseLimbs     = 0x00000080, // Can't shoot off limbs.
@@ -334,7 +337,7 @@ pub enum CritterFlag {
     NoKnock         = 0x00004000, // Can't knock down.
 }
 
-#[derive(Clone, Copy, Debug, Eq, PartialEq, Ord, PartialOrd, Primitive)]
+#[derive(Clone, Copy, Debug, Eq, Linearize, PartialEq, Ord, PartialOrd, Primitive)]
 pub enum CritterKillKind {
   Man = 0x0,
   Woman = 0x1,
@@ -448,8 +451,8 @@ impl MapExit {
         } else {
             TargetMap::CurrentMap
         };
-        let elevation = location & 0x3ffffff;
-        let pos = TileGrid::default().linear_to_rect_inv((location & 0xE0000000) >> 29)
+        let elevation = (location & 0xE0000000) >> 29;
+        let pos = TileGrid::default().linear_to_rect_inv(location & 0x3ffffff)
             .elevated(elevation);
         let direction = Direction::from_u32((location & 0x1C000000) >> 26)?;
         Some(MapExit {
@@ -458,6 +461,17 @@ impl MapExit {
             direction,
         })
     }
+
+    /// Inverse of `decode()`. Returns `(map, location)` pair.
+    pub fn encode(&self) -> (i32, u32) {
+        let map = match self.map {
+            TargetMap::CurrentMap => 0,
+            map => map.encode(),
+        };
+        let tile_num = TileGrid::default().rect_to_linear_inv(self.pos.point).unwrap();
+        let location = (self.pos.elevation << 29) | ((self.direction as u32) << 26) | tile_num;
+        (map, location)
+    }
 }
 
 #[derive(Clone, Copy, Eq, Debug, PartialEq)]
@@ -480,6 +494,15 @@ impl TargetMap {
             _ => return None,
         })
     }
+
+    pub fn encode(self) -> i32 {
+        match self {
+            TargetMap::Map { map_id } => map_id as i32,
+            TargetMap::CurrentMap => panic!("can't encode current map"),
+            TargetMap::WorldMap(WorldMapKind::Town) => -1,
+            TargetMap::WorldMap(WorldMapKind::World) => -2,
+        }
+    }
 }
 
 // Subset that has prototypes.
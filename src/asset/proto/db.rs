@@ -13,10 +13,12 @@ use crate::asset::frame::*;
 use crate::asset::message::{MessageId, Messages};
 use crate::game::script::ScriptPid;
 use crate::fs::FileSystem;
+use crate::profile::Profile;
 use crate::util::RangeInclusive;
 
 pub struct ProtoDb {
     fs: Rc<FileSystem>,
+    profile: Profile,
     lst: Lst,
     messages: Messages,
     entity_messages: StaticMap<EntityKind, Messages>,
@@ -24,7 +26,7 @@ pub struct ProtoDb {
 }
 
 impl ProtoDb {
-    pub fn new(fs: Rc<FileSystem>, language: &str) -> io::Result<Self> {
+    pub fn new(fs: Rc<FileSystem>, language: &str, profile: Profile) -> io::Result<Self> {
         let lst = Lst::read(&fs)?;
         let messages = Messages::read_file(&fs, language, "game/proto.msg")?;
         let entity_messages = Self::read_entity_messages(&fs, language)?;
@@ -57,6 +59,7 @@ impl ProtoDb {
 
         Ok(Self {
             fs,
+            profile,
             lst,
             messages,
             entity_messages,
@@ -145,7 +148,7 @@ impl ProtoDb {
 
         let sub = match kind {
             EntityKind::Item => SubProto::Item(Self::read_item(rd, &mut flags_ext)?),
-            EntityKind::Critter => SubProto::Critter(Self::read_critter(rd)?),
+            EntityKind::Critter => SubProto::Critter(Self::read_critter(rd, self.profile)?),
             EntityKind::Scenery => SubProto::Scenery(Self::read_scenery(rd)?),
             EntityKind::Wall => SubProto::Wall(Self::read_wall(rd)?),
             EntityKind::SqrTile => SubProto::SqrTile(Self::read_sqr_tile(&mut flags_ext)?),
@@ -403,7 +406,7 @@ impl ProtoDb {
         })
     }
 
-    fn read_critter(rd: &mut impl Read) -> io::Result<Critter> {
+    fn read_critter(rd: &mut impl Read, profile: Profile) -> io::Result<Critter> {
         let head_fid = FrameId::read_opt(rd)?;
         let ai_packet = rd.read_i32::<BigEndian>()?;
         let team_id = rd.read_i32::<BigEndian>()?;
@@ -428,7 +431,11 @@ impl ProtoDb {
         let body_kind = read_enum(rd, "invalid body kind in critter proto")?;
         let experience = rd.read_i32::<BigEndian>()?;
         let kill_kind = read_enum(rd, "invalid kill kind in critter proto")?;
-        let damage_kind = read_enum(rd, "invalid damage kind in critter proto")?;
+        let damage_kind = if profile.has_critter_damage_kind() {
+            read_enum(rd, "invalid damage kind in critter proto")?
+        } else {
+            DamageKind::Melee
+        };
 
         Ok(Critter {
             flags,
@@ -35,11 +35,34 @@ impl ScriptDb {
         self.infos.get(program_id.index())
     }
 
+    /// Finds program by name. The name is case-insensitive and may include `.int` extension.
+    pub fn program_id(&self, name: &bstr) -> Option<ProgramId> {
+        let name = name.as_bytes();
+        let name = if name.len() > 4 && name[name.len() - 4..].eq_ignore_ascii_case(b".int") {
+            &name[..name.len() - 4]
+        } else {
+            name
+        };
+        self.infos.iter()
+            .position(|i| i.name.as_bytes().eq_ignore_ascii_case(name))
+            .map(|i| ProgramId::new(i as u32 + 1).unwrap())
+    }
+
+    /// Returns bytecode of the program. If there's `scripts/<name>.ssl` source file it's compiled
+    /// instead of loading `scripts/<name>.int`.
     pub fn load(&self, program_id: ProgramId) -> io::Result<(Box<[u8]>, &ScriptInfo)> {
         let info = self.info_ok(program_id)?;
-        let path = format!("scripts/{}.int", info.name);
-        let mut code = Vec::new();
-        self.fs.reader(&path)?.read_to_end(&mut code)?;
+        let read = |path: &str| -> io::Result<Vec<u8>> {
+            let mut r = Vec::new();
+            self.fs.reader(path)?.read_to_end(&mut r)?;
+            Ok(r)
+        };
+        let src_path = format!("scripts/{}.ssl", info.name);
+        let code = if self.fs.exists(&src_path) {
+            crate::vm::ssl::compile::compile_file(&src_path, &mut |p| read(p))?
+        } else {
+            read(&format!("scripts/{}.int", info.name))?
+        };
         Ok((code.into(), info))
     }
 
@@ -109,6 +132,25 @@ fn read_lst(rd: &mut impl BufRead) -> io::Result<Vec<ScriptInfo>> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::util::test::Assets;
+    use crate::vm::Vm;
+
+    #[test]
+    fn load_source() {
+        let assets = Assets::with_files(|fs| {
+            fs.insert("scripts/test.ssl", "#include \"../headers/test.h\"\nprocedure start begin X; end");
+            fs.insert("headers/test.h", "#define X display_msg(\"hi\")");
+        });
+        let db = ScriptDb::new(assets.fs.clone(), "english").unwrap();
+        let program_id = ProgramId::new(Assets::PROGRAM).unwrap();
+        assert_eq!(db.program_id("TEST.INT".into()), Some(program_id));
+        assert_eq!(db.program_id("test".into()), Some(program_id));
+        assert_eq!(db.program_id("tes".into()), None);
+        let (code, info) = db.load(program_id).unwrap();
+        assert_eq!(info.name, "test");
+        let prg = Vm::default().load(info.name.clone(), code).unwrap();
+        assert_eq!(prg.proc(0).unwrap().name(), "start");
+    }
 
     #[test]
     fn read_lst_() {
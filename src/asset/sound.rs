@@ -0,0 +1,482 @@
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use std::cmp;
+use std::io::{self, Error, ErrorKind, prelude::*};
+
+const ACM_SIGNATURE: u32 = 0x01032897;
+
+pub const WAV_HEADER_LEN: u32 = 44;
+
+/// Decoded sound. Samples of multichannel sounds are interleaved.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Pcm {
+    pub channels: u16,
+    pub sample_rate: u32,
+    pub samples: Vec<i16>,
+}
+
+impl Pcm {
+    pub fn frame_count(&self) -> usize {
+        self.samples.len() / cmp::max(self.channels as usize, 1)
+    }
+}
+
+/// Reads and decodes the whole Interplay ACM stream.
+pub fn read_acm(rd: impl Read) -> io::Result<Pcm> {
+    let mut dec = AcmDecoder::new(rd)?;
+    let mut samples = vec![0; dec.sample_count()];
+    let mut pos = 0;
+    while pos < samples.len() {
+        let n = dec.read(&mut samples[pos..])?;
+        if n == 0 {
+            break;
+        }
+        pos += n;
+    }
+    samples.truncate(pos);
+    Ok(Pcm {
+        channels: dec.channels(),
+        sample_rate: dec.sample_rate(),
+        samples,
+    })
+}
+
+/// Writes the 16-bit PCM WAV header. `data_len` is the length of the sample data in bytes.
+pub fn write_wav_header(wr: &mut impl Write, channels: u16, sample_rate: u32, data_len: u32)
+    -> io::Result<()>
+{
+    let block_align = channels * 2;
+
+    wr.write_all(b"RIFF")?;
+    wr.write_u32::<LittleEndian>(WAV_HEADER_LEN - 8 + data_len)?;
+    wr.write_all(b"WAVE")?;
+
+    wr.write_all(b"fmt ")?;
+    wr.write_u32::<LittleEndian>(16)?;
+    // PCM
+    wr.write_u16::<LittleEndian>(1)?;
+    wr.write_u16::<LittleEndian>(channels)?;
+    wr.write_u32::<LittleEndian>(sample_rate)?;
+    wr.write_u32::<LittleEndian>(sample_rate * block_align as u32)?;
+    wr.write_u16::<LittleEndian>(block_align)?;
+    wr.write_u16::<LittleEndian>(16)?;
+
+    wr.write_all(b"data")?;
+    wr.write_u32::<LittleEndian>(data_len)
+}
+
+pub fn write_wav(wr: &mut impl Write, pcm: &Pcm) -> io::Result<()> {
+    write_wav_header(wr, pcm.channels, pcm.sample_rate, pcm.samples.len() as u32 * 2)?;
+    for &v in &pcm.samples {
+        wr.write_i16::<LittleEndian>(v)?;
+    }
+    Ok(())
+}
+
+/// Streaming decoder of Interplay ACM audio.
+///
+/// The stream consists of blocks of `rows x (1 << level)` values. Each column of a block is
+/// packed with one of the fillers selected by a 5-bit index. When `level` is non-zero the block
+/// is then passed through the inverse transform which keeps its state between blocks.
+pub struct AcmDecoder<R> {
+    bits: BitReader<R>,
+    channels: u16,
+    sample_rate: u32,
+    sample_count: usize,
+    samples_left: usize,
+    level: u32,
+    rows: usize,
+    block: Vec<i32>,
+    block_pos: usize,
+    wrap: Vec<i32>,
+}
+
+impl<R: Read> AcmDecoder<R> {
+    pub fn new(mut rd: R) -> io::Result<Self> {
+        let signature = rd.read_u32::<LittleEndian>()?;
+        if signature != ACM_SIGNATURE {
+            return Err(Error::new(ErrorKind::InvalidData, "bad ACM signature"));
+        }
+        let sample_count = rd.read_u32::<LittleEndian>()? as usize;
+        let channels = rd.read_u16::<LittleEndian>()?;
+        let sample_rate = rd.read_u16::<LittleEndian>()? as u32;
+        if channels == 0 {
+            return Err(Error::new(ErrorKind::InvalidData, "bad ACM channel count"));
+        }
+
+        let mut bits = BitReader::new(rd);
+        let level = bits.read(4)?;
+        let rows = bits.read(12)? as usize;
+        if rows == 0 {
+            return Err(Error::new(ErrorKind::InvalidData, "bad ACM row count"));
+        }
+        let cols = 1 << level;
+
+        Ok(Self {
+            bits,
+            channels,
+            sample_rate,
+            sample_count,
+            samples_left: sample_count,
+            level,
+            rows,
+            block: vec![0; rows * cols],
+            block_pos: rows * cols,
+            wrap: vec![0; 2 * cols - 2],
+        })
+    }
+
+    pub fn channels(&self) -> u16 {
+        self.channels
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    /// Total number of samples in all channels.
+    pub fn sample_count(&self) -> usize {
+        self.sample_count
+    }
+
+    /// Decodes samples into `buf` returning the number of samples written.
+    /// Returns 0 when the end of the stream is reached.
+    pub fn read(&mut self, buf: &mut [i16]) -> io::Result<usize> {
+        let mut pos = 0;
+        while pos < buf.len() && self.samples_left > 0 {
+            if self.block_pos == self.block.len() {
+                self.decode_block()?;
+            }
+            let count = cmp::min(cmp::min(buf.len() - pos, self.block.len() - self.block_pos),
+                self.samples_left);
+            let src = &self.block[self.block_pos..self.block_pos + count];
+            for (dst, &src) in buf[pos..pos + count].iter_mut().zip(src) {
+                *dst = (src >> self.level) as i16;
+            }
+            pos += count;
+            self.block_pos += count;
+            self.samples_left -= count;
+        }
+        Ok(pos)
+    }
+
+    fn decode_block(&mut self) -> io::Result<()> {
+        let _pwr = self.bits.read(4)?;
+        let amp = self.bits.read(16)? as i32;
+        for col in 0..1 << self.level {
+            let filler = self.bits.read(5)?;
+            self.fill_column(col, filler, amp)?;
+        }
+        self.juggle_block();
+        self.block_pos = 0;
+        Ok(())
+    }
+
+    fn fill_column(&mut self, col: usize, filler: u32, amp: i32) -> io::Result<()> {
+        static MAP_1BIT: [i32; 2] = [-1, 1];
+        static MAP_2BIT_NEAR: [i32; 4] = [-2, -1, 1, 2];
+        static MAP_2BIT_FAR: [i32; 4] = [-3, -2, 2, 3];
+        static MAP_3BIT: [i32; 8] = [-4, -3, -2, -1, 1, 2, 3, 4];
+
+        let bits = &mut self.bits;
+        let rows = self.rows;
+        let mut column = Column {
+            block: &mut self.block,
+            level: self.level,
+            col,
+            amp,
+        };
+        match filler {
+            0 => {
+                for row in 0..rows {
+                    column.set(row, 0);
+                }
+            }
+            3..=16 => {
+                let middle = 1 << (filler - 1);
+                for row in 0..rows {
+                    let v = bits.read(filler)? as i32;
+                    column.set(row, v - middle);
+                }
+            }
+            17 => column.fill_k(bits, rows, true, |b| Ok(MAP_1BIT[b.read(1)? as usize]))?,
+            18 => column.fill_k(bits, rows, false, |b| Ok(MAP_1BIT[b.read(1)? as usize]))?,
+            19 => column.fill_t(bits, rows, 5, 3, 3)?,
+            20 => column.fill_k(bits, rows, true, |b| Ok(MAP_2BIT_NEAR[b.read(2)? as usize]))?,
+            21 => column.fill_k(bits, rows, false, |b| Ok(MAP_2BIT_NEAR[b.read(2)? as usize]))?,
+            22 => column.fill_t(bits, rows, 7, 5, 3)?,
+            23 | 24 => column.fill_k(bits, rows, filler == 23, |b| Ok(if b.read(1)? == 0 {
+                MAP_1BIT[b.read(1)? as usize]
+            } else {
+                MAP_2BIT_FAR[b.read(2)? as usize]
+            }))?,
+            26 => column.fill_k(bits, rows, true, |b| Ok(MAP_3BIT[b.read(3)? as usize]))?,
+            27 => column.fill_k(bits, rows, false, |b| Ok(MAP_3BIT[b.read(3)? as usize]))?,
+            29 => column.fill_t(bits, rows, 7, 11, 2)?,
+            _ => return Err(Error::new(ErrorKind::InvalidData,
+                format!("bad ACM filler: {}", filler))),
+        }
+        Ok(())
+    }
+
+    fn juggle_block(&mut self) {
+        if self.level == 0 {
+            return;
+        }
+
+        let cols = 1 << self.level;
+        let step_rows = if self.level > 9 {
+            1
+        } else {
+            (2048 >> self.level) - 2
+        };
+
+        let mut rows_left = self.rows;
+        let mut block_pos = 0;
+        loop {
+            let block = &mut self.block[block_pos..];
+            let mut wrap = &mut self.wrap[..];
+
+            let mut sub_len = cols / 2;
+            let mut sub_count = cmp::min(step_rows, rows_left) * 2;
+
+            juggle(wrap, block, sub_len, sub_count);
+            wrap = &mut wrap[sub_len * 2..];
+
+            for i in 0..sub_count {
+                block[i * sub_len] = block[i * sub_len].wrapping_add(1);
+            }
+
+            while sub_len > 1 {
+                sub_len /= 2;
+                sub_count *= 2;
+                juggle(wrap, block, sub_len, sub_count);
+                wrap = &mut wrap[sub_len * 2..];
+            }
+
+            if rows_left <= step_rows {
+                break;
+            }
+            rows_left -= step_rows;
+            block_pos += step_rows * cols;
+        }
+    }
+}
+
+fn juggle(wrap: &mut [i32], block: &mut [i32], sub_len: usize, sub_count: usize) {
+    for i in 0..sub_len {
+        let mut r0 = wrap[i * 2];
+        let mut r1 = wrap[i * 2 + 1];
+        let mut p = i;
+        for _ in 0..sub_count / 2 {
+            let r2 = block[p];
+            block[p] = r1.wrapping_mul(2).wrapping_add(r0.wrapping_add(r2));
+            p += sub_len;
+            let r3 = block[p];
+            block[p] = r2.wrapping_mul(2).wrapping_sub(r1.wrapping_add(r3));
+            p += sub_len;
+            r0 = r2;
+            r1 = r3;
+        }
+        wrap[i * 2] = r0;
+        wrap[i * 2 + 1] = r1;
+    }
+}
+
+struct Column<'a> {
+    block: &'a mut [i32],
+    level: u32,
+    col: usize,
+    amp: i32,
+}
+
+impl Column<'_> {
+    fn set(&mut self, row: usize, v: i32) {
+        self.block[(row << self.level) + self.col] = v.wrapping_mul(self.amp);
+    }
+
+    /// Fillers where zeros are coded with one bit (or two zeros with one bit when `pairs` is set)
+    /// and non-zero values are read by `read_value`.
+    fn fill_k<R: Read>(&mut self, bits: &mut BitReader<R>, rows: usize, pairs: bool,
+        read_value: impl Fn(&mut BitReader<R>) -> io::Result<i32>) -> io::Result<()>
+    {
+        let mut row = 0;
+        while row < rows {
+            if pairs && bits.read(1)? == 0 {
+                self.set(row, 0);
+                if row + 1 < rows {
+                    self.set(row + 1, 0);
+                }
+                row += 2;
+                continue;
+            }
+            let v = if bits.read(1)? == 0 {
+                0
+            } else {
+                read_value(bits)?
+            };
+            self.set(row, v);
+            row += 1;
+        }
+        Ok(())
+    }
+
+    /// Fillers that pack `digits` values in base `base` into `bit_count` bits.
+    fn fill_t<R: Read>(&mut self, bits: &mut BitReader<R>, rows: usize, bit_count: u32,
+        base: u32, digits: usize) -> io::Result<()>
+    {
+        let offset = (base / 2) as i32;
+        let mut row = 0;
+        while row < rows {
+            let mut v = bits.read(bit_count)?;
+            for _ in 0..digits {
+                if row == rows {
+                    break;
+                }
+                self.set(row, (v % base) as i32 - offset);
+                v /= base;
+                row += 1;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Reads bits LSB first. Reading past the end of stream yields zero bits.
+struct BitReader<R> {
+    rd: R,
+    data: u32,
+    avail: u32,
+}
+
+impl<R: Read> BitReader<R> {
+    fn new(rd: R) -> Self {
+        Self {
+            rd,
+            data: 0,
+            avail: 0,
+        }
+    }
+
+    fn read(&mut self, count: u32) -> io::Result<u32> {
+        debug_assert!(count <= 16);
+        while self.avail < count {
+            let b = match self.rd.read_u8() {
+                Ok(v) => v,
+                Err(ref e) if e.kind() == ErrorKind::UnexpectedEof => 0,
+                Err(e) => return Err(e),
+            };
+            self.data |= (b as u32) << self.avail;
+            self.avail += 8;
+        }
+        let r = self.data & ((1 << count) - 1);
+        self.data >>= count;
+        self.avail -= count;
+        Ok(r)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::util::test::{acm, BitWriter};
+
+    fn header(level: u32, rows: u32, sample_count: u32) -> BitWriter {
+        let mut w = BitWriter::new();
+        w.write(ACM_SIGNATURE & 0xffff, 16);
+        w.write(ACM_SIGNATURE >> 16, 16);
+        w.write(sample_count, 16);
+        w.write(0, 16);
+        w.write(1, 16);
+        w.write(22050, 16);
+        w.write(level, 4);
+        w.write(rows, 12);
+        w
+    }
+
+    fn decode(data: &[u8]) -> Vec<i16> {
+        read_acm(data).unwrap().samples
+    }
+
+    #[test]
+    fn linear() {
+        let samples = [0, 1, -1, 32767, -32768, 1234];
+        let pcm = read_acm(&acm(2, 44100, &samples)[..]).unwrap();
+        assert_eq!(pcm, Pcm {
+            channels: 2,
+            sample_rate: 44100,
+            samples: samples.to_vec(),
+        });
+        assert_eq!(pcm.frame_count(), 3);
+    }
+
+    #[test]
+    fn fill_k13() {
+        let mut w = header(0, 5, 5);
+        w.write(0, 4);
+        w.write(3, 16);
+        w.write(17, 5);
+        for &(v, n) in &[(0, 1), (0b01, 2), (0b011, 3), (0b111, 3)] {
+            w.write(v, n);
+        }
+        assert_eq!(decode(&w.finish()), &[0, 0, 0, -3, 3]);
+    }
+
+    #[test]
+    fn fill_k34() {
+        let mut w = header(0, 4, 4);
+        w.write(0, 4);
+        w.write(1, 16);
+        w.write(24, 5);
+        for &(v, n) in &[(0, 1), (0b101, 3), (0b00_11, 4), (0b11_11, 4)] {
+            w.write(v, n);
+        }
+        assert_eq!(decode(&w.finish()), &[0, 1, -3, 3]);
+    }
+
+    #[test]
+    fn fill_t15() {
+        let mut w = header(0, 4, 4);
+        w.write(0, 4);
+        w.write(2, 16);
+        w.write(19, 5);
+        w.write(2 + 9, 5);
+        w.write(1, 5);
+        assert_eq!(decode(&w.finish()), &[2, -2, 0, 0]);
+    }
+
+    #[test]
+    fn juggle_keeps_state_between_blocks() {
+        let mut w = header(1, 1, 4);
+        for &(a, b) in &[(5, 3), (1, 1)] {
+            w.write(0, 4);
+            w.write(1, 16);
+            for v in [a, b] {
+                w.write(4, 5);
+                w.write((v + 8) as u32, 4);
+            }
+        }
+        assert_eq!(decode(&w.finish()), &[3, 4, 6, -1]);
+    }
+
+    #[test]
+    fn bad_data() {
+        assert_eq!(read_acm(&b"RIFF\0\0\0\0\0\0\0\0\0\0\0\0"[..]).unwrap_err().kind(),
+            ErrorKind::InvalidData);
+
+        let mut w = header(0, 1, 1);
+        w.write(0, 4);
+        w.write(1, 16);
+        w.write(1, 5);
+        assert_eq!(read_acm(&w.finish()[..]).unwrap_err().kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn truncated_stream_is_zero_padded() {
+        let mut data = acm(1, 22050, &[100, 200, 300]);
+        data.truncate(data.len() - 4);
+        let samples = decode(&data);
+        assert_eq!(samples.len(), 3);
+        assert_eq!(samples[0], 100);
+        assert_eq!(samples[2], -32768);
+    }
+}
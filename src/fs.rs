@@ -1,4 +1,5 @@
 pub mod dat;
+pub mod memory;
 pub mod std;
 
 use ::std::io::prelude::*;
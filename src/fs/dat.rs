@@ -2,3 +2,64 @@ mod lzss;
 mod util;
 pub mod v1;
 pub mod v2;
+
+use byteorder::{LittleEndian, ReadBytesExt};
+use std::io::{Result, Seek, SeekFrom};
+use std::io::prelude::*;
+use std::path::Path;
+
+use super::Provider;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Version {
+    /// Fallout 1 format with LZSS compression.
+    V1,
+    /// Fallout 2 format with zlib compression.
+    V2,
+}
+
+/// Detects format of the DAT file. DAT v2 file ends with its own size.
+pub fn detect_version(rd: &mut (impl Read + Seek)) -> Result<Version> {
+    let len = rd.seek(SeekFrom::End(0))?;
+    if len < 8 {
+        return Ok(Version::V1);
+    }
+    rd.seek(SeekFrom::End(-4))?;
+    let size = rd.read_u32::<LittleEndian>()?;
+    Ok(if size as u64 == len {
+        Version::V2
+    } else {
+        Version::V1
+    })
+}
+
+pub fn new_provider<P: AsRef<Path>>(path: P, version: Version) -> Result<Box<dyn Provider>> {
+    match version {
+        Version::V1 => v1::new_provider(path),
+        Version::V2 => v2::new_provider(path),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use byteorder::{BigEndian, WriteBytesExt};
+    use std::io::Cursor;
+
+    #[test]
+    fn detect_version_() {
+        let mut v1 = Vec::new();
+        for v in [1, 0, 0, 0] {
+            v1.write_u32::<BigEndian>(v).unwrap();
+        }
+        v1.extend_from_slice(&[1, b'.']);
+        assert_eq!(detect_version(&mut Cursor::new(&v1)).unwrap(), Version::V1);
+
+        let mut v2 = vec![0; 4];
+        v2.write_u32::<LittleEndian>(4).unwrap();
+        v2.write_u32::<LittleEndian>(12).unwrap();
+        assert_eq!(detect_version(&mut Cursor::new(&v2)).unwrap(), Version::V2);
+
+        assert_eq!(detect_version(&mut Cursor::new(&[])).unwrap(), Version::V1);
+    }
+}
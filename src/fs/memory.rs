@@ -0,0 +1,71 @@
+use std::collections::HashMap;
+use std::io::{Cursor, BufRead, Error, ErrorKind, Result};
+use std::sync::Arc;
+
+use super::{Metadata, Provider};
+
+/// Provider that serves files from memory. File paths are case-insensitive and both `/` and `\`
+/// are accepted as separators.
+#[derive(Clone, Default)]
+pub struct MemoryFileSystem {
+    files: HashMap<String, Arc<[u8]>>,
+}
+
+impl MemoryFileSystem {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn insert(&mut self, path: &str, data: impl Into<Vec<u8>>) {
+        self.files.insert(Self::normalize(path), data.into().into());
+    }
+
+    pub fn remove(&mut self, path: &str) -> bool {
+        self.files.remove(&Self::normalize(path)).is_some()
+    }
+
+    fn normalize(path: &str) -> String {
+        path.replace('\\', "/").to_ascii_lowercase()
+    }
+
+    fn get(&self, path: &str) -> Result<&Arc<[u8]>> {
+        self.files.get(&Self::normalize(path))
+            .ok_or_else(|| Error::new(ErrorKind::NotFound, format!("file not found: {}", path)))
+    }
+}
+
+impl Provider for MemoryFileSystem {
+    fn reader(&self, path: &str) -> Result<Box<dyn BufRead + Send>> {
+        Ok(Box::new(Cursor::new(ArcBytes(self.get(path)?.clone()))))
+    }
+
+    fn metadata(&self, path: &str) -> Result<Metadata> {
+        let len = self.get(path)?.len() as u64;
+        Ok(Metadata { len })
+    }
+}
+
+struct ArcBytes(Arc<[u8]>);
+
+impl AsRef<[u8]> for ArcBytes {
+    fn as_ref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Read;
+
+    #[test]
+    fn case_insensitive() {
+        let mut fs = MemoryFileSystem::new();
+        fs.insert("Art\\Critters\\FOO.LST", &b"hello"[..]);
+        let mut s = String::new();
+        fs.reader("art/critters/foo.lst").unwrap().read_to_string(&mut s).unwrap();
+        assert_eq!(s, "hello");
+        assert_eq!(fs.metadata("ART/CRITTERS/foo.lst").unwrap().len(), 5);
+        assert_eq!(fs.reader("art/critters/bar.lst").err().unwrap().kind(), ErrorKind::NotFound);
+    }
+}
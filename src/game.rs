@@ -1,29 +1,55 @@
+pub mod ai;
+pub mod automap;
+pub mod barter;
+pub mod char_select;
+pub mod character_editor;
+pub mod combat;
 pub mod dialog;
+pub mod drug;
 pub mod fidget;
 pub mod inventory;
+pub mod loot;
+pub mod main_menu;
+pub mod movie;
 pub mod object;
+pub mod options;
+pub mod party;
+pub mod pipboy;
+pub mod queue;
 pub mod rpg;
+pub mod save_load;
+pub mod savegame;
 pub mod script;
+pub mod sfx;
 pub mod sequence;
 pub mod skilldex;
 pub mod state;
 pub mod ui;
 pub mod world;
+pub mod worldmap;
 
 use crate::util::random::RollChecker;
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
 pub struct GameTime(u32);
 
 impl GameTime {
+    pub const MINUTE: Self = Self(60 * 10);
+    pub const HOUR: Self = Self(60 * 60 * 10);
+    pub const DAY: Self = Self(24 * 60 * 60 * 10);
+
     pub const fn from_decis(decis: u32) -> Self {
         Self(decis)
     }
 
-    pub fn as_decis(self) -> u32 {
+    pub const fn as_decis(self) -> u32 {
         self.0
     }
 
+    pub fn add_decis(self, decis: u32) -> Self {
+        Self(self.0 + decis)
+    }
+
     pub fn as_seconds(self) -> u32 {
         self.0 / 10
     }
@@ -0,0 +1,747 @@
+use enumflags2::{bitflags, BitFlags};
+use log::*;
+use std::cmp;
+use std::collections::HashMap;
+use std::io::{self, BufRead, Error, ErrorKind};
+use std::time::{Duration, Instant};
+
+use crate::asset::{read_ini, AttackCategory, AttackGroup, CritterAnim, Stat};
+use crate::asset::frame::{FrameDb, FrameId};
+use crate::asset::proto::{Drug, DrugEffectModifier, ProtoId};
+use crate::fs::FileSystem;
+use crate::game::object::{self, DamageFlag, Object, Objects};
+use crate::game::rpg::Rpg;
+use crate::graphics::{EPoint, Point};
+use crate::graphics::geometry::hex::{self, TileGrid};
+use crate::util::random::random;
+
+/// Action points spent on wielding a weapon, reloading it or using a drug.
+pub const INVENTORY_AP_COST: i32 = 2;
+
+/// Critters with higher aggression pick up weapons outside of combat.
+const ARM_AGGRESSION: i32 = 50;
+
+/// How often the critters outside of combat look after themselves.
+const IDLE_INTERVAL: Duration = Duration::from_secs(3);
+
+/// Max distance to the weapon lying on the ground the critter will go to pick it up.
+const PICK_UP_DISTANCE: u32 = 5;
+
+/// The sniper steps back when the enemy gets closer than this.
+const SNIPE_DISTANCE: u32 = 4;
+
+/// The stay-close critter doesn't go farther than this from the dude.
+const STAY_CLOSE_DISTANCE: u32 = 5;
+
+/// Team mates closer than this to the target can be hit by the area attack.
+const AREA_ATTACK_RADIUS: u32 = 2;
+
+fn invalid_data(msg: String) -> Error {
+    Error::new(ErrorKind::InvalidData, msg)
+}
+
+macro_rules! named_enum {
+    ($(#[$attr:meta])* $name:ident { $($(#[$vattr:meta])* $variant:ident = $s:literal,)* }) => {
+        $(#[$attr])*
+        #[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+        pub enum $name {
+            $($(#[$vattr])* $variant,)*
+        }
+
+        impl $name {
+            pub fn parse(s: &str) -> Option<Self> {
+                match s {
+                    $($s => Some(Self::$variant),)*
+                    _ => None,
+                }
+            }
+        }
+    };
+}
+
+named_enum!(
+    /// Whether the critter uses burst and other area attacks that can hit its team mates.
+    AreaAttackMode {
+        Always = "always",
+        #[default]
+        Sometimes = "sometimes",
+        BeSure = "be_sure",
+        BeCareful = "be_careful",
+        BeAbsolutelySure = "be_absolutely_sure",
+    }
+);
+
+named_enum!(
+    /// Overrides `Packet::min_hp` with a percent of the max hit points.
+    RunAwayMode {
+        #[default]
+        None = "none",
+        Coward = "coward",
+        FingerHurts = "finger_hurts",
+        Bleeding = "bleeding",
+        NotFeelingGood = "not_feeling_good",
+        Tourniquet = "tourniquet",
+        Never = "never",
+    }
+);
+
+impl RunAwayMode {
+    /// Percent of the max hit points the critter can lose before running away.
+    fn hp_loss_percent(self) -> Option<i32> {
+        use RunAwayMode::*;
+        Some(match self {
+            None => return Option::None,
+            Coward => 0,
+            FingerHurts => 25,
+            Bleeding => 40,
+            NotFeelingGood => 60,
+            Tourniquet => 75,
+            Never => 100,
+        })
+    }
+}
+
+named_enum!(
+    BestWeapon {
+        #[default]
+        NoPref = "no_pref",
+        Melee = "melee",
+        MeleeOverRanged = "melee_over_ranged",
+        RangedOverMelee = "ranged_over_melee",
+        Ranged = "ranged",
+        Unarmed = "unarmed",
+        UnarmedOverThrown = "unarmed_over_thrown",
+        Random = "random",
+    }
+);
+
+impl BestWeapon {
+    /// Returns rank of the attack category, lower is better. `None` if the category is not
+    /// acceptable.
+    fn rank(self, category: AttackCategory) -> Option<u32> {
+        use AttackCategory::*;
+        use BestWeapon::*;
+        let ranged = matches!(category, Throw | Fire);
+        match self {
+            NoPref | Random => Some(0),
+            Melee => (!ranged).then_some(0),
+            MeleeOverRanged => Some(ranged as u32),
+            RangedOverMelee => Some(!ranged as u32),
+            Ranged => ranged.then_some(0),
+            Unarmed => (category == MeleeUnarmed).then_some(0),
+            UnarmedOverThrown => match category {
+                MeleeUnarmed => Some(0),
+                Throw => Some(1),
+                _ => Option::None,
+            },
+        }
+    }
+}
+
+named_enum!(
+    /// How the critter keeps distance to its target.
+    Distance {
+        StayClose = "stay_close",
+        #[default]
+        Charge = "charge",
+        Snipe = "snipe",
+        OnYourOwn = "on_your_own",
+        Stay = "stay",
+    }
+);
+
+named_enum!(
+    AttackWho {
+        WhomeverAttackingMe = "whomever_attacking_me",
+        Strongest = "strongest",
+        Weakest = "weakest",
+        #[default]
+        Whomever = "whomever",
+        Closest = "closest",
+    }
+);
+
+named_enum!(
+    ChemUse {
+        #[default]
+        Clean = "clean",
+        StimsWhenHurtLittle = "stims_when_hurt_little",
+        StimsWhenHurtLots = "stims_when_hurt_lots",
+        Sometimes = "sometimes",
+        Anytime = "anytime",
+        Always = "always",
+    }
+);
+
+named_enum!(
+    /// Preset of the packet. Used for choosing the behavior of party members.
+    Disposition {
+        #[default]
+        None = "none",
+        Custom = "custom",
+        Coward = "coward",
+        Defensive = "defensive",
+        Aggressive = "aggressive",
+        Berserk = "berserk",
+    }
+);
+
+#[bitflags]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[repr(u8)]
+pub enum HurtTooMuch {
+    Blind = 1,
+    Crippled = 2,
+    CrippledLegs = 4,
+    CrippledArms = 8,
+}
+
+impl HurtTooMuch {
+    fn parse(s: &str) -> Option<Self> {
+        Some(match s {
+            "blind" => Self::Blind,
+            "crippled" => Self::Crippled,
+            "crippled_legs" => Self::CrippledLegs,
+            "crippled_arms" => Self::CrippledArms,
+            _ => return None,
+        })
+    }
+
+    fn damage_flags(self) -> BitFlags<DamageFlag> {
+        use DamageFlag::*;
+        match self {
+            Self::Blind => Blind.into(),
+            Self::Crippled => CripLegLeft | CripLegRight | CripArmLeft | CripArmRight,
+            Self::CrippledLegs => CripLegLeft | CripLegRight,
+            Self::CrippledArms => CripArmLeft | CripArmRight,
+        }
+    }
+}
+
+/// AI packet from `data/ai.txt`.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Packet {
+    pub name: String,
+    pub num: i32,
+    pub aggression: i32,
+    pub area_attack_mode: AreaAttackMode,
+    pub attack_who: AttackWho,
+    pub best_weapon: BestWeapon,
+    /// One in `called_freq` attacks is a called shot.
+    pub called_freq: i32,
+    /// Drugs the critter prefers in order of preference.
+    pub chem_primary_desire: Vec<ProtoId>,
+    pub chem_use: ChemUse,
+    pub disposition: Disposition,
+    pub distance: Distance,
+    /// The critter runs away if it has any of these injuries.
+    pub hurt_too_much: BitFlags<HurtTooMuch>,
+    /// Enemies farther than this are ignored unless they attack the critter.
+    pub max_dist: i32,
+    /// The critter runs away when its hit points go below this.
+    pub min_hp: i32,
+    /// Hit chance below which the critter tries to get closer before attacking.
+    pub min_to_hit: i32,
+    pub run_away_mode: RunAwayMode,
+    pub secondary_freq: i32,
+}
+
+impl Packet {
+    /// Hit points below which the critter runs away.
+    pub fn min_hp(&self, max_hp: i32) -> i32 {
+        match self.run_away_mode.hp_loss_percent() {
+            Some(pct) => max_hp - max_hp * pct / 100,
+            None => self.min_hp,
+        }
+    }
+
+    /// Damage flags that make the critter run away.
+    pub fn hurt_too_much_flags(&self) -> BitFlags<DamageFlag> {
+        self.hurt_too_much.iter().fold(BitFlags::empty(), |r, v| r | v.damage_flags())
+    }
+
+    // ai_check_drugs()
+    /// Whether the critter having `hp` out of `max_hp` hit points will use a healing drug.
+    /// `roll` is in 1..=100 range.
+    pub fn wants_healing(&self, hp: i32, max_hp: i32, roll: i32) -> bool {
+        use ChemUse::*;
+        let (threshold, chance) = match self.chem_use {
+            Clean => return false,
+            StimsWhenHurtLittle => (60, 100),
+            StimsWhenHurtLots => (30, 100),
+            Sometimes => (50, 25),
+            Anytime => (50, 75),
+            Always => (50, 100),
+        };
+        hp < max_hp * threshold / 100 && roll <= chance
+    }
+
+    /// Whether the area attack is allowed. `friends_at_risk` tells if any team mates can be hit.
+    /// `roll` is in 1..=100 range.
+    pub fn allows_area_attack(&self, hit_chance: i32, friends_at_risk: bool, roll: i32) -> bool {
+        use AreaAttackMode::*;
+        match self.area_attack_mode {
+            Always => true,
+            Sometimes => !friends_at_risk || roll <= 50,
+            BeSure => !friends_at_risk || hit_chance >= 85,
+            BeCareful => !friends_at_risk && hit_chance >= 50,
+            BeAbsolutelySure => !friends_at_risk && hit_chance >= 95,
+        }
+    }
+}
+
+/// AI packets keyed by `packet_num`.
+pub struct AiDb {
+    packets: HashMap<i32, Packet>,
+}
+
+impl AiDb {
+    // combat_ai_init()
+    pub fn new(fs: &FileSystem) -> io::Result<Self> {
+        Self::read(&mut fs.reader("data/ai.txt")?)
+    }
+
+    fn read(rd: &mut impl BufRead) -> io::Result<Self> {
+        let ini = read_ini(rd)?;
+        let mut packets = HashMap::with_capacity(ini.len());
+        for (name, section) in ini {
+            let get = |k: &str| section.get(k).map(|v| v.trim());
+            let int = |k: &str| -> io::Result<i32> {
+                get(k).map(|v| v.parse()
+                        .map_err(|_| invalid_data(format!("bad {} in AI packet {}: {}", k, name, v))))
+                    .unwrap_or(Ok(0))
+            };
+            fn enum_<T: Default>(name: &str, key: &str, v: Option<&str>,
+                parse: fn(&str) -> Option<T>) -> T
+            {
+                v.map(|v| parse(&v.to_ascii_lowercase()).unwrap_or_else(|| {
+                    warn!("unknown {} in AI packet {}: {}", key, name, v);
+                    T::default()
+                })).unwrap_or_default()
+            }
+
+            let num = get("packet_num")
+                .ok_or_else(|| invalid_data(format!("missing packet_num in AI packet {}", name)))?;
+            let num = num.parse()
+                .map_err(|_| invalid_data(format!("bad packet_num in AI packet {}: {}", name, num)))?;
+
+            let chem_primary_desire = get("chem_primary_desire").unwrap_or("")
+                .split(',')
+                .filter_map(|v| v.trim().parse::<i32>().ok())
+                .filter(|&v| v > 0)
+                .filter_map(|v| ProtoId::from_packed(v as u32))
+                .collect();
+
+            let mut hurt_too_much = BitFlags::empty();
+            for v in get("hurt_too_much").unwrap_or("").split(',') {
+                let v = v.trim().to_ascii_lowercase();
+                if v.is_empty() {
+                    continue;
+                }
+                if let Some(v) = HurtTooMuch::parse(&v) {
+                    hurt_too_much |= v;
+                } else {
+                    warn!("unknown hurt_too_much in AI packet {}: {}", name, v);
+                }
+            }
+
+            let packet = Packet {
+                num,
+                aggression: int("aggression")?,
+                area_attack_mode: enum_(&name, "area_attack_mode", get("area_attack_mode"),
+                    AreaAttackMode::parse),
+                attack_who: enum_(&name, "attack_who", get("attack_who"), AttackWho::parse),
+                best_weapon: enum_(&name, "best_weapon", get("best_weapon"), BestWeapon::parse),
+                called_freq: int("called_freq")?,
+                chem_primary_desire,
+                chem_use: enum_(&name, "chem_use", get("chem_use"), ChemUse::parse),
+                disposition: enum_(&name, "disposition", get("disposition"), Disposition::parse),
+                distance: enum_(&name, "distance", get("distance"), Distance::parse),
+                hurt_too_much,
+                max_dist: int("max_dist")?,
+                min_hp: int("min_hp")?,
+                min_to_hit: int("min_to_hit")?,
+                run_away_mode: enum_(&name, "run_away_mode", get("run_away_mode"),
+                    RunAwayMode::parse),
+                secondary_freq: int("secondary_freq")?,
+                name,
+            };
+            if let Some(existing) = packets.insert(num, packet) {
+                warn!("duplicate AI packet number {} in {}", num, existing.name);
+            }
+        }
+        Ok(Self {
+            packets,
+        })
+    }
+
+    pub fn get(&self, num: i32) -> Option<&Packet> {
+        self.packets.get(&num)
+    }
+
+    pub fn get_mut(&mut self, num: i32) -> Option<&mut Packet> {
+        self.packets.get_mut(&num)
+    }
+}
+
+/// Critter AI. In combat the game state asks it what the critter should do during its turn.
+/// Outside of combat the critters periodically heal themselves and pick up weapons.
+pub struct Ai {
+    db: AiDb,
+    next_idle_time: Instant,
+}
+
+impl Ai {
+    pub fn new(db: AiDb, now: Instant) -> Self {
+        Self {
+            db,
+            next_idle_time: now + IDLE_INTERVAL,
+        }
+    }
+
+    /// Returns AI packet of the critter.
+    pub fn packet(&self, obj: &Object) -> Option<&Packet> {
+        let num = obj.sub.as_critter()?.combat.ai_packet;
+        let r = self.db.get(num);
+        if r.is_none() {
+            debug!("critter {:?} has unknown AI packet {}", obj.handle(), num);
+        }
+        r
+    }
+
+    pub fn packet_mut(&mut self, num: i32) -> Option<&mut Packet> {
+        self.db.get_mut(num)
+    }
+
+    // ai_set_disposition()
+    /// Returns number of the critter's packet with the `disposition`. The critters that can
+    /// switch dispositions (the party members) have consecutive packets, one per disposition.
+    pub fn disposition_packet(&self, obj: &Object, disposition: Disposition) -> Option<i32> {
+        let packet = self.packet(obj)?;
+        let num = packet.num - (packet.disposition as i32 - disposition as i32);
+        self.db.get(num)
+            .filter(|p| p.disposition == disposition)
+            .map(|p| p.num)
+    }
+
+    /// Returns `true` if it's time for the critters outside of combat to look after themselves.
+    pub fn is_idle_time(&mut self, time: Instant) -> bool {
+        if time < self.next_idle_time {
+            return false;
+        }
+        self.next_idle_time = time + IDLE_INTERVAL;
+        true
+    }
+}
+
+/// Returns current and max hit points of the critter.
+pub fn hit_points(obj: &Object, rpg: &Rpg, objs: &Objects) -> (i32, i32) {
+    let hp = obj.sub.as_critter().map(|c| c.hit_points).unwrap_or(0);
+    (hp, rpg.stat(Stat::HitPoints, obj, objs))
+}
+
+/// Whether the critter is hurt enough to run away.
+pub fn is_hurt_too_much(packet: &Packet, obj: &Object, rpg: &Rpg, objs: &Objects) -> bool {
+    let Some(critter) = obj.sub.as_critter() else { return false };
+    let (hp, max_hp) = hit_points(obj, rpg, objs);
+    hp < packet.min_hp(max_hp)
+        || critter.combat.damage_flags.intersects(packet.hurt_too_much_flags())
+}
+
+// ai_danger_source()
+/// Chooses whom to attack among the `enemies`. `attackers` are the enemies attacking the critter.
+pub fn choose_target(
+    obj: object::Handle,
+    packet: &Packet,
+    enemies: &[object::Handle],
+    attackers: &[object::Handle],
+    objs: &Objects,
+) -> Option<object::Handle> {
+    let distance = |h| objs.distance(obj, h).unwrap_or(u32::MAX);
+    let candidates: Vec<_> = enemies.iter().copied()
+        .filter(|&h| packet.max_dist <= 0
+            || distance(h) <= packet.max_dist as u32
+            || attackers.contains(&h))
+        .collect();
+    let closest = |hs: &[object::Handle]| hs.iter().copied().min_by_key(|&h| distance(h));
+    let hp = |h| objs.get(h).sub.as_critter().map(|c| c.hit_points).unwrap_or(0);
+    match packet.attack_who {
+        AttackWho::WhomeverAttackingMe => closest(attackers).or_else(|| closest(&candidates)),
+        AttackWho::Strongest => candidates.iter().copied().max_by_key(|&h| hp(h)),
+        AttackWho::Weakest => candidates.iter().copied().min_by_key(|&h| hp(h)),
+        AttackWho::Whomever => if candidates.is_empty() {
+            None
+        } else {
+            Some(candidates[random(0, candidates.len() as i32 - 1) as usize])
+        }
+        AttackWho::Closest => closest(&candidates),
+    }
+}
+
+/// Returns ammo in the `owner`'s inventory that can be loaded into the `weapon`.
+pub fn find_ammo(owner: &Object, weapon: object::Handle, objs: &Objects) -> Option<object::Handle> {
+    let weapono = objs.get(weapon);
+    if weapono.proto_id() == Some(ProtoId::SOLAR_SCORCHER) {
+        return None;
+    }
+    owner.inventory.items.iter()
+        .map(|i| i.object)
+        .find(|&h| h != weapon && weapono.can_reload_weapon(&objs.get(h)).is_some_and(|v| v > 0))
+}
+
+/// Whether the critter can wield the weapon and attack with it.
+pub fn can_use_weapon(owner: &Object, weapon: &Object, frm_db: &FrameDb, objs: &Objects) -> bool {
+    let Some(proto) = weapon.proto() else { return false };
+    let Some(w) = proto.sub.as_weapon() else { return false };
+    let has_anim = owner.fid.critter().is_some_and(|fid|
+        frm_db.exists(FrameId::from(fid.with_weapon(w.kind).with_anim(CritterAnim::Stand))));
+    has_anim && (w.max_ammo_count == 0
+        || weapon.ammo_count().unwrap_or(0) > 0
+        || find_ammo(owner, weapon.handle(), objs).is_some())
+}
+
+// ai_search_inven_weap()
+/// Returns the best weapon in the critter inventory according to the packet preference.
+/// `None` means the critter should fight unarmed. The `wielded` weapon is kept if it's as good
+/// as the others.
+pub fn best_weapon(
+    owner: &Object,
+    wielded: Option<object::Handle>,
+    packet: &Packet,
+    frm_db: &FrameDb,
+    objs: &Objects,
+) -> Option<object::Handle> {
+    let key = |h: object::Handle| {
+        let weapon = objs.get(h);
+        if !can_use_weapon(owner, &weapon, frm_db, objs) {
+            return None;
+        }
+        let proto = weapon.proto().unwrap();
+        let w = proto.sub.as_weapon().unwrap();
+        let rank = packet.best_weapon.rank(w.attack_kinds[AttackGroup::Primary].category())?;
+        let damage = if packet.best_weapon == BestWeapon::Random {
+            0
+        } else {
+            w.damage.start + w.damage.end
+        };
+        Some((rank, cmp::Reverse(damage), Some(h) != wielded))
+    };
+    let best = owner.inventory.items.iter()
+        .map(|i| i.object)
+        .filter_map(|h| key(h).map(|k| (k, h)))
+        .min_by_key(|&(k, _)| k);
+    if packet.best_weapon == BestWeapon::Random
+        && best.is_some_and(|((_, _, not_wielded), _)| not_wielded)
+    {
+        let all: Vec<_> = owner.inventory.items.iter()
+            .map(|i| i.object)
+            .filter(|&h| key(h).is_some())
+            .collect();
+        return Some(all[random(0, all.len() as i32 - 1) as usize]);
+    }
+    best.map(|(_, h)| h)
+}
+
+/// Returns hit points restored immediately by the drug.
+pub fn drug_healing(drug: &Drug) -> i32 {
+    drug.effects.iter()
+        .filter(|e| e.delay == 0 && e.stat == Stat::CurrentHitPoints)
+        .map(|e| match e.modifier {
+            DrugEffectModifier::Fixed(v) => v,
+            DrugEffectModifier::Random(min, max) => (min + max) / 2,
+        })
+        .sum()
+}
+
+/// Returns the healing drug in the critter inventory that restores most hit points.
+/// Drugs from the `chem_primary_desire` list are preferred.
+pub fn find_healing_drug(owner: &Object, packet: &Packet, objs: &Objects)
+    -> Option<object::Handle>
+{
+    owner.inventory.items.iter()
+        .map(|i| i.object)
+        .filter_map(|h| {
+            let item = objs.get(h);
+            let proto = item.proto()?;
+            let healing = drug_healing(proto.sub.as_item()?.sub.as_drug()?);
+            let desire = packet.chem_primary_desire.iter()
+                .position(|&pid| pid == proto.id())
+                .unwrap_or(usize::MAX);
+            (healing > 0).then_some(((desire, cmp::Reverse(healing)), h))
+        })
+        .min_by_key(|&(k, _)| k)
+        .map(|(_, h)| h)
+}
+
+/// Returns the closest weapon lying on the ground the critter can pick up and use.
+pub fn find_weapon_nearby(owner: &Object, hex_grid: &TileGrid, frm_db: &FrameDb, objs: &Objects)
+    -> Option<object::Handle>
+{
+    let pos = owner.try_pos()?;
+    let mut best = None;
+    for y in pos.point.y - PICK_UP_DISTANCE as i32..=pos.point.y + PICK_UP_DISTANCE as i32 {
+        for x in pos.point.x - PICK_UP_DISTANCE as i32..=pos.point.x + PICK_UP_DISTANCE as i32 {
+            let p = Point::new(x, y);
+            if !hex_grid.is_in_bounds(p) {
+                continue;
+            }
+            let distance = hex::distance(pos.point, p);
+            if distance > PICK_UP_DISTANCE || best.is_some_and(|(d, _)| d <= distance) {
+                continue;
+            }
+            for &h in objs.at(EPoint::new(pos.elevation, p)) {
+                let o = objs.get(h);
+                if can_use_weapon(owner, &o, frm_db, objs) {
+                    best = Some((distance, h));
+                    break;
+                }
+            }
+        }
+    }
+    best.map(|(_, h)| h)
+}
+
+/// Returns the point `steps` hexes farther from `enemy` than the critter is.
+pub fn flee_point(obj: &Object, enemy: &Object, steps: u32, hex_grid: &TileGrid) -> Option<Point> {
+    let from = enemy.try_pos()?.point;
+    let to = obj.try_pos()?.point;
+    if from == to {
+        return None;
+    }
+    Some(hex_grid.beyond(from, to, hex::distance(from, to) + steps))
+}
+
+/// Returns the point the sniper steps back to if the `enemy` is too close.
+pub fn snipe_point(packet: &Packet, ranged: bool, obj: &Object, enemy: &Object,
+    hex_grid: &TileGrid) -> Option<Point>
+{
+    if packet.distance != Distance::Snipe || !ranged {
+        return None;
+    }
+    let distance = obj.distance(enemy)?;
+    if distance >= SNIPE_DISTANCE {
+        return None;
+    }
+    flee_point(obj, enemy, SNIPE_DISTANCE - distance, hex_grid)
+}
+
+/// Whether the critter outside of combat arms itself with weapons lying nearby.
+pub fn arms_when_idle(packet: &Packet) -> bool {
+    packet.aggression > ARM_AGGRESSION
+}
+
+/// Whether the critter may move towards the `target`.
+pub fn may_approach(packet: &Packet, target: &Object, objs: &Objects) -> bool {
+    match packet.distance {
+        Distance::Stay => false,
+        Distance::StayClose => objs.dude_ref().distance(target)
+            .is_some_and(|d| d <= STAY_CLOSE_DISTANCE),
+        Distance::Charge | Distance::Snipe | Distance::OnYourOwn => true,
+    }
+}
+
+/// Whether team mates of the `attacker` are close enough to the `target` to be hit by
+/// an area attack.
+pub fn friends_at_risk(attacker: &Object, target: &Object, objs: &Objects) -> bool {
+    let Some(team) = attacker.sub.as_critter().map(|c| c.combat.team_id) else { return false };
+    objs.iter()
+        .filter(|&h| h != attacker.handle() && h != target.handle())
+        .any(|h| {
+            let o = objs.get(h);
+            o.sub.as_critter().is_some_and(|c| !c.is_dead() && c.combat.team_id == team)
+                && o.distance(target).is_some_and(|d| d <= AREA_ATTACK_RADIUS)
+        })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::{BufReader, Cursor};
+
+    #[test]
+    fn read() {
+        let inp = "
+[Cannibal]
+aggression=90
+area_attack_mode=be_careful
+attack_who=Whomever_attacking_me
+best_weapon=melee_over_ranged
+called_freq=20
+chem_primary_desire=40,-1,-1
+chem_use=stims_when_hurt_lots
+distance=charge
+hurt_too_much=blind, crippled_legs
+max_dist=40
+min_hp=6
+min_to_hit=20
+packet_num=5
+run_away_mode=bleeding
+secondary_freq=10000
+
+[Coward]
+packet_num=6
+disposition=coward
+run_away_mode=whatever
+";
+        let db = AiDb::read(&mut BufReader::new(Cursor::new(inp))).unwrap();
+        let p = db.get(5).unwrap();
+        assert_eq!(p, &Packet {
+            name: "Cannibal".into(),
+            num: 5,
+            aggression: 90,
+            area_attack_mode: AreaAttackMode::BeCareful,
+            attack_who: AttackWho::WhomeverAttackingMe,
+            best_weapon: BestWeapon::MeleeOverRanged,
+            called_freq: 20,
+            chem_primary_desire: vec![ProtoId::from_packed(40).unwrap()],
+            chem_use: ChemUse::StimsWhenHurtLots,
+            disposition: Disposition::None,
+            distance: Distance::Charge,
+            hurt_too_much: HurtTooMuch::Blind | HurtTooMuch::CrippledLegs,
+            max_dist: 40,
+            min_hp: 6,
+            min_to_hit: 20,
+            run_away_mode: RunAwayMode::Bleeding,
+            secondary_freq: 10000,
+        });
+        assert_eq!(p.min_hp(50), 30);
+        assert_eq!(p.hurt_too_much_flags(),
+            DamageFlag::Blind | DamageFlag::CripLegLeft | DamageFlag::CripLegRight);
+        assert!(p.wants_healing(14, 50, 100));
+        assert!(!p.wants_healing(15, 50, 1));
+
+        let p = db.get(6).unwrap();
+        assert_eq!(p.disposition, Disposition::Coward);
+        assert_eq!(p.run_away_mode, RunAwayMode::None);
+        assert_eq!(p.min_hp(50), 0);
+        assert!(!p.wants_healing(1, 50, 1));
+
+        assert!(db.get(7).is_none());
+        assert!(AiDb::read(&mut BufReader::new(Cursor::new("[Bad]\naggression=1\n"))).is_err());
+    }
+
+    #[test]
+    fn run_away_mode() {
+        let mut p = Packet { min_hp: 10, ..Default::default() };
+        assert_eq!(p.min_hp(100), 10);
+        for (mode, min_hp) in [
+            (RunAwayMode::Coward, 100),
+            (RunAwayMode::FingerHurts, 75),
+            (RunAwayMode::Tourniquet, 25),
+            (RunAwayMode::Never, 0),
+        ] {
+            p.run_away_mode = mode;
+            assert_eq!(p.min_hp(100), min_hp);
+        }
+    }
+
+    #[test]
+    fn best_weapon_rank() {
+        use AttackCategory::*;
+        assert_eq!(BestWeapon::MeleeOverRanged.rank(MeleeWeapon), Some(0));
+        assert_eq!(BestWeapon::MeleeOverRanged.rank(Fire), Some(1));
+        assert_eq!(BestWeapon::Ranged.rank(MeleeWeapon), None);
+        assert_eq!(BestWeapon::UnarmedOverThrown.rank(Throw), Some(1));
+        assert_eq!(BestWeapon::UnarmedOverThrown.rank(Fire), None);
+    }
+}
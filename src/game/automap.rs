@@ -0,0 +1,143 @@
+use std::collections::HashMap;
+
+use crate::asset::EntityKind;
+use crate::asset::map::MapId;
+use crate::game::object::Objects;
+use crate::graphics::Point;
+use crate::graphics::geometry::hex::TileGrid;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Cell {
+    Empty,
+    Wall,
+    Scenery,
+}
+
+/// Layout of walls and scenery of a single map elevation as shown in the Pip-Boy.
+#[derive(Clone, Debug)]
+pub struct Automap {
+    width: i32,
+    height: i32,
+    cells: Vec<Cell>,
+}
+
+impl Automap {
+    // automap_pip_save()
+    pub fn new(objs: &Objects, hex_grid: &TileGrid, elevation: u32) -> Self {
+        let width = hex_grid.width();
+        let height = hex_grid.height();
+        let mut cells = vec![Cell::Empty; hex_grid.len()];
+        for h in objs.iter() {
+            let obj = objs.get(h);
+            let Some(pos) = obj.try_pos().filter(|p| p.elevation == elevation) else {
+                continue;
+            };
+            let cell = match obj.kind() {
+                EntityKind::Wall => Cell::Wall,
+                EntityKind::Scenery => Cell::Scenery,
+                _ => continue,
+            };
+            if let Some(i) = hex_grid.rect_to_linear(pos.point) {
+                let c = &mut cells[i as usize];
+                // Walls take precedence over scenery.
+                if *c != Cell::Wall {
+                    *c = cell;
+                }
+            }
+        }
+        Self {
+            width,
+            height,
+            cells,
+        }
+    }
+
+    pub fn width(&self) -> i32 {
+        self.width
+    }
+
+    pub fn height(&self) -> i32 {
+        self.height
+    }
+
+    pub fn get(&self, p: Point) -> Cell {
+        if p.x >= 0 && p.x < self.width && p.y >= 0 && p.y < self.height {
+            self.cells[(p.y * self.width + p.x) as usize]
+        } else {
+            Cell::Empty
+        }
+    }
+}
+
+/// Automaps of the visited map elevations.
+#[derive(Default)]
+pub struct AutomapDb {
+    maps: HashMap<(MapId, u32), Automap>,
+}
+
+impl AutomapDb {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn clear(&mut self) {
+        self.maps.clear();
+    }
+
+    /// Records the current layout of the map elevation replacing the previous one.
+    pub fn update(&mut self, map_id: MapId, elevation: u32, objs: &Objects, hex_grid: &TileGrid) {
+        self.maps.insert((map_id, elevation), Automap::new(objs, hex_grid, elevation));
+    }
+
+    pub fn get(&self, map_id: MapId, elevation: u32) -> Option<&Automap> {
+        self.maps.get(&(map_id, elevation))
+    }
+
+    /// Returns the visited map elevations ordered by map and elevation.
+    pub fn visited(&self) -> Vec<(MapId, u32)> {
+        let mut r: Vec<_> = self.maps.keys().copied().collect();
+        r.sort();
+        r
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::util::test::Assets;
+
+    #[test]
+    fn update() {
+        let assets = Assets::new();
+        let hex_grid = TileGrid::default();
+        let mut objs = Objects::new(hex_grid.clone(), 3, assets.frm_db.clone(),
+            assets.proto_db.clone());
+        let mut create = |pid, x, elevation| {
+            let proto = assets.proto_db.proto(Assets::pid(pid)).unwrap();
+            let _ = objs.create(None, Some(proto), Some(Point::new(x, 20).elevated(elevation)),
+                None);
+        };
+        create(Assets::WALL, 10, 0);
+        create(Assets::SCENERY, 11, 0);
+        create(Assets::SCENERY, 10, 0);
+        create(Assets::WALL, 12, 1);
+        create(Assets::MISC_ITEM, 13, 0);
+
+        let mut db = AutomapDb::new();
+        db.update(5, 0, &objs, &hex_grid);
+        assert_eq!(db.visited(), vec![(5, 0)]);
+        assert!(db.get(5, 1).is_none());
+
+        let map = db.get(5, 0).unwrap();
+        assert_eq!(map.width(), hex_grid.width());
+        assert_eq!(map.get(Point::new(10, 20)), Cell::Wall);
+        assert_eq!(map.get(Point::new(11, 20)), Cell::Scenery);
+        assert_eq!(map.get(Point::new(12, 20)), Cell::Empty);
+        assert_eq!(map.get(Point::new(13, 20)), Cell::Empty);
+        assert_eq!(map.get(Point::new(-1, 20)), Cell::Empty);
+
+        db.update(5, 1, &objs, &hex_grid);
+        assert_eq!(db.visited(), vec![(5, 0), (5, 1)]);
+        assert_eq!(db.get(5, 1).unwrap().get(Point::new(12, 20)), Cell::Wall);
+    }
+}
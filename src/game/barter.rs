@@ -0,0 +1,548 @@
+use bstring::bfmt::ToBString;
+use bstring::BString;
+use linearize::{static_map, Linearize, StaticMap};
+
+use crate::asset::frame::FrameId;
+use crate::asset::message::{MessageId, Messages};
+use crate::asset::proto::{CritterFlag, ProtoId};
+use crate::asset::{Flag, Perk, Skill, Stat};
+use crate::fs::FileSystem;
+use crate::game::object::{self, InventoryItem, Object, Objects};
+use crate::game::party::Party;
+use crate::game::rpg::Rpg;
+use crate::game::ui::inventory_list::{self, InventoryList, Scroll};
+use crate::game::ui::move_window::MoveWindow;
+use crate::game::world::WorldRef;
+use crate::graphics::color::GREEN;
+use crate::graphics::font::{DrawOptions, FontKey, HorzAlign};
+use crate::graphics::sprite::Sprite;
+use crate::graphics::{Point, Rect};
+use crate::ui::button::{self, Button};
+use crate::ui::command::barter::Command;
+use crate::ui::command::{inventory, move_window, UiCommand, UiCommandData};
+use crate::ui::panel::{self, Panel};
+use crate::ui::{self, Cursor, Ui};
+
+/// No, your offer is not good enough.
+const MSG_BAD_OFFER: MessageId = 28;
+/// Sorry, you cannot carry that much.
+const MSG_DUDE_OVERLOADED: MessageId = 31;
+/// Sorry, that's too much to carry.
+const MSG_NPC_OVERLOADED: MessageId = 32;
+
+/// Returns the price the NPC asks for goods that cost `cost` in total, `caps` of which are
+/// bottle caps. Caps are always traded at face value. The rest is scaled by the ratio of the
+/// barter skills and by the barter modifier set by the script.
+// barter_compute_value()
+pub fn trade_value(
+    cost: i32,
+    caps: i32,
+    dude_barter: i32,
+    npc_barter: i32,
+    barter_mod: i32,
+    master_trader: bool,
+) -> i32 {
+    let perk_bonus = if master_trader { 25.0 } else { 0.0 };
+    let mut mod_mult = (barter_mod as f64 + 100.0 - perk_bonus) * 0.01;
+    if mod_mult < 0.0 {
+        mod_mult = 0.01;
+    }
+    let balanced = (160.0 + npc_barter as f64) / (160.0 + dude_barter as f64)
+        * ((cost - caps) as f64 * 2.0);
+    (mod_mult * balanced + caps as f64) as i32
+}
+
+/// Whether the dude can barter with the critter.
+pub fn can_barter(obj: &Object, rpg: &Rpg) -> bool {
+    rpg.is_party_member(obj)
+        || obj.proto().and_then(|p| p.sub.as_critter().map(|c| c.flags))
+            .is_some_and(|f| f.contains(CritterFlag::Barter))
+}
+
+pub struct Barter {
+    msgs: Option<Messages>,
+    world: WorldRef,
+    internal: Option<Internal>,
+}
+
+impl Barter {
+    pub fn new(world: WorldRef, fs: &FileSystem, language: &str) -> Self {
+        let msgs = Some(Messages::read_file(fs, language, "game/inventry.msg").unwrap());
+        Self {
+            msgs,
+            world,
+            internal: None,
+        }
+    }
+
+    pub fn is_visible(&self) -> bool {
+        self.internal.is_some()
+    }
+
+    /// Shows the barter window for trading with the `npc`.
+    // gdialog_barter_create_win(), barter_inventory()
+    pub fn show(&mut self, npc: object::Handle, barter_mod: i32, rpg: &Rpg, party: &Party,
+        ui: &mut Ui)
+    {
+        let internal = Internal::new(self.msgs.take().unwrap(), self.world.clone(), npc,
+            barter_mod, ui);
+        internal.sync_to_ui(rpg, party, ui);
+        assert!(self.internal.replace(internal).is_none());
+    }
+
+    // gdialog_barter_destroy_win()
+    pub fn hide(&mut self, ui: &mut Ui) {
+        let i = self.internal.take().unwrap();
+        self.msgs = Some(i.hide(ui));
+    }
+
+    /// Returns the NPC reply to show in the dialog, if any.
+    pub fn handle(&mut self, cmd: UiCommand, rpg: &Rpg, party: &Party, ui: &mut Ui)
+        -> Option<BString>
+    {
+        if cmd.data == UiCommandData::Barter(Command::Talk) {
+            self.hide(ui);
+            return None;
+        }
+        self.internal.as_mut()?.handle(cmd, rpg, party, ui)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, Linearize, PartialEq)]
+enum List {
+    DudeInventory,
+    DudeOffer,
+    NpcOffer,
+    NpcInventory,
+}
+
+impl List {
+    fn is_offer(self) -> bool {
+        matches!(self, Self::DudeOffer | Self::NpcOffer)
+    }
+
+    /// The list items can be moved to and from.
+    fn counterpart(self) -> Self {
+        match self {
+            Self::DudeInventory => Self::DudeOffer,
+            Self::DudeOffer => Self::DudeInventory,
+            Self::NpcOffer => Self::NpcInventory,
+            Self::NpcInventory => Self::NpcOffer,
+        }
+    }
+}
+
+struct ListWidgets {
+    list: ui::Handle,
+    scroll_up: ui::Handle,
+    scroll_down: ui::Handle,
+}
+
+struct PendingMove {
+    src: List,
+    object: object::Handle,
+    win: MoveWindow,
+}
+
+struct Internal {
+    msgs: Messages,
+    world: WorldRef,
+    npc: object::Handle,
+    barter_mod: i32,
+    win: ui::Handle,
+    lists: StaticMap<List, ListWidgets>,
+    dude_value: ui::Handle,
+    npc_value: ui::Handle,
+    dude_offer: Vec<InventoryItem>,
+    npc_offer: Vec<InventoryItem>,
+    move_window: Option<PendingMove>,
+}
+
+impl Internal {
+    fn new(
+        msgs: Messages,
+        world: WorldRef,
+        npc: object::Handle,
+        barter_mod: i32,
+        ui: &mut Ui,
+    ) -> Self {
+        let win = ui.new_window(Rect::with_size(0, 290, 640, 190),
+            Some(Sprite::new(FrameId::BARTER)));
+        ui.widget_base_mut(win).set_modal(true);
+        ui.widget_base_mut(win).set_cursor(Some(Cursor::Hand));
+
+        let mut new_list = |rect: Rect, scroll_x: i32| {
+            let scroll_button = |scroll, up, down, disabled| {
+                let mut b = Button::new(up, down,
+                    Some(UiCommandData::Inventory(inventory::Command::Scroll(scroll))));
+                b.config_mut(button::State::Disabled).background = Some(Sprite::new(disabled));
+                b
+            };
+            let scroll_up = ui.new_widget(win, Rect::with_size(scroll_x, rect.top, 22, 23),
+                None, None,
+                scroll_button(Scroll::Up, FrameId::INVENTORY_SCROLL_UP_UP,
+                    FrameId::INVENTORY_SCROLL_UP_DOWN, FrameId::INVENTORY_SCROLL_UP_DISABLED));
+            let scroll_down = ui.new_widget(win, Rect::with_size(scroll_x, rect.top + 23, 22, 23),
+                None, None,
+                scroll_button(Scroll::Down, FrameId::INVENTORY_SCROLL_DOWN_UP,
+                    FrameId::INVENTORY_SCROLL_DOWN_DOWN, FrameId::INVENTORY_SCROLL_DOWN_DISABLED));
+            let list = ui.new_widget(win, rect, None, None, InventoryList::new(40, 8));
+            ListWidgets {
+                list,
+                scroll_up,
+                scroll_down,
+            }
+        };
+        let lists = static_map! {
+            List::DudeInventory => new_list(Rect::with_size(29, 10, 64, 48 * 3), 100),
+            List::DudeOffer => new_list(Rect::with_size(165, 20, 64, 48 * 3), 236),
+            List::NpcOffer => new_list(Rect::with_size(411, 20, 64, 48 * 3), 382),
+            List::NpcInventory => new_list(Rect::with_size(547, 10, 64, 48 * 3), 518),
+        };
+
+        fn value_panel() -> Panel {
+            let mut p = Panel::new();
+            p.set_text(Some(panel::Text {
+                text: "".into(),
+                font: FontKey::antialiased(1),
+                color: GREEN,
+                options: DrawOptions {
+                    horz_align: HorzAlign::Center,
+                    ..Default::default()
+                },
+            }));
+            p
+        }
+        let dude_value = ui.new_widget(win, Rect::with_size(165, 168, 64, 1), None, None,
+            value_panel());
+        let npc_value = ui.new_widget(win, Rect::with_size(411, 168, 64, 1), None, None,
+            value_panel());
+
+        ui.new_widget(win, Rect::with_size(41, 163, 14, 14), None, None,
+            Button::new(FrameId::DI_RDBT2, FrameId::DI_RDBT1,
+                Some(UiCommandData::Barter(Command::Offer))));
+        ui.new_widget(win, Rect::with_size(584, 162, 14, 14), None, None,
+            Button::new(FrameId::DI_RDBT2, FrameId::DI_RDBT1,
+                Some(UiCommandData::Barter(Command::Talk))));
+
+        Self {
+            msgs,
+            world,
+            npc,
+            barter_mod,
+            win,
+            lists,
+            dude_value,
+            npc_value,
+            dude_offer: Vec::new(),
+            npc_offer: Vec::new(),
+            move_window: None,
+        }
+    }
+
+    fn hide(self, ui: &mut Ui) -> Messages {
+        if let Some(m) = self.move_window {
+            m.win.hide(ui);
+        }
+        ui.remove(self.win);
+        self.msgs
+    }
+
+    fn owner(&self, list: List, objs: &Objects) -> object::Handle {
+        match list {
+            List::DudeInventory | List::DudeOffer => objs.dude(),
+            List::NpcOffer | List::NpcInventory => self.npc,
+        }
+    }
+
+    fn offer(&self, list: List) -> &Vec<InventoryItem> {
+        match list {
+            List::DudeInventory | List::DudeOffer => &self.dude_offer,
+            List::NpcOffer | List::NpcInventory => &self.npc_offer,
+        }
+    }
+
+    fn offer_mut(&mut self, list: List) -> &mut Vec<InventoryItem> {
+        match list {
+            List::DudeInventory | List::DudeOffer => &mut self.dude_offer,
+            List::NpcOffer | List::NpcInventory => &mut self.npc_offer,
+        }
+    }
+
+    fn offered_count(&self, list: List, object: object::Handle) -> u32 {
+        self.offer(list).iter()
+            .find(|i| i.object == object)
+            .map(|i| i.count)
+            .unwrap_or(0)
+    }
+
+    /// Number of the `object` items that can be moved out of the `list`.
+    fn available_count(&self, list: List, object: object::Handle) -> u32 {
+        if list.is_offer() {
+            return self.offered_count(list, object);
+        }
+        let world = self.world.borrow();
+        let owner = world.objects().get(self.owner(list, world.objects()));
+        owner.inventory.items.iter()
+            .find(|i| i.object == object)
+            .map(|i| i.count - self.offered_count(list, object))
+            .unwrap_or(0)
+    }
+
+    fn list_at(&self, widget: ui::Handle) -> Option<List> {
+        self.lists.iter().find(|(_, w)| w.list == widget).map(|(l, _)| l)
+    }
+
+    fn sync_to_ui(&self, rpg: &Rpg, party: &Party, ui: &Ui) {
+        let world = self.world.borrow();
+        let objs = world.objects();
+        for (list, w) in self.lists.iter() {
+            let mut listw = ui.widget_mut::<InventoryList>(w.list);
+            let scroll_idx = listw.scroll_idx();
+            listw.clear();
+            if list.is_offer() {
+                for item in self.offer(list) {
+                    listw.push(make_list_item(item.object, item.count, objs));
+                }
+            } else {
+                let owner = objs.get(self.owner(list, objs));
+                for item in &owner.inventory.items {
+                    if objs.get(item.object).flags
+                        .intersects(Flag::Worn | Flag::LeftHand | Flag::RightHand)
+                    {
+                        continue;
+                    }
+                    let count = item.count - self.offered_count(list, item.object);
+                    if count > 0 {
+                        listw.push(make_list_item(item.object, count, objs));
+                    }
+                }
+            }
+            listw.set_scroll_idx(scroll_idx);
+            ui.widget_mut::<Button>(w.scroll_up).set_enabled(listw.can_scroll(Scroll::Up));
+            ui.widget_mut::<Button>(w.scroll_down).set_enabled(listw.can_scroll(Scroll::Down));
+        }
+
+        let (dude_value, npc_value) = if self.is_npc_party_member(rpg, objs) {
+            // Party members trade for free, show the weights instead.
+            (offer_weight(&self.dude_offer, objs).to_bstring(),
+                offer_weight(&self.npc_offer, objs).to_bstring())
+        } else {
+            let dude_value = offer_cost(&self.dude_offer, objs);
+            let npc_value = self.price(rpg, party, objs);
+            (BString::concat(&[&b"$"[..], dude_value.to_bstring().as_bytes()]),
+                BString::concat(&[&b"$"[..], npc_value.to_bstring().as_bytes()]))
+        };
+        ui.widget_mut::<Panel>(self.dude_value).text_mut().unwrap().text = dude_value;
+        ui.widget_mut::<Panel>(self.npc_value).text_mut().unwrap().text = npc_value;
+    }
+
+    fn is_npc_party_member(&self, rpg: &Rpg, objs: &Objects) -> bool {
+        rpg.is_party_member(&objs.get(self.npc))
+    }
+
+    /// Price the NPC asks for the offered goods.
+    fn price(&self, rpg: &Rpg, party: &Party, objs: &Objects) -> i32 {
+        let cost = offer_cost(&self.npc_offer, objs);
+        let caps = self.npc_offer.iter()
+            .filter(|i| objs.get(i.object).proto_id() == Some(ProtoId::BOTTLE_CAPS))
+            .map(|i| i.count as i32)
+            .sum();
+        let dude_barter = party.best_skill_level(Skill::Barter, rpg, objs);
+        let npc_barter = rpg.skill(Skill::Barter, &objs.get(self.npc), objs);
+        let master_trader = rpg.has_perk(Perk::MasterTrader, ProtoId::DUDE);
+        trade_value(cost, caps, dude_barter, npc_barter, self.barter_mod, master_trader)
+    }
+
+    fn scroll(&self, button: ui::Handle, scroll: Scroll, ui: &Ui) -> bool {
+        let Some(w) = self.lists.values().find(|w| w.scroll_up == button || w.scroll_down == button)
+            else { return false };
+        let mut list = ui.widget_mut::<InventoryList>(w.list);
+        list.scroll(scroll);
+        ui.widget_mut::<Button>(w.scroll_up).set_enabled(list.can_scroll(Scroll::Up));
+        ui.widget_mut::<Button>(w.scroll_down).set_enabled(list.can_scroll(Scroll::Down));
+        true
+    }
+
+    // barter_move_inventory(), barter_move_from_table_inventory()
+    fn handle_list_drop(&mut self, src: List, pos: Point, object: object::Handle,
+        rpg: &Rpg, party: &Party, ui: &mut Ui)
+    {
+        let Some(target) = ui.widget_at(pos).and_then(|w| self.list_at(w)) else { return };
+        if target != src.counterpart() {
+            return;
+        }
+        let max = self.available_count(src, object);
+        if max > 1 {
+            let fid = {
+                let world = self.world.borrow();
+                let obj = world.objects().get(object);
+                obj.proto().unwrap().sub.as_item().unwrap().inventory_fid.unwrap()
+            };
+            let win = MoveWindow::show(fid, max, &self.msgs, ui);
+            assert!(self.move_window.replace(PendingMove { src, object, win }).is_none());
+        } else if max == 1 {
+            self.move_items(src, object, 1);
+            self.sync_to_ui(rpg, party, ui);
+        }
+    }
+
+    fn move_items(&mut self, src: List, object: object::Handle, count: u32) {
+        let offer = self.offer_mut(src);
+        let idx = offer.iter().position(|i| i.object == object);
+        if src.is_offer() {
+            let idx = idx.unwrap();
+            offer[idx].count -= count;
+            if offer[idx].count == 0 {
+                offer.remove(idx);
+            }
+        } else if let Some(idx) = idx {
+            offer[idx].count += count;
+        } else {
+            offer.push(InventoryItem { object, count });
+        }
+    }
+
+    /// Returns the NPC reply if the offer is rejected.
+    // barter_attempt_transaction()
+    fn offer_trade(&mut self, rpg: &Rpg, party: &Party, ui: &Ui) -> Option<BString> {
+        if self.dude_offer.is_empty() && self.npc_offer.is_empty() {
+            return None;
+        }
+        {
+            let world = self.world.borrow();
+            let objs = world.objects();
+            let msg = |id| Some(self.msgs.get(id).unwrap().text.clone());
+
+            let free_weight = |owner: object::Handle, given: &[InventoryItem]| {
+                let owner = &objs.get(owner);
+                rpg.stat(Stat::CarryWeight, owner, objs)
+                    - owner.inventory.weight(objs) as i32
+                    + offer_weight(given, objs) as i32
+            };
+            let dude = objs.dude();
+            if free_weight(dude, &self.dude_offer) < offer_weight(&self.npc_offer, objs) as i32 {
+                return msg(MSG_DUDE_OVERLOADED);
+            }
+            let party_member = self.is_npc_party_member(rpg, objs);
+            if party_member {
+                if free_weight(self.npc, &self.npc_offer)
+                    < offer_weight(&self.dude_offer, objs) as i32
+                {
+                    return msg(MSG_NPC_OVERLOADED);
+                }
+            } else if offer_cost(&self.dude_offer, objs) < self.price(rpg, party, objs) {
+                return msg(MSG_BAD_OFFER);
+            }
+        }
+
+        {
+            let mut world = self.world.borrow_mut();
+            let objs = world.objects_mut();
+            let dude = objs.dude();
+            let mut take = |owner, offer: &[InventoryItem]| -> Vec<InventoryItem> {
+                offer.iter()
+                    .map(|i| InventoryItem {
+                        object: objs.take_from_inventory(owner, i.object, i.count).unwrap(),
+                        count: i.count,
+                    })
+                    .collect()
+            };
+            let given = take(dude, &self.dude_offer);
+            let taken = take(self.npc, &self.npc_offer);
+            for i in given {
+                objs.move_into_inventory(self.npc, i.object, i.count);
+            }
+            for i in taken {
+                objs.move_into_inventory(dude, i.object, i.count);
+            }
+        }
+        self.dude_offer.clear();
+        self.npc_offer.clear();
+        self.sync_to_ui(rpg, party, ui);
+
+        None
+    }
+
+    fn handle(&mut self, cmd: UiCommand, rpg: &Rpg, party: &Party, ui: &mut Ui)
+        -> Option<BString>
+    {
+        match cmd.data {
+            UiCommandData::Barter(Command::Offer) => {
+                return self.offer_trade(rpg, party, ui);
+            }
+            UiCommandData::Barter(Command::Talk) => {}
+            UiCommandData::Inventory(inventory::Command::Scroll(scroll)) => {
+                self.scroll(cmd.source, scroll, ui);
+            }
+            UiCommandData::Inventory(inventory::Command::ListDrop { pos, object }) => {
+                if let Some(src) = self.list_at(cmd.source) {
+                    self.handle_list_drop(src, pos, object, rpg, party, ui);
+                }
+            }
+            UiCommandData::MoveWindow(move_window::Command::Hide { ok }) => {
+                if let Some(m) = self.move_window.take() {
+                    if ok {
+                        self.move_items(m.src, m.object, m.win.value());
+                        self.sync_to_ui(rpg, party, ui);
+                    }
+                    m.win.hide(ui);
+                }
+            }
+            _ => {}
+        }
+        if let Some(m) = self.move_window.as_mut() {
+            m.win.handle(cmd, ui);
+        }
+        None
+    }
+}
+
+fn make_list_item(object: object::Handle, count: u32, objs: &Objects) -> inventory_list::Item {
+    let obj = objs.get(object);
+    let proto = obj.proto().unwrap();
+    inventory_list::Item {
+        object,
+        fid: proto.sub.as_item().unwrap().inventory_fid.unwrap(),
+        count,
+    }
+}
+
+fn offer_cost(offer: &[InventoryItem], objs: &Objects) -> i32 {
+    offer.iter()
+        .map(|i| objs.get(i.object).stack_cost(i.count, objs).unwrap())
+        .sum()
+}
+
+fn offer_weight(offer: &[InventoryItem], objs: &Objects) -> u32 {
+    offer.iter()
+        .map(|i| objs.get(i.object).item_weight(objs).unwrap() * i.count)
+        .sum()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn trade_value_() {
+        assert_eq!(trade_value(100, 0, 50, 50, 0, false), 200);
+        assert_eq!(trade_value(0, 0, 50, 50, 0, false), 0);
+
+        // Caps are traded at face value.
+        assert_eq!(trade_value(50, 50, 0, 100, 0, false), 50);
+        assert_eq!(trade_value(150, 50, 50, 50, 0, false), 250);
+
+        // Better skill gives better prices.
+        assert_eq!(trade_value(100, 0, 80, 0, 0, false), 133);
+        assert_eq!(trade_value(100, 0, 0, 80, 0, false), 300);
+
+        assert_eq!(trade_value(100, 0, 50, 50, 0, true), 150);
+        assert_eq!(trade_value(100, 0, 50, 50, -50, false), 100);
+        assert_eq!(trade_value(100, 0, 50, 50, 30, true), 210);
+
+        // Modifier can't make the goods free.
+        assert_eq!(trade_value(100, 0, 50, 50, -200, false), 2);
+        assert_eq!(trade_value(100, 10, 50, 50, -200, false), 11);
+    }
+}
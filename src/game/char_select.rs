@@ -0,0 +1,416 @@
+use bstring::{bstr, BString};
+use byteorder::{BigEndian, ReadBytesExt};
+use log::*;
+use num_traits::FromPrimitive;
+use std::io::{self, prelude::*};
+use std::rc::Rc;
+
+use crate::asset::{Skill, Stat, Trait};
+use crate::asset::frame::FrameId;
+use crate::asset::message::{MessageId, Messages};
+use crate::asset::proto::Critter;
+use crate::fs::FileSystem;
+use crate::game::object::Objects;
+use crate::game::rpg::Rpg;
+use crate::game::savegame::read_critter_data;
+use crate::game::world::WorldRef;
+use crate::graphics::{Point, Rect};
+use crate::graphics::color::{Rgb15, GREEN};
+use crate::graphics::font::{DrawOptions, FontKey, HorzAlign, Overflow, OverflowAction,
+    OverflowBoundary};
+use crate::graphics::sprite::Sprite;
+use crate::ui::*;
+use crate::ui::button::Button;
+use crate::ui::command::UiCommandData;
+use crate::ui::command::char_select::Command;
+use crate::ui::panel::{self, Panel};
+
+const NAME_LEN: usize = 32;
+const TAGGED_SKILL_COUNT: usize = 4;
+const TRAIT_COUNT: usize = 2;
+
+const MSG_TAKE: MessageId = 31;
+const MSG_MODIFY: MessageId = 32;
+const MSG_CREATE: MessageId = 33;
+const MSG_BACK: MessageId = 34;
+
+/// Premade characters offered in the character selection: `.gcd` and `.bio` file path without
+/// extension and the portrait.
+const PREMADES: [(&str, FrameId); 3] = [
+    ("premade/combat", FrameId::COMBAT),
+    ("premade/stealth", FrameId::STEALTH),
+    ("premade/diplomat", FrameId::DIPLOMAT),
+];
+
+/// Character with all primary stats at 5 used as the base for creating the new character.
+pub const BLANK_GCD: &str = "premade/blank.gcd";
+
+const TEXT_FONT: FontKey = FontKey::antialiased(1);
+const TITLE_FONT: FontKey = FontKey::antialiased(2);
+const BUTTON_FONT: FontKey = FontKey::antialiased(3);
+const BUTTON_TEXT_COLOR: Rgb15 = unsafe { Rgb15::rgb15_from_packed_unchecked(0x4a23) };
+
+/// Part of the `.gcd` file that isn't stored in the critter proto.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Gcd {
+    pub name: BString,
+    pub tagged_skills: Vec<Skill>,
+    pub traits: Vec<Trait>,
+    pub char_points: i32,
+}
+
+/// Reads the character `.gcd` file. The critter data is read into `critter`.
+// pc_load_data()
+pub fn read_gcd(rd: &mut impl Read, critter: &mut Critter) -> io::Result<Gcd> {
+    read_critter_data(rd, critter)?;
+
+    let mut name = [0; NAME_LEN];
+    rd.read_exact(&mut name)?;
+    let name_len = name.iter().position(|&c| c == 0).unwrap_or(NAME_LEN);
+    let name = name[..name_len].into();
+
+    fn read_opt<T: FromPrimitive>(rd: &mut impl Read, err: &str) -> io::Result<Option<T>> {
+        let v = rd.read_i32::<BigEndian>()?;
+        if v >= 0 {
+            T::from_i32(v)
+                .map(Some)
+                .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, err))
+        } else {
+            Ok(None)
+        }
+    }
+
+    let mut tagged_skills = Vec::new();
+    for _ in 0..TAGGED_SKILL_COUNT {
+        if let Some(skill) = read_opt(rd, "invalid tagged skill")? {
+            tagged_skills.push(skill);
+        }
+    }
+    let mut traits = Vec::new();
+    for _ in 0..TRAIT_COUNT {
+        if let Some(tr) = read_opt(rd, "invalid trait")? {
+            traits.push(tr);
+        }
+    }
+    let char_points = rd.read_i32::<BigEndian>()?;
+
+    Ok(Gcd {
+        name,
+        tagged_skills,
+        traits,
+        char_points,
+    })
+}
+
+/// Loads the `.gcd` file into the dude resetting the player character state.
+// proto_dude_init()
+pub fn load_gcd(fs: &FileSystem, path: &str, objs: &Objects, rpg: &mut Rpg) -> io::Result<()> {
+    let mut rd = fs.reader(path)?;
+    let mut dude = objs.get_mut(objs.dude());
+    let gcd = {
+        let mut proto = dude.proto_mut().unwrap();
+        let gcd = read_gcd(&mut rd, proto.sub.as_critter_mut().unwrap())?;
+        proto.set_name(gcd.name.clone());
+        gcd
+    };
+
+    rpg.reset();
+    for &skill in &gcd.tagged_skills {
+        rpg.set_tagged(skill, true);
+    }
+    for &tr in &gcd.traits {
+        rpg.set_trait(tr, true);
+    }
+    rpg.recalc_derived_stats(&mut dude, objs);
+    let hit_points = rpg.stat(Stat::HitPoints, &dude, objs);
+    dude.sub.as_critter_mut().unwrap().hit_points = hit_points;
+
+    Ok(())
+}
+
+/// What the game should do after the character selection command.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Action {
+    /// Start the game with the selected character.
+    Take,
+    /// Edit the selected character in the character editor before starting the game.
+    Edit,
+    Back,
+}
+
+struct Internal {
+    window: Handle,
+    current: usize,
+    portrait: Handle,
+    name: Handle,
+    stats: Handle,
+    bio: Handle,
+}
+
+pub struct CharSelect {
+    world: WorldRef,
+    fs: Rc<FileSystem>,
+    misc_msgs: Rc<Messages>,
+    internal: Option<Internal>,
+}
+
+impl CharSelect {
+    pub fn new(world: WorldRef, fs: Rc<FileSystem>, misc_msgs: Rc<Messages>) -> Self {
+        Self {
+            world,
+            fs,
+            misc_msgs,
+            internal: None,
+        }
+    }
+
+    pub fn is_visible(&self) -> bool {
+        self.internal.is_some()
+    }
+
+    // select_character()
+    pub fn show(&mut self, rpg: &mut Rpg, ui: &mut Ui) {
+        assert!(self.internal.is_none());
+
+        let window = ui.new_window(Rect::with_size(0, 0, 640, 480),
+            Some(Sprite::new(FrameId::CHARWIN)));
+        ui.widget_base_mut(window).set_modal(true);
+
+        let portrait = ui.new_widget(window, Rect::with_size(27, 23, 1, 1), None,
+            Some(Sprite::new(PREMADES[0].1)), Panel::new());
+        let name = ui.new_widget(window, Rect::with_size(300, 23, 320, 20), None, None,
+            Panel::new());
+        let stats = ui.new_widget(window, Rect::with_size(300, 50, 130, 260), None, None,
+            Panel::new());
+        let bio = ui.new_widget(window, Rect::with_size(440, 50, 180, 260), None, None,
+            Panel::new());
+
+        ui.new_widget(window, Rect::with_size(292, 320, 20, 18), None, None,
+            Button::new(FrameId::SLU, FrameId::SLD, Some(UiCommandData::CharSelect(Command::Prev))));
+        ui.new_widget(window, Rect::with_size(318, 320, 20, 18), None, None,
+            Button::new(FrameId::SRU, FrameId::SRD, Some(UiCommandData::CharSelect(Command::Next))));
+
+        for (pos, cmd, msg) in [
+            (Point::new(81, 323), Command::Take, MSG_TAKE),
+            (Point::new(435, 320), Command::Modify, MSG_MODIFY),
+            (Point::new(80, 425), Command::Create, MSG_CREATE),
+            (Point::new(461, 425), Command::Back, MSG_BACK),
+        ] {
+            ui.new_widget(window, Rect::with_size(pos.x, pos.y, 15, 16), None, None,
+                Button::new(FrameId::SMALL_RED_BUTTON_UP, FrameId::SMALL_RED_BUTTON_DOWN,
+                    Some(UiCommandData::CharSelect(cmd))));
+            let mut label = Panel::new();
+            label.set_text(Some(panel::Text {
+                text: self.msg(msg),
+                font: BUTTON_FONT,
+                color: BUTTON_TEXT_COLOR,
+                options: Default::default(),
+            }));
+            ui.new_widget(window, Rect::with_size(pos.x + 21, pos.y + 1, 120, 16), None, None,
+                label);
+        }
+
+        self.internal = Some(Internal {
+            window,
+            current: 0,
+            portrait,
+            name,
+            stats,
+            bio,
+        });
+
+        self.select(0, rpg, ui);
+    }
+
+    pub fn hide(&mut self, ui: &mut Ui) {
+        if let Some(internal) = self.internal.take() {
+            ui.remove(internal.window);
+        }
+    }
+
+    pub fn handle(&mut self, cmd: Command, rpg: &mut Rpg, ui: &mut Ui) -> Option<Action> {
+        let current = self.internal.as_ref()?.current;
+        match cmd {
+            Command::Prev => {
+                self.select((current + PREMADES.len() - 1) % PREMADES.len(), rpg, ui);
+                None
+            }
+            Command::Next => {
+                self.select((current + 1) % PREMADES.len(), rpg, ui);
+                None
+            }
+            Command::Take => Some(Action::Take),
+            Command::Modify => Some(Action::Edit),
+            Command::Create => {
+                let world = self.world.borrow();
+                if let Err(e) = load_gcd(&self.fs, BLANK_GCD, world.objects(), rpg) {
+                    warn!("couldn't load {}: {}", BLANK_GCD, e);
+                }
+                Some(Action::Edit)
+            }
+            Command::Back => Some(Action::Back),
+        }
+    }
+
+    /// Updates the shown stats after the character was changed in the character editor.
+    pub fn sync(&self, rpg: &Rpg, ui: &mut Ui) {
+        let Some(internal) = &self.internal else {
+            return;
+        };
+
+        let world = self.world.borrow();
+        let objs = world.objects();
+        let dude = objs.get(objs.dude());
+
+        let name = dude.proto().unwrap().name().map(|s| s.to_owned()).unwrap_or_default();
+        ui.widget_mut::<Panel>(internal.name).set_text(Some(panel::Text {
+            text: name,
+            font: TITLE_FONT,
+            color: GREEN,
+            options: DrawOptions {
+                horz_align: HorzAlign::Center,
+                ..Default::default()
+            },
+        }));
+
+        let mut stats = BString::new();
+        for stat in [Stat::Strength, Stat::Perception, Stat::Endurance, Stat::Charisma,
+            Stat::Intelligence, Stat::Agility, Stat::Luck]
+        {
+            let value = rpg.stat(stat, &dude, objs);
+            line(&mut stats, rpg.stat_name(stat),
+                format!("{:02} {}", value, rpg.stat_level_description(value).display()));
+        }
+        stats.push(b'\n');
+        for stat in [Stat::HitPoints, Stat::ArmorClass, Stat::ActionPoints, Stat::MeleeDmg] {
+            line(&mut stats, rpg.stat_name(stat),
+                rpg.stat(stat, &dude, objs).to_string());
+        }
+        stats.push(b'\n');
+        for skill in rpg.tagged_skills() {
+            line(&mut stats, rpg.skill_name(skill),
+                format!("{}%", rpg.skill(skill, &dude, objs)));
+        }
+        stats.push(b'\n');
+        for tr in rpg.traits() {
+            line(&mut stats, rpg.trait_name(tr), String::new());
+        }
+
+        fn line(out: &mut BString, name: &bstr, value: String) {
+            out.push_str(name);
+            if !value.is_empty() {
+                out.push(b' ');
+                out.push_str(value);
+            }
+            out.push(b'\n');
+        }
+
+        ui.widget_mut::<Panel>(internal.stats).set_text(Some(panel::Text {
+            text: stats,
+            font: TEXT_FONT,
+            color: GREEN,
+            options: Default::default(),
+        }));
+    }
+
+    fn msg(&self, id: MessageId) -> BString {
+        self.misc_msgs.get(id).map(|m| m.text.clone()).unwrap_or_default()
+    }
+
+    fn select(&mut self, i: usize, rpg: &mut Rpg, ui: &mut Ui) {
+        let (path, portrait) = PREMADES[i];
+        {
+            let world = self.world.borrow();
+            let gcd = format!("{}.gcd", path);
+            if let Err(e) = load_gcd(&self.fs, &gcd, world.objects(), rpg) {
+                warn!("couldn't load premade character {}: {}", gcd, e);
+            }
+        }
+
+        let bio_path = format!("{}.bio", path);
+        let bio = self.fs.reader(&bio_path)
+            .and_then(|mut rd| {
+                let mut r = Vec::new();
+                rd.read_to_end(&mut r)?;
+                Ok(r)
+            })
+            .unwrap_or_else(|e| {
+                warn!("couldn't read {}: {}", bio_path, e);
+                Vec::new()
+            });
+
+        let internal = self.internal.as_mut().unwrap();
+        internal.current = i;
+        ui.widget_base_mut(internal.portrait).background_mut().unwrap().fid = portrait;
+        ui.widget_mut::<Panel>(internal.bio).set_text(Some(panel::Text {
+            text: bio.into(),
+            font: TEXT_FONT,
+            color: GREEN,
+            options: DrawOptions {
+                horz_overflow: Some(Overflow {
+                    size: 0,
+                    boundary: OverflowBoundary::Word,
+                    action: OverflowAction::Wrap,
+                }),
+                ..Default::default()
+            },
+        }));
+
+        self.sync(rpg, ui);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use byteorder::WriteBytesExt;
+    use super::*;
+    use crate::util::test::Assets;
+
+    #[test]
+    fn read_gcd_() {
+        let mut data = Vec::new();
+        data.write_u32::<BigEndian>(0).unwrap();
+        for i in 0..35 {
+            data.write_i32::<BigEndian>(i + 1).unwrap();
+        }
+        for _ in 0..35 {
+            data.write_i32::<BigEndian>(0).unwrap();
+        }
+        for i in 0..18 {
+            data.write_i32::<BigEndian>(i * 2).unwrap();
+        }
+        // body kind, experience, kill kind, damage kind
+        for v in [0, 1234, 0, 0] {
+            data.write_i32::<BigEndian>(v).unwrap();
+        }
+        let mut name = b"Narg".to_vec();
+        name.resize(NAME_LEN, 0);
+        data.extend_from_slice(&name);
+        for v in [Skill::SmallGuns as i32, Skill::Melee as i32, Skill::Throwing as i32, -1] {
+            data.write_i32::<BigEndian>(v).unwrap();
+        }
+        for v in [Trait::HeavyHanded as i32, Trait::Gifted as i32] {
+            data.write_i32::<BigEndian>(v).unwrap();
+        }
+        data.write_i32::<BigEndian>(0).unwrap();
+
+        let assets = Assets::new();
+        let proto = assets.proto_db.dude();
+        let mut proto = proto.borrow_mut();
+        let critter = proto.sub.as_critter_mut().unwrap();
+        let gcd = read_gcd(&mut &data[..], critter).unwrap();
+
+        assert_eq!(gcd, Gcd {
+            name: "Narg".into(),
+            tagged_skills: vec![Skill::SmallGuns, Skill::Melee, Skill::Throwing],
+            traits: vec![Trait::HeavyHanded, Trait::Gifted],
+            char_points: 0,
+        });
+        assert_eq!(critter.base_stats[Stat::Strength], 1);
+        assert_eq!(critter.base_stats[Stat::Luck], 7);
+        assert_eq!(critter.skills[Skill::Outdoorsman], 34);
+        assert_eq!(critter.experience, 1234);
+
+        assert!(read_gcd(&mut &data[..data.len() - 1], critter).is_err());
+    }
+}
@@ -0,0 +1,887 @@
+use bstring::{bfmt::ToBString, BString};
+use linearize::StaticMap;
+use std::cmp;
+
+use crate::asset::{EntityKind, PCStat, Perk, Skill, Stat, Trait};
+use crate::asset::frame::{FrameId, Idx};
+use crate::asset::message::{MessageId, Messages};
+use crate::fs::FileSystem;
+use crate::game::rpg::{self, Rpg};
+use crate::game::world::WorldRef;
+use crate::graphics::{Point, Rect};
+use crate::graphics::color::{Rgb15, BLACK, GREEN};
+use crate::graphics::font::{DrawOptions, FontKey, HorzAlign, Overflow, OverflowAction,
+    OverflowBoundary, VertAlign};
+use crate::graphics::sprite::Sprite;
+use crate::ui::*;
+use crate::ui::button::{self, Button};
+use crate::ui::command::{UiCommand, UiCommandData};
+use crate::ui::command::character_editor::Command;
+use crate::ui::image_text::ImageText;
+use crate::ui::panel::Panel;
+use crate::util::EnumExt;
+
+const MSG_DONE: MessageId = 100;
+const MSG_CANCEL: MessageId = 102;
+const MSG_PERKS: MessageId = 109;
+const MSG_SKILL_POINTS: MessageId = 112;
+const MSG_CHAR_POINTS: MessageId = 116;
+const MSG_SKILLS: MessageId = 117;
+const MSG_TAG_SKILLS: MessageId = 138;
+const MSG_OPTIONAL_TRAITS: MessageId = 139;
+const MSG_TAG_SKILLS_LEFT: MessageId = 140;
+const MSG_CHAR_POINTS_LEFT: MessageId = 148;
+const MSG_TOO_MANY_TRAITS: MessageId = 149;
+const MSG_SELECT_PERK: MessageId = 152;
+
+/// Sum of the primary stats of the new character when all character points are spent.
+const CHAR_POINTS_BUDGET: i32 = 40;
+
+const STAT_MIN: i32 = 1;
+const STAT_MAX: i32 = 10;
+
+const TEXT_FONT: FontKey = FontKey::antialiased(1);
+const TITLE_FONT: FontKey = FontKey::antialiased(2);
+const BUTTON_FONT: FontKey = FontKey::antialiased(3);
+
+const BUTTON_TEXT_COLOR: Rgb15 = unsafe { Rgb15::rgb15_from_packed_unchecked(0x4a23) };
+const SELECTED_COLOR: Rgb15 = unsafe { Rgb15::rgb15_from_packed_unchecked(0x7feb) };
+const TAGGED_COLOR: Rgb15 = unsafe { Rgb15::rgb15_from_packed_unchecked(0x5294) };
+const SELECTED_TAGGED_COLOR: Rgb15 = unsafe { Rgb15::rgb15_from_packed_unchecked(0x7fff) };
+
+const STAT_Y: [i32; 7] = [37, 70, 103, 136, 169, 202, 235];
+const SKILL_LINE_HEIGHT: i32 = 11;
+const TRAIT_LINE_HEIGHT: i32 = 13;
+const DERIVED_LINE_HEIGHT: i32 = 13;
+const PERK_LIST_LEN: usize = 11;
+
+/// Derived stats shown in the middle panel.
+const DERIVED_STATS: [Stat; 10] = [
+    Stat::ArmorClass,
+    Stat::ActionPoints,
+    Stat::CarryWeight,
+    Stat::MeleeDmg,
+    Stat::DmgResist,
+    Stat::PoisonResist,
+    Stat::RadResist,
+    Stat::Sequence,
+    Stat::HealRate,
+    Stat::CritChance,
+];
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Mode {
+    /// Creating the new character: distributing character points, picking traits and tagging
+    /// skills.
+    Create,
+    /// Spending skill points and picking perks gained with levels.
+    LevelUp,
+}
+
+/// Thing described in the card panel.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Item {
+    Stat(Stat),
+    PcStat(PCStat),
+    Skill(Skill),
+    Trait(Trait),
+    Perk(Perk),
+}
+
+/// Character state as it was when the editor was shown. Restored on cancel.
+struct Saved {
+    pc: rpg::PcState,
+    base_stats: StaticMap<Stat, i32>,
+    bonus_stats: StaticMap<Stat, i32>,
+    skills: StaticMap<Skill, i32>,
+    hit_points: i32,
+}
+
+struct StatRow {
+    stat: Stat,
+    value: Handle,
+    label: Handle,
+}
+
+struct PerkPicker {
+    window: Handle,
+    perks: Vec<Perk>,
+    scroll: usize,
+    rows: Vec<Handle>,
+    card: Handle,
+    selected: Option<Perk>,
+}
+
+struct Internal {
+    mode: Mode,
+    window: Handle,
+    saved: Saved,
+    char_points: i32,
+    selected: Item,
+    stats: Vec<StatRow>,
+    char_points_widget: Option<Handle>,
+    points_widget: Handle,
+    skills: StaticMap<Skill, (Handle, Option<Handle>)>,
+    traits: Vec<(Trait, Handle, Handle)>,
+    pc_stats: Vec<(PCStat, Handle)>,
+    perks: Vec<Handle>,
+    hit_points: Handle,
+    derived: Vec<(Stat, Handle)>,
+    card: Handle,
+    /// Message shown in the card instead of the selected item description.
+    notice: Option<MessageId>,
+    perk_picker: Option<PerkPicker>,
+}
+
+pub struct CharacterEditor {
+    msgs: Messages,
+    world: WorldRef,
+    internal: Option<Internal>,
+}
+
+impl CharacterEditor {
+    pub fn new(world: WorldRef, fs: &FileSystem, language: &str) -> Self {
+        let msgs = Messages::read_file(fs, language, "game/editor.msg").unwrap();
+        Self {
+            msgs,
+            world,
+            internal: None,
+        }
+    }
+
+    pub fn is_visible(&self) -> bool {
+        self.internal.is_some()
+    }
+
+    pub fn handle(&mut self, cmd: UiCommand, rpg: &mut Rpg, global_vars: &[i32], ui: &mut Ui) {
+        let UiCommandData::CharacterEditor(cmd) = cmd.data else {
+            return;
+        };
+        match cmd {
+            Command::Show => {
+                // The character without tagged skills hasn't been created yet.
+                let mode = if rpg.tagged_skills().next().is_none() {
+                    Mode::Create
+                } else {
+                    Mode::LevelUp
+                };
+                self.show(mode, rpg, global_vars, ui);
+                return;
+            }
+            Command::Done => {
+                if self.done(rpg, ui) {
+                    self.hide(ui);
+                }
+                return;
+            }
+            Command::Cancel => {
+                self.cancel(rpg);
+                self.hide(ui);
+                return;
+            }
+            _ => {}
+        }
+        let Some(internal) = &mut self.internal else {
+            return;
+        };
+        internal.notice = None;
+        match cmd {
+            Command::Select(item) => self.select(item),
+            Command::IncStat(stat) => self.change_stat(stat, 1, rpg),
+            Command::DecStat(stat) => self.change_stat(stat, -1, rpg),
+            Command::ToggleTrait(tr) => self.toggle_trait(tr, rpg),
+            Command::ToggleTag(skill) => self.toggle_tag(skill, rpg),
+            Command::IncSkill => self.change_skill(true, rpg),
+            Command::DecSkill => self.change_skill(false, rpg),
+            Command::ScrollPerks { up } => {
+                let picker = self.internal.as_mut().unwrap().perk_picker.as_mut().unwrap();
+                picker.scroll = if up {
+                    picker.scroll.saturating_sub(1)
+                } else {
+                    cmp::min(picker.scroll + 1, picker.perks.len().saturating_sub(PERK_LIST_LEN))
+                };
+            }
+            Command::PickPerk => self.pick_perk(rpg, global_vars, ui),
+            Command::CancelPerk => self.hide_perk_picker(ui),
+            Command::Show | Command::Done | Command::Cancel => unreachable!(),
+        }
+        self.sync(rpg, ui);
+    }
+
+    // editor_design()
+    pub fn show(&mut self, mode: Mode, rpg: &Rpg, global_vars: &[i32], ui: &mut Ui) {
+        assert!(self.internal.is_none());
+
+        let world = self.world.borrow();
+        let objs = world.objects();
+        let dude = objs.get(objs.dude());
+
+        let saved = {
+            let proto = dude.proto().unwrap();
+            let critter = proto.sub.as_critter().unwrap();
+            Saved {
+                pc: rpg.pc_state(),
+                base_stats: critter.base_stats.clone(),
+                bonus_stats: critter.bonus_stats.clone(),
+                skills: critter.skills.clone(),
+                hit_points: dude.sub.as_critter().unwrap().hit_points,
+            }
+        };
+        let char_points = if mode == Mode::Create {
+            cmp::max(CHAR_POINTS_BUDGET
+                - Stat::base().iter().map(|&s| rpg.base_stat(s, &dude)).sum::<i32>(), 0)
+        } else {
+            0
+        };
+
+        let bg = match mode {
+            Mode::Create => FrameId::EDTRCRTE,
+            Mode::LevelUp => FrameId::EDTREDT,
+        };
+        let window = ui.new_window(Rect::with_size(0, 0, 640, 480), Some(Sprite::new(bg)));
+        ui.widget_base_mut(window).set_modal(true);
+
+        let mut stats = Vec::new();
+        for (i, &stat) in Stat::base().iter().enumerate() {
+            let y = STAT_Y[i];
+            let value = ui.new_widget(window, Rect::with_size(59, y, 28, 24), None, None,
+                ImageText::big_numbers());
+            let mut label = Label::new(Some(Item::Stat(stat)));
+            label.name_pos = Point::new(103 - 20, 8);
+            let label = ui.new_widget(window, Rect::with_size(20, y, 128, 28), None, None, label);
+            if mode == Mode::Create {
+                ui.new_widget(window, Rect::with_size(149, y, 20, 11), None, None,
+                    Button::new(FrameId::STPLSOFF, FrameId::STPLSON,
+                        Some(UiCommandData::CharacterEditor(Command::IncStat(stat)))));
+                ui.new_widget(window, Rect::with_size(149, y + 11, 20, 11), None, None,
+                    Button::new(FrameId::STNEGOFF, FrameId::STNEGON,
+                        Some(UiCommandData::CharacterEditor(Command::DecStat(stat)))));
+            }
+            stats.push(StatRow { stat, value, label });
+        }
+
+        let (char_points_widget, pc_stats) = match mode {
+            Mode::Create => {
+                self.new_text(ui, window, Point::new(14, 286), MSG_CHAR_POINTS, TEXT_FONT, GREEN);
+                let w = ui.new_widget(window, Rect::with_size(126, 282, 28, 24), None, None,
+                    ImageText::big_numbers());
+                (Some(w), Vec::new())
+            }
+            Mode::LevelUp => {
+                let pc_stats = [PCStat::Level, PCStat::Experience].iter()
+                    .enumerate()
+                    .map(|(i, &s)| (s, ui.new_widget(window,
+                        Rect::with_size(32, 280 + 12 * i as i32, 130, 12), None, None,
+                        Label::new(Some(Item::PcStat(s))))))
+                    .collect();
+                (None, pc_stats)
+            }
+        };
+
+        let hit_points = ui.new_widget(window, Rect::with_size(194, 46, 130, 13), None, None,
+            Label::new(Some(Item::Stat(Stat::HitPoints))));
+        let derived = DERIVED_STATS.iter()
+            .enumerate()
+            .map(|(i, &stat)| (stat, ui.new_widget(window,
+                Rect::with_size(194, 179 + DERIVED_LINE_HEIGHT * i as i32, 130, DERIVED_LINE_HEIGHT),
+                None, None, Label::new(Some(Item::Stat(stat))))))
+            .collect();
+
+        self.new_text(ui, window, Point::new(380, 5), MSG_SKILLS, TITLE_FONT, GREEN);
+        let skills = linearize::static_map! {
+            skill => {
+                let y = 27 + SKILL_LINE_HEIGHT * skill as i32;
+                let tag = (mode == Mode::Create).then(||
+                    ui.new_widget(window, Rect::with_size(347, y - 1, 22, 11), None, None,
+                        Button::new(FrameId::TGSKLOFF, FrameId::TGSKLON,
+                            Some(UiCommandData::CharacterEditor(Command::ToggleTag(skill))))));
+                let label = ui.new_widget(window,
+                    Rect::with_size(380, y, 573 - 380, SKILL_LINE_HEIGHT), None, None,
+                    Label::new(Some(Item::Skill(skill))));
+                (label, tag)
+            }
+        };
+        let points_msg = match mode {
+            Mode::Create => MSG_TAG_SKILLS,
+            Mode::LevelUp => MSG_SKILL_POINTS,
+        };
+        self.new_text(ui, window, Point::new(383, 233), points_msg, TEXT_FONT, GREEN);
+        let points_widget = ui.new_widget(window, Rect::with_size(522, 228, 28, 24), None, None,
+            ImageText::big_numbers());
+        if mode == Mode::LevelUp {
+            ui.new_widget(window, Rect::with_size(614, 225, 20, 11), None, None,
+                Button::new(FrameId::BUTTON_PLUS_UP, FrameId::BUTTON_PLUS_DOWN,
+                    Some(UiCommandData::CharacterEditor(Command::IncSkill))));
+            ui.new_widget(window, Rect::with_size(614, 236, 20, 11), None, None,
+                Button::new(FrameId::BUTTON_MINUS_UP, FrameId::BUTTON_MINUS_DOWN,
+                    Some(UiCommandData::CharacterEditor(Command::DecSkill))));
+        }
+
+        let (traits, perks) = match mode {
+            Mode::Create => {
+                self.new_text(ui, window, Point::new(47, 330), MSG_OPTIONAL_TRAITS, TITLE_FONT,
+                    GREEN);
+                let half = Trait::iter().count() / 2;
+                let traits = Trait::iter()
+                    .enumerate()
+                    .map(|(i, tr)| {
+                        let left = i < half;
+                        let y = 352 + TRAIT_LINE_HEIGHT * (i % half) as i32;
+                        let button = ui.new_widget(window,
+                            Rect::with_size(if left { 23 } else { 610 }, y, 22, 11), None, None,
+                            Button::new(FrameId::TGSKLOFF, FrameId::TGSKLON,
+                                Some(UiCommandData::CharacterEditor(Command::ToggleTrait(tr)))));
+                        let mut label = Label::new(Some(Item::Trait(tr)));
+                        if !left {
+                            label.align = HorzAlign::Right;
+                        }
+                        let rect = if left {
+                            Rect::with_size(47, y + 1, 150, TRAIT_LINE_HEIGHT)
+                        } else {
+                            Rect::with_size(596 - 150, y + 1, 150, TRAIT_LINE_HEIGHT)
+                        };
+                        let label = ui.new_widget(window, rect, None, None, label);
+                        (tr, button, label)
+                    })
+                    .collect();
+                (traits, Vec::new())
+            }
+            Mode::LevelUp => {
+                self.new_text(ui, window, Point::new(34, 330), MSG_PERKS, TITLE_FONT, GREEN);
+                let perks = (0..8)
+                    .map(|i| ui.new_widget(window,
+                        Rect::with_size(34, 352 + SKILL_LINE_HEIGHT * i, 280, SKILL_LINE_HEIGHT),
+                        None, None, Label::new(None)))
+                    .collect();
+                (Vec::new(), perks)
+            }
+        };
+
+        let card = ui.new_widget(window, Rect::with_size(348, 267, 270, 180), None, None,
+            Card::new(Point::new(136, 42), 136));
+
+        self.new_button(ui, window, Point::new(455, 454), MSG_DONE, Command::Done);
+        self.new_button(ui, window, Point::new(552, 454), MSG_CANCEL, Command::Cancel);
+
+        let selected = Item::Stat(Stat::Strength);
+
+        drop(dude);
+        drop(world);
+
+        self.internal = Some(Internal {
+            mode,
+            window,
+            saved,
+            char_points,
+            selected,
+            stats,
+            char_points_widget,
+            points_widget,
+            skills,
+            traits,
+            pc_stats,
+            perks,
+            hit_points,
+            derived,
+            card,
+            notice: None,
+            perk_picker: None,
+        });
+
+        if mode == Mode::LevelUp && rpg.free_perks() > 0 {
+            self.show_perk_picker(rpg, global_vars, ui);
+        }
+
+        self.sync(rpg, ui);
+    }
+
+    pub fn hide(&mut self, ui: &mut Ui) {
+        self.hide_perk_picker(ui);
+        let internal = self.internal.take().unwrap();
+        ui.remove(internal.window);
+    }
+
+    fn msg(&self, id: MessageId) -> BString {
+        self.msgs.get(id).map(|m| m.text.clone()).unwrap_or_default()
+    }
+
+    fn new_text(&self, ui: &mut Ui, window: Handle, pos: Point, msg: MessageId, font: FontKey,
+        color: Rgb15)
+    {
+        let mut panel = Panel::new();
+        panel.set_text(Some(crate::ui::panel::Text {
+            text: self.msg(msg),
+            font,
+            color,
+            options: Default::default(),
+        }));
+        ui.new_widget(window, Rect::with_size(pos.x, pos.y, 1, 1), None, None, panel);
+    }
+
+    fn new_button(&self, ui: &mut Ui, window: Handle, pos: Point, msg: MessageId,
+        command: Command)
+    {
+        let btn_size = ui.frm_db().get(FrameId::SMALL_RED_BUTTON_UP).unwrap().first().size();
+        let mut btn = Button::new(FrameId::SMALL_RED_BUTTON_UP, FrameId::SMALL_RED_BUTTON_DOWN,
+            Some(UiCommandData::CharacterEditor(command)));
+        let mut text = button::Text::new(self.msg(msg), BUTTON_FONT);
+        text.pos = Point::new(btn_size.x + 4, 0);
+        text.color = BUTTON_TEXT_COLOR;
+        text.options.vert_align = VertAlign::Middle;
+        btn.set_text(Some(text));
+        ui.new_widget(window, Rect::with_size(pos.x, pos.y, 80, btn_size.y), None, None, btn);
+    }
+
+    // perk_dialog()
+    fn show_perk_picker(&mut self, rpg: &Rpg, global_vars: &[i32], ui: &mut Ui) {
+        let perks = {
+            let world = self.world.borrow();
+            let objs = world.objects();
+            let dude = objs.get(objs.dude());
+            rpg.available_perks(&dude, objs, global_vars)
+        };
+        if perks.is_empty() {
+            return;
+        }
+
+        let win_size = ui.frm_db().get(FrameId::PERKWIN).unwrap().first().size();
+        let window = ui.new_window(Rect::with_size(33, 91, win_size.x, win_size.y),
+            Some(Sprite::new(FrameId::PERKWIN)));
+        ui.widget_base_mut(window).set_modal(true);
+
+        self.new_text(ui, window, Point::new(45, 15), MSG_SELECT_PERK, TITLE_FONT, GREEN);
+
+        let rows = (0..PERK_LIST_LEN as i32)
+            .map(|i| ui.new_widget(window,
+                Rect::with_size(45, 43 + SKILL_LINE_HEIGHT * i, 190, SKILL_LINE_HEIGHT),
+                None, None, Label::new(None)))
+            .collect();
+        ui.new_widget(window, Rect::with_size(25, 43, 11, 11), None, None,
+            Button::new(FrameId::UPARWOFF, FrameId::UPARWON,
+                Some(UiCommandData::CharacterEditor(Command::ScrollPerks { up: true }))));
+        ui.new_widget(window, Rect::with_size(25, 43 + SKILL_LINE_HEIGHT * 10, 11, 11), None,
+            None, Button::new(FrameId::DNARWOFF, FrameId::DNARWON,
+                Some(UiCommandData::CharacterEditor(Command::ScrollPerks { up: false }))));
+
+        let card = ui.new_widget(window, Rect::with_size(280, 26, 280, 150), None, None,
+            Card::new(Point::new(150, 22), 145));
+
+        self.new_button(ui, window, Point::new(48, 186), MSG_DONE, Command::PickPerk);
+        self.new_button(ui, window, Point::new(153, 186), MSG_CANCEL, Command::CancelPerk);
+
+        let selected = perks.first().copied();
+        self.internal.as_mut().unwrap().perk_picker = Some(PerkPicker {
+            window,
+            perks,
+            scroll: 0,
+            rows,
+            card,
+            selected,
+        });
+    }
+
+    fn hide_perk_picker(&mut self, ui: &mut Ui) {
+        if let Some(picker) = self.internal.as_mut().and_then(|v| v.perk_picker.take()) {
+            ui.remove(picker.window);
+        }
+    }
+
+    fn pick_perk(&mut self, rpg: &mut Rpg, global_vars: &[i32], ui: &mut Ui) {
+        let Some(perk) = self.internal.as_ref().unwrap().perk_picker.as_ref().unwrap().selected
+        else {
+            return;
+        };
+        let added = {
+            let world = self.world.borrow();
+            let objs = world.objects();
+            let mut dude = objs.get_mut(objs.dude());
+            rpg.add_perk(perk, &mut dude, objs, global_vars)
+        };
+        if added {
+            rpg.set_free_perks(rpg.free_perks() - 1);
+            self.internal.as_mut().unwrap().selected = Item::Perk(perk);
+        }
+        self.hide_perk_picker(ui);
+    }
+
+    fn select(&mut self, item: Item) {
+        let internal = self.internal.as_mut().unwrap();
+        if let Item::Perk(perk) = item
+            && let Some(picker) = &mut internal.perk_picker
+        {
+            picker.selected = Some(perk);
+        } else {
+            internal.selected = item;
+        }
+    }
+
+    // inc_stat(), dec_stat()
+    fn change_stat(&mut self, stat: Stat, delta: i32, rpg: &Rpg) {
+        let internal = self.internal.as_mut().unwrap();
+        internal.selected = Item::Stat(stat);
+        if internal.mode != Mode::Create
+            || delta > 0 && internal.char_points < delta
+        {
+            return;
+        }
+        let world = self.world.borrow();
+        let objs = world.objects();
+        let mut dude = objs.get_mut(objs.dude());
+        let value = rpg.base_stat(stat, &dude) + delta;
+        if !(STAT_MIN..=STAT_MAX).contains(&value) {
+            return;
+        }
+        rpg.set_base_stat(stat, &mut dude, value, objs);
+        internal.char_points -= delta;
+    }
+
+    fn toggle_trait(&mut self, tr: Trait, rpg: &mut Rpg) {
+        let internal = self.internal.as_mut().unwrap();
+        internal.selected = Item::Trait(tr);
+        if rpg.has_trait(tr) {
+            rpg.set_trait(tr, false);
+        } else if rpg.traits().count() < rpg::MAX_TRAITS {
+            rpg.set_trait(tr, true);
+        } else {
+            internal.notice = Some(MSG_TOO_MANY_TRAITS);
+            return;
+        }
+        let world = self.world.borrow();
+        let objs = world.objects();
+        rpg.recalc_derived_stats(&mut objs.get_mut(objs.dude()), objs);
+    }
+
+    fn toggle_tag(&mut self, skill: Skill, rpg: &mut Rpg) {
+        let internal = self.internal.as_mut().unwrap();
+        internal.selected = Item::Skill(skill);
+        if rpg.is_tagged(skill) {
+            rpg.set_tagged(skill, false);
+        } else if rpg.tagged_skills().count() < rpg::TAG_SKILL_COUNT {
+            rpg.set_tagged(skill, true);
+        }
+    }
+
+    fn change_skill(&mut self, inc: bool, rpg: &mut Rpg) {
+        let internal = self.internal.as_ref().unwrap();
+        let Item::Skill(skill) = internal.selected else {
+            return;
+        };
+        let world = self.world.borrow();
+        let objs = world.objects();
+        let dude = objs.get(objs.dude());
+        if inc {
+            rpg.inc_skill(skill, &dude, objs);
+        } else if rpg.skill_points(skill, &dude) > internal.saved.skills[skill] {
+            rpg.dec_skill(skill, &dude, objs);
+        }
+    }
+
+    /// Returns `false` if the character is not finished yet.
+    fn done(&mut self, rpg: &Rpg, ui: &mut Ui) -> bool {
+        let internal = self.internal.as_mut().unwrap();
+        if internal.mode == Mode::Create {
+            let notice = if internal.char_points > 0 {
+                Some(MSG_CHAR_POINTS_LEFT)
+            } else if rpg.tagged_skills().count() < rpg::TAG_SKILL_COUNT {
+                Some(MSG_TAG_SKILLS_LEFT)
+            } else {
+                None
+            };
+            if notice.is_some() {
+                internal.notice = notice;
+                self.sync(rpg, ui);
+                return false;
+            }
+
+            let world = self.world.borrow();
+            let objs = world.objects();
+            let mut dude = objs.get_mut(objs.dude());
+            rpg.recalc_derived_stats(&mut dude, objs);
+            let max_hp = rpg.stat(Stat::HitPoints, &dude, objs);
+            dude.sub.as_critter_mut().unwrap().hit_points = max_hp;
+        }
+        true
+    }
+
+    fn cancel(&mut self, rpg: &mut Rpg) {
+        let saved = &self.internal.as_ref().unwrap().saved;
+        rpg.set_pc_state(saved.pc.clone());
+        let world = self.world.borrow();
+        let objs = world.objects();
+        let mut dude = objs.get_mut(objs.dude());
+        {
+            let mut proto = dude.proto_mut().unwrap();
+            let critter = proto.sub.as_critter_mut().unwrap();
+            critter.base_stats = saved.base_stats.clone();
+            critter.bonus_stats = saved.bonus_stats.clone();
+            critter.skills = saved.skills.clone();
+        }
+        dude.sub.as_critter_mut().unwrap().hit_points = saved.hit_points;
+    }
+
+    fn sync(&self, rpg: &Rpg, ui: &mut Ui) {
+        let Some(internal) = &self.internal else {
+            return;
+        };
+        let world = self.world.borrow();
+        let objs = world.objects();
+        let dude = objs.get(objs.dude());
+
+        let color = |item: Item| if internal.selected == item { SELECTED_COLOR } else { GREEN };
+
+        for row in &internal.stats {
+            let value = rpg.stat(row.stat, &dude, objs);
+            *ui.widget_mut::<ImageText>(row.value).text_mut() =
+                format!("{:02}", cmp::min(value, 99)).into();
+            let mut label = ui.widget_mut::<Label>(row.label);
+            label.name = rpg.stat_level_description(value).into();
+            label.color = color(Item::Stat(row.stat));
+        }
+
+        if let Some(w) = internal.char_points_widget {
+            *ui.widget_mut::<ImageText>(w).text_mut() =
+                format!("{:02}", internal.char_points).into();
+        }
+        let points = match internal.mode {
+            Mode::Create => rpg::TAG_SKILL_COUNT - rpg.tagged_skills().count(),
+            Mode::LevelUp => rpg.pc_stat(PCStat::UnspentSkillPoints).max(0) as usize,
+        };
+        *ui.widget_mut::<ImageText>(internal.points_widget).text_mut() =
+            format!("{:02}", cmp::min(points, 99)).into();
+
+        for &(pc_stat, w) in &internal.pc_stats {
+            let mut label = ui.widget_mut::<Label>(w);
+            label.name = rpg.pc_stat_name(pc_stat).into();
+            label.value = rpg.pc_stat(pc_stat).to_bstring();
+            label.color = color(Item::PcStat(pc_stat));
+        }
+
+        {
+            let hp = dude.sub.as_critter().unwrap().hit_points;
+            let max_hp = rpg.stat(Stat::HitPoints, &dude, objs);
+            let mut label = ui.widget_mut::<Label>(internal.hit_points);
+            label.name = rpg.stat_name(Stat::HitPoints).into();
+            label.value = format!("{}/{}", hp, max_hp).into();
+            label.color = color(Item::Stat(Stat::HitPoints));
+        }
+        for &(stat, w) in &internal.derived {
+            let value = rpg.stat(stat, &dude, objs);
+            let mut label = ui.widget_mut::<Label>(w);
+            label.name = rpg.stat_name(stat).into();
+            label.value = match stat {
+                Stat::DmgResist | Stat::PoisonResist | Stat::RadResist | Stat::CritChance
+                    => format!("{}%", value),
+                _ => value.to_string(),
+            }.into();
+            label.color = color(Item::Stat(stat));
+        }
+
+        for (skill, &(label, tag)) in internal.skills.iter() {
+            let tagged = rpg.is_tagged(skill);
+            let selected = internal.selected == Item::Skill(skill);
+            let mut labelw = ui.widget_mut::<Label>(label);
+            labelw.name = rpg.skill_name(skill).into();
+            labelw.value = format!("{}%", rpg.skill(skill, &dude, objs)).into();
+            labelw.color = match (selected, tagged) {
+                (false, false) => GREEN,
+                (false, true) => TAGGED_COLOR,
+                (true, false) => SELECTED_COLOR,
+                (true, true) => SELECTED_TAGGED_COLOR,
+            };
+            drop(labelw);
+            if let Some(tag) = tag {
+                let fid = if tagged { FrameId::TGSKLON } else { FrameId::TGSKLOFF };
+                ui.widget_mut::<Button>(tag).config_mut(button::State::Up).background =
+                    Some(Sprite::new(fid));
+            }
+        }
+
+        for &(tr, button, label) in &internal.traits {
+            let has = rpg.has_trait(tr);
+            let fid = if has { FrameId::TGSKLON } else { FrameId::TGSKLOFF };
+            ui.widget_mut::<Button>(button).config_mut(button::State::Up).background =
+                Some(Sprite::new(fid));
+            let mut label = ui.widget_mut::<Label>(label);
+            label.name = rpg.trait_name(tr).into();
+            label.color = match (internal.selected == Item::Trait(tr), has) {
+                (false, false) => GREEN,
+                (false, true) => TAGGED_COLOR,
+                (true, false) => SELECTED_COLOR,
+                (true, true) => SELECTED_TAGGED_COLOR,
+            };
+        }
+
+        if !internal.perks.is_empty() {
+            let items = rpg.traits().map(Item::Trait)
+                .chain(Perk::iter()
+                    .filter(|&p| rpg.has_perk(p, dude.proto_id().unwrap()))
+                    .map(Item::Perk));
+            let mut rows = internal.perks.iter();
+            for item in items {
+                let Some(&w) = rows.next() else { break };
+                let mut label = ui.widget_mut::<Label>(w);
+                label.item = Some(item);
+                (label.name, label.value) = match item {
+                    Item::Trait(tr) => (rpg.trait_name(tr).into(), BString::new()),
+                    Item::Perk(perk) => {
+                        let rank = rpg.perk(perk, dude.proto_id().unwrap());
+                        (rpg.perk_name(perk).into(),
+                            if rank > 1 { format!("({})", rank).into() } else { BString::new() })
+                    }
+                    _ => unreachable!(),
+                };
+                label.color = color(item);
+            }
+            for &w in rows {
+                let mut label = ui.widget_mut::<Label>(w);
+                label.item = None;
+                label.name.clear();
+                label.value.clear();
+            }
+        }
+
+        let (title, image, descr) = if let Some(notice) = internal.notice {
+            (self.msg(notice), None, BString::new())
+        } else {
+            self.describe(internal.selected, rpg)
+        };
+        ui.widget_mut::<Card>(internal.card).set(title, image, descr);
+
+        if let Some(picker) = &internal.perk_picker {
+            for (i, &w) in picker.rows.iter().enumerate() {
+                let perk = picker.perks.get(picker.scroll + i).copied();
+                let mut label = ui.widget_mut::<Label>(w);
+                label.item = perk.map(Item::Perk);
+                label.name = perk.map(|p| rpg.perk_name(p).into()).unwrap_or_default();
+                label.color = if perk.is_some() && perk == picker.selected {
+                    SELECTED_COLOR
+                } else {
+                    GREEN
+                };
+            }
+            if let Some(perk) = picker.selected {
+                let (title, image, descr) = self.describe(Item::Perk(perk), rpg);
+                ui.widget_mut::<Card>(picker.card).set(title, image, descr);
+            }
+        }
+    }
+
+    /// Returns card title, image and description for the `item`.
+    fn describe(&self, item: Item, rpg: &Rpg) -> (BString, Option<FrameId>, BString) {
+        let (title, image, descr) = match item {
+            Item::Stat(stat) => (rpg.stat_name(stat), Some(rpg.stat_image_fid_id(stat)),
+                rpg.stat_description(stat)),
+            Item::PcStat(pc_stat) => (rpg.pc_stat_name(pc_stat), None,
+                rpg.pc_stat_description(pc_stat)),
+            Item::Skill(skill) => (rpg.skill_name(skill), Some(rpg.skill_image_fid_id(skill)),
+                rpg.skill_description(skill)),
+            Item::Trait(tr) => (rpg.trait_name(tr), Some(rpg.trait_image_fid_id(tr)),
+                rpg.trait_description(tr)),
+            Item::Perk(perk) => (rpg.perk_name(perk), Some(rpg.perk_image_fid_id(perk)),
+                rpg.perk_description(perk)),
+        };
+        let image = image.and_then(|id| FrameId::new_generic(EntityKind::Skilldex, id as Idx));
+        (title.into(), image, descr.into())
+    }
+}
+
+/// Line of text optionally followed by right aligned value. Clicking it selects the `item`.
+struct Label {
+    item: Option<Item>,
+    name: BString,
+    name_pos: Point,
+    value: BString,
+    align: HorzAlign,
+    color: Rgb15,
+}
+
+impl Label {
+    fn new(item: Option<Item>) -> Self {
+        Self {
+            item,
+            name: BString::new(),
+            name_pos: Point::new(0, 0),
+            value: BString::new(),
+            align: HorzAlign::Left,
+            color: GREEN,
+        }
+    }
+}
+
+impl Widget for Label {
+    fn handle_event(&mut self, mut ctx: HandleEvent) {
+        if let Event::MouseDown { button: MouseButton::Left, .. } = ctx.event
+            && let Some(item) = self.item
+        {
+            ctx.out(UiCommandData::CharacterEditor(Command::Select(item)));
+        }
+    }
+
+    fn render(&mut self, ctx: Render) {
+        let rect = ctx.base.unwrap().rect();
+        let x = match self.align {
+            HorzAlign::Left | HorzAlign::Center => rect.left,
+            HorzAlign::Right => rect.right,
+        };
+        ctx.canvas.draw_text(&self.name, Point::new(x, rect.top) + self.name_pos, TEXT_FONT,
+            self.color, &DrawOptions {
+                horz_align: self.align,
+                ..Default::default()
+            });
+        if !self.value.is_empty() {
+            ctx.canvas.draw_text(&self.value, Point::new(rect.right, rect.top), TEXT_FONT,
+                self.color, &DrawOptions {
+                    horz_align: HorzAlign::Right,
+                    ..Default::default()
+                });
+        }
+    }
+}
+
+/// Card with title, image and description of the selected item.
+struct Card {
+    title: BString,
+    image: Option<FrameId>,
+    image_pos: Point,
+    description: BString,
+    description_width: i32,
+}
+
+impl Card {
+    fn new(image_pos: Point, description_width: i32) -> Self {
+        Self {
+            title: BString::new(),
+            image: None,
+            image_pos,
+            description: BString::new(),
+            description_width,
+        }
+    }
+
+    fn set(&mut self, title: BString, image: Option<FrameId>, description: BString) {
+        self.title = title;
+        self.image = image;
+        self.description = description;
+    }
+}
+
+impl Widget for Card {
+    fn render(&mut self, ctx: Render) {
+        let rect = ctx.base.unwrap().rect();
+        ctx.canvas.draw_text(&self.title, rect.top_left() + Point::new(0, 5), TITLE_FONT, BLACK,
+            &Default::default());
+        if let Some(fid) = self.image.filter(|&fid| ctx.frm_db.exists(fid)) {
+            Sprite::new_with_pos(fid, rect.top_left() + self.image_pos)
+                .render(ctx.canvas, ctx.frm_db);
+        }
+        ctx.canvas.draw_text(&self.description, rect.top_left() + Point::new(0, 48), TEXT_FONT,
+            BLACK, &DrawOptions {
+                horz_overflow: Some(Overflow {
+                    size: self.description_width,
+                    boundary: OverflowBoundary::Word,
+                    action: OverflowAction::Wrap,
+                }),
+                ..Default::default()
+            });
+    }
+}
@@ -0,0 +1,1312 @@
+use enum_primitive_derive::Primitive;
+use enumflags2::BitFlags;
+use std::cmp;
+
+use crate::asset::{AttackCategory, AttackGroup, AttackKind, CritterAnim, DamageKind, Flag,
+    Perk, Skill, Stat, Trait, WeaponKind};
+use crate::game::object::{self, DamageFlag, EquipmentSlot, Hand, Object, Objects};
+use crate::game::rpg::Rpg;
+use crate::game::world::World;
+use crate::graphics::Point;
+use crate::graphics::geometry::hex::{self, Direction};
+use crate::util::RangeInclusive;
+use crate::util::random::{random, RollChecker, RollCheckResult};
+
+/// Game or combat difficulty as returned by the `difficulty_level` and `combat_difficulty`
+/// script instructions.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Primitive)]
+pub enum Difficulty {
+    Easy = 0,
+    #[default]
+    Normal = 1,
+    Hard = 2,
+}
+
+impl Difficulty {
+    fn hit_chance_modifier(self) -> i32 {
+        match self {
+            Self::Easy => -20,
+            Self::Normal => 0,
+            Self::Hard => 20,
+        }
+    }
+
+    fn damage_modifier(self) -> i32 {
+        match self {
+            Self::Easy => 75,
+            Self::Normal => 100,
+            Self::Hard => 125,
+        }
+    }
+}
+
+/// Value of `fixed_param` passed to the `combat_p_proc`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Primitive)]
+pub enum CombatSubtype {
+    WeaponUsed = 1,
+    HitSucceeded = 2,
+    Turn = 4,
+}
+
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Primitive)]
+pub enum HitLocation {
+    Head = 0,
+    LeftArm = 1,
+    RightArm = 2,
+    Torso = 3,
+    RightLeg = 4,
+    LeftLeg = 5,
+    Eyes = 6,
+    Groin = 7,
+    #[default]
+    Uncalled = 8,
+}
+
+impl HitLocation {
+    // hit_location_penalty
+    pub fn hit_chance_modifier(self) -> i32 {
+        use HitLocation::*;
+        match self {
+            Head => -40,
+            LeftArm | RightArm => -30,
+            Torso | Uncalled => 0,
+            RightLeg | LeftLeg => -20,
+            Eyes => -60,
+            Groin => -30,
+        }
+    }
+}
+
+/// Returns the hand the critter attacks with.
+pub fn attack_hand(obj: &Object) -> Hand {
+    obj.sub.as_critter()
+        .and_then(|c| c.try_dude())
+        .map(|d| d.active_hand)
+        .unwrap_or(Hand::Right)
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct AmmoModifiers {
+    pub armor_class: i32,
+    pub damage_resist: i32,
+    pub damage_mult: i32,
+    pub damage_div: i32,
+}
+
+impl Default for AmmoModifiers {
+    fn default() -> Self {
+        Self {
+            armor_class: 0,
+            damage_resist: 0,
+            damage_mult: 1,
+            damage_div: 1,
+        }
+    }
+}
+
+/// Properties of the attack the critter can make with the item in its attack hand.
+#[derive(Clone, Debug)]
+pub struct Weapon {
+    /// `None` for unarmed attacks.
+    pub obj: Option<object::Handle>,
+    pub attack_kind: AttackKind,
+    pub skill: Skill,
+    pub damage: RangeInclusive<i32>,
+    pub damage_kind: DamageKind,
+    pub range: i32,
+    pub ap_cost: i32,
+    pub perk: Option<Perk>,
+    /// Number of rounds fired in one attack. Zero for weapons that don't use ammo.
+    pub rounds: u32,
+    pub ammo: AmmoModifiers,
+    /// Row of `CRITICAL_FAILURE_EFFECTS` rolled on critical failures.
+    pub crit_failure_table: usize,
+}
+
+impl Weapon {
+    // item_w_subtype
+    pub fn category(&self) -> AttackCategory {
+        self.attack_kind.category()
+    }
+
+    pub fn is_ranged(&self) -> bool {
+        matches!(self.category(), AttackCategory::Throw | AttackCategory::Fire)
+    }
+
+    // item_w_anim
+    pub fn anim(&self) -> CritterAnim {
+        match self.attack_kind {
+            AttackKind::Stand => CritterAnim::Stand,
+            AttackKind::Punch => CritterAnim::ThrowPunch,
+            AttackKind::Kick => CritterAnim::KickLeg,
+            AttackKind::Swing => CritterAnim::SwingAnim,
+            AttackKind::Thrust => CritterAnim::ThrustAnim,
+            AttackKind::Throw => CritterAnim::ThrowAnim,
+            AttackKind::FireSingle => CritterAnim::FireSingle,
+            AttackKind::FireBurst => CritterAnim::FireBurst,
+            AttackKind::FireContinuous => CritterAnim::FireContinuous,
+        }
+    }
+
+    /// Returns the weapon `attacker` currently attacks with or `None` if it can't attack
+    /// (for example the weapon is out of ammo).
+    pub fn of(attacker: object::Handle, rpg: &Rpg, objs: &Objects) -> Option<Self> {
+        let attackero = objs.get(attacker);
+        let is_dude = attackero.is_dude();
+        let item = attackero.equipment(EquipmentSlot::Hand(attack_hand(&attackero)), objs)
+            .filter(|&h| objs.get(h).proto().is_some_and(|p| p.sub.as_weapon().is_some()));
+        let melee_dmg = rpg.stat(Stat::MeleeDmg, &attackero, objs);
+
+        let Some(item) = item else {
+            // Unarmed.
+            let damage_kind = attackero.proto()?.sub.as_critter()?.damage_kind;
+            return Some(Self {
+                obj: None,
+                attack_kind: AttackKind::Punch,
+                skill: Skill::UnarmedCombat,
+                damage: RangeInclusive { start: 1, end: 2 + melee_dmg },
+                damage_kind,
+                range: 1,
+                ap_cost: 3,
+                perk: None,
+                rounds: 0,
+                ammo: AmmoModifiers::default(),
+                crit_failure_table: 0,
+            });
+        };
+
+        let itemo = objs.get(item);
+        let proto = itemo.proto().unwrap();
+        let weapon = proto.sub.as_weapon().unwrap();
+        let group = AttackGroup::Primary;
+        let attack_kind = weapon.attack_kinds[group];
+        let category = attack_kind.category();
+
+        let rounds = if weapon.max_ammo_count > 0 {
+            let ammo_count = itemo.ammo_count().unwrap_or(0);
+            if ammo_count == 0 {
+                return None;
+            }
+            if attack_kind == AttackKind::FireBurst {
+                cmp::min(weapon.burst_bullet_count.max(1) as u32, ammo_count)
+            } else {
+                1
+            }
+        } else {
+            0
+        };
+
+        let ammo = itemo.sub.as_item()
+            .and_then(|i| i.ammo_proto.as_ref())
+            .and_then(|p| p.borrow().sub.as_item().and_then(|i| i.sub.as_ammo())
+                .map(|a| AmmoModifiers {
+                    armor_class: a.ac_modifier,
+                    damage_resist: a.dr_modifier,
+                    damage_mult: a.damage_mult,
+                    damage_div: a.damage_div,
+                }))
+            .unwrap_or_default();
+
+        let mut damage = weapon.damage;
+        if category.is_melee() {
+            damage.end += melee_dmg;
+        }
+
+        // item_w_skill
+        let skill = match category {
+            AttackCategory::Stand | AttackCategory::MeleeUnarmed => Skill::UnarmedCombat,
+            AttackCategory::MeleeWeapon => Skill::Melee,
+            AttackCategory::Throw => Skill::Throwing,
+            AttackCategory::Fire => match weapon.damage_kind {
+                DamageKind::Laser | DamageKind::Plasma | DamageKind::Electric => Skill::EnergyWeapons,
+                _ => if matches!(weapon.kind, WeaponKind::BigGun | WeaponKind::Minigun | WeaponKind::Launcher) {
+                    Skill::BigGuns
+                } else {
+                    Skill::SmallGuns
+                }
+            }
+        };
+
+        // item_w_mp_cost
+        let mut ap_cost = weapon.ap_costs[group];
+        if is_dude && category == AttackCategory::Fire && rpg.has_trait(Trait::FastShot) {
+            ap_cost -= 1;
+        }
+        let ap_cost = cmp::max(ap_cost, 1);
+
+        let perk = weapon.perk;
+        let damage_kind = weapon.damage_kind;
+        let crit_failure_table = usize::try_from(weapon.crit_failure_table).unwrap_or(0)
+            .min(CRITICAL_FAILURE_EFFECTS.len() - 1);
+        let range = itemo.weapon_range(group, rpg, objs).unwrap_or(1);
+
+        Some(Self {
+            obj: Some(item),
+            attack_kind,
+            skill,
+            damage,
+            damage_kind,
+            range,
+            ap_cost,
+            perk,
+            rounds,
+            ammo,
+            crit_failure_table,
+        })
+    }
+}
+
+/// Inputs of the hit chance formula.
+#[derive(Clone, Debug)]
+pub struct HitChanceParams {
+    pub skill: i32,
+    pub ranged: bool,
+    pub attacker_is_dude: bool,
+    pub perception: i32,
+    pub sharpshooter: i32,
+    pub weapon_perk: Option<Perk>,
+    pub distance: i32,
+    /// Number of critters in the line of fire.
+    pub blockers: i32,
+    pub target_armor_class: i32,
+    pub target_multihex: bool,
+    pub target_prone: bool,
+    pub attacker_blind: bool,
+    pub location: HitLocation,
+    /// Difficulty if the attacker is hostile to the player.
+    pub difficulty: Option<Difficulty>,
+}
+
+// determine_to_hit_func()
+pub fn hit_chance(p: &HitChanceParams) -> i32 {
+    let mut r = p.skill;
+
+    if p.ranged {
+        let (perception_mult, min_distance) = match p.weapon_perk {
+            Some(Perk::WeaponLongRange) => (4, 0),
+            Some(Perk::WeaponScopeRange) => (5, 8),
+            _ => (2, 0),
+        };
+        let mut dist = p.distance;
+        if dist >= min_distance {
+            dist -= if p.attacker_is_dude {
+                perception_mult * (p.perception - 2)
+            } else {
+                perception_mult * p.perception
+            };
+        } else {
+            dist += min_distance;
+        }
+        dist = cmp::max(dist, -2 * p.perception);
+        if p.attacker_is_dude {
+            dist -= 2 * p.sharpshooter;
+        }
+        r -= if dist >= 0 && p.attacker_blind {
+            12 * dist
+        } else {
+            4 * dist
+        };
+        r -= 10 * p.blockers;
+        r += p.location.hit_chance_modifier();
+    } else {
+        r += p.location.hit_chance_modifier() / 2;
+    }
+
+    r -= cmp::max(p.target_armor_class, 0);
+
+    if p.target_multihex {
+        r += 15;
+    }
+    if p.attacker_blind {
+        r -= 25;
+    }
+    if p.target_prone {
+        r += 40;
+    }
+    if let Some(d) = p.difficulty {
+        r += d.hit_chance_modifier();
+    }
+
+    cmp::min(r, 95)
+}
+
+// compute_damage()
+/// Applies damage threshold and resistance to a single hit.
+/// `multiplier` is in halves, i.e. 2 means the damage is not multiplied.
+pub fn damage_after_armor(raw: i32, threshold: i32, resist: i32, multiplier: i32, divisor: i32,
+    difficulty_modifier: i32) -> i32
+{
+    let mut r = raw * multiplier;
+    if divisor != 0 {
+        r /= divisor;
+    }
+    r /= 2;
+    r = r * difficulty_modifier / 100;
+    r -= threshold;
+    if r > 0 {
+        r -= r * resist / 100;
+    }
+    cmp::max(r, 0)
+}
+
+#[derive(Clone, Copy, Debug)]
+struct CriticalEffect {
+    /// Damage multiplier in halves.
+    damage_mult: i32,
+    flags: u32,
+    /// If the stat check with modifier fails the extra flags are applied.
+    check: Option<(Stat, i32, u32)>,
+}
+
+const fn crit(damage_mult: i32, flags: u32, check: Option<(Stat, i32, u32)>) -> CriticalEffect {
+    CriticalEffect { damage_mult, flags, check }
+}
+
+const BYPASS: u32 = DamageFlag::Bypass as u32;
+const DEAD: u32 = DamageFlag::Dead as u32;
+const KNOCKED_DOWN: u32 = DamageFlag::KnockedDown as u32;
+const KNOCKED_OUT: u32 = DamageFlag::KnockedOut as u32;
+const LOSE_TURN: u32 = DamageFlag::LoseTurn as u32;
+const BLIND: u32 = DamageFlag::Blind as u32;
+const CRIP_ARM_LEFT: u32 = DamageFlag::CripArmLeft as u32;
+const CRIP_ARM_RIGHT: u32 = DamageFlag::CripArmRight as u32;
+const CRIP_LEG_LEFT: u32 = DamageFlag::CripLegLeft as u32;
+const CRIP_LEG_RIGHT: u32 = DamageFlag::CripLegRight as u32;
+const CRIP_RANDOM: u32 = DamageFlag::CripRandom as u32;
+const DESTROY: u32 = DamageFlag::Destroy as u32;
+const DROP: u32 = DamageFlag::Drop as u32;
+const DUD: u32 = DamageFlag::Dud as u32;
+const EXPLODE: u32 = DamageFlag::Explode as u32;
+const HIT_SELF: u32 = DamageFlag::HitSelf as u32;
+const HURT_SELF: u32 = DamageFlag::HurtSelf as u32;
+const LOSE_AMMO: u32 = DamageFlag::LoseAmmo as u32;
+const RANDOM_HIT: u32 = DamageFlag::RandomHit as u32;
+
+// crit_succ_eff
+/// Effects of critical hits per hit location and critical level. These follow the table for
+/// humans, the per kill kind tables of the original are not reproduced.
+const CRITICAL_EFFECTS: [[CriticalEffect; 6]; 9] = {
+    use Stat::*;
+    const TORSO: [CriticalEffect; 6] = [
+        crit(3, 0, None),
+        crit(3, BYPASS, None),
+        crit(4, 0, None),
+        crit(4, KNOCKED_DOWN | BYPASS, None),
+        crit(6, KNOCKED_DOWN | BYPASS, None),
+        crit(6, DEAD, None),
+    ];
+    [
+        // Head
+        [
+            crit(4, 0, Some((Endurance, 0, KNOCKED_OUT))),
+            crit(4, BYPASS, Some((Endurance, -3, KNOCKED_OUT))),
+            crit(5, BYPASS, Some((Endurance, -3, KNOCKED_OUT))),
+            crit(5, KNOCKED_DOWN | BYPASS, Some((Endurance, -4, KNOCKED_OUT))),
+            crit(6, KNOCKED_OUT | BYPASS, None),
+            crit(6, DEAD, None),
+        ],
+        // LeftArm
+        [
+            crit(3, 0, None),
+            crit(3, LOSE_TURN, None),
+            crit(4, 0, Some((Endurance, -3, CRIP_ARM_LEFT))),
+            crit(4, CRIP_ARM_LEFT, None),
+            crit(4, CRIP_ARM_LEFT | BYPASS, None),
+            crit(4, CRIP_ARM_LEFT | BYPASS, None),
+        ],
+        // RightArm
+        [
+            crit(3, 0, None),
+            crit(3, LOSE_TURN, None),
+            crit(4, 0, Some((Endurance, -3, CRIP_ARM_RIGHT))),
+            crit(4, CRIP_ARM_RIGHT, None),
+            crit(4, CRIP_ARM_RIGHT | BYPASS, None),
+            crit(4, CRIP_ARM_RIGHT | BYPASS, None),
+        ],
+        TORSO,
+        // RightLeg
+        [
+            crit(3, KNOCKED_DOWN, None),
+            crit(3, KNOCKED_DOWN, Some((Endurance, 0, CRIP_LEG_RIGHT))),
+            crit(4, KNOCKED_DOWN, Some((Endurance, -3, CRIP_LEG_RIGHT))),
+            crit(4, KNOCKED_DOWN | CRIP_LEG_RIGHT, None),
+            crit(4, KNOCKED_DOWN | CRIP_LEG_RIGHT | BYPASS, None),
+            crit(6, KNOCKED_OUT | CRIP_LEG_RIGHT, None),
+        ],
+        // LeftLeg
+        [
+            crit(3, KNOCKED_DOWN, None),
+            crit(3, KNOCKED_DOWN, Some((Endurance, 0, CRIP_LEG_LEFT))),
+            crit(4, KNOCKED_DOWN, Some((Endurance, -3, CRIP_LEG_LEFT))),
+            crit(4, KNOCKED_DOWN | CRIP_LEG_LEFT, None),
+            crit(4, KNOCKED_DOWN | CRIP_LEG_LEFT | BYPASS, None),
+            crit(6, KNOCKED_OUT | CRIP_LEG_LEFT, None),
+        ],
+        // Eyes
+        [
+            crit(4, 0, Some((Luck, 4, BLIND))),
+            crit(4, BYPASS, Some((Luck, 3, BLIND))),
+            crit(6, BYPASS, Some((Luck, 2, BLIND))),
+            crit(6, BLIND | BYPASS, None),
+            crit(8, KNOCKED_OUT | BLIND | BYPASS, None),
+            crit(8, DEAD, None),
+        ],
+        // Groin
+        [
+            crit(3, 0, None),
+            crit(3, BYPASS, Some((Endurance, -3, KNOCKED_DOWN))),
+            crit(3, KNOCKED_DOWN, Some((Endurance, -3, KNOCKED_OUT))),
+            crit(3, KNOCKED_OUT, None),
+            crit(4, KNOCKED_OUT | BYPASS, None),
+            crit(6, DEAD, None),
+        ],
+        // Uncalled
+        TORSO,
+    ]
+};
+
+/// Maps the critical roll (1..=100 plus the `BetterCrit` stat) to the critical level.
+pub fn critical_level(roll: i32) -> usize {
+    match roll {
+        ..=20 => 0,
+        21..=45 => 1,
+        46..=70 => 2,
+        71..=90 => 3,
+        91..=100 => 4,
+        _ => 5,
+    }
+}
+
+// cf_table
+/// Attacker flags of critical failures per weapon `crit_failure_table` and failure level.
+const CRITICAL_FAILURE_EFFECTS: [[u32; 5]; 7] = [
+    // Unarmed
+    [0, LOSE_TURN, LOSE_TURN, HURT_SELF | KNOCKED_DOWN, CRIP_RANDOM],
+    // Melee
+    [0, LOSE_TURN, DROP, RANDOM_HIT, HIT_SELF],
+    // Firearms
+    [0, LOSE_AMMO, DROP, RANDOM_HIT, DESTROY],
+    // Energy weapons
+    [LOSE_TURN, LOSE_TURN | LOSE_AMMO, DROP | LOSE_TURN, RANDOM_HIT, EXPLODE | LOSE_TURN],
+    // Grenades
+    [DUD, DROP, DROP | HURT_SELF, RANDOM_HIT, EXPLODE],
+    // Rocket launchers
+    [LOSE_TURN, LOSE_TURN, LOSE_TURN, HURT_SELF | LOSE_TURN, HURT_SELF | KNOCKED_DOWN | LOSE_TURN],
+    // Flamers
+    [0, LOSE_TURN, RANDOM_HIT, DESTROY, RANDOM_HIT | LOSE_TURN],
+];
+
+/// Maps the critical failure roll (1..=100 adjusted by `Luck`) to the failure level.
+pub fn critical_failure_level(roll: i32) -> usize {
+    match roll {
+        ..=20 => 0,
+        21..=50 => 1,
+        51..=75 => 2,
+        76..=95 => 3,
+        _ => 4,
+    }
+}
+
+/// Returns `true` if `target` faces the `from` point.
+pub fn is_facing(target_pos: Point, target_direction: Direction, from: Point) -> bool {
+    let dir = hex::direction(target_pos, from);
+    dir == target_direction
+        || dir == target_direction.rotate_cw()
+        || dir == target_direction.rotate_ccw()
+}
+
+// pick_death()
+/// Returns animation of the knocked down critter getting up depending on how it fell.
+pub fn stand_up_anim(obj: &Object) -> CritterAnim {
+    let fell_front = obj.fid.critter().is_some_and(|fid| matches!(fid.anim(),
+        CritterAnim::FallFront | CritterAnim::FallFrontSf));
+    if fell_front {
+        CritterAnim::ProneToStanding
+    } else {
+        CritterAnim::BackToStanding
+    }
+}
+
+/// Picks death animation of a critter killed with `damage` of `damage_kind`.
+/// The caller should fall back to `FallBack`/`FallFront` if the critter doesn't have the
+/// returned animation.
+pub fn death_anim(damage_kind: DamageKind, attack_kind: AttackKind, damage: i32, critical: bool,
+    from_front: bool) -> CritterAnim
+{
+    let normal = if from_front {
+        CritterAnim::FallBack
+    } else {
+        CritterAnim::FallFront
+    };
+    if damage < 15 && !critical {
+        return normal;
+    }
+    let massive = damage >= 45;
+    match damage_kind {
+        DamageKind::Melee => match attack_kind.category() {
+            AttackCategory::Fire if matches!(attack_kind,
+                    AttackKind::FireBurst | AttackKind::FireContinuous) => CritterAnim::DancingAutofire,
+            AttackCategory::Fire if massive => CritterAnim::BigHole,
+            _ => normal,
+        }
+        DamageKind::Laser => if massive {
+            CritterAnim::BurnedToNothing
+        } else {
+            CritterAnim::SlicedInHalf
+        }
+        DamageKind::Fire => if massive {
+            CritterAnim::FireDance
+        } else {
+            CritterAnim::CharredBody
+        }
+        DamageKind::Plasma => if massive {
+            CritterAnim::MeltedToNothing
+        } else {
+            normal
+        }
+        DamageKind::Electric => if massive {
+            CritterAnim::ElectrifiedToNothing
+        } else {
+            CritterAnim::Electrify
+        }
+        DamageKind::Explosion => if massive {
+            CritterAnim::ExplodedToNothing
+        } else {
+            CritterAnim::ChunksOfFlesh
+        }
+        DamageKind::Emp | DamageKind::Radiation | DamageKind::Poison => normal,
+    }
+}
+
+/// Attack parameters set by the `attack_complex` script instruction.
+// STRUCT_COMBAT_GCSD
+#[derive(Clone, Copy, Debug)]
+pub struct ScriptedAttack {
+    pub called_shot: HitLocation,
+    /// Number of attacks the parameters apply to.
+    pub num_attacks: u32,
+    pub hit_chance_bonus: i32,
+    pub min_damage: i32,
+    pub max_damage: i32,
+}
+
+/// Outcome of a single attack. It's computed before the attack animation is played and applied
+/// when the animation reaches the hit.
+#[derive(Clone, Debug)]
+pub struct Attack {
+    pub attacker: object::Handle,
+    pub target: object::Handle,
+    pub weapon: Weapon,
+    pub location: HitLocation,
+    pub result: RollCheckResult,
+    pub damage: i32,
+    pub target_flags: BitFlags<DamageFlag>,
+    /// Damage the attacker deals to itself on critical failure.
+    pub attacker_damage: i32,
+    /// Critical failure effects on the attacker.
+    pub attacker_flags: BitFlags<DamageFlag>,
+}
+
+impl Attack {
+    pub fn is_hit(&self) -> bool {
+        self.result.is_success()
+    }
+
+    pub fn is_critical(&self) -> bool {
+        self.result == RollCheckResult::CriticalSuccess
+    }
+}
+
+// determine_to_hit()
+/// Returns chance of `attacker` hitting `target` with the `weapon`.
+pub fn attack_hit_chance(
+    attacker: object::Handle,
+    target: object::Handle,
+    weapon: &Weapon,
+    location: HitLocation,
+    difficulty: Difficulty,
+    rpg: &Rpg,
+    objs: &Objects,
+) -> i32 {
+    let attackero = objs.get(attacker);
+    let targeto = objs.get(target);
+    let attacker_is_dude = attackero.is_dude();
+    let attacker_critter = attackero.sub.as_critter().unwrap();
+    let target_critter = targeto.sub.as_critter();
+    let hostile_to_dude = attacker_critter.combat.team_id
+        != objs.dude_ref().sub.as_critter().unwrap().combat.team_id;
+
+    let distance = attackero.distance(&targeto).unwrap_or(0) as i32;
+    let blockers = if weapon.is_ranged() {
+        objs.shot_blockers_count(attacker, target)
+    } else {
+        0
+    };
+    let params = HitChanceParams {
+        skill: rpg.skill(weapon.skill, &attackero, objs),
+        ranged: weapon.is_ranged(),
+        attacker_is_dude,
+        perception: rpg.stat(Stat::Perception, &attackero, objs),
+        sharpshooter: if attacker_is_dude {
+            rpg.perk(Perk::Sharpshooter, attackero.proto_id().unwrap()) as i32
+        } else {
+            0
+        },
+        weapon_perk: weapon.perk,
+        distance,
+        blockers,
+        target_armor_class: target_critter
+            .map(|_| rpg.stat(Stat::ArmorClass, &targeto, objs) + weapon.ammo.armor_class)
+            .unwrap_or(0),
+        target_multihex: targeto.flags.contains(Flag::MultiHex),
+        target_prone: targeto.is_critter_prone(),
+        attacker_blind: attacker_critter.combat.damage_flags.contains(DamageFlag::Blind),
+        location,
+        difficulty: if hostile_to_dude { Some(difficulty) } else { None },
+    };
+    hit_chance(&params)
+}
+
+// combat_attack()
+/// Rolls the attack of `attacker` on `target` with the `weapon`. The `scripted` parameters
+/// adjust the hit chance and limit the damage.
+#[allow(clippy::too_many_arguments)]
+pub fn roll_attack(
+    attacker: object::Handle,
+    target: object::Handle,
+    weapon: Weapon,
+    location: HitLocation,
+    scripted: Option<&ScriptedAttack>,
+    difficulty: Difficulty,
+    roll_checker: RollChecker,
+    rpg: &Rpg,
+    objs: &Objects,
+) -> Attack {
+    let attackero = objs.get(attacker);
+    let mut chance = attack_hit_chance(attacker, target, &weapon, location, difficulty, rpg, objs);
+    if let Some(scripted) = scripted {
+        chance = cmp::min(chance + scripted.hit_chance_bonus, 95);
+    }
+    let crit_chance = rpg.stat(Stat::CritChance, &attackero, objs)
+        - location.hit_chance_modifier() / 2;
+    let (result, _) = roll_checker.roll_check(chance, crit_chance);
+
+    let mut attack = Attack {
+        attacker,
+        target,
+        weapon,
+        location,
+        result,
+        damage: 0,
+        target_flags: BitFlags::empty(),
+        attacker_damage: 0,
+        attacker_flags: BitFlags::empty(),
+    };
+    if !attack.is_hit() {
+        if result == RollCheckResult::CriticalFailure {
+            roll_critical_failure(&mut attack, difficulty, rpg, objs);
+        }
+        return attack;
+    }
+
+    let targeto = objs.get(target);
+    if targeto.sub.as_critter().is_none() {
+        return attack;
+    }
+
+    let mut damage_mult = 2;
+    if attack.is_critical() {
+        let roll = random(1, 100) + rpg.stat(Stat::BetterCrit, &attackero, objs);
+        let effect = CRITICAL_EFFECTS[location as usize][critical_level(roll)];
+        damage_mult = effect.damage_mult;
+        attack.target_flags |= BitFlags::from_bits_truncate(effect.flags) | DamageFlag::Critical;
+        if let Some((stat, modifier, flags)) = effect.check
+            && !rpg.roll_check_stat(stat, modifier, &targeto, objs).0.is_success()
+        {
+            attack.target_flags |= BitFlags::from_bits_truncate(flags);
+        }
+    }
+    attack.target_flags |= DamageFlag::Hit;
+
+    let hits = 1 + (1..cmp::max(attack.weapon.rounds, 1))
+        .filter(|_| roll_checker.roll_check(chance, 0).0.is_success())
+        .count() as u32;
+    let bypass = attack.target_flags.contains(DamageFlag::Bypass);
+    attack.damage = roll_damage(&attackero, &targeto, &attack.weapon, hits, bypass, damage_mult,
+        difficulty, rpg, objs);
+    if let Some(scripted) = scripted {
+        attack.damage = attack.damage.min(scripted.max_damage).max(scripted.min_damage);
+    }
+
+    if attack.damage >= targeto.sub.as_critter().unwrap().hit_points {
+        attack.target_flags |= DamageFlag::Dead;
+    }
+
+    attack
+}
+
+// attack_crit_failure()
+/// Rolls the critical failure effect from the weapon's critical failure table and sets the
+/// attacker flags accordingly. Depending on the effect the attacker damages itself or the
+/// attack hits a random critter nearby instead of the target.
+fn roll_critical_failure(attack: &mut Attack, difficulty: Difficulty, rpg: &Rpg,
+    objs: &Objects)
+{
+    let attackero = objs.get(attack.attacker);
+    let roll = random(1, 100) - 5 * (rpg.stat(Stat::Luck, &attackero, objs) - 5);
+    let effect = CRITICAL_FAILURE_EFFECTS[attack.weapon.crit_failure_table]
+        [critical_failure_level(roll)];
+    let mut flags = BitFlags::from_bits_truncate(effect);
+    if flags.is_empty() {
+        return;
+    }
+    if flags.contains(DamageFlag::LoseAmmo) && attack.weapon.rounds == 0 {
+        flags.remove(DamageFlag::LoseAmmo);
+    }
+    if flags.contains(DamageFlag::CripRandom) {
+        flags.remove(DamageFlag::CripRandom);
+        flags |= [
+            DamageFlag::CripLegLeft,
+            DamageFlag::CripLegRight,
+            DamageFlag::CripArmLeft,
+            DamageFlag::CripArmRight,
+        ][random(0, 3) as usize];
+    }
+    attack.attacker_flags = flags | DamageFlag::Critical;
+
+    let hits = cmp::max(attack.weapon.rounds, 1);
+    if flags.intersects(DamageFlag::HitSelf | DamageFlag::HurtSelf | DamageFlag::Explode) {
+        attack.attacker_damage = roll_damage(&attackero, &attackero, &attack.weapon, hits, false,
+            2, difficulty, rpg, objs);
+    }
+
+    if flags.contains(DamageFlag::RandomHit) {
+        let elevation = attackero.pos().elevation;
+        let candidates: Vec<_> = objs.iter()
+            .filter(|&h| h != attack.attacker && h != attack.target)
+            .filter(|&h| {
+                let o = objs.get(h);
+                o.sub.as_critter().is_some_and(|c| !c.is_dead())
+                    && o.try_pos().is_some_and(|p| p.elevation == elevation)
+                    && o.distance(&attackero).is_some_and(|d| d as i32 <= attack.weapon.range)
+            })
+            .collect();
+        if !candidates.is_empty() {
+            let target = candidates[random(0, candidates.len() as i32 - 1) as usize];
+            let targeto = objs.get(target);
+            attack.target = target;
+            attack.result = RollCheckResult::Success;
+            attack.target_flags = DamageFlag::Hit.into();
+            attack.damage = roll_damage(&attackero, &targeto, &attack.weapon, hits, false, 2,
+                difficulty, rpg, objs);
+            if attack.damage >= targeto.sub.as_critter().unwrap().hit_points {
+                attack.target_flags |= DamageFlag::Dead;
+            }
+        }
+    }
+}
+
+/// Rolls the damage `hits` hits of the `weapon` deal to the target critter after its armor.
+/// `damage_mult` is in halves.
+#[allow(clippy::too_many_arguments)]
+fn roll_damage(
+    attackero: &Object,
+    targeto: &Object,
+    weapon: &Weapon,
+    hits: u32,
+    bypass: bool,
+    damage_mult: i32,
+    difficulty: Difficulty,
+    rpg: &Rpg,
+    objs: &Objects,
+) -> i32 {
+    let bypass = bypass && weapon.damage_kind != DamageKind::Emp;
+    let kind = weapon.damage_kind;
+    let mut threshold = kind.thresh_stat()
+        .map(|s| rpg.stat(s, targeto, objs))
+        .unwrap_or(0);
+    let mut resist = rpg.stat(kind.resist_stat(), targeto, objs);
+    if bypass {
+        threshold = threshold * 20 / 100;
+        resist = resist * 20 / 100;
+    } else {
+        resist += weapon.ammo.damage_resist;
+    }
+    let resist = resist.clamp(0, 100);
+    let mut bonus = 0;
+    if attackero.is_dude() && weapon.is_ranged() {
+        bonus += 2 * rpg.perk(Perk::BonusRangedDamage, attackero.proto_id().unwrap()) as i32;
+    }
+    let hostile_to_dude = attackero.sub.as_critter().unwrap().combat.team_id
+        != objs.dude_ref().sub.as_critter().unwrap().combat.team_id;
+    let difficulty_modifier = if hostile_to_dude {
+        difficulty.damage_modifier()
+    } else {
+        100
+    };
+    let damage = &weapon.damage;
+    (0..hits)
+        .map(|_| {
+            let raw = random(damage.start, cmp::max(damage.end, damage.start)) + bonus;
+            damage_after_armor(raw, threshold, resist, damage_mult * weapon.ammo.damage_mult,
+                weapon.ammo.damage_div, difficulty_modifier)
+        })
+        .sum()
+}
+
+/// Applies `damage` and `flags` to the critter. Returns `true` if the critter has died.
+// critter_adjust_hits()
+pub fn damage_critter(obj: &mut Object, damage: i32, flags: BitFlags<DamageFlag>) -> bool {
+    let critter = obj.sub.as_critter_mut().unwrap();
+    critter.hit_points -= damage;
+    // Only the lasting conditions are kept, the rest describe the attack itself.
+    let flags = flags & (DamageFlag::KnockedOut | DamageFlag::KnockedDown
+        | DamageFlag::CripLegLeft | DamageFlag::CripLegRight
+        | DamageFlag::CripArmLeft | DamageFlag::CripArmRight
+        | DamageFlag::Blind | DamageFlag::Dead | DamageFlag::LoseTurn);
+    critter.combat.damage_flags |= flags;
+    if critter.hit_points <= 0 {
+        critter.hit_points = 0;
+        critter.combat.damage_flags |= DamageFlag::Dead;
+    }
+    critter.is_dead()
+}
+
+/// Marks the critter as dead and sets its frame to the last frame of the death `anim`.
+// critter_kill()
+pub fn kill_critter(world: &mut World, obj: object::Handle, anim: CritterAnim) {
+    let has_anim = {
+        let mut o = world.objects().get_mut(obj);
+        let critter = o.sub.as_critter_mut().unwrap();
+        critter.hit_points = 0;
+        critter.combat.damage_flags |= DamageFlag::Dead;
+        if let Some(fid) = o.fid.critter().map(|fid| fid.with_anim(anim).into())
+            && world.frm_db().exists(fid)
+        {
+            o.fid = fid;
+            true
+        } else {
+            false
+        }
+    };
+    if has_anim {
+        world.objects_mut().set_frame(obj, object::SetFrame::Last);
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum Request {
+    /// Starts combat or adds the critters to the ongoing combat.
+    Attack {
+        attacker: object::Handle,
+        target: object::Handle,
+        scripted: Option<ScriptedAttack>,
+    },
+    Damage {
+        target: object::Handle,
+        damage: i32,
+        damage_kind: DamageKind,
+        bypass_armor: bool,
+        animate: bool,
+    },
+    End,
+}
+
+#[derive(Clone, Debug)]
+struct Participant {
+    obj: object::Handle,
+    sequence: i32,
+    action_points: i32,
+    /// Critter this one fights with.
+    target: Option<object::Handle>,
+    scripted_attack: Option<ScriptedAttack>,
+}
+
+#[derive(Debug)]
+struct Turns {
+    participants: Vec<Participant>,
+    current: usize,
+    round: u32,
+    turn_started: bool,
+}
+
+/// Turn-based combat state.
+/// Critters take turns in order of their `Sequence` stat, the critter that started the combat
+/// goes first. Each turn the critter gets `ActionPoints` which are spent on moving (one per hex)
+/// and attacking. Scripts request combat actions which are then processed by the game state.
+pub struct Combat {
+    difficulty: Difficulty,
+    turns: Option<Turns>,
+    requests: Vec<Request>,
+}
+
+impl Combat {
+    pub fn new() -> Self {
+        Self {
+            difficulty: Difficulty::default(),
+            turns: None,
+            requests: Vec::new(),
+        }
+    }
+
+    pub fn difficulty(&self) -> Difficulty {
+        self.difficulty
+    }
+
+    pub fn set_difficulty(&mut self, difficulty: Difficulty) {
+        self.difficulty = difficulty;
+    }
+
+    // isInCombat()
+    pub fn is_active(&self) -> bool {
+        self.turns.is_some()
+    }
+
+    pub fn request(&mut self, request: Request) {
+        self.requests.push(request);
+    }
+
+    pub fn take_requests(&mut self) -> Vec<Request> {
+        std::mem::take(&mut self.requests)
+    }
+
+    /// Starts combat with `initiator` taking the first turn and the rest ordered by `Sequence`.
+    // combat_begin()
+    pub fn begin(&mut self, initiator: (object::Handle, i32),
+        others: impl IntoIterator<Item=(object::Handle, i32)>)
+    {
+        assert!(!self.is_active());
+        let mut participants = vec![Self::new_participant(initiator)];
+        participants.extend(others.into_iter()
+            .filter(|&(h, _)| h != initiator.0)
+            .map(Self::new_participant));
+        participants[1..].sort_by_key(|p| cmp::Reverse(p.sequence));
+        self.turns = Some(Turns {
+            participants,
+            current: 0,
+            round: 1,
+            turn_started: false,
+        });
+    }
+
+    /// Adds critter to the ongoing combat. It will take its turn last in the current round.
+    pub fn join(&mut self, obj: object::Handle, sequence: i32) {
+        let turns = self.turns.as_mut().unwrap();
+        if !turns.participants.iter().any(|p| p.obj == obj) {
+            turns.participants.push(Self::new_participant((obj, sequence)));
+        }
+    }
+
+    // combat_over()
+    pub fn end(&mut self) -> Vec<object::Handle> {
+        self.turns.take()
+            .map(|t| t.participants.into_iter().map(|p| p.obj).collect())
+            .unwrap_or_default()
+    }
+
+    pub fn participants(&self) -> impl Iterator<Item=object::Handle> + '_ {
+        self.turns.iter().flat_map(|t| t.participants.iter().map(|p| p.obj))
+    }
+
+    pub fn contains(&self, obj: object::Handle) -> bool {
+        self.participant(obj).is_some()
+    }
+
+    pub fn round(&self) -> u32 {
+        self.turns.as_ref().map(|t| t.round).unwrap_or(0)
+    }
+
+    /// Critter whose turn it is.
+    pub fn current(&self) -> Option<object::Handle> {
+        self.turns.as_ref().map(|t| t.participants[t.current].obj)
+    }
+
+    pub fn is_turn_started(&self) -> bool {
+        self.turns.as_ref().is_some_and(|t| t.turn_started)
+    }
+
+    pub fn start_turn(&mut self, action_points: i32) {
+        let turns = self.turns.as_mut().unwrap();
+        turns.turn_started = true;
+        turns.participants[turns.current].action_points = action_points;
+    }
+
+    /// Passes the turn to the next critter. Returns `true` if a new round has started.
+    pub fn next_turn(&mut self) -> bool {
+        let turns = self.turns.as_mut().unwrap();
+        turns.turn_started = false;
+        turns.current += 1;
+        if turns.current >= turns.participants.len() {
+            turns.current = 0;
+            turns.round += 1;
+            turns.participants.sort_by_key(|p| cmp::Reverse(p.sequence));
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Removes the critter from combat, for example because it has died.
+    pub fn remove(&mut self, obj: object::Handle) {
+        let Some(turns) = self.turns.as_mut() else { return };
+        let Some(i) = turns.participants.iter().position(|p| p.obj == obj) else { return };
+        turns.participants.remove(i);
+        for p in &mut turns.participants {
+            if p.target == Some(obj) {
+                p.target = None;
+            }
+        }
+        if turns.participants.is_empty() {
+            self.turns = None;
+            return;
+        }
+        if i < turns.current {
+            turns.current -= 1;
+        } else if i == turns.current {
+            turns.turn_started = false;
+            if turns.current >= turns.participants.len() {
+                turns.current = 0;
+                turns.round += 1;
+            }
+        }
+    }
+
+    pub fn action_points(&self, obj: object::Handle) -> i32 {
+        self.participant(obj).map(|p| p.action_points).unwrap_or(0)
+    }
+
+    /// Spends `count` action points. Returns `false` if there's not enough points.
+    pub fn spend_action_points(&mut self, obj: object::Handle, count: i32) -> bool {
+        let Some(p) = self.participant_mut(obj) else { return false };
+        if p.action_points < count {
+            return false;
+        }
+        p.action_points -= count;
+        true
+    }
+
+    pub fn target(&self, obj: object::Handle) -> Option<object::Handle> {
+        self.participant(obj).and_then(|p| p.target)
+    }
+
+    pub fn set_target(&mut self, obj: object::Handle, target: Option<object::Handle>) {
+        if let Some(p) = self.participant_mut(obj) {
+            p.target = target;
+        }
+    }
+
+    pub fn set_scripted_attack(&mut self, obj: object::Handle, attack: Option<ScriptedAttack>) {
+        if let Some(p) = self.participant_mut(obj) {
+            p.scripted_attack = attack;
+        }
+    }
+
+    /// Returns the scripted parameters of the next attack of `obj`. The parameters are dropped
+    /// once they've been used `num_attacks` times.
+    pub fn take_scripted_attack(&mut self, obj: object::Handle) -> Option<ScriptedAttack> {
+        let p = self.participant_mut(obj)?;
+        let attack = p.scripted_attack?;
+        if attack.num_attacks > 1 {
+            p.scripted_attack.as_mut().unwrap().num_attacks -= 1;
+        } else {
+            p.scripted_attack = None;
+        }
+        Some(attack)
+    }
+
+    /// Whether any participant still has someone to fight with.
+    pub fn has_hostiles(&self) -> bool {
+        self.turns.as_ref().is_some_and(|t| t.participants.iter()
+            .any(|p| p.target.is_some_and(|h| self.contains(h))))
+    }
+
+    fn new_participant((obj, sequence): (object::Handle, i32)) -> Participant {
+        Participant {
+            obj,
+            sequence,
+            action_points: 0,
+            target: None,
+            scripted_attack: None,
+        }
+    }
+
+    fn participant(&self, obj: object::Handle) -> Option<&Participant> {
+        self.turns.as_ref()?.participants.iter().find(|p| p.obj == obj)
+    }
+
+    fn participant_mut(&mut self, obj: object::Handle) -> Option<&mut Participant> {
+        self.turns.as_mut()?.participants.iter_mut().find(|p| p.obj == obj)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use slotmap::SlotMap;
+
+    fn handles(n: usize) -> Vec<object::Handle> {
+        let mut m = SlotMap::<object::Handle, ()>::with_key();
+        (0..n).map(|_| m.insert(())).collect()
+    }
+
+    #[test]
+    fn turn_order() {
+        let h = handles(4);
+        let mut c = Combat::new();
+        c.begin((h[2], 5), vec![(h[0], 6), (h[1], 10), (h[2], 5), (h[3], 8)]);
+        assert_eq!(c.participants().collect::<Vec<_>>(), vec![h[2], h[1], h[3], h[0]]);
+        assert_eq!(c.current(), Some(h[2]));
+
+        assert!(!c.next_turn());
+        assert!(!c.next_turn());
+        assert!(!c.next_turn());
+        assert_eq!(c.current(), Some(h[0]));
+
+        // New round is ordered by sequence only.
+        assert!(c.next_turn());
+        assert_eq!(c.round(), 2);
+        assert_eq!(c.participants().collect::<Vec<_>>(), vec![h[1], h[3], h[0], h[2]]);
+
+        c.set_target(h[3], Some(h[0]));
+        assert!(c.has_hostiles());
+        assert!(!c.next_turn());
+        assert_eq!(c.current(), Some(h[3]));
+        c.remove(h[0]);
+        assert!(!c.has_hostiles());
+        assert_eq!(c.target(h[3]), None);
+        assert_eq!(c.current(), Some(h[3]));
+        c.remove(h[3]);
+        assert_eq!(c.current(), Some(h[2]));
+        c.remove(h[2]);
+        assert_eq!(c.current(), Some(h[1]));
+        assert_eq!(c.round(), 3);
+        c.remove(h[1]);
+        assert!(!c.is_active());
+    }
+
+    #[test]
+    fn action_points() {
+        let h = handles(1);
+        let mut c = Combat::new();
+        c.begin((h[0], 0), vec![]);
+        c.start_turn(5);
+        assert!(c.spend_action_points(h[0], 3));
+        assert!(!c.spend_action_points(h[0], 3));
+        assert_eq!(c.action_points(h[0]), 2);
+        assert_eq!(c.end(), vec![h[0]]);
+    }
+
+    #[test]
+    fn damage_after_armor_() {
+        assert_eq!(damage_after_armor(10, 0, 0, 2, 1, 100), 10);
+        assert_eq!(damage_after_armor(10, 4, 50, 2, 1, 100), 3);
+        // Critical x1.5 and ammo 2/1.
+        assert_eq!(damage_after_armor(10, 0, 0, 3 * 2, 1, 100), 30);
+        assert_eq!(damage_after_armor(10, 0, 0, 2, 2, 75), 3);
+        assert_eq!(damage_after_armor(3, 5, 0, 2, 1, 125), 0);
+        assert_eq!(damage_after_armor(10, 0, 100, 2, 1, 100), 0);
+    }
+
+    #[test]
+    fn hit_chance_() {
+        let mut p = HitChanceParams {
+            skill: 80,
+            ranged: false,
+            attacker_is_dude: true,
+            perception: 6,
+            sharpshooter: 0,
+            weapon_perk: None,
+            distance: 1,
+            blockers: 0,
+            target_armor_class: 10,
+            target_multihex: false,
+            target_prone: false,
+            attacker_blind: false,
+            location: HitLocation::Uncalled,
+            difficulty: None,
+        };
+        assert_eq!(hit_chance(&p), 70);
+
+        p.location = HitLocation::Eyes;
+        assert_eq!(hit_chance(&p), 40);
+
+        p.ranged = true;
+        p.location = HitLocation::Torso;
+        p.distance = 20;
+        // (20 - 2 * (6 - 2)) * 4 = 48
+        assert_eq!(hit_chance(&p), 22);
+
+        p.weapon_perk = Some(Perk::WeaponLongRange);
+        // (20 - 4 * (6 - 2)) * 4 = 16
+        assert_eq!(hit_chance(&p), 54);
+
+        p.blockers = 1;
+        p.target_prone = true;
+        assert_eq!(hit_chance(&p), 84);
+
+        p.difficulty = Some(Difficulty::Hard);
+        assert_eq!(hit_chance(&p), 95);
+    }
+
+    #[test]
+    fn critical_level_() {
+        assert_eq!(critical_level(1), 0);
+        assert_eq!(critical_level(20), 0);
+        assert_eq!(critical_level(21), 1);
+        assert_eq!(critical_level(70), 2);
+        assert_eq!(critical_level(90), 3);
+        assert_eq!(critical_level(100), 4);
+        assert_eq!(critical_level(101), 5);
+    }
+
+    #[test]
+    fn critical_failure_level_() {
+        assert_eq!(critical_failure_level(-10), 0);
+        assert_eq!(critical_failure_level(20), 0);
+        assert_eq!(critical_failure_level(21), 1);
+        assert_eq!(critical_failure_level(75), 2);
+        assert_eq!(critical_failure_level(95), 3);
+        assert_eq!(critical_failure_level(96), 4);
+    }
+
+    #[test]
+    fn scripted_attack() {
+        let h = handles(2);
+        let mut c = Combat::new();
+        c.begin((h[0], 0), vec![(h[1], 0)]);
+        c.set_scripted_attack(h[0], Some(ScriptedAttack {
+            called_shot: HitLocation::Eyes,
+            num_attacks: 2,
+            hit_chance_bonus: 10,
+            min_damage: 0,
+            max_damage: 5,
+        }));
+        assert!(c.take_scripted_attack(h[1]).is_none());
+        assert_eq!(c.take_scripted_attack(h[0]).unwrap().num_attacks, 2);
+        assert_eq!(c.take_scripted_attack(h[0]).unwrap().called_shot, HitLocation::Eyes);
+        assert!(c.take_scripted_attack(h[0]).is_none());
+    }
+
+    #[test]
+    fn death_anim_() {
+        use AttackKind::{FireBurst, FireSingle, Punch, Throw};
+        use CritterAnim::*;
+        assert_eq!(death_anim(DamageKind::Laser, FireSingle, 10, false, true), FallBack);
+        assert_eq!(death_anim(DamageKind::Laser, FireSingle, 10, false, false), FallFront);
+        assert_eq!(death_anim(DamageKind::Laser, FireSingle, 20, false, true), SlicedInHalf);
+        assert_eq!(death_anim(DamageKind::Plasma, FireSingle, 50, false, true), MeltedToNothing);
+        assert_eq!(death_anim(DamageKind::Melee, FireBurst, 20, false, true), DancingAutofire);
+        assert_eq!(death_anim(DamageKind::Melee, Punch, 50, true, true), FallBack);
+        assert_eq!(death_anim(DamageKind::Explosion, Throw, 10, true, true), ChunksOfFlesh);
+    }
+
+    #[test]
+    fn is_facing_() {
+        let p = Point::new(10, 10);
+        let ne = hex::go(p, Direction::NE, 3);
+        let sw = hex::go(p, Direction::SW, 3);
+        assert!(is_facing(p, Direction::NE, ne));
+        assert!(is_facing(p, Direction::E, ne));
+        assert!(!is_facing(p, Direction::NE, sw));
+    }
+}
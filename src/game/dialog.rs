@@ -1,38 +1,105 @@
 use bstring::{bstr, BString};
+use enum_primitive_derive::Primitive;
 
-use crate::asset::frame::FrameId;
+use crate::asset::EntityKind;
+use crate::asset::frame::{FrameId, Idx};
 use crate::asset::message::BULLET_STR;
 use crate::game::object;
 use crate::game::script::ScriptIid;
+use crate::game::ui::talking_head::{Mood, TalkingHead};
 use crate::game::world::World;
 use crate::graphics::{Point, Rect};
-use crate::graphics::color::{Rgb15, GREEN};
+use crate::graphics::color::{Rgb15, GREEN, WHITE};
 use crate::graphics::font::FontKey;
 use crate::graphics::sprite::{Sprite, Effect};
+use crate::sound::Sound;
 use crate::ui::*;
+use crate::ui::button::Button;
+use crate::ui::command::UiCommandData;
+use crate::ui::command::dialog::Command;
 use crate::ui::message_panel::{MessagePanel, MouseControl};
 use crate::ui::panel::Panel;
 
+/// Maximum number of entries kept in the review history.
+const MAX_HISTORY_LEN: usize = 200;
+
+/// Reaction of the NPC an option is expected to cause. Shown to the player with the Empathy perk.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Primitive)]
+pub enum Reaction {
+    Good = 49,
+    Neutral = 50,
+    Bad = 51,
+}
+
 pub struct OptionInfo {
     pub proc_id: Option<u32>,
+    text: BString,
+}
+
+/// Talking head setup as passed to `start_gdialog`.
+#[derive(Clone, Copy, Debug)]
+pub struct Head {
+    pub idx: Idx,
+    pub mood: Mood,
+    pub background: Option<Idx>,
+}
+
+enum HistoryEntry {
+    Reply(BString),
+    Option(BString),
+}
+
+struct Review {
+    window: Handle,
 }
 
 pub struct Dialog {
     window: Handle,
     reply: Handle,
     options_widget: Handle,
+    head: Option<Handle>,
+    speech_dir: Option<String>,
     options: Vec<OptionInfo>,
+    history: Vec<HistoryEntry>,
+    review: Option<Review>,
+    empathy: bool,
     sid: ScriptIid,
     saved_camera_origin: Point,
     pub obj: object::Handle,
     pub running: bool,
+    pub barter_mod: i32,
+    barter_requested: bool,
 }
 
 impl Dialog {
-    pub fn show(ui: &mut Ui, world: &mut World, obj: object::Handle) -> Self {
+    /// Shows the dialog window. If `empathy` is true the options are colored by the expected
+    /// reaction.
+    pub fn show(ui: &mut Ui, world: &mut World, obj: object::Handle, head: Option<Head>,
+        empathy: bool) -> Self
+    {
         let window = ui.new_window(Rect::with_size(0, 0, 640, 480),
             Some(Sprite::new(FrameId::ALLTLK)));
 
+        let (head, speech_dir) = if let Some(head) = head {
+            let frm_db = ui.frm_db().clone();
+            let speech_dir = FrameId::new_head(0, 0, head.idx)
+                .and_then(|fid| frm_db.base_name(fid))
+                .map(|s| s.to_ascii_lowercase());
+            let head_rect = Rect::with_size(126, 14, 388, 200);
+            if let Some(fid) = head.background
+                .and_then(|idx| FrameId::new_generic(EntityKind::Background, idx))
+                .filter(|&fid| frm_db.exists(fid))
+            {
+                ui.new_widget(window, head_rect, None, Some(Sprite::new_with_pos(fid,
+                    head_rect.top_left())), Panel::new());
+            }
+            let head = ui.new_widget(window, head_rect, None, None,
+                TalkingHead::new(frm_db, head.idx, head.mood));
+            (Some(head), speech_dir)
+        } else {
+            (None, None)
+        };
+
         ui.new_widget(window, Rect::with_size(0, 480 - 190, 640, 480), None,
             Some(Sprite::new(FrameId::DI_TALK)), Panel::new());
 
@@ -45,6 +112,13 @@ impl Dialog {
         options.set_message_spacing(2);
         let options_widget = ui.new_widget(window, Rect::with_size(127, 340, 397, 100), None, None, options);
 
+        ui.new_widget(window, Rect::with_size(13, 290 + 154, 51, 18), None, None,
+            Button::new(FrameId::DI_REST1, FrameId::DI_REST2,
+                Some(UiCommandData::Dialog(Command::Review))));
+        ui.new_widget(window, Rect::with_size(593, 290 + 41, 14, 14), None, None,
+            Button::new(FrameId::DI_RDBT2, FrameId::DI_RDBT1,
+                Some(UiCommandData::Dialog(Command::Barter))));
+
         let mut spr = Sprite::new(FrameId::HILIGHT1);
         spr.effect = Some(Effect::Highlight { color: Rgb15::from_packed(0x4631) });
         ui.new_widget(window, Rect::with_size(426, 15, 1, 1), None, Some(spr),
@@ -68,15 +142,23 @@ impl Dialog {
             window,
             reply,
             options_widget,
+            head,
+            speech_dir,
             options: Vec::new(),
+            history: Vec::new(),
+            review: None,
+            empathy,
             running: false,
             sid,
             saved_camera_origin,
             obj,
+            barter_mod: 0,
+            barter_requested: false,
         }
     }
 
-    pub fn hide(self, ui: &mut Ui, world: &mut World) {
+    pub fn hide(mut self, ui: &mut Ui, world: &mut World) {
+        self.hide_review(ui);
         ui.remove(self.window);
         world.camera_mut().origin = self.saved_camera_origin;
     }
@@ -85,10 +167,35 @@ impl Dialog {
         self.options_widget == widget
     }
 
-    pub fn set_reply(&self, ui: &mut Ui, reply: impl AsRef<bstr>) {
+    pub fn set_reply(&mut self, ui: &mut Ui, reply: impl AsRef<bstr>) {
+        let reply = reply.as_ref();
         let mut replyw = ui.widget_mut::<MessagePanel>(self.reply);
         replyw.clear_messages();
-        replyw.push_message(BString::concat(&[&b"  "[..], reply.as_ref().as_bytes()]))
+        replyw.push_message(BString::concat(&[&b"  "[..], reply.as_bytes()]));
+        self.push_history(HistoryEntry::Reply(reply.into()));
+    }
+
+    /// Plays the reply speech and lip syncs the talking head. `audio` is the speech file name
+    /// as set in the message. Does nothing if there's no talking head.
+    // gdialog_setup_speech()
+    pub fn speak(&self, ui: &mut Ui, sound: &mut Sound, audio: &bstr) {
+        let (Some(head), Some(dir)) = (self.head, &self.speech_dir) else {
+            return;
+        };
+        if audio.is_empty() {
+            return;
+        }
+        let name = format!("{}/{}", dir, audio.display());
+        sound.play_speech(&name);
+        let lips = sound.load_lips(&name);
+        ui.widget_mut::<TalkingHead>(head).speak(lips);
+    }
+
+    /// Shows the NPC reaction with the talking head.
+    pub fn react(&self, ui: &mut Ui, mood: Mood) {
+        if let Some(head) = self.head {
+            ui.widget_mut::<TalkingHead>(head).react(mood);
+        }
     }
 
     pub fn clear_options(&mut self, ui: &mut Ui) {
@@ -96,11 +203,24 @@ impl Dialog {
         self.options.clear();
     }
 
-    pub fn add_option(&mut self, ui: &mut Ui, text: impl AsRef<bstr>, proc_id: Option<u32>) {
+    pub fn add_option(&mut self, ui: &mut Ui, text: impl AsRef<bstr>, proc_id: Option<u32>,
+        reaction: Reaction)
+    {
+        let text = text.as_ref();
+        let color = if self.empathy {
+            match reaction {
+                Reaction::Good => Some(Rgb15::from_packed(0x7feb)),
+                Reaction::Neutral => None,
+                Reaction::Bad => Some(Rgb15::from_packed(0x7c1f)),
+            }
+        } else {
+            None
+        };
         let mut optionsw = ui.widget_mut::<MessagePanel>(self.options_widget);
-        optionsw.push_message(Self::build_option(text.as_ref()));
+        optionsw.push_message_with_color(Self::build_option(text), color);
         self.options.push(OptionInfo {
             proc_id,
+            text: text.into(),
         });
     }
 
@@ -108,6 +228,17 @@ impl Dialog {
         &self.options[id as usize]
     }
 
+    /// Records the picked option in the review history and clears the options.
+    /// Returns the option's procedure.
+    pub fn pick_option(&mut self, ui: &mut Ui, id: u32) -> Option<u32> {
+        let option = &self.options[id as usize];
+        let proc_id = option.proc_id;
+        let text = option.text.clone();
+        self.push_history(HistoryEntry::Option(text));
+        self.clear_options(ui);
+        proc_id
+    }
+
     pub fn is_empty(&self) -> bool {
         self.options.is_empty()
     }
@@ -116,8 +247,61 @@ impl Dialog {
         self.sid
     }
 
+    // gdReviewInit()
+    pub fn show_review(&mut self, ui: &mut Ui) {
+        if self.review.is_some() {
+            return;
+        }
+        let window = ui.new_window(Rect::with_size(0, 0, 640, 480),
+            Some(Sprite::new(FrameId::REVIEW)));
+        ui.widget_base_mut(window).set_modal(true);
+
+        let mut text = MessagePanel::new(ui.fonts().clone(), FontKey::antialiased(1), GREEN);
+        text.set_capacity(Some(MAX_HISTORY_LEN));
+        let text = ui.new_widget(window, Rect::with_size(113, 76, 422, 340), None, None, text);
+        {
+            let mut text = ui.widget_mut::<MessagePanel>(text);
+            for entry in &self.history {
+                match entry {
+                    HistoryEntry::Reply(s) => text.push_message(s),
+                    HistoryEntry::Option(s) => text.push_message_with_color(
+                        Self::build_option(s), Some(WHITE)),
+                }
+            }
+        }
+
+        ui.new_widget(window, Rect::with_size(500, 398, 51, 18), None, None,
+            Button::new(FrameId::DI_DONE1, FrameId::DI_DONE2,
+                Some(UiCommandData::Dialog(Command::ReviewDone))));
+
+        self.review = Some(Review { window });
+    }
+
+    // gdReviewExit()
+    pub fn hide_review(&mut self, ui: &mut Ui) {
+        if let Some(review) = self.review.take() {
+            ui.remove(review.window);
+        }
+    }
+
+    /// Requests the barter screen to be shown. See `take_barter_request()`.
+    // gdialog_barter()
+    pub fn barter(&mut self) {
+        self.barter_requested = true;
+    }
+
+    pub fn take_barter_request(&mut self) -> bool {
+        std::mem::replace(&mut self.barter_requested, false)
+    }
+
+    fn push_history(&mut self, entry: HistoryEntry) {
+        if self.history.len() >= MAX_HISTORY_LEN {
+            self.history.remove(0);
+        }
+        self.history.push(entry);
+    }
+
     fn build_option(option: &bstr) -> BString {
         BString::concat(&[&b"  "[..], BULLET_STR, &b" "[..], option.as_bytes()])
     }
 }
-
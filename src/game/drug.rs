@@ -0,0 +1,208 @@
+use log::*;
+use std::cmp;
+
+use crate::asset::{Perk, Stat, Trait};
+use crate::asset::proto::{DrugEffect, DrugEffectModifier, ProtoId};
+use crate::game::GameTime;
+use crate::game::object::{Handle, Object, Objects};
+use crate::game::queue::{self, Event, Queue};
+use crate::game::rpg::Rpg;
+use crate::util::random::random;
+
+/// Duration of the withdrawal in minutes.
+const WITHDRAWAL_DURATION: u32 = 7 * 24 * 60;
+
+type Modifiers = [Option<(Stat, i32)>; queue::DRUG_EFFECT_COUNT];
+
+/// Applies the immediate effects of the `drug` taken by the `critter` and schedules the delayed
+/// ones. Rolls for addiction if the `critter` is the dude. Taking the drug the dude is addicted to
+/// ends the withdrawal and postpones the next one.
+// item_d_take_drug()
+pub fn take_drug(
+    critter: Handle,
+    drug: Handle,
+    rpg: &Rpg,
+    objs: &Objects,
+    queue: &mut Queue,
+    now: GameTime,
+) {
+    let (pid, events, addiction) = {
+        let drugo = objs.get(drug);
+        let proto = drugo.proto().unwrap();
+        let d = proto.sub.as_item().unwrap().sub.as_drug().unwrap();
+        (drugo.proto_id().unwrap(), effect_events(&d.effects, roll_modifier),
+            (d.addiction.chance, d.addiction.perk, d.addiction.delay))
+    };
+    let is_dude = objs.get(critter).is_dude();
+    let chem_resistant = is_dude && rpg.has_trait(Trait::ChemResistant);
+
+    for (delay, modifiers) in events {
+        if delay == 0 {
+            process_drug(critter, &modifiers, rpg, objs, queue, now);
+        } else {
+            let delay = if chem_resistant { delay / 2 } else { delay };
+            queue.push(now.add_decis(delay * GameTime::MINUTE.as_decis()), Some(critter),
+                Event::Drug { pid, modifiers });
+        }
+    }
+
+    if !is_dude {
+        return;
+    }
+    let (chance, perk, onset_delay) = addiction;
+    let withdrawals = queue.remove(|e| e.obj == Some(critter)
+        && matches!(e.event, Event::Withdrawal { pid: p, .. } if p == pid));
+    let addicted = !withdrawals.is_empty();
+    for e in withdrawals {
+        if let Event::Withdrawal { perk: Some(perk), onset: false, .. } = e.event {
+            rpg.remove_perk_effect(perk, &mut objs.get_mut(critter), objs);
+        }
+    }
+    let chance = addiction_chance(chance, rpg, &objs.get(critter));
+    if addicted || random(1, 100) <= chance as i32 {
+        debug!("{:?} is addicted to {:?}", critter, pid);
+        queue.push(now.add_decis(onset_delay * GameTime::MINUTE.as_decis()), Some(critter),
+            Event::Withdrawal { pid, perk, onset: true });
+    }
+}
+
+/// Starts or ends the withdrawal from the drug `pid`. Returns `true` if the withdrawal has just
+/// set in. Withdrawal from Jet never ends.
+// item_wd_process()
+#[allow(clippy::too_many_arguments)]
+pub fn process_withdrawal(
+    critter: Handle,
+    pid: ProtoId,
+    perk: Option<Perk>,
+    onset: bool,
+    rpg: &Rpg,
+    objs: &Objects,
+    queue: &mut Queue,
+    now: GameTime,
+) -> bool {
+    let end = Event::Withdrawal { pid, perk, onset: false };
+    if onset {
+        // perform_withdrawal_start()
+        let mut duration = WITHDRAWAL_DURATION;
+        {
+            let obj = &mut objs.get_mut(critter);
+            if let Some(perk) = perk {
+                rpg.add_perk_effect(perk, obj, objs);
+            }
+            if obj.is_dude() {
+                if rpg.has_trait(Trait::ChemReliant) {
+                    duration /= 2;
+                }
+                if rpg.has_perk(Perk::FlowerChild, ProtoId::DUDE) {
+                    duration /= 2;
+                }
+            }
+        }
+        queue.push(now.add_decis(duration * GameTime::MINUTE.as_decis()), Some(critter), end);
+        true
+    } else {
+        if perk == Some(Perk::JetReliance) {
+            // Stays addicted until cured.
+            queue.push(now.add_decis(WITHDRAWAL_DURATION * GameTime::MINUTE.as_decis()),
+                Some(critter), end);
+            return false;
+        }
+        // perform_withdrawal_end()
+        if let Some(perk) = perk {
+            rpg.remove_perk_effect(perk, &mut objs.get_mut(critter), objs);
+        }
+        debug!("{:?} is no longer addicted to {:?}", critter, pid);
+        false
+    }
+}
+
+/// Returns the chance in percents of the `obj` getting addicted to a drug with base `chance`.
+fn addiction_chance(chance: u32, rpg: &Rpg, obj: &Object) -> u32 {
+    if !obj.is_dude() {
+        return chance;
+    }
+    let mut r = chance;
+    if rpg.has_trait(Trait::ChemReliant) {
+        r *= 2;
+    }
+    if rpg.has_trait(Trait::ChemResistant) {
+        r /= 2;
+    }
+    if rpg.has_perk(Perk::FlowerChild, ProtoId::DUDE) {
+        r /= 2;
+    }
+    cmp::min(r, 100)
+}
+
+fn roll_modifier(modifier: DrugEffectModifier) -> i32 {
+    match modifier {
+        DrugEffectModifier::Fixed(v) => v,
+        DrugEffectModifier::Random(min, max) => random(min, max),
+    }
+}
+
+/// Groups the effects by their delay (in minutes) ordered by the delay.
+fn effect_events(effects: &[DrugEffect], mut roll: impl FnMut(DrugEffectModifier) -> i32)
+    -> Vec<(u32, Modifiers)>
+{
+    let mut r: Vec<(u32, Modifiers)> = Vec::new();
+    for effect in effects {
+        let modifier = Some((effect.stat, roll(effect.modifier)));
+        if let Some((_, mods)) = r.iter_mut().find(|(d, _)| *d == effect.delay)
+            && let Some(m) = mods.iter_mut().find(|m| m.is_none())
+        {
+            *m = modifier;
+            continue;
+        }
+        let mut mods = [None; queue::DRUG_EFFECT_COUNT];
+        mods[0] = modifier;
+        r.push((effect.delay, mods));
+    }
+    r.sort_by_key(|&(d, _)| d);
+    r
+}
+
+/// Adds the drug effect modifiers to the stats of the `critter`.
+// item_d_process()
+pub fn process_drug(
+    critter: Handle,
+    modifiers: &Modifiers,
+    rpg: &Rpg,
+    objs: &Objects,
+    queue: &mut Queue,
+    now: GameTime,
+) {
+    let obj = &mut objs.get_mut(critter);
+    for &(stat, v) in modifiers.iter().flatten() {
+        rpg.add_bonus_stat(stat, obj, v, objs);
+    }
+    if obj.is_dude() && modifiers.iter().flatten().any(|&(s, _)| s == Stat::CurrentPoison) {
+        queue.schedule_poison(now, critter, obj.sub.as_critter().unwrap().poison);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn effect_events_() {
+        let e = |delay, stat, modifier| DrugEffect { delay, stat, modifier };
+        let effects = [
+            e(0, Stat::Strength, DrugEffectModifier::Fixed(2)),
+            e(360, Stat::Strength, DrugEffectModifier::Fixed(-3)),
+            e(0, Stat::Agility, DrugEffectModifier::Random(1, 3)),
+            e(60, Stat::Agility, DrugEffectModifier::Fixed(-2)),
+            e(360, Stat::Agility, DrugEffectModifier::Fixed(1)),
+        ];
+        let actual = effect_events(&effects, |m| match m {
+            DrugEffectModifier::Fixed(v) => v,
+            DrugEffectModifier::Random(min, _) => min * 10,
+        });
+        assert_eq!(actual, vec![
+            (0, [Some((Stat::Strength, 2)), Some((Stat::Agility, 10)), None]),
+            (60, [Some((Stat::Agility, -2)), None, None]),
+            (360, [Some((Stat::Strength, -3)), Some((Stat::Agility, 1)), None]),
+        ]);
+    }
+}
@@ -55,7 +55,13 @@ impl Inventory {
         self.internal.is_some()
     }
 
-    pub fn handle(&mut self, cmd: UiCommand, rpg: &Rpg, ui: &mut Ui, ui_sequencer: &mut Sequencer) {
+    /// Returns the drug the owner wants to take.
+    pub fn handle(&mut self,
+        cmd: UiCommand,
+        rpg: &Rpg,
+        ui: &mut Ui,
+        ui_sequencer: &mut Sequencer,
+    ) -> Option<object::Handle> {
         if let UiCommandData::Inventory(c) = cmd.data {
             match c {
                 Command::Show => {
@@ -67,8 +73,13 @@ impl Inventory {
                 _ => {}
             }
         }
-        if let Some(v) = self.internal.as_mut() {
-            v.handle(cmd, rpg, ui);
+        self.internal.as_mut().and_then(|v| v.handle(cmd, rpg, ui))
+    }
+
+    /// Updates the screen after the owner or its inventory was changed outside of it.
+    pub fn sync_to_ui(&self, rpg: &Rpg, ui: &Ui) {
+        if let Some(v) = self.internal.as_ref() {
+            v.sync_to_ui(rpg, ui);
         }
     }
 
@@ -577,6 +588,7 @@ impl Internal {
         })
     }
 
+    /// Returns the item if it's a drug dropped on the owner image.
     // switch_hands
     fn handle_list_drop(&mut self,
         src: ui::Handle,
@@ -584,14 +596,17 @@ impl Internal {
         src_obj: object::Handle,
         rpg: &Rpg,
         ui: &mut Ui,
-    ) {
+    ) -> Option<object::Handle> {
         let src_slot = self.slot_from_widget(src).unwrap();
 
-        let Some(target) = ui.widget_at(pos) else { return };
+        let target = ui.widget_at(pos)?;
         if target == src {
-            return;
+            return None;
         }
-        let Some(target_slot) = self.slot_from_widget(target) else { return };
+        if target == self.owner_image {
+            return self.is_drug(src_obj).then_some(src_obj);
+        }
+        let target_slot = self.slot_from_widget(target)?;
 
         assert_ne!(src_slot, target_slot);
 
@@ -628,7 +643,7 @@ impl Internal {
 
                 if eq_slot == EquipmentSlot::Armor {
                     if src_obj.proto().unwrap().kind() != ExactEntityKind::Item(ItemKind::Armor) {
-                        return;
+                        return None;
                     }
                     actions.push(Action::ArmorChange {
                         old_armor: target_obj,
@@ -723,6 +738,13 @@ impl Internal {
 
         self.sync_owner_fid(rpg);
         self.sync_to_ui(rpg, ui);
+        None
+    }
+
+    fn is_drug(&self, item: object::Handle) -> bool {
+        let world = self.world.borrow();
+        let item = world.objects().get(item);
+        item.proto().unwrap().kind() == ExactEntityKind::Item(ItemKind::Drug)
     }
 
     fn sync_owner_fid(&self, rpg: &Rpg) {
@@ -740,7 +762,8 @@ impl Internal {
         self.sync_to_ui(rpg, ui);
     }
 
-    fn handle(&mut self, cmd: UiCommand, rpg: &Rpg, ui: &mut Ui) {
+    fn handle(&mut self, cmd: UiCommand, rpg: &Rpg, ui: &mut Ui) -> Option<object::Handle> {
+        let mut drug = None;
         match cmd.data {
             UiCommandData::Inventory(c) => match c {
                 Command::Show | Command::Hide => {}
@@ -753,12 +776,14 @@ impl Internal {
                 }
                 Command::Action { object, action } => {
                     self.hide_action_menu(ui);
-                    if let Some(Action::Unload) = action {
-                        self.unload(object, rpg, ui);
+                    match action {
+                        Some(Action::Unload) => self.unload(object, rpg, ui),
+                        Some(Action::UseHand) if self.is_drug(object) => drug = Some(object),
+                        _ => {}
                     }
                 }
                 Command::ListDrop { pos, object } => {
-                    self.handle_list_drop(cmd.source, pos, object, rpg, ui);
+                    drug = self.handle_list_drop(cmd.source, pos, object, rpg, ui);
                 }
                 Command::ToggleMouseMode => {
                     self.toggle_mouse_mode(rpg, ui);
@@ -778,6 +803,7 @@ impl Internal {
         if let Some(v) = self.move_window.as_mut() {
             v.win.handle(cmd, ui);
         }
+        drug
     }
 }
 
@@ -0,0 +1,571 @@
+use bstring::BString;
+use linearize::{static_map, Linearize, StaticMap};
+use sdl2::mouse::MouseButton;
+use std::cmp;
+
+use crate::asset::frame::FrameId;
+use crate::asset::message::{MessageId, Messages};
+use crate::asset::{EntityKind, Flag, ItemKind, Perk, Skill, Stat};
+use crate::fs::FileSystem;
+use crate::game::combat;
+use crate::game::object::{self, Object, Objects};
+use crate::game::rpg::Rpg;
+use crate::game::ui::inventory_list::{self, InventoryList, Scroll};
+use crate::game::ui::move_window::MoveWindow;
+use crate::game::world::WorldRef;
+use crate::graphics::{Point, Rect};
+use crate::graphics::sprite::{Anchor, Sprite};
+use crate::ui::button::{self, Button};
+use crate::ui::command::loot::Command;
+use crate::ui::command::{inventory, move_window, UiCommand, UiCommandData};
+use crate::ui::{self, Cursor, HandleEvent, Ui, Widget};
+use crate::util::random::{RollCheckResult, RollChecker};
+use crate::util::sprintf;
+
+/// You steal the %s.
+const MSG_STOLEN: MessageId = 570;
+/// You plant the %s.
+const MSG_PLANTED: MessageId = 571;
+/// You are caught stealing the %s.
+const MSG_CAUGHT_STEALING: MessageId = 572;
+/// You are caught planting the %s.
+const MSG_CAUGHT_PLANTING: MessageId = 573;
+
+/// Returns the penalty to the Steal skill roll.
+pub fn steal_penalty(item_size: i32, facing: bool, prone: bool, pickpocket: bool) -> i32 {
+    let mut r = 0;
+    if !pickpocket {
+        r += 4 * item_size;
+        if facing {
+            r += 25;
+        }
+    }
+    if prone {
+        r -= 20;
+    }
+    r
+}
+
+/// Rolls the attempt of the `thief` to steal or plant the `item`. Returns `true` if the thief
+/// isn't caught.
+// skill_check_stealing()
+pub fn roll_steal(
+    thief: &Object,
+    target: &Object,
+    item: &Object,
+    rpg: &Rpg,
+    roll_checker: RollChecker,
+    objs: &Objects,
+) -> bool {
+    let item_size = item.proto().unwrap().sub.as_item().unwrap().size;
+    let facing = target.kind() == EntityKind::Critter
+        && combat::is_facing(target.pos().point, target.direction, thief.pos().point);
+    let pickpocket = thief.is_dude() && rpg.has_perk(Perk::Pickpocket, thief.proto_id().unwrap());
+    let modifier = -steal_penalty(item_size, facing, target.is_critter_prone(), pickpocket);
+
+    let steal_chance = cmp::min(rpg.skill(Skill::Steal, thief, objs) + modifier, 95);
+    let crit_chance = rpg.stat(Stat::CritChance, thief, objs);
+    let catch_chance = if target.kind() == EntityKind::Critter {
+        rpg.skill(Skill::Steal, target, objs) - modifier
+    } else {
+        30 - modifier
+    };
+    let party_member = thief.is_dude() && rpg.is_party_member(target);
+
+    !is_caught(party_member,
+        || roll_checker.roll_check(steal_chance, crit_chance).0,
+        || roll_checker.roll_check(catch_chance, 0).0)
+}
+
+/// Unless the steal roll is critical the target gets a roll to catch the thief.
+fn is_caught(
+    auto_success: bool,
+    steal_roll: impl FnOnce() -> RollCheckResult,
+    catch_roll: impl FnOnce() -> RollCheckResult,
+) -> bool {
+    use RollCheckResult::*;
+
+    let steal = if auto_success { CriticalSuccess } else { steal_roll() };
+    match steal {
+        CriticalSuccess => false,
+        CriticalFailure => true,
+        Success | Failure => catch_roll().is_success(),
+    }
+}
+
+/// Result of an attempt to steal or plant an item.
+pub struct Steal {
+    pub target: object::Handle,
+    pub caught: bool,
+    pub msg: BString,
+}
+
+pub struct Loot {
+    msgs: Option<Messages>,
+    world: WorldRef,
+    internal: Option<Internal>,
+}
+
+impl Loot {
+    pub fn new(world: WorldRef, fs: &FileSystem, language: &str) -> Self {
+        let msgs = Some(Messages::read_file(fs, language, "game/inventry.msg").unwrap());
+        Self {
+            msgs,
+            world,
+            internal: None,
+        }
+    }
+
+    pub fn is_visible(&self) -> bool {
+        self.internal.is_some()
+    }
+
+    /// Shows the loot screen for transferring items between the dude and the `target`.
+    /// If the target is an active critter the items are stolen.
+    // loot_container(), inven_steal_container()
+    pub fn show(&mut self, target: object::Handle, ui: &mut Ui) {
+        let internal = Internal::new(self.msgs.take().unwrap(), self.world.clone(), target, ui);
+        internal.sync_to_ui(ui);
+        assert!(self.internal.replace(internal).is_none());
+    }
+
+    pub fn hide(&mut self, ui: &mut Ui) {
+        let i = self.internal.take().unwrap();
+        self.msgs = Some(i.hide(ui));
+    }
+
+    /// Returns the steal attempt result if any. If the thief is caught the loot screen is hidden.
+    pub fn handle(&mut self, cmd: UiCommand, rpg: &Rpg, ui: &mut Ui) -> Option<Steal> {
+        if cmd.data == UiCommandData::Loot(Command::Done) {
+            self.hide(ui);
+            return None;
+        }
+        let r = self.internal.as_mut()?.handle(cmd, rpg, ui);
+        if r.as_ref().is_some_and(|r| r.caught) {
+            self.hide(ui);
+        }
+        r
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, Linearize, PartialEq)]
+enum Side {
+    Dude,
+    Target,
+}
+
+impl Side {
+    fn other(self) -> Self {
+        match self {
+            Self::Dude => Self::Target,
+            Self::Target => Self::Dude,
+        }
+    }
+}
+
+struct Pane {
+    /// The owner followed by the opened nested containers.
+    stack: Vec<object::Handle>,
+    list: ui::Handle,
+    scroll_up: ui::Handle,
+    scroll_down: ui::Handle,
+    image: ui::Handle,
+}
+
+impl Pane {
+    fn owner(&self) -> object::Handle {
+        *self.stack.last().unwrap()
+    }
+}
+
+struct PendingMove {
+    src: Side,
+    object: object::Handle,
+    win: MoveWindow,
+}
+
+struct Internal {
+    msgs: Messages,
+    world: WorldRef,
+    win: ui::Handle,
+    panes: StaticMap<Side, Pane>,
+    steal: bool,
+    move_window: Option<PendingMove>,
+}
+
+impl Internal {
+    fn new(msgs: Messages, world: WorldRef, target: object::Handle, ui: &mut Ui) -> Self {
+        let (dude, steal) = {
+            let world = world.borrow();
+            let targeto = world.objects().get(target);
+            (world.objects().dude(), targeto.sub.as_critter().is_some_and(|c| c.is_active()))
+        };
+
+        let win = ui.new_window(Rect::with_size(80, 0, 537, 376),
+            Some(Sprite::new(FrameId::LOOT)));
+        ui.widget_base_mut(win).set_modal(true);
+        ui.widget_base_mut(win).set_cursor(Some(Cursor::Hand));
+
+        let mut new_pane = |side, owner, list_x, scroll_x, image_x| {
+            let mut scroll_up = Button::new(FrameId::INVENTORY_SCROLL_UP_UP,
+                FrameId::INVENTORY_SCROLL_UP_DOWN,
+                Some(UiCommandData::Inventory(inventory::Command::Scroll(Scroll::Up))));
+            scroll_up.config_mut(button::State::Disabled).background =
+                Some(Sprite::new(FrameId::INVENTORY_SCROLL_UP_DISABLED));
+            let scroll_up = ui.new_widget(win, Rect::with_size(scroll_x, 39, 22, 23),
+                None, None, scroll_up);
+
+            let mut scroll_down = Button::new(FrameId::INVENTORY_SCROLL_DOWN_UP,
+                FrameId::INVENTORY_SCROLL_DOWN_DOWN,
+                Some(UiCommandData::Inventory(inventory::Command::Scroll(Scroll::Down))));
+            scroll_down.config_mut(button::State::Disabled).background =
+                Some(Sprite::new(FrameId::INVENTORY_SCROLL_DOWN_DISABLED));
+            let scroll_down = ui.new_widget(win, Rect::with_size(scroll_x, 62, 22, 23),
+                None, None, scroll_down);
+
+            let mut image = Sprite::new(FrameId::BLANK);
+            image.anchor = Anchor::Center;
+            let image = ui.new_widget(win, Rect::with_size(image_x, 35, 60, 100), None,
+                Some(image), ContainerImage { side });
+
+            let list = ui.new_widget(win, Rect::with_size(list_x, 37, 56, 50 * 6), None, None,
+                InventoryList::new(40, 10));
+
+            Pane {
+                stack: vec![owner],
+                list,
+                scroll_up,
+                scroll_down,
+                image,
+            }
+        };
+        let panes = static_map! {
+            Side::Dude => new_pane(Side::Dude, dude, 176, 128, 44),
+            Side::Target => new_pane(Side::Target, target, 297, 379, 422),
+        };
+
+        if !steal {
+            ui.new_widget(win, Rect::with_size(432, 204, 39, 27), None, None,
+                Button::new(FrameId::INVMAUP, FrameId::INVMADN,
+                    Some(UiCommandData::Loot(Command::TakeAll))));
+        }
+        ui.new_widget(win, Rect::with_size(476, 331, 15, 16), None, None,
+            Button::new(FrameId::SMALL_RED_BUTTON_UP, FrameId::SMALL_RED_BUTTON_DOWN,
+                Some(UiCommandData::Loot(Command::Done))));
+
+        Self {
+            msgs,
+            world,
+            win,
+            panes,
+            steal,
+            move_window: None,
+        }
+    }
+
+    fn hide(self, ui: &mut Ui) -> Messages {
+        if let Some(m) = self.move_window {
+            m.win.hide(ui);
+        }
+        ui.remove(self.win);
+        self.msgs
+    }
+
+    fn side_of_list(&self, widget: ui::Handle) -> Option<Side> {
+        self.panes.iter().find(|(_, p)| p.list == widget).map(|(s, _)| s)
+    }
+
+    fn is_listed(&self, side: Side, item: &Object) -> bool {
+        // Equipped items of the dude and of the critter being robbed can't be moved.
+        !((side == Side::Dude || self.steal) && self.panes[side].stack.len() == 1
+            && item.flags.intersects(Flag::Worn | Flag::LeftHand | Flag::RightHand))
+    }
+
+    fn sync_to_ui(&self, ui: &Ui) {
+        let world = self.world.borrow();
+        let objs = world.objects();
+        for (side, pane) in self.panes.iter() {
+            let owner = objs.get(pane.owner());
+            let mut list = ui.widget_mut::<InventoryList>(pane.list);
+            let scroll_idx = list.scroll_idx();
+            list.clear();
+            for item in &owner.inventory.items {
+                let itemo = objs.get(item.object);
+                if !self.is_listed(side, &itemo) {
+                    continue;
+                }
+                list.push(inventory_list::Item {
+                    object: item.object,
+                    fid: itemo.proto().unwrap().sub.as_item().unwrap().inventory_fid.unwrap(),
+                    count: item.count,
+                });
+            }
+            list.set_scroll_idx(scroll_idx);
+            ui.widget_mut::<Button>(pane.scroll_up).set_enabled(list.can_scroll(Scroll::Up));
+            ui.widget_mut::<Button>(pane.scroll_down).set_enabled(list.can_scroll(Scroll::Down));
+
+            let fid = if pane.stack.len() > 1 {
+                owner.proto().unwrap().sub.as_item().unwrap().inventory_fid.unwrap()
+            } else {
+                owner.fid
+            };
+            ui.widget_base_mut(pane.image).background_mut().unwrap().fid = fid;
+        }
+    }
+
+    fn scroll(&self, button: ui::Handle, scroll: Scroll, ui: &Ui) {
+        let Some(pane) = self.panes.values()
+            .find(|p| p.scroll_up == button || p.scroll_down == button)
+            else { return };
+        let mut list = ui.widget_mut::<InventoryList>(pane.list);
+        list.scroll(scroll);
+        ui.widget_mut::<Button>(pane.scroll_up).set_enabled(list.can_scroll(Scroll::Up));
+        ui.widget_mut::<Button>(pane.scroll_down).set_enabled(list.can_scroll(Scroll::Down));
+    }
+
+    fn open_container(&mut self, side: Side, container: object::Handle, ui: &Ui) {
+        let is_container = self.world.borrow().objects().get(container).item_kind()
+            == Some(ItemKind::Container);
+        if is_container {
+            self.panes[side].stack.push(container);
+            ui.widget_mut::<InventoryList>(self.panes[side].list).clear();
+            self.sync_to_ui(ui);
+        }
+    }
+
+    fn close_container(&mut self, side: Side, ui: &Ui) {
+        if self.panes[side].stack.len() > 1 {
+            self.panes[side].stack.pop();
+            ui.widget_mut::<InventoryList>(self.panes[side].list).clear();
+            self.sync_to_ui(ui);
+        }
+    }
+
+    fn available_count(&self, side: Side, object: object::Handle) -> u32 {
+        let world = self.world.borrow();
+        world.objects().get(self.panes[side].owner()).inventory.items.iter()
+            .find(|i| i.object == object)
+            .map(|i| i.count)
+            .unwrap_or(0)
+    }
+
+    // move_inventory()
+    fn handle_list_drop(&mut self, src: ui::Handle, pos: Point,
+        object: object::Handle, rpg: &Rpg, ui: &mut Ui) -> Option<Steal>
+    {
+        let src = self.side_of_list(src)?;
+        let target = ui.widget_at(pos)?;
+        if target == self.panes[src].list {
+            // Clicking a container opens it.
+            self.open_container(src, object, ui);
+            return None;
+        }
+        if target != self.panes[src.other()].list && target != self.panes[src.other()].image {
+            return None;
+        }
+        let max = self.available_count(src, object);
+        if max > 1 {
+            let fid = {
+                let world = self.world.borrow();
+                let obj = world.objects().get(object);
+                obj.proto().unwrap().sub.as_item().unwrap().inventory_fid.unwrap()
+            };
+            let win = MoveWindow::show(fid, max, &self.msgs, ui);
+            assert!(self.move_window.replace(PendingMove { src, object, win }).is_none());
+            None
+        } else if max == 1 {
+            self.move_items(src, object, 1, rpg, ui)
+        } else {
+            None
+        }
+    }
+
+    fn move_items(&mut self, src: Side, object: object::Handle, count: u32, rpg: &Rpg,
+        ui: &Ui) -> Option<Steal>
+    {
+        let steal = if self.steal {
+            Some(self.roll_steal(src, object, rpg))
+        } else {
+            None
+        };
+        if steal.as_ref().is_none_or(|s| !s.caught) {
+            let mut world = self.world.borrow_mut();
+            let objs = world.objects_mut();
+            let item = objs.take_from_inventory(self.panes[src].owner(), object, count).unwrap();
+            objs.move_into_inventory(self.panes[src.other()].owner(), item, count);
+        }
+        self.sync_to_ui(ui);
+        steal
+    }
+
+    fn roll_steal(&self, src: Side, object: object::Handle, rpg: &Rpg) -> Steal {
+        let world = self.world.borrow();
+        let objs = world.objects();
+        let target = self.panes[Side::Target].stack[0];
+        let ok = roll_steal(&objs.dude_ref(), &objs.get(target), &objs.get(object), rpg,
+            world.game_time.roll_checker(), objs);
+        let msg_id = match (src, ok) {
+            (Side::Target, true) => MSG_STOLEN,
+            (Side::Dude, true) => MSG_PLANTED,
+            (Side::Target, false) => MSG_CAUGHT_STEALING,
+            (Side::Dude, false) => MSG_CAUGHT_PLANTING,
+        };
+        let msg = &rpg.skill_msgs().get(msg_id).unwrap().text;
+        let name = world.object_name(object).unwrap_or_default();
+        Steal {
+            target,
+            caught: !ok,
+            msg: sprintf(msg, &[&name]),
+        }
+    }
+
+    // loot_all()
+    fn take_all(&mut self, ui: &Ui) {
+        {
+            let mut world = self.world.borrow_mut();
+            let objs = world.objects_mut();
+            let from = self.panes[Side::Target].owner();
+            let to = self.panes[Side::Dude].owner();
+            let items: Vec<_> = objs.get(from).inventory.items.iter()
+                .map(|i| (i.object, i.count))
+                .collect();
+            for (item, count) in items {
+                let item = objs.take_from_inventory(from, item, count).unwrap();
+                objs.move_into_inventory(to, item, count);
+            }
+        }
+        self.sync_to_ui(ui);
+    }
+
+    fn handle(&mut self, cmd: UiCommand, rpg: &Rpg, ui: &mut Ui) -> Option<Steal> {
+        let mut r = None;
+        match cmd.data {
+            UiCommandData::Loot(Command::TakeAll) => self.take_all(ui),
+            UiCommandData::Loot(Command::Done) => {}
+            UiCommandData::Loot(Command::CloseContainer { dude }) => {
+                self.close_container(if dude { Side::Dude } else { Side::Target }, ui);
+            }
+            UiCommandData::Inventory(inventory::Command::Scroll(scroll)) => {
+                self.scroll(cmd.source, scroll, ui);
+            }
+            UiCommandData::Inventory(inventory::Command::ListDrop { pos, object }) => {
+                r = self.handle_list_drop(cmd.source, pos, object, rpg, ui);
+            }
+            UiCommandData::MoveWindow(move_window::Command::Hide { ok }) => {
+                if let Some(m) = self.move_window.take() {
+                    let count = m.win.value();
+                    m.win.hide(ui);
+                    if ok {
+                        return self.move_items(m.src, m.object, count, rpg, ui);
+                    }
+                }
+            }
+            _ => {}
+        }
+        if let Some(m) = self.move_window.as_mut() {
+            m.win.handle(cmd, ui);
+        }
+        r
+    }
+}
+
+/// Image of the pane owner. Clicking it closes the opened nested container.
+struct ContainerImage {
+    side: Side,
+}
+
+impl Widget for ContainerImage {
+    fn handle_event(&mut self, mut ctx: HandleEvent) {
+        if let ui::Event::MouseUp { button: MouseButton::Left, .. } = ctx.event {
+            ctx.out(UiCommandData::Loot(Command::CloseContainer {
+                dude: self.side == Side::Dude,
+            }));
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::graphics::geometry::hex::TileGrid;
+    use crate::util::random;
+    use crate::util::test::Assets;
+
+    #[test]
+    fn steal_penalty_() {
+        assert_eq!(steal_penalty(1, false, false, false), 4);
+        assert_eq!(steal_penalty(3, true, false, false), 37);
+        assert_eq!(steal_penalty(3, true, true, false), 17);
+        assert_eq!(steal_penalty(3, true, false, true), 0);
+        assert_eq!(steal_penalty(3, false, true, true), -20);
+    }
+
+    #[test]
+    fn is_caught_() {
+        use RollCheckResult::*;
+
+        let no_roll = || -> RollCheckResult { unreachable!() };
+        assert!(!is_caught(true, no_roll, no_roll));
+        assert!(!is_caught(false, || CriticalSuccess, no_roll));
+        assert!(is_caught(false, || CriticalFailure, no_roll));
+        for steal in [Success, Failure] {
+            assert!(is_caught(false, || steal, || Success));
+            assert!(is_caught(false, || steal, || CriticalSuccess));
+            assert!(!is_caught(false, || steal, || Failure));
+            assert!(!is_caught(false, || steal, || CriticalFailure));
+        }
+    }
+
+    #[test]
+    fn roll_steal_() {
+        let assets = Assets::new();
+        let mut rpg = assets.rpg();
+        let mut objs = Objects::new(TileGrid::default(), 3, assets.frm_db.clone(),
+            assets.proto_db.clone());
+        let dude = objs.create(None, Some(assets.proto_db.dude()),
+            Some(Point::new(12, 20).elevated(0)), Some(&rpg)).handle();
+        let mut create = |pid, x| {
+            let proto = assets.proto_db.proto(Assets::pid(pid)).unwrap();
+            objs.create(None, Some(proto), Some(Point::new(x, 20).elevated(0)), Some(&rpg)).handle()
+        };
+        let critter = create(Assets::CRITTER, 13);
+        let container = create(Assets::CONTAINER, 11);
+        let item = create(Assets::MISC_ITEM, 14);
+        objs.get(dude).proto_mut().unwrap().sub.as_critter_mut().unwrap().skills[Skill::Steal] = 150;
+
+        let checker = RollChecker::new(false);
+        let check = |target: object::Handle, rpg: &Rpg, expected_catch_chance: i32| {
+            let (dude, target, item) = (&objs.get(dude), &objs.get(target), &objs.get(item));
+            let item_size = item.proto().unwrap().sub.as_item().unwrap().size;
+            let facing = target.kind() == EntityKind::Critter
+                && combat::is_facing(target.pos().point, target.direction, dude.pos().point);
+            let penalty = steal_penalty(item_size, facing, false, false);
+            assert!(rpg.skill(Skill::Steal, dude, &objs) - penalty > 95);
+            let crit_chance = rpg.stat(Stat::CritChance, dude, &objs);
+            let catch_chance = expected_catch_chance + penalty;
+
+            let mut caught_count = 0;
+            for seed in 0..200 {
+                random::set_seed(seed);
+                let expected = is_caught(false,
+                    || checker.roll_check(95, crit_chance).0,
+                    || checker.roll_check(catch_chance, 0).0);
+                random::set_seed(seed);
+                assert_eq!(roll_steal(dude, target, item, rpg, checker, &objs), !expected);
+                caught_count += expected as u32;
+            }
+            assert!(caught_count > 0 && caught_count < 200);
+        };
+        let target_skill = rpg.skill(Skill::Steal, &objs.get(critter), &objs);
+        check(critter, &rpg, target_skill);
+        check(container, &rpg, 30);
+
+        // Stealing from a party member always succeeds.
+        rpg.add_party_member(Assets::pid(Assets::CRITTER));
+        for seed in 0..50 {
+            random::set_seed(seed);
+            assert!(roll_steal(&objs.get(dude), &objs.get(critter), &objs.get(item), &rpg,
+                checker, &objs));
+        }
+    }
+}
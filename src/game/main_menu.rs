@@ -0,0 +1,94 @@
+use std::rc::Rc;
+
+use crate::asset::frame::FrameId;
+use crate::asset::message::{MessageId, Messages};
+use crate::graphics::Rect;
+use crate::graphics::color::Rgb15;
+use crate::graphics::font::{DrawOptions, FontKey, HorzAlign};
+use crate::graphics::sprite::Sprite;
+use crate::ui::*;
+use crate::ui::button::Button;
+use crate::ui::command::UiCommandData;
+use crate::ui::command::main_menu::Command;
+use crate::ui::panel::{self, Panel};
+
+const MSG_BUTTON_BASE: MessageId = 9;
+
+const BUTTONS: [Command; 6] = [
+    Command::Intro,
+    Command::NewGame,
+    Command::LoadGame,
+    Command::Options,
+    Command::Credits,
+    Command::Exit,
+];
+
+const BUTTON_X: i32 = 30;
+const BUTTON_Y: i32 = 19;
+const BUTTON_STEP: i32 = 41;
+const LABEL_CENTER_X: i32 = 126;
+
+const LABEL_FONT: FontKey = FontKey::antialiased(4);
+const LABEL_COLOR: Rgb15 = unsafe { Rgb15::rgb15_from_packed_unchecked(0x5263) };
+
+pub struct MainMenu {
+    misc_msgs: Rc<Messages>,
+    window: Option<Handle>,
+}
+
+impl MainMenu {
+    pub fn new(misc_msgs: Rc<Messages>) -> Self {
+        Self {
+            misc_msgs,
+            window: None,
+        }
+    }
+
+    pub fn is_visible(&self) -> bool {
+        self.window.is_some()
+    }
+
+    // main_menu_create()
+    pub fn show(&mut self, ui: &mut Ui) {
+        if self.window.is_some() {
+            return;
+        }
+
+        let window = ui.new_window(Rect::with_size(0, 0, 640, 480),
+            Some(Sprite::new(FrameId::MAINMENU)));
+        ui.widget_base_mut(window).set_modal(true);
+
+        for (i, &cmd) in BUTTONS.iter().enumerate() {
+            let y = BUTTON_Y + i as i32 * BUTTON_STEP;
+            ui.new_widget(window, Rect::with_size(BUTTON_X, y, 26, 26), None, None,
+                Button::new(FrameId::MENUUP, FrameId::MENUDOWN,
+                    Some(UiCommandData::MainMenu(cmd))));
+
+            let text = self.misc_msgs.get(MSG_BUTTON_BASE + i as MessageId)
+                .map(|m| m.text.clone())
+                .unwrap_or_default();
+            let mut label = Panel::new();
+            label.set_text(Some(panel::Text {
+                text,
+                font: LABEL_FONT,
+                color: LABEL_COLOR,
+                options: DrawOptions {
+                    horz_align: HorzAlign::Center,
+                    ..Default::default()
+                },
+            }));
+            let width = 2 * (LABEL_CENTER_X - BUTTON_X - 26);
+            ui.new_widget(window, Rect::with_size(LABEL_CENTER_X - width / 2, y + 1, width, 26),
+                None, None, label);
+        }
+
+        self.window = Some(window);
+    }
+
+    // main_menu_destroy()
+    pub fn hide(&mut self, ui: &mut Ui) {
+        if let Some(window) = self.window.take() {
+            ui.remove(window);
+        }
+    }
+}
@@ -0,0 +1,176 @@
+use log::*;
+use std::collections::VecDeque;
+use std::io::prelude::*;
+use std::rc::Rc;
+
+use crate::asset::movie::MveDecoder;
+use crate::fs::FileSystem;
+use crate::graphics::Rect;
+use crate::sound::Sound;
+use crate::ui::{self, Cursor, Ui};
+use crate::ui::movie::Movie;
+
+// gmovie_list
+const GAME_MOVIES: [&str; 17] = [
+    "iplogo",
+    "intro",
+    "elder",
+    "vsuit",
+    "afailed",
+    "adestroy",
+    "car",
+    "cartucci",
+    "timeout",
+    "tanker",
+    "enclave",
+    "derrick",
+    "artimer1",
+    "artimer2",
+    "artimer3",
+    "artimer4",
+    "credits",
+];
+
+pub const IPLOGO_MOVIE: u32 = 0;
+pub const INTRO_MOVIE: u32 = 1;
+pub const ELDER_MOVIE: u32 = 2;
+pub const CREDITS_MOVIE: u32 = 16;
+
+/// Returns path of the game movie as used by `play_gmovie` script instruction.
+pub fn game_movie_path(id: u32) -> Option<String> {
+    GAME_MOVIES.get(id as usize).map(|n| format!("art/cuts/{}.mve", n))
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+struct Request {
+    path: String,
+    rect: Option<Rect>,
+}
+
+struct Playing {
+    window: ui::Handle,
+    prev_keyboard_focus: Option<ui::Handle>,
+}
+
+/// Plays the requested movies one after another in a modal window covering the screen.
+/// Game time and the background music are paused while movies are playing.
+pub struct Movies {
+    fs: Rc<FileSystem>,
+    queue: VecDeque<Request>,
+    playing: Option<Playing>,
+    flags: u32,
+    /// Game movies played so far. They can be replayed from the Pip-Boy archives.
+    seen: [bool; GAME_MOVIES.len()],
+}
+
+impl Movies {
+    pub fn new(fs: Rc<FileSystem>) -> Self {
+        Self {
+            fs,
+            queue: VecDeque::new(),
+            playing: None,
+            flags: 0,
+            seen: [false; GAME_MOVIES.len()],
+        }
+    }
+
+    /// Queues the movie at `path`. If `rect` is not `None` the movie is scaled to it, otherwise
+    /// it's scaled to the whole screen.
+    pub fn play(&mut self, path: impl Into<String>, rect: Option<Rect>) {
+        self.queue.push_back(Request {
+            path: path.into(),
+            rect,
+        });
+    }
+
+    // gmovie_play()
+    /// Returns `false` if there's no game movie with such `id`.
+    pub fn play_game_movie(&mut self, id: u32) -> bool {
+        if let Some(path) = game_movie_path(id) {
+            self.play(path, None);
+            self.seen[id as usize] = true;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Returns IDs of the game movies that were played.
+    pub fn seen_game_movies(&self) -> impl Iterator<Item=u32> + '_ {
+        (0..self.seen.len() as u32).filter(|&id| self.seen[id as usize])
+    }
+
+    /// Stops the current movie and discards the queued ones.
+    pub fn stop(&mut self, ui: &mut Ui, sound: &mut Sound) {
+        self.queue.clear();
+        self.hide(ui, sound);
+    }
+
+    pub fn is_playing(&self) -> bool {
+        self.playing.is_some() || !self.queue.is_empty()
+    }
+
+    pub fn flags(&self) -> u32 {
+        self.flags
+    }
+
+    /// Flags set by the `movieflags` script instruction. Currently they're only stored.
+    pub fn set_flags(&mut self, flags: u32) {
+        self.flags = flags;
+    }
+
+    /// Should be called when the `UiCommandData::MovieDone` command is received.
+    pub fn hide(&mut self, ui: &mut Ui, sound: &mut Sound) {
+        if let Some(playing) = self.playing.take() {
+            ui.remove(playing.window);
+            ui.set_keyboard_focus(playing.prev_keyboard_focus);
+            if self.queue.is_empty() {
+                sound.set_music_paused(false);
+            }
+        }
+    }
+
+    /// Starts the next queued movie if nothing is playing.
+    pub fn update(&mut self, ui: &mut Ui, sound: &mut Sound) {
+        while self.playing.is_none() {
+            let req = if let Some(v) = self.queue.pop_front() {
+                v
+            } else {
+                break;
+            };
+            let decoder = self.fs.reader(&req.path)
+                .and_then(|rd| MveDecoder::new(Box::new(rd) as Box<dyn Read>));
+            let decoder = match decoder {
+                Ok(v) => v,
+                Err(e) => {
+                    warn!("couldn't play movie `{}`: {}", req.path, e);
+                    if self.queue.is_empty() {
+                        sound.set_music_paused(false);
+                    }
+                    continue;
+                }
+            };
+
+            sound.set_music_paused(true);
+
+            let screen = Rect::with_size(0, 0, 640, 480);
+            let window = ui.new_window(screen, None);
+            {
+                let mut base = ui.widget_base_mut(window);
+                base.set_modal(true);
+                base.set_cursor(Some(Cursor::Hidden));
+            }
+            let movie = ui.new_widget(window, req.rect.unwrap_or(screen), Some(Cursor::Hidden),
+                None, Movie::new(decoder, ui.frm_db().texture_factory().clone(),
+                    sound.mixer().clone()));
+            ui.widget_base_mut(movie).set_listener(true);
+            let prev_keyboard_focus = ui.keyboard_focus();
+            ui.set_keyboard_focus(Some(movie));
+
+            self.playing = Some(Playing {
+                window,
+                prev_keyboard_focus,
+            });
+        }
+    }
+}
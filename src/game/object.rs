@@ -68,6 +68,21 @@ impl Inventory {
 
         // See https://trello.com/c/ksAC8gWn
     }
+
+    // item_total_cost
+    pub fn cost(&self, objects: &Objects) -> i32 {
+        self.items.iter()
+            .map(|item| objects.get(item.object).stack_cost(item.count, objects).unwrap())
+            .sum()
+    }
+
+    // item_caps_total
+    pub fn caps(&self, objects: &Objects) -> u32 {
+        self.items.iter()
+            .filter(|item| objects.get(item.object).proto_id() == Some(ProtoId::BOTTLE_CAPS))
+            .map(|item| item.count)
+            .sum()
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -204,6 +219,11 @@ impl Object {
         self.proto_ref().map(|v| v.borrow_mut())
     }
 
+    /// Replaces the proto. Used when a party member levels up.
+    pub fn set_proto(&mut self, proto: ProtoRef) {
+        self.proto = Some(proto);
+    }
+
     pub fn proto_id(&self) -> Option<ProtoId> {
         self.proto().map(|v| v.id())
     }
@@ -281,8 +301,7 @@ impl Object {
     // critter_is_dead()
     #[must_use]
     pub fn is_critter_dead(&self) -> bool {
-        // FIXME
-        false
+        self.sub.as_critter().is_some_and(|c| c.is_dead())
     }
 
     // critter_is_prone()
@@ -463,6 +482,53 @@ impl Object {
         Some(weight)
     }
 
+    // item_cost
+    #[must_use]
+    pub fn item_cost(&self, objects: &Objects) -> Option<i32> {
+        let proto = self.proto()?;
+        let item = proto.sub.as_item()?;
+        let mut cost = item.price;
+        match &item.sub {
+            SubItem::Container(_) => cost += self.inventory.cost(objects),
+            SubItem::Weapon(_) => {
+                let item_obj = self.sub.as_item().unwrap();
+                if item_obj.ammo_count > 0 && let Some(ammo) = item_obj.ammo_proto.as_ref() {
+                    let ammo = ammo.borrow();
+                    let max_ammo_count = ammo.sub.as_ammo().unwrap().max_ammo_count;
+                    if max_ammo_count > 0 {
+                        cost += item_obj.ammo_count as i32 * ammo.sub.as_item().unwrap().price
+                            / max_ammo_count as i32;
+                    }
+                }
+            }
+            SubItem::Ammo(ammo) => {
+                // Partially used clip costs proportionally less.
+                let ammo_count = self.sub.as_item().unwrap().ammo_count;
+                cost = if ammo.max_ammo_count > 0 {
+                    cost * ammo_count as i32 / ammo.max_ammo_count as i32
+                } else {
+                    0
+                };
+            }
+            _ => {}
+        }
+        Some(cost)
+    }
+
+    /// Cost of `count` items stacked with this one. For ammo only the top clip can be partially
+    /// used.
+    #[must_use]
+    pub fn stack_cost(&self, count: u32, objects: &Objects) -> Option<i32> {
+        let cost = self.item_cost(objects)?;
+        Some(if count == 0 {
+            0
+        } else if self.item_kind() == Some(ItemKind::Ammo) {
+            cost + self.proto().unwrap().sub.as_item().unwrap().price * (count - 1) as i32
+        } else {
+            cost * count as i32
+        })
+    }
+
     // inven_left_hand
     // inven_right_hand
     // inven_worn
@@ -669,7 +735,7 @@ impl Object {
             .or(dude.map(|d| d.naked_fidx))
             .unwrap_or(self.fid.idx());
 
-        let active_hand = dude.map(|d| d.active_hand).unwrap_or(Hand::Left);
+        let active_hand = dude.map(|d| d.active_hand).unwrap_or(Hand::Right);
         let weapon = self.equipment(EquipmentSlot::Hand(active_hand), objects)
             .and_then(|item| {
                 objects.get(item).proto().unwrap().sub
@@ -940,6 +1006,7 @@ impl Objects {
                             ai_packet: p.ai_packet,
                             team_id: p.team_id,
                             who_hit_me: 0,
+                            fleeing: false,
                         },
                         dude: None,
                     })
@@ -1417,10 +1484,23 @@ impl Objects {
     // combat_is_shot_blocked()
     #[must_use]
     pub fn is_shot_blocked(&self, shooter: Handle, target: Handle) -> bool {
+        self.line_of_fire(shooter, target).0
+    }
+
+    /// Number of critters standing in the line of fire between `shooter` and `target`.
+    #[must_use]
+    pub fn shot_blockers_count(&self, shooter: Handle, target: Handle) -> i32 {
+        self.line_of_fire(shooter, target).1
+    }
+
+    /// Returns whether the shot is blocked by a non-critter object and the number of critters
+    /// on the way.
+    fn line_of_fire(&self, shooter: Handle, target: Handle) -> (bool, i32) {
         let pos = self.get(shooter).pos();
         let target_pos = self.get(target).pos();
         assert_eq!(pos.elevation, target_pos.elevation);
         let mut last_blocker = None;
+        let mut critters = 0;
         for p in hex::ray(pos.point, target_pos.point) {
             let blocker = self.shot_blocker_at(shooter, p.elevated(pos.elevation));
 
@@ -1430,8 +1510,9 @@ impl Objects {
                 if blocker != shooter && blocker != target {
                     let o = self.get(blocker);
                     if o.kind() != EntityKind::Critter {
-                        return true;
+                        return (true, critters);
                     }
+                    critters += 1;
                 }
                 last_blocker = Some(blocker);
             }
@@ -1439,7 +1520,7 @@ impl Objects {
                 break;
             }
         }
-        false
+        (false, critters)
     }
 
     /// Based on spatial information are the objects able to talk?
@@ -1687,6 +1768,59 @@ impl Objects {
         self.set_pos(item, None);
     }
 
+    /// Returns the object having `item` in its inventory.
+    pub fn inventory_owner(&self, item: Handle) -> Option<Handle> {
+        self.iter().find(|&h| self.get(h).inventory.items.iter().any(|i| i.object == item))
+    }
+
+    // item_remove_mult
+    /// Removes `count` of the `item` from the inventory. Returns the number of items left or
+    /// `None` if there's not enough items. When no items are left the inventory entry is removed
+    /// but the item object itself is kept.
+    pub fn remove_from_inventory(&mut self, inventory: Handle, item: Handle, count: u32)
+        -> Option<u32>
+    {
+        let mut inventory = self.get_mut(inventory);
+        let idx = inventory.inventory.items.iter().position(|i| i.object == item)?;
+        let entry = &mut inventory.inventory.items[idx];
+        entry.count = entry.count.checked_sub(count)?;
+        let left = entry.count;
+        if left == 0 {
+            inventory.inventory.items.remove(idx);
+            self.get_mut(item).flags.remove(Flag::Worn | Flag::LeftHand | Flag::RightHand);
+        }
+        Some(left)
+    }
+
+    /// Takes `count` of the `item` out of the inventory. Returns the object holding the taken
+    /// items: the `item` itself if the whole stack is taken or a new copy otherwise.
+    /// Returns `None` if there's not enough items.
+    pub fn take_from_inventory(&mut self, inventory: Handle, item: Handle, count: u32)
+        -> Option<Handle>
+    {
+        if self.remove_from_inventory(inventory, item, count)? == 0 {
+            return Some(item);
+        }
+        let proto = self.get(item).proto.clone();
+        Some(self.create(None, proto, None, None).handle())
+    }
+
+    /// Moves all items from the inventory of `owner` onto the map at its position. The items
+    /// of a stack are dropped one by one.
+    // item_drop_all()
+    pub fn drop_inventory(&mut self, owner: Handle) {
+        let pos = self.get(owner).pos();
+        let items: Vec<_> = self.get(owner).inventory.items.iter()
+            .map(|i| (i.object, i.count))
+            .collect();
+        for (item, count) in items {
+            for _ in 0..count {
+                let item = self.take_from_inventory(owner, item, 1).unwrap();
+                self.set_pos(item, Some(pos));
+            }
+        }
+    }
+
     // item_w_unload
     pub fn unload_weapon(&mut self, weapon: Handle) -> Option<Handle> {
         let (ammo_proto, count) = {
@@ -1987,6 +2121,8 @@ pub struct CritterCombat {
     pub ai_packet: i32,
     pub team_id: i32,
     pub who_hit_me: i32,
+    /// Set when the critter runs away from combat. Cleared when the combat is over.
+    pub fleeing: bool,
 }
 
 #[bitflags]
@@ -2062,6 +2198,7 @@ pub struct Key {
 mod test {
     use super::*;
     use crate::graphics::geometry::hex::View;
+    use crate::util::test::Assets;
 
     #[test]
     fn bounds() {
@@ -2074,4 +2211,25 @@ mod test {
             Rect::with_points(Point::new(1, -51), Point::new(30, 12))
                 .translate(base));
     }
+
+    #[test]
+    fn drop_inventory() {
+        let assets = Assets::new();
+        let rpg = assets.rpg();
+        let mut objs = Objects::new(TileGrid::default(), 3, assets.frm_db.clone(),
+            assets.proto_db.clone());
+        let pos = Point::new(12, 34).elevated(1);
+        let proto = |pid| Some(assets.proto_db.proto(Assets::pid(pid)).unwrap());
+        let critter = objs.create(None, proto(Assets::CRITTER), Some(pos), Some(&rpg)).handle();
+        let item = objs.create(None, proto(Assets::MISC_ITEM), None, None).handle();
+        objs.move_into_inventory(critter, item, 2);
+
+        objs.drop_inventory(critter);
+
+        assert!(objs.get(critter).inventory.items.is_empty());
+        let dropped: Vec<_> = objs.at(pos).iter()
+            .filter(|&&h| objs.get(h).proto_id() == Some(Assets::pid(Assets::MISC_ITEM)))
+            .collect();
+        assert_eq!(dropped.len(), 2);
+    }
 }
@@ -0,0 +1,386 @@
+use bstring::BString;
+use std::cmp;
+use std::rc::Rc;
+use std::time::Duration;
+
+use crate::asset::frame::FrameId;
+use crate::asset::message::{MessageId, Messages};
+use crate::game::combat::Difficulty;
+use crate::graphics::{Point, Rect};
+use crate::graphics::color::{Rgb15, GREEN};
+use crate::graphics::font::{DrawOptions, FontKey, HorzAlign};
+use crate::graphics::sprite::Sprite;
+use crate::ui::*;
+use crate::ui::button::{self, Button};
+use crate::ui::command::UiCommandData;
+use crate::ui::command::options::Command;
+use crate::ui::panel::{self, Panel};
+use crate::ui::slider::Slider;
+
+const MSG_MENU_BASE: MessageId = 0;
+const MSG_GAME_DIFFICULTY: MessageId = 100;
+const MSG_COMBAT_DIFFICULTY: MessageId = 101;
+const MSG_COMBAT_SPEED: MessageId = 105;
+const MSG_TEXT_DELAY: MessageId = 106;
+const MSG_BRIGHTNESS: MessageId = 109;
+const MSG_DIFFICULTY_BASE: MessageId = 203;
+const MSG_DEFAULT: MessageId = 300;
+const MSG_DONE: MessageId = 301;
+const MSG_CANCEL: MessageId = 302;
+
+const MENU_COMMANDS: [Command; 5] = [
+    Command::SaveGame,
+    Command::LoadGame,
+    Command::Preferences,
+    Command::ExitGame,
+    Command::Done,
+];
+
+const MENU_FONT: FontKey = FontKey::antialiased(3);
+const MENU_TEXT_COLOR: Rgb15 = unsafe { Rgb15::rgb15_from_packed_unchecked(0x4a23) };
+const LABEL_FONT: FontKey = FontKey::antialiased(1);
+const TITLE_FONT: FontKey = FontKey::antialiased(2);
+
+const COMBAT_SPEED_MAX: u32 = 50;
+const TEXT_DELAY_MIN: Duration = Duration::from_millis(1_000);
+const TEXT_DELAY_MAX: Duration = Duration::from_millis(6_000);
+const BRIGHTNESS_STEPS: i32 = 18;
+const BRIGHTNESS_MAX: f64 = 1.18;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Pref {
+    GameDifficulty,
+    CombatDifficulty,
+    CombatSpeed,
+    TextDelay,
+    Brightness,
+}
+
+/// Game preferences adjustable in the preferences screen.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Preferences {
+    pub game_difficulty: Difficulty,
+    pub combat_difficulty: Difficulty,
+    /// Frames per second added to the animations during combat, `0..=50`.
+    pub combat_speed: u32,
+    /// Base time the floating texts are shown for.
+    pub text_delay: Duration,
+    /// Palette gamma, `1.0..=1.18`.
+    pub brightness: f64,
+}
+
+impl Default for Preferences {
+    fn default() -> Self {
+        Self {
+            game_difficulty: Difficulty::Normal,
+            combat_difficulty: Difficulty::Normal,
+            combat_speed: 0,
+            text_delay: Duration::from_millis(3_500),
+            brightness: 1.0,
+        }
+    }
+}
+
+impl Preferences {
+    /// Returns the preference as the knob position or slider value.
+    pub fn get(&self, pref: Pref) -> i32 {
+        match pref {
+            Pref::GameDifficulty => self.game_difficulty as i32,
+            Pref::CombatDifficulty => self.combat_difficulty as i32,
+            Pref::CombatSpeed => self.combat_speed as i32,
+            // Slider goes from slow to fast.
+            Pref::TextDelay => (TEXT_DELAY_MAX + TEXT_DELAY_MIN - self.text_delay).as_millis() as i32 / 100,
+            Pref::Brightness => ((self.brightness - 1.0) / (BRIGHTNESS_MAX - 1.0)
+                * BRIGHTNESS_STEPS as f64).round() as i32,
+        }
+    }
+
+    /// Sets the preference from the knob position or slider value clamping it to the valid range.
+    pub fn set(&mut self, pref: Pref, value: i32) {
+        let (min, max) = Self::range(pref);
+        let value = cmp::min(cmp::max(value, min), max);
+        match pref {
+            Pref::GameDifficulty => self.game_difficulty = difficulty(value),
+            Pref::CombatDifficulty => self.combat_difficulty = difficulty(value),
+            Pref::CombatSpeed => self.combat_speed = value as u32,
+            Pref::TextDelay => self.text_delay = TEXT_DELAY_MAX + TEXT_DELAY_MIN
+                - Duration::from_millis(value as u64 * 100),
+            Pref::Brightness => self.brightness = 1.0
+                + (BRIGHTNESS_MAX - 1.0) * value as f64 / BRIGHTNESS_STEPS as f64,
+        }
+    }
+
+    /// Switches the knob to the next position wrapping around.
+    pub fn cycle(&mut self, pref: Pref) {
+        let (min, max) = Self::range(pref);
+        let v = self.get(pref) + 1;
+        self.set(pref, if v > max { min } else { v });
+    }
+
+    pub fn range(pref: Pref) -> (i32, i32) {
+        match pref {
+            Pref::GameDifficulty | Pref::CombatDifficulty =>
+                (Difficulty::Easy as i32, Difficulty::Hard as i32),
+            Pref::CombatSpeed => (0, COMBAT_SPEED_MAX as i32),
+            Pref::TextDelay => (TEXT_DELAY_MIN.as_millis() as i32 / 100,
+                TEXT_DELAY_MAX.as_millis() as i32 / 100),
+            Pref::Brightness => (0, BRIGHTNESS_STEPS),
+        }
+    }
+}
+
+fn difficulty(v: i32) -> Difficulty {
+    match v {
+        0 => Difficulty::Easy,
+        1 => Difficulty::Normal,
+        _ => Difficulty::Hard,
+    }
+}
+
+struct PrefsScreen {
+    window: Handle,
+    /// Value labels of the knobs.
+    knobs: Vec<(Pref, Handle)>,
+    sliders: Vec<(Pref, Handle)>,
+    /// Preferences to restore on cancel.
+    saved: Preferences,
+}
+
+/// Options menu shown over the game and the preferences screen.
+pub struct Options {
+    msgs: Rc<Messages>,
+    prefs: Preferences,
+    menu: Option<Handle>,
+    prefs_screen: Option<PrefsScreen>,
+}
+
+impl Options {
+    pub fn new(msgs: Rc<Messages>) -> Self {
+        Self {
+            msgs,
+            prefs: Preferences::default(),
+            menu: None,
+            prefs_screen: None,
+        }
+    }
+
+    pub fn prefs(&self) -> &Preferences {
+        &self.prefs
+    }
+
+    pub fn is_menu_visible(&self) -> bool {
+        self.menu.is_some()
+    }
+
+    pub fn is_prefs_visible(&self) -> bool {
+        self.prefs_screen.is_some()
+    }
+
+    // do_optionsFunc()
+    pub fn show_menu(&mut self, ui: &mut Ui) {
+        if self.menu.is_some() {
+            return;
+        }
+
+        let size = ui.frm_db().get(FrameId::OPBASE).unwrap().first().size();
+        let window = ui.new_window(Rect::with_size((640 - size.x) / 2, (380 - size.y) / 2,
+            size.x, size.y), Some(Sprite::new(FrameId::OPBASE)));
+        ui.widget_base_mut(window).set_modal(true);
+
+        let button_size = ui.frm_db().get(FrameId::OPBTNOFF).unwrap().first().size();
+        for (i, &cmd) in MENU_COMMANDS.iter().enumerate() {
+            let mut button = Button::new(FrameId::OPBTNOFF, FrameId::OPBTNON,
+                Some(UiCommandData::Options(cmd)));
+            button.set_text(Some(button::Text {
+                pos: Point::new(0, 1),
+                text: self.msg(MSG_MENU_BASE + i as MessageId),
+                font: MENU_FONT,
+                color: MENU_TEXT_COLOR,
+                options: DrawOptions {
+                    horz_align: HorzAlign::Center,
+                    ..Default::default()
+                },
+            }));
+            ui.new_widget(window, Rect::with_size(13, 17 + 37 * i as i32,
+                button_size.x, button_size.y), None, None, button);
+        }
+
+        self.menu = Some(window);
+    }
+
+    pub fn hide_menu(&mut self, ui: &mut Ui) {
+        if let Some(window) = self.menu.take() {
+            ui.remove(window);
+        }
+    }
+
+    // do_prefscreen()
+    pub fn show_prefs(&mut self, ui: &mut Ui) {
+        if self.prefs_screen.is_some() {
+            return;
+        }
+
+        let size = ui.frm_db().get(FrameId::PREFSCRN).unwrap().first().size();
+        let window = ui.new_window(Rect::with_size((640 - size.x) / 2, (480 - size.y) / 2,
+            size.x, size.y), Some(Sprite::new(FrameId::PREFSCRN)));
+        ui.widget_base_mut(window).set_modal(true);
+
+        let mut knobs = Vec::new();
+        for (pref, pos, msg) in [
+            (Pref::GameDifficulty, Point::new(76, 71), MSG_GAME_DIFFICULTY),
+            (Pref::CombatDifficulty, Point::new(76, 149), MSG_COMBAT_DIFFICULTY),
+        ] {
+            self.new_label(ui, window, Rect::with_size(pos.x - 40, pos.y - 30, 126, 20), msg,
+                TITLE_FONT);
+            ui.new_widget(window, Rect::with_size(pos.x, pos.y, 46, 47), None, None,
+                Button::new(FrameId::PRFBKNBS, FrameId::PRFBKNBS,
+                    Some(UiCommandData::Options(Command::Cycle(pref)))));
+            let value = ui.new_widget(window,
+                Rect::with_size(pos.x - 40, pos.y + 50, 126, 16), None, None, Panel::new());
+            knobs.push((pref, value));
+        }
+
+        let mut sliders = Vec::new();
+        for (pref, y, msg) in [
+            (Pref::CombatSpeed, 50, MSG_COMBAT_SPEED),
+            (Pref::TextDelay, 125, MSG_TEXT_DELAY),
+            (Pref::Brightness, 196, MSG_BRIGHTNESS),
+        ] {
+            self.new_label(ui, window, Rect::with_size(384, y - 30, 236, 20), msg, TITLE_FONT);
+            let (min, max) = Preferences::range(pref);
+            let command: fn(i32) -> UiCommandData = match pref {
+                Pref::CombatSpeed => |value| UiCommandData::Options(
+                    Command::Set { pref: Pref::CombatSpeed, value }),
+                Pref::TextDelay => |value| UiCommandData::Options(
+                    Command::Set { pref: Pref::TextDelay, value }),
+                Pref::Brightness => |value| UiCommandData::Options(
+                    Command::Set { pref: Pref::Brightness, value }),
+                Pref::GameDifficulty | Pref::CombatDifficulty => unreachable!(),
+            };
+            let slider = Slider::new(ui.frm_db(), FrameId::PRFSLDOF, FrameId::PRFSLDON,
+                min, max, command);
+            let h = ui.new_widget(window, Rect::with_size(384, y, 236, 14), None, None, slider);
+            sliders.push((pref, h));
+        }
+
+        for (x, cmd, msg) in [
+            (23, Command::PrefsDefault, MSG_DEFAULT),
+            (148, Command::PrefsDone, MSG_DONE),
+            (263, Command::PrefsCancel, MSG_CANCEL),
+        ] {
+            let y = size.y - 30;
+            ui.new_widget(window, Rect::with_size(x, y, 15, 16), None, None,
+                Button::new(FrameId::SMALL_RED_BUTTON_UP, FrameId::SMALL_RED_BUTTON_DOWN,
+                    Some(UiCommandData::Options(cmd))));
+            let mut label = Panel::new();
+            label.set_text(Some(panel::Text {
+                text: self.msg(msg),
+                font: MENU_FONT,
+                color: MENU_TEXT_COLOR,
+                options: Default::default(),
+            }));
+            ui.new_widget(window, Rect::with_size(x + 20, y + 1, 90, 16), None, None, label);
+        }
+
+        self.prefs_screen = Some(PrefsScreen {
+            window,
+            knobs,
+            sliders,
+            saved: self.prefs.clone(),
+        });
+        self.sync(ui);
+    }
+
+    /// Closes the preferences screen. If `apply` is `false` the preferences are restored to the
+    /// ones before the screen was shown.
+    pub fn hide_prefs(&mut self, apply: bool, ui: &mut Ui) {
+        if let Some(screen) = self.prefs_screen.take() {
+            ui.remove(screen.window);
+            if !apply {
+                self.prefs = screen.saved;
+            }
+        }
+    }
+
+    /// Handles the preferences screen command. Returns `true` if the preferences were changed.
+    pub fn handle_prefs(&mut self, cmd: Command, ui: &mut Ui) -> bool {
+        match cmd {
+            Command::Cycle(pref) => self.prefs.cycle(pref),
+            Command::Set { pref, value } => self.prefs.set(pref, value),
+            Command::PrefsDefault => self.prefs = Preferences::default(),
+            Command::PrefsDone => self.hide_prefs(true, ui),
+            Command::PrefsCancel => self.hide_prefs(false, ui),
+            _ => return false,
+        }
+        self.sync(ui);
+        true
+    }
+
+    fn sync(&self, ui: &mut Ui) {
+        let Some(screen) = &self.prefs_screen else {
+            return;
+        };
+        for &(pref, h) in &screen.knobs {
+            let text = self.msg(MSG_DIFFICULTY_BASE + self.prefs.get(pref) as MessageId);
+            ui.widget_mut::<Panel>(h).set_text(Some(panel::Text {
+                text,
+                font: LABEL_FONT,
+                color: GREEN,
+                options: DrawOptions {
+                    horz_align: HorzAlign::Center,
+                    ..Default::default()
+                },
+            }));
+        }
+        for &(pref, h) in &screen.sliders {
+            ui.widget_mut::<Slider>(h).set_value(self.prefs.get(pref));
+        }
+    }
+
+    fn new_label(&self, ui: &mut Ui, window: Handle, rect: Rect, msg: MessageId, font: FontKey) {
+        let mut label = Panel::new();
+        label.set_text(Some(panel::Text {
+            text: self.msg(msg),
+            font,
+            color: GREEN,
+            options: DrawOptions {
+                horz_align: HorzAlign::Center,
+                ..Default::default()
+            },
+        }));
+        ui.new_widget(window, rect, None, None, label);
+    }
+
+    fn msg(&self, id: MessageId) -> BString {
+        self.msgs.get(id).map(|m| m.text.clone()).unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn prefs() {
+        let mut p = Preferences::default();
+        assert_eq!(p.get(Pref::TextDelay), 35);
+        assert_eq!(p.get(Pref::Brightness), 0);
+
+        p.set(Pref::TextDelay, 60);
+        assert_eq!(p.text_delay, Duration::from_millis(1_000));
+        p.set(Pref::TextDelay, 0);
+        assert_eq!(p.text_delay, Duration::from_millis(6_000));
+
+        p.set(Pref::Brightness, 9);
+        assert!((p.brightness - 1.09).abs() < 1e-9);
+        assert_eq!(p.get(Pref::Brightness), 9);
+
+        p.set(Pref::CombatSpeed, 100);
+        assert_eq!(p.combat_speed, 50);
+
+        p.cycle(Pref::GameDifficulty);
+        assert_eq!(p.game_difficulty, Difficulty::Hard);
+        p.cycle(Pref::GameDifficulty);
+        assert_eq!(p.game_difficulty, Difficulty::Easy);
+    }
+}
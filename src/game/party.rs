@@ -0,0 +1,551 @@
+use log::*;
+use std::cmp;
+use std::io::{self, BufRead, Error, ErrorKind};
+
+use crate::asset::{read_ini, CritterAnim, Flag, Skill, Stat};
+use crate::asset::proto::{ProtoDb, ProtoId};
+use crate::asset::script::ProgramId;
+use crate::fs::FileSystem;
+use crate::game::ai::*;
+use crate::game::object::{Handle, Object, ObjectGraph, Objects};
+use crate::game::rpg::Rpg;
+use crate::game::script::{ScriptKind, Scripts};
+use crate::graphics::EPoint;
+use crate::graphics::geometry::hex::{Direction, TileGrid};
+use crate::util::EnumExt;
+
+/// Party members farther than this from the dude walk to him.
+const FOLLOW_DISTANCE: u32 = 3;
+
+/// Party members farther than this from the dude run instead of walking.
+const RUN_DISTANCE: u32 = 8;
+
+/// Max distance from the dude at which the party members are placed when entering a map or
+/// elevation.
+const PLACE_DISTANCE: u32 = 4;
+
+/// Skills the party members can use on behalf of the dude.
+const USE_SKILLS: [Skill; 7] = [
+    Skill::FirstAid,
+    Skill::Doctor,
+    Skill::Lockpick,
+    Skill::Steal,
+    Skill::Traps,
+    Skill::Science,
+    Skill::Repair,
+];
+
+fn invalid_data(msg: String) -> Error {
+    Error::new(ErrorKind::InvalidData, msg)
+}
+
+/// Combat control setting of a party member.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Control {
+    AreaAttackMode(AreaAttackMode),
+    AttackWho(AttackWho),
+    BestWeapon(BestWeapon),
+    ChemUse(ChemUse),
+    Distance(Distance),
+    RunAwayMode(RunAwayMode),
+    Disposition(Disposition),
+}
+
+impl Control {
+    fn apply(self, packet: &mut Packet) {
+        match self {
+            Self::AreaAttackMode(v) => packet.area_attack_mode = v,
+            Self::AttackWho(v) => packet.attack_who = v,
+            Self::BestWeapon(v) => packet.best_weapon = v,
+            Self::ChemUse(v) => packet.chem_use = v,
+            Self::Distance(v) => packet.distance = v,
+            Self::RunAwayMode(v) => packet.run_away_mode = v,
+            Self::Disposition(v) => packet.disposition = v,
+        }
+    }
+}
+
+/// Party member description from `data/party.txt`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct MemberDef {
+    pub pid: ProtoId,
+    /// Combat control settings the player can choose for the member.
+    pub area_attack_modes: Vec<AreaAttackMode>,
+    pub attack_who: Vec<AttackWho>,
+    pub best_weapons: Vec<BestWeapon>,
+    pub chem_uses: Vec<ChemUse>,
+    pub distances: Vec<Distance>,
+    pub run_away_modes: Vec<RunAwayMode>,
+    pub dispositions: Vec<Disposition>,
+    /// Dude level starting from which the member levels up.
+    pub level_minimum: i32,
+    /// The member levels up once per this many dude levels. Zero if the member never levels up.
+    pub level_up_every: u32,
+    /// Protos the member switches to on each level up.
+    pub level_pids: Vec<ProtoId>,
+}
+
+impl MemberDef {
+    pub fn allows(&self, control: Control) -> bool {
+        match control {
+            Control::AreaAttackMode(v) => self.area_attack_modes.contains(&v),
+            Control::AttackWho(v) => self.attack_who.contains(&v),
+            Control::BestWeapon(v) => self.best_weapons.contains(&v),
+            Control::ChemUse(v) => self.chem_uses.contains(&v),
+            Control::Distance(v) => self.distances.contains(&v),
+            Control::RunAwayMode(v) => self.run_away_modes.contains(&v),
+            Control::Disposition(v) => self.dispositions.contains(&v),
+        }
+    }
+
+    /// Whether `pid` is the proto of this member at any level.
+    fn has_pid(&self, pid: ProtoId) -> bool {
+        self.pid == pid || self.level_pids.contains(&pid)
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Member {
+    obj: Handle,
+    def: usize,
+    /// Number of level ups done so far.
+    level: usize,
+    /// Dude levels gained since the last level up.
+    dude_levels: u32,
+}
+
+/// Party member that is leaving the map along with the dude.
+pub struct Traveler {
+    member: Member,
+    graph: ObjectGraph,
+    script: Option<(ScriptKind, ProgramId, Box<[i32]>)>,
+}
+
+/// Critters that joined the dude. The party members follow the dude and travel with him between
+/// maps.
+pub struct Party {
+    defs: Vec<MemberDef>,
+    members: Vec<Member>,
+}
+
+impl Party {
+    // partyMember_init()
+    pub fn new(fs: &FileSystem) -> io::Result<Self> {
+        if fs.exists("data/party.txt") {
+            Self::read(&mut fs.reader("data/party.txt")?)
+        } else {
+            Ok(Self {
+                defs: Vec::new(),
+                members: Vec::new(),
+            })
+        }
+    }
+
+    fn read(rd: &mut impl BufRead) -> io::Result<Self> {
+        let ini = read_ini(rd)?;
+        let mut defs = Vec::new();
+        for i in 0.. {
+            let name = format!("Party Member {}", i);
+            let Some(section) = ini.get(&name) else { break };
+            let get = |k: &str| section.get(k).map(|v| v.trim()).unwrap_or("");
+            let parse_pid = |v: &str| v.trim().parse::<i32>().ok()
+                .filter(|&v| v > 0)
+                .and_then(|v| ProtoId::from_packed(v as u32));
+            let int = |k: &str| -> io::Result<i32> {
+                let v = get(k);
+                if v.is_empty() {
+                    Ok(0)
+                } else {
+                    v.parse().map_err(|_| invalid_data(format!("bad {} in {}: {}", k, name, v)))
+                }
+            };
+            fn list<T>(name: &str, key: &str, v: &str, parse: fn(&str) -> Option<T>) -> Vec<T> {
+                v.split(',')
+                    .map(|v| v.trim().to_ascii_lowercase())
+                    .filter(|v| !v.is_empty())
+                    .filter_map(|v| {
+                        let r = parse(&v);
+                        // `none` means no choice for the settings that don't have such value.
+                        if r.is_none() && v != "none" {
+                            warn!("unknown {} in {}: {}", key, name, v);
+                        }
+                        r
+                    })
+                    .collect()
+            }
+
+            let pid = parse_pid(get("party_member_pid"))
+                .ok_or_else(|| invalid_data(format!("invalid party_member_pid in {}", name)))?;
+            defs.push(MemberDef {
+                pid,
+                area_attack_modes: list(&name, "area_attack_mode", get("area_attack_mode"),
+                    AreaAttackMode::parse),
+                attack_who: list(&name, "attack_who", get("attack_who"), AttackWho::parse),
+                best_weapons: list(&name, "best_weapon", get("best_weapon"), BestWeapon::parse),
+                chem_uses: list(&name, "chem_use", get("chem_use"), ChemUse::parse),
+                distances: list(&name, "distance", get("distance"), Distance::parse),
+                run_away_modes: list(&name, "run_away_mode", get("run_away_mode"),
+                    RunAwayMode::parse),
+                dispositions: list(&name, "disposition", get("disposition"), Disposition::parse),
+                level_minimum: int("level_minimum")?,
+                level_up_every: int("level_up_every")?.max(0) as u32,
+                level_pids: get("level_pids").split(',').filter_map(parse_pid).collect(),
+            });
+        }
+        Ok(Self {
+            defs,
+            members: Vec::new(),
+        })
+    }
+
+    pub fn defs(&self) -> &[MemberDef] {
+        &self.defs
+    }
+
+    /// Returns description of the party member.
+    pub fn def(&self, obj: Handle) -> Option<&MemberDef> {
+        self.member(obj).map(|m| &self.defs[m.def])
+    }
+
+    /// Removes all members. Called when the game is started or loaded.
+    pub fn clear(&mut self) {
+        self.members.clear();
+    }
+
+    pub fn members(&self) -> impl Iterator<Item=Handle> + '_ {
+        self.members.iter().map(|m| m.obj)
+    }
+
+    pub fn contains(&self, obj: Handle) -> bool {
+        self.member(obj).is_some()
+    }
+
+    fn member(&self, obj: Handle) -> Option<&Member> {
+        self.members.iter().find(|m| m.obj == obj)
+    }
+
+    // partyMemberAdd()
+    /// Adds the critter to the party putting it on the dude's team. Returns `false` if the critter
+    /// is not described in `party.txt`.
+    pub fn add(&mut self, obj: Handle, objs: &Objects, rpg: &mut Rpg) -> bool {
+        if self.contains(obj) {
+            return true;
+        }
+        let Some(pid) = objs.get(obj).proto_id() else { return false };
+        let Some(def) = self.defs.iter().position(|d| d.has_pid(pid) && !pid.is_dude()) else {
+            warn!("{:?} can't join the party: {:?} is not described in party.txt", obj, pid);
+            return false;
+        };
+        let level = self.defs[def].level_pids.iter().position(|&p| p == pid)
+            .map(|i| i + 1)
+            .unwrap_or(0);
+        self.members.push(Member {
+            obj,
+            def,
+            level,
+            dude_levels: 0,
+        });
+        rpg.add_party_member(pid);
+
+        let team = objs.dude_ref().sub.as_critter().unwrap().combat.team_id;
+        if let Some(c) = objs.get_mut(obj).sub.as_critter_mut() {
+            c.combat.team_id = team;
+        }
+        true
+    }
+
+    // partyMemberRemove()
+    pub fn remove(&mut self, obj: Handle) -> bool {
+        let len = self.members.len();
+        self.members.retain(|m| m.obj != obj);
+        self.members.len() != len
+    }
+
+    // getPartyMemberCount()
+    /// Number of live party members including the dude.
+    pub fn count(&self, objs: &Objects) -> u32 {
+        1 + self.members.iter()
+            .filter(|m| {
+                let o = objs.get(m.obj);
+                o.sub.as_critter().is_some_and(|c| !c.is_dead())
+                    && !o.flags.contains(Flag::TurnedOff)
+            })
+            .count() as u32
+    }
+
+    // partyMemberFindObjFromPid()
+    pub fn find(&self, pid: ProtoId, objs: &Objects) -> Option<Handle> {
+        self.members().find(|&h| objs.get(h).proto_id() == Some(pid))
+    }
+
+    /// Rebuilds the member list from the critters of the current map after a game is loaded.
+    /// The live critters of the `party.txt` protos on the dude's team are the party members.
+    pub fn restore(&mut self, objs: &Objects, rpg: &mut Rpg) {
+        self.members.clear();
+        let team = objs.dude_ref().sub.as_critter().unwrap().combat.team_id;
+        let candidates: Vec<_> = objs.iter()
+            .filter(|&h| {
+                let o = objs.get(h);
+                !o.is_dude() && o.sub.as_critter()
+                    .is_some_and(|c| !c.is_dead() && c.combat.team_id == team)
+            })
+            .collect();
+        for h in candidates {
+            let pid = objs.get(h).proto_id().unwrap();
+            if self.defs.iter().any(|d| d.has_pid(pid)) {
+                self.add(h, objs, rpg);
+            }
+        }
+    }
+
+    /// Removes the party members from the map the dude is leaving. Dead members leave the party.
+    pub fn leave_map(&mut self, objs: &mut Objects, scripts: &Scripts) -> Vec<Traveler> {
+        let mut r = Vec::new();
+        for member in self.members.drain(..) {
+            let (alive, script) = {
+                let o = objs.get(member.obj);
+                let alive = o.sub.as_critter().is_some_and(|c| !c.is_dead());
+                let script = o.script
+                    .and_then(|(sid, _)| scripts.get(sid).map(|s| (sid.kind(), s)))
+                    .map(|(kind, s)| (kind, s.program_id, s.local_vars.clone()));
+                (alive, script)
+            };
+            if !alive {
+                debug!("dead party member {:?} stays on the map", member.obj);
+                continue;
+            }
+            let graph = objs.remove_deep(member.obj);
+            r.push(Traveler {
+                member,
+                graph,
+                script,
+            });
+        }
+        r
+    }
+
+    /// Places the party members that came with the dude around him.
+    pub fn enter_map(&mut self,
+        travelers: Vec<Traveler>,
+        objs: &mut Objects,
+        scripts: &mut Scripts,
+        hex_grid: &TileGrid,
+    ) {
+        let dude_pos = objs.dude_ref().pos();
+        for Traveler { mut member, mut graph, script } in travelers {
+            let Some(pos) = free_pos_near(dude_pos, objs, hex_grid) else {
+                warn!("no room for party member {:?}", member.obj);
+                continue;
+            };
+            let script = script.and_then(|(kind, program_id, local_vars)| {
+                scripts.instantiate_new(kind, program_id, Some(local_vars))
+                    .map_err(|e| warn!("couldn't instantiate party member script: {}", e))
+                    .ok()
+                    .map(|sid| (sid, program_id))
+            });
+            {
+                let obj = graph.objects.get_mut(graph.root).unwrap();
+                obj.set_pos(Some(pos));
+                obj.direction = objs.dude_ref().direction;
+                obj.script = script;
+            }
+            member.obj = objs.insert_graph(graph);
+            objs.make_standing(member.obj);
+            if let Some((sid, _)) = script {
+                scripts.attach_to_object(sid, member.obj);
+            }
+            self.members.push(member);
+        }
+    }
+
+    // partyMemberIncLevels()
+    /// Called when the dude reaches `dude_level`. Levels up the members according to their
+    /// `party.txt` level tables. Returns the members that gained a level.
+    pub fn level_up(&mut self, dude_level: i32, proto_db: &ProtoDb, rpg: &mut Rpg,
+        objs: &Objects) -> Vec<Handle>
+    {
+        let mut r = Vec::new();
+        for member in &mut self.members {
+            let def = &self.defs[member.def];
+            if def.level_up_every == 0
+                || member.level >= def.level_pids.len()
+                || dude_level < def.level_minimum
+            {
+                continue;
+            }
+            member.dude_levels += 1;
+            if member.dude_levels < def.level_up_every {
+                continue;
+            }
+            member.dude_levels = 0;
+
+            let pid = def.level_pids[member.level];
+            let proto = match proto_db.proto(pid) {
+                Ok(v) => v,
+                Err(e) => {
+                    warn!("error loading party member level proto {:?}: {}", pid, e);
+                    continue;
+                }
+            };
+            member.level += 1;
+            rpg.add_party_member(pid);
+
+            let mut obj = objs.get_mut(member.obj);
+            obj.set_proto(proto);
+            let max_hp = rpg.stat(Stat::HitPoints, &obj, objs);
+            if let Some(c) = obj.sub.as_critter_mut() {
+                c.hit_points = max_hp;
+            }
+            debug!("party member {:?} leveled up to {:?}", member.obj, pid);
+            r.push(member.obj);
+        }
+        r
+    }
+
+    /// Changes combat control setting of the party member. Settings other than the disposition
+    /// switch the member to the custom disposition. Returns `false` if the setting is not
+    /// allowed for the member.
+    pub fn set_control(&self, obj: Handle, control: Control, ai: &mut Ai, objs: &Objects)
+        -> bool
+    {
+        let Some(def) = self.def(obj) else { return false };
+        if !def.allows(control) {
+            return false;
+        }
+        let disposition = if let Control::Disposition(v) = control {
+            v
+        } else {
+            Disposition::Custom
+        };
+        let mut o = objs.get_mut(obj);
+        let Some(num) = ai.disposition_packet(&o, disposition) else {
+            warn!("{:?} has no AI packet for disposition {:?}", obj, disposition);
+            return false;
+        };
+        o.sub.as_critter_mut().unwrap().combat.ai_packet = num;
+        if !matches!(control, Control::Disposition(_)) {
+            control.apply(ai.packet_mut(num).unwrap());
+        }
+        true
+    }
+
+    // partyMemberWithHighestSkill()
+    /// Returns the party member who uses the `skill` in place of the dude. That's the member
+    /// with the highest level in the skill if it's also the member's best skill.
+    pub fn best_in_skill(&self, skill: Skill, rpg: &Rpg, objs: &Objects) -> Option<Handle> {
+        let dude_level = rpg.skill(skill, &objs.dude_ref(), objs);
+        let dude_elevation = objs.dude_ref().pos().elevation;
+        let (h, level) = self.members()
+            .filter(|&h| {
+                let o = objs.get(h);
+                o.sub.as_critter().is_some_and(|c| !c.is_dead())
+                    && o.try_pos().is_some_and(|p| p.elevation == dude_elevation)
+            })
+            .map(|h| (h, rpg.skill(skill, &objs.get(h), objs)))
+            .max_by_key(|&(_, level)| level)?;
+        if level <= dude_level {
+            return None;
+        }
+        // partyMemberSkill()
+        let best = USE_SKILLS.iter().copied()
+            .max_by_key(|&s| rpg.skill(s, &objs.get(h), objs))
+            .unwrap();
+        (best == skill).then_some(h)
+    }
+
+    /// Returns the highest `skill` level among the dude and the live party members.
+    // partyGetBestSkillValue()
+    pub fn best_skill_level(&self, skill: Skill, rpg: &Rpg, objs: &Objects) -> i32 {
+        let dude_level = rpg.skill(skill, &objs.dude_ref(), objs);
+        self.members()
+            .filter(|&h| objs.get(h).sub.as_critter().is_some_and(|c| !c.is_dead()))
+            .map(|h| rpg.skill(skill, &objs.get(h), objs))
+            .fold(dude_level, cmp::max)
+    }
+}
+
+/// Returns the animation the party member should use to follow the dude or `None` if the
+/// member is close enough.
+pub fn follow_anim(member: &Object, dude: &Object) -> Option<CritterAnim> {
+    let distance = member.distance(dude)?;
+    if distance <= FOLLOW_DISTANCE {
+        None
+    } else if distance <= RUN_DISTANCE {
+        Some(CritterAnim::Walk)
+    } else {
+        Some(CritterAnim::Running)
+    }
+}
+
+/// Returns a free hex close to `center` suitable for placing a party member.
+pub fn free_pos_near(center: EPoint, objs: &Objects, hex_grid: &TileGrid) -> Option<EPoint> {
+    (1..=PLACE_DISTANCE)
+        .flat_map(|distance| Direction::iter().map(move |dir| (dir, distance)))
+        .map(|(dir, distance)| center.with_point(hex_grid.go_clipped(center.point, dir, distance)))
+        .find(|&pos| pos.point != center.point && !objs.has_blocker_at(pos, None))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::{BufReader, Cursor};
+
+    #[test]
+    fn read() {
+        let inp = "
+[Party Member 0]
+party_member_pid=16777216
+
+[Party Member 1]
+party_member_pid=16777313
+area_attack_mode=always, be_careful
+attack_who=whomever_attacking_me, closest
+best_weapon=no_pref, melee
+chem_use=clean
+distance=stay_close, charge
+run_away_mode=none, bleeding
+disposition=custom, coward, berserk
+level_minimum=3
+level_up_every=2
+level_pids=16777410, 16777411, -1
+
+[Party Member 2]
+party_member_pid=16777220
+area_attack_mode=none
+best_weapon=whatever
+
+[Party Member 4]
+party_member_pid=16777221
+";
+        let party = Party::read(&mut BufReader::new(Cursor::new(inp))).unwrap();
+        let defs = party.defs();
+        assert_eq!(defs.len(), 3);
+        assert_eq!(defs[0].pid, ProtoId::DUDE);
+
+        let d = &defs[1];
+        assert_eq!(d.pid, ProtoId::from_packed(16777313).unwrap());
+        assert_eq!(d.area_attack_modes, vec![AreaAttackMode::Always, AreaAttackMode::BeCareful]);
+        assert_eq!(d.best_weapons, vec![BestWeapon::NoPref, BestWeapon::Melee]);
+        assert_eq!(d.run_away_modes, vec![RunAwayMode::None, RunAwayMode::Bleeding]);
+        assert_eq!(d.level_minimum, 3);
+        assert_eq!(d.level_up_every, 2);
+        assert_eq!(d.level_pids, vec![
+            ProtoId::from_packed(16777410).unwrap(),
+            ProtoId::from_packed(16777411).unwrap(),
+        ]);
+        assert!(d.allows(Control::Disposition(Disposition::Berserk)));
+        assert!(!d.allows(Control::Disposition(Disposition::Aggressive)));
+        assert!(d.allows(Control::Distance(Distance::StayClose)));
+        assert!(!d.allows(Control::ChemUse(ChemUse::Always)));
+        assert!(d.has_pid(ProtoId::from_packed(16777411).unwrap()));
+
+        let d = &defs[2];
+        assert!(d.area_attack_modes.is_empty());
+        assert!(d.best_weapons.is_empty());
+        assert_eq!(d.level_up_every, 0);
+        assert!(d.level_pids.is_empty());
+
+        assert!(Party::read(&mut BufReader::new(Cursor::new(
+            "[Party Member 0]\nparty_member_pid=x\n"))).is_err());
+    }
+}
@@ -0,0 +1,636 @@
+use bstring::{bfmt::ToBString, BString};
+use std::io::{self, prelude::*, Error, ErrorKind};
+
+use crate::asset::frame::FrameId;
+use crate::asset::map::MapId;
+use crate::asset::map::db::MapDb;
+use crate::asset::message::{MessageId, Messages};
+use crate::fs::FileSystem;
+use crate::game::GameTime;
+use crate::game::automap::AutomapDb;
+use crate::game::movie::Movies;
+use crate::game::ui::automap::AutomapView;
+use crate::graphics::{EPoint, Rect};
+use crate::graphics::color::{Rgb15, GREEN};
+use crate::graphics::font::FontKey;
+use crate::graphics::sprite::Sprite;
+use crate::ui::*;
+use crate::ui::button::Button;
+use crate::ui::command::UiCommandData;
+use crate::ui::command::pipboy::Command;
+use crate::ui::message_panel::{MessagePanel, MouseControl};
+use crate::ui::panel::{self, Panel};
+
+const MSG_STATUS: MessageId = 202;
+const MSG_AUTOMAPS: MessageId = 205;
+const MSG_ARCHIVES: MessageId = 206;
+const MSG_NO_QUESTS: MessageId = 203;
+const MSG_DATA: MessageId = 211;
+const MSG_CANT_REST: MessageId = 215;
+const MSG_REST_BASE: MessageId = 302;
+const MSG_MOVIE_BASE: MessageId = 500;
+
+const END_PAR: &[u8] = b"**END-PAR**";
+const END_DISK: &[u8] = b"**END-DISK**";
+
+const TEXT_FONT: FontKey = FontKey::antialiased(1);
+const TITLE_FONT: FontKey = FontKey::antialiased(2);
+const TITLE_COLOR: Rgb15 = unsafe { Rgb15::rgb15_from_packed_unchecked(0x7feb) };
+const COMPLETED_COLOR: Rgb15 = unsafe { Rgb15::rgb15_from_packed_unchecked(0x0200) };
+const HIGHLIGHT_COLOR: Rgb15 = unsafe { Rgb15::rgb15_from_packed_unchecked(0x7fff) };
+
+const CONTENT_RECT: Rect = Rect { left: 254, top: 46, right: 254 + 374, bottom: 46 + 410 };
+const TITLE_HEIGHT: i32 = 20;
+
+const MONTHS: [&str; 12] = ["JAN", "FEB", "MAR", "APR", "MAY", "JUN", "JUL", "AUG", "SEP", "OCT",
+    "NOV", "DEC"];
+
+fn invalid_data(msg: String) -> Error {
+    Error::new(ErrorKind::InvalidData, msg)
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Page {
+    Status,
+    Automaps,
+    Archives,
+    Clock,
+}
+
+/// Quest entry of the `data/quests.txt`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Quest {
+    /// Message in `game/map.msg`.
+    pub location: MessageId,
+    /// Message in `game/quests.msg`.
+    pub description: MessageId,
+    pub gvar: usize,
+    /// The quest is shown when the global var reaches this value.
+    pub display_threshold: i32,
+    /// The quest is completed when the global var reaches this value.
+    pub completed_threshold: i32,
+}
+
+impl Quest {
+    pub fn is_visible(&self, global_vars: &[i32]) -> bool {
+        global_vars.get(self.gvar).is_some_and(|&v| v >= self.display_threshold)
+    }
+
+    pub fn is_completed(&self, global_vars: &[i32]) -> bool {
+        global_vars.get(self.gvar).is_some_and(|&v| v >= self.completed_threshold)
+    }
+}
+
+/// Holodisk entry of the `data/holodisk.txt`. The holodisk is available when its global var
+/// is non-zero.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Holodisk {
+    pub gvar: usize,
+    /// Message in `game/pipboy.msg`.
+    pub name: MessageId,
+    /// First message of the text in `game/pipboy.msg`. The text ends with the `**END-DISK**`
+    /// message.
+    pub text: MessageId,
+}
+
+/// Parses comma separated integer fields of the `quests.txt` and `holodisk.txt` lines.
+/// Comments start with `#`.
+fn read_records<const N: usize>(rd: &mut impl BufRead) -> io::Result<Vec<[i32; N]>> {
+    let mut r = Vec::new();
+    for l in rd.lines() {
+        let l = l?;
+        let l = l.split('#').next().unwrap().trim();
+        if l.is_empty() {
+            continue;
+        }
+        let fields = l.split(',')
+            .map(|s| s.trim().parse::<i32>()
+                .map_err(|_| invalid_data(format!("bad field `{}` in line: {}", s, l))))
+            .collect::<io::Result<Vec<_>>>()?;
+        let fields = <[i32; N]>::try_from(fields)
+            .map_err(|_| invalid_data(format!("expected {} fields in line: {}", N, l)))?;
+        r.push(fields);
+    }
+    Ok(r)
+}
+
+fn gvar(v: i32) -> io::Result<usize> {
+    usize::try_from(v).map_err(|_| invalid_data(format!("bad global var: {}", v)))
+}
+
+// questInit()
+fn read_quests(rd: &mut impl BufRead) -> io::Result<Vec<Quest>> {
+    read_records(rd)?.into_iter()
+        .map(|[location, description, gv, display_threshold, completed_threshold]| Ok(Quest {
+            location,
+            description,
+            gvar: gvar(gv)?,
+            display_threshold,
+            completed_threshold,
+        }))
+        .collect()
+}
+
+// holodiskInit()
+fn read_holodisks(rd: &mut impl BufRead) -> io::Result<Vec<Holodisk>> {
+    read_records(rd)?.into_iter()
+        .map(|[gv, name, text]| Ok(Holodisk {
+            gvar: gvar(gv)?,
+            name,
+            text,
+        }))
+        .collect()
+}
+
+/// Condition to rest until.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum RestUntil {
+    /// Rest for the duration.
+    Elapsed(GameTime),
+    /// Rest until the next occurrence of the hour of day.
+    Hour(u8),
+    /// Rest until the dude is fully healed.
+    Healed,
+    /// Rest until the dude and the party members are fully healed.
+    PartyHealed,
+}
+
+impl RestUntil {
+    /// Options in the order of the clock page list.
+    pub const ALL: [Self; 14] = [
+        Self::Elapsed(GameTime::from_decis(10 * GameTime::MINUTE.as_decis())),
+        Self::Elapsed(GameTime::from_decis(30 * GameTime::MINUTE.as_decis())),
+        Self::Elapsed(GameTime::HOUR),
+        Self::Elapsed(GameTime::from_decis(2 * GameTime::HOUR.as_decis())),
+        Self::Elapsed(GameTime::from_decis(3 * GameTime::HOUR.as_decis())),
+        Self::Elapsed(GameTime::from_decis(4 * GameTime::HOUR.as_decis())),
+        Self::Elapsed(GameTime::from_decis(5 * GameTime::HOUR.as_decis())),
+        Self::Elapsed(GameTime::from_decis(6 * GameTime::HOUR.as_decis())),
+        Self::Hour(6),
+        Self::Hour(12),
+        Self::Hour(18),
+        Self::Hour(0),
+        Self::Healed,
+        Self::PartyHealed,
+    ];
+
+    /// Returns the game time when resting started at `now` should end. Returns `None` if the
+    /// end doesn't depend on time.
+    pub fn end_time(self, now: GameTime) -> Option<GameTime> {
+        match self {
+            Self::Elapsed(t) => Some(now.add_decis(t.as_decis())),
+            Self::Hour(hour) => {
+                let day_start = now.as_decis() - now.as_decis() % GameTime::DAY.as_decis();
+                let mut end = day_start + hour as u32 * GameTime::HOUR.as_decis();
+                if end <= now.as_decis() {
+                    end += GameTime::DAY.as_decis();
+                }
+                Some(GameTime::from_decis(end))
+            }
+            Self::Healed | Self::PartyHealed => None,
+        }
+    }
+}
+
+/// State of the rest in progress.
+#[derive(Clone, Copy, Debug)]
+pub struct Rest {
+    pub until: RestUntil,
+    pub end: Option<GameTime>,
+    /// Game time when the hit points were last restored.
+    pub last_heal: GameTime,
+}
+
+/// What's shown when the list line is picked.
+#[derive(Clone, Copy, Debug)]
+enum Entry {
+    None,
+    Page(Page),
+    Location(MessageId),
+    Holodisk(usize),
+    Automap(MapId, u32),
+    Movie(u32),
+    Rest(RestUntil),
+}
+
+/// Something the Pip-Boy requests from the game.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Action {
+    PlayMovie(u32),
+    Rest(RestUntil),
+}
+
+/// Game state shown by the Pip-Boy.
+pub struct Info<'a> {
+    pub global_vars: &'a [i32],
+    pub automaps: &'a AutomapDb,
+    pub map_db: &'a MapDb,
+    pub movies: &'a Movies,
+    pub dude_pos: Option<(MapId, EPoint)>,
+    pub game_time: GameTime,
+    pub can_rest: bool,
+}
+
+struct Internal {
+    window: Handle,
+    date: Handle,
+    title: Handle,
+    list: Handle,
+    automap: Handle,
+    entries: Vec<Entry>,
+}
+
+pub struct Pipboy {
+    msgs: Messages,
+    map_msgs: Messages,
+    quest_msgs: Messages,
+    quests: Vec<Quest>,
+    holodisks: Vec<Holodisk>,
+    rest: Option<Rest>,
+    internal: Option<Internal>,
+}
+
+impl Pipboy {
+    /// Fallout 1 doesn't have the quest and holodisk data files and gets no quests and
+    /// holodisks.
+    pub fn new(fs: &FileSystem, language: &str) -> io::Result<Self> {
+        let (quest_msgs, quests) = if fs.exists("data/quests.txt") {
+            (Messages::read_file(fs, language, "game/quests.msg")?,
+                read_quests(&mut fs.reader("data/quests.txt")?)?)
+        } else {
+            (Messages::default(), Vec::new())
+        };
+        let holodisks = if fs.exists("data/holodisk.txt") {
+            read_holodisks(&mut fs.reader("data/holodisk.txt")?)?
+        } else {
+            Vec::new()
+        };
+        Ok(Self {
+            msgs: Messages::read_file(fs, language, "game/pipboy.msg")?,
+            map_msgs: Messages::read_file(fs, language, "game/map.msg")?,
+            quest_msgs,
+            quests,
+            holodisks,
+            rest: None,
+            internal: None,
+        })
+    }
+
+    pub fn is_visible(&self) -> bool {
+        self.internal.is_some()
+    }
+
+    /// Whether `widget` is the list of the Pip-Boy.
+    pub fn is(&self, widget: Handle) -> bool {
+        self.internal.as_ref().is_some_and(|i| i.list == widget)
+    }
+
+    pub fn rest(&self) -> Option<Rest> {
+        self.rest
+    }
+
+    pub fn rest_mut(&mut self) -> Option<&mut Rest> {
+        self.rest.as_mut()
+    }
+
+    pub fn start_rest(&mut self, until: RestUntil, now: GameTime) {
+        self.rest = Some(Rest {
+            until,
+            end: until.end_time(now),
+            last_heal: now,
+        });
+    }
+
+    pub fn stop_rest(&mut self) {
+        self.rest = None;
+    }
+
+    // pipboy_init()
+    pub fn show(&mut self, info: &Info, ui: &mut Ui) {
+        assert!(self.internal.is_none());
+
+        let window = ui.new_window(Rect::with_size(0, 0, 640, 480), Some(Sprite::new(FrameId::PIP)));
+        ui.widget_base_mut(window).set_modal(true);
+
+        let date = ui.new_widget(window, Rect::with_size(20, 17, 100, 12), None, None,
+            Panel::new());
+
+        ui.new_widget(window, Rect::with_size(124, 13, 1, 1), None, None,
+            Button::new(FrameId::ALARMOUT, FrameId::ALARMIN,
+                Some(UiCommandData::Pipboy(Command::Page(Page::Clock)))));
+
+        for (y, cmd) in [
+            (341, Command::Page(Page::Status)),
+            (395, Command::Page(Page::Automaps)),
+            (422, Command::Page(Page::Archives)),
+            (449, Command::Hide),
+        ] {
+            ui.new_widget(window, Rect::with_size(53, y, 15, 16), None, None,
+                Button::new(FrameId::SMALL_RED_BUTTON_UP, FrameId::SMALL_RED_BUTTON_DOWN,
+                    Some(UiCommandData::Pipboy(cmd))));
+        }
+
+        let title = ui.new_widget(window, Rect::with_size(CONTENT_RECT.left, CONTENT_RECT.top,
+            CONTENT_RECT.width(), TITLE_HEIGHT), None, None, Panel::new());
+
+        let list_rect = Rect::with_size(CONTENT_RECT.left, CONTENT_RECT.top + TITLE_HEIGHT,
+            CONTENT_RECT.width(), CONTENT_RECT.height() - TITLE_HEIGHT);
+
+        // Must go before the list so the list receives the mouse events.
+        let automap = ui.new_widget(window, list_rect, None, None,
+            AutomapView::new(ui.frm_db().texture_factory().clone()));
+
+        let mut list = MessagePanel::new(ui.fonts().clone(), TEXT_FONT, GREEN);
+        list.set_mouse_control(MouseControl::Pick);
+        list.set_highlight_color(HIGHLIGHT_COLOR);
+        list.set_message_spacing(2);
+        let list = ui.new_widget(window, list_rect, None, None, list);
+
+        self.internal = Some(Internal {
+            window,
+            date,
+            title,
+            list,
+            automap,
+            entries: Vec::new(),
+        });
+
+        self.sync_time(info.game_time, ui);
+        self.show_page(Page::Status, info, ui);
+    }
+
+    // pipboy_exit()
+    pub fn hide(&mut self, ui: &mut Ui) {
+        self.rest = None;
+        if let Some(internal) = self.internal.take() {
+            ui.remove(internal.window);
+        }
+    }
+
+    /// Updates the date and time display.
+    pub fn sync_time(&self, time: GameTime, ui: &mut Ui) {
+        let Some(internal) = &self.internal else {
+            return;
+        };
+        let text = format!("{:02} {} {} {:02}{:02}",
+            time.day(), MONTHS[time.month() as usize - 1], time.year(), time.hour(), time.minute());
+        ui.widget_mut::<Panel>(internal.date).set_text(Some(panel::Text {
+            text: text.into(),
+            font: TEXT_FONT,
+            color: GREEN,
+            options: Default::default(),
+        }));
+    }
+
+    pub fn show_page(&mut self, page: Page, info: &Info, ui: &mut Ui) {
+        match page {
+            Page::Status => self.show_status(info, ui),
+            Page::Automaps => self.show_automaps(info, ui),
+            Page::Archives => self.show_archives(info, ui),
+            Page::Clock => self.show_clock(info, ui),
+        }
+    }
+
+    /// Handles pick from the list. Returns action the game should perform.
+    pub fn pick(&mut self, id: u32, info: &Info, ui: &mut Ui) -> Option<Action> {
+        let entry = *self.internal.as_ref()?.entries.get(id as usize)?;
+        match entry {
+            Entry::None => {}
+            Entry::Page(page) => self.show_page(page, info, ui),
+            Entry::Location(location) => self.show_quests(location, info, ui),
+            Entry::Holodisk(i) => self.show_holodisk(i, ui),
+            Entry::Automap(map_id, elevation) => self.show_automap(map_id, elevation, info, ui),
+            Entry::Movie(id) => return Some(Action::PlayMovie(id)),
+            Entry::Rest(until) => return Some(Action::Rest(until)),
+        }
+        None
+    }
+
+    fn msg(msgs: &Messages, id: MessageId) -> BString {
+        msgs.get(id).map(|m| m.text.clone()).unwrap_or_default()
+    }
+
+    /// Clears the content setting the title.
+    fn clear(&mut self, title: BString, ui: &mut Ui) {
+        let internal = self.internal.as_mut().unwrap();
+        internal.entries.clear();
+        ui.widget_mut::<MessagePanel>(internal.list).clear_messages();
+        ui.widget_mut::<AutomapView>(internal.automap).set(None, None);
+        ui.widget_mut::<Panel>(internal.title).set_text(Some(panel::Text {
+            text: title,
+            font: TITLE_FONT,
+            color: TITLE_COLOR,
+            options: Default::default(),
+        }));
+    }
+
+    fn push(&mut self, text: BString, color: Option<Rgb15>, entry: Entry, ui: &mut Ui) {
+        let internal = self.internal.as_mut().unwrap();
+        ui.widget_mut::<MessagePanel>(internal.list).push_message_with_color(text, color);
+        internal.entries.push(entry);
+    }
+
+    // pipboy_status()
+    fn show_status(&mut self, info: &Info, ui: &mut Ui) {
+        self.clear(Self::msg(&self.msgs, MSG_STATUS), ui);
+
+        let mut locations = Vec::new();
+        for quest in &self.quests {
+            if quest.is_visible(info.global_vars) && !locations.contains(&quest.location) {
+                locations.push(quest.location);
+            }
+        }
+        if locations.is_empty() {
+            self.push(Self::msg(&self.msgs, MSG_NO_QUESTS), None, Entry::None, ui);
+        }
+        for location in locations {
+            self.push(Self::msg(&self.map_msgs, location), None, Entry::Location(location), ui);
+        }
+
+        let holodisks: Vec<_> = self.holodisks.iter()
+            .enumerate()
+            .filter(|(_, h)| info.global_vars.get(h.gvar).is_some_and(|&v| v != 0))
+            .map(|(i, h)| (i, Self::msg(&self.msgs, h.name)))
+            .collect();
+        if !holodisks.is_empty() {
+            self.push(Self::msg(&self.msgs, MSG_DATA), Some(TITLE_COLOR), Entry::None, ui);
+            for (i, name) in holodisks {
+                self.push(name, None, Entry::Holodisk(i), ui);
+            }
+        }
+    }
+
+    fn show_quests(&mut self, location: MessageId, info: &Info, ui: &mut Ui) {
+        self.clear(Self::msg(&self.map_msgs, location), ui);
+        let quests: Vec<_> = self.quests.iter()
+            .filter(|q| q.location == location && q.is_visible(info.global_vars))
+            .map(|q| (Self::msg(&self.quest_msgs, q.description),
+                q.is_completed(info.global_vars)))
+            .collect();
+        for (text, completed) in quests {
+            let color = completed.then_some(COMPLETED_COLOR);
+            self.push(text, color, Entry::Page(Page::Status), ui);
+        }
+    }
+
+    fn show_holodisk(&mut self, i: usize, ui: &mut Ui) {
+        let holodisk = &self.holodisks[i];
+        let title = Self::msg(&self.msgs, holodisk.name);
+        let mut paragraphs = Vec::new();
+        let mut paragraph = BString::new();
+        for id in holodisk.text.. {
+            let Some(msg) = self.msgs.get(id) else {
+                break;
+            };
+            let text = msg.text.as_bytes();
+            if text == END_DISK {
+                break;
+            }
+            if text == END_PAR {
+                paragraphs.push(std::mem::take(&mut paragraph));
+                continue;
+            }
+            if !paragraph.is_empty() {
+                paragraph.push(b' ');
+            }
+            paragraph.push_str(text);
+        }
+        if !paragraph.is_empty() {
+            paragraphs.push(paragraph);
+        }
+
+        self.clear(title, ui);
+        for p in paragraphs {
+            self.push(p, None, Entry::Page(Page::Status), ui);
+        }
+    }
+
+    // pipboy_automaps()
+    fn show_automaps(&mut self, info: &Info, ui: &mut Ui) {
+        self.clear(Self::msg(&self.msgs, MSG_AUTOMAPS), ui);
+        let mut last_map = None;
+        for (map_id, elevation) in info.automaps.visited() {
+            if last_map != Some(map_id) {
+                last_map = Some(map_id);
+                let name = info.map_db.get(map_id)
+                    .map(|m| m.lookup_name.to_bstring())
+                    .unwrap_or_default();
+                self.push(name, Some(TITLE_COLOR), Entry::None, ui);
+            }
+            let name = self.map_msgs.get(200 + 3 * map_id as MessageId + elevation as MessageId)
+                .map(|m| m.text.clone())
+                .unwrap_or_else(|| format!("{}", elevation + 1).into());
+            self.push(BString::concat(&[&b"  "[..], name.as_bytes()]), None,
+                Entry::Automap(map_id, elevation), ui);
+        }
+    }
+
+    fn show_automap(&mut self, map_id: MapId, elevation: u32, info: &Info, ui: &mut Ui) {
+        let Some(automap) = info.automaps.get(map_id, elevation) else {
+            return;
+        };
+        let title = self.map_msgs.get(200 + 3 * map_id as MessageId + elevation as MessageId)
+            .map(|m| m.text.clone())
+            .unwrap_or_default();
+        self.clear(title, ui);
+        let dude_pos = info.dude_pos
+            .filter(|&(m, pos)| m == map_id && pos.elevation == elevation)
+            .map(|(_, pos)| pos.point);
+        let internal = self.internal.as_ref().unwrap();
+        ui.widget_mut::<AutomapView>(internal.automap).set(Some(automap.clone()), dude_pos);
+    }
+
+    // pipboy_video_archives()
+    fn show_archives(&mut self, info: &Info, ui: &mut Ui) {
+        self.clear(Self::msg(&self.msgs, MSG_ARCHIVES), ui);
+        for id in info.movies.seen_game_movies() {
+            let Some(name) = self.msgs.get(MSG_MOVIE_BASE + id as MessageId) else {
+                continue;
+            };
+            let name = name.text.clone();
+            self.push(name, None, Entry::Movie(id), ui);
+        }
+    }
+
+    // pipboy_alarm_clock()
+    fn show_clock(&mut self, info: &Info, ui: &mut Ui) {
+        self.clear(BString::new(), ui);
+        if !info.can_rest {
+            self.push(Self::msg(&self.msgs, MSG_CANT_REST), None, Entry::None, ui);
+            return;
+        }
+        for (i, until) in RestUntil::ALL.iter().enumerate() {
+            let text = Self::msg(&self.msgs, MSG_REST_BASE + i as MessageId);
+            self.push(text, None, Entry::Rest(*until), ui);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn read_quests_() {
+        let quests = read_quests(&mut Cursor::new("
+# location, description, gvar, display, completed
+1500, 100, 12, 1, 3
+1501,101,13,2,9999 # comment
+")).unwrap();
+        assert_eq!(quests, vec![
+            Quest {
+                location: 1500,
+                description: 100,
+                gvar: 12,
+                display_threshold: 1,
+                completed_threshold: 3,
+            },
+            Quest {
+                location: 1501,
+                description: 101,
+                gvar: 13,
+                display_threshold: 2,
+                completed_threshold: 9999,
+            },
+        ]);
+
+        let gvars = &[0; 14][..];
+        assert!(!quests[0].is_visible(gvars));
+        let mut gvars = gvars.to_vec();
+        gvars[12] = 2;
+        assert!(quests[0].is_visible(&gvars));
+        assert!(!quests[0].is_completed(&gvars));
+        gvars[12] = 3;
+        assert!(quests[0].is_completed(&gvars));
+
+        assert!(read_quests(&mut Cursor::new("1500, 100, 12, 1")).is_err());
+        assert!(read_quests(&mut Cursor::new("1500, 100, x, 1, 2")).is_err());
+    }
+
+    #[test]
+    fn read_holodisks_() {
+        let holodisks = read_holodisks(&mut Cursor::new("
+# gvar, name, text
+401, 1000, 1100
+")).unwrap();
+        assert_eq!(holodisks, vec![Holodisk { gvar: 401, name: 1000, text: 1100 }]);
+    }
+
+    #[test]
+    fn rest_end_time() {
+        let t = |d: u32, h: u32, m: u32| GameTime::from_decis(
+            d * GameTime::DAY.as_decis()
+            + h * GameTime::HOUR.as_decis()
+            + m * GameTime::MINUTE.as_decis());
+        let now = t(3, 10, 15);
+
+        assert_eq!(RestUntil::ALL[0].end_time(now), Some(t(3, 10, 25)));
+        assert_eq!(RestUntil::ALL[7].end_time(now), Some(t(3, 16, 15)));
+        assert_eq!(RestUntil::Hour(6).end_time(now), Some(t(4, 6, 0)));
+        assert_eq!(RestUntil::Hour(12).end_time(now), Some(t(3, 12, 0)));
+        assert_eq!(RestUntil::Hour(0).end_time(now), Some(t(4, 0, 0)));
+        assert_eq!(RestUntil::Hour(12).end_time(t(3, 12, 0)), Some(t(4, 12, 0)));
+        assert_eq!(RestUntil::Healed.end_time(now), None);
+    }
+}
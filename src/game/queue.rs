@@ -0,0 +1,402 @@
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use enum_primitive_derive::Primitive;
+use log::*;
+use num_traits::FromPrimitive;
+use std::cmp;
+use std::io::{self, Error, ErrorKind, prelude::*};
+
+use crate::asset::{Perk, Stat};
+use crate::asset::proto::ProtoId;
+use crate::game::GameTime;
+use crate::game::object::Handle;
+use crate::util::random::random;
+
+/// Object ID of the dude in the saved queue.
+const DUDE_ID: i32 = 18000;
+/// Object ID of the events not bound to any object in the saved queue.
+const NO_OBJECT_ID: i32 = -2;
+/// Number of stat modifiers stored in a drug event.
+pub const DRUG_EFFECT_COUNT: usize = 3;
+
+fn invalid_data(msg: String) -> Error {
+    Error::new(ErrorKind::InvalidData, msg)
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Primitive)]
+pub enum EventKind {
+    Drug = 0,
+    Knockout = 1,
+    Withdrawal = 2,
+    Script = 3,
+    Poison = 5,
+    Radiation = 6,
+    Flare = 7,
+    Explosion = 8,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Event {
+    /// Delayed drug effects. The modifiers are added to the stats.
+    Drug {
+        pid: ProtoId,
+        modifiers: [Option<(Stat, i32)>; DRUG_EFFECT_COUNT],
+    },
+    /// Knocked out critter regaining consciousness.
+    Knockout,
+    /// Withdrawal from the drug `pid` setting in if `onset` is `true` or wearing off otherwise.
+    Withdrawal {
+        pid: ProtoId,
+        perk: Option<Perk>,
+        onset: bool,
+    },
+    /// Timer set with `add_timer_event`. Fires `timed_event_p_proc` of the object's script.
+    Script {
+        fixed_param: i32,
+    },
+    /// Poison doing damage.
+    Poison,
+    /// Radiation sickness of `level` setting in or wearing off if `healing` is `true`.
+    Radiation {
+        level: u32,
+        healing: bool,
+    },
+    /// Lit flare burning out.
+    Flare,
+    /// Armed explosive going off.
+    Explosion,
+}
+
+impl Event {
+    pub fn kind(&self) -> EventKind {
+        match self {
+            Self::Drug { .. } => EventKind::Drug,
+            Self::Knockout => EventKind::Knockout,
+            Self::Withdrawal { .. } => EventKind::Withdrawal,
+            Self::Script { .. } => EventKind::Script,
+            Self::Poison => EventKind::Poison,
+            Self::Radiation { .. } => EventKind::Radiation,
+            Self::Flare => EventKind::Flare,
+            Self::Explosion => EventKind::Explosion,
+        }
+    }
+
+    fn read(kind: EventKind, rd: &mut impl Read) -> io::Result<Self> {
+        Ok(match kind {
+            EventKind::Drug => {
+                let mut stats = [0; DRUG_EFFECT_COUNT];
+                rd.read_i32_into::<BigEndian>(&mut stats)?;
+                let mut values = [0; DRUG_EFFECT_COUNT];
+                rd.read_i32_into::<BigEndian>(&mut values)?;
+                let pid = ProtoId::read(rd)?;
+                let mut modifiers = [None; DRUG_EFFECT_COUNT];
+                for ((m, stat), value) in modifiers.iter_mut().zip(stats).zip(values) {
+                    if stat >= 0 {
+                        let stat = Stat::from_i32(stat)
+                            .ok_or_else(|| invalid_data(format!("invalid drug event stat: {}", stat)))?;
+                        *m = Some((stat, value));
+                    }
+                }
+                Self::Drug { pid, modifiers }
+            }
+            EventKind::Knockout => Self::Knockout,
+            EventKind::Withdrawal => {
+                let onset = rd.read_i32::<BigEndian>()? != 0;
+                let pid = ProtoId::read(rd)?;
+                let perk = rd.read_i32::<BigEndian>()?;
+                let perk = if perk >= 0 {
+                    Some(Perk::from_i32(perk)
+                        .ok_or_else(|| invalid_data(format!("invalid withdrawal perk: {}", perk)))?)
+                } else {
+                    None
+                };
+                Self::Withdrawal { pid, perk, onset }
+            }
+            EventKind::Script => {
+                let _sid = rd.read_i32::<BigEndian>()?;
+                let fixed_param = rd.read_i32::<BigEndian>()?;
+                Self::Script { fixed_param }
+            }
+            EventKind::Poison => Self::Poison,
+            EventKind::Radiation => {
+                let level = rd.read_i32::<BigEndian>()?;
+                let healing = rd.read_i32::<BigEndian>()? != 0;
+                Self::Radiation { level: level.max(0) as u32, healing }
+            }
+            EventKind::Flare => Self::Flare,
+            EventKind::Explosion => Self::Explosion,
+        })
+    }
+
+    fn write(&self, wr: &mut impl Write) -> io::Result<()> {
+        match *self {
+            Self::Drug { pid, modifiers } => {
+                for m in modifiers {
+                    wr.write_i32::<BigEndian>(m.map(|(s, _)| s as i32).unwrap_or(-1))?;
+                }
+                for m in modifiers {
+                    wr.write_i32::<BigEndian>(m.map(|(_, v)| v).unwrap_or(0))?;
+                }
+                wr.write_u32::<BigEndian>(pid.pack())?;
+            }
+            Self::Withdrawal { pid, perk, onset } => {
+                wr.write_i32::<BigEndian>(onset as i32)?;
+                wr.write_u32::<BigEndian>(pid.pack())?;
+                wr.write_i32::<BigEndian>(perk.map(|p| p as i32).unwrap_or(-1))?;
+            }
+            Self::Script { fixed_param } => {
+                wr.write_i32::<BigEndian>(-1)?;
+                wr.write_i32::<BigEndian>(fixed_param)?;
+            }
+            Self::Radiation { level, healing } => {
+                wr.write_i32::<BigEndian>(level as i32)?;
+                wr.write_i32::<BigEndian>(healing as i32)?;
+            }
+            Self::Knockout | Self::Poison | Self::Flare | Self::Explosion => {}
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Entry {
+    pub time: GameTime,
+    pub obj: Option<Handle>,
+    pub event: Event,
+}
+
+/// Events scheduled at game time. Events due at the same time fire in the order they were added.
+#[derive(Default)]
+pub struct Queue {
+    entries: Vec<Entry>,
+}
+
+impl Queue {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item=&Entry> {
+        self.entries.iter()
+    }
+
+    // queue_add()
+    pub fn push(&mut self, time: GameTime, obj: Option<Handle>, event: Event) {
+        let i = self.entries.partition_point(|e| e.time.as_decis() <= time.as_decis());
+        self.entries.insert(i, Entry { time, obj, event });
+    }
+
+    /// Removes and returns the earliest event that is due at `now`.
+    pub fn pop_due(&mut self, now: GameTime) -> Option<Entry> {
+        if self.entries.first()?.time.as_decis() <= now.as_decis() {
+            Some(self.entries.remove(0))
+        } else {
+            None
+        }
+    }
+
+    /// Removes and returns events matching the predicate.
+    // queue_remove_this()
+    pub fn remove(&mut self, mut f: impl FnMut(&Entry) -> bool) -> Vec<Entry> {
+        let mut r = Vec::new();
+        self.entries.retain(|e| if f(e) {
+            r.push(*e);
+            false
+        } else {
+            true
+        });
+        r
+    }
+
+    pub fn contains(&self, obj: Option<Handle>, kind: EventKind) -> bool {
+        self.entries.iter().any(|e| e.obj == obj && e.event.kind() == kind)
+    }
+
+    /// Reschedules the poison damage of the `dude` according to the new `poison` level.
+    // critter_adjust_poison()
+    pub fn schedule_poison(&mut self, now: GameTime, dude: Handle, poison: i32) {
+        self.remove(|e| e.obj == Some(dude) && e.event.kind() == EventKind::Poison);
+        if poison > 0 {
+            let delay = cmp::max(10 * (505 - 5 * poison), 10) as u32;
+            self.push(now.add_decis(delay), Some(dude), Event::Poison);
+        }
+    }
+
+    /// Schedules radiation sickness of `level` in a few hours unless the `dude` already has
+    /// the same or worse one pending or in effect.
+    // critter_check_rads()
+    pub fn schedule_radiation(&mut self, now: GameTime, dude: Handle, level: u32) {
+        let current = self.entries.iter()
+            .filter(|e| e.obj == Some(dude))
+            .filter_map(|e| match e.event {
+                Event::Radiation { level, .. } => Some(level),
+                _ => None,
+            })
+            .max()
+            .unwrap_or(0);
+        if level > current {
+            let delay = GameTime::HOUR.as_decis() * random(4, 18) as u32;
+            self.push(now.add_decis(delay), Some(dude), Event::Radiation { level, healing: false });
+        }
+    }
+
+    /// Removes and returns events that go with the `dude` to another map. Events bound to other
+    /// objects of the map are discarded.
+    // queue_leave_map()
+    pub fn leave_map(&mut self, dude: Handle) -> Vec<Entry> {
+        let r = self.remove(|e| e.obj.is_none_or(|o| o == dude));
+        self.entries.clear();
+        r
+    }
+
+    /// Restores events that came from another map binding the dude's ones to the new `dude`
+    /// handle.
+    pub fn enter_map(&mut self, entries: Vec<Entry>, dude: Handle) {
+        for e in entries {
+            self.push(e.time, e.obj.map(|_| dude), e.event);
+        }
+    }
+
+    /// Reads events saved with `write()`. The dude's events are bound to `dude`.
+    // queue_load()
+    pub fn read(rd: &mut impl Read, dude: Handle) -> io::Result<Self> {
+        let count = rd.read_i32::<BigEndian>()?;
+        let mut r = Self::new();
+        for _ in 0..count {
+            let time = GameTime::from_decis(rd.read_u32::<BigEndian>()?);
+            let kind = rd.read_i32::<BigEndian>()?;
+            let kind = EventKind::from_i32(kind)
+                .ok_or_else(|| invalid_data(format!("invalid queue event kind: {}", kind)))?;
+            let obj_id = rd.read_i32::<BigEndian>()?;
+            let event = Event::read(kind, rd)?;
+            let obj = match obj_id {
+                NO_OBJECT_ID => None,
+                DUDE_ID => Some(dude),
+                _ => {
+                    warn!("discarding {:?} queue event of unknown object {}", kind, obj_id);
+                    continue;
+                }
+            };
+            r.push(time, obj, event);
+        }
+        Ok(r)
+    }
+
+    /// Writes events of the `dude` and those not bound to any object. Events of the map objects
+    /// are not saved.
+    // queue_save()
+    pub fn write(&self, wr: &mut impl Write, dude: Handle) -> io::Result<()> {
+        let entries: Vec<_> = self.entries.iter()
+            .filter(|e| e.obj.is_none_or(|o| o == dude))
+            .collect();
+        wr.write_i32::<BigEndian>(entries.len() as i32)?;
+        for e in entries {
+            wr.write_u32::<BigEndian>(e.time.as_decis())?;
+            wr.write_i32::<BigEndian>(e.event.kind() as i32)?;
+            wr.write_i32::<BigEndian>(if e.obj.is_some() { DUDE_ID } else { NO_OBJECT_ID })?;
+            e.event.write(wr)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use slotmap::SlotMap;
+
+    fn t(decis: u32) -> GameTime {
+        GameTime::from_decis(decis)
+    }
+
+    #[test]
+    fn pop_due() {
+        let mut q = Queue::new();
+        q.push(t(20), None, Event::Flare);
+        q.push(t(10), None, Event::Poison);
+        q.push(t(20), None, Event::Knockout);
+        q.push(t(10), None, Event::Explosion);
+
+        assert_eq!(q.pop_due(t(5)), None);
+        let e = |time, event| Some(Entry { time: t(time), obj: None, event });
+        assert_eq!(q.pop_due(t(20)), e(10, Event::Poison));
+        assert_eq!(q.pop_due(t(20)), e(10, Event::Explosion));
+        assert_eq!(q.pop_due(t(20)), e(20, Event::Flare));
+        assert_eq!(q.pop_due(t(20)), e(20, Event::Knockout));
+        assert_eq!(q.pop_due(t(100)), None);
+        assert!(q.is_empty());
+    }
+
+    #[test]
+    fn leave_enter_map() {
+        let mut handles = SlotMap::<Handle, ()>::with_key();
+        let dude = handles.insert(());
+        let other = handles.insert(());
+        let new_dude = handles.insert(());
+
+        let mut q = Queue::new();
+        q.push(t(1), Some(dude), Event::Poison);
+        q.push(t(2), Some(other), Event::Script { fixed_param: 1 });
+        q.push(t(3), None, Event::Flare);
+
+        let travel = q.leave_map(dude);
+        assert!(q.is_empty());
+        q.enter_map(travel, new_dude);
+        assert_eq!(q.iter().map(|e| (e.obj, e.event)).collect::<Vec<_>>(),
+            vec![(Some(new_dude), Event::Poison), (None, Event::Flare)]);
+    }
+
+    #[test]
+    fn schedule_poison() {
+        let mut handles = SlotMap::<Handle, ()>::with_key();
+        let dude = handles.insert(());
+
+        let mut q = Queue::new();
+        q.schedule_poison(t(0), dude, 1);
+        q.schedule_poison(t(0), dude, 100);
+        assert_eq!(q.iter().copied().collect::<Vec<_>>(),
+            vec![Entry { time: t(50), obj: Some(dude), event: Event::Poison }]);
+        q.schedule_poison(t(0), dude, 0);
+        assert!(q.is_empty());
+    }
+
+    #[test]
+    fn roundtrip() {
+        let mut handles = SlotMap::<Handle, ()>::with_key();
+        let dude = handles.insert(());
+        let other = handles.insert(());
+        let new_dude = handles.insert(());
+        let pid = ProtoId::from_packed(0x35).unwrap();
+
+        let mut q = Queue::new();
+        q.push(t(100), Some(dude), Event::Drug {
+            pid,
+            modifiers: [Some((Stat::Strength, 2)), None, Some((Stat::HitPoints, -5))],
+        });
+        q.push(t(200), Some(dude), Event::Withdrawal {
+            pid,
+            perk: Some(Perk::from_i32(0).unwrap()),
+            onset: true,
+        });
+        q.push(t(300), Some(other), Event::Script { fixed_param: 7 });
+        q.push(t(400), None, Event::Radiation { level: 2, healing: true });
+        q.push(t(500), Some(dude), Event::Knockout);
+
+        let mut data = Vec::new();
+        q.write(&mut data, dude).unwrap();
+        let actual = Queue::read(&mut &data[..], new_dude).unwrap();
+
+        let expected: Vec<_> = q.iter()
+            .filter(|e| e.obj != Some(other))
+            .map(|e| Entry { obj: e.obj.map(|_| new_dude), ..*e })
+            .collect();
+        assert_eq!(actual.iter().copied().collect::<Vec<_>>(), expected);
+    }
+}
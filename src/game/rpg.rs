@@ -9,9 +9,11 @@ use std::convert::TryFrom;
 use std::io;
 use crate::asset::{DamageKind, ExactEntityKind, Perk, PCStat, Skill, Stat, Trait};
 use crate::asset::message::{Messages, MessageId};
-use crate::asset::proto::ProtoId;
+use crate::asset::proto::{CritterKillKind, ProtoId};
+use crate::game::combat::Difficulty;
 use crate::game::object::{DamageFlag, EquipmentSlot, Hand, Object, Objects};
 use crate::fs::FileSystem;
+use crate::util::EnumExt;
 use crate::util::random::*;
 
 use def::perk::*;
@@ -30,7 +32,47 @@ const SKILL_FORMULA_MSG_BASE: MessageId = 300;
 const LEVEL_UP_MSG: MessageId = 600;
 const PERK_NAME_MSG_BASE: MessageId = 101;
 const PERK_DESCR_MSG_BASE: MessageId = 1101;
-
+const TRAIT_NAME_MSG_BASE: MessageId = 100;
+const TRAIT_DESCR_MSG_BASE: MessageId = 200;
+const TRAIT_IMAGE_FID_ID_BASE: u32 = 55;
+
+/// Max number of traits the player can pick.
+pub const MAX_TRAITS: usize = 2;
+
+/// Number of skills tagged at character creation.
+pub const TAG_SKILL_COUNT: usize = 3;
+
+/// Skill points spent on a skill can't raise it above this value.
+pub const MAX_SKILL_EDIT_LEVEL: i32 = 300;
+
+/// Rads needed to reach each radiation level starting from level 1.
+const RADIATION_LEVEL_RADS: [i32; 5] = [100, 200, 400, 600, 1000];
+
+/// Endurance roll modifiers resisting the radiation sickness per radiation level.
+const RADIATION_ENDURANCE_MODS: [i32; 6] = [2, 0, -2, -4, -6, -8];
+
+/// Stats affected by the radiation sickness.
+const RADIATION_EFFECT_STATS: [Stat; 8] = [
+    Stat::Strength,
+    Stat::Perception,
+    Stat::Endurance,
+    Stat::Charisma,
+    Stat::Intelligence,
+    Stat::Agility,
+    Stat::CurrentHitPoints,
+    Stat::HealRate,
+];
+
+/// Modifiers to `RADIATION_EFFECT_STATS` per radiation level starting from level 1.
+const RADIATION_EFFECTS: [[i32; 8]; 5] = [
+    [-1,  0,  0,  0,  0,  0,   0,  -2],
+    [-1,  0,  0,  0,  0, -1,   0,  -3],
+    [-2,  0, -1,  0,  0, -2,  -5,  -5],
+    [-4, -3, -3, -3, -1, -5, -15, -10],
+    [-6, -5, -5, -5, -3, -6, -20, -10],
+];
+
+#[derive(Clone)]
 struct Tagged {
     tagged: bool,
     inc_base: bool,
@@ -45,10 +87,22 @@ impl Default for Tagged {
     }
 }
 
+/// Snapshot of the player character state kept by `Rpg`. Used to revert changes made in the
+/// character editor.
+#[derive(Clone)]
+pub struct PcState {
+    traits: StaticMap<Trait, bool>,
+    perks: StaticMap<Perk, u32>,
+    tagged: StaticMap<Skill, Tagged>,
+    pc_stats: StaticMap<PCStat, i32>,
+    free_perks: u32,
+}
+
 pub struct Rpg {
     stat_msgs: Messages,
     skill_msgs: Messages,
     perk_msgs: Messages,
+    trait_msgs: Messages,
     stat_defs: StaticMap<Stat, StatDef>,
     skill_defs: StaticMap<Skill, SkillDef>,
     perk_defs: StaticMap<Perk, PerkDef>,
@@ -57,6 +111,11 @@ pub struct Rpg {
     tagged: StaticMap<Skill, Tagged>,
     pc_stat_defs: StaticMap<PCStat, PCStatDef>,
     pc_stats: StaticMap<PCStat, i32>,
+    /// Number of critters of each kind killed by the dude.
+    kills: StaticMap<CritterKillKind, u32>,
+    /// Number of perks the player can pick in the character editor.
+    free_perks: u32,
+    game_difficulty: Difficulty,
 }
 
 impl Rpg {
@@ -70,6 +129,8 @@ impl Rpg {
         let perk_msgs = Messages::read_file(fs, language, "game/perk.msg")?;
         let perk_defs = PerkDef::defaults();
 
+        let trait_msgs = Messages::read_file(fs, language, "game/trait.msg")?;
+
         let mut perks = HashMap::new();
         perks.insert(ProtoId::DUDE, Default::default());
 
@@ -82,6 +143,7 @@ impl Rpg {
             stat_msgs,
             skill_msgs,
             perk_msgs,
+            trait_msgs,
             stat_defs,
             skill_defs,
             perk_defs,
@@ -90,9 +152,54 @@ impl Rpg {
             tagged: Default::default(),
             pc_stat_defs,
             pc_stats,
+            kills: Default::default(),
+            free_perks: 0,
+            game_difficulty: Difficulty::default(),
         })
     }
 
+    /// Resets the player character state for the new game.
+    pub fn reset(&mut self) {
+        self.traits = Default::default();
+        self.perks.clear();
+        self.perks.insert(ProtoId::DUDE, Default::default());
+        self.tagged = Default::default();
+        self.pc_stats = static_map! {
+            s => self.pc_stat_defs[s].default
+        };
+        self.kills = Default::default();
+        self.free_perks = 0;
+    }
+
+    pub fn game_difficulty(&self) -> Difficulty {
+        self.game_difficulty
+    }
+
+    pub fn set_game_difficulty(&mut self, difficulty: Difficulty) {
+        self.game_difficulty = difficulty;
+    }
+
+    // stat_name
+    pub fn stat_name(&self, stat: Stat) -> &bstr {
+        &self.stat_msgs.get(STAT_NAME_MSG_BASE + stat as MessageId).unwrap().text
+    }
+
+    // stat_description
+    pub fn stat_description(&self, stat: Stat) -> &bstr {
+        &self.stat_msgs.get(STAT_DESCR_MSG_BASE + stat as MessageId).unwrap().text
+    }
+
+    // stat_level_description
+    /// Returns textual description of the base stat value like "Good" or "Great".
+    pub fn stat_level_description(&self, value: i32) -> &bstr {
+        let value = clamp(value, 1, 10);
+        &self.stat_msgs.get(STAT_LEVEL_DESCR_BASE + value as MessageId).unwrap().text
+    }
+
+    pub fn stat_image_fid_id(&self, stat: Stat) -> u32 {
+        self.stat_defs[stat].image_fid_id
+    }
+
     pub fn skill_msgs(&self) -> &Messages {
         &self.skill_msgs
     }
@@ -112,6 +219,25 @@ impl Rpg {
         &self.skill_msgs.get(SKILL_FORMULA_MSG_BASE + skill as MessageId).unwrap().text
     }
 
+    pub fn skill_image_fid_id(&self, skill: Skill) -> u32 {
+        self.skill_defs[skill].image_fid_id
+    }
+
+    // trait_name
+    pub fn trait_name(&self, tr: Trait) -> &bstr {
+        &self.trait_msgs.get(TRAIT_NAME_MSG_BASE + tr as MessageId).unwrap().text
+    }
+
+    // trait_description
+    pub fn trait_description(&self, tr: Trait) -> &bstr {
+        &self.trait_msgs.get(TRAIT_DESCR_MSG_BASE + tr as MessageId).unwrap().text
+    }
+
+    // trait_pic
+    pub fn trait_image_fid_id(&self, tr: Trait) -> u32 {
+        TRAIT_IMAGE_FID_ID_BASE + tr as u32
+    }
+
     // perk_name
     pub fn perk_name(&self, perk: Perk) -> &bstr {
         &self.perk_msgs.get(PERK_NAME_MSG_BASE + perk as MessageId).unwrap().text
@@ -122,6 +248,11 @@ impl Rpg {
         &self.perk_msgs.get(PERK_DESCR_MSG_BASE + perk as MessageId).unwrap().text
     }
 
+    // perk_skilldex_fid
+    pub fn perk_image_fid_id(&self, perk: Perk) -> u32 {
+        self.perk_defs[perk].image_fid_id
+    }
+
     // stat_pc_name
     pub fn pc_stat_name(&self, pc_stat: PCStat) -> &bstr {
         &self.stat_msgs.get(PC_STAT_NAME_MSG_BASE + pc_stat as MessageId).unwrap().text
@@ -175,14 +306,169 @@ impl Rpg {
         any && all
     }
 
+    /// Starts tracking perks of the party member proto.
+    pub fn add_party_member(&mut self, pid: ProtoId) {
+        self.perks.entry(pid).or_default();
+    }
+
+    // isPartyMember()
+    /// Whether the critter is the dude or a party member. Only these have their perks tracked.
+    pub fn is_party_member(&self, obj: &Object) -> bool {
+        obj.proto_id().is_some_and(|pid| self.perks.contains_key(&pid))
+    }
+
+    pub fn set_perk(&mut self, perk: Perk, pid: ProtoId, rank: u32) {
+        self.perks.entry(pid).or_default()[perk] = rank;
+    }
+
+    // perk_add
+    /// Adds a rank of the `perk` and applies its effect. Returns `false` if the perk can't be
+    /// added.
+    pub fn add_perk(&mut self,
+        perk: Perk,
+        obj: &mut Object,
+        objs: &Objects,
+        global_vars: &[i32],
+    ) -> bool {
+        if !self.can_add_perk(perk, obj, objs, global_vars) {
+            return false;
+        }
+        let pid = obj.proto_id().unwrap();
+        let rank = self.perk(perk, pid);
+        self.set_perk(perk, pid, rank + 1);
+        self.add_perk_effect(perk, obj, objs);
+        true
+    }
+
+    /// Returns all perks that can be added to the `obj` now.
+    pub fn available_perks(&self, obj: &Object, objs: &Objects, global_vars: &[i32]) -> Vec<Perk> {
+        Perk::iter()
+            .filter(|&perk| self.can_add_perk(perk, obj, objs, global_vars))
+            .collect()
+    }
+
+    pub fn free_perks(&self) -> u32 {
+        self.free_perks
+    }
+
+    pub fn set_free_perks(&mut self, free_perks: u32) {
+        self.free_perks = free_perks;
+    }
+
+    /// Number of levels between the perks.
+    // perk_get_level_interval()
+    pub fn perk_level_interval(&self) -> u32 {
+        if self.has_trait(Trait::Skilled) { 4 } else { 3 }
+    }
+
+    pub fn traits(&self) -> impl Iterator<Item=Trait> + '_ {
+        Trait::iter().filter(|&tr| self.traits[tr])
+    }
+
     pub fn has_trait(&self, tr: Trait) -> bool {
         self.traits[tr]
     }
 
+    pub fn set_trait(&mut self, tr: Trait, value: bool) {
+        self.traits[tr] = value;
+    }
+
     pub fn is_tagged(&self, skill: Skill) -> bool {
         self.tagged[skill].tagged
     }
 
+    pub fn set_tagged(&mut self, skill: Skill, tagged: bool) {
+        self.tagged[skill].tagged = tagged;
+    }
+
+    pub fn tagged_skills(&self) -> impl Iterator<Item=Skill> + '_ {
+        Skill::iter().filter(|&skill| self.tagged[skill].tagged)
+    }
+
+    /// Returns the player character state which is affected by the character editor.
+    pub fn pc_state(&self) -> PcState {
+        PcState {
+            traits: self.traits.clone(),
+            perks: self.perks[&ProtoId::DUDE].clone(),
+            tagged: self.tagged.clone(),
+            pc_stats: self.pc_stats.clone(),
+            free_perks: self.free_perks,
+        }
+    }
+
+    pub fn set_pc_state(&mut self, state: PcState) {
+        let PcState { traits, perks, tagged, pc_stats, free_perks } = state;
+        self.traits = traits;
+        self.perks.insert(ProtoId::DUDE, perks);
+        self.tagged = tagged;
+        self.pc_stats = pc_stats;
+        self.free_perks = free_perks;
+    }
+
+    /// Returns base value of the `stat` without any modifiers.
+    pub fn base_stat(&self, stat: Stat, obj: &Object) -> i32 {
+        self.stat_base_direct(stat, obj)
+    }
+
+    /// Sets base value of the primary `stat` and recalculates the derived stats.
+    // inc_stat(), dec_stat()
+    pub fn set_base_stat(&self, stat: Stat, obj: &mut Object, value: i32, objs: &Objects) {
+        assert!(stat.is_base());
+        let def = &self.stat_defs[stat];
+        obj.proto_mut().unwrap().sub.as_critter_mut().unwrap().base_stats[stat] =
+            clamp(value, def.min, def.max);
+        self.recalc_derived_stats(obj, objs);
+    }
+
+    /// Returns the number of skill points needed to raise the skill currently at `level`.
+    // skill_inc_point()
+    pub fn skill_point_cost(level: i32) -> i32 {
+        match level {
+            ..=100 => 1,
+            101..=125 => 2,
+            126..=150 => 3,
+            151..=175 => 4,
+            176..=200 => 5,
+            _ => 6,
+        }
+    }
+
+    /// Spends unspent skill points to raise the `skill` by one point.
+    /// Returns `false` if there's not enough skill points or the skill is at max.
+    // skill_inc_point()
+    pub fn inc_skill(&mut self, skill: Skill, obj: &Object, objs: &Objects) -> bool {
+        let level = self.skill(skill, obj, objs);
+        let cost = Self::skill_point_cost(level);
+        let unspent = self.pc_stat(PCStat::UnspentSkillPoints);
+        if level >= MAX_SKILL_EDIT_LEVEL || unspent < cost {
+            return false;
+        }
+        obj.proto_mut().unwrap().sub.as_critter_mut().unwrap().skills[skill] += 1;
+        self.pc_stats[PCStat::UnspentSkillPoints] = unspent - cost;
+        true
+    }
+
+    /// Reverts a single point spent by `inc_skill()`. Returns `false` if the skill has no points.
+    // skill_dec_point()
+    pub fn dec_skill(&mut self, skill: Skill, obj: &Object, objs: &Objects) -> bool {
+        {
+            let mut proto = obj.proto_mut().unwrap();
+            let points = &mut proto.sub.as_critter_mut().unwrap().skills[skill];
+            if *points == 0 {
+                return false;
+            }
+            *points -= 1;
+        }
+        let level = self.skill(skill, obj, objs);
+        self.pc_stats[PCStat::UnspentSkillPoints] += Self::skill_point_cost(level);
+        true
+    }
+
+    /// Returns the skill points spent on the `skill`.
+    pub fn skill_points(&self, skill: Skill, obj: &Object) -> i32 {
+        obj.proto().unwrap().sub.as_critter().unwrap().skills[skill]
+    }
+
     // stat_level()
     pub fn stat(&self, stat: Stat, obj: &Object, objs: &Objects) -> i32 {
         use Perk::*;
@@ -287,12 +573,12 @@ impl Rpg {
         if obj.proto_id().unwrap().is_dude() {
             if self.tagged[skill].tagged {
                 r += level;
-            }
-            if self.tagged[skill].inc_base {
-                r += 20;
+                if self.tagged[skill].inc_base {
+                    r += 20;
+                }
             }
             r += self.trait_skill_mod(skill) + self.perk_skill_mod(skill, obj);
-            // TODO r+= skill_game_difficulty()
+            r += self.difficulty_skill_mod(skill);
         }
 
         cmp::min(r, 300)
@@ -394,7 +680,7 @@ impl Rpg {
                 + armor_stat(new_armor, stat);
             self.set_bonus_stat(stat, obj, new, objs);
         }
-        if obj.is_dude() { // TODO isPartyMember
+        if self.is_party_member(obj) {
             if let Some(old_perk) = old_armor.as_ref()
                 .and_then(|o| o.proto().unwrap().sub.as_armor().unwrap().perk)
             {
@@ -409,7 +695,7 @@ impl Rpg {
     }
 
     // perk_add_effect
-    fn add_perk_effect(&self, perk: Perk, obj: &mut Object, objs: &Objects) {
+    pub fn add_perk_effect(&self, perk: Perk, obj: &mut Object, objs: &Objects) {
         assert_eq!(obj.proto().unwrap().kind(), ExactEntityKind::Critter);
         let def = &self.perk_defs[perk];
         for &(stat, bonus) in def.stat_bonuses {
@@ -430,8 +716,8 @@ impl Rpg {
         }
     }
 
-    // perk_add_effect
-    fn remove_perk_effect(&self, perk: Perk, obj: &mut Object, objs: &Objects) {
+    // perk_remove_effect
+    pub fn remove_perk_effect(&self, perk: Perk, obj: &mut Object, objs: &Objects) {
         assert_eq!(obj.proto().unwrap().kind(), ExactEntityKind::Critter);
         let def = &self.perk_defs[perk];
         for &(stat, bonus) in def.stat_bonuses {
@@ -482,6 +768,26 @@ impl Rpg {
         self.pc_stats[pc_stat]
     }
 
+    /// Sets the PC stat without any validation or side effects. Used when restoring a saved game.
+    pub fn set_pc_stat(&mut self, pc_stat: PCStat, value: i32) {
+        self.pc_stats[pc_stat] = value;
+    }
+
+    // critter_kill_count()
+    pub fn kill_count(&self, kind: CritterKillKind) -> u32 {
+        self.kills[kind]
+    }
+
+    /// Sets the kill count without any side effects. Used when restoring a saved game.
+    pub fn set_kill_count(&mut self, kind: CritterKillKind, value: u32) {
+        self.kills[kind] = value;
+    }
+
+    // critter_kill_count_inc()
+    pub fn inc_kill_count(&mut self, kind: CritterKillKind) {
+        self.kills[kind] += 1;
+    }
+
     // stat_pc_set
     pub fn try_set_pc_stat(&mut self, pc_stat: PCStat, value: i32) -> bool {
         let def = &self.pc_stat_defs[pc_stat];
@@ -504,6 +810,150 @@ impl Rpg {
         level_experience(self.pc_stat(PCStat::Level) as u32 + 1)
     }
 
+    /// Restores up to `amount` hit points of the critter without exceeding the maximum.
+    /// Returns the number of restored hit points.
+    // critter_adjust_hits()
+    pub fn heal(&self, obj: &mut Object, amount: i32, objs: &Objects) -> i32 {
+        let max_hp = self.stat(Stat::HitPoints, obj, objs);
+        let critter = obj.sub.as_critter_mut().unwrap();
+        let old = critter.hit_points;
+        critter.hit_points = cmp::max(cmp::min(old + cmp::max(amount, 0), max_hp), old);
+        critter.hit_points - old
+    }
+
+    /// Changes poison level of the dude by `amount`. Positive amount is reduced by the poison
+    /// resistance. Returns the new poison level or `None` if `obj` is not the dude.
+    // critter_adjust_poison()
+    pub fn adjust_poison(&self, obj: &mut Object, amount: i32, objs: &Objects) -> Option<i32> {
+        if !obj.is_dude() {
+            return None;
+        }
+        let amount = if amount > 0 {
+            amount - amount * self.stat(Stat::PoisonResist, obj, objs) / 100
+        } else {
+            amount
+        };
+        let critter = obj.sub.as_critter_mut().unwrap();
+        critter.poison = cmp::max(critter.poison + amount, 0);
+        Some(critter.poison)
+    }
+
+    /// Changes rads of the dude by `amount`. Positive amount is reduced by the radiation
+    /// resistance. Returns the actual change or `None` if `obj` is not the dude.
+    // critter_adjust_rads()
+    pub fn adjust_radiation(&self, obj: &mut Object, amount: i32, objs: &Objects) -> Option<i32> {
+        if !obj.is_dude() {
+            return None;
+        }
+        let amount = if amount > 0 {
+            amount - amount * self.stat(Stat::RadResist, obj, objs) / 100
+        } else {
+            amount
+        };
+        let critter = obj.sub.as_critter_mut().unwrap();
+        let old = critter.radiation;
+        critter.radiation = cmp::max(old + amount, 0);
+        Some(critter.radiation - old)
+    }
+
+    /// Returns level of the radiation sickness the critter is going to get from the current
+    /// rads. Failed endurance roll makes it one level worse.
+    // critter_check_rads()
+    pub fn roll_radiation_level(&self, obj: &Object, objs: &Objects) -> u32 {
+        let rads = obj.sub.as_critter().unwrap().radiation;
+        let level = radiation_level(rads);
+        let bonus = RADIATION_ENDURANCE_MODS[level as usize];
+        if self.roll_check_stat(Stat::Endurance, bonus, obj, objs).0.is_success() {
+            level
+        } else {
+            cmp::min(level + 1, RADIATION_LEVEL_RADS.len() as u32)
+        }
+    }
+
+    /// Applies effects of the radiation sickness of `level` or removes them if `healing` is
+    /// `true`. Lost hit points are not restored when healing. Returns `true` if the sickness is
+    /// deadly.
+    // process_rads()
+    pub fn apply_radiation_effects(&self,
+        obj: &mut Object,
+        level: u32,
+        healing: bool,
+        objs: &Objects,
+    ) -> bool {
+        let Some(effects) = level.checked_sub(1).and_then(|i| RADIATION_EFFECTS.get(i as usize))
+        else {
+            return false;
+        };
+        let sign = if healing { -1 } else { 1 };
+        for (&stat, &v) in RADIATION_EFFECT_STATS.iter().zip(effects) {
+            if stat == Stat::CurrentHitPoints {
+                if !healing {
+                    let critter = obj.sub.as_critter_mut().unwrap();
+                    critter.hit_points = cmp::max(critter.hit_points + v, 0);
+                }
+            } else {
+                let bonus = self.bonus_stat(stat, obj);
+                self.set_bonus_stat(stat, obj, bonus + sign * v, objs);
+            }
+        }
+        !healing && RADIATION_EFFECT_STATS.iter()
+            .filter(|&&stat| stat != Stat::HealRate)
+            .any(|&stat| self.stat(stat, obj, objs) <= 0)
+    }
+
+    /// Adds experience points to the player character gaining levels if needed. Each level
+    /// gives skill points, hit points and possibly a free perk. Returns the number of levels
+    /// gained.
+    // stat_pc_add_experience()
+    pub fn add_experience(&mut self, xp: i32, dude: &mut Object, objs: &Objects) -> u32 {
+        let xp = xp + xp * 5 * self.perk(Perk::SwiftLearner, ProtoId::DUDE) as i32 / 100;
+        let exp_def = &self.pc_stat_defs[PCStat::Experience];
+        self.pc_stats[PCStat::Experience] = clamp(self.pc_stat(PCStat::Experience) + xp,
+            exp_def.min, exp_def.max);
+
+        let max_level = self.pc_stat_defs[PCStat::Level].max;
+        let mut levels = 0;
+        while self.pc_stat(PCStat::Level) < max_level
+            && self.pc_stat(PCStat::Experience) as u32 >= self.next_level_experience()
+        {
+            self.pc_stats[PCStat::Level] += 1;
+            levels += 1;
+
+            let skill_points = 5
+                + 2 * self.stat(Stat::Intelligence, dude, objs)
+                + 2 * self.perk(Perk::Educated, ProtoId::DUDE) as i32
+                + if self.has_trait(Trait::Skilled) { 5 } else { 0 }
+                - if self.has_trait(Trait::Gifted) { 5 } else { 0 };
+            self.pc_stats[PCStat::UnspentSkillPoints] += cmp::max(skill_points, 1);
+
+            if (self.pc_stat(PCStat::Level) as u32).is_multiple_of(self.perk_level_interval()) {
+                self.free_perks += 1;
+            }
+
+            let hp = 2 + self.stat(Stat::Endurance, dude, objs) / 2
+                + 4 * self.perk(Perk::Lifegiver, ProtoId::DUDE) as i32;
+            let bonus = self.bonus_stat(Stat::HitPoints, dude);
+            self.set_bonus_stat(Stat::HitPoints, dude, bonus + hp, objs);
+            dude.sub.as_critter_mut().unwrap().hit_points += hp;
+        }
+        levels
+    }
+
+    // skill_game_difficulty()
+    fn difficulty_skill_mod(&self, skill: Skill) -> i32 {
+        use Skill::*;
+        match skill {
+            | FirstAid | Doctor | Sneak | Lockpick | Steal | Traps | Science | Repair
+            | Conversant | Barter | Gambling | Outdoorsman
+            => match self.game_difficulty {
+                Difficulty::Easy => 20,
+                Difficulty::Normal => 0,
+                Difficulty::Hard => -10,
+            }
+            _ => 0,
+        }
+    }
+
     // trait_adjust_skill
     fn trait_skill_mod(&self, skill: Skill) -> i32 {
         let mut r = 0;
@@ -585,12 +1035,32 @@ impl Rpg {
         obj.proto().unwrap().sub.as_critter().unwrap().bonus_stats[stat]
     }
 
+    /// Adds `v` to the bonus of the stat. Current hit points, poison and rads don't have a bonus
+    /// and are changed directly.
+    pub fn add_bonus_stat(&self, stat: Stat, obj: &mut Object, v: i32, objs: &Objects) {
+        let v = match stat {
+            Stat::CurrentHitPoints | Stat::CurrentPoison | Stat::CurrentRad => v,
+            _ => self.bonus_stat(stat, obj) + v,
+        };
+        self.set_bonus_stat(stat, obj, v, objs);
+    }
+
+    /// Sets the bonus of the stat. For current hit points, poison and rads `v` is the amount to
+    /// change them by.
     // stat_set_bonus
     fn set_bonus_stat(&self, stat: Stat, obj: &mut Object, v: i32, objs: &Objects) {
         match stat {
-            Stat::CurrentHitPoints => unimplemented!("TODO"),
-            Stat::CurrentPoison => unimplemented!("TODO"),
-            Stat::CurrentRad => unimplemented!("TODO"),
+            Stat::CurrentHitPoints => {
+                let max_hp = self.stat(Stat::HitPoints, obj, objs);
+                let critter = obj.sub.as_critter_mut().unwrap();
+                critter.hit_points = (critter.hit_points + v).clamp(0, cmp::max(max_hp, 0));
+            }
+            Stat::CurrentPoison => {
+                self.adjust_poison(obj, v, objs);
+            }
+            Stat::CurrentRad => {
+                self.adjust_radiation(obj, v, objs);
+            }
             _ => {
                 obj.proto_mut().unwrap()
                     .sub.as_critter_mut().unwrap().bonus_stats[stat] = v;
@@ -602,6 +1072,11 @@ impl Rpg {
     }
 }
 
+/// Returns radiation level for the rads. Level 0 means no radiation sickness.
+pub fn radiation_level(rads: i32) -> u32 {
+    RADIATION_LEVEL_RADS.iter().take_while(|&&v| rads >= v).count() as u32
+}
+
 pub fn level_experience(level: u32) -> u32 {
     try_level_experience(level).expect("level experience overflow/underflow")
 }
@@ -614,6 +1089,86 @@ fn try_level_experience(level: u32) -> Option<u32> {
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::game::object::{self, Objects};
+    use crate::graphics::geometry::hex::TileGrid;
+    use crate::graphics::Point;
+    use crate::util::test::Assets;
+
+    fn new_dude(assets: &Assets, rpg: &Rpg) -> (Objects, object::Handle) {
+        let mut objs = Objects::new(TileGrid::default(), 3, assets.frm_db.clone(),
+            assets.proto_db.clone());
+        let dude = objs.create(None, Some(assets.proto_db.dude()),
+            Some(Point::new(12, 34).elevated(0)), Some(rpg)).handle();
+        (objs, dude)
+    }
+
+    #[test]
+    fn radiation_level_() {
+        assert_eq!(radiation_level(0), 0);
+        assert_eq!(radiation_level(99), 0);
+        assert_eq!(radiation_level(100), 1);
+        assert_eq!(radiation_level(599), 3);
+        assert_eq!(radiation_level(1000), 5);
+        assert_eq!(radiation_level(5000), 5);
+    }
+
+    #[test]
+    fn skill_point_cost() {
+        let f = Rpg::skill_point_cost;
+        assert_eq!(f(0), 1);
+        assert_eq!(f(100), 1);
+        assert_eq!(f(101), 2);
+        assert_eq!(f(150), 3);
+        assert_eq!(f(175), 4);
+        assert_eq!(f(176), 5);
+        assert_eq!(f(201), 6);
+    }
+
+    #[test]
+    fn inc_dec_skill() {
+        let assets = Assets::new();
+        let mut rpg = assets.rpg();
+        let (objs, dude) = new_dude(&assets, &rpg);
+        let dude = &objs.get(dude);
+        dude.proto_mut().unwrap().sub.as_critter_mut().unwrap().skills[Skill::Lockpick] = 0;
+        rpg.set_pc_stat(PCStat::UnspentSkillPoints, 1);
+
+        let level = rpg.skill(Skill::Lockpick, dude, &objs);
+        assert!(rpg.inc_skill(Skill::Lockpick, dude, &objs));
+        assert_eq!(rpg.skill(Skill::Lockpick, dude, &objs), level + 1);
+        assert_eq!(rpg.skill_points(Skill::Lockpick, dude), 1);
+        assert_eq!(rpg.pc_stat(PCStat::UnspentSkillPoints), 0);
+        assert!(!rpg.inc_skill(Skill::Lockpick, dude, &objs));
+
+        assert!(rpg.dec_skill(Skill::Lockpick, dude, &objs));
+        assert_eq!(rpg.skill(Skill::Lockpick, dude, &objs), level);
+        assert_eq!(rpg.pc_stat(PCStat::UnspentSkillPoints), 1);
+        assert!(!rpg.dec_skill(Skill::Lockpick, dude, &objs));
+    }
+
+    #[test]
+    fn add_experience() {
+        let assets = Assets::new();
+        let mut rpg = assets.rpg();
+        let (objs, dude) = new_dude(&assets, &rpg);
+        let dude = &mut objs.get_mut(dude);
+        let int = rpg.stat(Stat::Intelligence, dude, &objs);
+        let end = rpg.stat(Stat::Endurance, dude, &objs);
+        let max_hp = rpg.stat(Stat::HitPoints, dude, &objs);
+        let hp = dude.sub.as_critter().unwrap().hit_points;
+
+        assert_eq!(rpg.add_experience(999, dude, &objs), 0);
+        assert_eq!(rpg.pc_stat(PCStat::Level), 1);
+
+        assert_eq!(rpg.add_experience(2001, dude, &objs), 2);
+        assert_eq!(rpg.pc_stat(PCStat::Level), 3);
+        assert_eq!(rpg.pc_stat(PCStat::Experience), 3000);
+        assert_eq!(rpg.pc_stat(PCStat::UnspentSkillPoints), 2 * cmp::max(5 + 2 * int, 1));
+        assert_eq!(rpg.free_perks(), 1);
+        let hp_per_level = 2 + end / 2;
+        assert_eq!(rpg.stat(Stat::HitPoints, dude, &objs), max_hp + 2 * hp_per_level);
+        assert_eq!(dude.sub.as_critter().unwrap().hit_points, hp + 2 * hp_per_level);
+    }
 
     #[test]
     fn try_level_experience_() {
@@ -0,0 +1,251 @@
+use bstring::BString;
+use log::*;
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use crate::asset::frame::FrameId;
+use crate::asset::message::{MessageId, Messages};
+use crate::game::savegame::{self, Header, THUMBNAIL_HEIGHT, THUMBNAIL_WIDTH};
+use crate::graphics::Rect;
+use crate::graphics::color::{Rgb15, GREEN};
+use crate::graphics::font::{DrawOptions, FontKey, HorzAlign};
+use crate::graphics::sprite::Sprite;
+use crate::ui::*;
+use crate::ui::button::Button;
+use crate::ui::command::UiCommandData;
+use crate::ui::command::save_load::Command;
+use crate::ui::message_panel::{MessagePanel, MouseControl};
+use crate::ui::panel::{self, Panel};
+use crate::ui::picture::Picture;
+
+pub const SLOT_COUNT: usize = 10;
+
+const MSG_LOAD_TITLE: MessageId = 100;
+const MSG_SAVE_TITLE: MessageId = 102;
+const MSG_DONE: MessageId = 104;
+const MSG_CANCEL: MessageId = 105;
+const MSG_SLOT: MessageId = 109;
+const MSG_EMPTY: MessageId = 111;
+
+const TEXT_FONT: FontKey = FontKey::antialiased(1);
+const TITLE_FONT: FontKey = FontKey::antialiased(3);
+const BUTTON_TEXT_COLOR: Rgb15 = unsafe { Rgb15::rgb15_from_packed_unchecked(0x4a23) };
+const SELECTED_COLOR: Rgb15 = unsafe { Rgb15::rgb15_from_packed_unchecked(0x7feb) };
+const HIGHLIGHT_COLOR: Rgb15 = unsafe { Rgb15::rgb15_from_packed_unchecked(0x7fff) };
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Mode {
+    Save,
+    Load,
+}
+
+/// Returns directory of the save slot like `savegame/slot01`.
+pub fn slot_dir(save_dir: &Path, slot: usize) -> PathBuf {
+    save_dir.join(format!("slot{:02}", slot + 1))
+}
+
+fn read_header(dir: &Path) -> io::Result<Header> {
+    Header::read(&mut BufReader::new(File::open(dir.join(savegame::SAVE_DAT))?))
+}
+
+struct Internal {
+    window: Handle,
+    mode: Mode,
+    list: Handle,
+    thumbnail: Handle,
+    info: Handle,
+    selected: usize,
+    headers: Vec<Option<Header>>,
+}
+
+/// Save and load game screen listing the save slots with the thumbnail and description of the
+/// selected one.
+pub struct SaveLoad {
+    msgs: Rc<Messages>,
+    save_dir: PathBuf,
+    internal: Option<Internal>,
+}
+
+impl SaveLoad {
+    pub fn new(msgs: Rc<Messages>, save_dir: PathBuf) -> Self {
+        Self {
+            msgs,
+            save_dir,
+            internal: None,
+        }
+    }
+
+    pub fn is_visible(&self) -> bool {
+        self.internal.is_some()
+    }
+
+    pub fn mode(&self) -> Option<Mode> {
+        self.internal.as_ref().map(|i| i.mode)
+    }
+
+    /// Returns `true` if the `widget` belongs to the screen.
+    pub fn is(&self, widget: Handle, ui: &Ui) -> bool {
+        self.internal.as_ref().is_some_and(|i| ui.window_of(widget) == Some(i.window))
+    }
+
+    // SaveGame(), LoadGame()
+    pub fn show(&mut self, mode: Mode, ui: &mut Ui) {
+        assert!(self.internal.is_none());
+
+        let window = ui.new_window(Rect::with_size(0, 0, 640, 480),
+            Some(Sprite::new(FrameId::LSGAME)));
+        ui.widget_base_mut(window).set_modal(true);
+
+        let mut title = Panel::new();
+        title.set_text(Some(panel::Text {
+            text: self.msg(match mode {
+                Mode::Save => MSG_SAVE_TITLE,
+                Mode::Load => MSG_LOAD_TITLE,
+            }),
+            font: TITLE_FONT,
+            color: GREEN,
+            options: Default::default(),
+        }));
+        ui.new_widget(window, Rect::with_size(48, 27, 200, 20), None, None, title);
+
+        let thumbnail = ui.new_widget(window,
+            Rect::with_size(366, 58, THUMBNAIL_WIDTH as i32, THUMBNAIL_HEIGHT as i32), None,
+            None, Picture::new(ui.frm_db().texture_factory().clone()));
+        let info = ui.new_widget(window, Rect::with_size(396, 210, 164, 120), None, None,
+            Panel::new());
+
+        let mut list = MessagePanel::new(ui.fonts().clone(), TEXT_FONT, GREEN);
+        list.set_mouse_control(MouseControl::Pick);
+        list.set_highlight_color(HIGHLIGHT_COLOR);
+        list.set_message_spacing(2);
+        let list = ui.new_widget(window, Rect::with_size(55, 87, 230, 353), None, None, list);
+
+        for (x, cmd, msg) in [
+            (391, Command::Done, MSG_DONE),
+            (495, Command::Cancel, MSG_CANCEL),
+        ] {
+            ui.new_widget(window, Rect::with_size(x, 349, 15, 16), None, None,
+                Button::new(FrameId::SMALL_RED_BUTTON_UP, FrameId::SMALL_RED_BUTTON_DOWN,
+                    Some(UiCommandData::SaveLoad(cmd))));
+            let mut label = Panel::new();
+            label.set_text(Some(panel::Text {
+                text: self.msg(msg),
+                font: TITLE_FONT,
+                color: BUTTON_TEXT_COLOR,
+                options: Default::default(),
+            }));
+            ui.new_widget(window, Rect::with_size(x + 18, 349, 80, 16), None, None, label);
+        }
+
+        let headers = (0..SLOT_COUNT)
+            .map(|slot| {
+                let dir = slot_dir(&self.save_dir, slot);
+                match read_header(&dir) {
+                    Ok(h) => Some(h),
+                    Err(e) => {
+                        if e.kind() != io::ErrorKind::NotFound {
+                            warn!("couldn't read save slot {}: {}", dir.display(), e);
+                        }
+                        None
+                    }
+                }
+            })
+            .collect();
+
+        self.internal = Some(Internal {
+            window,
+            mode,
+            list,
+            thumbnail,
+            info,
+            selected: 0,
+            headers,
+        });
+
+        self.select(0, ui);
+    }
+
+    pub fn hide(&mut self, ui: &mut Ui) {
+        if let Some(internal) = self.internal.take() {
+            ui.remove(internal.window);
+        }
+    }
+
+    pub fn select(&mut self, slot: usize, ui: &mut Ui) {
+        if slot >= SLOT_COUNT {
+            return;
+        }
+        let empty = self.msg(MSG_EMPTY);
+        let slot_fmt = self.msg(MSG_SLOT);
+        let internal = self.internal.as_mut().unwrap();
+        internal.selected = slot;
+
+        {
+            let mut list = ui.widget_mut::<MessagePanel>(internal.list);
+            list.clear_messages();
+            for (i, header) in internal.headers.iter().enumerate() {
+                let mut s = BString::new();
+                s.push_str(format!("[ {} {:02} ]: ", slot_fmt.display(), i + 1));
+                s.push_str(header.as_ref().map(|h| &h.description).unwrap_or(&empty));
+                list.push_message_with_color(s, (i == slot).then_some(SELECTED_COLOR));
+            }
+        }
+
+        let header = internal.headers[slot].as_ref();
+
+        {
+            let mut pic = ui.widget_mut::<Picture>(internal.thumbnail);
+            if let Some(h) = header {
+                pic.set(THUMBNAIL_WIDTH as i32, THUMBNAIL_HEIGHT as i32, h.thumbnail.clone());
+            } else {
+                pic.clear();
+            }
+        }
+
+        let info = header.map(|h| {
+            let (day, month, year) = h.file_date;
+            let mut s = h.character_name.clone();
+            s.push(b'\n');
+            s.push_str(&h.description);
+            s.push(b'\n');
+            s.push_str(format!("{:02}/{:02}/{}  {:02}:{:02}",
+                day, month, year, h.file_time / 100, h.file_time % 100));
+            s.push(b'\n');
+            s.push_str(&h.map_file_name);
+            s
+        }).unwrap_or_default();
+        ui.widget_mut::<Panel>(internal.info).set_text(Some(panel::Text {
+            text: info,
+            font: TEXT_FONT,
+            color: GREEN,
+            options: DrawOptions {
+                horz_align: HorzAlign::Center,
+                ..Default::default()
+            },
+        }));
+    }
+
+    /// Returns the directory and header of the selected slot.
+    pub fn selected(&self) -> Option<(PathBuf, Option<&Header>)> {
+        let internal = self.internal.as_ref()?;
+        Some((slot_dir(&self.save_dir, internal.selected),
+            internal.headers[internal.selected].as_ref()))
+    }
+
+    fn msg(&self, id: MessageId) -> BString {
+        self.msgs.get(id).map(|m| m.text.clone()).unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn slot_dir_() {
+        assert_eq!(slot_dir(Path::new("savegame"), 0), Path::new("savegame/slot01"));
+        assert_eq!(slot_dir(Path::new("savegame"), 9), Path::new("savegame/slot10"));
+    }
+}
@@ -0,0 +1,1077 @@
+use bstring::{bstr, BString};
+use byteorder::{BigEndian, ByteOrder, ReadBytesExt, WriteBytesExt};
+use flate2::Compression;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use log::*;
+use num_traits::FromPrimitive;
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Error, ErrorKind, prelude::*};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::asset::{self, Perk, PCStat, Skill, Stat, Trait};
+use crate::asset::frame::FrameDb;
+use crate::asset::map::{MapId, MapReader, MapWriter};
+use crate::asset::proto::{self, CritterKillKind, ProtoDb, ProtoId};
+use crate::fs::FileSystem;
+use crate::game::GameTime;
+use crate::game::object::{Dude, Hand, Handle, Objects};
+use crate::game::queue::Queue;
+use crate::game::rpg::Rpg;
+use crate::game::script::Scripts;
+use crate::graphics::Rect;
+use crate::profile::Profile;
+use crate::util::EnumExt;
+
+pub const SIGNATURE: &[u8] = b"FALLOUT SAVE FILE";
+pub const SAVE_DAT: &str = "SAVE.DAT";
+pub const MAPS_DIR: &str = "MAPS";
+pub const THUMBNAIL_WIDTH: usize = 224;
+pub const THUMBNAIL_HEIGHT: usize = 133;
+
+const SIGNATURE_LEN: usize = 24;
+const CHARACTER_NAME_LEN: usize = 32;
+const DESCRIPTION_LEN: usize = 30;
+const MAP_FILE_NAME_LEN: usize = 16;
+const HEADER_PADDING_LEN: usize = 128;
+/// Number of stats stored in the critter proto data.
+const SAVEABLE_STAT_COUNT: usize = 35;
+const KILL_KIND_COUNT: usize = 19;
+const TAGGED_SKILL_COUNT: usize = 4;
+const SELECTED_TRAIT_COUNT: usize = 2;
+const AI_PACKET_FIELD_COUNT: usize = 6;
+const COMBAT_STATE_IN_COMBAT: u32 = 0x1;
+const COMBAT_STATE_NOT_IN_COMBAT: u32 = 0x2;
+const AUTOMAP_LEN: usize = 4;
+/// 13 ints, text delay (float), 4 volumes (ints), brightness and mouse sensitivity (floats).
+const PREFERENCES_LEN: usize = 20 * 4;
+/// Last level (int) and free perk (byte).
+const CHARACTER_EDITOR_LEN: usize = 5;
+/// Frank Horrigan met, area, position, encounter icon and map/table/entry, car and its area
+/// and fuel.
+const WORLD_MAP_GEN_FIELD_COUNT: usize = 11;
+/// Position, state and visited state. Followed by the entrance states.
+const WORLD_MAP_AREA_FIELD_COUNT: usize = 4;
+const WORLD_MAP_SUBTILE_COUNT: usize = 7 * 6;
+/// Encounter table, entry and counter.
+const WORLD_MAP_COUNTER_FIELD_COUNT: usize = 3;
+const PIPBOY_LEN: usize = 4;
+const MOVIE_COUNT: usize = 17;
+const SKILL_USES_PER_DAY: usize = 3;
+/// Level, level-ups and early flag.
+const PARTY_LEVEL_UP_FIELD_COUNT: usize = 3;
+/// Enabled, hidden, current hand and end buttons visible.
+const INTERFACE_LEN: usize = 4 * 4;
+
+/// Fixed-size header of the `SAVE.DAT` file shown in the load/save game screens.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Header {
+    pub version_major: u16,
+    pub version_minor: u16,
+    pub release: u8,
+    pub character_name: BString,
+    pub description: BString,
+    /// Real world date (day, month, year) the game was saved at.
+    pub file_date: (u16, u16, u16),
+    /// Real world time in `hours * 100 + minutes` form.
+    pub file_time: u32,
+    pub game_time: GameTime,
+    pub elevation: u32,
+    pub map_id: MapId,
+    /// File name of the current map save file like `ARTEMPLE.SAV`.
+    pub map_file_name: BString,
+    /// 8-bit palette-indexed picture `THUMBNAIL_WIDTH` x `THUMBNAIL_HEIGHT`.
+    pub thumbnail: Box<[u8]>,
+}
+
+impl Header {
+    pub fn new(
+        character_name: BString,
+        description: BString,
+        game_time: GameTime,
+        elevation: u32,
+        map_id: MapId,
+        map_file_name: BString,
+    ) -> Self {
+        let (file_date, file_time) = utc_date_time();
+        Self {
+            version_major: 2,
+            version_minor: 1,
+            release: b'R',
+            character_name,
+            description,
+            file_date,
+            file_time,
+            game_time,
+            elevation,
+            map_id,
+            map_file_name,
+            thumbnail: vec![0; THUMBNAIL_WIDTH * THUMBNAIL_HEIGHT].into(),
+        }
+    }
+
+    pub fn read(rd: &mut impl Read) -> io::Result<Self> {
+        let signature = read_str(rd, SIGNATURE_LEN)?;
+        if signature.as_bytes() != SIGNATURE {
+            return Err(Error::new(ErrorKind::InvalidData, "invalid savegame signature"));
+        }
+        let version_minor = rd.read_u16::<BigEndian>()?;
+        let version_major = rd.read_u16::<BigEndian>()?;
+        let release = rd.read_u8()?;
+        let character_name = read_str(rd, CHARACTER_NAME_LEN)?;
+        let description = read_str(rd, DESCRIPTION_LEN)?;
+        let file_day = rd.read_u16::<BigEndian>()?;
+        let file_month = rd.read_u16::<BigEndian>()?;
+        let file_year = rd.read_u16::<BigEndian>()?;
+        let file_time = rd.read_u32::<BigEndian>()?;
+        // Game month, day and year are derived from the game time.
+        rd.read_exact(&mut [0; 3 * 2])?;
+        let game_time = GameTime::from_decis(rd.read_u32::<BigEndian>()?);
+        let elevation = rd.read_u16::<BigEndian>()? as u32;
+        let map_id = rd.read_u16::<BigEndian>()? as u32;
+        let map_file_name = read_str(rd, MAP_FILE_NAME_LEN)?;
+        let mut thumbnail = vec![0; THUMBNAIL_WIDTH * THUMBNAIL_HEIGHT];
+        rd.read_exact(&mut thumbnail)?;
+        rd.read_exact(&mut [0; HEADER_PADDING_LEN])?;
+        Ok(Self {
+            version_major,
+            version_minor,
+            release,
+            character_name,
+            description,
+            file_date: (file_day, file_month, file_year),
+            file_time,
+            game_time,
+            elevation,
+            map_id,
+            map_file_name,
+            thumbnail: thumbnail.into(),
+        })
+    }
+
+    pub fn write(&self, wr: &mut impl Write) -> io::Result<()> {
+        assert_eq!(self.thumbnail.len(), THUMBNAIL_WIDTH * THUMBNAIL_HEIGHT);
+        write_str(wr, SIGNATURE.into(), SIGNATURE_LEN)?;
+        wr.write_u16::<BigEndian>(self.version_minor)?;
+        wr.write_u16::<BigEndian>(self.version_major)?;
+        wr.write_u8(self.release)?;
+        write_str(wr, &self.character_name, CHARACTER_NAME_LEN)?;
+        write_str(wr, &self.description, DESCRIPTION_LEN)?;
+        let (day, month, year) = self.file_date;
+        wr.write_u16::<BigEndian>(day)?;
+        wr.write_u16::<BigEndian>(month)?;
+        wr.write_u16::<BigEndian>(year)?;
+        wr.write_u32::<BigEndian>(self.file_time)?;
+        wr.write_u16::<BigEndian>(self.game_time.month() as u16)?;
+        wr.write_u16::<BigEndian>(self.game_time.day() as u16)?;
+        wr.write_u16::<BigEndian>(self.game_time.year())?;
+        wr.write_u32::<BigEndian>(self.game_time.as_decis())?;
+        wr.write_u16::<BigEndian>(self.elevation as u16)?;
+        wr.write_u16::<BigEndian>(self.map_id as u16)?;
+        write_str(wr, &self.map_file_name, MAP_FILE_NAME_LEN)?;
+        wr.write_all(&self.thumbnail)?;
+        wr.write_all(&[0; HEADER_PADDING_LEN])?;
+        Ok(())
+    }
+}
+
+/// Record counts of the `SAVE.DAT` sections which depend on the game data files.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Layout {
+    pub global_var_count: usize,
+    /// PIDs of all possible party members as listed in `data/party.txt`. The first one is the dude.
+    pub party_member_pids: Vec<ProtoId>,
+    pub ai_packet_count: usize,
+}
+
+impl Layout {
+    pub fn read(fs: &FileSystem) -> io::Result<Self> {
+        let global_var_count =
+            asset::read_game_global_vars(&mut fs.reader("data/vault13.gam")?)?.len();
+
+        let mut party_member_pids = Vec::new();
+        if fs.exists("data/party.txt") {
+            let ini = asset::read_ini(&mut fs.reader("data/party.txt")?)?;
+            for i in 0.. {
+                let Some(section) = ini.get(&format!("Party Member {}", i)) else { break };
+                let pid = section.get("party_member_pid")
+                    .and_then(|s| s.trim().parse::<u32>().ok())
+                    .and_then(ProtoId::from_packed)
+                    .ok_or_else(|| Error::new(ErrorKind::InvalidData,
+                        format!("invalid party_member_pid in party.txt section {}", i)))?;
+                party_member_pids.push(pid);
+            }
+        }
+        if party_member_pids.first() != Some(&ProtoId::DUDE) {
+            party_member_pids.insert(0, ProtoId::DUDE);
+        }
+
+        let ai_packet_count = if fs.exists("data/ai.txt") {
+            asset::read_ini(&mut fs.reader("data/ai.txt")?)?.len()
+        } else {
+            0
+        };
+
+        Ok(Self {
+            global_var_count,
+            party_member_pids,
+            ai_packet_count,
+        })
+    }
+}
+
+/// Contents of the `SAVE.DAT` that are not applied directly to the game state.
+pub struct SaveDat {
+    pub header: Header,
+    /// File names of the map saves in the `MAPS` directory.
+    pub map_files: Vec<BString>,
+    pub dude: Handle,
+    pub passthrough: Passthrough,
+}
+
+/// `SAVE.DAT` sections the game state doesn't model. They're kept as read and written back
+/// unchanged.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Passthrough {
+    /// Combat ID of the dude.
+    pub dude_cid: i32,
+    /// Size of the `AUTOMAP.DB` file in the save slot.
+    pub automap_db_len: i32,
+    pub sneak_working: i32,
+    /// AI packet preferences, `AI_PACKET_FIELD_COUNT` values per packet. If empty all values
+    /// are written as zeros.
+    pub ai_packets: Vec<i32>,
+    /// Raw sections between the traits and the event queue: automap, preferences, character
+    /// editor, world map, Pip-Boy, movies, skill usage and party members. If empty the defaults
+    /// are written.
+    pub pre_queue: Vec<u8>,
+    /// Raw sections following the event queue: the interface bar. If empty the defaults are
+    /// written.
+    pub post_queue: Vec<u8>,
+}
+
+impl Default for Passthrough {
+    fn default() -> Self {
+        Self {
+            dude_cid: -1,
+            automap_db_len: 0,
+            sneak_working: 0,
+            ai_packets: Vec::new(),
+            pre_queue: Vec::new(),
+            post_queue: Vec::new(),
+        }
+    }
+}
+
+/// Reads `SAVE.DAT` restoring the dude object into `objects`, global vars and event queue into
+/// `scripts`, and dude's stats, perks, traits and tagged skills into `rpg` and the dude proto.
+///
+/// The sections that aren't modelled (automap, AI packets, preferences, world map, Pip-Boy etc)
+/// are kept in `SaveDat::passthrough`.
+pub struct SaveDatReader<'a, R: 'a> {
+    pub reader: &'a mut R,
+    pub layout: &'a Layout,
+    pub objects: &'a mut Objects,
+    pub proto_db: &'a ProtoDb,
+    pub frm_db: &'a FrameDb,
+    pub scripts: &'a mut Scripts,
+    pub rpg: &'a mut Rpg,
+}
+
+impl<'a, R: 'a + Read> SaveDatReader<'a, R> {
+    pub fn read(&mut self) -> io::Result<SaveDat> {
+        let header = Header::read(self.reader)?;
+
+        let dude_cid = self.reader.read_i32::<BigEndian>()?;
+
+        self.scripts.vars.global_vars = self.read_global_vars()?;
+
+        let map_file_count = self.reader.read_i32::<BigEndian>()?;
+        let mut map_files = Vec::with_capacity(map_file_count.max(0) as usize);
+        for _ in 0..map_file_count {
+            map_files.push(read_c_str(self.reader)?);
+        }
+        let automap_db_len = self.reader.read_i32::<BigEndian>()?;
+
+        // Global vars are stored twice.
+        let _ = self.read_global_vars()?;
+
+        let dude = self.read_dude()?;
+        let _center_tile = self.reader.read_i32::<BigEndian>()?;
+
+        let sneak_working = self.reader.read_i32::<BigEndian>()?;
+        self.read_dude_proto()?;
+
+        for i in 0..KILL_KIND_COUNT {
+            let count = self.reader.read_i32::<BigEndian>()?;
+            self.rpg.set_kill_count(CritterKillKind::from_usize(i).unwrap(), count.max(0) as u32);
+        }
+
+        for skill in Skill::iter() {
+            self.rpg.set_tagged(skill, false);
+        }
+        for _ in 0..TAGGED_SKILL_COUNT {
+            if let Some(skill) = read_opt_enum::<Skill>(self.reader, "invalid tagged skill")? {
+                self.rpg.set_tagged(skill, true);
+            }
+        }
+
+        // There's no data in the random section.
+
+        for &pid in &self.layout.party_member_pids {
+            for perk in Perk::iter() {
+                let rank = self.reader.read_i32::<BigEndian>()?;
+                self.rpg.set_perk(perk, pid, rank.max(0) as u32);
+            }
+        }
+
+        let combat_state = self.reader.read_u32::<BigEndian>()?;
+        if combat_state & COMBAT_STATE_IN_COMBAT != 0 {
+            return Err(Error::new(ErrorKind::InvalidData,
+                "games saved during combat are not supported"));
+        }
+
+        let mut ai_packets = vec![0; self.layout.ai_packet_count * AI_PACKET_FIELD_COUNT];
+        self.reader.read_i32_into::<BigEndian>(&mut ai_packets)?;
+
+        for pc_stat in PCStat::iter() {
+            let v = self.reader.read_i32::<BigEndian>()?;
+            self.rpg.set_pc_stat(pc_stat, v);
+        }
+
+        // There's no data in the items section.
+
+        for tr in Trait::iter() {
+            self.rpg.set_trait(tr, false);
+        }
+        for _ in 0..SELECTED_TRAIT_COUNT {
+            if let Some(tr) = read_opt_enum::<Trait>(self.reader, "invalid trait")? {
+                self.rpg.set_trait(tr, true);
+            }
+        }
+
+        let pre_queue = self.read_pre_queue()?;
+
+        self.scripts.queue = Queue::read(self.reader, dude)?;
+
+        let mut post_queue = Vec::new();
+        self.reader.read_to_end(&mut post_queue)?;
+
+        self.proto_db.dude().borrow_mut().set_name(header.character_name.clone());
+
+        Ok(SaveDat {
+            header,
+            map_files,
+            dude,
+            passthrough: Passthrough {
+                dude_cid,
+                automap_db_len,
+                sneak_working,
+                ai_packets,
+                pre_queue,
+                post_queue,
+            },
+        })
+    }
+
+    /// Reads raw sections preceding the event queue. Only the counts needed to find where the
+    /// variable-length sections end are decoded.
+    fn read_pre_queue(&mut self) -> io::Result<Vec<u8>> {
+        let mut rd = RawReader {
+            reader: self.reader,
+            buf: Vec::new(),
+        };
+
+        rd.bytes(AUTOMAP_LEN + PREFERENCES_LEN + CHARACTER_EDITOR_LEN)?;
+
+        // world map
+        rd.i32s(WORLD_MAP_GEN_FIELD_COUNT)?;
+        let area_count = rd.count("world map area")?;
+        for _ in 0..area_count {
+            rd.i32s(WORLD_MAP_AREA_FIELD_COUNT)?;
+            let entrance_count = rd.count("world map area entrance")?;
+            rd.i32s(entrance_count)?;
+        }
+        let tile_count = rd.count("world map tile")?;
+        // tiles per row
+        rd.i32s(1)?;
+        rd.i32s(tile_count * WORLD_MAP_SUBTILE_COUNT)?;
+        let counter_count = rd.count("encounter counter")?;
+        rd.i32s(counter_count * WORLD_MAP_COUNTER_FIELD_COUNT)?;
+
+        rd.bytes(PIPBOY_LEN + MOVIE_COUNT)?;
+        rd.i32s(Skill::len() * SKILL_USES_PER_DAY)?;
+
+        // party members
+        let member_count = rd.count("party member")?;
+        // party member item count
+        rd.i32s(1)?;
+        // object IDs of the members except the dude
+        rd.i32s(member_count.saturating_sub(1))?;
+        rd.i32s(self.layout.party_member_pids.len().saturating_sub(1)
+            * PARTY_LEVEL_UP_FIELD_COUNT)?;
+
+        Ok(rd.buf)
+    }
+
+    fn read_global_vars(&mut self) -> io::Result<Box<[i32]>> {
+        let mut r = vec![0; self.layout.global_var_count];
+        self.reader.read_i32_into::<BigEndian>(&mut r)?;
+        Ok(r.into())
+    }
+
+    fn read_dude(&mut self) -> io::Result<Handle> {
+        let mut obj = MapReader {
+            reader: self.reader,
+            objects: self.objects,
+            proto_db: self.proto_db,
+            frm_db: self.frm_db,
+            scripts: self.scripts,
+        }.read_object(Profile::Fallout2)?;
+        if !obj.is_dude() {
+            return Err(Error::new(ErrorKind::InvalidData, "saved dude object is not dude"));
+        }
+        obj.script = None;
+        obj.sub.as_critter_mut()
+            .ok_or_else(|| Error::new(ErrorKind::InvalidData, "saved dude object is not critter"))?
+            .dude = Some(Box::new(Dude {
+                naked_fidx: 0x3e,
+                active_hand: Hand::Left,
+            }));
+        Ok(self.objects.insert(obj))
+    }
+
+    fn read_dude_proto(&mut self) -> io::Result<()> {
+        let proto = self.proto_db.dude();
+        let mut proto = proto.borrow_mut();
+        read_critter_data(self.reader, proto.sub.as_critter_mut().unwrap())
+    }
+}
+
+/// Writes `SAVE.DAT` in the same layout `SaveDatReader` reads.
+pub struct SaveDatWriter<'a, W: 'a> {
+    pub writer: &'a mut W,
+    pub layout: &'a Layout,
+    pub objects: &'a Objects,
+    pub proto_db: &'a ProtoDb,
+    pub scripts: &'a Scripts,
+    pub rpg: &'a Rpg,
+}
+
+impl<'a, W: 'a + Write> SaveDatWriter<'a, W> {
+    pub fn write(&mut self, header: &Header, map_files: &[BString], passthrough: &Passthrough)
+        -> io::Result<()>
+    {
+        header.write(self.writer)?;
+
+        self.writer.write_i32::<BigEndian>(passthrough.dude_cid)?;
+
+        self.write_global_vars()?;
+
+        self.writer.write_i32::<BigEndian>(map_files.len() as i32)?;
+        for name in map_files {
+            self.writer.write_all(name.as_bytes())?;
+            self.writer.write_u8(0)?;
+        }
+        self.writer.write_i32::<BigEndian>(passthrough.automap_db_len)?;
+
+        self.write_global_vars()?;
+
+        let dude = self.objects.dude();
+        let elevation = self.objects.get(dude).try_pos().map(|p| p.elevation).unwrap_or(0);
+        MapWriter {
+            writer: self.writer,
+            objects: self.objects,
+            scripts: self.scripts,
+        }.write_object(dude, elevation, Profile::Fallout2)?;
+        let center_tile = self.objects.get(dude).try_pos()
+            .and_then(|p| crate::graphics::geometry::hex::TileGrid::default()
+                .rect_to_linear_inv(p.point))
+            .map(|v| v as i32)
+            .unwrap_or(-1);
+        self.writer.write_i32::<BigEndian>(center_tile)?;
+
+        self.writer.write_i32::<BigEndian>(passthrough.sneak_working)?;
+        self.write_dude_proto()?;
+
+        for i in 0..KILL_KIND_COUNT {
+            let count = self.rpg.kill_count(CritterKillKind::from_usize(i).unwrap());
+            self.writer.write_i32::<BigEndian>(count as i32)?;
+        }
+
+        let mut tagged = Skill::iter().filter(|&s| self.rpg.is_tagged(s));
+        for _ in 0..TAGGED_SKILL_COUNT {
+            self.writer.write_i32::<BigEndian>(tagged.next().map(|s| s as i32).unwrap_or(-1))?;
+        }
+
+        for &pid in &self.layout.party_member_pids {
+            for perk in Perk::iter() {
+                self.writer.write_i32::<BigEndian>(self.rpg.perk(perk, pid) as i32)?;
+            }
+        }
+
+        self.writer.write_u32::<BigEndian>(COMBAT_STATE_NOT_IN_COMBAT)?;
+
+        let ai_packet_len = self.layout.ai_packet_count * AI_PACKET_FIELD_COUNT;
+        if passthrough.ai_packets.is_empty() {
+            for _ in 0..ai_packet_len {
+                self.writer.write_i32::<BigEndian>(0)?;
+            }
+        } else if passthrough.ai_packets.len() == ai_packet_len {
+            for &v in &passthrough.ai_packets {
+                self.writer.write_i32::<BigEndian>(v)?;
+            }
+        } else {
+            return Err(Error::new(ErrorKind::InvalidInput,
+                format!("expected {} AI packet values but found {}",
+                    ai_packet_len, passthrough.ai_packets.len())));
+        }
+
+        for pc_stat in PCStat::iter() {
+            self.writer.write_i32::<BigEndian>(self.rpg.pc_stat(pc_stat))?;
+        }
+
+        let mut traits = Trait::iter().filter(|&t| self.rpg.has_trait(t));
+        for _ in 0..SELECTED_TRAIT_COUNT {
+            self.writer.write_i32::<BigEndian>(traits.next().map(|t| t as i32).unwrap_or(-1))?;
+        }
+
+        if passthrough.pre_queue.is_empty() {
+            self.write_default_pre_queue()?;
+        } else {
+            self.writer.write_all(&passthrough.pre_queue)?;
+        }
+
+        self.scripts.queue.write(self.writer, dude)?;
+
+        if passthrough.post_queue.is_empty() {
+            self.writer.write_all(&[0; INTERFACE_LEN])?;
+        } else {
+            self.writer.write_all(&passthrough.post_queue)?;
+        }
+
+        Ok(())
+    }
+
+    /// Writes the sections preceding the event queue as in a new game with empty world map
+    /// state and no party members other than the dude.
+    fn write_default_pre_queue(&mut self) -> io::Result<()> {
+        self.writer.write_all(&[0; AUTOMAP_LEN + PREFERENCES_LEN + CHARACTER_EDITOR_LEN])?;
+
+        // world map: areas, tiles, tiles per row and encounter counters
+        for _ in 0..WORLD_MAP_GEN_FIELD_COUNT + 4 {
+            self.writer.write_i32::<BigEndian>(0)?;
+        }
+
+        self.writer.write_all(&[0; PIPBOY_LEN + MOVIE_COUNT])?;
+        for _ in 0..Skill::len() * SKILL_USES_PER_DAY {
+            self.writer.write_i32::<BigEndian>(0)?;
+        }
+
+        // party members: the dude only and no items
+        self.writer.write_i32::<BigEndian>(1)?;
+        self.writer.write_i32::<BigEndian>(0)?;
+        for _ in 0..self.layout.party_member_pids.len().saturating_sub(1)
+            * PARTY_LEVEL_UP_FIELD_COUNT
+        {
+            self.writer.write_i32::<BigEndian>(0)?;
+        }
+
+        Ok(())
+    }
+
+    fn write_global_vars(&mut self) -> io::Result<()> {
+        let global_vars = &self.scripts.vars.global_vars;
+        if global_vars.len() != self.layout.global_var_count {
+            return Err(Error::new(ErrorKind::InvalidInput,
+                format!("expected {} global vars but found {}",
+                    self.layout.global_var_count, global_vars.len())));
+        }
+        for &v in global_vars.iter() {
+            self.writer.write_i32::<BigEndian>(v)?;
+        }
+        Ok(())
+    }
+
+    fn write_dude_proto(&mut self) -> io::Result<()> {
+        let proto = self.proto_db.dude();
+        let proto = proto.borrow();
+        let critter: &proto::Critter = proto.sub.as_critter().unwrap();
+
+        self.writer.write_u32::<BigEndian>(critter.flags.bits())?;
+        for i in 0..SAVEABLE_STAT_COUNT {
+            self.writer.write_i32::<BigEndian>(critter.base_stats[Stat::from_usize(i).unwrap()])?;
+        }
+        for i in 0..SAVEABLE_STAT_COUNT {
+            self.writer.write_i32::<BigEndian>(critter.bonus_stats[Stat::from_usize(i).unwrap()])?;
+        }
+        for skill in Skill::iter() {
+            self.writer.write_i32::<BigEndian>(critter.skills[skill])?;
+        }
+        self.writer.write_u32::<BigEndian>(critter.body_kind as u32)?;
+        self.writer.write_i32::<BigEndian>(critter.experience)?;
+        self.writer.write_u32::<BigEndian>(critter.kill_kind as u32)?;
+        self.writer.write_u32::<BigEndian>(critter.damage_kind as u32)?;
+        Ok(())
+    }
+}
+
+/// Returns name of the map save file like `ARTEMPLE.SAV` for map `name` like `artemple`.
+pub fn map_file_name(name: &str) -> String {
+    format!("{}.SAV", name.to_ascii_uppercase())
+}
+
+/// Reads map save files from the `MAPS` subdirectory of the save slot `dir`.
+/// The files can be either plain or gzip-compressed.
+/// Returns map save data keyed by the map name in lower case.
+pub fn read_map_files(dir: &Path, map_files: &[BString]) -> io::Result<HashMap<String, Vec<u8>>> {
+    let mut r = HashMap::new();
+    for name in map_files {
+        let name = name.display().to_string();
+        let path = dir.join(MAPS_DIR).join(&name);
+        let mut data = Vec::new();
+        BufReader::new(File::open(&path)?).read_to_end(&mut data)?;
+        if data.starts_with(&[0x1f, 0x8b]) {
+            let mut d = Vec::new();
+            GzDecoder::new(&data[..]).read_to_end(&mut d)?;
+            data = d;
+        }
+        let key = name.split('.').next().unwrap().to_ascii_lowercase();
+        debug!("read map save {} ({} bytes)", path.display(), data.len());
+        r.insert(key, data);
+    }
+    Ok(r)
+}
+
+/// Writes gzip-compressed map save files into the `MAPS` subdirectory of the save slot `dir`.
+/// Returns the file names in the sorted order.
+pub fn write_map_files(dir: &Path, maps: &HashMap<String, Vec<u8>>) -> io::Result<Vec<BString>> {
+    let maps_dir = dir.join(MAPS_DIR);
+    fs::create_dir_all(&maps_dir)?;
+    let mut names: Vec<_> = maps.keys().collect();
+    names.sort();
+    let mut r = Vec::with_capacity(names.len());
+    for name in names {
+        let file_name = map_file_name(name);
+        let mut wr = GzEncoder::new(BufWriter::new(File::create(maps_dir.join(&file_name))?),
+            Compression::default());
+        wr.write_all(&maps[name])?;
+        wr.finish()?.flush()?;
+        r.push(file_name.into());
+    }
+    Ok(r)
+}
+
+/// Reads the saveable part of the critter proto as it's stored in `SAVE.DAT` and in the
+/// premade character `.gcd` files.
+// critter_read_data()
+pub fn read_critter_data(rd: &mut impl Read, critter: &mut proto::Critter) -> io::Result<()> {
+    let flags = rd.read_u32::<BigEndian>()?;
+    critter.flags = enumflags2::BitFlags::from_bits(flags)
+        .map_err(|_| Error::new(ErrorKind::InvalidData,
+            format!("invalid critter flags: {:x}", flags)))?;
+    for i in 0..SAVEABLE_STAT_COUNT {
+        critter.base_stats[Stat::from_usize(i).unwrap()] = rd.read_i32::<BigEndian>()?;
+    }
+    for i in 0..SAVEABLE_STAT_COUNT {
+        critter.bonus_stats[Stat::from_usize(i).unwrap()] = rd.read_i32::<BigEndian>()?;
+    }
+    for skill in Skill::iter() {
+        critter.skills[skill] = rd.read_i32::<BigEndian>()?;
+    }
+    critter.body_kind = read_enum(rd, "invalid critter body kind")?;
+    critter.experience = rd.read_i32::<BigEndian>()?;
+    critter.kill_kind = read_enum(rd, "invalid critter kill kind")?;
+    critter.damage_kind = read_enum(rd, "invalid critter damage kind")?;
+    Ok(())
+}
+
+/// Makes the save game thumbnail from the `rect` area of the palette-indexed `pixels` picture
+/// which is `width` pixels wide.
+pub fn make_thumbnail(pixels: &[u8], width: i32, rect: Rect) -> Box<[u8]> {
+    let mut r = vec![0; THUMBNAIL_WIDTH * THUMBNAIL_HEIGHT];
+    for y in 0..THUMBNAIL_HEIGHT {
+        let src_y = rect.top + y as i32 * rect.height() / THUMBNAIL_HEIGHT as i32;
+        for x in 0..THUMBNAIL_WIDTH {
+            let src_x = rect.left + x as i32 * rect.width() / THUMBNAIL_WIDTH as i32;
+            r[y * THUMBNAIL_WIDTH + x] = pixels[(src_y * width + src_x) as usize];
+        }
+    }
+    r.into()
+}
+
+/// Copies raw bytes of the sections that are passed through.
+struct RawReader<'a, R> {
+    reader: &'a mut R,
+    buf: Vec<u8>,
+}
+
+impl<R: Read> RawReader<'_, R> {
+    fn bytes(&mut self, len: usize) -> io::Result<()> {
+        let read = (&mut *self.reader).take(len as u64).read_to_end(&mut self.buf)?;
+        if read < len {
+            return Err(Error::new(ErrorKind::UnexpectedEof, "unexpected end of SAVE.DAT"));
+        }
+        Ok(())
+    }
+
+    fn i32s(&mut self, count: usize) -> io::Result<()> {
+        self.bytes(count * 4)
+    }
+
+    fn count(&mut self, what: &str) -> io::Result<usize> {
+        self.i32s(1)?;
+        let v = BigEndian::read_i32(&self.buf[self.buf.len() - 4..]);
+        usize::try_from(v).map_err(|_| Error::new(ErrorKind::InvalidData,
+            format!("invalid {} count: {}", what, v)))
+    }
+}
+
+fn read_str(rd: &mut impl Read, len: usize) -> io::Result<BString> {
+    let mut buf = vec![0; len];
+    rd.read_exact(&mut buf)?;
+    let end = buf.iter().position(|&c| c == 0).unwrap_or(len);
+    buf.truncate(end);
+    Ok(buf.into())
+}
+
+fn write_str(wr: &mut impl Write, s: &bstr, len: usize) -> io::Result<()> {
+    let mut buf = vec![0; len];
+    // Always keep the terminating zero.
+    let n = s.len().min(len - 1);
+    buf[..n].copy_from_slice(&s.as_bytes()[..n]);
+    wr.write_all(&buf)
+}
+
+fn read_c_str(rd: &mut impl Read) -> io::Result<BString> {
+    let mut r = Vec::new();
+    loop {
+        match rd.read_u8()? {
+            0 => break,
+            c => r.push(c),
+        }
+    }
+    Ok(r.into())
+}
+
+fn read_enum<T: FromPrimitive>(rd: &mut impl Read, err: &str) -> io::Result<T> {
+    T::from_u32(rd.read_u32::<BigEndian>()?)
+        .ok_or_else(|| Error::new(ErrorKind::InvalidData, err))
+}
+
+fn read_opt_enum<T: FromPrimitive>(rd: &mut impl Read, err: &str) -> io::Result<Option<T>> {
+    let v = rd.read_i32::<BigEndian>()?;
+    if v >= 0 {
+        Ok(Some(T::from_i32(v).ok_or_else(|| Error::new(ErrorKind::InvalidData, err))?))
+    } else {
+        Ok(None)
+    }
+}
+
+/// Returns current UTC date as (day, month, year) and time as `hours * 100 + minutes`.
+fn utc_date_time() -> ((u16, u16, u16), u32) {
+    let secs = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+    let days = (secs / 86400) as i64;
+    let time = (secs % 86400) as u32;
+
+    // Civil from days algorithm.
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + (month <= 2) as i64;
+
+    ((day as u16, month as u16, year as u16), time / 3600 * 100 + time % 3600 / 60)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::game::object::Objects;
+    use crate::game::queue;
+    use crate::graphics::geometry::hex::{Direction, TileGrid};
+    use crate::graphics::Point;
+    use crate::util::test::Assets;
+
+    const PARTY_MEMBER: u32 = Assets::CRITTER;
+
+    fn assets() -> Assets {
+        Assets::with_files(|fs| {
+            fs.insert("data/vault13.gam", "GAME_GLOBAL_VARS:\nA := 1;\nB := 2;\nC := 3;\n");
+            fs.insert("data/party.txt", format!(
+                "[Party Member 0]\nparty_member_pid=16777216\n\
+                 [Party Member 1]\nparty_member_pid={}\n", PARTY_MEMBER));
+            fs.insert("data/ai.txt", "[A]\nx=1\n[B]\nx=2\n");
+        })
+    }
+
+    fn write_i32s(wr: &mut Vec<u8>, values: &[i32]) {
+        for &v in values {
+            wr.write_i32::<BigEndian>(v).unwrap();
+        }
+    }
+
+    fn new_objects(assets: &Assets) -> Objects {
+        Objects::new(TileGrid::default(), 3, assets.frm_db.clone(), assets.proto_db.clone())
+    }
+
+    #[test]
+    fn layout() {
+        let layout = Layout::read(&assets().fs).unwrap();
+        assert_eq!(layout, Layout {
+            global_var_count: 3,
+            party_member_pids: vec![ProtoId::DUDE, Assets::pid(PARTY_MEMBER)],
+            ai_packet_count: 2,
+        });
+    }
+
+    #[test]
+    fn header_roundtrip() {
+        let mut header = Header::new("Narg".into(), "Before the temple".into(),
+            GameTime::from_decis(302400000), 1, 12, map_file_name("artemple").into());
+        header.thumbnail[123] = 42;
+
+        let mut data = Vec::new();
+        header.write(&mut data).unwrap();
+        assert_eq!(data.len(), 0x7563);
+        assert_eq!(&data[..SIGNATURE.len()], SIGNATURE);
+        assert_eq!(&data[0x1d..0x1d + 4], b"Narg");
+
+        assert_eq!(Header::read(&mut &data[..]).unwrap(), header);
+    }
+
+    #[test]
+    fn save_dat_roundtrip() {
+        let assets = &assets();
+        let layout = &Layout::read(&assets.fs).unwrap();
+        let mut objects = new_objects(assets);
+        let mut scripts = assets.scripts();
+        let mut rpg = assets.rpg();
+
+        scripts.vars.global_vars = vec![7, 8, 9].into();
+        let dude = objects.create(None, Some(assets.proto_db.dude()),
+            Some(Point::new(12, 34).elevated(1)), Some(&rpg)).handle();
+        objects.get_mut(dude).direction = Direction::SW;
+        objects.get_mut(dude).sub.as_critter_mut().unwrap().hit_points = 21;
+        {
+            let proto = assets.proto_db.dude();
+            let mut proto = proto.borrow_mut();
+            let critter = proto.sub.as_critter_mut().unwrap();
+            critter.base_stats[Stat::Strength] = 8;
+            critter.bonus_stats[Stat::Luck] = 2;
+            critter.skills[Skill::Lockpick] = 55;
+            critter.experience = 1234;
+        }
+        rpg.set_tagged(Skill::SmallGuns, true);
+        rpg.set_tagged(Skill::Conversant, true);
+        rpg.set_trait(Trait::Bruiser, true);
+        rpg.set_perk(Perk::BonusAwareness, ProtoId::DUDE, 1);
+        rpg.set_perk(Perk::Toughness, Assets::pid(PARTY_MEMBER), 2);
+        rpg.set_pc_stat(PCStat::Level, 3);
+        rpg.set_pc_stat(PCStat::Karma, -50);
+        rpg.set_kill_count(CritterKillKind::Rat, 5);
+        rpg.set_kill_count(CritterKillKind::BigBadBoss, 1);
+        scripts.queue.push(GameTime::from_decis(2000), Some(dude), queue::Event::Knockout);
+        scripts.queue.push(GameTime::from_decis(3000), None,
+            queue::Event::Script { fixed_param: 7 });
+
+        let mut pre_queue = vec![1; AUTOMAP_LEN + PREFERENCES_LEN + CHARACTER_EDITOR_LEN];
+        // world map with two areas and one entrance
+        write_i32s(&mut pre_queue, &[2; WORLD_MAP_GEN_FIELD_COUNT]);
+        write_i32s(&mut pre_queue, &[2, 10, 20, 1, 1, 1, 3]);
+        write_i32s(&mut pre_queue, &[30, 40, 0, 0, 0]);
+        write_i32s(&mut pre_queue, &[1, 1]);
+        write_i32s(&mut pre_queue, &[1; WORLD_MAP_SUBTILE_COUNT]);
+        write_i32s(&mut pre_queue, &[1, 5, 6, 7]);
+        pre_queue.extend_from_slice(&[3; PIPBOY_LEN + MOVIE_COUNT]);
+        write_i32s(&mut pre_queue, &vec![4; Skill::len() * SKILL_USES_PER_DAY]);
+        // dude and one party member
+        write_i32s(&mut pre_queue, &[2, 1, 42, 5, 1, 0]);
+        let passthrough = Passthrough {
+            dude_cid: 3,
+            automap_db_len: 123,
+            sneak_working: 1,
+            ai_packets: (1..=12).collect(),
+            pre_queue,
+            post_queue: vec![5; INTERFACE_LEN],
+        };
+
+        let header = Header::new("Narg".into(), "".into(), GameTime::from_decis(1000), 1, 12,
+            "ARTEMPLE.SAV".into());
+        let map_files = vec![BString::from("ARTEMPLE.SAV"), BString::from("ARVILLAG.SAV")];
+
+        let mut data = Vec::new();
+        SaveDatWriter {
+            writer: &mut data,
+            layout,
+            objects: &objects,
+            proto_db: &assets.proto_db,
+            scripts: &scripts,
+            rpg: &rpg,
+        }.write(&header, &map_files, &passthrough).unwrap();
+
+        // Reset the state that is restored from the SAVE.DAT.
+        {
+            let proto = assets.proto_db.dude();
+            let mut proto = proto.borrow_mut();
+            proto.set_name("None".into());
+            let critter = proto.sub.as_critter_mut().unwrap();
+            critter.base_stats[Stat::Strength] = 0;
+            critter.bonus_stats[Stat::Luck] = 0;
+            critter.skills[Skill::Lockpick] = 0;
+            critter.experience = 0;
+        }
+        let mut objects = new_objects(assets);
+        let mut scripts = assets.scripts();
+        let mut rpg = assets.rpg();
+        rpg.set_tagged(Skill::Doctor, true);
+
+        let save_dat = SaveDatReader {
+            reader: &mut &data[..],
+            layout,
+            objects: &mut objects,
+            proto_db: &assets.proto_db,
+            frm_db: &assets.frm_db,
+            scripts: &mut scripts,
+            rpg: &mut rpg,
+        }.read().unwrap();
+
+        assert_eq!(save_dat.header, header);
+        assert_eq!(save_dat.map_files, map_files);
+        assert_eq!(save_dat.passthrough, passthrough);
+        assert_eq!(&scripts.vars.global_vars[..], &[7, 8, 9]);
+
+        assert_eq!(objects.dude(), save_dat.dude);
+        let dude = objects.dude_ref();
+        assert_eq!(dude.pos(), Point::new(12, 34).elevated(1));
+        assert_eq!(dude.direction, Direction::SW);
+        assert_eq!(dude.sub.as_critter().unwrap().hit_points, 21);
+        assert!(dude.sub.as_critter().unwrap().dude.is_some());
+
+        {
+            let proto = assets.proto_db.dude();
+            let proto = proto.borrow();
+            assert_eq!(proto.name().unwrap(), "Narg");
+            let critter = proto.sub.as_critter().unwrap();
+            assert_eq!(critter.base_stats[Stat::Strength], 8);
+            assert_eq!(critter.bonus_stats[Stat::Luck], 2);
+            assert_eq!(critter.skills[Skill::Lockpick], 55);
+            assert_eq!(critter.experience, 1234);
+        }
+
+        assert_eq!(Skill::iter().filter(|&s| rpg.is_tagged(s)).collect::<Vec<_>>(),
+            &[Skill::SmallGuns, Skill::Conversant]);
+        assert_eq!(Trait::iter().filter(|&t| rpg.has_trait(t)).collect::<Vec<_>>(),
+            &[Trait::Bruiser]);
+        assert_eq!(rpg.perk(Perk::BonusAwareness, ProtoId::DUDE), 1);
+        assert_eq!(rpg.perk(Perk::Toughness, ProtoId::DUDE), 0);
+        assert_eq!(rpg.perk(Perk::Toughness, Assets::pid(PARTY_MEMBER)), 2);
+        assert_eq!(rpg.pc_stat(PCStat::Level), 3);
+        assert_eq!(rpg.pc_stat(PCStat::Karma), -50);
+        assert_eq!(rpg.kill_count(CritterKillKind::Rat), 5);
+        assert_eq!(rpg.kill_count(CritterKillKind::BigBadBoss), 1);
+        assert_eq!(rpg.kill_count(CritterKillKind::Man), 0);
+        assert_eq!(scripts.queue.iter().map(|e| (e.time, e.obj, e.event)).collect::<Vec<_>>(), &[
+            (GameTime::from_decis(2000), Some(save_dat.dude), queue::Event::Knockout),
+            (GameTime::from_decis(3000), None, queue::Event::Script { fixed_param: 7 }),
+        ]);
+
+        // Writing back what was read must reproduce the file.
+        let mut data2 = Vec::new();
+        SaveDatWriter {
+            writer: &mut data2,
+            layout,
+            objects: &objects,
+            proto_db: &assets.proto_db,
+            scripts: &scripts,
+            rpg: &rpg,
+        }.write(&save_dat.header, &save_dat.map_files, &save_dat.passthrough).unwrap();
+        assert_eq!(data2, data);
+    }
+
+    #[test]
+    fn save_dat_default_passthrough() {
+        let assets = &assets();
+        let layout = &Layout::read(&assets.fs).unwrap();
+        let mut objects = new_objects(assets);
+        let mut scripts = assets.scripts();
+        let rpg = assets.rpg();
+
+        scripts.vars.global_vars = vec![1, 2, 3].into();
+        let dude = objects.create(None, Some(assets.proto_db.dude()),
+            Some(Point::new(12, 34).elevated(0)), Some(&rpg)).handle();
+        scripts.queue.push(GameTime::from_decis(2000), Some(dude), queue::Event::Poison);
+
+        let header = Header::new("Narg".into(), "".into(), GameTime::from_decis(1000), 0, 12,
+            "ARTEMPLE.SAV".into());
+        let mut data = Vec::new();
+        SaveDatWriter {
+            writer: &mut data,
+            layout,
+            objects: &objects,
+            proto_db: &assets.proto_db,
+            scripts: &scripts,
+            rpg: &rpg,
+        }.write(&header, &[], &Passthrough::default()).unwrap();
+
+        let mut objects = new_objects(assets);
+        let mut scripts = assets.scripts();
+        let mut rpg = assets.rpg();
+        let save_dat = SaveDatReader {
+            reader: &mut &data[..],
+            layout,
+            objects: &mut objects,
+            proto_db: &assets.proto_db,
+            frm_db: &assets.frm_db,
+            scripts: &mut scripts,
+            rpg: &mut rpg,
+        }.read().unwrap();
+
+        assert_eq!(save_dat.passthrough.dude_cid, -1);
+        assert_eq!(save_dat.passthrough.post_queue, &[0; INTERFACE_LEN]);
+        assert_eq!(scripts.queue.iter().map(|e| (e.obj, e.event)).collect::<Vec<_>>(),
+            &[(Some(save_dat.dude), queue::Event::Poison)]);
+    }
+
+    #[test]
+    fn map_files_roundtrip() {
+        let dir = std::env::temp_dir().join(format!("vault13-savegame-test-{}", std::process::id()));
+        let mut maps = HashMap::new();
+        maps.insert("artemple".to_owned(), vec![1, 2, 3]);
+        maps.insert("arcaves".to_owned(), vec![4; 1000]);
+
+        let names = write_map_files(&dir, &maps).unwrap();
+        assert_eq!(names, &[BString::from("ARCAVES.SAV"), BString::from("ARTEMPLE.SAV")]);
+
+        // Uncompressed files are also accepted.
+        fs::write(dir.join(MAPS_DIR).join("ARVILLAG.SAV"), [5, 6]).unwrap();
+        let mut names = names;
+        names.push("ARVILLAG.SAV".into());
+        maps.insert("arvillag".to_owned(), vec![5, 6]);
+
+        let actual = read_map_files(&dir, &names);
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(actual.unwrap(), maps);
+    }
+
+    #[test]
+    fn thumbnail() {
+        let width = THUMBNAIL_WIDTH as i32 * 2;
+        let height = THUMBNAIL_HEIGHT as i32 * 2 + 10;
+        let pixels: Vec<u8> = (0..width * height)
+            .map(|i| ((i % width) / 2 % 16 + (i / width - 10) / 2 % 16 * 16) as u8)
+            .collect();
+        let thumb = make_thumbnail(&pixels, width, Rect::with_size(0, 10, width, height - 10));
+        assert_eq!(thumb.len(), THUMBNAIL_WIDTH * THUMBNAIL_HEIGHT);
+        assert_eq!(thumb[0], 0);
+        assert_eq!(thumb[1], 1);
+        assert_eq!(thumb[THUMBNAIL_WIDTH + 2], 0x12);
+        assert_eq!(thumb[3 * THUMBNAIL_WIDTH + 17], 0x31);
+    }
+}
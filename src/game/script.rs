@@ -1,23 +1,27 @@
-use bstring::BString;
+use bstring::{bstr, BString};
 use byteorder::{BigEndian, ReadBytesExt};
 use linearize::{static_map, StaticMap};
 use linearize::Linearize;
 use enum_primitive_derive::Primitive;
 use num_traits::FromPrimitive;
 use log::*;
+use std::cell::RefCell;
 use std::collections::HashMap;
 use std::collections::hash_map::Entry;
 use std::fmt;
 use std::io::{self, prelude::*};
 use std::rc::Rc;
+use std::time::Instant;
 
 use crate::asset::map::MapId;
 use crate::asset::proto::ProtoDb;
 use crate::asset::script::ProgramId;
 use crate::asset::script::db::ScriptDb;
 use crate::game::object;
+use crate::game::queue::Queue;
 use crate::util::EnumExt;
 use crate::vm::{self, *};
+use crate::vm::debug::Debugger;
 use crate::vm::value::Value;
 
 pub const GVAR_PLAYER_REPUTATION: usize = 0;
@@ -99,6 +103,10 @@ impl ScriptIid {
         Ok(SidInternal::read_opt(rd)?.map(Self))
     }
 
+    pub fn pack(self) -> u32 {
+        self.0.pack()
+    }
+
     pub fn kind(self) -> ScriptKind {
         self.0.kind()
     }
@@ -160,7 +168,14 @@ pub struct Context<'a> {
     pub source_obj: Option<object::Handle>,
     pub target_obj: Option<object::Handle>,
     pub skill: Option<crate::asset::Skill>,
+    pub fixed_param: i32,
     pub rpg: &'a mut crate::game::rpg::Rpg,
+    pub sound: &'a mut crate::sound::Sound,
+    pub map_db: &'a mut crate::asset::map::db::MapDb,
+    pub movies: &'a mut crate::game::movie::Movies,
+    pub combat: &'a mut crate::game::combat::Combat,
+    pub world_map: &'a mut crate::game::worldmap::WorldMap,
+    pub party: &'a mut crate::game::party::Party,
 }
 
 pub struct Vars {
@@ -196,7 +211,7 @@ pub struct NewScripts {
 }
 
 impl NewScripts {
-    fn new(scripts: &Scripts) -> Self {
+    pub fn new(scripts: &Scripts) -> Self {
         let mut unused_sids = static_map! {
             k => ScriptIid::new(k, 0)
         } ;
@@ -244,6 +259,7 @@ pub struct Scripts {
     scripts: HashMap<ScriptIid, Script>,
     map_sid: Option<ScriptIid>,
     pub vars: Vars,
+    pub queue: Queue,
     suspend_stack: Vec<ScriptIid>,
 }
 
@@ -257,10 +273,19 @@ impl Scripts {
             scripts: HashMap::new(),
             map_sid: None,
             vars: Vars::new(),
+            queue: Queue::new(),
             suspend_stack: Vec::new(),
         }
     }
 
+    pub fn debugger(&self) -> Option<&Rc<RefCell<Debugger>>> {
+        self.vm.debugger()
+    }
+
+    pub fn set_debugger(&mut self, debugger: Option<Rc<RefCell<Debugger>>>) {
+        self.vm.set_debugger(debugger);
+    }
+
     pub fn map_sid(&self) -> Option<ScriptIid> {
         self.map_sid
     }
@@ -270,6 +295,7 @@ impl Scripts {
         self.map_sid = None;
         self.vars.map_vars = vec![].into();
         self.vars.external_vars.clear();
+        self.queue.clear();
         self.suspend_stack.clear();
     }
 
@@ -325,10 +351,25 @@ impl Scripts {
         Ok(sid)
     }
 
+    /// Instantiates the program as a script with an unused id of the `kind`.
+    pub fn instantiate_new(&mut self,
+        kind: ScriptKind,
+        program_id: ProgramId,
+        local_vars: Option<Box<[i32]>>,
+    ) -> io::Result<ScriptIid> {
+        let sid = NewScripts::new(self).unused_sid(kind);
+        self.instantiate(sid, program_id, local_vars)?;
+        Ok(sid)
+    }
+
     pub fn get(&self, sid: ScriptIid) -> Option<&Script> {
         self.scripts.get(&sid)
     }
 
+    pub fn iter(&self) -> impl Iterator<Item=(ScriptIid, &Script)> {
+        self.scripts.iter().map(|(&sid, script)| (sid, script))
+    }
+
     pub fn attach_to_object(&mut self, sid: ScriptIid, obj: object::Handle) {
         self.scripts.get_mut(&sid).unwrap().object = Some(obj);
     }
@@ -342,6 +383,7 @@ impl Scripts {
             let mut vm_ctx = Self::make_vm_ctx(
                 &mut script.local_vars,
                 &mut self.vars,
+                &mut self.queue,
                 &mut self.db,
                 new_scripts,
                 &self.proto_db,
@@ -447,6 +489,7 @@ impl Scripts {
             let mut vm_ctx = Self::make_vm_ctx(
                 &mut script.local_vars,
                 &mut self.vars,
+                &mut self.queue,
                 &mut self.db,
                 new_scripts,
                 &self.proto_db,
@@ -459,10 +502,100 @@ impl Scripts {
         r
     }
 
+    /// Runs the scheduled script code and carries out the requests made by scripts: starts new
+    /// processes, dispatches named events and removes exited processes.
+    pub fn update(&mut self, now: Instant, ctx: &mut Context) {
+        let sids: Vec<_> = self.scripts.iter()
+            .filter(|(_, s)| s.inited)
+            .map(|(&sid, _)| sid)
+            .collect();
+        for sid in sids {
+            let new_scripts = {
+                let new_scripts = NewScripts::new(self);
+                let script = self.scripts.get_mut(&sid).unwrap();
+                let mut vm_ctx = Self::make_vm_ctx(
+                    &mut script.local_vars,
+                    &mut self.vars,
+                    &mut self.queue,
+                    &mut self.db,
+                    new_scripts,
+                    &self.proto_db,
+                    script.object,
+                    ctx);
+                self.vm.program_state_mut(script.program).update(now, &mut vm_ctx).unwrap();
+                vm_ctx.new_scripts
+            };
+            new_scripts.instantiate(self);
+        }
+
+        loop {
+            let requests = self.vm.take_requests();
+            if requests.is_empty() {
+                break;
+            }
+            for (program, request) in requests {
+                self.handle_request(program, request, ctx);
+            }
+        }
+    }
+
+    fn handle_request(&mut self, program: vm::Handle, request: Request, ctx: &mut Context) {
+        let sid = self.sid_by_program(program);
+        match request {
+            Request::Start { name, mode } => {
+                let child = sid.and_then(|sid| self.start_process(sid, &name));
+                let child_program = child.map(|c| self.scripts[&c].program);
+                self.vm.start_process(program, mode, child_program);
+                if let Some(child) = child
+                    && let Some(r) = self.execute_predefined_proc(child, PredefinedProc::Start, ctx)
+                {
+                    assert!(r.suspend.is_none(), "can't suspend in process start");
+                }
+            }
+            Request::Signal(name) => {
+                for (handler, proc_id) in self.vm.event_handlers(&name) {
+                    if let Some(sid) = self.sid_by_program(handler) {
+                        let r = self.execute_proc(sid, proc_id, ctx);
+                        assert!(r.suspend.is_none(), "can't suspend in named event handler");
+                    }
+                }
+            }
+            Request::Exit => {
+                if let Some(sid) = sid {
+                    debug!("{:?}: process exited", sid);
+                    self.scripts.remove(&sid);
+                }
+                self.vm.remove(program);
+            }
+        }
+    }
+
+    fn start_process(&mut self, parent: ScriptIid, name: &bstr) -> Option<ScriptIid> {
+        let Some(program_id) = self.db.program_id(name) else {
+            warn!("{:?}: can't start process: program `{}` not found", parent, name.display());
+            return None;
+        };
+        let child = self.instantiate_new(parent.kind(), program_id, None)
+            .map_err(|e| warn!("{:?}: can't start process `{}`: {}", parent, name.display(), e))
+            .ok()?;
+        debug!("{:?}: started process `{}` as {:?}", parent, name.display(), child);
+        let object = self.scripts[&parent].object;
+        self.scripts.get_mut(&child).unwrap().object = object;
+        Some(child)
+    }
+
+    fn sid_by_program(&self, program: vm::Handle) -> Option<ScriptIid> {
+        self.scripts.iter()
+            .find(|(_, s)| s.program == program)
+            .map(|(&sid, _)| sid)
+    }
+
     #[inline]
+    #[allow(clippy::too_many_arguments)]
     fn make_vm_ctx<'a>(
         local_vars: &'a mut [i32],
         vars: &'a mut Vars,
+        queue: &'a mut Queue,
         script_db: &'a mut ScriptDb,
         new_scripts: NewScripts,
         proto_db: &'a ProtoDb,
@@ -474,11 +607,13 @@ impl Scripts {
             map_vars: &mut vars.map_vars,
             global_vars: &mut vars.global_vars,
             external_vars: &mut vars.external_vars,
+            queue,
 
             self_obj,
             source_obj: ctx.source_obj,
             target_obj: ctx.target_obj,
             skill: ctx.skill,
+            fixed_param: ctx.fixed_param,
             ui: ctx.ui,
             world: ctx.world,
             obj_sequencer: ctx.obj_sequencer,
@@ -489,6 +624,12 @@ impl Scripts {
             proto_db,
             map_id: ctx.map_id,
             rpg: ctx.rpg,
+            sound: ctx.sound,
+            map_db: ctx.map_db,
+            movies: ctx.movies,
+            combat: ctx.combat,
+            world_map: ctx.world_map,
+            party: ctx.party,
         }
     }
 }
\ No newline at end of file
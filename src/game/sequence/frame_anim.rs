@@ -70,7 +70,7 @@ impl FrameAnim {
             obj.fid
         };
 
-        self.frame_len = Duration::from_millis(1000 / world.frm_db().get(obj.fid).unwrap().fps as u64);
+        self.frame_len = world.frame_len(obj.fid);
     }
 }
 
@@ -56,7 +56,7 @@ impl Move {
             obj.frame_idx = 0;
         }
 
-        self.frame_len = Duration::from_millis(1000 / world.frm_db().get(obj.fid).unwrap().fps as u64);
+        self.frame_len = world.frame_len(obj.fid);
     }
 
     fn rebuild_path(&mut self, world: &mut World) {
@@ -0,0 +1,171 @@
+use enum_primitive_derive::Primitive;
+
+use crate::asset::{CritterAnim, DamageKind, EntityKind, Material, WeaponKind};
+use crate::asset::frame::critter_anim_codes;
+use crate::asset::proto::SubProto;
+use crate::game::object;
+use crate::game::world::World;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Primitive)]
+pub enum SceneryAction {
+    Open = 0,
+    Close = 1,
+    Lock = 2,
+    Unlock = 3,
+    Use = 4,
+}
+
+impl SceneryAction {
+    fn code(self) -> char {
+        b"OCLNU"[self as usize] as char
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Primitive)]
+pub enum CharSfx {
+    Unused = 0,
+    Knockdown = 1,
+    PassOut = 2,
+    Die = 3,
+    Contact = 4,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Primitive)]
+pub enum WeaponSfx {
+    Ready = 0,
+    Attack = 1,
+    OutOfAmmo = 2,
+    AmmoFlying = 3,
+    Hit = 4,
+}
+
+impl WeaponSfx {
+    fn code(self) -> char {
+        b"RAOFH"[self as usize] as char
+    }
+}
+
+// gsnd_build_character_sfx_name()
+pub fn build_char_name(world: &World, obj: object::Handle, anim: CritterAnim, kind: CharSfx)
+    -> Option<String>
+{
+    let fid = world.objects().get(obj).fid;
+    if fid.kind() != EntityKind::Critter {
+        return None;
+    }
+    let base_name = world.frm_db().base_name(fid)?;
+    let (mut c1, c2) = critter_anim_codes(WeaponKind::Unarmed, anim)?;
+    match anim {
+        CritterAnim::FallFront | CritterAnim::FallBack => match kind {
+            CharSfx::PassOut => c1 = 'y',
+            CharSfx::Die => c1 = 'z',
+            _ => {}
+        }
+        CritterAnim::ThrowPunch | CritterAnim::KickLeg if kind == CharSfx::Contact => c1 = 'z',
+        _ => {}
+    }
+    Some(fixup(format!("{}{}{}", base_name, c1, c2)))
+}
+
+// gsnd_build_ambient_sfx_name()
+pub fn build_ambient_name(name: &str) -> String {
+    fixup(format!("A{:>6}{}", name, 1))
+}
+
+// gsnd_build_interface_sfx_name()
+pub fn build_interface_name(name: &str) -> String {
+    fixup(format!("N{:<7}", name))
+}
+
+// gsnd_build_item_sfx_name()
+pub fn build_item_name(name: &str) -> String {
+    fixup(format!("I{:<7}", name))
+}
+
+// gsnd_build_weapon_sfx_name()
+pub fn build_weapon_name(world: &World, kind: WeaponSfx, weapon: object::Handle, primary: bool,
+    target: Option<object::Handle>) -> String
+{
+    let objs = world.objects();
+    let (sound_id, damage_kind) = objs.get(weapon).proto()
+        .and_then(|p| p.sub.as_item()
+            .and_then(|i| i.sub.as_weapon())
+            .map(|w| (w.sound_id, w.damage_kind)))
+        .unwrap_or((0, DamageKind::Melee));
+    let attack = if kind == WeaponSfx::Ready || kind == WeaponSfx::OutOfAmmo || primary {
+        1
+    } else {
+        2
+    };
+    let material_code = match target {
+        Some(target) if kind == WeaponSfx::Hit && !matches!(damage_kind,
+                DamageKind::Explosion | DamageKind::Plasma | DamageKind::Emp) => {
+            let material = objs.get(target).proto().and_then(|p| match &p.sub {
+                SubProto::Item(v) => Some(v.material),
+                SubProto::Scenery(v) => Some(v.material),
+                SubProto::Wall(v) => Some(v.material),
+                _ => None,
+            });
+            match material {
+                Some(Material::Glass | Material::Metal | Material::Plastic) => 'M',
+                Some(Material::Wood) => 'W',
+                Some(Material::Dirt | Material::Stone | Material::Cement) => 'S',
+                _ => 'F',
+            }
+        }
+        _ => 'X',
+    };
+    fixup(format!("W{}{}{}{}XX{}", kind.code(), sound_id as char, attack, material_code, 1))
+}
+
+// gsnd_build_scenery_sfx_name()
+pub fn build_scenery_name(passive: bool, action: SceneryAction, name: &str) -> String {
+    fixup(format!("S{}{}{:>4}{}", if passive { 'P' } else { 'A' }, action.code(), name, 1))
+}
+
+// gsnd_build_open_sfx_name()
+pub fn build_open_name(world: &World, obj: object::Handle, action: SceneryAction) -> String {
+    let obj = world.objects().get(obj);
+    let proto = obj.proto();
+    if obj.fid.kind() == EntityKind::Scenery {
+        let sound_id = proto.and_then(|p| p.sub.as_scenery().map(|s| s.sound_id))
+            .unwrap_or(b'A');
+        fixup(format!("S{}DOORS{}", action.code(), sound_id as char))
+    } else {
+        let sound_id = proto.and_then(|p| p.sub.as_item().map(|i| i.sound_id))
+            .unwrap_or(b'A');
+        fixup(format!("S{}CNTNR{}", action.code(), sound_id as char))
+    }
+}
+
+// gsound_compute_relative_volume()
+/// Sounds of the objects that are out of sight are played at reduced volume.
+pub fn relative_volume(world: &World, obj: object::Handle) -> f32 {
+    let o = world.objects().get(obj);
+    if o.try_pos().is_none() || o.fid.kind() > EntityKind::Scenery ||
+        world.is_object_in_camera(obj)
+    {
+        1.0
+    } else {
+        1.0 / 3.0
+    }
+}
+
+fn fixup(s: String) -> String {
+    s.to_ascii_uppercase().replace(' ', "_")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn names() {
+        assert_eq!(build_ambient_name("wind"), "A__WIND1");
+        assert_eq!(build_interface_name("ib1p1xx1"), "NIB1P1XX1");
+        assert_eq!(build_interface_name("butin"), "NBUTIN__");
+        assert_eq!(build_item_name("ammo"), "IAMMO___");
+        assert_eq!(build_scenery_name(false, SceneryAction::Use, "comp"), "SAUCOMP1");
+        assert_eq!(build_scenery_name(true, SceneryAction::Open, "lt"), "SPO__LT1");
+    }
+}
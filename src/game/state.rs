@@ -1,4 +1,5 @@
 use bstring::{bstr, BString};
+use enumflags2::BitFlags;
 use linearize::{static_map, StaticMap};
 use log::*;
 use measure_time::*;
@@ -6,23 +7,47 @@ use sdl2::event::Event as SdlEvent;
 use sdl2::keyboard::Keycode;
 use std::cell::RefCell;
 use std::cmp;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, prelude::*};
+use std::mem;
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
 use std::rc::Rc;
 use std::time::{Duration, Instant};
 
 use crate::asset::frame::{FrameDb, FrameId};
 use crate::asset::map::db::MapDb;
-use crate::asset::map::{MapId, MapReader, ELEVATION_COUNT};
-use crate::asset::message::{Messages, BULLET};
+use crate::asset::map::{Map, MapId, MapReader, MapWriter, ELEVATION_COUNT};
+use crate::asset::message::{MessageId, Messages, BULLET};
 use crate::asset::proto::*;
 use crate::asset::script::db::ScriptDb;
 use crate::asset::{self, *};
 use crate::fs::FileSystem;
+use crate::game::GameTime;
+use crate::game::ai::{self, Ai, AiDb};
+use crate::game::automap::AutomapDb;
+use crate::game::barter::{self, Barter};
+use crate::game::char_select::{load_gcd, Action as CharSelectAction, CharSelect};
+use crate::game::combat::{self, Combat, CombatSubtype, HitLocation};
+use crate::game::character_editor::{CharacterEditor, Mode as CharacterEditorMode};
 use crate::game::dialog::Dialog;
+use crate::game::drug;
 use crate::game::fidget::Fidget;
 use crate::game::inventory::Inventory;
+use crate::game::loot::Loot;
+use crate::game::main_menu::MainMenu;
+use crate::game::movie::{self, Movies};
 use crate::game::object::{self, *};
+use crate::game::options::Options;
+use crate::game::party::{self, Party};
+use crate::game::pipboy::{Action as PipboyAction, Info as PipboyInfo, Pipboy, RestUntil};
+use crate::game::queue;
 use crate::game::rpg::Rpg;
+use crate::game::save_load::{Mode as SaveLoadMode, SaveLoad};
+use crate::game::savegame::{self, SaveDatReader, SaveDatWriter};
 use crate::game::script::{self, ScriptKind, Scripts};
+use crate::game::sfx::{self, CharSfx, SceneryAction, WeaponSfx};
 use crate::game::sequence::frame_anim::{AnimDirection, FrameAnim, FrameAnimOptions};
 use crate::game::sequence::move_seq::Move;
 use crate::game::sequence::stand::Stand;
@@ -32,13 +57,16 @@ use crate::game::ui::action_menu::{self, Action};
 use crate::game::ui::hud;
 use crate::game::ui::scroll_area::ScrollArea;
 use crate::game::ui::world::{HexCursorStyle, WorldView};
-use crate::game::world::{ScrollDirection, World, WorldRef};
+use crate::game::world::{self as game_world, ScrollDirection, World, WorldRef};
+use crate::game::worldmap::{self, EncounterSpawn, Entrance, TravelEvent, WorldMap};
 use crate::graphics::font::Fonts;
 use crate::graphics::geometry::hex::{self, Direction};
 use crate::graphics::{EPoint, Rect};
+use crate::profile::Profile;
 use crate::sequence::chain::Chain;
 use crate::sequence::event::PushEvent;
 use crate::sequence::{self, Sequencer};
+use crate::sound::{Sound, VoiceId};
 use crate::state::{self, *};
 use crate::ui::command::inventory::Command;
 use crate::ui::command::*;
@@ -46,9 +74,32 @@ use crate::ui::message_panel::MessagePanel;
 use crate::ui::{self, Ui};
 use crate::util::random::random;
 use crate::util::{sprintf, EnumExt};
-use crate::vm::{PredefinedProc, Suspend, Vm};
+use crate::vm::{InvocationResult, PredefinedProc, Suspend, Vm, VmConfig};
+use crate::vm::debug::Debugger;
 
 const SCROLL_STEP: i32 = 10;
+const WORLD_MAP_STEP_INTERVAL: Duration = Duration::from_millis(20);
+/// Real time per one decisecond of the game time.
+const GAME_TICK_INTERVAL: Duration = Duration::from_millis(100);
+/// Game time passing per frame while resting.
+const REST_STEP: GameTime = GameTime::from_decis(5 * 60 * 10);
+/// Hit points are restored with the heal rate once per this game time while resting.
+const REST_HEAL_INTERVAL: GameTime = GameTime::from_decis(3 * 60 * 60 * 10);
+/// Radiation sickness wears off after this time.
+const RADIATION_SICKNESS_TIME: GameTime = GameTime::from_decis(7 * 24 * 60 * 60 * 10);
+const DYNAMITE_DAMAGE: RangeInclusive<i32> = 30..=50;
+const PLASTIC_EXPLOSIVE_DAMAGE: RangeInclusive<i32> = 40..=80;
+const EXPLOSION_RADIUS: u32 = 1;
+const MSG_PIPBOY_NOT_ACTIVE: MessageId = 7000;
+const MSG_RADIATION_LEVEL_BASE: MessageId = 1000;
+const MSG_RADIATION_DEATH: MessageId = 1006;
+const MSG_POISON_DAMAGE: MessageId = 3001;
+const MSG_POISON_DEATH: MessageId = 3002;
+const MSG_POISON_CURED: MessageId = 3003;
+/// Map the new game starts on.
+const START_MAP: &str = "artemple";
+/// Character loaded into the dude when the game is started with a map given on the command line.
+const DEFAULT_GCD: &str = "premade/combat.gcd";
 
 pub struct GameState {
     time: PausableTime,
@@ -60,6 +111,8 @@ pub struct GameState {
     scripts: Scripts,
     obj_sequencer: ObjSequencer,
     fidget: Fidget,
+    ai: Ai,
+    party: Party,
     message_panel: ui::Handle,
     world_view: ui::Handle,
     dialog: Option<Dialog>,
@@ -68,26 +121,61 @@ pub struct GameState {
     object_action_menu: Option<ObjectActionMenu>,
     user_paused: bool,
     map_id: Option<MapId>,
-    in_combat: bool,
+    /// Lowercase file name of the current map without the extension.
+    map_name: Option<String>,
+    /// Header of the current map. The `sqr_tiles` and `map_vars` are moved out into `World` and
+    /// `Scripts`.
+    map: Option<Map>,
+    /// Saved state of the visited maps keyed by the lowercase map name.
+    map_saves: HashMap<String, Vec<u8>>,
+    /// `SAVE.DAT` sections of the loaded game to write back when saving.
+    save_passthrough: savegame::Passthrough,
+    combat: Combat,
     seq_events: Vec<sequence::Event>,
     misc_msgs: Rc<Messages>,
     scroll_areas: StaticMap<ScrollDirection, ui::Handle>,
     rpg: Rpg,
     skilldex: Skilldex,
     inventory: Inventory,
+    barter: Barter,
+    loot: Loot,
+    character_editor: CharacterEditor,
     ui_sequencer: Sequencer,
+    sound: Sound,
+    next_ambient_sfx: Instant,
+    /// Last footstep sound per critter so footsteps don't overlap.
+    footsteps: HashMap<object::Handle, VoiceId>,
+    movies: Movies,
+    world_map: WorldMap,
+    next_world_map_step: Instant,
+    next_game_tick: Instant,
+    pipboy: Pipboy,
+    automaps: AutomapDb,
+    main_menu: MainMenu,
+    char_select: CharSelect,
+    save_load: SaveLoad,
+    options: Options,
+    /// Picture of the game screen taken when the options menu is shown. Used as the save game
+    /// thumbnail.
+    thumbnail: Option<Box<[u8]>>,
+    /// The character editor is shown to create the new character from the character selection.
+    new_character: bool,
+    app_events: Vec<AppEvent>,
 }
 
 impl GameState {
     #[allow(clippy::too_many_arguments)]
     pub fn new(
+        profile: Profile,
         fs: Rc<FileSystem>,
         language: &str,
         proto_db: Rc<ProtoDb>,
         frm_db: Rc<FrameDb>,
         fonts: Rc<Fonts>,
         misc_msgs: Rc<Messages>,
+        save_dir: PathBuf,
         now: Instant,
+        sound: Sound,
         ui: &mut Ui,
     ) -> Self {
         let time = PausableTime::new(now);
@@ -101,7 +189,7 @@ impl GameState {
         let scripts = Scripts::new(
             proto_db.clone(),
             ScriptDb::new(fs.clone(), language).unwrap(),
-            Vm::default());
+            Vm::new(Rc::new(VmConfig::new(profile))));
         let world = World::new(
             proto_db.clone(),
             frm_db.clone(),
@@ -113,10 +201,14 @@ impl GameState {
         let world = Rc::new(RefCell::new(world));
         let obj_sequencer = ObjSequencer::new(now);
         let fidget = Fidget::new(now);
+        let ai = Ai::new(AiDb::new(&fs).unwrap(), now);
+        let party = Party::new(&fs).unwrap();
 
         let world_view_rect = Rect::with_size(0, 0, 640, 379);
         let world_view = {
             let win = ui.new_window(world_view_rect, None);
+            // Shown when the map is loaded.
+            ui.widget_base_mut(win).set_visible(false);
             ui.new_widget(win, world_view_rect, None, None, WorldView::new(world.clone()))
         };
         let message_panel = hud::create(ui);
@@ -129,8 +221,28 @@ impl GameState {
 
         let inventory = Inventory::new(world.clone(), &fs, language);
 
+        let barter = Barter::new(world.clone(), &fs, language);
+
+        let loot = Loot::new(world.clone(), &fs, language);
+
+        let character_editor = CharacterEditor::new(world.clone(), &fs, language);
+
         let ui_sequencer = Sequencer::new(now);
 
+        let movies = Movies::new(fs.clone());
+
+        let world_map = WorldMap::new(&fs).unwrap();
+
+        let pipboy = Pipboy::new(&fs, language).unwrap();
+
+        let main_menu = MainMenu::new(misc_msgs.clone());
+        let char_select = CharSelect::new(world.clone(), fs.clone(), misc_msgs.clone());
+        let save_load = SaveLoad::new(
+            Rc::new(Messages::read_file(&fs, language, "game/lsgame.msg").unwrap()),
+            save_dir);
+        let options = Options::new(
+            Rc::new(Messages::read_file(&fs, language, "game/options.msg").unwrap()));
+
         Self {
             time,
             fs,
@@ -141,6 +253,8 @@ impl GameState {
             scripts,
             obj_sequencer,
             fidget,
+            ai,
+            party,
             message_panel,
             world_view,
             dialog: None,
@@ -149,14 +263,37 @@ impl GameState {
             object_action_menu: None,
             user_paused: false,
             map_id: None,
-            in_combat: false,
+            map_name: None,
+            map: None,
+            map_saves: HashMap::new(),
+            save_passthrough: Default::default(),
+            combat: Combat::new(),
             seq_events: Vec::new(),
             misc_msgs,
             scroll_areas,
             rpg,
             skilldex,
             inventory,
+            barter,
+            loot,
+            character_editor,
             ui_sequencer,
+            sound,
+            next_ambient_sfx: now,
+            footsteps: HashMap::new(),
+            movies,
+            world_map,
+            next_world_map_step: now,
+            next_game_tick: now,
+            pipboy,
+            automaps: AutomapDb::new(),
+            main_menu,
+            char_select,
+            save_load,
+            options,
+            thumbnail: None,
+            new_character: false,
+            app_events: Vec::new(),
         }
     }
 
@@ -172,37 +309,101 @@ impl GameState {
         &self.time
     }
 
+    /// Attaches the interactive debugger to the scripts. In game the backquote key makes
+    /// the debugger pause before the next script instruction.
+    pub fn set_script_debugger(&mut self, debugger: Debugger) {
+        self.scripts.set_debugger(Some(debugger.into_shared()));
+    }
+
+    /// Starts the new game with the default character. The map should be loaded afterwards.
     pub fn new_game(&mut self) {
+        self.map_saves.clear();
+        self.save_passthrough = Default::default();
+        self.automaps.clear();
+        self.party.clear();
+
         self.scripts.vars.global_vars =
             asset::read_game_global_vars(&mut self.fs.reader("data/vault13.gam").unwrap()).unwrap().into();
 
-        let dude_fid = FrameId::from_packed(0x100003E).unwrap();
-        //    let dude_fid = FrameId::from_packed(0x101600A).unwrap();
-        let _ = self.world.borrow_mut().objects_mut().create(
-            Some(dude_fid),
-            Some(self.proto_db.dude()),
-            Some(Default::default()),
-            Some(&self.rpg));
-
-        // TODO replace with proper init
-        self.world.borrow_mut().objects_mut().dude_mut().sub.as_critter_mut().unwrap()
-            .hit_points = 44;
-        let d = self.proto_db.dude();
-        let mut d = d.borrow_mut();
-        d.set_name("Narg".into());
-        let c = d.sub.as_critter_mut().unwrap();
-        c.base_stats[Stat::Strength] = 9;
-        c.base_stats[Stat::Perception] = 6;
-        c.base_stats[Stat::Endurance] = 10;
-        c.base_stats[Stat::Charisma] = 4;
-        c.base_stats[Stat::Intelligence] = 5;
-        c.base_stats[Stat::Agility] = 8;
-        c.base_stats[Stat::Luck] = 5;
-        c.base_stats[Stat::HitPoints] = 44;
-        c.base_stats[Stat::ArmorClass] = 8;
-        c.base_stats[Stat::ActionPoints] = 9;
-        c.base_stats[Stat::MeleeDmg] = 8;
-        c.base_stats[Stat::CarryWeight] = 250;
+        {
+            let mut world = self.world.borrow_mut();
+            world.clear();
+            world.game_time = game_world::START_GAME_TIME;
+
+            let dude_fid = FrameId::from_packed(0x100003E).unwrap();
+            //    let dude_fid = FrameId::from_packed(0x101600A).unwrap();
+            let _ = world.objects_mut().create(
+                Some(dude_fid),
+                Some(self.proto_db.dude()),
+                Some(Default::default()),
+                Some(&self.rpg));
+
+            if let Err(e) = load_gcd(&self.fs, DEFAULT_GCD, world.objects(),
+                &mut self.rpg)
+            {
+                warn!("couldn't load {}: {}", DEFAULT_GCD, e);
+            }
+        }
+        self.apply_preferences();
+    }
+
+    /// Shows the main menu optionally playing the intro movies first.
+    // main_menu_loop()
+    pub fn show_main_menu(&mut self, intro: bool, ui: &mut Ui) {
+        self.set_world_view_visible(false, ui);
+        self.main_menu.show(ui);
+        if intro {
+            self.movies.play_game_movie(movie::IPLOGO_MOVIE);
+            self.movies.play_game_movie(movie::INTRO_MOVIE);
+        }
+    }
+
+    pub fn in_game(&self) -> bool {
+        self.map_id.is_some()
+    }
+
+    /// Palette gamma set in the preferences.
+    pub fn brightness(&self) -> f64 {
+        self.options.prefs().brightness
+    }
+
+    /// Discards the current game.
+    // game_reset()
+    fn end_game(&mut self, ui: &mut Ui) {
+        self.scripts.reset();
+        self.obj_sequencer.clear();
+        self.combat.end();
+        self.dialog = None;
+        self.map_id = None;
+        self.map_name = None;
+        self.map = None;
+        self.map_saves.clear();
+        self.save_passthrough = Default::default();
+        self.automaps.clear();
+        self.party.clear();
+        self.world.borrow_mut().clear();
+        self.sound.stop_music();
+        self.set_world_view_visible(false, ui);
+    }
+
+    /// Starts the game with the character picked in the character selection.
+    fn start_new_game(&mut self, ui: &mut Ui) {
+        self.char_select.hide(ui);
+        self.main_menu.hide(ui);
+        self.switch_map(START_MAP, ui);
+        self.movies.play_game_movie(movie::ELDER_MOVIE);
+    }
+
+    fn apply_preferences(&mut self) {
+        let prefs = self.options.prefs();
+        self.rpg.set_game_difficulty(prefs.game_difficulty);
+        self.combat.set_difficulty(prefs.combat_difficulty);
+        self.world.borrow_mut().text_delay = prefs.text_delay;
+    }
+
+    fn set_world_view_visible(&self, visible: bool, ui: &mut Ui) {
+        let win = ui.window_of(self.world_view).unwrap();
+        ui.widget_base_mut(win).set_visible(visible);
     }
 
     pub fn switch_map(&mut self, map_name: &str, ui: &mut Ui) {
@@ -219,11 +420,29 @@ impl GameState {
                 source_obj: None,
                 target_obj: None,
                 skill: None,
+                fixed_param: 0,
                 rpg: &mut self.rpg,
+                sound: &mut self.sound,
+                map_db: &mut self.map_db,
+                movies: &mut self.movies,
+                combat: &mut self.combat,
+                world_map: &mut self.world_map,
+                party: &mut self.party,
             };
             self.scripts.execute_map_procs(PredefinedProc::MapExit, ctx);
         }
 
+        self.update_automap();
+
+        // The party members leave with the dude so they aren't saved with the map.
+        let travelers = self.party.leave_map(self.world.borrow_mut().objects_mut(), &self.scripts);
+        let dude_events = self.scripts.queue.leave_map(self.world.borrow().objects().dude());
+
+        let saved = self.map_id.map(|id| self.map_db.get(id).map(|d| d.saved).unwrap_or(true));
+        if saved == Some(true) {
+            self.save_map_state().unwrap();
+        }
+
         let mut dude_obj = {
             let mut world = self.world.borrow_mut();
             let dude_obj = world.objects().dude();
@@ -234,21 +453,47 @@ impl GameState {
 
         self.scripts.reset();
         self.obj_sequencer.clear();
+        self.combat.end();
 
         // Reinsert the hex cursor. Needs `world` to be not borrowed.
         ui.widget_mut::<WorldView>(self.world_view).ensure_hex_cursor();
 
         let world = &mut self.world.borrow_mut();
 
-        let map = MapReader {
-            reader: &mut self.fs.reader(&format!("maps/{}.map", map_name)).unwrap(),
-            objects: world.objects_mut(),
-            proto_db: &self.proto_db,
-            frm_db: &self.frm_db,
-            scripts: &mut self.scripts,
-        }.read().unwrap();
+        let mut map = if let Some(data) = self.map_saves.get(&map_name.to_ascii_lowercase()) {
+            debug!("restoring saved state of map `{}`", map_name);
+            MapReader {
+                reader: &mut &data[..],
+                objects: world.objects_mut(),
+                proto_db: &self.proto_db,
+                frm_db: &self.frm_db,
+                scripts: &mut self.scripts,
+            }.read().unwrap()
+        } else {
+            MapReader {
+                reader: &mut self.fs.reader(&format!("maps/{}.map", map_name)).unwrap(),
+                objects: world.objects_mut(),
+                proto_db: &self.proto_db,
+                frm_db: &self.frm_db,
+                scripts: &mut self.scripts,
+            }.read().unwrap()
+        };
 
         self.map_id = Some(map.id);
+        self.map_name = Some(map_name.to_ascii_lowercase());
+
+        // Fallout 1 has no map definitions.
+        let map_def = self.map_db.get(map.id);
+        if let Some(map_def) = map_def {
+            self.world_map.enter_map(&map_def.lookup_name, map.last_visit);
+        }
+        if let Some(music) = map_def.and_then(|d| d.music.as_ref()) {
+            self.sound.play_music(music);
+        } else {
+            self.sound.stop_music();
+        }
+        self.next_ambient_sfx = self.time.time() + Self::ambient_sfx_delay();
+        self.footsteps.clear();
 
         for elev in map.sqr_tiles.iter().flatten() {
             for &(floor, roof) in elev.as_slice() {
@@ -276,7 +521,7 @@ impl GameState {
         }
         self.frm_db.get(FrameId::EGG).unwrap();
 
-        world.set_sqr_tiles(map.sqr_tiles);
+        world.set_sqr_tiles(mem::take(&mut map.sqr_tiles));
 
         {
             let dude_obj = dude_obj.objects.get_mut(dude_obj.root).unwrap();
@@ -288,18 +533,23 @@ impl GameState {
             dude_obj.set_pos(Some(map.entrance));
         }
         let dude_obj = world.objects_mut().insert_graph(dude_obj);
+        self.scripts.queue.enter_map(dude_events, dude_obj);
 
         world.objects_mut().make_standing(dude_obj);
 
-        {
-            assert!(!map.savegame);
+        let hex_grid = world.hex_grid().clone();
+        self.party.enter_map(travelers, world.objects_mut(), &mut self.scripts, &hex_grid);
+
+        self.scripts.vars.map_vars = if map.savegame {
+            mem::take(&mut map.map_vars)
+        } else {
             let path = format!("maps/{}.gam", map_name);
-            self.scripts.vars.map_vars = if self.fs.exists(&path) {
+            if self.fs.exists(&path) {
                 asset::read_map_global_vars(&mut self.fs.reader(&path).unwrap()).unwrap().into()
             } else {
                 Vec::new().into()
-            };
-        }
+            }
+        };
 
         // Init scripts.
         {
@@ -313,7 +563,14 @@ impl GameState {
                 source_obj: None,
                 target_obj: None,
                 skill: None,
+                fixed_param: 0,
                 rpg: &mut self.rpg,
+                sound: &mut self.sound,
+                map_db: &mut self.map_db,
+                movies: &mut self.movies,
+                combat: &mut self.combat,
+                world_map: &mut self.world_map,
+                party: &mut self.party,
             };
 
             // PredefinedProc::Start for map script is never called.
@@ -329,6 +586,128 @@ impl GameState {
         }
 
         world.camera_look_at_dude();
+
+        let elevation = world.objects().dude_ref().pos().elevation;
+        self.automaps.update(map.id, elevation, world.objects(), world.hex_grid());
+
+        self.map = Some(map);
+
+        self.set_world_view_visible(true, ui);
+    }
+
+    /// Saves the game into the save slot directory `dir` creating it if needed.
+    pub fn save_game(&mut self, dir: &Path, description: &bstr) -> io::Result<()> {
+        debug!("saving game to {}", dir.display());
+
+        self.save_map_state()?;
+
+        std::fs::create_dir_all(dir)?;
+        let map_files = savegame::write_map_files(dir, &self.map_saves)?;
+
+        let layout = savegame::Layout::read(&self.fs)?;
+        let world = self.world.borrow();
+        let map_id = self.map_id.unwrap();
+        let map_name = self.map_name.as_ref().unwrap();
+        let mut header = savegame::Header::new(
+            self.proto_db.dude().borrow().name().unwrap_or_default().into(),
+            description.into(),
+            world.game_time,
+            world.elevation(),
+            map_id,
+            savegame::map_file_name(map_name).into());
+        if let Some(thumbnail) = &self.thumbnail {
+            header.thumbnail = thumbnail.clone();
+        }
+
+        let mut writer = BufWriter::new(File::create(dir.join(savegame::SAVE_DAT))?);
+        SaveDatWriter {
+            writer: &mut writer,
+            layout: &layout,
+            objects: world.objects(),
+            proto_db: &self.proto_db,
+            scripts: &self.scripts,
+            rpg: &self.rpg,
+        }.write(&header, &map_files, &self.save_passthrough)?;
+        writer.flush()
+    }
+
+    /// Loads the game saved in the save slot directory `dir` replacing the current game.
+    pub fn load_game(&mut self, dir: &Path, ui: &mut Ui) -> io::Result<()> {
+        debug!("loading game from {}", dir.display());
+
+        let layout = savegame::Layout::read(&self.fs)?;
+        let mut reader = BufReader::new(File::open(dir.join(savegame::SAVE_DAT))?);
+
+        self.scripts.reset();
+        self.obj_sequencer.clear();
+        self.combat.end();
+        self.map_id = None;
+        self.map_name = None;
+        self.map = None;
+        self.automaps.clear();
+        self.party.clear();
+
+        let save_dat = {
+            let mut world = self.world.borrow_mut();
+            world.clear();
+            SaveDatReader {
+                reader: &mut reader,
+                layout: &layout,
+                objects: world.objects_mut(),
+                proto_db: &self.proto_db,
+                frm_db: &self.frm_db,
+                scripts: &mut self.scripts,
+                rpg: &mut self.rpg,
+            }.read()?
+        };
+
+        self.map_saves = savegame::read_map_files(dir, &save_dat.map_files)?;
+        self.save_passthrough = save_dat.passthrough;
+        self.world.borrow_mut().game_time = save_dat.header.game_time;
+
+        let map_name = self.map_db.get(save_dat.header.map_id)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData,
+                format!("unknown map id: {}", save_dat.header.map_id)))?
+            .name.clone();
+        if !self.map_saves.contains_key(&map_name.to_ascii_lowercase()) {
+            return Err(io::Error::new(io::ErrorKind::InvalidData,
+                format!("missing saved state of the current map `{}`", map_name)));
+        }
+        self.switch_map(&map_name, ui);
+
+        // The party members were saved with the current map.
+        self.party.restore(self.world.borrow().objects(), &mut self.rpg);
+
+        Ok(())
+    }
+
+    /// Serializes state of the current map into `map_saves`.
+    fn save_map_state(&mut self) -> io::Result<()> {
+        let world = self.world.borrow();
+        let map_name = self.map_name.clone().unwrap();
+        let map = {
+            let dude = world.objects().dude_ref();
+            Map {
+                savegame: true,
+                last_visit: world.game_time,
+                entrance: dude.pos(),
+                entrance_direction: dude.direction,
+                sqr_tiles: world.sqr_tiles().to_vec(),
+                map_vars: self.scripts.vars.map_vars.clone(),
+                ..self.map.clone().unwrap()
+            }
+        };
+
+        let mut data = Vec::new();
+        MapWriter {
+            writer: &mut data,
+            objects: world.objects(),
+            scripts: &self.scripts,
+        }.write(&map)?;
+        debug!("saved state of map `{}` ({} bytes)", map_name, data.len());
+        self.map_saves.insert(map_name, data);
+
+        Ok(())
     }
 
     fn handle_action(
@@ -369,580 +748,2348 @@ impl GameState {
         }
     }
 
-    fn handle_seq_events(&mut self, ctx: &mut state::Update) {
-        use sequence::Event::*;
-        let mut events = std::mem::take(&mut self.seq_events);
-        for event in events.drain(..) {
-            match event {
-                ObjectMoved { obj, new_pos, .. } => {
-                    let world = self.world.borrow();
-                    if obj == world.objects().dude() {
-                        for &h in world.objects().at(new_pos) {
-                            let obj = world.objects().get(h);
-                            if let Some(map_exit) = obj.sub.as_map_exit() {
-                                debug!("dude on map exit object at {:?}: {:?}", new_pos, map_exit);
-                                ctx.out.push(AppEvent::MapExit {
-                                    map: map_exit.map,
-                                    pos: map_exit.pos,
-                                    direction: map_exit.direction,
-                                });
-                            }
-                        }
-                    }
-                }
-                Talk { talker, talked } => {
-                    self.talk(talker, talked, ctx.ui);
-                }
-                SetDoorState { door, open } => {
-                    self.set_door_state(door, open);
-                }
-                Use { user, used } => {
-                    self.use_obj(user, used, ctx.ui);
-                }
-                UseSkill { skill, user, target } => {
-                    self.use_skill_on(skill, user, target, ctx.ui);
-                }
-            }
-        }
-        self.seq_events = events;
+    /// Executes `proc` of the script attached to `obj` if there's one.
+    fn execute_obj_proc(&mut self,
+        obj: object::Handle,
+        proc: PredefinedProc,
+        source_obj: Option<object::Handle>,
+        fixed_param: i32,
+        ui: &mut Ui,
+    ) -> Option<InvocationResult> {
+        let sid = self.world.borrow().objects().get(obj).script.map(|(sid, _)| sid)?;
+        let r = self.scripts.execute_predefined_proc(sid, proc, &mut script::Context {
+            world: &mut self.world.borrow_mut(),
+            obj_sequencer: &mut self.obj_sequencer,
+            dialog: &mut self.dialog,
+            ui,
+            message_panel: self.message_panel,
+            map_id: self.map_id.unwrap(),
+            source_obj,
+            target_obj: None,
+            skill: None,
+            fixed_param,
+            rpg: &mut self.rpg,
+            sound: &mut self.sound,
+            map_db: &mut self.map_db,
+            movies: &mut self.movies,
+            combat: &mut self.combat,
+            world_map: &mut self.world_map,
+            party: &mut self.party,
+        })?;
+        assert!(r.suspend.is_none(), "can't suspend in {:?}", proc);
+        Some(r)
     }
 
-    fn actions(&self, objh: object::Handle) -> Vec<(Action, UiCommandData)> {
-        let mut r = Vec::new();
-        let world = self.world.borrow();
-        let obj = world.objects().get(objh);
-        match obj.kind() {
-            EntityKind::Critter => {
-                if objh == world.objects().dude() {
-                    r.push(Action::Rotate);
-                } else {
-                    if world.objects().get(objh).can_talk_to() {
-                        if !self.in_combat {
-                            r.push(Action::Talk);
-                        }
-                    } else if !obj.proto().unwrap()
-                        .sub.as_critter().unwrap()
-                        .flags.contains(CritterFlag::NoSteal)
-                    {
-                        r.push(Action::UseHand);
-                    }
-                    if world.objects().can_push(world.objects().dude(), objh,
-                        &self.scripts, self.in_combat)
-                    {
-                        r.push(Action::Push);
-                    }
+    /// Processes combat requests made by scripts and advances the combat turns.
+    // combat()
+    fn update_combat(&mut self, ui: &mut Ui) {
+        for request in self.combat.take_requests() {
+            match request {
+                combat::Request::Attack { attacker, target, scripted } => {
+                    self.request_attack(attacker, target, scripted, ui);
                 }
-                r.extend_from_slice(&[Action::Look, Action::Inventory, Action::UseSkill])
-            }
-            EntityKind::Item => {
-                r.extend_from_slice(&[Action::UseHand, Action::Look]);
-                if world.objects().get(objh).item_kind() == Some(ItemKind::Container) {
-                    r.extend_from_slice(&[Action::UseSkill, Action::Inventory]);
+                combat::Request::Damage { target, damage, damage_kind, bypass_armor, animate } => {
+                    self.damage_critter(target, damage, damage_kind, bypass_armor, animate, ui);
                 }
+                combat::Request::End => self.end_combat(ui),
             }
-            EntityKind::Scenery => {
-                if world.objects().get(objh).can_use() {
-                    r.push(Action::UseHand)
-                }
-                r.extend_from_slice(&[Action::Look, Action::Inventory, Action::UseSkill])
+        }
+
+        if !self.combat.is_active()
+            || self.combat.participants().any(|h| self.obj_sequencer.is_running(h))
+        {
+            return;
+        }
+
+        {
+            let world = self.world.borrow();
+            let objs = world.objects();
+            let gone: Vec<_> = self.combat.participants()
+                .filter(|&h| !objs.contains(h)
+                    || objs.get(h).try_pos().is_none()
+                    || objs.get(h).is_critter_dead())
+                .collect();
+            for h in gone {
+                self.combat.remove(h);
             }
-            EntityKind::Wall => {
-                r.push(Action::Look);
-                if world.objects().get(objh).can_use() {
-                    r.push(Action::UseHand)
-                }
+        }
+
+        if !self.combat.has_hostiles() {
+            self.end_combat(ui);
+            return;
+        }
+
+        let current = self.combat.current().unwrap();
+        if !self.combat.is_turn_started() {
+            if !self.start_combat_turn(current, ui) {
+                self.combat.next_turn();
             }
-            _ => {}
+            return;
         }
-        if !r.is_empty() {
-            r.push(Action::Cancel)
+        if current == self.world.borrow().objects().dude() {
+            // Waiting for the player.
+            return;
+        }
+        if !self.combat_ai(current) {
+            self.combat.next_turn();
         }
-        r.iter()
-            .map(|&action| (action, UiCommandData::Action { action }))
-            .collect()
     }
 
-    fn look_at_object(&mut self, looker: object::Handle, looked: object::Handle, ui: &mut Ui)
-        -> Option<BString>
-    {
-        let sid = {
+    // scripts_request_combat()
+    fn request_attack(&mut self,
+        attacker: object::Handle,
+        target: object::Handle,
+        scripted: Option<combat::ScriptedAttack>,
+        ui: &mut Ui,
+    ) {
+        let (dude, participants) = {
             let world = self.world.borrow();
-            let lookero = world.objects().get(looker);
-            let lookedo = world.objects().get(looked);
-            if lookero.sub.as_critter().map(|c| c.is_dead()).unwrap_or(true)
-                // TODO This is only useful for mapper?
-                || lookedo.kind() == EntityKind::SqrTile
-                || lookedo.proto_ref().is_none()
+            let objs = world.objects();
+            let is_live_critter = |h| objs.contains(h)
+                && objs.get(h).sub.as_critter().is_some_and(|c| !c.is_dead());
+            let elevation = |h| objs.get(h).try_pos().map(|p| p.elevation);
+            if attacker == target
+                || !is_live_critter(attacker)
+                || !is_live_critter(target)
+                || elevation(attacker).is_none()
+                || elevation(attacker) != elevation(target)
             {
-                return None;
+                debug!("ignoring combat request {:?} -> {:?}", attacker, target);
+                return;
             }
-            lookedo.script.map(|(v, _)| v)
+            let team = |h| objs.get(h).sub.as_critter().unwrap().combat.team_id;
+            let sequence = |h| self.rpg.stat(Stat::Sequence, &objs.get(h), objs);
+            let dude = objs.dude();
+
+            // The dude is always in combat. Team mates of the attacker and the target join in.
+            let mut participants = vec![(attacker, Some(target)), (target, Some(attacker))];
+            for h in objs.iter() {
+                if h == attacker || h == target || !is_live_critter(h)
+                    || elevation(h) != elevation(attacker)
+                    || objs.get(h).flags.contains(Flag::TurnedOff)
+                {
+                    continue;
+                }
+                let enemy = if team(h) == team(attacker) {
+                    Some(target)
+                } else if team(h) == team(target) {
+                    Some(attacker)
+                } else if h == dude {
+                    None
+                } else {
+                    continue;
+                };
+                participants.push((h, enemy));
+            }
+            let participants: Vec<_> = participants.into_iter()
+                .map(|(h, enemy)| (h, sequence(h), enemy))
+                .collect();
+            (dude, participants)
         };
 
-        if let Some(sid) = sid &&
-            let Some(r) = self.scripts.execute_predefined_proc(sid, PredefinedProc::LookAt,
-            &mut script::Context {
-                world: &mut self.world.borrow_mut(),
-                obj_sequencer: &mut self.obj_sequencer,
-                dialog: &mut self.dialog,
-                ui,
-                message_panel: self.message_panel,
-                map_id: self.map_id.unwrap(),
-                source_obj: Some(looker),
-                target_obj: Some(looked),
-                skill: None,
-                rpg: &mut self.rpg,
-            })
-       {
-            assert!(r.suspend.is_none(), "can't suspend");
-            if r.script_overrides {
-                return None;
+        let starting = !self.combat.is_active();
+        if starting {
+            debug!("starting combat: {:?} attacks {:?}", attacker, target);
+            self.combat.begin((attacker, participants[0].1),
+                participants.iter().map(|&(h, sequence, _)| (h, sequence)));
+        } else {
+            for &(h, sequence, _) in &participants {
+                self.combat.join(h, sequence);
+            }
+        }
+        self.combat.set_target(attacker, Some(target));
+        self.combat.set_scripted_attack(attacker, scripted);
+        for &(h, _, enemy) in &participants[1..] {
+            // The player chooses targets himself.
+            if h != dude && self.combat.target(h).is_none() {
+                self.combat.set_target(h, enemy);
             }
         }
 
-        let world = self.world.borrow();
-        let lookedo = world.objects().get(looked);
-        let msg_id = if lookedo.sub.as_critter().map(|c| c.is_dead()).unwrap_or(false) {
-            491 + random(0, 1)
-        } else {
-            490
-        };
-        if let Some(msg) = self.proto_db.messages().get(msg_id) &&
-            let Some(name) = world.object_name(looked)
-        {
-            Some(sprintf(&msg.text, &[&*name]))
-        } else {
-            None
+        if starting {
+            let participants: Vec<_> = self.combat.participants().collect();
+            for &h in &participants {
+                self.obj_sequencer.cancel(h);
+                self.world.borrow_mut().objects_mut().make_standing(h);
+            }
+            for &h in &participants {
+                self.execute_obj_proc(h, PredefinedProc::CombatIsStarting, None, 0, ui);
+            }
         }
     }
 
-    fn dude_look_at_object(&mut self, obj: object::Handle, ui: &mut Ui) {
-        let dude_obj = self.world().borrow().objects().dude();
-        if let Some(msg) = self.look_at_object(dude_obj, obj, ui) {
-            self.push_message(&msg, ui);
+    // combat_over()
+    fn end_combat(&mut self, ui: &mut Ui) {
+        let participants = self.combat.end();
+        if !participants.is_empty() {
+            debug!("combat is over");
+        }
+        for obj in participants {
+            if self.world.borrow().objects().contains(obj) {
+                if let Some(c) = self.world.borrow().objects().get_mut(obj).sub.as_critter_mut() {
+                    c.combat.fleeing = false;
+                }
+                self.execute_obj_proc(obj, PredefinedProc::CombatIsOver, None, 0, ui);
+            }
         }
     }
 
-    // obj_examine_func()
-    fn examine_object(&mut self, examiner: object::Handle, examined: object::Handle, ui: &mut Ui)
-        -> Vec<BString>
-    {
-        let sid = {
+    /// Returns `false` if the critter skips the turn.
+    // combat_turn()
+    fn start_combat_turn(&mut self, obj: object::Handle, ui: &mut Ui) -> bool {
+        let (action_points, stand_up_anim) = {
             let world = self.world.borrow();
-            let examinero = world.objects().get(examiner);
-            let examinedo = world.objects().get(examined);
-            if examinero.sub.as_critter().map(|c| c.is_dead()).unwrap_or(false)
-                // TODO This is only useful for mapper?
-                || examinedo.kind() == EntityKind::SqrTile
-            {
-                return Vec::new();
+            let objs = world.objects();
+            let mut o = objs.get_mut(obj);
+            let flags = &mut o.sub.as_critter_mut().unwrap().combat.damage_flags;
+            if flags.contains(DamageFlag::LoseTurn) {
+                flags.remove(DamageFlag::LoseTurn);
+                return false;
             }
-            examinedo.script.map(|(v, _)| v)
-        };
+            if flags.contains(DamageFlag::KnockedOut) {
+                flags.remove(DamageFlag::KnockedOut);
+                flags.insert(DamageFlag::KnockedDown);
+                return false;
+            }
+            let knocked_down = flags.contains(DamageFlag::KnockedDown);
+            flags.remove(DamageFlag::KnockedDown);
 
-        let script_overrides = if let Some(sid) = sid &&
-            let Some(r) = self.scripts.execute_predefined_proc(sid, PredefinedProc::Description,
-            &mut script::Context {
-                world: &mut self.world.borrow_mut(),
-                obj_sequencer: &mut self.obj_sequencer,
-                dialog: &mut self.dialog,
-                ui,
-                message_panel: self.message_panel,
-                map_id: self.map_id.unwrap(),
-                source_obj: Some(examiner),
-                target_obj: Some(examined),
-                skill: None,
-                rpg: &mut self.rpg,
-            })
-        {
-            assert!(r.suspend.is_none(), "can't suspend");
-            r.script_overrides
-        } else {
-            false
+            let mut action_points = self.rpg.stat(Stat::ActionPoints, &o, objs);
+            let stand_up_anim = if knocked_down {
+                action_points -= 3;
+                Some(combat::stand_up_anim(&o))
+            } else {
+                None
+            };
+            (cmp::max(action_points, 0), stand_up_anim)
         };
+        if let Some(anim) = stand_up_anim {
+            self.play_critter_anim(obj, anim, true);
+        }
 
-        let mut r = Vec::new();
+        self.combat.start_turn(action_points);
 
-        if !script_overrides {
+        let overrides = self.execute_obj_proc(obj, PredefinedProc::Combat, None,
+            CombatSubtype::Turn as i32, ui)
+            .is_some_and(|r| r.script_overrides);
+        !overrides
+    }
+
+    /// Attacks `target` if it's within the weapon range, otherwise moves closer.
+    /// Returns `false` if there's nothing the critter can do.
+    fn combat_approach_or_attack(&mut self, obj: object::Handle, target: object::Handle) -> bool {
+        let (weapon, distance, blocked) = {
             let world = self.world.borrow();
-            let examinedo = world.objects().get(examined);
-            if !examinedo.sub.as_critter().map(|c| c.is_dead()).unwrap_or(false) {
-                let descr = examinedo.proto()
-                    .and_then(|p| {
-                        p.description()
-                            .filter(|s| {
-                                // Compare to "<None>".
-                                s != &self.proto_db.messages().get(10).unwrap().text
-                            })
-                            .map(|s| s.to_owned())
-                    })
-                    .unwrap_or_else(|| self.proto_db.messages().get(493).unwrap().text.clone());
-                r.push(descr);
+            let objs = world.objects();
+            if objs.get(obj).try_pos().map(|p| p.elevation)
+                != objs.get(target).try_pos().map(|p| p.elevation)
+            {
+                return false;
+            }
+            let Some(weapon) = combat::Weapon::of(obj, &self.rpg, objs) else {
+                return false;
+            };
+            let distance = objs.distance(obj, target).unwrap() as i32;
+            let blocked = weapon.is_ranged() && objs.is_shot_blocked(obj, target);
+            (weapon, distance, blocked)
+        };
+        let action_points = self.combat.action_points(obj);
+        if distance <= weapon.range && !blocked {
+            if action_points < weapon.ap_cost {
+                return false;
             }
+            self.combat_attack(obj, target, weapon, HitLocation::Uncalled);
+            true
+        } else {
+            let steps = cmp::min(action_points, cmp::max(distance - weapon.range, 1));
+            self.combat_move(obj, PathTo::Object(target), steps)
         }
+    }
 
-        // TODO critter state/hp, weapon/ammo description, car info etc
+    /// Decides what the critter does during its combat turn according to its AI packet.
+    /// Returns `false` if the critter has nothing else to do this turn.
+    // combat_ai()
+    fn combat_ai(&mut self, obj: object::Handle) -> bool {
+        let packet = self.ai.packet(&self.world.borrow().objects().get(obj)).cloned();
+        let Some(packet) = packet else {
+            return self.combat.target(obj)
+                .is_some_and(|target| self.combat_approach_or_attack(obj, target));
+        };
 
-        r
-    }
+        if self.combat.action_points(obj) >= ai::INVENTORY_AP_COST && self.ai_heal(obj, &packet) {
+            self.combat.spend_action_points(obj, ai::INVENTORY_AP_COST);
+            return true;
+        }
 
-    fn dude_examine_object(&mut self, obj: object::Handle, ui: &mut Ui) {
-        let dude_obj = self.world().borrow().objects().dude();
-        for msg in self.examine_object(dude_obj, obj, ui) {
-            self.push_message(&msg, ui);
+        let enemies = self.combat_enemies(obj);
+        let (fleeing, target) = {
+            let world = self.world.borrow();
+            let objs = world.objects();
+            let hurt = ai::is_hurt_too_much(&packet, &objs.get(obj), &self.rpg, objs);
+            let fleeing = {
+                let mut o = objs.get_mut(obj);
+                let combat = &mut o.sub.as_critter_mut().unwrap().combat;
+                combat.fleeing |= hurt;
+                combat.fleeing
+            };
+            let attackers: Vec<_> = enemies.iter().copied()
+                .filter(|&h| self.combat.target(h) == Some(obj))
+                .collect();
+            (fleeing, ai::choose_target(obj, &packet, &enemies, &attackers, objs))
+        };
+        if fleeing {
+            return self.combat_flee(obj, &enemies);
+        }
+
+        self.combat.set_target(obj, target);
+        let Some(target) = target else {
+            return false;
+        };
+
+        if self.combat.action_points(obj) >= ai::INVENTORY_AP_COST
+            && self.ai_equip_weapon(obj, &packet)
+        {
+            self.combat.spend_action_points(obj, ai::INVENTORY_AP_COST);
+            return true;
         }
+        if self.ai_pick_up_weapon(obj, true) {
+            return true;
+        }
+
+        self.combat_ai_attack(obj, target, &packet)
     }
 
-    fn push_message(&self, msg: &bstr, ui: &mut Ui) {
-        let mut mp = ui.widget_mut::<MessagePanel>(self.message_panel);
-        let mut m = BString::new();
-        m.push(BULLET);
-        m.push_str(msg);
-        mp.push_message(m);
+    /// Returns the combat participants hostile to the critter.
+    fn combat_enemies(&self, obj: object::Handle) -> Vec<object::Handle> {
+        let world = self.world.borrow();
+        let objs = world.objects();
+        let team = |h| objs.get(h).sub.as_critter().map(|c| c.combat.team_id);
+        // Whether `a` fights with someone from the team of `b`.
+        let fights = |a, b| self.combat.target(a).is_some_and(|t| team(t) == team(b));
+        self.combat.participants()
+            .filter(|&h| team(h) != team(obj) && (fights(h, obj) || fights(obj, h)))
+            .collect()
     }
 
-    // action_talk_to()
-    fn action_talk(&mut self, talker: object::Handle, talked: object::Handle, ui: &mut Ui) {
-        // TODO handle combat state
+    /// Attacks `target` or moves to a better position keeping the distance preferred by the
+    /// AI packet. Returns `false` if there's nothing the critter can do.
+    fn combat_ai_attack(&mut self,
+        obj: object::Handle,
+        target: object::Handle,
+        packet: &ai::Packet,
+    ) -> bool {
+        const CALLED_LOCATIONS: [HitLocation; 8] = [
+            HitLocation::Head,
+            HitLocation::LeftArm,
+            HitLocation::RightArm,
+            HitLocation::Torso,
+            HitLocation::RightLeg,
+            HitLocation::LeftLeg,
+            HitLocation::Eyes,
+            HitLocation::Groin,
+        ];
+
+        let action_points = self.combat.action_points(obj);
+        let (weapon, distance, in_range, snipe_point, may_approach, good_chance, area_ok, location) = {
+            let world = self.world.borrow();
+            let objs = world.objects();
+            let (o, t) = (objs.get(obj), objs.get(target));
+            if o.try_pos().map(|p| p.elevation) != t.try_pos().map(|p| p.elevation) {
+                return false;
+            }
+            let Some(weapon) = combat::Weapon::of(obj, &self.rpg, objs) else {
+                return false;
+            };
+            let distance = o.distance(&t).unwrap() as i32;
+            let in_range = distance <= weapon.range
+                && !(weapon.is_ranged() && objs.is_shot_blocked(obj, target));
+            let hit_chance = |location| combat::attack_hit_chance(obj, target, &weapon, location,
+                self.combat.difficulty(), &self.rpg, objs);
+            let chance = hit_chance(HitLocation::Uncalled);
+            let area_ok = weapon.attack_kind != AttackKind::FireBurst
+                || packet.allows_area_attack(chance, ai::friends_at_risk(&o, &t, objs),
+                    random(1, 100));
+            let location = if packet.called_freq > 0
+                && weapon.attack_kind != AttackKind::FireBurst
+                && random(1, packet.called_freq) == 1
+            {
+                Some(CALLED_LOCATIONS[random(0, CALLED_LOCATIONS.len() as i32 - 1) as usize])
+                    .filter(|&l| hit_chance(l) >= packet.min_to_hit)
+            } else {
+                None
+            };
+            let snipe_point = ai::snipe_point(packet, weapon.is_ranged(), &o, &t,
+                world.hex_grid());
+            let may_approach = ai::may_approach(packet, &t, objs);
+            let good_chance = chance >= packet.min_to_hit;
+            (weapon, distance, in_range, snipe_point, may_approach, good_chance, area_ok,
+                location.unwrap_or(HitLocation::Uncalled))
+        };
 
+        if let Some(point) = snipe_point
+            && action_points > weapon.ap_cost
+            && self.combat_move(obj, PathTo::Point { point, neighbor_if_blocked: true },
+                action_points - weapon.ap_cost)
         {
+            return true;
+        }
+        if in_range && area_ok && (good_chance || distance <= 1 || !may_approach) {
+            if action_points < weapon.ap_cost {
+                return false;
+            }
+            self.combat_attack(obj, target, weapon, location);
+            return true;
+        }
+        if !may_approach {
+            return false;
+        }
+        // Get closer to improve the hit chance.
+        let steps = if in_range {
+            1
+        } else {
+            cmp::max(distance - weapon.range, 1)
+        };
+        self.combat_move(obj, PathTo::Object(target), cmp::min(action_points, steps))
+    }
+
+    /// Runs away from the closest enemy spending all action points.
+    fn combat_flee(&mut self, obj: object::Handle, enemies: &[object::Handle]) -> bool {
+        let action_points = self.combat.action_points(obj);
+        let point = {
             let world = self.world.borrow();
             let objs = world.objects();
+            let o = objs.get(obj);
+            let Some(enemy) = enemies.iter().copied()
+                .min_by_key(|&h| objs.distance(obj, h).unwrap_or(u32::MAX))
+            else {
+                return false;
+            };
+            ai::flee_point(&o, &objs.get(enemy), action_points as u32, world.hex_grid())
+        };
+        point.is_some_and(|point| self.combat_move(obj,
+            PathTo::Point { point, neighbor_if_blocked: true }, action_points))
+    }
 
-            if objs.distance(talker, talked).unwrap() >= 9 || // TODO this value is different (12) in can_talk2()
-                objs.is_shot_blocked(talker, talked)
-            {
-                let chain = Chain::new();
-                chain.control()
-                    .cancellable(Move::new(talker, PathTo::Object(talked), CritterAnim::Running))
-                    .finalizing(Stand::new(talker))
-                    .finalizing(PushEvent::new(sequence::Event::Talk { talker, talked }));
-                self.obj_sequencer.replace(talker, chain);
-                return;
+    /// Uses a healing drug from the inventory if the critter is hurt enough according to its
+    /// AI packet. Returns `true` if the drug was used.
+    fn ai_heal(&mut self, obj: object::Handle, packet: &ai::Packet) -> bool {
+        let drug = {
+            let world = self.world.borrow();
+            let objs = world.objects();
+            let o = objs.get(obj);
+            let (hp, max_hp) = ai::hit_points(&o, &self.rpg, objs);
+            if !packet.wants_healing(hp, max_hp, random(1, 100)) {
+                return false;
             }
-        }
+            ai::find_healing_drug(&o, packet, objs)
+        };
+        let Some(drug) = drug else {
+            return false;
+        };
+        self.use_drug(obj, drug);
+        self.play_critter_anim(obj, CritterAnim::MagicHandsMiddle, true);
+        true
+    }
 
-        self.talk(talker, talked, ui);
+    /// Takes the drug by the critter and removes it from the inventory.
+    // item_d_take_drug()
+    fn use_drug(&mut self, critter: object::Handle, drug: object::Handle) {
+        let mut world = self.world.borrow_mut();
+        let now = world.game_time;
+        let objs = world.objects_mut();
+        drug::take_drug(critter, drug, &self.rpg, objs, &mut self.scripts.queue, now);
+        debug!("{:?} used {:?}", critter, drug);
+        if objs.remove_from_inventory(critter, drug, 1) == Some(0) {
+            objs.remove(drug);
+        }
     }
 
-    // talk_to(), gdialogEnter()
-    fn talk(&mut self, talker: object::Handle, talked: object::Handle, ui: &mut Ui) {
-        if self.world.borrow().objects().can_talk_now(talker, talked) {
-            let world = &mut *self.world.borrow_mut();
-            self.obj_sequencer.clear();
-            self.obj_sequencer.sync(&mut sequence::Sync {
-                world,
-                ui,
-            });
-            let script = world.objects().get(talked).script;
-            if let Some((sid, _)) = script {
-                match self.scripts.execute_predefined_proc(sid, PredefinedProc::Talk,
-                    &mut script::Context {
-                        world,
-                        obj_sequencer: &mut self.obj_sequencer,
-                        dialog: &mut self.dialog,
-                        ui,
-                        message_panel: self.message_panel,
-                        map_id: self.map_id.unwrap(),
-                        source_obj: Some(talker),
-                        target_obj: Some(talked),
-                        skill: None,
-                        rpg: &mut self.rpg,
-                    }).and_then(|r| r.suspend)
-                    {
-                        None | Some(Suspend::GsayEnd) => {}
-                    }
+    /// Wields the best weapon according to the AI packet or reloads the wielded one.
+    /// Returns `true` if the critter did something.
+    // ai_switch_weapons()
+    fn ai_equip_weapon(&mut self, obj: object::Handle, packet: &ai::Packet) -> bool {
+        let reload = {
+            let world = self.world.borrow();
+            let objs = world.objects();
+            let hand = combat::attack_hand(&objs.get(obj));
+            let hand_flag = match hand {
+                Hand::Left => Flag::LeftHand,
+                Hand::Right => Flag::RightHand,
+            };
+            let in_hand = objs.get(obj).equipment(EquipmentSlot::Hand(hand), objs);
+            let wielded = in_hand
+                .filter(|&h| objs.get(h).proto().is_some_and(|p| p.sub.as_weapon().is_some()));
+            let best = ai::best_weapon(&objs.get(obj), wielded, packet, &self.frm_db, objs);
+            if best != wielded {
+                debug!("{:?} switches weapon {:?} -> {:?}", obj, wielded, best);
+                if let Some(h) = in_hand {
+                    objs.get_mut(h).flags.remove(hand_flag);
+                }
+                if let Some(h) = best {
+                    objs.get_mut(h).flags.insert(hand_flag);
+                }
+                let mut o = objs.get_mut(obj);
+                o.fid = o.equipped_fid(objs, &self.rpg);
+                return true;
             }
+            wielded
+                .filter(|&h| {
+                    let w = objs.get(h);
+                    w.ammo_count() == Some(0)
+                        && w.proto().and_then(|p| p.max_ammo_count()).unwrap_or(0) > 0
+                })
+                .and_then(|h| ai::find_ammo(&objs.get(obj), h, objs).map(|ammo| (h, ammo)))
+        };
+        if let Some((weapon, ammo)) = reload {
+            debug!("{:?} reloads {:?} with {:?}", obj, weapon, ammo);
+            self.world.borrow_mut().objects_mut().reload_weapon_from_inventory(obj, weapon, ammo);
+            true
         } else {
-            assert_eq!(talker, self.world.borrow().objects().dude());
-            let msg = &self.misc_msgs.get(2000).unwrap().text;
-            self.push_message(msg, ui);
+            false
         }
     }
 
-    //  action_use_an_item_on_object_
-    fn action_use_obj(&mut self, user: object::Handle, used: object::Handle) {
-        let world = self.world.borrow();
-        let objs = world.objects();
-        let usero = objs.get(user);
-        let usedo = objs.get(used);
+    /// Goes to pick up a weapon lying nearby if the critter has nothing to fight with.
+    fn ai_pick_up_weapon(&mut self, obj: object::Handle, in_combat: bool) -> bool {
+        let item = {
+            let world = self.world.borrow();
+            let objs = world.objects();
+            let o = objs.get(obj);
+            let armed = o.inventory.items.iter()
+                .any(|i| ai::can_use_weapon(&o, &objs.get(i.object), &self.frm_db, objs));
+            if armed {
+                return false;
+            }
+            ai::find_weapon_nearby(&o, world.hex_grid(), &self.frm_db, objs)
+        };
+        item.is_some_and(|item| self.action_pick_up(obj, item, in_combat))
+    }
 
-        let used_kind = usedo.proto().map(|p| p.kind()).unwrap();
-        if used_kind == ExactEntityKind::Scenery(SceneryKind::LadderDown) {
-            // TODO action_climb_ladder
+    /// Lets the critters outside of combat heal themselves and pick up weapons.
+    fn update_idle_ai(&mut self) {
+        if !self.ai.is_idle_time(self.time.time()) {
             return;
         }
-
-        let seq = Chain::new();
-
-        let move_anim = if usero.distance(&usedo).unwrap() < 5 {
-            CritterAnim::Walk
-        } else {
-            CritterAnim::Running
+        let critters: Vec<_> = {
+            let world = self.world.borrow();
+            let objs = world.objects();
+            let dude = objs.dude();
+            objs.iter()
+                .filter(|&h| {
+                    let o = objs.get(h);
+                    h != dude
+                        && o.sub.as_critter().is_some_and(|c| !c.is_dead())
+                        && !o.flags.contains(Flag::TurnedOff)
+                        && o.try_pos().is_some_and(|p| p.elevation == world.elevation())
+                        && !self.obj_sequencer.is_running(h)
+                })
+                .collect()
         };
-        seq.control().cancellable(Move::new(user, PathTo::Object(used), move_anim));
-
-        let weapon = usero.fid.critter().unwrap().weapon();
-        if weapon != WeaponKind::Unarmed {
-            seq.control().cancellable(FrameAnim::new(user,
-                FrameAnimOptions { anim: Some(CritterAnim::PutAway), ..Default::default() }));
+        for h in critters {
+            let Some(packet) = self.ai.packet(&self.world.borrow().objects().get(h)).cloned() else {
+                continue;
+            };
+            if !self.ai_heal(h, &packet) && ai::arms_when_idle(&packet) {
+                self.ai_pick_up_weapon(h, false);
+            }
         }
+    }
 
-        if used_kind != ExactEntityKind::Scenery(SceneryKind::Stairs) {
-            // FIXME must call check_next_to() before running this animation
-            let use_anim = if usedo.is_critter_prone() ||
-                usedo.kind() == EntityKind::Scenery &&
-                    usedo.proto().unwrap().flags_ext.contains(FlagExt::Prone)
-            {
-                CritterAnim::MagicHandsGround
-            } else {
-                CritterAnim::MagicHandsMiddle
+    /// Makes the party members follow the dude. The members left on another elevation are moved
+    /// next to the dude.
+    // partyMemberSyncPosition()
+    fn update_party(&mut self) {
+        let world = &mut self.world.borrow_mut();
+        let dude = world.objects().dude();
+        let Some(dude_pos) = world.objects().dude_ref().try_pos() else {
+            return;
+        };
+        let hex_grid = world.hex_grid().clone();
+        let members: Vec<_> = self.party.members().collect();
+        for h in members {
+            if self.obj_sequencer.is_running(h) {
+                continue;
+            }
+            let (elevation, anim) = {
+                let objs = world.objects();
+                let o = objs.get(h);
+                if o.sub.as_critter().is_none_or(|c| c.is_dead())
+                    || o.flags.contains(Flag::TurnedOff)
+                {
+                    continue;
+                }
+                (o.try_pos().map(|p| p.elevation), party::follow_anim(&o, &objs.dude_ref()))
             };
-            seq.control().cancellable(FrameAnim::new(user,
-                FrameAnimOptions { anim: Some(use_anim), ..Default::default() }));
+            if elevation != Some(dude_pos.elevation) {
+                if let Some(pos) = party::free_pos_near(dude_pos, world.objects(), &hex_grid) {
+                    world.objects_mut().set_pos(h, Some(pos));
+                    world.objects_mut().make_standing(h);
+                }
+            } else if let Some(anim) = anim
+                && world.objects().path(h, PathTo::Object(dude), true).is_some()
+            {
+                let seq = Chain::new();
+                seq.control()
+                    .cancellable(Move::new(h, PathTo::Object(dude), anim))
+                    .finalizing(Stand::new(h));
+                self.obj_sequencer.replace(h, seq);
+            }
         }
+    }
 
-        seq.control().cancellable(PushEvent::new(sequence::Event::Use { user, used }));
-        if weapon != WeaponKind::Unarmed {
-            seq.control().cancellable(FrameAnim::new(user,
-                FrameAnimOptions { anim: Some(CritterAnim::TakeOut), ..Default::default() }));
+    /// Walks to the item lying on the ground and picks it up. In combat the walking costs
+    /// action points. Returns `false` if the item can't be reached.
+    fn action_pick_up(&mut self, picker: object::Handle, item: object::Handle, in_combat: bool)
+        -> bool
+    {
+        {
+            let world = self.world.borrow();
+            let Some(path) = world.objects().path(picker, PathTo::Object(item), true) else {
+                return false;
+            };
+            if in_combat && !self.combat.spend_action_points(picker, path.len() as i32) {
+                return false;
+            }
         }
-        seq.control().finalizing(Stand::new(user));
-
-        self.obj_sequencer.replace(user, seq);
+        let seq = Chain::new();
+        seq.control()
+            .cancellable(Move::new(picker, PathTo::Object(item), CritterAnim::Walk))
+            .cancellable(FrameAnim::new(picker, FrameAnimOptions {
+                anim: Some(CritterAnim::MagicHandsGround),
+                ..Default::default()
+            }))
+            .cancellable(PushEvent::new(sequence::Event::PickUp { picker, item }))
+            .finalizing(Stand::new(picker));
+        self.obj_sequencer.replace(picker, seq);
+        true
     }
 
-    // obj_use
-    fn use_obj(&mut self, user: object::Handle, used: object::Handle, ui: &mut Ui) {
-        if !self.check_next_to(user, used, ui) {
+    // obj_pickup()
+    fn pick_up(&mut self, picker: object::Handle, item: object::Handle) {
+        let mut world = self.world.borrow_mut();
+        let objs = world.objects_mut();
+        if !objs.contains(item)
+            || objs.get(item).try_pos().is_none()
+            || objs.distance(picker, item).is_none_or(|d| d > 1)
+        {
             return;
         }
-        // TODO why different results?
-        // if ( user == g_obj_dude )
-        //   {
-        //     if ( used_type != OBJ_TYPE_SCENERY )
-        //       return -1;
-        //   }
-        //   else if ( used_type != OBJ_TYPE_SCENERY )
-        //   {
-        //     return 0;
-        //   }
+        debug!("{:?} picks up {:?}", picker, item);
+        objs.move_into_inventory(picker, item, 1);
+    }
 
-        let (used_kind, script) = {
+    /// Moves the critter at most `max_steps` hexes towards `to` spending the action points.
+    /// Returns `false` if the critter can't move.
+    fn combat_move(&mut self, obj: object::Handle, to: PathTo, max_steps: i32) -> bool {
+        let (point, steps) = {
             let world = self.world.borrow();
-            let usedo = world.objects().get(used);
-            let Some(ExactEntityKind::Scenery(used_kind)) = usedo.proto().map(|p| p.kind()) else { return };
-            (used_kind, usedo.script)
+            let objs = world.objects();
+            let Some(path) = objs.path(obj, to, true) else {
+                return false;
+            };
+            let steps = cmp::min(path.len(), cmp::max(max_steps, 0) as usize);
+            if steps == 0 {
+                return false;
+            }
+            let point = path[..steps].iter()
+                .fold(objs.get(obj).pos().point, |p, &dir| hex::go(p, dir, 1));
+            (point, steps)
         };
+        self.combat.spend_action_points(obj, steps as i32);
 
-        if used_kind == SceneryKind::Door {
-            self.use_door(user, used, ui);
+        let seq = Chain::new();
+        seq.control()
+            .cancellable(Move::new(obj, PathTo::Point {
+                point,
+                neighbor_if_blocked: false,
+            }, CritterAnim::Walk))
+            .finalizing(Stand::new(obj));
+        self.obj_sequencer.replace(obj, seq);
+        true
+    }
+
+    // combat_attack()
+    fn combat_attack(&mut self,
+        attacker: object::Handle,
+        target: object::Handle,
+        weapon: combat::Weapon,
+        location: HitLocation,
+    ) {
+        if !self.combat.spend_action_points(attacker, weapon.ap_cost) {
+            return;
+        }
+        self.combat.set_target(attacker, Some(target));
+        let scripted = self.combat.take_scripted_attack(attacker);
+        let location = match scripted {
+            Some(s) if s.called_shot != HitLocation::Uncalled => s.called_shot,
+            _ => location,
+        };
+
+        let seq = Chain::new();
+        {
+            let world = self.world.borrow();
+            let objs = world.objects();
+            let attack = combat::roll_attack(attacker, target, weapon, location, scripted.as_ref(),
+                self.combat.difficulty(), world.game_time.roll_checker(), &self.rpg, objs);
+            debug!("{:?} attacks {:?}: {:?}, damage {}", attacker, target, attack.result,
+                attack.damage);
+
+            if let Some(weapon) = attack.weapon.obj
+                && attack.weapon.rounds > 0
+            {
+                let mut weapono = objs.get_mut(weapon);
+                let ammo_count = weapono.ammo_count().unwrap_or(0);
+                weapono.set_ammo_count(ammo_count.saturating_sub(attack.weapon.rounds));
+            }
+
+            let target_point = objs.get(target).pos().point;
+            let fid = {
+                let mut attackero = objs.get_mut(attacker);
+                attackero.direction = hex::direction(attackero.pos().point, target_point);
+                attackero.fid.critter().map(|fid| FrameId::from(fid.with_anim(attack.weapon.anim())))
+            };
+            if let Some(weapon) = attack.weapon.obj {
+                let name = sfx::build_weapon_name(&world, WeaponSfx::Attack, weapon, true, None);
+                seq.control().cancellable(PushEvent::new(
+                    sequence::Event::PlaySfx { obj: attacker, name }));
+            }
+            if fid.is_some_and(|fid| self.frm_db.exists(fid)) {
+                seq.control().cancellable(FrameAnim::new(attacker,
+                    FrameAnimOptions { anim: Some(attack.weapon.anim()), ..Default::default() }));
+            }
+            seq.control().finalizing(PushEvent::new(sequence::Event::Attack { attack }));
+        }
+        seq.control().finalizing(Stand::new(attacker));
+        self.obj_sequencer.replace(attacker, seq);
+    }
+
+    fn apply_attack(&mut self, attack: combat::Attack, ui: &mut Ui) {
+        let (attacker, target) = (attack.attacker, attack.target);
+        if !attack.attacker_flags.is_empty() {
+            self.apply_critical_failure(&attack, ui);
+        }
+        let dude = {
+            let world = self.world.borrow();
+            let objs = world.objects();
+            if !objs.contains(target) || !objs.contains(attacker)
+                || objs.get(target).sub.as_critter().is_none_or(|c| c.is_dead())
+            {
+                return;
+            }
+            objs.dude()
+        };
+        if target != dude && self.combat.target(target).is_none() {
+            self.combat.set_target(target, Some(attacker));
+        }
+
+        if !attack.is_hit() {
+            self.play_critter_anim(target, CritterAnim::DodgeAnim, true);
+            return;
+        }
+
+        let (died, from_front) = {
+            let world = self.world.borrow();
+            let objs = world.objects();
+            let attacker_point = objs.get(attacker).pos().point;
+            let mut targeto = objs.get_mut(target);
+            let from_front = combat::is_facing(targeto.pos().point, targeto.direction,
+                attacker_point);
+            let died = combat::damage_critter(&mut targeto, attack.damage, attack.target_flags);
+            (died, from_front)
+        };
+        if let Some(weapon) = attack.weapon.obj {
+            let world = self.world.borrow();
+            let name = sfx::build_weapon_name(&world, WeaponSfx::Hit, weapon, true, Some(target));
+            self.sound.play_sfx(&name, sfx::relative_volume(&world, target));
+        }
+
+        self.execute_obj_proc(target, PredefinedProc::Damage, Some(attacker), 0, ui);
+
+        if died {
+            let anim = combat::death_anim(attack.weapon.damage_kind, attack.weapon.attack_kind,
+                attack.damage, attack.is_critical(), from_front);
+            self.critter_died(target, Some(attacker), Some(anim), ui);
+        } else if attack.target_flags.intersects(DamageFlag::KnockedDown | DamageFlag::KnockedOut) {
+            if attack.target_flags.contains(DamageFlag::KnockedOut) {
+                let world = self.world.borrow();
+                let objs = world.objects();
+                let endurance = self.rpg.stat(Stat::Endurance, &objs.get(target), objs);
+                let time = world.game_time.add_decis(cmp::max(10 * (35 - 3 * endurance), 0) as u32);
+                self.scripts.queue.push(time, Some(target), queue::Event::Knockout);
+            }
+            let anim = if from_front {
+                CritterAnim::FallBack
+            } else {
+                CritterAnim::FallFront
+            };
+            self.play_critter_anim(target, anim, false);
         } else {
+            let anim = if from_front {
+                CritterAnim::HitFromFront
+            } else {
+                CritterAnim::HitFromBack
+            };
+            self.play_critter_anim(target, anim, true);
+        }
+    }
+
+    /// Applies the critical failure effects of the `attack` to the attacker and its weapon.
+    fn apply_critical_failure(&mut self, attack: &combat::Attack, ui: &mut Ui) {
+        let attacker = attack.attacker;
+        let flags = attack.attacker_flags;
+        let died = {
             let world = &mut self.world.borrow_mut();
+            let objs = world.objects_mut();
+            if !objs.contains(attacker)
+                || objs.get(attacker).sub.as_critter().is_none_or(|c| c.is_dead())
+            {
+                return;
+            }
+            if let Some(weapon) = attack.weapon.obj
+                && objs.get(attacker).inventory.items.iter().any(|i| i.object == weapon)
+            {
+                if flags.contains(DamageFlag::LoseAmmo) {
+                    objs.get_mut(weapon).set_ammo_count(0);
+                }
+                if flags.intersects(DamageFlag::Drop | DamageFlag::Destroy) {
+                    let item = objs.take_from_inventory(attacker, weapon, 1).unwrap();
+                    if flags.contains(DamageFlag::Destroy) {
+                        objs.remove(item);
+                    } else {
+                        let pos = objs.get(attacker).pos();
+                        objs.set_pos(item, Some(pos));
+                    }
+                    let mut attackero = objs.get_mut(attacker);
+                    if let Some(fid) = attackero.fid.critter() {
+                        attackero.fid = fid.with_weapon(WeaponKind::Unarmed).into();
+                    }
+                }
+            }
+            combat::damage_critter(&mut objs.get_mut(attacker), attack.attacker_damage, flags)
+        };
 
-            let script_overrides = if let Some((sid, _)) = script {
-                self.scripts.execute_predefined_proc(sid, PredefinedProc::Use,
-                    &mut script::Context {
-                        world,
-                        obj_sequencer: &mut self.obj_sequencer,
-                        dialog: &mut self.dialog,
-                        ui,
-                        message_panel: self.message_panel,
-                        map_id: self.map_id.unwrap(),
-                        source_obj: Some(user),
-                        target_obj: Some(used),
-                        skill: None,
-                        rpg: &mut self.rpg,
-                    }).unwrap().assert_no_suspend().script_overrides
+        if attack.attacker_damage > 0 {
+            self.execute_obj_proc(attacker, PredefinedProc::Damage, None, 0, ui);
+        }
+
+        if died {
+            self.critter_died(attacker, None, Some(CritterAnim::FallBack), ui);
+        } else if flags.intersects(DamageFlag::KnockedDown | DamageFlag::KnockedOut) {
+            self.play_critter_anim(attacker, CritterAnim::FallBack, false);
+        }
+    }
+
+    // action_dmg()
+    fn damage_critter(&mut self,
+        target: object::Handle,
+        damage: i32,
+        damage_kind: DamageKind,
+        bypass_armor: bool,
+        animate: bool,
+        ui: &mut Ui,
+    ) {
+        let died = {
+            let world = self.world.borrow();
+            let objs = world.objects();
+            if !objs.contains(target) {
+                return;
+            }
+            let mut targeto = objs.get_mut(target);
+            if targeto.sub.as_critter().is_none_or(|c| c.is_dead()) {
+                return;
+            }
+            let damage = if bypass_armor {
+                damage
             } else {
-                false
+                let threshold = damage_kind.thresh_stat()
+                    .map(|s| self.rpg.stat(s, &targeto, objs))
+                    .unwrap_or(0);
+                let resist = self.rpg.stat(damage_kind.resist_stat(), &targeto, objs)
+                    .clamp(0, 100);
+                combat::damage_after_armor(damage, threshold, resist, 2, 1, 100)
             };
-            let script_overrides = if !script_overrides {
-                match used_kind {
-                    SceneryKind::Door => unreachable!(),
-                    // TODO
-                    | SceneryKind::Stairs
-                    | SceneryKind::Elevator
-                    | SceneryKind::LadderDown
-                    | SceneryKind::LadderUp
-                    => {
-                        warn!("{:?} use is not implemented", used_kind);
-                        false
+            combat::damage_critter(&mut targeto, damage, BitFlags::empty())
+        };
+
+        self.execute_obj_proc(target, PredefinedProc::Damage, None, 0, ui);
+
+        if died {
+            self.critter_died(target, None, animate.then_some(CritterAnim::FallBack), ui);
+        } else if animate {
+            self.play_critter_anim(target, CritterAnim::HitFromFront, true);
+        }
+    }
+
+    /// If `anim` is `None` the critter is put into the dead pose immediately. The dude gets the
+    /// experience for the kill if it's the `killer`. The game is over if it's the dude who died.
+    // critter_kill()
+    fn critter_died(&mut self,
+        obj: object::Handle,
+        killer: Option<object::Handle>,
+        anim: Option<CritterAnim>,
+        ui: &mut Ui,
+    ) {
+        debug!("{:?} died", obj);
+        self.combat.remove(obj);
+        self.obj_sequencer.cancel(obj);
+        if let Some(anim) = anim {
+            if !self.play_critter_anim(obj, anim, false) {
+                self.play_critter_anim(obj, CritterAnim::FallBack, false);
+            }
+            let world = self.world.borrow();
+            if let Some(name) = sfx::build_char_name(&world, obj, anim, CharSfx::Die) {
+                self.sound.play_sfx(&name, sfx::relative_volume(&world, obj));
+            }
+        } else {
+            combat::kill_critter(&mut self.world.borrow_mut(), obj, CritterAnim::FallBackSf);
+        }
+
+        let dude = self.world.borrow().objects().dude();
+        if obj == dude {
+            self.app_events.push(AppEvent::GameOver);
+            return;
+        }
+
+        let (experience, kill_kind, no_drop) = {
+            let world = self.world.borrow();
+            let obj = world.objects().get(obj);
+            let proto = obj.proto().unwrap();
+            let critter = proto.sub.as_critter().unwrap();
+            (critter.experience, critter.kill_kind, critter.flags.contains(CritterFlag::NoDrop))
+        };
+        if killer == Some(dude) {
+            self.rpg.inc_kill_count(kill_kind);
+            let world = self.world.borrow();
+            let objs = world.objects();
+            let levels = self.rpg.add_experience(experience, &mut objs.get_mut(dude), objs);
+            if levels > 0 {
+                let level = self.rpg.pc_stat(PCStat::Level);
+                for level in level + 1 - levels as i32..=level {
+                    self.party.level_up(level, &self.proto_db, &mut self.rpg, objs);
+                }
+            }
+        }
+
+        self.execute_obj_proc(obj, PredefinedProc::Destroy, None, 0, ui);
+
+        let world = &mut self.world.borrow_mut();
+        if !no_drop && world.objects().contains(obj) {
+            world.objects_mut().drop_inventory(obj);
+        }
+    }
+
+    /// Plays `anim` of the critter if it has such animation. If `stand` is `true` the critter
+    /// returns to the standing pose afterwards.
+    fn play_critter_anim(&mut self, obj: object::Handle, anim: CritterAnim, stand: bool) -> bool {
+        let fid = self.world.borrow().objects().get(obj).fid.critter()
+            .map(|fid| FrameId::from(fid.with_anim(anim)));
+        if !fid.is_some_and(|fid| self.frm_db.exists(fid)) {
+            return false;
+        }
+        let seq = Chain::new();
+        seq.control().cancellable(FrameAnim::new(obj,
+            FrameAnimOptions { anim: Some(anim), ..Default::default() }));
+        if stand {
+            seq.control().finalizing(Stand::new(obj));
+        }
+        self.obj_sequencer.replace(obj, seq);
+        true
+    }
+
+    fn is_dude_turn(&self) -> bool {
+        let dude = self.world.borrow().objects().dude();
+        self.combat.current() == Some(dude)
+            && self.combat.is_turn_started()
+            && !self.obj_sequencer.is_running(dude)
+    }
+
+    fn dude_combat_move(&mut self, point: crate::graphics::Point) {
+        if !self.is_dude_turn() {
+            return;
+        }
+        let dude = self.world.borrow().objects().dude();
+        let action_points = self.combat.action_points(dude);
+        self.combat_move(dude, PathTo::Point {
+            point,
+            neighbor_if_blocked: false,
+        }, action_points);
+    }
+
+    fn dude_combat_attack(&mut self, target: object::Handle) {
+        if !self.is_dude_turn() {
+            return;
+        }
+        let dude = self.world.borrow().objects().dude();
+        let (weapon, in_range) = {
+            let world = self.world.borrow();
+            let objs = world.objects();
+            let Some(weapon) = combat::Weapon::of(dude, &self.rpg, objs) else {
+                return;
+            };
+            let in_range = objs.distance(dude, target)
+                .is_some_and(|d| d as i32 <= weapon.range)
+                && !(weapon.is_ranged() && objs.is_shot_blocked(dude, target));
+            (weapon, in_range)
+        };
+        if in_range {
+            if self.combat.action_points(dude) >= weapon.ap_cost {
+                self.combat_attack(dude, target, weapon, HitLocation::Uncalled);
+            }
+        } else {
+            let steps = self.combat.action_points(dude);
+            self.combat_move(dude, PathTo::Object(target), steps);
+        }
+    }
+
+    fn handle_seq_events(&mut self, ctx: &mut state::Update) {
+        use sequence::Event::*;
+        let mut events = std::mem::take(&mut self.seq_events);
+        for event in events.drain(..) {
+            match event {
+                ObjectMoved { obj, new_pos, .. } => {
+                    self.play_footstep(obj);
+                    let world = self.world.borrow();
+                    if obj == world.objects().dude() {
+                        for &h in world.objects().at(new_pos) {
+                            let obj = world.objects().get(h);
+                            if let Some(map_exit) = obj.sub.as_map_exit() {
+                                debug!("dude on map exit object at {:?}: {:?}", new_pos, map_exit);
+                                ctx.out.push(AppEvent::MapExit {
+                                    map: map_exit.map,
+                                    pos: map_exit.pos,
+                                    direction: map_exit.direction,
+                                });
+                            }
+                        }
+                    }
+                }
+                Talk { talker, talked } => {
+                    self.talk(talker, talked, ctx.ui);
+                }
+                PlaySfx { obj, name } => {
+                    let volume = sfx::relative_volume(&self.world.borrow(), obj);
+                    self.sound.play_sfx(&name, volume);
+                }
+                SetDoorState { door, open } => {
+                    self.set_door_state(door, open);
+                }
+                Use { user, used } => {
+                    self.use_obj(user, used, ctx.ui);
+                }
+                UseSkill { skill, user, target } => {
+                    self.use_skill_on(skill, user, target, ctx.ui);
+                }
+                PickUp { picker, item } => {
+                    self.pick_up(picker, item);
+                }
+                Attack { attack } => {
+                    self.apply_attack(attack, ctx.ui);
+                }
+            }
+        }
+        self.seq_events = events;
+    }
+
+    fn play_footstep(&mut self, obj: object::Handle) {
+        let world = &self.world.borrow();
+        let Some(fid) = world.objects().get(obj).fid.critter() else { return };
+        if self.footsteps.get(&obj).is_some_and(|&v| self.sound.mixer().is_playing(v)) {
+            return;
+        }
+        if let Some(name) = sfx::build_char_name(world, obj, fid.anim(), CharSfx::Unused)
+            && let Some(voice) = self.sound.play_sfx(&name, sfx::relative_volume(world, obj))
+        {
+            self.footsteps.insert(obj, voice);
+        }
+    }
+
+    fn ambient_sfx_delay() -> Duration {
+        Duration::from_secs(random(15, 20) as u64)
+    }
+
+    fn update_ambient_sfx(&mut self) {
+        let now = self.time.time();
+        if now < self.next_ambient_sfx {
+            return;
+        }
+        self.next_ambient_sfx = now + Self::ambient_sfx_delay();
+
+        let Some(map_def) = self.map_id.and_then(|id| self.map_db.get(id)) else { return };
+        // The weights are percents.
+        let roll = random(1, 100) as u32;
+        let mut sum = 0;
+        for (name, weight) in &map_def.ambient_sfx {
+            sum += weight;
+            if roll <= sum {
+                self.sound.play_ambient(name);
+                break;
+            }
+        }
+    }
+
+    fn actions(&self, objh: object::Handle) -> Vec<(Action, UiCommandData)> {
+        let mut r = Vec::new();
+        let world = self.world.borrow();
+        let obj = world.objects().get(objh);
+        match obj.kind() {
+            EntityKind::Critter => {
+                if objh == world.objects().dude() {
+                    r.push(Action::Rotate);
+                } else {
+                    if world.objects().get(objh).can_talk_to() {
+                        if !self.combat.is_active() {
+                            r.push(Action::Talk);
+                        }
+                    } else if !obj.proto().unwrap()
+                        .sub.as_critter().unwrap()
+                        .flags.contains(CritterFlag::NoSteal)
+                    {
+                        r.push(Action::UseHand);
+                    }
+                    if world.objects().can_push(world.objects().dude(), objh,
+                        &self.scripts, self.combat.is_active())
+                    {
+                        r.push(Action::Push);
                     }
-                    SceneryKind::Misc => false,
                 }
+                r.extend_from_slice(&[Action::Look, Action::Inventory, Action::UseSkill])
+            }
+            EntityKind::Item => {
+                r.extend_from_slice(&[Action::UseHand, Action::Look]);
+                if world.objects().get(objh).item_kind() == Some(ItemKind::Container) {
+                    r.extend_from_slice(&[Action::UseSkill, Action::Inventory]);
+                }
+            }
+            EntityKind::Scenery => {
+                if world.objects().get(objh).can_use() {
+                    r.push(Action::UseHand)
+                }
+                r.extend_from_slice(&[Action::Look, Action::Inventory, Action::UseSkill])
+            }
+            EntityKind::Wall => {
+                r.push(Action::Look);
+                if world.objects().get(objh).can_use() {
+                    r.push(Action::UseHand)
+                }
+            }
+            _ => {}
+        }
+        if !r.is_empty() {
+            r.push(Action::Cancel)
+        }
+        r.iter()
+            .map(|&action| (action, UiCommandData::Action { action }))
+            .collect()
+    }
+
+    fn look_at_object(&mut self, looker: object::Handle, looked: object::Handle, ui: &mut Ui)
+        -> Option<BString>
+    {
+        let sid = {
+            let world = self.world.borrow();
+            let lookero = world.objects().get(looker);
+            let lookedo = world.objects().get(looked);
+            if lookero.sub.as_critter().map(|c| c.is_dead()).unwrap_or(true)
+                // TODO This is only useful for mapper?
+                || lookedo.kind() == EntityKind::SqrTile
+                || lookedo.proto_ref().is_none()
+            {
+                return None;
+            }
+            lookedo.script.map(|(v, _)| v)
+        };
+
+        if let Some(sid) = sid &&
+            let Some(r) = self.scripts.execute_predefined_proc(sid, PredefinedProc::LookAt,
+            &mut script::Context {
+                world: &mut self.world.borrow_mut(),
+                obj_sequencer: &mut self.obj_sequencer,
+                dialog: &mut self.dialog,
+                ui,
+                message_panel: self.message_panel,
+                map_id: self.map_id.unwrap(),
+                source_obj: Some(looker),
+                target_obj: Some(looked),
+                skill: None,
+                fixed_param: 0,
+                rpg: &mut self.rpg,
+                sound: &mut self.sound,
+                map_db: &mut self.map_db,
+                movies: &mut self.movies,
+                combat: &mut self.combat,
+                world_map: &mut self.world_map,
+                party: &mut self.party,
+            })
+       {
+            assert!(r.suspend.is_none(), "can't suspend");
+            if r.script_overrides {
+                return None;
+            }
+        }
+
+        let world = self.world.borrow();
+        let lookedo = world.objects().get(looked);
+        let msg_id = if lookedo.sub.as_critter().map(|c| c.is_dead()).unwrap_or(false) {
+            491 + random(0, 1)
+        } else {
+            490
+        };
+        if let Some(msg) = self.proto_db.messages().get(msg_id) &&
+            let Some(name) = world.object_name(looked)
+        {
+            Some(sprintf(&msg.text, &[&*name]))
+        } else {
+            None
+        }
+    }
+
+    fn dude_look_at_object(&mut self, obj: object::Handle, ui: &mut Ui) {
+        let dude_obj = self.world().borrow().objects().dude();
+        if let Some(msg) = self.look_at_object(dude_obj, obj, ui) {
+            self.push_message(&msg, ui);
+        }
+    }
+
+    // obj_examine_func()
+    fn examine_object(&mut self, examiner: object::Handle, examined: object::Handle, ui: &mut Ui)
+        -> Vec<BString>
+    {
+        let sid = {
+            let world = self.world.borrow();
+            let examinero = world.objects().get(examiner);
+            let examinedo = world.objects().get(examined);
+            if examinero.sub.as_critter().map(|c| c.is_dead()).unwrap_or(false)
+                // TODO This is only useful for mapper?
+                || examinedo.kind() == EntityKind::SqrTile
+            {
+                return Vec::new();
+            }
+            examinedo.script.map(|(v, _)| v)
+        };
+
+        let script_overrides = if let Some(sid) = sid &&
+            let Some(r) = self.scripts.execute_predefined_proc(sid, PredefinedProc::Description,
+            &mut script::Context {
+                world: &mut self.world.borrow_mut(),
+                obj_sequencer: &mut self.obj_sequencer,
+                dialog: &mut self.dialog,
+                ui,
+                message_panel: self.message_panel,
+                map_id: self.map_id.unwrap(),
+                source_obj: Some(examiner),
+                target_obj: Some(examined),
+                skill: None,
+                fixed_param: 0,
+                rpg: &mut self.rpg,
+                sound: &mut self.sound,
+                map_db: &mut self.map_db,
+                movies: &mut self.movies,
+                combat: &mut self.combat,
+                world_map: &mut self.world_map,
+                party: &mut self.party,
+            })
+        {
+            assert!(r.suspend.is_none(), "can't suspend");
+            r.script_overrides
+        } else {
+            false
+        };
+
+        let mut r = Vec::new();
+
+        if !script_overrides {
+            let world = self.world.borrow();
+            let examinedo = world.objects().get(examined);
+            if !examinedo.sub.as_critter().map(|c| c.is_dead()).unwrap_or(false) {
+                let descr = examinedo.proto()
+                    .and_then(|p| {
+                        p.description()
+                            .filter(|s| {
+                                // Compare to "<None>".
+                                s != &self.proto_db.messages().get(10).unwrap().text
+                            })
+                            .map(|s| s.to_owned())
+                    })
+                    .unwrap_or_else(|| self.proto_db.messages().get(493).unwrap().text.clone());
+                r.push(descr);
+            }
+        }
+
+        // TODO critter state/hp, weapon/ammo description, car info etc
+
+        r
+    }
+
+    fn dude_examine_object(&mut self, obj: object::Handle, ui: &mut Ui) {
+        let dude_obj = self.world().borrow().objects().dude();
+        for msg in self.examine_object(dude_obj, obj, ui) {
+            self.push_message(&msg, ui);
+        }
+    }
+
+    fn push_message(&self, msg: &bstr, ui: &mut Ui) {
+        let mut mp = ui.widget_mut::<MessagePanel>(self.message_panel);
+        let mut m = BString::new();
+        m.push(BULLET);
+        m.push_str(msg);
+        mp.push_message(m);
+    }
+
+    // action_talk_to()
+    fn action_talk(&mut self, talker: object::Handle, talked: object::Handle, ui: &mut Ui) {
+        // TODO handle combat state
+
+        {
+            let world = self.world.borrow();
+            let objs = world.objects();
+
+            if objs.distance(talker, talked).unwrap() >= 9 || // TODO this value is different (12) in can_talk2()
+                objs.is_shot_blocked(talker, talked)
+            {
+                let chain = Chain::new();
+                chain.control()
+                    .cancellable(Move::new(talker, PathTo::Object(talked), CritterAnim::Running))
+                    .finalizing(Stand::new(talker))
+                    .finalizing(PushEvent::new(sequence::Event::Talk { talker, talked }));
+                self.obj_sequencer.replace(talker, chain);
+                return;
+            }
+        }
+
+        self.talk(talker, talked, ui);
+    }
+
+    // talk_to(), gdialogEnter()
+    fn talk(&mut self, talker: object::Handle, talked: object::Handle, ui: &mut Ui) {
+        if self.world.borrow().objects().can_talk_now(talker, talked) {
+            let world = &mut *self.world.borrow_mut();
+            self.obj_sequencer.clear();
+            self.obj_sequencer.sync(&mut sequence::Sync {
+                world,
+                ui,
+            });
+            let script = world.objects().get(talked).script;
+            if let Some((sid, _)) = script {
+                match self.scripts.execute_predefined_proc(sid, PredefinedProc::Talk,
+                    &mut script::Context {
+                        world,
+                        obj_sequencer: &mut self.obj_sequencer,
+                        dialog: &mut self.dialog,
+                        ui,
+                        message_panel: self.message_panel,
+                        map_id: self.map_id.unwrap(),
+                        source_obj: Some(talker),
+                        target_obj: Some(talked),
+                        skill: None,
+                        fixed_param: 0,
+                        rpg: &mut self.rpg,
+                        sound: &mut self.sound,
+                        map_db: &mut self.map_db,
+                        movies: &mut self.movies,
+                        combat: &mut self.combat,
+                        world_map: &mut self.world_map,
+                        party: &mut self.party,
+                    }).and_then(|r| r.suspend)
+                    {
+                        None | Some(Suspend::GsayEnd) => {}
+                    }
+            }
+        } else {
+            assert_eq!(talker, self.world.borrow().objects().dude());
+            let msg = &self.misc_msgs.get(2000).unwrap().text;
+            self.push_message(msg, ui);
+        }
+    }
+
+    //  action_use_an_item_on_object_
+    fn action_use_obj(&mut self, user: object::Handle, used: object::Handle) {
+        let world = self.world.borrow();
+        let objs = world.objects();
+        let usero = objs.get(user);
+        let usedo = objs.get(used);
+
+        let used_kind = usedo.proto().map(|p| p.kind()).unwrap();
+        if used_kind == ExactEntityKind::Scenery(SceneryKind::LadderDown) {
+            // TODO action_climb_ladder
+            return;
+        }
+
+        let seq = Chain::new();
+
+        let move_anim = if usero.distance(&usedo).unwrap() < 5 {
+            CritterAnim::Walk
+        } else {
+            CritterAnim::Running
+        };
+        seq.control().cancellable(Move::new(user, PathTo::Object(used), move_anim));
+
+        let weapon = usero.fid.critter().unwrap().weapon();
+        if weapon != WeaponKind::Unarmed {
+            seq.control().cancellable(FrameAnim::new(user,
+                FrameAnimOptions { anim: Some(CritterAnim::PutAway), ..Default::default() }));
+        }
+
+        if used_kind != ExactEntityKind::Scenery(SceneryKind::Stairs) {
+            // FIXME must call check_next_to() before running this animation
+            let use_anim = if usedo.is_critter_prone() ||
+                usedo.kind() == EntityKind::Scenery &&
+                    usedo.proto().unwrap().flags_ext.contains(FlagExt::Prone)
+            {
+                CritterAnim::MagicHandsGround
+            } else {
+                CritterAnim::MagicHandsMiddle
+            };
+            seq.control().cancellable(FrameAnim::new(user,
+                FrameAnimOptions { anim: Some(use_anim), ..Default::default() }));
+        }
+
+        seq.control().cancellable(PushEvent::new(sequence::Event::Use { user, used }));
+        if weapon != WeaponKind::Unarmed {
+            seq.control().cancellable(FrameAnim::new(user,
+                FrameAnimOptions { anim: Some(CritterAnim::TakeOut), ..Default::default() }));
+        }
+        seq.control().finalizing(Stand::new(user));
+
+        self.obj_sequencer.replace(user, seq);
+    }
+
+    // obj_use
+    fn use_obj(&mut self, user: object::Handle, used: object::Handle, ui: &mut Ui) {
+        if !self.check_next_to(user, used, ui) {
+            return;
+        }
+        let (is_critter, is_container) = {
+            let world = self.world.borrow();
+            let usedo = world.objects().get(used);
+            (usedo.kind() == EntityKind::Critter, usedo.item_kind() == Some(ItemKind::Container))
+        };
+        if is_container {
+            self.use_container(user, used, ui);
+            return;
+        }
+        if is_critter {
+            if user == self.world.borrow().objects().dude() {
+                self.loot.show(used, ui);
+            }
+            return;
+        }
+        // TODO why different results?
+        // if ( user == g_obj_dude )
+        //   {
+        //     if ( used_type != OBJ_TYPE_SCENERY )
+        //       return -1;
+        //   }
+        //   else if ( used_type != OBJ_TYPE_SCENERY )
+        //   {
+        //     return 0;
+        //   }
+
+        let (used_kind, script) = {
+            let world = self.world.borrow();
+            let usedo = world.objects().get(used);
+            let Some(ExactEntityKind::Scenery(used_kind)) = usedo.proto().map(|p| p.kind()) else { return };
+            (used_kind, usedo.script)
+        };
+
+        if used_kind == SceneryKind::Door {
+            self.use_door(user, used, ui);
+        } else {
+            let world = &mut self.world.borrow_mut();
+
+            let script_overrides = if let Some((sid, _)) = script {
+                self.scripts.execute_predefined_proc(sid, PredefinedProc::Use,
+                    &mut script::Context {
+                        world,
+                        obj_sequencer: &mut self.obj_sequencer,
+                        dialog: &mut self.dialog,
+                        ui,
+                        message_panel: self.message_panel,
+                        map_id: self.map_id.unwrap(),
+                        source_obj: Some(user),
+                        target_obj: Some(used),
+                        skill: None,
+                        fixed_param: 0,
+                        rpg: &mut self.rpg,
+                        sound: &mut self.sound,
+                        map_db: &mut self.map_db,
+                        movies: &mut self.movies,
+                        combat: &mut self.combat,
+                        world_map: &mut self.world_map,
+                        party: &mut self.party,
+                    }).unwrap().assert_no_suspend().script_overrides
+            } else {
+                false
+            };
+            let script_overrides = if !script_overrides {
+                match used_kind {
+                    SceneryKind::Door => unreachable!(),
+                    // TODO
+                    | SceneryKind::Stairs
+                    | SceneryKind::Elevator
+                    | SceneryKind::LadderDown
+                    | SceneryKind::LadderUp
+                    => {
+                        warn!("{:?} use is not implemented", used_kind);
+                        false
+                    }
+                    SceneryKind::Misc => false,
+                }
+            } else {
+                false
+            };
+            if !script_overrides && user == world.objects().dude()
+                && let Some(obj_name) = world.object_name(used)
+            {
+                let msg = &self.proto_db.messages().get(MSG_YOU_SEE_X).unwrap().text;
+                let msg = sprintf(msg, &[&obj_name]);
+                self.push_message(&msg, ui);
+            }
+        }
+    }
+
+    // obj_use_container()
+    fn use_container(&mut self, user: object::Handle, container: object::Handle, ui: &mut Ui) {
+        if self.execute_obj_proc(container, PredefinedProc::Use, Some(user), 0, ui)
+            .is_some_and(|r| r.script_overrides)
+        {
+            return;
+        }
+        if user != self.world.borrow().objects().dude() {
+            return;
+        }
+        if self.world.borrow().objects().get(container).is_locked() == Some(true) {
+            let msg = &self.proto_db.messages().get(MSG_IT_IS_LOCKED).unwrap().text;
+            self.push_message(msg, ui);
+            return;
+        }
+        self.loot.show(container, ui);
+    }
+
+    fn use_door(&mut self, user: object::Handle, door: object::Handle, ui: &mut Ui) {
+        let world = &mut self.world.borrow_mut();
+
+        let script = {
+            let dooro = world.objects().get(door);
+            if dooro.is_locked().unwrap() {
+                // TODO sfx
+            }
+            dooro.script
+        };
+
+        if let Some((sid, _)) = script {
+            let script_overrides = self.scripts.execute_predefined_proc(sid, PredefinedProc::Use,
+                &mut script::Context {
+                    world,
+                    obj_sequencer: &mut self.obj_sequencer,
+                    dialog: &mut self.dialog,
+                    ui,
+                    message_panel: self.message_panel,
+                    map_id: self.map_id.unwrap(),
+                    source_obj: Some(user),
+                    target_obj: Some(door),
+                    skill: None,
+                    fixed_param: 0,
+                    rpg: &mut self.rpg,
+                    sound: &mut self.sound,
+                    map_db: &mut self.map_db,
+                    movies: &mut self.movies,
+                    combat: &mut self.combat,
+                    world_map: &mut self.world_map,
+                    party: &mut self.party,
+                }).unwrap().assert_no_suspend().script_overrides;
+            if script_overrides {
+                return;
+            }
+        }
+
+        let dooro = world.objects().get(door);
+        let need_open = if dooro.frame_idx > 0 { // Indicates the door is open
+            if world.objects().has_blocker_at(dooro.pos(), None) {
+                let msg = &self.proto_db.messages().get(MSG_DOORWAY_SEEMS_TO_BE_BLOCKED).unwrap().text;
+                self.push_message(msg, ui);
+                return
+            }
+            false
+        } else {
+            if dooro.sub.as_scenery().unwrap().as_door().unwrap().flags.contains(DoorFlag::Open) {
+                return;
+            }
+            true
+        };
+
+        let sfx = sfx::build_open_name(world, door,
+            if need_open { SceneryAction::Open } else { SceneryAction::Close });
+
+        let seq = Chain::new();
+        seq.control()
+            .cancellable(PushEvent::new(sequence::Event::PlaySfx { obj: door, name: sfx }))
+            .cancellable(FrameAnim::new(door, FrameAnimOptions {
+                direction: if need_open { AnimDirection::Forward } else { AnimDirection::Backward },
+                skip: 1,
+                ..Default::default()
+            }))
+            .finalizing(PushEvent::new(sequence::Event::SetDoorState { door, open: need_open }));
+
+        self.obj_sequencer.replace(door, seq);
+    }
+
+    // set_door_open, set_door_closed, check_door_state
+    fn set_door_state(&mut self, door: object::Handle, open: bool) {
+        let mut world = self.world.borrow_mut();
+        {
+            {
+                let mut dooro = world.objects_mut().get_mut(door);
+                {
+                    let door = dooro.sub.as_scenery_mut().unwrap().as_door_mut().unwrap();
+                    if open {
+                        door.flags.insert(DoorFlag::Open);
+                    } else {
+                        door.flags.remove(DoorFlag::Open);
+                    }
+                }
+                if open {
+                    dooro.flags.insert(Flag::ShootThru | Flag::LightThru | Flag::NoBlock);
+                } else {
+                    dooro.flags.remove(Flag::ShootThru | Flag::LightThru | Flag::NoBlock);
+                }
+            }
+
+            world.objects_mut().set_frame(door, if open {
+                SetFrame::Last
+            } else {
+                SetFrame::Index(0)
+            });
+        }
+        world.objects_mut().rebuild_light_grid();
+    }
+
+    // is_next_to
+    fn check_next_to(&mut self, obj1: object::Handle, obj2: object::Handle, ui: &mut Ui) -> bool {
+        if self.world.borrow().objects().distance(obj1, obj2).unwrap() > 1 {
+            let msg = &self.misc_msgs.get(2000).unwrap().text;
+            self.push_message(msg, ui);
+            false
+        } else {
+            true
+        }
+    }
+
+    fn create_scroll_areas(rect: Rect, ui: &mut Ui) -> StaticMap<ScrollDirection, ui::Handle> {
+        let mut new = |rect, cur, curx| {
+            let win = ui.new_window(rect, None);
+            ui.new_widget(win, Rect::with_size(0, 0, rect.width(), rect.height()), None, None,
+                ScrollArea::new(cur, curx, Duration::from_millis(0), Duration::from_millis(30)))
+        };
+        use ui::Cursor::*;
+        use ScrollDirection::*;
+        static_map! {
+            N => new(
+                Rect::new(rect.left + 1, rect.top, rect.right - 1, rect.top + 1),
+                ScrollNorth, ScrollNorthX),
+            NE => new(
+                Rect::new(rect.right - 1, rect.top, rect.right, rect.top + 1),
+                ScrollNorthEast, ScrollNorthEastX),
+            E => new(
+                Rect::new(rect.right - 1, rect.top + 1, rect.right, rect.bottom - 1),
+                ScrollEast, ScrollEastX),
+            SE => new(
+                Rect::new(rect.right - 1, rect.bottom - 1, rect.right, rect.bottom),
+                ScrollSouthEast, ScrollSouthEastX),
+            S => new(
+                Rect::new(rect.left + 1, rect.bottom - 1, rect.right - 1, rect.bottom),
+                ScrollSouth, ScrollSouthX),
+            SW => new(
+                Rect::new(rect.left, rect.bottom - 1, rect.left + 1, rect.bottom),
+                ScrollSouthWest, ScrollSouthWestX),
+            W => new(
+                Rect::new(rect.left, rect.top + 1, rect.left + 1, rect.bottom - 1),
+                ScrollWest, ScrollWestX),
+            NW => new(
+                Rect::new(rect.left, rect.top, rect.left + 1, rect.top + 1),
+                ScrollNorthWest, ScrollNorthWestX),
+        }
+    }
+
+    fn set_dude_pos(&mut self, pos: EPoint, direction: Direction, ui: &mut Ui) {
+        self.update_automap();
+        self.set_dude_pos0(pos, direction, ui);
+        self.update_automap();
+    }
+
+    fn set_dude_pos0(&mut self, pos: EPoint, direction: Direction, ui: &mut Ui) {
+        let world = &mut self.world.borrow_mut();
+        let dude_objh = world.objects().dude();
+        let elevation_change = {
+            let mut dude_obj = world.objects_mut().get_mut(dude_objh);
+            dude_obj.direction = direction;
+            dude_obj.pos().elevation != pos.elevation
+        };
+        world.objects_mut().set_pos(dude_objh, Some(pos));
+        if elevation_change {
+            let ctx = &mut script::Context {
+                ui,
+                world,
+                obj_sequencer: &mut self.obj_sequencer,
+                dialog: &mut self.dialog,
+                message_panel: self.message_panel,
+                map_id: self.map_id.unwrap(),
+                source_obj: None,
+                target_obj: None,
+                skill: None,
+                fixed_param: 0,
+                rpg: &mut self.rpg,
+                sound: &mut self.sound,
+                map_db: &mut self.map_db,
+                movies: &mut self.movies,
+                combat: &mut self.combat,
+                world_map: &mut self.world_map,
+                party: &mut self.party,
+            };
+            self.scripts.execute_map_procs(PredefinedProc::MapUpdate, ctx);
+        }
+        world.camera_look_at_dude();
+    }
+
+    /// Records automap of the current elevation.
+    // automap_pip_save()
+    fn update_automap(&mut self) {
+        let Some(map_id) = self.map_id else {
+            return;
+        };
+        let world = self.world.borrow();
+        let Some(pos) = world.objects().dude_ref().try_pos() else {
+            return;
+        };
+        self.automaps.update(map_id, pos.elevation, world.objects(), world.hex_grid());
+    }
+
+    /// Advances game time with the real time. The time stops in combat.
+    fn update_game_time(&mut self) {
+        let now = self.time.time();
+        if self.combat.is_active() {
+            self.next_game_tick = now + GAME_TICK_INTERVAL;
+            return;
+        }
+        let mut ticks = 0;
+        while now >= self.next_game_tick {
+            self.next_game_tick += GAME_TICK_INTERVAL;
+            ticks += 1;
+        }
+        let world = &mut self.world.borrow_mut();
+        world.game_time = world.game_time.add_decis(ticks);
+    }
+
+    /// Fires the queue events that are due.
+    // queue_process()
+    fn process_queue(&mut self, ui: &mut Ui) {
+        if self.map_id.is_none() {
+            return;
+        }
+        let now = self.world.borrow().game_time;
+        while let Some(entry) = self.scripts.queue.pop_due(now) {
+            let Some(obj) = entry.obj else {
+                continue;
+            };
+            if !self.world.borrow().objects().contains(obj) {
+                continue;
+            }
+            match entry.event {
+                queue::Event::Script { fixed_param } => {
+                    self.execute_obj_proc(obj, PredefinedProc::TimedEvent, None, fixed_param, ui);
+                }
+                queue::Event::Knockout => self.wake_up(obj),
+                queue::Event::Poison => self.process_poison(obj, ui),
+                queue::Event::Radiation { level, healing } =>
+                    self.process_radiation(obj, level, healing, ui),
+                queue::Event::Flare => self.destroy_item(obj),
+                queue::Event::Explosion => self.explode(obj, ui),
+                queue::Event::Drug { modifiers, .. } => {
+                    let world = self.world.borrow();
+                    drug::process_drug(obj, &modifiers, &self.rpg, world.objects(),
+                        &mut self.scripts.queue, now);
+                }
+                queue::Event::Withdrawal { pid, perk, onset } => {
+                    let started = {
+                        let world = self.world.borrow();
+                        drug::process_withdrawal(obj, pid, perk, onset, &self.rpg,
+                            world.objects(), &mut self.scripts.queue, now)
+                    };
+                    if started
+                        && let Some(perk) = perk
+                        && self.world.borrow().objects().get(obj).is_dude()
+                    {
+                        let msg = self.rpg.perk_description(perk).to_owned();
+                        self.push_message(&msg, ui);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Runs the script scheduler: resumes waiting scripts, calls timed and conditional procedures,
+    /// handles named events and script processes.
+    // updatePrograms()
+    fn update_scripts(&mut self, ui: &mut Ui) {
+        let Some(map_id) = self.map_id else {
+            return;
+        };
+        self.scripts.update(self.time.time(), &mut script::Context {
+            world: &mut self.world.borrow_mut(),
+            obj_sequencer: &mut self.obj_sequencer,
+            dialog: &mut self.dialog,
+            ui,
+            message_panel: self.message_panel,
+            map_id,
+            source_obj: None,
+            target_obj: None,
+            skill: None,
+            fixed_param: 0,
+            rpg: &mut self.rpg,
+            sound: &mut self.sound,
+            map_db: &mut self.map_db,
+            movies: &mut self.movies,
+            combat: &mut self.combat,
+            world_map: &mut self.world_map,
+            party: &mut self.party,
+        });
+    }
+
+    // critter_wake_up()
+    fn wake_up(&mut self, obj: object::Handle) {
+        let anim = {
+            let world = self.world.borrow();
+            let mut o = world.objects().get_mut(obj);
+            let Some(critter) = o.sub.as_critter_mut() else {
+                return;
+            };
+            let flags = &mut critter.combat.damage_flags;
+            if flags.contains(DamageFlag::Dead) || !flags.contains(DamageFlag::KnockedOut) {
+                return;
+            }
+            flags.remove(DamageFlag::KnockedOut);
+            if self.combat.is_active() {
+                // Stands up on its turn.
+                flags.insert(DamageFlag::KnockedDown);
+                return;
+            }
+            flags.remove(DamageFlag::KnockedDown);
+            combat::stand_up_anim(&o)
+        };
+        debug!("{:?} wakes up", obj);
+        self.play_critter_anim(obj, anim, true);
+    }
+
+    // critter_check_poison()
+    fn process_poison(&mut self, obj: object::Handle, ui: &mut Ui) {
+        let poison = {
+            let world = self.world.borrow();
+            let objs = world.objects();
+            self.rpg.adjust_poison(&mut objs.get_mut(obj), -2, objs)
+        };
+        let Some(poison) = poison else {
+            return;
+        };
+        let now = self.world.borrow().game_time;
+        self.scripts.queue.schedule_poison(now, obj, poison);
+
+        self.show_misc_msg(MSG_POISON_DAMAGE, ui);
+        self.damage_critter(obj, 1, DamageKind::Poison, true, false, ui);
+        let dead = self.world.borrow().objects().get(obj).sub.as_critter().unwrap().is_dead();
+        if dead {
+            self.show_misc_msg(MSG_POISON_DEATH, ui);
+        } else if poison == 0 {
+            self.show_misc_msg(MSG_POISON_CURED, ui);
+        }
+    }
+
+    // process_rads()
+    fn process_radiation(&mut self, obj: object::Handle, level: u32, healing: bool, ui: &mut Ui) {
+        let deadly = {
+            let world = self.world.borrow();
+            let objs = world.objects();
+            let mut o = objs.get_mut(obj);
+            if o.sub.as_critter().is_none_or(|c| c.is_dead()) {
+                return;
+            }
+            if !healing {
+                // The new sickness replaces the one in effect.
+                let cured = self.scripts.queue.remove(|e| e.obj == Some(obj)
+                    && matches!(e.event, queue::Event::Radiation { healing: true, .. }));
+                for e in cured {
+                    if let queue::Event::Radiation { level, .. } = e.event {
+                        self.rpg.apply_radiation_effects(&mut o, level, true, objs);
+                    }
+                }
+                self.scripts.queue.push(world.game_time.add_decis(RADIATION_SICKNESS_TIME.as_decis()),
+                    Some(obj), queue::Event::Radiation { level, healing: true });
+            }
+            self.rpg.apply_radiation_effects(&mut o, level, healing, objs)
+        };
+        if !healing && level > 0 {
+            self.show_misc_msg(MSG_RADIATION_LEVEL_BASE + level as MessageId - 1, ui);
+        }
+        if deadly {
+            self.show_misc_msg(MSG_RADIATION_DEATH, ui);
+            self.critter_died(obj, None, Some(CritterAnim::FallBack), ui);
+        }
+    }
+
+    // queue_explode()
+    fn explode(&mut self, obj: object::Handle, ui: &mut Ui) {
+        let (pos, damage) = {
+            let world = self.world.borrow();
+            let objs = world.objects();
+            let pos = objs.get(obj).try_pos()
+                .or_else(|| objs.inventory_owner(obj).and_then(|h| objs.get(h).try_pos()));
+            let damage = if objs.get(obj).proto_id() == Some(ProtoId::ACTIVE_PLASTIC_EXPLOSIVE) {
+                PLASTIC_EXPLOSIVE_DAMAGE
+            } else {
+                DYNAMITE_DAMAGE
+            };
+            (pos, damage)
+        };
+        self.destroy_item(obj);
+        let Some(pos) = pos else {
+            return;
+        };
+        debug!("{:?} explodes at {:?}", obj, pos);
+        let targets: Vec<_> = {
+            let world = self.world.borrow();
+            let objs = world.objects();
+            objs.iter()
+                .filter(|&h| {
+                    let o = objs.get(h);
+                    o.sub.as_critter().is_some_and(|c| !c.is_dead())
+                        && o.try_pos().is_some_and(|p| p.elevation == pos.elevation
+                            && hex::distance(p.point, pos.point) <= EXPLOSION_RADIUS)
+                })
+                .collect()
+        };
+        for target in targets {
+            let damage = random(*damage.start(), *damage.end());
+            self.damage_critter(target, damage, DamageKind::Explosion, false, true, ui);
+        }
+    }
+
+    /// Removes the item from the map or from the inventory it's in.
+    // obj_destroy()
+    fn destroy_item(&mut self, obj: object::Handle) {
+        let world = &mut self.world.borrow_mut();
+        let objs = world.objects_mut();
+        if let Some(owner) = objs.inventory_owner(obj) {
+            let count = objs.get(owner).inventory.items.iter()
+                .find(|i| i.object == obj)
+                .unwrap()
+                .count;
+            objs.remove_from_inventory(owner, obj, count);
+        }
+        objs.remove_deep(obj);
+    }
+
+    fn show_misc_msg(&mut self, id: MessageId, ui: &mut Ui) {
+        if let Some(msg) = self.misc_msgs.get(id) {
+            let msg = msg.text.clone();
+            self.push_message(&msg, ui);
+        }
+    }
+
+    fn handle_pipboy_command(&mut self, command: UiCommand, ui: &mut Ui) {
+        if command.data == UiCommandData::Pipboy(pipboy::Command::Show) {
+            if self.pipboy.is_visible() {
+                return;
+            }
+            if self.map_id.and_then(|id| self.map_db.get(id)).is_some_and(|m| !m.pipboy_active) {
+                if let Some(msg) = self.misc_msgs.get(MSG_PIPBOY_NOT_ACTIVE) {
+                    self.push_message(&msg.text, ui);
+                }
+                return;
+            }
+            self.obj_sequencer.cancel(self.world.borrow().objects().dude());
+            self.update_automap();
+        }
+
+        let action = {
+            let map_def = self.map_id.and_then(|id| self.map_db.get(id));
+            let world = self.world.borrow();
+            let dude_pos = world.objects().dude_ref().try_pos();
+            let can_rest = !self.combat.is_active() && map_def
+                .zip(dude_pos)
+                .and_then(|(m, pos)| m.can_rest_here.get(pos.elevation as usize).copied())
+                .unwrap_or(false);
+            let info = &PipboyInfo {
+                global_vars: &self.scripts.vars.global_vars,
+                automaps: &self.automaps,
+                map_db: &self.map_db,
+                movies: &self.movies,
+                dude_pos: self.map_id.zip(dude_pos),
+                game_time: world.game_time,
+                can_rest,
+            };
+            match command.data {
+                UiCommandData::Pipboy(pipboy::Command::Show) => {
+                    self.pipboy.show(info, ui);
+                    None
+                }
+                UiCommandData::Pipboy(pipboy::Command::Hide) => {
+                    self.pipboy.hide(ui);
+                    None
+                }
+                UiCommandData::Pipboy(pipboy::Command::Page(page)) => {
+                    self.pipboy.stop_rest();
+                    self.pipboy.show_page(page, info, ui);
+                    None
+                }
+                UiCommandData::Pick { id } => self.pipboy.pick(id, info, ui),
+                _ => None,
+            }
+        };
+        match action {
+            Some(PipboyAction::PlayMovie(id)) => {
+                self.movies.play_game_movie(id);
+            }
+            Some(PipboyAction::Rest(until)) => {
+                let now = self.world.borrow().game_time;
+                self.pipboy.start_rest(until, now);
+            }
+            None => {}
+        }
+    }
+
+    fn handle_main_menu_command(&mut self, cmd: main_menu::Command, ui: &mut Ui) {
+        match cmd {
+            main_menu::Command::Intro => {
+                self.movies.play_game_movie(movie::INTRO_MOVIE);
+            }
+            main_menu::Command::NewGame => {
+                self.new_game();
+                self.char_select.show(&mut self.rpg, ui);
+            }
+            main_menu::Command::LoadGame => self.save_load.show(SaveLoadMode::Load, ui),
+            main_menu::Command::Options => self.options.show_prefs(ui),
+            main_menu::Command::Credits => {
+                // TODO credits_show()
+                debug!("credits are not implemented");
+            }
+            main_menu::Command::Exit => self.app_events.push(AppEvent::Quit),
+        }
+    }
+
+    fn handle_char_select_command(&mut self, cmd: char_select::Command, ui: &mut Ui) {
+        match self.char_select.handle(cmd, &mut self.rpg, ui) {
+            Some(CharSelectAction::Take) => self.start_new_game(ui),
+            Some(CharSelectAction::Edit) => {
+                self.new_character = true;
+                self.character_editor.show(CharacterEditorMode::Create, &self.rpg,
+                    &self.scripts.vars.global_vars, ui);
+            }
+            Some(CharSelectAction::Back) => self.char_select.hide(ui),
+            None => {}
+        }
+    }
+
+    fn handle_save_load_command(&mut self, cmd: save_load::Command, ui: &mut Ui) {
+        if cmd == save_load::Command::Cancel {
+            self.save_load.hide(ui);
+            return;
+        }
+        let Some((dir, header)) = self.save_load.selected() else {
+            return;
+        };
+        match self.save_load.mode().unwrap() {
+            SaveLoadMode::Save => {
+                let description = header.map(|h| h.description.clone())
+                    .or_else(|| self.map_id
+                        .and_then(|id| self.map_db.get(id))
+                        .map(|m| m.lookup_name.as_str().into()))
+                    .unwrap_or_default();
+                self.save_load.hide(ui);
+                self.options.hide_menu(ui);
+                if let Err(e) = self.save_game(&dir, &description) {
+                    error!("couldn't save game to {}: {}", dir.display(), e);
+                }
+            }
+            SaveLoadMode::Load => {
+                if header.is_none() {
+                    return;
+                }
+                self.save_load.hide(ui);
+                self.options.hide_menu(ui);
+                self.main_menu.hide(ui);
+                if let Err(e) = self.load_game(&dir, ui) {
+                    error!("couldn't load game from {}: {}", dir.display(), e);
+                    self.end_game(ui);
+                    self.show_main_menu(false, ui);
+                }
+            }
+        }
+    }
+
+    fn handle_options_command(&mut self, cmd: options::Command, ui: &mut Ui) {
+        match cmd {
+            options::Command::Show => self.app_events.push(AppEvent::ShowOptions),
+            options::Command::SaveGame => self.save_load.show(SaveLoadMode::Save, ui),
+            options::Command::LoadGame => self.save_load.show(SaveLoadMode::Load, ui),
+            options::Command::Preferences => self.options.show_prefs(ui),
+            options::Command::ExitGame => {
+                self.options.hide_menu(ui);
+                self.end_game(ui);
+                self.show_main_menu(false, ui);
+            }
+            options::Command::Done => self.options.hide_menu(ui),
+            _ => {
+                if self.options.handle_prefs(cmd, ui) {
+                    self.apply_preferences();
+                }
+            }
+        }
+    }
+
+    /// Closes the topmost front end screen or shows the options menu in game. Returns `false` if
+    /// the key wasn't handled.
+    fn handle_escape(&mut self, ui: &mut Ui) -> bool {
+        if self.save_load.is_visible() {
+            self.save_load.hide(ui);
+        } else if self.options.is_prefs_visible() {
+            self.options.hide_prefs(false, ui);
+            self.apply_preferences();
+        } else if self.options.is_menu_visible() {
+            self.options.hide_menu(ui);
+        } else if self.char_select.is_visible() && !self.character_editor.is_visible() {
+            self.char_select.hide(ui);
+        } else if self.main_menu.is_visible() && !self.char_select.is_visible() {
+            self.app_events.push(AppEvent::Quit);
+        } else if self.in_game()
+            && self.dialog.is_none()
+            && !self.movies.is_playing()
+            && !self.skilldex.is_visible()
+            && !self.inventory.is_visible()
+            && !self.character_editor.is_visible()
+            && !self.pipboy.is_visible()
+            && !self.world_map.is_visible()
+        {
+            self.app_events.push(AppEvent::ShowOptions);
+        } else {
+            return false;
+        }
+        true
+    }
+
+    /// Shows the barter screen when requested by the dialog and hides it once the dialog ends.
+    // gdialog_barter_pressed()
+    fn update_barter(&mut self, ui: &mut Ui) {
+        let Some(dialog) = self.dialog.as_mut() else {
+            if self.barter.is_visible() {
+                self.barter.hide(ui);
+            }
+            return;
+        };
+        if dialog.take_barter_request() && !self.barter.is_visible() {
+            let world = self.world.borrow();
+            if barter::can_barter(&world.objects().get(dialog.obj), &self.rpg) {
+                self.barter.show(dialog.obj, dialog.barter_mod, &self.rpg, &self.party, ui);
             } else {
-                false
-            };
-            if !script_overrides && user == world.objects().dude()
-                && let Some(obj_name) = world.object_name(used)
-            {
-                let msg = &self.proto_db.messages().get(MSG_YOU_SEE_X).unwrap().text;
-                let msg = sprintf(msg, &[&obj_name]);
-                self.push_message(&msg, ui);
+                debug!("{:?} doesn't barter", dialog.obj);
             }
         }
     }
 
-    fn use_door(&mut self, user: object::Handle, door: object::Handle, ui: &mut Ui) {
-        let world = &mut self.world.borrow_mut();
-
-        let script = {
-            let dooro = world.objects().get(door);
-            if dooro.is_locked().unwrap() {
-                // TODO sfx
-            }
-            dooro.script
+    /// Advances game time while resting. The rest is interrupted by combat, dialog or movie.
+    // pipboy_rest()
+    fn update_rest(&mut self, ui: &mut Ui) {
+        let Some(rest) = self.pipboy.rest() else {
+            return;
         };
 
-        if let Some((sid, _)) = script {
-            let script_overrides = self.scripts.execute_predefined_proc(sid, PredefinedProc::Use,
-                &mut script::Context {
-                    world,
-                    obj_sequencer: &mut self.obj_sequencer,
-                    dialog: &mut self.dialog,
-                    ui,
-                    message_panel: self.message_panel,
-                    map_id: self.map_id.unwrap(),
-                    source_obj: Some(user),
-                    target_obj: Some(door),
-                    skill: None,
-                    rpg: &mut self.rpg,
-                }).unwrap().assert_no_suspend().script_overrides;
-            if script_overrides {
-                return;
+        let now = {
+            let world = &mut self.world.borrow_mut();
+            let mut next = world.game_time.add_decis(REST_STEP.as_decis());
+            if let Some(end) = rest.end
+                && end.as_decis() < next.as_decis()
+            {
+                next = end;
             }
-        }
+            world.game_time = next;
+            next
+        };
 
-        let dooro = world.objects().get(door);
-        let need_open = if dooro.frame_idx > 0 { // Indicates the door is open
-            if world.objects().has_blocker_at(dooro.pos(), None) {
-                let msg = &self.proto_db.messages().get(MSG_DOORWAY_SEEMS_TO_BE_BLOCKED).unwrap().text;
-                self.push_message(msg, ui);
-                return
+        let (healed, party_healed) = {
+            let world = self.world.borrow();
+            let objs = world.objects();
+            let heal = now.as_decis() - rest.last_heal.as_decis() >= REST_HEAL_INTERVAL.as_decis();
+            if heal {
+                self.pipboy.rest_mut().unwrap().last_heal = now;
             }
-            false
-        } else {
-            if dooro.sub.as_scenery().unwrap().as_door().unwrap().flags.contains(DoorFlag::Open) {
-                return;
+            let healed = |h| {
+                let mut obj = objs.get_mut(h);
+                if obj.sub.as_critter().is_none_or(|c| c.is_dead()) {
+                    return true;
+                }
+                if heal {
+                    let heal_rate = self.rpg.stat(Stat::HealRate, &obj, objs);
+                    self.rpg.heal(&mut obj, heal_rate, objs);
+                }
+                obj.sub.as_critter().unwrap().hit_points >= self.rpg.stat(Stat::HitPoints, &obj, objs)
+            };
+            let dude_healed = healed(objs.dude());
+            let mut party_healed = dude_healed;
+            for h in self.party.members() {
+                party_healed &= healed(h);
             }
-            true
+            (dude_healed, party_healed)
         };
 
-        let seq = Chain::new();
-        seq.control()
-            .cancellable(FrameAnim::new(door, FrameAnimOptions {
-                direction: if need_open { AnimDirection::Forward } else { AnimDirection::Backward },
-                skip: 1,
-                ..Default::default()
-            }))
-            .finalizing(PushEvent::new(sequence::Event::SetDoorState { door, open: need_open }));
+        self.process_queue(ui);
 
-        self.obj_sequencer.replace(door, seq);
+        let done = match rest.until {
+            RestUntil::Elapsed(_) | RestUntil::Hour(_) =>
+                rest.end.is_none_or(|end| now.as_decis() >= end.as_decis()),
+            RestUntil::Healed => healed,
+            RestUntil::PartyHealed => party_healed,
+        };
+        if done || self.combat.is_active() || self.dialog.is_some() || self.movies.is_playing() {
+            self.pipboy.stop_rest();
+        }
+        self.pipboy.sync_time(now, ui);
     }
 
-    // set_door_open, set_door_closed, check_door_state
-    fn set_door_state(&mut self, door: object::Handle, open: bool) {
-        let mut world = self.world.borrow_mut();
-        {
-            {
-                let mut dooro = world.objects_mut().get_mut(door);
-                {
-                    let door = dooro.sub.as_scenery_mut().unwrap().as_door_mut().unwrap();
-                    if open {
-                        door.flags.insert(DoorFlag::Open);
-                    } else {
-                        door.flags.remove(DoorFlag::Open);
-                    }
-                }
-                if open {
-                    dooro.flags.insert(Flag::ShootThru | Flag::LightThru | Flag::NoBlock);
-                } else {
-                    dooro.flags.remove(Flag::ShootThru | Flag::LightThru | Flag::NoBlock);
-                }
-            }
+    fn show_world_map(&mut self, ui: &mut Ui) {
+        if self.world_map.is_visible() {
+            return;
+        }
+        self.obj_sequencer.cancel(self.world.borrow().objects().dude());
+        self.world_map.show(ui);
+    }
 
-            world.objects_mut().set_frame(door, if open {
-                SetFrame::Last
-            } else {
-                SetFrame::Index(0)
-            });
+    // wmWorldMapFunc()
+    fn update_world_map(&mut self, now: Instant, ui: &mut Ui) {
+        if now < self.next_world_map_step {
+            return;
+        }
+        self.next_world_map_step = now + WORLD_MAP_STEP_INTERVAL;
+
+        let event = self.world_map.step(&mut self.world.borrow_mut().game_time);
+        self.world_map.sync_view(ui);
+        self.process_queue(ui);
+        match event {
+            Some(TravelEvent::Arrived { area: Some(area) }) => self.enter_area(area, ui),
+            Some(TravelEvent::Arrived { area: None }) | None => {}
+            Some(TravelEvent::Encounter(spawn)) => self.enter_encounter(spawn, ui),
         }
-        world.objects_mut().rebuild_light_grid();
     }
 
-    // is_next_to
-    fn check_next_to(&mut self, obj1: object::Handle, obj2: object::Handle, ui: &mut Ui) -> bool {
-        if self.world.borrow().objects().distance(obj1, obj2).unwrap() > 1 {
-            let msg = &self.misc_msgs.get(2000).unwrap().text;
-            self.push_message(msg, ui);
-            false
-        } else {
-            true
+    // wmTownMapFunc()
+    /// Shows the town map of the current area or the world map if there's no town map.
+    fn show_town_map(&mut self, ui: &mut Ui) {
+        if self.world_map.is_visible() {
+            return;
+        }
+        self.obj_sequencer.cancel(self.world.borrow().objects().dude());
+        if !self.world_map.show_town_map(ui) {
+            self.world_map.show(ui);
         }
     }
 
-    fn create_scroll_areas(rect: Rect, ui: &mut Ui) -> StaticMap<ScrollDirection, ui::Handle> {
-        let mut new = |rect, cur, curx| {
-            let win = ui.new_window(rect, None);
-            ui.new_widget(win, Rect::with_size(0, 0, rect.width(), rect.height()), None, None,
-                ScrollArea::new(cur, curx, Duration::from_millis(0), Duration::from_millis(30)))
+    fn enter_area(&mut self, area: u32, ui: &mut Ui) {
+        let Some(entrance) = self.world_map.area_entrance(area).cloned() else {
+            warn!("area {} has no entrances", area);
+            return;
         };
-        use ui::Cursor::*;
-        use ScrollDirection::*;
-        static_map! {
-            N => new(
-                Rect::new(rect.left + 1, rect.top, rect.right - 1, rect.top + 1),
-                ScrollNorth, ScrollNorthX),
-            NE => new(
-                Rect::new(rect.right - 1, rect.top, rect.right, rect.top + 1),
-                ScrollNorthEast, ScrollNorthEastX),
-            E => new(
-                Rect::new(rect.right - 1, rect.top + 1, rect.right, rect.bottom - 1),
-                ScrollEast, ScrollEastX),
-            SE => new(
-                Rect::new(rect.right - 1, rect.bottom - 1, rect.right, rect.bottom),
-                ScrollSouthEast, ScrollSouthEastX),
-            S => new(
-                Rect::new(rect.left + 1, rect.bottom - 1, rect.right - 1, rect.bottom),
-                ScrollSouth, ScrollSouthX),
-            SW => new(
-                Rect::new(rect.left, rect.bottom - 1, rect.left + 1, rect.bottom),
-                ScrollSouthWest, ScrollSouthWestX),
-            W => new(
-                Rect::new(rect.left, rect.top + 1, rect.left + 1, rect.bottom - 1),
-                ScrollWest, ScrollWestX),
-            NW => new(
-                Rect::new(rect.left, rect.top, rect.left + 1, rect.top + 1),
-                ScrollNorthWest, ScrollNorthWestX),
+        self.enter_entrance(area, entrance, ui);
+    }
+
+    fn enter_entrance(&mut self, area: u32, entrance: Entrance, ui: &mut Ui) {
+        let Some(map_id) = self.map_db.find(&entrance.map) else {
+            warn!("unknown map `{}` in entrance of area {}", entrance.map, area);
+            return;
+        };
+        self.world_map.hide(ui);
+        if self.map_id != Some(map_id) {
+            let name = self.map_db.get(map_id).unwrap().name.clone();
+            self.switch_map(&name, ui);
+        }
+        if let Some(pos) = entrance.pos {
+            self.set_dude_pos(pos, entrance.direction, ui);
         }
     }
 
-    fn set_dude_pos(&mut self, pos: EPoint, direction: Direction, ui: &mut Ui) {
-        let world = &mut self.world.borrow_mut();
-        let dude_objh = world.objects().dude();
-        let elevation_change = {
-            let mut dude_obj = world.objects_mut().get_mut(dude_objh);
-            dude_obj.direction = direction;
-            dude_obj.pos().elevation != pos.elevation
+    // wmRndEncounterPick()
+    fn enter_encounter(&mut self, spawn: EncounterSpawn, ui: &mut Ui) {
+        let Some(map_id) = self.map_db.find(&spawn.map) else {
+            warn!("unknown encounter map `{}`", spawn.map);
+            return;
         };
-        world.objects_mut().set_pos(dude_objh, Some(pos));
-        if elevation_change {
-            let ctx = &mut script::Context {
-                ui,
-                world,
-                obj_sequencer: &mut self.obj_sequencer,
-                dialog: &mut self.dialog,
-                message_panel: self.message_panel,
-                map_id: self.map_id.unwrap(),
-                source_obj: None,
-                target_obj: None,
-                skill: None,
-                rpg: &mut self.rpg,
+        self.world_map.hide(ui);
+
+        let map_def = self.map_db.get(map_id).unwrap();
+        let name = map_def.name.clone();
+        let start_pos = if map_def.random_start_points.is_empty() {
+            None
+        } else {
+            let i = random(0, map_def.random_start_points.len() as i32 - 1);
+            Some(map_def.random_start_points[i as usize])
+        };
+        self.switch_map(&name, ui);
+        if let Some(pos) = start_pos {
+            let direction = self.world.borrow().objects().dude_ref().direction;
+            self.set_dude_pos(pos, direction, ui);
+        }
+
+        // wmSetupRndNextTileNum()
+        let distance = {
+            let world = self.world.borrow();
+            let dude = world.objects().dude_ref();
+            let mut v = self.rpg.stat(Stat::Perception, &dude, world.objects()) + random(-2, 2);
+            if self.rpg.has_perk(Perk::CautiousNature, ProtoId::DUDE) {
+                v += 3;
+            }
+            cmp::max(v, 0) as u32
+        };
+
+        let mut critters = Vec::new();
+        for group in spawn.groups {
+            let world = &mut self.world.borrow_mut();
+            let dude_pos = world.objects().dude_ref().pos();
+            let positions = {
+                let objs = world.objects();
+                worldmap::place_critters(group.placement, group.critters.len(), dude_pos.point,
+                    distance, world.hex_grid(),
+                    |p| !objs.has_blocker_at(dude_pos.with_point(p), None))
             };
-            self.scripts.execute_map_procs(PredefinedProc::MapUpdate, ctx);
+            for (critter, pos) in group.critters.into_iter().zip(positions) {
+                let proto = match self.proto_db.proto(critter.pid) {
+                    Ok(v) => v,
+                    Err(e) => {
+                        warn!("error loading encounter critter proto {:?}: {:?}",
+                            critter.pid, e);
+                        continue;
+                    }
+                };
+                let program_id = critter.script
+                    .or_else(|| proto.borrow().script.map(|sid| sid.program_id()));
+                let pos = dude_pos.with_point(pos);
+                let obj = {
+                    let mut obj = world.objects_mut()
+                        .create(None, Some(proto), Some(pos), Some(&self.rpg));
+                    obj.direction = hex::direction(pos.point, dude_pos.point);
+                    obj.handle()
+                };
+                world.objects_mut().make_standing(obj);
+                if let Some(program_id) = program_id {
+                    match self.scripts.instantiate_new(ScriptKind::Critter, program_id, None) {
+                        Ok(sid) => {
+                            world.objects_mut().get_mut(obj).script = Some((sid, program_id));
+                            self.scripts.attach_to_object(sid, obj);
+                        }
+                        Err(e) => warn!("couldn't instantiate encounter critter script: {}", e),
+                    }
+                }
+                critters.push(obj);
+            }
+        }
+
+        for obj in critters {
+            self.execute_obj_proc(obj, PredefinedProc::Start, None, 0, ui);
         }
-        world.camera_look_at_dude();
     }
 
     fn show_skilldex(&mut self, ui: &mut Ui, target: Option<object::Handle>) {
@@ -959,7 +3106,6 @@ impl GameState {
         let world = self.world.borrow();
         let objs = world.objects();
         let user = world.objects().dude();
-        let usero = objs.get(user);
         let targeto = objs.get(target);
 
         match skill {
@@ -981,7 +3127,13 @@ impl GameState {
             _ => return,
         }
 
-        // TODO handle party members
+        // The party member better in the skill does the job.
+        let user = if skill == Skill::Steal {
+            user
+        } else {
+            self.party.best_in_skill(skill, &self.rpg, objs).unwrap_or(user)
+        };
+        let usero = objs.get(user);
 
         let seq = Chain::new();
 
@@ -1039,7 +3191,14 @@ impl GameState {
                         source_obj: Some(user),
                         target_obj: Some(target),
                         skill: Some(skill),
+                        fixed_param: 0,
                         rpg: &mut self.rpg,
+                        sound: &mut self.sound,
+                        map_db: &mut self.map_db,
+                        movies: &mut self.movies,
+                        combat: &mut self.combat,
+                        world_map: &mut self.world_map,
+                        party: &mut self.party,
                     }).unwrap().assert_no_suspend().script_overrides
             } else {
                 false
@@ -1052,10 +3211,14 @@ impl GameState {
 
     fn default_use_skill_on(&mut self,
         skill: Skill,
-        _user: object::Handle,
+        user: object::Handle,
         target: object::Handle,
         ui: &mut Ui,
     ) {
+        if skill == Skill::Steal {
+            self.steal(user, target, ui);
+            return;
+        }
         let world = &mut self.world.borrow_mut();
         {
             let targeto = world.objects().get(target);
@@ -1087,9 +3250,6 @@ impl GameState {
                     // TODO
                     return;
                 }
-                Skill::Steal => {
-                    // TODO
-                }
                 Skill::Traps => {
                     self.push_message(&self.rpg.skill_msgs().get(551).unwrap().text, ui);
                     return;
@@ -1107,6 +3267,30 @@ impl GameState {
             debug!("TODO");
         }
     }
+
+    /// Opens the loot screen for stealing from the critter or the container.
+    fn steal(&mut self, thief: object::Handle, target: object::Handle, ui: &mut Ui) {
+        {
+            let world = self.world.borrow();
+            let targeto = world.objects().get(target);
+            if thief != world.objects().dude() || self.combat.is_active() {
+                return;
+            }
+            let can_steal = match targeto.kind() {
+                EntityKind::Critter => !targeto.proto().unwrap()
+                    .sub.as_critter().unwrap()
+                    .flags.contains(CritterFlag::NoSteal),
+                EntityKind::Item => targeto.item_kind() == Some(ItemKind::Container)
+                    && targeto.is_locked() != Some(true),
+                _ => false,
+            };
+            if !can_steal {
+                debug!("{:?} can't steal from {:?}", thief, target);
+                return;
+            }
+        }
+        self.loot.show(target, ui);
+    }
 }
 
 impl AppState for GameState {
@@ -1127,15 +3311,34 @@ impl AppState for GameState {
                         }
                         self.set_dude_pos(pos, direction, ctx.ui);
                     }
-                    TargetMap::WorldMap(k) => {
-                        warn!("map exit to {:?} is not implemented", k);
-                    }
+                    TargetMap::WorldMap(WorldMapKind::Town) => self.show_town_map(ctx.ui),
+                    TargetMap::WorldMap(WorldMapKind::World) => self.show_world_map(ctx.ui),
+                }
+            }
+            AppEvent::ShowOptions => {
+                if !self.in_game() || self.options.is_menu_visible() {
+                    return;
                 }
+                self.thumbnail = Some(savegame::make_thumbnail(ctx.screen.back_buffer(),
+                    ctx.screen.width(), Rect::with_size(0, 0, 640, 380)));
+                self.options.show_menu(ctx.ui);
             }
+            AppEvent::GameOver => {
+                self.end_game(ctx.ui);
+                self.show_main_menu(false, ctx.ui);
+            }
+            AppEvent::Quit => {}
         }
     }
 
     fn handle_input(&mut self, event: &SdlEvent, ui: &mut Ui) -> bool {
+        if let SdlEvent::KeyDown { keycode: Some(Keycode::Escape), .. } = event {
+            return self.handle_escape(ui);
+        }
+        if !self.in_game() {
+            return false;
+        }
+
         let mut world = self.world.borrow_mut();
         match event {
             SdlEvent::KeyDown { keycode: Some(Keycode::Right), .. } => {
@@ -1195,7 +3398,23 @@ impl AppState for GameState {
             SdlEvent::KeyDown { keycode: Some(Keycode::P), .. } => {
                 self.user_paused = !self.user_paused;
             }
+            SdlEvent::KeyDown { keycode: Some(Keycode::Backquote), .. } => {
+                if let Some(debugger) = self.scripts.debugger() {
+                    info!("script debugger will pause before the next instruction");
+                    debugger.borrow_mut().interrupt();
+                }
+            }
 
+            SdlEvent::KeyDown { keycode: Some(Keycode::Space), .. } => {
+                // End the dude's turn.
+                let dude = world.objects().dude();
+                if self.combat.current() == Some(dude)
+                    && self.combat.is_turn_started()
+                    && !self.obj_sequencer.is_running(dude)
+                {
+                    self.combat.next_turn();
+                }
+            }
             SdlEvent::KeyDown { keycode: Some(Keycode::LShift), .. } |
             SdlEvent::KeyDown { keycode: Some(Keycode::RShift), .. } => self.shift_key_down = true,
             SdlEvent::KeyUp { keycode: Some(Keycode::LShift), .. } |
@@ -1206,7 +3425,35 @@ impl AppState for GameState {
     }
 
     fn handle_ui_command(&mut self, command: UiCommand, ui: &mut Ui) {
-        self.inventory.handle(command, &self.rpg, ui, &mut self.ui_sequencer);
+        if let Some(drug) = self.inventory.handle(command, &self.rpg, ui, &mut self.ui_sequencer) {
+            let dude = self.world.borrow().objects().dude();
+            self.use_drug(dude, drug);
+            self.inventory.sync_to_ui(&self.rpg, ui);
+        }
+        if self.barter.is_visible()
+            && let Some(reply) = self.barter.handle(command, &self.rpg, &self.party, ui)
+        {
+            self.dialog.as_mut().unwrap().set_reply(ui, reply);
+        }
+        if self.loot.is_visible()
+            && let Some(steal) = self.loot.handle(command, &self.rpg, ui)
+        {
+            self.push_message(&steal.msg, ui);
+            if steal.caught {
+                // The victim script decides what happens to the thief.
+                let dude = self.world.borrow().objects().dude();
+                self.execute_obj_proc(steal.target, PredefinedProc::Pickup, Some(dude), 0, ui);
+            }
+        }
+        self.character_editor.handle(command, &mut self.rpg, &self.scripts.vars.global_vars, ui);
+        if self.new_character && !self.character_editor.is_visible() {
+            self.new_character = false;
+            if command.data == UiCommandData::CharacterEditor(character_editor::Command::Done) {
+                self.start_new_game(ui);
+            } else {
+                self.char_select.sync(&self.rpg, ui);
+            }
+        }
 
         match command.data {
             UiCommandData::ObjectPick { kind, obj: objh } => {
@@ -1238,6 +3485,14 @@ impl AppState for GameState {
 
                         self.time.set_paused(true);
                     }
+                    ObjectPickKind::DefaultAction if self.combat.is_active() && {
+                        let world = self.world.borrow();
+                        objh != world.objects().dude()
+                            && !world.objects().get(objh).is_critter_dead()
+                            && world.objects().get(objh).sub.as_critter().is_some()
+                    } => {
+                        self.dude_combat_attack(objh);
+                    }
                     ObjectPickKind::DefaultAction => if let Some(a) = default_action {
                         ui.widget_mut::<WorldView>(self.world_view).default_action_icon = if self.object_action_menu.is_none() {
                             default_action
@@ -1252,7 +3507,9 @@ impl AppState for GameState {
                 }
             }
             UiCommandData::HexPick { action, pos } => {
-                if action {
+                if action && self.combat.is_active() {
+                    self.dude_combat_move(pos.point);
+                } else if action {
                     let dude_objh = self.world.borrow().objects().dude();
 
                     let seq = Chain::new();
@@ -1290,13 +3547,18 @@ impl AppState for GameState {
                 action_menu::hide(object_action.menu, ui);
                 self.time.set_paused(false);
             }
+            UiCommandData::Pick { id } if self.save_load.is(command.source, ui) => {
+                self.save_load.select(id as usize, ui);
+            }
+            UiCommandData::Pick { .. } if self.pipboy.is(command.source) => {
+                self.handle_pipboy_command(command, ui);
+            }
             UiCommandData::Pick { id } => {
                 let (sid, proc_id) = {
                     let dialog = self.dialog.as_mut().unwrap();
 
                     assert!(dialog.is(command.source));
-                    let proc_id = dialog.option(id).proc_id;
-                    dialog.clear_options(ui);
+                    let proc_id = dialog.pick_option(ui, id);
 
                     (dialog.sid(), proc_id)
                 };
@@ -1315,7 +3577,14 @@ impl AppState for GameState {
                             source_obj,
                             target_obj,
                             skill: None,
+                            fixed_param: 0,
                             rpg: &mut self.rpg,
+                            sound: &mut self.sound,
+                            map_db: &mut self.map_db,
+                            movies: &mut self.movies,
+                            combat: &mut self.combat,
+                            world_map: &mut self.world_map,
+                            party: &mut self.party,
                         }).assert_no_suspend();
                     // No dialog options means the dialog is finished.
                     self.dialog.as_ref().unwrap().is_empty()
@@ -1333,7 +3602,14 @@ impl AppState for GameState {
                         source_obj: None,
                         target_obj: None,
                         skill: None,
+                        fixed_param: 0,
                         rpg: &mut self.rpg,
+                        sound: &mut self.sound,
+                        map_db: &mut self.map_db,
+                        movies: &mut self.movies,
+                        combat: &mut self.combat,
+                        world_map: &mut self.world_map,
+                        party: &mut self.party,
                     };
                     self.scripts.resume(ctx).assert_no_suspend();
                     assert!(!self.scripts.can_resume());
@@ -1343,6 +3619,16 @@ impl AppState for GameState {
                     self.scripts.execute_map_procs(PredefinedProc::MapUpdate, ctx);
                 }
             }
+            UiCommandData::Dialog(cmd) => {
+                let dialog = self.dialog.as_mut().unwrap();
+                match cmd {
+                    dialog::Command::Review => dialog.show_review(ui),
+                    dialog::Command::ReviewDone => dialog.hide_review(ui),
+                    dialog::Command::Barter => dialog.barter(),
+                }
+            }
+            UiCommandData::Barter(_) => {}
+            UiCommandData::Loot(_) => {}
             UiCommandData::Scroll => {
                 let (dir, widg) = self.scroll_areas
                     .iter()
@@ -1384,15 +3670,60 @@ impl AppState for GameState {
                 _ => {}
             }
             UiCommandData::MoveWindow(_) => {}
+            UiCommandData::CharacterEditor(_) => {}
+            UiCommandData::Pipboy(_) => self.handle_pipboy_command(command, ui),
+            UiCommandData::MainMenu(cmd) => self.handle_main_menu_command(cmd, ui),
+            UiCommandData::CharSelect(cmd) => self.handle_char_select_command(cmd, ui),
+            UiCommandData::SaveLoad(cmd) => self.handle_save_load_command(cmd, ui),
+            UiCommandData::Options(cmd) => self.handle_options_command(cmd, ui),
+            UiCommandData::MovieDone => self.movies.hide(ui, &mut self.sound),
+            UiCommandData::WorldMap(cmd) => {
+                let dest = match cmd {
+                    world_map::Command::Travel { pos } => Some(pos),
+                    world_map::Command::Area { id } => self.world_map.area(id).map(|a| a.pos),
+                    world_map::Command::Entrance { area, entrance } => {
+                        if let Some(entrance) = self.world_map.entrance(area, entrance).cloned() {
+                            self.enter_entrance(area, entrance, ui);
+                        }
+                        return;
+                    }
+                };
+                // Traveling is shown on the world map.
+                if self.world_map.is_town_map_visible() {
+                    self.world_map.hide(ui);
+                    self.world_map.show(ui);
+                }
+                self.world_map.set_destination(dest);
+                self.world_map.sync_view(ui);
+            }
         }
     }
 
     fn update(&mut self, mut ctx: state::Update) {
+        self.movies.update(ctx.ui, &mut self.sound);
+
         self.time.set_paused(
             self.user_paused ||
+            self.movies.is_playing() ||
             self.scripts.can_resume() ||
             self.skilldex.is_visible() ||
-            self.inventory.is_visible());
+            self.inventory.is_visible() ||
+            self.loot.is_visible() ||
+            self.character_editor.is_visible() ||
+            self.pipboy.is_visible() ||
+            self.world_map.is_visible() ||
+            self.main_menu.is_visible() ||
+            self.char_select.is_visible() ||
+            self.save_load.is_visible() ||
+            self.options.is_menu_visible() ||
+            self.options.is_prefs_visible() ||
+            !self.in_game());
+
+        self.world.borrow_mut().anim_speedup = if self.combat.is_active() {
+            self.options.prefs().combat_speed
+        } else {
+            0
+        };
 
         self.time.update(ctx.delta);
 
@@ -1421,10 +3752,22 @@ impl AppState for GameState {
                 self.handle_seq_events(&mut ctx);
             }
 
-            self.fidget.update(
-                self.time.time(),
-                &mut self.world.borrow_mut(),
-                &mut self.obj_sequencer);
+            self.update_combat(ctx.ui);
+
+            self.update_game_time();
+            self.process_queue(ctx.ui);
+            self.update_scripts(ctx.ui);
+
+            if !self.combat.is_active() {
+                self.update_party();
+                self.update_idle_ai();
+                self.fidget.update(
+                    self.time.time(),
+                    &mut self.world.borrow_mut(),
+                    &mut self.obj_sequencer);
+            }
+
+            self.update_ambient_sfx();
         } else {
             self.obj_sequencer.sync(&mut sequence::Sync {
                 world: &mut self.world.borrow_mut(),
@@ -1432,6 +3775,17 @@ impl AppState for GameState {
             });
         }
 
+        self.update_barter(ctx.ui);
+        if self.world_map.take_show_request() {
+            self.show_world_map(ctx.ui);
+        }
+        if self.world_map.is_visible() {
+            self.update_world_map(ctx.time, ctx.ui);
+        }
+        if self.pipboy.rest().is_some() {
+            self.update_rest(ctx.ui);
+        }
+
         self.ui_sequencer.update(&mut sequence::Update {
             time: ctx.time,
             world: &mut self.world.borrow_mut(),
@@ -1439,6 +3793,10 @@ impl AppState for GameState {
             out: &mut self.seq_events,
         });
         assert!(self.seq_events.is_empty());
+
+        self.sound.update(ctx.time);
+
+        ctx.out.append(&mut self.app_events);
     }
 }
 
@@ -1,6 +1,9 @@
 pub mod action_menu;
+pub mod automap;
 pub mod hud;
 pub mod inventory_list;
 pub mod move_window;
 pub mod scroll_area;
+pub mod talking_head;
 pub mod world;
+pub mod world_map;
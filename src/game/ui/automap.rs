@@ -0,0 +1,91 @@
+use crate::game::automap::{Automap, Cell};
+use crate::graphics::{Point, Rect};
+use crate::graphics::color::{Rgb15, RED};
+use crate::graphics::render::{TextureFactory, TextureHandle};
+use crate::ui::*;
+
+const WALL_COLOR: Rgb15 = unsafe { Rgb15::rgb15_from_packed_unchecked(0x03e0) };
+const SCENERY_COLOR: Rgb15 = unsafe { Rgb15::rgb15_from_packed_unchecked(0x0200) };
+const DUDE_MARK_SIZE: i32 = 3;
+
+/// Automap scaled to fit the widget rect. The dude is marked if his position is set.
+pub struct AutomapView {
+    automap: Option<Automap>,
+    dude_pos: Option<Point>,
+    texture_factory: TextureFactory,
+    texture: Option<TextureHandle>,
+    dude_mark: Option<TextureHandle>,
+}
+
+impl AutomapView {
+    pub fn new(texture_factory: TextureFactory) -> Self {
+        Self {
+            automap: None,
+            dude_pos: None,
+            texture_factory,
+            texture: None,
+            dude_mark: None,
+        }
+    }
+
+    pub fn set(&mut self, automap: Option<Automap>, dude_pos: Option<Point>) {
+        self.automap = automap;
+        self.dude_pos = dude_pos;
+        self.texture = None;
+    }
+
+    /// Hex `x` grows westward so the columns are mirrored.
+    fn screen_x(automap: &Automap, x: i32) -> i32 {
+        automap.width() - 1 - x
+    }
+}
+
+impl Widget for AutomapView {
+    fn handle_event(&mut self, _ctx: HandleEvent) {}
+
+    fn render(&mut self, ctx: Render) {
+        let Some(automap) = &self.automap else {
+            return;
+        };
+        let rect = ctx.base.unwrap().rect();
+        let (w, h) = (automap.width(), automap.height());
+        if w == 0 || h == 0 {
+            return;
+        }
+
+        let texture = self.texture.get_or_insert_with(|| {
+            let palette = ctx.canvas.palette();
+            let wall = palette.color_idx(WALL_COLOR);
+            let scenery = palette.color_idx(SCENERY_COLOR);
+            let mut data = vec![0; (w * h) as usize];
+            for y in 0..h {
+                for x in 0..w {
+                    let c = match automap.get(Point::new(x, y)) {
+                        Cell::Empty => continue,
+                        Cell::Wall => wall,
+                        Cell::Scenery => scenery,
+                    };
+                    data[(y * w + Self::screen_x(automap, x)) as usize] = c;
+                }
+            }
+            self.texture_factory.new_texture(w, h, data.into())
+        });
+
+        // Keep the aspect ratio.
+        let scale = std::cmp::min(rect.width() * 0x10000 / w, rect.height() * 0x10000 / h);
+        let size = Point::new((w * scale) >> 16, (h * scale) >> 16);
+        let top_left = rect.top_left() + (Point::new(rect.width(), rect.height()) - size) / 2;
+        let dst = Rect::with_points(top_left, top_left + size);
+        ctx.canvas.draw_scaled(texture, dst);
+
+        if let Some(pos) = self.dude_pos {
+            let mark = self.dude_mark.get_or_insert_with(|| self.texture_factory.new_texture(
+                DUDE_MARK_SIZE, DUDE_MARK_SIZE,
+                vec![7; (DUDE_MARK_SIZE * DUDE_MARK_SIZE) as usize].into()));
+            let pos = top_left
+                + Point::new((Self::screen_x(automap, pos.x) * scale) >> 16, (pos.y * scale) >> 16)
+                - Point::new(DUDE_MARK_SIZE / 2, DUDE_MARK_SIZE / 2);
+            ctx.canvas.draw_masked_color(RED, None, pos, mark);
+        }
+    }
+}
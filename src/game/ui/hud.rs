@@ -5,7 +5,8 @@ use crate::graphics::font::FontKey;
 use crate::graphics::sprite::Sprite;
 use crate::ui::*;
 use crate::ui::button::Button;
-use crate::ui::command::{inventory, SkilldexCommand, UiCommandData};
+use crate::ui::command::{character_editor, inventory, options, pipboy, SkilldexCommand,
+    UiCommandData};
 use crate::ui::message_panel::{MessagePanel, Anchor};
 
 pub fn create(ui: &mut Ui) -> Handle {
@@ -26,7 +27,8 @@ pub fn create(ui: &mut Ui) -> Handle {
 
     // Options button.
     ui.new_widget(main_hud, Rect::with_size(210, 62, 34, 34), None, None,
-        Button::new(FrameId::OPTIONS_BUTTON_UP, FrameId::OPTIONS_BUTTON_DOWN, None));
+        Button::new(FrameId::OPTIONS_BUTTON_UP, FrameId::OPTIONS_BUTTON_DOWN,
+            Some(UiCommandData::Options(options::Command::Show))));
 
     // Single/burst switch button.
     ui.new_widget(main_hud, Rect::with_size(218, 6, 22, 21), None, None,
@@ -43,11 +45,13 @@ pub fn create(ui: &mut Ui) -> Handle {
 
     // CHA button.
     ui.new_widget(main_hud, Rect::with_size(526, 59, 41, 19), None, None,
-        Button::new(FrameId::CHARACTER_BUTTON_UP, FrameId::CHARACTER_BUTTON_DOWN, None));
+        Button::new(FrameId::CHARACTER_BUTTON_UP, FrameId::CHARACTER_BUTTON_DOWN,
+            Some(UiCommandData::CharacterEditor(character_editor::Command::Show))));
 
     // PIP button.
     ui.new_widget(main_hud, Rect::with_size(526, 78, 41, 19), None, None,
-        Button::new(FrameId::PIP_BUTTON_UP, FrameId::PIP_BUTTON_DOWN, None));
+        Button::new(FrameId::PIP_BUTTON_UP, FrameId::PIP_BUTTON_DOWN,
+            Some(UiCommandData::Pipboy(pipboy::Command::Show))));
 
     // Attack button.
     // FIXME this should be a custom button with overlay text images.
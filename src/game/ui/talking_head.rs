@@ -0,0 +1,228 @@
+use std::collections::VecDeque;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use crate::asset::frame::{FrameDb, FrameId, Idx};
+use crate::asset::lip::Lips;
+use crate::graphics::geometry::hex::Direction;
+use crate::graphics::sprite::{Anchor, Sprite};
+use crate::ui::*;
+use crate::util::random::random;
+
+/// Maps LIP phonemes to frames of the phoneme animation.
+// head_phoneme_lookup
+static PHONEME_TO_FRAME: [u8; 42] = [
+    0, 3, 1, 1, 3, 1, 1, 1, 7, 8,
+    7, 3, 1, 8, 1, 7, 7, 6, 6, 2,
+    2, 2, 2, 4, 4, 5, 5, 2, 2, 2,
+    2, 2, 6, 2, 2, 5, 8, 2, 2, 2,
+    2, 8,
+];
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Mood {
+    Good,
+    Neutral,
+    Bad,
+}
+
+impl Mood {
+    /// Maps the fidget animation number as passed to `start_gdialog` to mood.
+    pub fn from_fidget_anim(anim: i32) -> Option<Self> {
+        Some(match anim {
+            1 => Mood::Good,
+            4 => Mood::Neutral,
+            7 => Mood::Bad,
+            _ => return None,
+        })
+    }
+
+    fn fidget_anim(self) -> u8 {
+        match self {
+            Mood::Good => 1,
+            Mood::Neutral => 4,
+            Mood::Bad => 7,
+        }
+    }
+
+    fn phoneme_anim(self) -> u8 {
+        match self {
+            Mood::Good => 9,
+            Mood::Neutral => 10,
+            Mood::Bad => 11,
+        }
+    }
+}
+
+struct Playing {
+    fid: FrameId,
+    frame_idx: usize,
+    last_frame: Option<Instant>,
+}
+
+/// Animated head of the NPC in dialog. Plays fidgets while idle, transitions between moods and
+/// lip syncs the speech.
+pub struct TalkingHead {
+    frm_db: Rc<FrameDb>,
+    idx: Idx,
+    mood: Mood,
+    queue: VecDeque<FrameId>,
+    playing: Option<Playing>,
+    next_fidget: Option<Instant>,
+    lips: Option<Lips>,
+    lips_start: Option<Instant>,
+    now: Option<Instant>,
+}
+
+impl TalkingHead {
+    pub fn new(frm_db: Rc<FrameDb>, idx: Idx, mood: Mood) -> Self {
+        Self {
+            frm_db,
+            idx,
+            mood,
+            queue: VecDeque::new(),
+            playing: None,
+            next_fidget: None,
+            lips: None,
+            lips_start: None,
+            now: None,
+        }
+    }
+
+    pub fn mood(&self) -> Mood {
+        self.mood
+    }
+
+    /// Changes mood playing the transition animations. Reacting with the current good or bad
+    /// mood plays the strong reaction animation.
+    // gdialog_reaction()
+    pub fn react(&mut self, mood: Mood) {
+        use Mood::*;
+        let anims: &[u8] = match (self.mood, mood) {
+            (Good, Good) => &[0],
+            (Good, Neutral) => &[2],
+            (Good, Bad) => &[2, 5],
+            (Neutral, Good) => &[3],
+            (Neutral, Neutral) => &[],
+            (Neutral, Bad) => &[5],
+            (Bad, Good) => &[6, 3],
+            (Bad, Neutral) => &[6],
+            (Bad, Bad) => &[8],
+        };
+        for &anim in anims {
+            if let Some(fid) = self.fid(anim, 0) {
+                self.queue.push_back(fid);
+            }
+        }
+        self.mood = mood;
+    }
+
+    /// Starts lip syncing `lips`. The timing starts with the next tick.
+    pub fn speak(&mut self, lips: Option<Lips>) {
+        self.lips = lips;
+        self.lips_start = None;
+    }
+
+    pub fn stop_speaking(&mut self) {
+        self.lips = None;
+        self.lips_start = None;
+    }
+
+    fn fid(&self, anim: u8, sub_anim: u8) -> Option<FrameId> {
+        FrameId::new_head(anim, sub_anim, self.idx)
+            .filter(|&fid| self.frm_db.exists(fid))
+    }
+
+    fn idle_fid(&self) -> Option<FrameId> {
+        self.fid(self.mood.fidget_anim(), 1)
+    }
+
+    fn random_fidget(&self) -> Option<FrameId> {
+        let count = (1..=9)
+            .take_while(|&i| self.fid(self.mood.fidget_anim(), i).is_some())
+            .count() as i32;
+        if count == 0 {
+            return None;
+        }
+        self.fid(self.mood.fidget_anim(), random(1, count) as u8)
+    }
+
+    fn phoneme(&self) -> Option<(FrameId, usize)> {
+        let lips = self.lips.as_ref()?;
+        let elapsed = self.now?.saturating_duration_since(self.lips_start?).as_millis() as u32;
+        let phoneme = lips.phoneme_at(elapsed)?;
+        let frame_idx = *PHONEME_TO_FRAME.get(phoneme as usize)? as usize;
+        let fid = self.fid(self.mood.phoneme_anim(), 0)?;
+        let frame_count = self.frm_db.get(fid).ok()?.frame_lists[Direction::NE].frames.len();
+        Some((fid, frame_idx.min(frame_count.saturating_sub(1))))
+    }
+
+    fn update(&mut self, now: Instant) {
+        self.now = Some(now);
+        if self.lips.is_some() {
+            let start = *self.lips_start.get_or_insert(now);
+            let elapsed = now.saturating_duration_since(start).as_millis() as u32;
+            if elapsed > self.lips.as_ref().unwrap().duration_millis() {
+                self.stop_speaking();
+            }
+        }
+
+        if self.playing.is_none() {
+            if let Some(fid) = self.queue.pop_front() {
+                self.playing = Some(Playing { fid, frame_idx: 0, last_frame: None });
+            } else if self.lips.is_none() {
+                let next_fidget = *self.next_fidget.get_or_insert_with(||
+                    now + Duration::from_millis(random(3000, 8000) as u64));
+                if now >= next_fidget {
+                    self.next_fidget = None;
+                    if let Some(fid) = self.random_fidget() {
+                        self.playing = Some(Playing { fid, frame_idx: 0, last_frame: None });
+                    }
+                }
+            }
+        }
+
+        if let Some(playing) = &mut self.playing {
+            let Ok(frms) = self.frm_db.get(playing.fid) else {
+                self.playing = None;
+                return;
+            };
+            let fps = frms.fps.max(1) as u32;
+            let last_frame = *playing.last_frame.get_or_insert(now);
+            if now >= last_frame + Duration::from_millis(1000 / fps as u64) {
+                playing.last_frame = Some(now);
+                playing.frame_idx += 1;
+                if playing.frame_idx >= frms.frame_lists[Direction::NE].frames.len() {
+                    self.playing = None;
+                }
+            }
+        }
+    }
+}
+
+impl Widget for TalkingHead {
+    fn handle_event(&mut self, ctx: HandleEvent) {
+        if let Event::Tick = ctx.event {
+            self.update(ctx.now);
+        }
+    }
+
+    fn render(&mut self, ctx: Render) {
+        let (fid, frame_idx) = if let Some(v) = self.phoneme() {
+            v
+        } else if let Some(playing) = &self.playing {
+            (playing.fid, playing.frame_idx)
+        } else if let Some(fid) = self.idle_fid() {
+            (fid, 0)
+        } else {
+            return;
+        };
+        let rect = ctx.base.unwrap().rect();
+        let mut sprite = Sprite::new_with_pos(fid, rect.center());
+        sprite.anchor = Anchor::Center;
+        sprite.frame_idx = frame_idx;
+        ctx.canvas.set_clip_rect(rect);
+        sprite.render(ctx.canvas, ctx.frm_db);
+        ctx.canvas.reset_clip_rect();
+    }
+}
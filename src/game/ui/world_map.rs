@@ -0,0 +1,135 @@
+use bstring::BString;
+
+use crate::asset::frame::FrameId;
+use crate::game::worldmap::{SUBTILE_SIZE, TILE_HEIGHT, TILE_WIDTH};
+use crate::graphics::{Point, Rect};
+use crate::graphics::color::{BLACK, GREEN};
+use crate::graphics::font::{self, FontKey, HorzAlign};
+use crate::graphics::render::{TextureFactory, TextureHandle};
+use crate::graphics::sprite::{Anchor, Sprite};
+use crate::ui::*;
+use crate::ui::command::UiCommandData;
+use crate::ui::command::world_map::Command;
+
+const AREA_NAME_FONT: FontKey = FontKey::antialiased(1);
+
+pub struct AreaMark {
+    pub pos: Point,
+    pub radius: i32,
+    pub name: BString,
+}
+
+/// Scrollable view of the world map tiles centered on the party. Undiscovered subtiles are
+/// covered with black. Clicking the view emits `world_map::Command::Travel`.
+pub struct WorldMapView {
+    tiles: Vec<FrameId>,
+    tile_cols: i32,
+    /// Discovered subtiles in row-major order.
+    seen: Vec<bool>,
+    seen_cols: i32,
+    areas: Vec<AreaMark>,
+    party_pos: Point,
+    dest: Option<Point>,
+    texture_factory: TextureFactory,
+    fog_mask: Option<TextureHandle>,
+}
+
+impl WorldMapView {
+    pub fn new(tiles: Vec<FrameId>, tile_cols: i32, texture_factory: TextureFactory) -> Self {
+        Self {
+            tiles,
+            tile_cols,
+            seen: Vec::new(),
+            seen_cols: 1,
+            areas: Vec::new(),
+            party_pos: Point::new(0, 0),
+            dest: None,
+            texture_factory,
+            fog_mask: None,
+        }
+    }
+
+    pub fn set_party_pos(&mut self, pos: Point, dest: Option<Point>) {
+        self.party_pos = pos;
+        self.dest = dest;
+    }
+
+    pub fn set_seen(&mut self, seen: Vec<bool>, cols: i32) {
+        self.seen = seen;
+        self.seen_cols = cols;
+    }
+
+    pub fn set_areas(&mut self, areas: Vec<AreaMark>) {
+        self.areas = areas;
+    }
+
+    fn world_size(&self) -> Point {
+        Point::new(
+            self.tile_cols * TILE_WIDTH,
+            self.tiles.len() as i32 / self.tile_cols * TILE_HEIGHT)
+    }
+
+    /// Returns world position of the view's top left corner.
+    fn origin(&self, rect: Rect) -> Point {
+        let size = self.world_size();
+        let origin = self.party_pos - Point::new(rect.width() / 2, rect.height() / 2);
+        Point::new(
+            origin.x.clamp(0, (size.x - rect.width()).max(0)),
+            origin.y.clamp(0, (size.y - rect.height()).max(0)))
+    }
+}
+
+impl Widget for WorldMapView {
+    fn handle_event(&mut self, mut ctx: HandleEvent) {
+        if let Event::MouseDown { pos, button: MouseButton::Left } = ctx.event {
+            let rect = ctx.base.rect();
+            let pos = pos - rect.top_left() + self.origin(rect);
+            ctx.out(UiCommandData::WorldMap(Command::Travel { pos }));
+        }
+    }
+
+    fn render(&mut self, ctx: Render) {
+        let rect = ctx.base.unwrap().rect();
+        let offset = rect.top_left() - self.origin(rect);
+
+        ctx.canvas.set_clip_rect(rect);
+
+        for (i, &fid) in self.tiles.iter().enumerate() {
+            let i = i as i32;
+            let pos = Point::new(i % self.tile_cols * TILE_WIDTH, i / self.tile_cols * TILE_HEIGHT)
+                + offset;
+            if rect.intersects(Rect::with_size(pos.x, pos.y, TILE_WIDTH, TILE_HEIGHT)) {
+                Sprite::new_with_pos(fid, pos).render(ctx.canvas, ctx.frm_db);
+            }
+        }
+
+        let fog_mask = self.fog_mask.get_or_insert_with(|| self.texture_factory.new_texture(
+            SUBTILE_SIZE, SUBTILE_SIZE, vec![7; (SUBTILE_SIZE * SUBTILE_SIZE) as usize].into()));
+        for (i, _) in self.seen.iter().enumerate().filter(|&(_, &seen)| !seen) {
+            let i = i as i32;
+            let pos = Point::new(i % self.seen_cols, i / self.seen_cols) * SUBTILE_SIZE + offset;
+            if rect.intersects(Rect::with_size(pos.x, pos.y, SUBTILE_SIZE, SUBTILE_SIZE)) {
+                ctx.canvas.draw_masked_color(BLACK, None, pos, fog_mask);
+            }
+        }
+
+        for area in &self.areas {
+            let pos = area.pos + offset + Point::new(0, area.radius + 2);
+            ctx.canvas.draw_text(&area.name, pos, AREA_NAME_FONT, GREEN, &font::DrawOptions {
+                horz_align: HorzAlign::Center,
+                ..Default::default()
+            });
+        }
+
+        if let Some(dest) = self.dest {
+            let mut sprite = Sprite::new_with_pos(FrameId::WMAPTARG, dest + offset);
+            sprite.anchor = Anchor::Center;
+            sprite.render(ctx.canvas, ctx.frm_db);
+        }
+        let mut sprite = Sprite::new_with_pos(FrameId::WMAPLOC, self.party_pos + offset);
+        sprite.anchor = Anchor::Center;
+        sprite.render(ctx.canvas, ctx.frm_db);
+
+        ctx.canvas.reset_clip_rect();
+    }
+}
@@ -27,7 +27,7 @@ use crate::util::array2d::Array2d;
 use floating_text::FloatingText;
 
 // scr_game_init()
-const START_GAME_TIME: GameTime = GameTime::from_decis(302400);
+pub const START_GAME_TIME: GameTime = GameTime::from_decis(302400);
 
 const MAX_FLOATING_TEXTS: usize = 19;
 
@@ -76,6 +76,10 @@ pub struct World {
 
     pub game_time: GameTime,
     pub ambient_light: u32,
+    /// Base time the floating texts are shown for. Each line adds 1.4 seconds.
+    pub text_delay: Duration,
+    /// Frames per second added to the object animations. Used to speed up combat.
+    pub anim_speedup: u32,
 }
 
 impl World {
@@ -110,6 +114,8 @@ impl World {
             fonts,
             game_time: START_GAME_TIME,
             ambient_light: 0x10000,
+            text_delay: Duration::from_millis(3_500),
+            anim_speedup: 0,
         }
     }
 
@@ -121,6 +127,13 @@ impl World {
         &self.frm_db
     }
 
+    /// Returns duration of a single frame of the object animation `fid`.
+    // compute_tpf()
+    pub fn frame_len(&self, fid: FrameId) -> Duration {
+        let fps = self.frm_db.get(fid).unwrap().fps as u32 + self.anim_speedup;
+        Duration::from_millis(1000 / fps as u64)
+    }
+
     pub fn hex_grid(&self) -> &hex::TileGrid {
         &self.hex_grid
     }
@@ -149,6 +162,10 @@ impl World {
         self.floating_texts.clear();
     }
 
+    pub fn sqr_tiles(&self) -> &[Option<Array2d<(u16, u16)>>] {
+        &self.sqr_tiles
+    }
+
     pub fn set_sqr_tiles(&mut self, sqr_tiles: Vec<Option<Array2d<(u16, u16)>>>) {
         assert_eq!(sqr_tiles.len(), ELEVATION_COUNT as usize);
         self.sqr_tiles = sqr_tiles;
@@ -360,9 +377,9 @@ impl World {
 
     fn expire_floating_texts(&mut self) {
         let update_time = self.update_time;
+        let text_delay = self.text_delay;
         self.floating_texts.retain(|ft| {
-            let expires_at = ft.expires_at(Duration::from_millis(3_500),
-                Duration::from_millis(1_400));
+            let expires_at = ft.expires_at(text_delay, Duration::from_millis(1_400));
             expires_at > update_time
         })
     }
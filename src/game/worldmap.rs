@@ -0,0 +1,1339 @@
+use bstring::BString;
+use linearize::{static_map, Linearize, StaticMap};
+use log::*;
+use num_traits::FromPrimitive;
+use std::collections::HashMap;
+use std::io::{self, BufRead, Error, ErrorKind};
+
+use crate::asset::EntityKind;
+use crate::asset::frame::FrameId;
+use crate::asset::proto::ProtoId;
+use crate::asset::read_ini;
+use crate::asset::script::ProgramId;
+use crate::fs::FileSystem;
+use crate::game::GameTime;
+use crate::game::ui::world_map::{AreaMark, WorldMapView};
+use crate::graphics::{EPoint, Point, Rect};
+use crate::graphics::color::Rgb15;
+use crate::graphics::font::{FontKey, VertAlign};
+use crate::graphics::geometry::hex::{Direction, TileGrid};
+use crate::graphics::sprite::Sprite;
+use crate::ui::{self, Ui};
+use crate::ui::button::{self, Button};
+use crate::ui::command::UiCommandData;
+use crate::ui::command::world_map::Command;
+use crate::ui::panel::Panel;
+use crate::util::EnumExt;
+use crate::util::random::random;
+
+pub const TILE_WIDTH: i32 = 350;
+pub const TILE_HEIGHT: i32 = 300;
+pub const SUBTILE_SIZE: i32 = 50;
+pub const SUBTILE_COLS: i32 = TILE_WIDTH / SUBTILE_SIZE;
+pub const SUBTILE_ROWS: i32 = TILE_HEIGHT / SUBTILE_SIZE;
+
+/// Game time spent by the party to travel one pixel of terrain with difficulty 1.
+const STEP_TIME: u32 = 1500;
+
+/// Radius in subtiles around the party that gets discovered while traveling.
+const SEEN_RADIUS: i32 = 1;
+
+/// How many random positions are tried for each critter of an encounter group.
+const PLACEMENT_ATTEMPTS: u32 = 25;
+
+const VIEWPORT: Rect = Rect { left: 22, top: 21, right: 22 + 450, bottom: 21 + 443 };
+const MAX_AREA_BUTTONS: usize = 7;
+
+const TEXT_FONT: FontKey = FontKey::antialiased(3);
+const TEXT_COLOR: Rgb15 = unsafe { Rgb15::rgb15_from_packed_unchecked(0x4a23) };
+
+fn invalid_data(msg: String) -> Error {
+    Error::new(ErrorKind::InvalidData, msg)
+}
+
+#[derive(Clone, Copy, Debug, Eq, Linearize, PartialEq)]
+pub enum DayPart {
+    Morning,
+    Afternoon,
+    Night,
+}
+
+impl DayPart {
+    pub fn of(time: GameTime) -> Self {
+        match time.hour() {
+            6..=11 => Self::Morning,
+            12..=17 => Self::Afternoon,
+            _ => Self::Night,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Terrain {
+    pub name: String,
+    /// How many times slower the party moves through this terrain.
+    pub difficulty: u32,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct SubTile {
+    /// Index into `WorldMap::terrains`.
+    pub terrain: usize,
+    /// Chance of random encounter per hour of travel, in percents.
+    pub encounter_chance: StaticMap<DayPart, u32>,
+    /// Index into `WorldMap::encounter_tables`.
+    pub encounter_table: Option<usize>,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Tile {
+    pub fid: FrameId,
+    /// `SUBTILE_COLS` x `SUBTILE_ROWS` subtiles in row-major order.
+    pub subtiles: Vec<SubTile>,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct EncounterTable {
+    pub lookup_name: String,
+    /// Lookup names of the maps where the encounter can take place.
+    pub maps: Vec<String>,
+    pub encounters: Vec<Encounter>,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Encounter {
+    /// Relative chance of this encounter within the table.
+    pub chance: u32,
+    /// Overrides the table maps.
+    pub map: Option<String>,
+    pub groups: Vec<EncounterGroup>,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct EncounterGroup {
+    pub min_count: u32,
+    pub max_count: u32,
+    /// Lowercase name of the `[Encounter: <name>]` section.
+    pub critters: String,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct EncounterCritter {
+    /// Relative chance of this critter in the group.
+    pub ratio: u32,
+    pub pid: ProtoId,
+    /// Overrides the script of the critter proto.
+    pub script: Option<ProgramId>,
+}
+
+/// Arrangement of the encounter group critters relative to the dude.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Formation {
+    /// Each critter is placed at random around the dude.
+    Surrounding,
+    StraightLine,
+    DoubleLine,
+    /// Line bent away from the dude.
+    Wedge,
+    /// Line bent towards the dude.
+    Cone,
+    Huddle,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Placement {
+    pub formation: Formation,
+    /// Distance in hexes between the adjacent critters of the formation.
+    pub spacing: u32,
+    /// Distance in hexes from the dude to the formation. If `None` it's based on the dude's
+    /// perception.
+    pub distance: Option<u32>,
+}
+
+impl Default for Placement {
+    fn default() -> Self {
+        Self {
+            formation: Formation::Surrounding,
+            spacing: 1,
+            distance: None,
+        }
+    }
+}
+
+/// Critters of `[Encounter: <name>]` section.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct CritterGroup {
+    pub placement: Placement,
+    pub critters: Vec<EncounterCritter>,
+}
+
+#[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
+pub enum AreaState {
+    Unknown,
+    Known,
+    Visited,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Entrance {
+    pub known: bool,
+    /// Position of the entrance hotspot on the town map.
+    pub town_map_pos: Point,
+    /// Lookup name of the map.
+    pub map: String,
+    /// `None` if the map's default entrance should be used.
+    pub pos: Option<EPoint>,
+    pub direction: Direction,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Area {
+    pub name: String,
+    pub pos: Point,
+    pub radius: i32,
+    pub state: AreaState,
+    pub town_map: Option<FrameId>,
+    pub entrances: Vec<Entrance>,
+}
+
+impl Area {
+    pub fn contains(&self, pos: Point) -> bool {
+        let d = pos - self.pos;
+        d.x * d.x + d.y * d.y <= self.radius * self.radius
+    }
+}
+
+/// Result of the party travel step.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum TravelEvent {
+    /// Party reached the destination. Contains the area if the destination is inside a known one.
+    Arrived {
+        area: Option<u32>,
+    },
+    Encounter(EncounterSpawn),
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct EncounterSpawn {
+    /// Lookup name of the encounter map.
+    pub map: String,
+    /// Critters picked for each of the encounter groups.
+    pub groups: Vec<CritterGroup>,
+}
+
+/// World map data from `data/worldmap.txt` and `data/city.txt` along with the party travel state.
+pub struct WorldMap {
+    terrains: Vec<Terrain>,
+    tile_cols: i32,
+    tiles: Vec<Tile>,
+    encounter_tables: Vec<EncounterTable>,
+    critters: HashMap<String, CritterGroup>,
+    areas: Vec<Area>,
+    /// Discovered subtiles.
+    seen: Vec<bool>,
+    pos: Point,
+    dest: Option<Point>,
+    step_progress: u32,
+    current_area: Option<u32>,
+    map_last_visit: GameTime,
+    show_requested: bool,
+    window: Option<ui::Handle>,
+    view: Option<ui::Handle>,
+    /// Area which town map is shown instead of the world map.
+    town_map: Option<u32>,
+}
+
+impl WorldMap {
+    /// Fallout 1 doesn't have the world map data files and gets an empty world map.
+    pub fn new(fs: &FileSystem) -> io::Result<Self> {
+        if fs.exists("data/worldmap.txt") {
+            Self::read(
+                &mut fs.reader("data/worldmap.txt")?,
+                &mut fs.reader("data/city.txt")?)
+        } else {
+            Ok(Self::with_data(Vec::new(), 1, Vec::new(), Vec::new(), HashMap::new(), Vec::new()))
+        }
+    }
+
+    fn read(worldmap: &mut impl BufRead, city: &mut impl BufRead) -> io::Result<Self> {
+        let ini = read_ini(worldmap)?;
+        let empty = HashMap::new();
+
+        let data = ini.get("Data").unwrap_or(&empty);
+        let terrains = data.get("terrain_types")
+            .ok_or_else(|| invalid_data("missing terrain_types".into()))?
+            .split(',')
+            .map(|s| {
+                let (name, difficulty) = s.split_once(':').unwrap_or((s, "1"));
+                let difficulty = difficulty.trim().parse::<u32>()
+                    .map_err(|_| invalid_data(format!("bad terrain difficulty: {}", s)))?;
+                Ok(Terrain {
+                    name: name.trim().into(),
+                    difficulty: difficulty.max(1),
+                })
+            })
+            .collect::<io::Result<Vec<_>>>()?;
+        let tile_cols: i32 = data.get("num_horizontal_tiles")
+            .and_then(|s| s.parse().ok())
+            .filter(|&v| v > 0)
+            .ok_or_else(|| invalid_data("missing or bad num_horizontal_tiles".into()))?;
+
+        let frequencies: HashMap<String, u32> = ini.get("Random Encounter Frequency")
+            .unwrap_or(&empty)
+            .iter()
+            .map(|(k, v)| Ok((k.to_ascii_lowercase(), parse_percent(v)?)))
+            .collect::<io::Result<_>>()?;
+
+        let mut encounter_tables = Vec::new();
+        for i in 0.. {
+            let Some(section) = ini.get(&format!("Encounter Table {}", i)) else { break };
+            encounter_tables.push(parse_encounter_table(section)?);
+        }
+
+        let mut critters = HashMap::new();
+        for (name, section) in &ini {
+            let Some(name) = name.strip_prefix("Encounter:") else { continue };
+            let placement = section.get("position")
+                .map(|s| parse_placement(s))
+                .transpose()?
+                .unwrap_or_default();
+            let mut list = Vec::new();
+            for i in 0..100 {
+                if let Some(s) = section.get(&format!("type_{:02}", i)) {
+                    list.extend(parse_encounter_critter(s)?);
+                }
+            }
+            critters.insert(name.trim().to_ascii_lowercase(), CritterGroup {
+                placement,
+                critters: list,
+            });
+        }
+
+        let mut tiles = Vec::new();
+        for i in 0.. {
+            let Some(section) = ini.get(&format!("Tile {}", i)) else { break };
+            let fid = section.get("art_idx")
+                .and_then(|s| s.parse().ok())
+                .and_then(|idx| FrameId::new_generic(EntityKind::Interface, idx))
+                .ok_or_else(|| invalid_data(format!("missing or bad art_idx in tile {}", i)))?;
+            let mut subtiles = Vec::with_capacity((SUBTILE_COLS * SUBTILE_ROWS) as usize);
+            for y in 0..SUBTILE_ROWS {
+                for x in 0..SUBTILE_COLS {
+                    let s = section.get(&format!("{}_{}", x, y))
+                        .ok_or_else(|| invalid_data(format!("missing subtile {}_{} in tile {}",
+                            x, y, i)))?;
+                    subtiles.push(parse_subtile(s, &terrains, &frequencies, &encounter_tables)?);
+                }
+            }
+            tiles.push(Tile {
+                fid,
+                subtiles,
+            });
+        }
+        if tiles.is_empty() || tiles.len() as i32 % tile_cols != 0 {
+            return Err(invalid_data(format!("bad number of tiles: {}", tiles.len())));
+        }
+
+        let areas = read_areas(city)?;
+
+        Ok(Self::with_data(terrains, tile_cols, tiles, encounter_tables, critters, areas))
+    }
+
+    fn with_data(
+        terrains: Vec<Terrain>,
+        tile_cols: i32,
+        tiles: Vec<Tile>,
+        encounter_tables: Vec<EncounterTable>,
+        critters: HashMap<String, CritterGroup>,
+        areas: Vec<Area>,
+    ) -> Self {
+        let seen = vec![false; tiles.len() * (SUBTILE_COLS * SUBTILE_ROWS) as usize];
+        Self {
+            terrains,
+            tile_cols,
+            tiles,
+            encounter_tables,
+            critters,
+            areas,
+            seen,
+            pos: Point::new(0, 0),
+            dest: None,
+            step_progress: 0,
+            current_area: None,
+            map_last_visit: GameTime::from_decis(0),
+            show_requested: false,
+            window: None,
+            view: None,
+            town_map: None,
+        }
+    }
+
+    /// World map size in pixels.
+    pub fn size(&self) -> Point {
+        Point::new(
+            self.tile_cols * TILE_WIDTH,
+            self.tiles.len() as i32 / self.tile_cols * TILE_HEIGHT)
+    }
+
+    pub fn pos(&self) -> Point {
+        self.pos
+    }
+
+    pub fn area(&self, id: u32) -> Option<&Area> {
+        self.areas.get(id as usize)
+    }
+
+    pub fn current_area(&self) -> Option<u32> {
+        self.current_area
+    }
+
+    pub fn terrain(&self, pos: Point) -> Option<&Terrain> {
+        self.subtile(pos).map(|s| &self.terrains[s.terrain])
+    }
+
+    pub fn subtile(&self, pos: Point) -> Option<&SubTile> {
+        let (tile, subtile) = self.subtile_idx(pos)?;
+        Some(&self.tiles[tile].subtiles[subtile])
+    }
+
+    fn subtile_idx(&self, pos: Point) -> Option<(usize, usize)> {
+        let size = self.size();
+        if pos.x < 0 || pos.y < 0 || pos.x >= size.x || pos.y >= size.y {
+            return None;
+        }
+        let tile = pos.y / TILE_HEIGHT * self.tile_cols + pos.x / TILE_WIDTH;
+        let subtile = pos.y % TILE_HEIGHT / SUBTILE_SIZE * SUBTILE_COLS
+            + pos.x % TILE_WIDTH / SUBTILE_SIZE;
+        Some((tile as usize, subtile as usize))
+    }
+
+    /// Index into `seen` of the subtile at column `x` and row `y` of the whole world map.
+    fn seen_idx(&self, x: i32, y: i32) -> Option<usize> {
+        let cols = self.tile_cols * SUBTILE_COLS;
+        let rows = self.tiles.len() as i32 / self.tile_cols * SUBTILE_ROWS;
+        if x < 0 || y < 0 || x >= cols || y >= rows {
+            return None;
+        }
+        Some((y * cols + x) as usize)
+    }
+
+    pub fn is_seen(&self, pos: Point) -> bool {
+        self.seen_idx(pos.x.div_euclid(SUBTILE_SIZE), pos.y.div_euclid(SUBTILE_SIZE))
+            .is_some_and(|i| self.seen[i])
+    }
+
+    // wmMarkSubTileRadiusVisited()
+    fn mark_seen(&mut self, pos: Point, radius: i32) {
+        let (cx, cy) = (pos.x.div_euclid(SUBTILE_SIZE), pos.y.div_euclid(SUBTILE_SIZE));
+        for y in cy - radius..=cy + radius {
+            for x in cx - radius..=cx + radius {
+                if let Some(i) = self.seen_idx(x, y) {
+                    self.seen[i] = true;
+                }
+            }
+        }
+    }
+
+    /// Finds the area having entrance to the map with `map_lookup_name`.
+    pub fn area_of_map(&self, map_lookup_name: &str) -> Option<u32> {
+        self.areas.iter()
+            .position(|a| a.entrances.iter().any(|e| e.map.eq_ignore_ascii_case(map_lookup_name)))
+            .map(|i| i as u32)
+    }
+
+    // wmAreaSetVisibleState()
+    pub fn set_area_state(&mut self, id: u32, state: AreaState) -> bool {
+        if let Some(area) = self.areas.get_mut(id as usize) {
+            area.state = state;
+            if state != AreaState::Unknown {
+                let (pos, radius) = (area.pos, area.radius);
+                self.mark_seen(pos, (radius + SUBTILE_SIZE - 1) / SUBTILE_SIZE);
+            }
+            true
+        } else {
+            false
+        }
+    }
+
+    // wmMapMarkVisited()
+    /// Sets known state of the entrances to the map with `map_lookup_name`.
+    pub fn set_map_known(&mut self, map_lookup_name: &str, known: bool) -> bool {
+        let mut found = false;
+        for entrance in self.areas.iter_mut().flat_map(|a| &mut a.entrances) {
+            if entrance.map.eq_ignore_ascii_case(map_lookup_name) {
+                entrance.known = known;
+                found = true;
+            }
+        }
+        found
+    }
+
+    // wmAreaSetWorldPos()
+    pub fn set_area_pos(&mut self, id: u32, pos: Point) -> bool {
+        if let Some(area) = self.areas.get_mut(id as usize) {
+            area.pos = pos;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Called when the party enters a map. `last_visit` is the time the map was last left,
+    /// or zero if it's never been visited.
+    pub fn enter_map(&mut self, map_lookup_name: &str, last_visit: GameTime) {
+        self.map_last_visit = last_visit;
+        self.current_area = self.area_of_map(map_lookup_name);
+        if let Some(id) = self.current_area {
+            self.set_area_state(id, AreaState::Visited);
+            let pos = self.areas[id as usize].pos;
+            self.set_pos(pos);
+        }
+    }
+
+    /// Number of whole days passed since the current map was last visited.
+    pub fn days_since_visited(&self, now: GameTime) -> u32 {
+        now.as_decis().saturating_sub(self.map_last_visit.as_decis()) / GameTime::DAY.as_decis()
+    }
+
+    pub fn set_pos(&mut self, pos: Point) {
+        let size = self.size();
+        self.pos = Point::new(pos.x.clamp(0, size.x - 1), pos.y.clamp(0, size.y - 1));
+        self.dest = None;
+        self.step_progress = 0;
+        self.mark_seen(self.pos, SEEN_RADIUS);
+    }
+
+    pub fn set_destination(&mut self, dest: Option<Point>) {
+        self.dest = dest.map(|p| {
+            let size = self.size();
+            Point::new(p.x.clamp(0, size.x - 1), p.y.clamp(0, size.y - 1))
+        });
+    }
+
+    /// Requests the world map to be shown. The request is picked up with `take_show_request()`.
+    pub fn request_show(&mut self) {
+        self.show_requested = true;
+    }
+
+    pub fn take_show_request(&mut self) -> bool {
+        std::mem::replace(&mut self.show_requested, false)
+    }
+
+    pub fn is_traveling(&self) -> bool {
+        self.dest.is_some()
+    }
+
+    // wmPartyWalkingStep()
+    /// Moves the party one step towards the destination advancing the `game_time`.
+    pub fn step(&mut self, game_time: &mut GameTime) -> Option<TravelEvent> {
+        let dest = self.dest?;
+
+        let difficulty = self.terrain(self.pos).map(|t| t.difficulty).unwrap_or(1);
+        self.step_progress += 1;
+        if self.step_progress >= difficulty {
+            self.step_progress = 0;
+            let d = dest - self.pos;
+            // Move diagonally until aligned with the destination on one axis.
+            let pos = self.pos + Point::new(d.x.signum(), d.y.signum());
+            self.pos = pos;
+            self.mark_seen(pos, SEEN_RADIUS);
+        }
+
+        let prev_time = *game_time;
+        *game_time = game_time.add_decis(STEP_TIME);
+
+        if self.pos == dest {
+            self.dest = None;
+            let area = self.areas.iter()
+                .position(|a| a.state != AreaState::Unknown && a.contains(dest))
+                .map(|i| i as u32);
+            return Some(TravelEvent::Arrived { area });
+        }
+
+        if game_time.as_hours() != prev_time.as_hours()
+            && let Some(spawn) = self.roll_encounter(*game_time)
+        {
+            self.dest = None;
+            return Some(TravelEvent::Encounter(spawn));
+        }
+
+        None
+    }
+
+    // wmRndEncounterOccurred()
+    fn roll_encounter(&self, game_time: GameTime) -> Option<EncounterSpawn> {
+        // No encounters near known places.
+        if self.areas.iter().any(|a| a.state != AreaState::Unknown && a.contains(self.pos)) {
+            return None;
+        }
+        let subtile = self.subtile(self.pos)?;
+        let chance = subtile.encounter_chance[DayPart::of(game_time)] as i32;
+        if random(1, 100) > chance {
+            return None;
+        }
+        let table = &self.encounter_tables[subtile.encounter_table?];
+        let encounter = pick_weighted(&table.encounters, |e| e.chance)?;
+        let map = if let Some(map) = &encounter.map {
+            map.clone()
+        } else if table.maps.is_empty() {
+            warn!("no maps in encounter table {}", table.lookup_name);
+            return None;
+        } else {
+            table.maps[random(0, table.maps.len() as i32 - 1) as usize].clone()
+        };
+
+        let mut groups = Vec::new();
+        for group in &encounter.groups {
+            let Some(kinds) = self.critters.get(&group.critters) else {
+                warn!("unknown encounter critter group: {}", group.critters);
+                continue;
+            };
+            let count = random(group.min_count as i32, group.max_count as i32);
+            let critters = (0..count)
+                .filter_map(|_| pick_weighted(&kinds.critters, |c| c.ratio).copied())
+                .collect();
+            groups.push(CritterGroup {
+                placement: kinds.placement,
+                critters,
+            });
+        }
+        debug!("random encounter from table `{}` on map `{}`: {:?}",
+            table.lookup_name, map, groups);
+
+        Some(EncounterSpawn {
+            map,
+            groups,
+        })
+    }
+
+    pub fn is_visible(&self) -> bool {
+        self.window.is_some()
+    }
+
+    // wmWorldMapFunc()
+    pub fn show(&mut self, ui: &mut Ui) {
+        assert!(self.window.is_none());
+
+        let window = self.new_window(ui);
+
+        let tiles = self.tiles.iter().map(|t| t.fid).collect();
+        let view = WorldMapView::new(tiles, self.tile_cols, ui.frm_db().texture_factory().clone());
+        let view = ui.new_widget(window, VIEWPORT, None, None, view);
+
+        self.window = Some(window);
+        self.view = Some(view);
+        self.sync_view(ui);
+    }
+
+    // wmTownMapFunc()
+    /// Shows the town map of the current area with hotspots of the known entrances.
+    /// Returns `false` if the party isn't in an area or the area has no town map.
+    pub fn show_town_map(&mut self, ui: &mut Ui) -> bool {
+        assert!(self.window.is_none());
+
+        let Some(id) = self.current_area else { return false };
+        let area = &self.areas[id as usize];
+        let Some(fid) = area.town_map else { return false };
+
+        let window = self.new_window(ui);
+        ui.new_widget(window, VIEWPORT, None, Some(Sprite::new(fid)), Panel::new());
+
+        let btn_size = ui.frm_db().get(FrameId::HOTSPOT1).unwrap().first().size();
+        for (i, entrance) in area.entrances.iter().enumerate().filter(|(_, e)| e.known) {
+            let mut btn = Button::new(FrameId::HOTSPOT1, FrameId::HOTSPOT2,
+                Some(UiCommandData::WorldMap(Command::Entrance { area: id, entrance: i as u32 })));
+            let mut text = button::Text::new(entrance.map.clone().into(), TEXT_FONT);
+            text.pos = Point::new(btn_size.x + 3, 0);
+            text.color = TEXT_COLOR;
+            text.options.vert_align = VertAlign::Middle;
+            btn.set_text(Some(text));
+            let pos = VIEWPORT.top_left() + entrance.town_map_pos;
+            ui.new_widget(window, Rect::with_size(pos.x, pos.y, btn_size.x, btn_size.y),
+                None, None, btn);
+        }
+
+        self.window = Some(window);
+        self.town_map = Some(id);
+        true
+    }
+
+    pub fn is_town_map_visible(&self) -> bool {
+        self.town_map.is_some()
+    }
+
+    /// Creates the world map window with buttons of the known areas.
+    fn new_window(&self, ui: &mut Ui) -> ui::Handle {
+        let window = ui.new_window(Rect::with_size(0, 0, 640, 480),
+            Some(Sprite::new(FrameId::WMAPBOX)));
+        ui.widget_base_mut(window).set_modal(true);
+
+        let btn_size = ui.frm_db().get(FrameId::SMALL_RED_BUTTON_UP).unwrap().first().size();
+        let known_areas = self.areas.iter().enumerate()
+            .filter(|(_, a)| a.state != AreaState::Unknown)
+            .take(MAX_AREA_BUTTONS);
+        for (i, (id, area)) in known_areas.enumerate() {
+            let mut btn = Button::new(FrameId::SMALL_RED_BUTTON_UP, FrameId::SMALL_RED_BUTTON_DOWN,
+                Some(UiCommandData::WorldMap(Command::Area { id: id as u32 })));
+            let mut text = button::Text::new(area.name.clone().into(), TEXT_FONT);
+            text.pos = Point::new(btn_size.x + 6, 1);
+            text.color = TEXT_COLOR;
+            text.options.vert_align = VertAlign::Middle;
+            btn.set_text(Some(text));
+            ui.new_widget(window, Rect::with_size(500, 138 + 27 * i as i32, 120, btn_size.y),
+                None, None, btn);
+        }
+
+        window
+    }
+
+    pub fn hide(&mut self, ui: &mut Ui) {
+        let window = self.window.take().unwrap();
+        self.view = None;
+        self.town_map = None;
+        ui.remove(window);
+    }
+
+    /// Updates the world map view to reflect the current state.
+    pub fn sync_view(&self, ui: &mut Ui) {
+        let Some(view) = self.view else { return };
+        let mut view = ui.widget_mut::<WorldMapView>(view);
+        view.set_party_pos(self.pos, self.dest);
+        view.set_seen(self.seen.clone(), self.tile_cols * SUBTILE_COLS);
+        view.set_areas(self.areas.iter()
+            .filter(|a| a.state != AreaState::Unknown)
+            .map(|a| AreaMark {
+                pos: a.pos,
+                radius: a.radius,
+                name: BString::from(a.name.clone()),
+            })
+            .collect());
+    }
+
+    pub fn entrance(&self, area: u32, entrance: u32) -> Option<&Entrance> {
+        self.areas.get(area as usize)?.entrances.get(entrance as usize)
+    }
+
+    /// Returns the entrance to use when entering the `area`.
+    pub fn area_entrance(&self, area: u32) -> Option<&Entrance> {
+        let area = self.areas.get(area as usize)?;
+        area.entrances.iter().find(|e| e.known)
+            .or_else(|| area.entrances.first())
+    }
+}
+
+fn pick_weighted<T>(items: &[T], weight: impl Fn(&T) -> u32) -> Option<&T> {
+    let total: u32 = items.iter().map(&weight).sum();
+    if total == 0 {
+        return items.first();
+    }
+    let mut roll = random(0, total as i32 - 1) as u32;
+    for item in items {
+        let w = weight(item);
+        if roll < w {
+            return Some(item);
+        }
+        roll -= w;
+    }
+    unreachable!()
+}
+
+// wmSetupCritterObjs()
+/// Finds positions for `count` critters arranged according to the `placement` around the dude
+/// at `center`. `distance` is used when the placement doesn't specify one. Critters that can't be
+/// placed on a free hex are left out.
+pub fn place_critters(
+    placement: Placement,
+    count: usize,
+    center: Point,
+    distance: u32,
+    hex_grid: &TileGrid,
+    is_free: impl Fn(Point) -> bool,
+) -> Vec<Point> {
+    let distance = placement.distance.unwrap_or(distance);
+    let spacing = placement.spacing;
+    let direction = Direction::from_ordinal(random(0, Direction::len() as i32 - 1) as usize);
+    let rotate = |d: Direction, n: usize| Direction::from_ordinal((d.ordinal() + n) % Direction::len());
+    let origin = hex_grid.go_clipped(center, direction, distance);
+    // Direction from the formation towards the dude.
+    let facing = rotate(direction, 3);
+
+    let mut r: Vec<Point> = Vec::with_capacity(count);
+    for i in 0..count {
+        let pos = if placement.formation == Formation::Surrounding {
+            let base = hex_grid.go_clipped(center, rotate(direction, i), distance);
+            (0..PLACEMENT_ATTEMPTS)
+                .map(|_| {
+                    let dir = Direction::from_ordinal(
+                        random(0, Direction::len() as i32 - 1) as usize);
+                    hex_grid.go_clipped(base, dir, random(0, distance as i32 / 2) as u32)
+                })
+                .find(|&p| p != center && is_free(p) && !r.contains(&p))
+        } else {
+            // Critters are added alternately to the left and right of the formation origin.
+            let (n, row) = if placement.formation == Formation::DoubleLine {
+                (i / 2, i % 2)
+            } else {
+                (i, 0)
+            };
+            let offset = n.div_ceil(2) as u32 * spacing;
+            let left = n % 2 == 1;
+            let pos = match placement.formation {
+                Formation::StraightLine | Formation::DoubleLine => {
+                    let row_start = hex_grid.go_clipped(origin, direction, row as u32 * spacing);
+                    let dir = if left { rotate(facing, 5) } else { rotate(facing, 2) };
+                    hex_grid.go_clipped(row_start, dir, offset)
+                }
+                Formation::Wedge => {
+                    let dir = if left { rotate(direction, 5) } else { rotate(direction, 1) };
+                    hex_grid.go_clipped(origin, dir, offset)
+                }
+                Formation::Cone => {
+                    let dir = if left { rotate(facing, 5) } else { rotate(facing, 1) };
+                    hex_grid.go_clipped(origin, dir, offset)
+                }
+                Formation::Huddle => if let Some(&prev) = r.last() {
+                    hex_grid.go_clipped(prev, rotate(direction, i), spacing)
+                } else {
+                    origin
+                }
+                Formation::Surrounding => unreachable!(),
+            };
+            // Take the nearest free hex if the one in the formation is occupied.
+            (0..=spacing)
+                .flat_map(|d| Direction::iter().map(move |dir| (dir, d)))
+                .map(|(dir, d)| hex_grid.go_clipped(pos, dir, d))
+                .find(|&p| p != center && is_free(p) && !r.contains(&p))
+        };
+        if let Some(pos) = pos {
+            r.push(pos);
+        }
+    }
+    r
+}
+
+/// Parses key-value list of form `Key1:value1, Key2:value2, Flag, ...`.
+fn parse_props(s: &str) -> impl Iterator<Item=(String, &str)> {
+    s.split(',')
+        .map(|p| {
+            let (k, v) = p.split_once(':').unwrap_or((p, ""));
+            (k.trim().to_ascii_lowercase(), v.trim())
+        })
+}
+
+fn parse_percent(s: &str) -> io::Result<u32> {
+    s.trim_end_matches('%').trim().parse()
+        .map_err(|_| invalid_data(format!("bad percent value: {}", s)))
+}
+
+// wmParseSubTileInfo()
+fn parse_subtile(s: &str,
+    terrains: &[Terrain],
+    frequencies: &HashMap<String, u32>,
+    encounter_tables: &[EncounterTable],
+) -> io::Result<SubTile> {
+    let parts: Vec<_> = s.split(',').map(|s| s.trim()).collect();
+    if parts.len() != 6 {
+        return Err(invalid_data(format!("bad subtile: {}", s)));
+    }
+    let terrain = terrains.iter().position(|t| t.name.eq_ignore_ascii_case(parts[0]))
+        .ok_or_else(|| invalid_data(format!("unknown terrain: {}", parts[0])))?;
+    let frequency = |s: &str| frequencies.get(&s.to_ascii_lowercase()).copied()
+        .ok_or_else(|| invalid_data(format!("unknown encounter frequency: {}", s)));
+    let encounter_chance = static_map! {
+        DayPart::Morning => frequency(parts[2])?,
+        DayPart::Afternoon => frequency(parts[3])?,
+        DayPart::Night => frequency(parts[4])?,
+    };
+    let encounter_table = if parts[5].eq_ignore_ascii_case("none") {
+        None
+    } else {
+        Some(encounter_tables.iter()
+            .position(|t| t.lookup_name.eq_ignore_ascii_case(parts[5]))
+            .ok_or_else(|| invalid_data(format!("unknown encounter table: {}", parts[5])))?)
+    };
+    Ok(SubTile {
+        terrain,
+        encounter_chance,
+        encounter_table,
+    })
+}
+
+// wmParseEncounterTableIndex()
+fn parse_encounter_table(section: &HashMap<String, String>) -> io::Result<EncounterTable> {
+    let lookup_name = section.get("lookup_name")
+        .ok_or_else(|| invalid_data("missing lookup_name in encounter table".into()))?
+        .clone();
+    let maps = section.get("maps")
+        .map(|s| s.split(',').map(|s| s.trim().to_owned()).filter(|s| !s.is_empty()).collect())
+        .unwrap_or_default();
+    let mut encounters = Vec::new();
+    for i in 0..100 {
+        if let Some(s) = section.get(&format!("enc_{:02}", i)) {
+            encounters.push(parse_encounter(s)?);
+        }
+    }
+    Ok(EncounterTable {
+        lookup_name,
+        maps,
+        encounters,
+    })
+}
+
+// wmParseEncounterItemType()
+fn parse_encounter(s: &str) -> io::Result<Encounter> {
+    let mut chance = 0;
+    let mut map = None;
+    let mut groups = Vec::new();
+    for (k, v) in parse_props(s) {
+        match k.as_str() {
+            "chance" => chance = parse_percent(v)?,
+            "map" => map = Some(v.to_owned()),
+            "enc" => groups = parse_encounter_groups(v)
+                .ok_or_else(|| invalid_data(format!("bad encounter: {}", s)))?,
+            _ => {}
+        }
+    }
+    Ok(Encounter {
+        chance,
+        map,
+        groups,
+    })
+}
+
+/// Parses `(2-4) NAME1 AND (1) NAME2 FIGHTING (3-5) NAME3`.
+fn parse_encounter_groups(s: &str) -> Option<Vec<EncounterGroup>> {
+    let mut r = Vec::new();
+    let mut rest = s.trim();
+    while !rest.is_empty() {
+        let (count, tail) = rest.strip_prefix('(')?.split_once(')')?;
+        let (min_count, max_count) = if let Some((min, max)) = count.split_once('-') {
+            (min.trim().parse().ok()?, max.trim().parse().ok()?)
+        } else {
+            let c = count.trim().parse().ok()?;
+            (c, c)
+        };
+        if min_count > max_count {
+            return None;
+        }
+        let (critters, tail) = split_word(tail)?;
+        r.push(EncounterGroup {
+            min_count,
+            max_count,
+            critters: critters.to_ascii_lowercase(),
+        });
+        rest = match split_word(tail) {
+            Some((w, tail)) if w.eq_ignore_ascii_case("and") || w.eq_ignore_ascii_case("fighting") =>
+                tail,
+            Some(_) => return None,
+            None => "",
+        };
+    }
+    Some(r)
+}
+
+/// Splits off the first whitespace-separated word. Returns the word and the rest with leading
+/// whitespace removed.
+fn split_word(s: &str) -> Option<(&str, &str)> {
+    let s = s.trim_start();
+    if s.is_empty() {
+        return None;
+    }
+    let (word, rest) = s.split_once(char::is_whitespace).unwrap_or((s, ""));
+    Some((word, rest.trim_start()))
+}
+
+fn parse_encounter_critter(s: &str) -> io::Result<Option<EncounterCritter>> {
+    let mut ratio = 100;
+    let mut pid = None;
+    let mut script = None;
+    for (k, v) in parse_props(s) {
+        match k.as_str() {
+            "ratio" => ratio = parse_percent(v)?,
+            "pid" => pid = Some(v.parse::<u32>().ok()
+                .and_then(ProtoId::from_packed)
+                .ok_or_else(|| invalid_data(format!("bad pid: {}", v)))?),
+            "script" => script = v.parse::<i32>()
+                .map_err(|_| invalid_data(format!("bad script: {}", v)))
+                .map(|v| u32::try_from(v).ok().and_then(ProgramId::new))?,
+            _ => {}
+        }
+    }
+    Ok(pid.map(|pid| EncounterCritter {
+        ratio,
+        pid,
+        script,
+    }))
+}
+
+// wmReadEncBaseType()
+/// Parses `surrounding, spacing:3, distance:5`.
+fn parse_placement(s: &str) -> io::Result<Placement> {
+    let mut r = Placement::default();
+    for (k, v) in parse_props(s) {
+        let number = || v.parse::<u32>()
+            .map_err(|_| invalid_data(format!("bad {} in encounter position: {}", k, s)));
+        match k.as_str() {
+            "surrounding" => r.formation = Formation::Surrounding,
+            "straight_line" => r.formation = Formation::StraightLine,
+            "double_line" => r.formation = Formation::DoubleLine,
+            "wedge" => r.formation = Formation::Wedge,
+            "cone" => r.formation = Formation::Cone,
+            "huddle" => r.formation = Formation::Huddle,
+            "spacing" => r.spacing = number()?.max(1),
+            "distance" => r.distance = Some(number()?).filter(|&d| d > 0),
+            _ => return Err(invalid_data(format!("bad encounter position: {}", s))),
+        }
+    }
+    Ok(r)
+}
+
+// wmAreaInit()
+fn read_areas(rd: &mut impl BufRead) -> io::Result<Vec<Area>> {
+    let ini = read_ini(rd)?;
+    let mut areas = Vec::new();
+    for i in 0.. {
+        let Some(section) = ini.get(&format!("Area {:02}", i)) else { break };
+        let get = |k: &str| section.get(k)
+            .ok_or_else(|| invalid_data(format!("missing {} in area {}", k, i)));
+
+        let name = get("area_name")?.clone();
+        let pos = get("world_pos")?.split_once(',')
+            .and_then(|(x, y)| Some(Point::new(x.trim().parse().ok()?, y.trim().parse().ok()?)))
+            .ok_or_else(|| invalid_data(format!("bad world_pos in area {}", i)))?;
+        let state = if parse_on_off(get("start_state")?)? {
+            AreaState::Known
+        } else {
+            AreaState::Unknown
+        };
+        let town_map = section.get("townmap_art_idx")
+            .and_then(|s| s.trim().parse().ok())
+            .and_then(|idx| FrameId::new_generic(EntityKind::Interface, idx));
+        let radius = match get("size")?.to_ascii_lowercase().as_str() {
+            "small" => 5,
+            "medium" => 10,
+            "large" => 20,
+            s => return Err(invalid_data(format!("bad area size: {}", s))),
+        };
+
+        let mut entrances = Vec::new();
+        for j in 0..10 {
+            let Some(s) = section.get(&format!("entrance_{}", j)) else { continue };
+            entrances.push(parse_entrance(s)
+                .ok_or_else(|| invalid_data(format!("bad entrance: {}", s)))?);
+        }
+
+        areas.push(Area {
+            name,
+            pos,
+            radius,
+            state,
+            town_map,
+            entrances,
+        });
+    }
+    Ok(areas)
+}
+
+fn parse_on_off(s: &str) -> io::Result<bool> {
+    match s.trim().to_ascii_lowercase().as_str() {
+        "on" => Ok(true),
+        "off" => Ok(false),
+        _ => Err(invalid_data(format!("expected On/Off but found: {}", s))),
+    }
+}
+
+/// Parses `On,353,171,Arroyo Village,-1,-1,0`: state, town map position, map lookup name,
+/// elevation, tile number and direction.
+fn parse_entrance(s: &str) -> Option<Entrance> {
+    let parts: Vec<_> = s.split(',').map(|s| s.trim()).collect();
+    if parts.len() != 7 {
+        return None;
+    }
+    let known = parse_on_off(parts[0]).ok()?;
+    let town_map_pos = Point::new(parts[1].parse().ok()?, parts[2].parse().ok()?);
+    let map = parts[3].to_owned();
+    let elevation: i32 = parts[4].parse().ok()?;
+    let tile_num: i32 = parts[5].parse().ok()?;
+    let pos = if elevation >= 0 && tile_num >= 0 {
+        Some(EPoint::new(elevation as u32,
+            TileGrid::default().linear_to_rect_inv(tile_num as u32)))
+    } else {
+        None
+    };
+    let direction = parts[6].parse::<u32>().ok()
+        .and_then(Direction::from_u32)?;
+    Some(Entrance {
+        known,
+        town_map_pos,
+        map,
+        pos,
+        direction,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Cursor;
+    use crate::graphics::geometry::hex;
+
+    const WORLDMAP: &str = "
+[Data]
+terrain_types=Desert:1, Mountain:3
+num_horizontal_tiles=2
+
+[Random Encounter Frequency]
+Forced=100%
+Common=22
+None=0
+
+[Encounter Table 0]
+lookup_name=Desert
+maps=Desert Encounter 1, Desert Encounter 2
+enc_00=Chance:0%, Enc:(2-4) Geckos AND (1) Rats
+enc_01=Chance:100%, Counter:1, Enc:(3) Rats FIGHTING (1-1) Geckos
+
+[Encounter: Geckos]
+position=Wedge, distance:4
+type_00=Ratio:100%, pid:16777264, Item:(0-10)41, Script:312
+
+[Encounter: Rats]
+position=surrounding, spacing:3
+type_00=Ratio:100%, pid:16777265
+";
+
+    const CITY: &str = "
+[Area 00]
+area_name=Arroyo
+world_pos=100,100
+start_state=On
+size=Large
+townmap_art_idx=160
+entrance_0=On,353,171,Arroyo Village,0,12345,2
+entrance_1=Off,353,171,Arroyo Caves,-1,-1,0
+
+[Area 01]
+area_name=Klamath
+world_pos=500,50
+start_state=Off
+size=Small
+entrance_0=Off,1,1,Klamath Downtown,-1,-1,0
+";
+
+    fn tile(terrain: &str, freq: &str) -> String {
+        let mut s = "art_idx=339\n".to_owned();
+        for y in 0..SUBTILE_ROWS {
+            for x in 0..SUBTILE_COLS {
+                s += &format!("{}_{}={},No_Fill,{},{},{},Desert\n", x, y, terrain, freq, freq, freq);
+            }
+        }
+        s
+    }
+
+    fn world_map(freq: &str) -> WorldMap {
+        let mut s = WORLDMAP.to_owned();
+        s += &format!("[Tile 0]\n{}", tile("Desert", freq));
+        s += &format!("[Tile 1]\n{}", tile("mountain", freq));
+        WorldMap::read(&mut Cursor::new(s), &mut Cursor::new(CITY)).unwrap()
+    }
+
+    #[test]
+    fn read() {
+        let wm = world_map("Common");
+        assert_eq!(wm.terrains, vec![
+            Terrain { name: "Desert".into(), difficulty: 1 },
+            Terrain { name: "Mountain".into(), difficulty: 3 },
+        ]);
+        assert_eq!(wm.size(), Point::new(700, 300));
+        assert_eq!(wm.tiles[0].fid, FrameId::new_generic(EntityKind::Interface, 339).unwrap());
+        assert_eq!(wm.terrain(Point::new(349, 0)).unwrap().name, "Desert");
+        assert_eq!(wm.terrain(Point::new(350, 299)).unwrap().name, "Mountain");
+        assert!(wm.terrain(Point::new(700, 0)).is_none());
+        let st = wm.subtile(Point::new(10, 10)).unwrap();
+        assert_eq!(st.encounter_chance[DayPart::Night], 22);
+        assert_eq!(st.encounter_table, Some(0));
+
+        let table = &wm.encounter_tables[0];
+        assert_eq!(table.maps, vec!["Desert Encounter 1", "Desert Encounter 2"]);
+        assert_eq!(table.encounters[0], Encounter {
+            chance: 0,
+            map: None,
+            groups: vec![
+                EncounterGroup { min_count: 2, max_count: 4, critters: "geckos".into() },
+                EncounterGroup { min_count: 1, max_count: 1, critters: "rats".into() },
+            ],
+        });
+        assert_eq!(table.encounters[1].groups[0].min_count, 3);
+        assert_eq!(wm.critters["rats"], CritterGroup {
+            placement: Placement {
+                formation: Formation::Surrounding,
+                spacing: 3,
+                distance: None,
+            },
+            critters: vec![EncounterCritter {
+                ratio: 100,
+                pid: ProtoId::from_packed(16777265).unwrap(),
+                script: None,
+            }],
+        });
+        let geckos = &wm.critters["geckos"];
+        assert_eq!(geckos.placement, Placement {
+            formation: Formation::Wedge,
+            spacing: 1,
+            distance: Some(4),
+        });
+        assert_eq!(geckos.critters[0].script, ProgramId::new(312));
+
+        assert_eq!(wm.areas.len(), 2);
+        let arroyo = &wm.areas[0];
+        assert_eq!(arroyo.pos, Point::new(100, 100));
+        assert_eq!(arroyo.state, AreaState::Known);
+        assert_eq!(arroyo.radius, 20);
+        assert_eq!(arroyo.town_map, Some(FrameId::TWNDEN));
+        assert_eq!(arroyo.entrances[0].town_map_pos, Point::new(353, 171));
+        assert_eq!(arroyo.entrances[0].pos,
+            Some(EPoint::new(0, TileGrid::default().linear_to_rect_inv(12345))));
+        assert_eq!(arroyo.entrances[0].direction, Direction::SE);
+        assert_eq!(arroyo.entrances[1].pos, None);
+        assert!(!arroyo.entrances[1].known);
+        assert_eq!(wm.areas[1].state, AreaState::Unknown);
+        assert_eq!(wm.areas[1].town_map, None);
+        assert_eq!(wm.area_of_map("klamath downtown"), Some(1));
+    }
+
+    #[test]
+    fn parse_encounter_groups_() {
+        assert_eq!(parse_encounter_groups("(1)A"), Some(vec![
+            EncounterGroup { min_count: 1, max_count: 1, critters: "a".into() }]));
+        assert_eq!(parse_encounter_groups("(1-2) A and (3) B").unwrap().len(), 2);
+        assert_eq!(parse_encounter_groups("(2) BANDITS AND (1) RATS FIGHTING (3-4) HANDYMEN"),
+            Some(vec![
+                EncounterGroup { min_count: 2, max_count: 2, critters: "bandits".into() },
+                EncounterGroup { min_count: 1, max_count: 1, critters: "rats".into() },
+                EncounterGroup { min_count: 3, max_count: 4, critters: "handymen".into() },
+            ]));
+        assert_eq!(parse_encounter_groups("(2-1) A"), None);
+        assert_eq!(parse_encounter_groups("(1) A OR (2) B"), None);
+        assert_eq!(parse_encounter_groups("1 A"), None);
+    }
+
+    #[test]
+    fn travel() {
+        let mut wm = world_map("None");
+        wm.set_pos(Point::new(340, 10));
+        assert!(wm.is_seen(Point::new(340, 10)));
+        assert!(!wm.is_seen(Point::new(10, 250)));
+        wm.set_destination(Some(Point::new(352, 10)));
+
+        let mut time = GameTime::from_decis(0);
+        let mut steps = 0;
+        let event = loop {
+            steps += 1;
+            if let Some(e) = wm.step(&mut time) {
+                break e;
+            }
+        };
+        assert_eq!(event, TravelEvent::Arrived { area: None });
+        assert_eq!(wm.pos(), Point::new(352, 10));
+        assert!(!wm.is_traveling());
+        // 10 px of desert and 2 px of mountains.
+        assert_eq!(steps, 10 + 2 * 3);
+        assert_eq!(time.as_decis(), steps * STEP_TIME);
+
+        wm.set_destination(Some(Point::new(110, 95)));
+        let event = loop {
+            if let Some(e) = wm.step(&mut time) {
+                break e;
+            }
+        };
+        assert_eq!(event, TravelEvent::Arrived { area: Some(0) });
+    }
+
+    #[test]
+    fn encounter() {
+        let mut wm = world_map("Forced");
+        wm.set_pos(Point::new(300, 250));
+        wm.set_destination(Some(Point::new(0, 250)));
+        let mut time = GameTime::from_decis(0);
+        let event = loop {
+            if let Some(e) = wm.step(&mut time) {
+                break e;
+            }
+        };
+        let TravelEvent::Encounter(spawn) = event else { panic!("{:?}", event) };
+        assert!(spawn.map.starts_with("Desert Encounter"));
+        assert_eq!(spawn.groups.len(), 2);
+        assert_eq!(spawn.groups[0].critters.len(), 3);
+        assert_eq!(spawn.groups[0].placement.spacing, 3);
+        assert_eq!(spawn.groups[1].critters.len(), 1);
+        assert_eq!(spawn.groups[1].placement.formation, Formation::Wedge);
+        assert_eq!(time.as_hours(), 1);
+        assert!(!wm.is_traveling());
+
+        wm.encounter_tables[0].maps.clear();
+        assert_eq!(wm.roll_encounter(time), None);
+    }
+
+    #[test]
+    fn parse_placement_() {
+        assert_eq!(parse_placement("surrounding").unwrap(), Placement::default());
+        assert_eq!(parse_placement("Double_Line, spacing:2, distance:0").unwrap(), Placement {
+            formation: Formation::DoubleLine,
+            spacing: 2,
+            distance: None,
+        });
+        assert!(parse_placement("circle").is_err());
+        assert!(parse_placement("huddle, spacing:x").is_err());
+    }
+
+    #[test]
+    fn place_critters_() {
+        let grid = TileGrid::default();
+        let center = Point::new(100, 100);
+        for formation in [Formation::Surrounding, Formation::StraightLine, Formation::DoubleLine,
+            Formation::Wedge, Formation::Cone, Formation::Huddle]
+        {
+            let placement = Placement { formation, spacing: 2, distance: None };
+            let blocked = hex::go(center, Direction::NE, 1);
+            let r = place_critters(placement, 5, center, 6, &grid, |p| p != blocked);
+            assert_eq!(r.len(), 5, "{:?}", formation);
+            for (i, &p) in r.iter().enumerate() {
+                assert!(p != center && p != blocked, "{:?}", formation);
+                assert!(!r[i + 1..].contains(&p), "{:?}", formation);
+                assert!(hex::distance(center, p) <= 6 + 2 * 4, "{:?}", formation);
+            }
+        }
+
+        // The line is centered on the origin.
+        let placement = Placement {
+            formation: Formation::StraightLine,
+            spacing: 2,
+            distance: Some(4),
+        };
+        let r = place_critters(placement, 3, center, 10, &grid, |_| true);
+        assert_eq!(hex::distance(center, r[0]), 4);
+        assert_eq!(hex::distance(r[0], r[1]), 2);
+        assert_eq!(hex::distance(r[0], r[2]), 2);
+        assert_eq!(hex::distance(r[1], r[2]), 4);
+
+        assert!(place_critters(placement, 3, center, 10, &grid, |_| false).is_empty());
+    }
+
+    #[test]
+    fn area_state() {
+        let mut wm = world_map("None");
+        assert!(!wm.is_seen(Point::new(500, 50)));
+        assert!(wm.set_area_state(1, AreaState::Known));
+        assert!(wm.is_seen(Point::new(500, 50)));
+        assert!(!wm.set_area_state(2, AreaState::Known));
+
+        assert!(wm.set_map_known("ARROYO CAVES", true));
+        assert!(wm.areas[0].entrances[1].known);
+
+        wm.enter_map("Klamath Downtown", GameTime::from_decis(0));
+        assert_eq!(wm.current_area(), Some(1));
+        assert_eq!(wm.area(1).unwrap().state, AreaState::Visited);
+        assert_eq!(wm.pos(), Point::new(500, 50));
+        assert_eq!(wm.days_since_visited(GameTime::from_decis(3 * 864000 + 1)), 3);
+    }
+}
@@ -1,3 +1,4 @@
+pub mod sdl;
 pub mod software;
 
 use bstring::bstr;
@@ -7,6 +8,7 @@ use std::rc::Rc;
 use std::time::Instant;
 
 use crate::graphics::color::Rgb15;
+use crate::graphics::color::palette::Palette;
 use crate::graphics::font::{self, FontKey, Fonts};
 use crate::graphics::{Point, Rect};
 
@@ -66,8 +68,15 @@ pub trait Canvas {
     fn present(&mut self);
     fn update(&mut self, time: Instant);
 
+    /// Sets gamma applied to the palette colors when presenting. `1.0` is the normal brightness,
+    /// greater values make the picture brighter.
+    fn set_brightness(&mut self, brightness: f64);
+
     fn fonts(&self) -> &Rc<Fonts>;
 
+    /// Palette the texture pixels are indexed in.
+    fn palette(&self) -> &Palette;
+
     fn set_clip_rect(&mut self, rect: Rect);
     fn reset_clip_rect(&mut self);
 
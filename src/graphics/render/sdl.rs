@@ -0,0 +1,118 @@
+use sdl2::pixels::PixelFormatEnum;
+use sdl2::render::{Texture as SdlTexture, WindowCanvas};
+use std::rc::Rc;
+use std::time::Instant;
+
+use super::*;
+use super::software::OffscreenCanvas;
+
+/// Presents the software rendered back buffer in SDL window.
+pub struct SdlCanvas {
+    inner: OffscreenCanvas,
+    canvas: WindowCanvas,
+    texture: SdlTexture,
+}
+
+impl SdlCanvas {
+    pub fn new(canvas: WindowCanvas, inner: OffscreenCanvas) -> Self {
+        let texture = canvas
+            .texture_creator()
+            .create_texture_streaming(PixelFormatEnum::RGB24,
+                inner.width() as u32, inner.height() as u32)
+            .unwrap();
+        Self {
+            inner,
+            canvas,
+            texture,
+        }
+    }
+
+    pub fn inner(&self) -> &OffscreenCanvas {
+        &self.inner
+    }
+}
+
+impl Canvas for SdlCanvas {
+    fn cleanup(&mut self) {
+        self.inner.cleanup();
+    }
+
+    fn present(&mut self) {
+        let inner = &self.inner;
+        self.texture.with_lock(None, |dst, stride| inner.write_rgb(dst, stride)).unwrap();
+        self.canvas.copy(&self.texture, None, None).unwrap();
+        self.canvas.present();
+    }
+
+    fn update(&mut self, time: Instant) {
+        self.inner.update(time);
+    }
+
+    fn set_brightness(&mut self, brightness: f64) {
+        self.inner.set_brightness(brightness);
+    }
+
+    fn fonts(&self) -> &Rc<Fonts> {
+        self.inner.fonts()
+    }
+
+    fn palette(&self) -> &Palette {
+        self.inner.palette()
+    }
+
+    fn set_clip_rect(&mut self, rect: Rect) {
+        self.inner.set_clip_rect(rect);
+    }
+
+    fn reset_clip_rect(&mut self) {
+        self.inner.reset_clip_rect();
+    }
+
+    fn clear(&mut self, color: Rgb15) {
+        self.inner.clear(color);
+    }
+
+    fn draw(&mut self, tex: &TextureHandle, pos: Point, light: u32) {
+        self.inner.draw(tex, pos, light);
+    }
+
+    fn draw_multi_light(&mut self, tex: &TextureHandle, pos: Point, lights: &[u32]) {
+        self.inner.draw_multi_light(tex, pos, lights);
+    }
+
+    fn draw_masked(&mut self, texture: &TextureHandle, pos: Point,
+                   mask: &TextureHandle, mask_pos: Point,
+                   light: u32) {
+        self.inner.draw_masked(texture, pos, mask, mask_pos, light);
+    }
+
+    fn draw_masked_color(&mut self, src: Rgb15, dst: Option<Rgb15>, pos: Point,
+                         mask: &TextureHandle) {
+        self.inner.draw_masked_color(src, dst, pos, mask);
+    }
+
+    fn draw_highlight(&mut self, color: Rgb15, pos: Point, mask: &TextureHandle) {
+        self.inner.draw_highlight(color, pos, mask);
+    }
+
+    fn draw_translucent(&mut self, tex: &TextureHandle, pos: Point, color: Rgb15, light: u32) {
+        self.inner.draw_translucent(tex, pos, color, light);
+    }
+
+    fn draw_translucent_dark(&mut self, tex: &TextureHandle, pos: Point, color: Rgb15, light: u32) {
+        self.inner.draw_translucent_dark(tex, pos, color, light);
+    }
+
+    fn draw_outline(&mut self, tex: &TextureHandle, pos: Point, outline: Outline) {
+        self.inner.draw_outline(tex, pos, outline);
+    }
+
+    fn draw_text(&mut self, text: &bstr, pos: Point, font: FontKey, color: Rgb15,
+            options: &font::DrawOptions) {
+        self.inner.draw_text(text, pos, font, color, options);
+    }
+
+    fn draw_scaled(&mut self, src: &TextureHandle, dst: Rect) {
+        self.inner.draw_scaled(src, dst);
+    }
+}
@@ -1,12 +1,12 @@
-use sdl2::pixels::PixelFormatEnum;
-use sdl2::render::{Texture as SdlTexture, WindowCanvas};
+pub mod screenshot;
+
 use slotmap::{SecondaryMap, SlotMap};
 use std::cmp;
 use std::rc::Rc;
 use std::cell::{Ref, RefCell};
 
 use super::*;
-use crate::graphics::color::Color8;
+use crate::graphics::color::{Rgb18, Rgb24};
 use crate::graphics::color::palette::Palette;
 use crate::graphics::color::palette::overlay::PaletteOverlay;
 use crate::graphics::font::{self, FontKey, Fonts};
@@ -14,17 +14,14 @@ use crate::graphics::lighting::light_map::{self, LightMap};
 use crate::graphics::{Point, Rect};
 
 pub struct Backend {
-    canvas: WindowCanvas,
     palette: Box<Palette>,
     palette_overlay: PaletteOverlay,
     textures: Textures,
 }
 
 impl Backend {
-    pub fn new(canvas: WindowCanvas, palette: Box<Palette>,
-            palette_overlay: PaletteOverlay) -> Self {
+    pub fn new(palette: Box<Palette>, palette_overlay: PaletteOverlay) -> Self {
         Self {
-            canvas,
             palette,
             palette_overlay,
             textures: Textures::new(),
@@ -35,8 +32,8 @@ impl Backend {
         TextureFactory(TextureFactoryInner::Software(self.textures.clone()))
     }
 
-    pub fn into_canvas(self, fonts: Rc<Fonts>) -> Box<dyn Canvas> {
-        Box::new(CanvasImpl::new(self, fonts))
+    pub fn into_canvas(self, width: i32, height: i32, fonts: Rc<Fonts>) -> OffscreenCanvas {
+        OffscreenCanvas::new(self, width, height, fonts)
     }
 }
 
@@ -102,6 +99,12 @@ impl TexturesInner {
     }
 }
 
+/// Returns texture factory not attached to any backend.
+#[cfg(test)]
+pub fn new_test_texture_factory() -> TextureFactory {
+    TextureFactory(TextureFactoryInner::Software(Textures::new()))
+}
+
 #[derive(Clone)]
 pub(in super) struct Textures(Rc<RefCell<TexturesInner>>);
 
@@ -126,36 +129,83 @@ impl Textures {
     }
 }
 
-struct CanvasImpl {
-    canvas: WindowCanvas,
+/// Canvas that renders into in-memory 8-bit indexed back buffer. The back buffer can be
+/// converted to RGB with `to_rgb()`. `Canvas::present()` is a noop.
+pub struct OffscreenCanvas {
     palette: Box<Palette>,
     palette_overlay: PaletteOverlay,
     textures: Textures,
     light_map: LightMap,
     back_buf: Texture,
-    canvas_texture: SdlTexture,
     clip_rect: Rect,
     fonts: Rc<Fonts>,
+    /// Maps 6-bit color components to the brightness adjusted ones.
+    gamma: [u8; 64],
 }
 
-impl CanvasImpl {
-    fn new(backend: Backend, fonts: Rc<Fonts>) -> Self {
-        let (w, h) = backend.canvas.window().size();
-        let canvas_texture = backend.canvas
-            .texture_creator()
-            .create_texture_streaming(PixelFormatEnum::RGB24, w, h)
-            .unwrap();
+impl OffscreenCanvas {
+    fn new(backend: Backend, width: i32, height: i32, fonts: Rc<Fonts>) -> Self {
         Self {
-            canvas: backend.canvas,
             palette: backend.palette,
             palette_overlay: backend.palette_overlay,
             textures: backend.textures,
             light_map: LightMap::new(),
-            back_buf: Texture::new_empty(w as i32, h as i32, 0),
-            canvas_texture,
-            clip_rect: Rect::with_size(0, 0, w as i32, h as i32),
+            back_buf: Texture::new_empty(width, height, 0),
+            clip_rect: Rect::with_size(0, 0, width, height),
             fonts,
+            gamma: Self::gamma_table(1.0),
+        }
+    }
+
+    fn gamma_table(brightness: f64) -> [u8; 64] {
+        let mut r = [0; 64];
+        for (i, v) in r.iter_mut().enumerate() {
+            *v = (i as f64).powf(brightness).clamp(0.0, 63.0) as u8;
         }
+        r
+    }
+
+    pub fn width(&self) -> i32 {
+        self.back_buf.width
+    }
+
+    pub fn height(&self) -> i32 {
+        self.back_buf.height
+    }
+
+    /// Back buffer pixels as palette color indices, row by row.
+    pub fn back_buffer(&self) -> &[u8] {
+        &self.back_buf.data
+    }
+
+    /// Returns final color of the `color_idx` with the palette overlay applied.
+    pub fn rgb(&self, color_idx: u8) -> Rgb24 {
+        let c = self.palette_overlay.get(color_idx)
+            .unwrap_or_else(|| self.palette.rgb18(color_idx));
+        let g = &self.gamma;
+        Rgb18::new(g[c.r() as usize], g[c.g() as usize], g[c.b() as usize]).scale()
+    }
+
+    /// Converts the back buffer into RGB24 pixels writing rows into `dst` that are `stride`
+    /// bytes apart.
+    pub fn write_rgb(&self, dst: &mut [u8], stride: usize) {
+        let src_width = self.back_buf.width as usize;
+        for (src_row, dst_row) in self.back_buf.data.chunks(src_width).zip(dst.chunks_mut(stride)) {
+            for (&src_pixel, dst_pixel) in src_row.iter().zip(dst_row.chunks_mut(3)) {
+                let rgb = self.rgb(src_pixel);
+                dst_pixel[0] = rgb.r();
+                dst_pixel[1] = rgb.g();
+                dst_pixel[2] = rgb.b();
+            }
+        }
+    }
+
+    /// Returns the back buffer converted into RGB24 pixels.
+    pub fn to_rgb(&self) -> Vec<u8> {
+        let stride = self.back_buf.width as usize * 3;
+        let mut r = vec![0; stride * self.back_buf.height as usize];
+        self.write_rgb(&mut r, stride);
+        r
     }
 
     fn make_translucent(src: u8, dst: u8, trans_color_idx: u8, palette: &Palette,
@@ -219,48 +269,37 @@ impl CanvasImpl {
     }
 }
 
-impl Canvas for CanvasImpl {
+impl Canvas for OffscreenCanvas {
     fn cleanup(&mut self) {
         self.textures.cleanup();
     }
 
-    fn present(&mut self) {
-        let pal = &self.palette;
-        let pal_overlay = &self.palette_overlay;
-        let src = &self.back_buf.data;
-        let src_width = self.back_buf.width;
-        self.canvas_texture.with_lock(None, |dst, stride| {
-            for (src_row, dst_row) in src.chunks(src_width as usize).zip(dst.chunks_mut(stride)) {
-                for (&src_pixel, dst_pixel) in src_row.iter().zip(dst_row.chunks_mut(3)) {
-                    let rgb = pal_overlay.get(src_pixel)
-                        .unwrap_or_else(|| pal.rgb18(src_pixel))
-                        .scale::<Color8>();
-                    dst_pixel[0] = rgb.r();
-                    dst_pixel[1] = rgb.g();
-                    dst_pixel[2] = rgb.b();
-                }
-            }
-        }).unwrap();
-        self.canvas.copy(&self.canvas_texture, None, None).unwrap();
-        self.canvas.present();
-    }
+    fn present(&mut self) {}
 
     fn update(&mut self, time: Instant) {
         self.palette_overlay.rotate(time);
     }
 
+    // colorSetBrightness()
+    fn set_brightness(&mut self, brightness: f64) {
+        self.gamma = Self::gamma_table(brightness);
+    }
+
     fn fonts(&self) -> &Rc<Fonts> {
         &self.fonts
     }
 
+    fn palette(&self) -> &Palette {
+        &self.palette
+    }
+
     fn set_clip_rect(&mut self, rect: Rect) {
         self.reset_clip_rect();
         self.clip_rect = rect.intersect(self.clip_rect);
     }
 
     fn reset_clip_rect(&mut self) {
-        let (w, h) = self.canvas.window().size();
-        self.clip_rect = Rect::with_size(0, 0, w as i32, h as i32);
+        self.clip_rect = Rect::with_size(0, 0, self.back_buf.width, self.back_buf.height);
     }
 
     fn clear(&mut self, color: Rgb15) {
@@ -534,3 +573,90 @@ impl Canvas for CanvasImpl {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::graphics::color::{BLACK, WHITE};
+    use crate::util::test::ungz;
+
+    const FULL_LIGHT: u32 = 0x10000;
+
+    fn canvas(width: i32, height: i32) -> OffscreenCanvas {
+        let data = ungz(include_bytes!("../color/color.pal.gz"));
+        let pal = crate::asset::palette::read_palette(&mut std::io::Cursor::new(&data[..])).unwrap();
+        Backend::new(Box::new(pal), PaletteOverlay::standard())
+            .into_canvas(width, height, Rc::new(Fonts::new()))
+    }
+
+    fn texture(canvas: &OffscreenCanvas, width: i32, height: i32, data: &[u8]) -> TextureHandle {
+        canvas.textures.new_texture(width, height, data.into())
+    }
+
+    #[test]
+    fn draw() {
+        let mut c = canvas(4, 3);
+        c.clear(BLACK);
+        let black = c.palette().color_idx(BLACK);
+        let tex = texture(&c, 2, 2, &[
+            1, 0,
+            2, 3,
+        ]);
+
+        c.draw(&tex, Point::new(1, 1), FULL_LIGHT);
+        assert_eq!(c.back_buffer(), &[
+            black, black, black, black,
+            black, 1,     black, black,
+            black, 2,     3,     black,
+        ]);
+
+        c.clear(BLACK);
+        c.set_clip_rect(Rect::with_size(0, 0, 2, 3));
+        c.draw(&tex, Point::new(-1, 1), FULL_LIGHT);
+        c.draw(&tex, Point::new(1, 0), FULL_LIGHT);
+        assert_eq!(c.back_buffer(), &[
+            black, 1,     black, black,
+            black, 2,     black, black,
+            3,     black, black, black,
+        ]);
+    }
+
+    #[test]
+    fn draw_outline() {
+        let mut c = canvas(5, 5);
+        c.clear(BLACK);
+        let black = c.palette().color_idx(BLACK);
+        let white = c.palette().color_idx(WHITE);
+        let tex = texture(&c, 3, 3, &[
+            0, 0, 0,
+            0, 1, 0,
+            0, 0, 0,
+        ]);
+
+        c.draw_outline(&tex, Point::new(1, 1), Outline::Fixed { color: WHITE, trans_color: None });
+        assert_eq!(c.back_buffer(), &[
+            black, black, black, black, black,
+            black, black, white, black, black,
+            black, white, black, white, black,
+            black, black, white, black, black,
+            black, black, black, black, black,
+        ]);
+    }
+
+    #[test]
+    fn to_rgb() {
+        let mut c = canvas(2, 1);
+        let tex = texture(&c, 2, 1, &[1, 229]);
+        c.draw(&tex, Point::new(0, 0), FULL_LIGHT);
+
+        let rgb = c.to_rgb();
+        assert_eq!(rgb.len(), 2 * 3);
+        let exp = c.palette().rgb18(1).scale::<crate::graphics::color::Color8>();
+        assert_eq!(&rgb[..3], &[exp.r(), exp.g(), exp.b()]);
+
+        // Color 229 is in the animated range of the standard palette overlay.
+        let exp = c.palette_overlay.get(229).unwrap().scale::<crate::graphics::color::Color8>();
+        assert_eq!(&rgb[3..], &[exp.r(), exp.g(), exp.b()]);
+        assert_eq!(c.rgb(229), exp);
+    }
+}
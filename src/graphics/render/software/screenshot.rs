@@ -0,0 +1,224 @@
+use byteorder::{BigEndian, LittleEndian, WriteBytesExt};
+use flate2::Compression;
+use flate2::write::ZlibEncoder;
+use std::fs::File;
+use std::io::{self, prelude::*, BufWriter};
+use std::path::{Path, PathBuf};
+
+use super::OffscreenCanvas;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ImageFormat {
+    Bmp,
+    Png,
+}
+
+impl ImageFormat {
+    /// Guesses format from the file extension.
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let ext = path.extension()?.to_str()?.to_ascii_lowercase();
+        match ext.as_str() {
+            "bmp" => Some(Self::Bmp),
+            "png" => Some(Self::Png),
+            _ => None,
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::Bmp => "bmp",
+            Self::Png => "png",
+        }
+    }
+}
+
+impl OffscreenCanvas {
+    /// Saves the back buffer as it would be presented on screen. The format is chosen by the
+    /// file extension.
+    pub fn save_screenshot(&self, path: &Path) -> io::Result<()> {
+        let format = ImageFormat::from_path(path)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput,
+                "unsupported image file extension"))?;
+        let mut wr = BufWriter::new(File::create(path)?);
+        let (width, height) = (self.width() as u32, self.height() as u32);
+        let rgb = self.to_rgb();
+        match format {
+            ImageFormat::Bmp => write_bmp(&mut wr, width, height, &rgb)?,
+            ImageFormat::Png => write_png(&mut wr, width, height, &rgb)?,
+        }
+        wr.flush()
+    }
+}
+
+/// Returns path of the first non-existing `scrNNNNN.<ext>` file in `dir`.
+// dump_screen()
+pub fn next_screenshot_path(dir: &Path, format: ImageFormat) -> Option<PathBuf> {
+    (0..100_000)
+        .map(|i| dir.join(format!("scr{:05}.{}", i, format.extension())))
+        .find(|p| !p.exists())
+}
+
+/// Writes RGB24 pixels as 24-bit uncompressed BMP.
+pub fn write_bmp(wr: &mut impl Write, width: u32, height: u32, rgb: &[u8]) -> io::Result<()> {
+    assert_eq!(rgb.len(), (width * height * 3) as usize);
+
+    const HEADER_LEN: u32 = 14 + 40;
+    let stride = (width * 3 + 3) & !3;
+    let data_len = stride * height;
+
+    wr.write_all(b"BM")?;
+    wr.write_u32::<LittleEndian>(HEADER_LEN + data_len)?;
+    wr.write_u32::<LittleEndian>(0)?;
+    wr.write_u32::<LittleEndian>(HEADER_LEN)?;
+
+    // BITMAPINFOHEADER
+    wr.write_u32::<LittleEndian>(40)?;
+    wr.write_i32::<LittleEndian>(width as i32)?;
+    wr.write_i32::<LittleEndian>(height as i32)?;
+    wr.write_u16::<LittleEndian>(1)?; // planes
+    wr.write_u16::<LittleEndian>(24)?; // bits per pixel
+    wr.write_u32::<LittleEndian>(0)?; // BI_RGB
+    wr.write_u32::<LittleEndian>(data_len)?;
+    wr.write_i32::<LittleEndian>(2835)?; // 72 DPI
+    wr.write_i32::<LittleEndian>(2835)?;
+    wr.write_u32::<LittleEndian>(0)?;
+    wr.write_u32::<LittleEndian>(0)?;
+
+    // Rows are stored bottom-up in BGR order.
+    let padding = [0; 3];
+    for row in rgb.chunks((width * 3) as usize).rev() {
+        for px in row.chunks(3) {
+            wr.write_all(&[px[2], px[1], px[0]])?;
+        }
+        wr.write_all(&padding[..(stride - width * 3) as usize])?;
+    }
+
+    Ok(())
+}
+
+/// Writes RGB24 pixels as 8-bit truecolor PNG.
+pub fn write_png(wr: &mut impl Write, width: u32, height: u32, rgb: &[u8]) -> io::Result<()> {
+    assert_eq!(rgb.len(), (width * height * 3) as usize);
+
+    wr.write_all(b"\x89PNG\r\n\x1a\n")?;
+
+    let mut ihdr = Vec::with_capacity(13);
+    ihdr.write_u32::<BigEndian>(width)?;
+    ihdr.write_u32::<BigEndian>(height)?;
+    ihdr.write_all(&[
+        8, // bit depth
+        2, // color type: truecolor
+        0, // compression method
+        0, // filter method
+        0, // interlace method
+    ])?;
+    write_png_chunk(wr, b"IHDR", &ihdr)?;
+
+    let mut enc = ZlibEncoder::new(Vec::new(), Compression::default());
+    for row in rgb.chunks((width * 3) as usize) {
+        // Filter type: none.
+        enc.write_all(&[0])?;
+        enc.write_all(row)?;
+    }
+    write_png_chunk(wr, b"IDAT", &enc.finish()?)?;
+
+    write_png_chunk(wr, b"IEND", &[])
+}
+
+fn write_png_chunk(wr: &mut impl Write, kind: &[u8; 4], data: &[u8]) -> io::Result<()> {
+    wr.write_u32::<BigEndian>(data.len() as u32)?;
+    wr.write_all(kind)?;
+    wr.write_all(data)?;
+    wr.write_u32::<BigEndian>(crc32(crc32(0, kind), data))
+}
+
+fn crc32(crc: u32, data: &[u8]) -> u32 {
+    let mut crc = !crc;
+    for &b in data {
+        crc ^= b as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xedb88320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use byteorder::{ByteOrder, ReadBytesExt};
+    use flate2::read::ZlibDecoder;
+
+    const RGB: [u8; 12] = [
+        1, 2, 3,    4, 5, 6,
+        7, 8, 9,    10, 11, 12,
+    ];
+
+    #[test]
+    fn format_from_path() {
+        assert_eq!(ImageFormat::from_path(Path::new("a/b.PNG")), Some(ImageFormat::Png));
+        assert_eq!(ImageFormat::from_path(Path::new("b.bmp")), Some(ImageFormat::Bmp));
+        assert_eq!(ImageFormat::from_path(Path::new("b.ppm")), None);
+        assert_eq!(ImageFormat::from_path(Path::new("b")), None);
+    }
+
+    #[test]
+    fn crc32_() {
+        assert_eq!(crc32(0, b""), 0);
+        assert_eq!(crc32(0, b"IEND"), 0xae426082);
+        assert_eq!(crc32(crc32(0, b"IE"), b"ND"), 0xae426082);
+    }
+
+    #[test]
+    fn bmp() {
+        let mut data = Vec::new();
+        write_bmp(&mut data, 2, 2, &RGB).unwrap();
+
+        assert_eq!(&data[..2], b"BM");
+        assert_eq!(LittleEndian::read_u32(&data[2..]) as usize, data.len());
+        assert_eq!(LittleEndian::read_u32(&data[10..]), 54);
+        assert_eq!(LittleEndian::read_i32(&data[18..]), 2);
+        assert_eq!(LittleEndian::read_i32(&data[22..]), 2);
+        assert_eq!(LittleEndian::read_u16(&data[28..]), 24);
+        assert_eq!(&data[54..], &[
+            9, 8, 7,    12, 11, 10,     0, 0,
+            3, 2, 1,    6, 5, 4,        0, 0,
+        ]);
+    }
+
+    #[test]
+    fn png() {
+        let mut data = Vec::new();
+        write_png(&mut data, 2, 2, &RGB).unwrap();
+
+        assert_eq!(&data[..8], b"\x89PNG\r\n\x1a\n");
+
+        let mut rd = &data[8..];
+        let mut chunks = Vec::new();
+        while !rd.is_empty() {
+            let len = rd.read_u32::<BigEndian>().unwrap() as usize;
+            let kind = rd[..4].to_vec();
+            let body = rd[4..4 + len].to_vec();
+            rd = &rd[4 + len..];
+            let crc = rd.read_u32::<BigEndian>().unwrap();
+            assert_eq!(crc, crc32(crc32(0, &kind), &body));
+            chunks.push((kind, body));
+        }
+
+        assert_eq!(chunks.len(), 3);
+        assert_eq!(chunks[0].0, b"IHDR");
+        assert_eq!(chunks[0].1, &[0, 0, 0, 2, 0, 0, 0, 2, 8, 2, 0, 0, 0]);
+        assert_eq!(chunks[1].0, b"IDAT");
+        let mut pixels = Vec::new();
+        ZlibDecoder::new(&chunks[1].1[..]).read_to_end(&mut pixels).unwrap();
+        assert_eq!(pixels, &[
+            0, 1, 2, 3, 4, 5, 6,
+            0, 7, 8, 9, 10, 11, 12,
+        ]);
+        assert_eq!(chunks[2], (b"IEND".to_vec(), vec![]));
+    }
+}
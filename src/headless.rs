@@ -0,0 +1,341 @@
+use sdl2::event::Event;
+use sdl2::keyboard::{Keycode, Mod};
+use sdl2::mouse::{MouseButton, MouseState};
+use std::io::{self, Error, ErrorKind};
+
+use crate::{App, FRAME_TIME};
+use crate::graphics::Point;
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+enum Action {
+    KeyDown(Keycode),
+    KeyUp(Keycode),
+    MoveTo(Point),
+    MouseDown(MouseButton),
+    MouseUp(MouseButton),
+    Quit,
+}
+
+/// Input events to feed into the headless simulation at specific ticks.
+///
+/// The script is a text with one command per line. Empty lines and lines starting with `#` are
+/// ignored. Each command is prefixed with the tick number (frames since the start) when it's
+/// fired. Ticks must not decrease. Supported commands:
+///
+/// ```text
+/// <tick> key <key>            press and release the key (SDL key name, e.g. `Space`)
+/// <tick> keydown <key>
+/// <tick> keyup <key>
+/// <tick> move <x> <y>         move cursor to the screen position
+/// <tick> click <x> <y> [left|right]
+/// <tick> mousedown [left|right]
+/// <tick> mouseup [left|right]
+/// <tick> quit                 stop the simulation
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct InputScript {
+    actions: Vec<(u64, Action)>,
+    next: usize,
+}
+
+impl InputScript {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn parse(s: &str) -> io::Result<Self> {
+        fn err(line_num: usize, msg: &str) -> Error {
+            Error::new(ErrorKind::InvalidData, format!("line {}: {}", line_num + 1, msg))
+        }
+
+        let mut actions = Vec::new();
+        for (line_num, line) in s.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut args = line.split_whitespace();
+            let tick: u64 = args.next().unwrap().parse()
+                .map_err(|_| err(line_num, "bad tick number"))?;
+            if actions.last().is_some_and(|&(t, _)| tick < t) {
+                return Err(err(line_num, "tick number is less than the previous one"));
+            }
+            let cmd = args.next().ok_or_else(|| err(line_num, "missing command"))?;
+
+            let key = |args: &mut dyn Iterator<Item=&str>| {
+                let name = args.next().ok_or_else(|| err(line_num, "missing key name"))?;
+                Keycode::from_name(name).ok_or_else(|| err(line_num, "unknown key name"))
+            };
+            let point = |args: &mut dyn Iterator<Item=&str>| -> io::Result<Point> {
+                let mut coord = || args.next()
+                    .and_then(|v| v.parse().ok())
+                    .ok_or_else(|| err(line_num, "bad coordinates"));
+                Ok(Point::new(coord()?, coord()?))
+            };
+            let button = |args: &mut dyn Iterator<Item=&str>| match args.next() {
+                None | Some("left") => Ok(MouseButton::Left),
+                Some("right") => Ok(MouseButton::Right),
+                Some(_) => Err(err(line_num, "bad mouse button")),
+            };
+
+            match cmd {
+                "key" => {
+                    let key = key(&mut args)?;
+                    actions.push((tick, Action::KeyDown(key)));
+                    actions.push((tick, Action::KeyUp(key)));
+                }
+                "keydown" => actions.push((tick, Action::KeyDown(key(&mut args)?))),
+                "keyup" => actions.push((tick, Action::KeyUp(key(&mut args)?))),
+                "move" => actions.push((tick, Action::MoveTo(point(&mut args)?))),
+                "click" => {
+                    let point = point(&mut args)?;
+                    let button = button(&mut args)?;
+                    actions.push((tick, Action::MoveTo(point)));
+                    actions.push((tick, Action::MouseDown(button)));
+                    actions.push((tick, Action::MouseUp(button)));
+                }
+                "mousedown" => actions.push((tick, Action::MouseDown(button(&mut args)?))),
+                "mouseup" => actions.push((tick, Action::MouseUp(button(&mut args)?))),
+                "quit" => actions.push((tick, Action::Quit)),
+                _ => return Err(err(line_num, "unknown command")),
+            }
+            if args.next().is_some() {
+                return Err(err(line_num, "too many arguments"));
+            }
+        }
+        Ok(Self {
+            actions,
+            next: 0,
+        })
+    }
+
+    /// Returns events scheduled for the `tick`. `cursor_pos` is the current cursor position
+    /// which is needed to compute relative mouse motion.
+    pub fn events(&mut self, tick: u64, mut cursor_pos: Point) -> Vec<Event> {
+        let mut r = Vec::new();
+        while let Some((t, action)) = self.actions.get(self.next) {
+            if *t > tick {
+                break;
+            }
+            self.next += 1;
+            let (x, y) = (cursor_pos.x, cursor_pos.y);
+            r.push(match *action {
+                Action::KeyDown(keycode) => Event::KeyDown {
+                    timestamp: 0,
+                    window_id: 0,
+                    keycode: Some(keycode),
+                    scancode: None,
+                    keymod: Mod::NOMOD,
+                    repeat: false,
+                },
+                Action::KeyUp(keycode) => Event::KeyUp {
+                    timestamp: 0,
+                    window_id: 0,
+                    keycode: Some(keycode),
+                    scancode: None,
+                    keymod: Mod::NOMOD,
+                    repeat: false,
+                },
+                Action::MoveTo(pos) => {
+                    let rel = pos - cursor_pos;
+                    cursor_pos = pos;
+                    Event::MouseMotion {
+                        timestamp: 0,
+                        window_id: 0,
+                        which: 0,
+                        mousestate: MouseState::from_sdl_state(0),
+                        x: pos.x,
+                        y: pos.y,
+                        xrel: rel.x,
+                        yrel: rel.y,
+                    }
+                }
+                Action::MouseDown(mouse_btn) => Event::MouseButtonDown {
+                    timestamp: 0,
+                    window_id: 0,
+                    which: 0,
+                    mouse_btn,
+                    clicks: 1,
+                    x,
+                    y,
+                },
+                Action::MouseUp(mouse_btn) => Event::MouseButtonUp {
+                    timestamp: 0,
+                    window_id: 0,
+                    which: 0,
+                    mouse_btn,
+                    clicks: 1,
+                    x,
+                    y,
+                },
+                Action::Quit => Event::Quit { timestamp: 0 },
+            });
+        }
+        r
+    }
+}
+
+/// Runs the app for at most `ticks` frames feeding it the events from the `input` script.
+/// The time advances by exactly one frame per tick so the run is reproducible.
+/// Returns the number of frames run.
+pub fn run(app: &mut App, input: &mut InputScript, ticks: u64) -> u64 {
+    while app.tick < ticks {
+        let events = input.events(app.tick, app.ui.cursor_pos());
+        if !app.frame(events) {
+            break;
+        }
+        app.timer.tick(app.timer.time() + FRAME_TIME);
+    }
+    app.tick
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::path::PathBuf;
+    use std::rc::Rc;
+    use std::time::Instant;
+    use crate::AppCanvas;
+    use crate::asset::frame::FrameDb;
+    use crate::asset::map::{Map, MapWriter, ELEVATION_COUNT};
+    use crate::asset::message::Messages;
+    use crate::asset::palette::read_palette;
+    use crate::game::GameTime;
+    use crate::game::object::Objects;
+    use crate::game::state::GameState;
+    use crate::graphics::color::palette::overlay::PaletteOverlay;
+    use crate::graphics::geometry::hex::{Direction, TileGrid};
+    use crate::graphics::render::software::Backend;
+    use crate::profile::Profile;
+    use crate::sound::{Mixer, Sound};
+    use crate::sound::output::NullOutput;
+    use crate::ui::Ui;
+    use crate::util::array2d::Array2d;
+    use crate::util::test::{fonts, ungz, Assets};
+
+    /// App with the new game started on an empty map.
+    fn app() -> App {
+        let assets = Assets::new();
+        let map = Map {
+            version: 20,
+            name: "TEST.MAP".into(),
+            id: 0,
+            savegame: false,
+            last_visit: GameTime::from_decis(0),
+            entrance: Point::new(100, 100).elevated(0),
+            entrance_direction: Direction::NE,
+            sqr_tiles: vec![Some(Array2d::with_default(100, 100)), None, None],
+            map_vars: Box::new([]),
+            extra: Default::default(),
+        };
+        let mut map_data = Vec::new();
+        MapWriter {
+            writer: &mut map_data,
+            objects: &Objects::new(TileGrid::default(), ELEVATION_COUNT,
+                assets.frm_db.clone(), assets.proto_db.clone()),
+            scripts: &assets.scripts(),
+        }.write(&map).unwrap();
+        let assets = Assets::with_files(|fs| {
+            fs.insert("maps/test.map", map_data);
+            // All interface and critter frames are the same test frames.
+            fs.insert("art/intrface/intrface.lst", "test.frm\n".repeat(400));
+            fs.insert("art/critters/critters.lst", "hmtest\n".repeat(100));
+        });
+
+        let pal = ungz(include_bytes!("graphics/color/color.pal.gz"));
+        let backend = Backend::new(Box::new(read_palette(&mut &pal[..]).unwrap()),
+            PaletteOverlay::standard());
+        let texture_factory = backend.new_texture_factory();
+        let frm_db = Rc::new(FrameDb::new(assets.fs.clone(), "english", texture_factory.clone())
+            .unwrap());
+        let fonts = Rc::new(fonts(&texture_factory));
+        let canvas = backend.into_canvas(640, 480, fonts.clone());
+
+        let start = Instant::now();
+        let mut ui = Ui::new(frm_db.clone(), fonts.clone(), 640, 480);
+        let mut state = GameState::new(
+            Profile::Fallout2,
+            assets.fs.clone(),
+            "english",
+            assets.proto_db.clone(),
+            frm_db,
+            fonts,
+            Rc::new(Messages::read(&mut &b""[..]).unwrap()),
+            PathBuf::new(),
+            start,
+            Sound::new(assets.fs.clone(), Mixer::new(), Box::new(NullOutput::new())),
+            &mut ui);
+        state.new_game();
+        state.switch_map("test", &mut ui);
+        App::new(state, ui, AppCanvas::Offscreen(canvas), start)
+    }
+
+    #[test]
+    fn run_() {
+        let mut app = app();
+        let start = app.state.world().borrow().game_time;
+        let mut input = InputScript::parse("90 quit").unwrap();
+
+        assert_eq!(run(&mut app, &mut input, 60), 60);
+        {
+            let world = app.state.world().borrow();
+            assert_eq!(world.game_time.as_decis() - start.as_decis(), 10);
+            let dude = world.objects().get(world.objects().dude());
+            assert_eq!(dude.pos(), Point::new(100, 100).elevated(0));
+            assert_eq!(dude.direction, Direction::NE);
+        }
+
+        // Resumes from the last tick and stops at the scripted quit.
+        assert_eq!(run(&mut app, &mut input, 600), 90);
+        assert_eq!(app.state.world().borrow().game_time.as_decis() - start.as_decis(), 15);
+    }
+
+    #[test]
+    fn parse() {
+        let mut s = InputScript::parse("
+            # comment
+            0 move 10 20
+            5 click 100 50 right
+
+            5 quit
+        ").unwrap();
+        assert_eq!(s.actions, vec![
+            (0, Action::MoveTo(Point::new(10, 20))),
+            (5, Action::MoveTo(Point::new(100, 50))),
+            (5, Action::MouseDown(MouseButton::Right)),
+            (5, Action::MouseUp(MouseButton::Right)),
+            (5, Action::Quit),
+        ]);
+
+        match s.events(0, Point::new(5, 5)).as_slice() {
+            [Event::MouseMotion { xrel: 5, yrel: 15, .. }] => {}
+            e => panic!("{:?}", e),
+        }
+        assert!(s.events(4, Point::new(10, 20)).is_empty());
+        match s.events(5, Point::new(10, 20)).as_slice() {
+            [
+                Event::MouseMotion { xrel: 90, yrel: 30, .. },
+                Event::MouseButtonDown { mouse_btn: MouseButton::Right, x: 100, y: 50, .. },
+                Event::MouseButtonUp { mouse_btn: MouseButton::Right, .. },
+                Event::Quit { .. },
+            ] => {}
+            e => panic!("{:?}", e),
+        }
+        assert!(s.events(100, Point::new(0, 0)).is_empty());
+    }
+
+    #[test]
+    fn parse_errors() {
+        for s in &[
+            "x move 1 2",
+            "1",
+            "1 move 1",
+            "1 move 1 2 3",
+            "1 click 1 2 middle",
+            "1 jump",
+            "2 quit\n1 quit",
+        ] {
+            assert_eq!(InputScript::parse(s).unwrap_err().kind(), ErrorKind::InvalidData, "{}", s);
+        }
+    }
+}
@@ -14,7 +14,10 @@ mod asset;
 mod fs;
 mod game;
 mod graphics;
+mod headless;
+mod profile;
 mod sequence;
+mod sound;
 mod state;
 mod ui;
 mod util;
@@ -40,9 +43,19 @@ use crate::graphics::color::palette::overlay::PaletteOverlay;
 use crate::graphics::font::{self, FontKey};
 use crate::graphics::geometry::TileGridView;
 use crate::graphics::geometry::sqr;
-use crate::graphics::render::software::Backend;
-use crate::state::{AppState, Update, HandleAppEvent};
+use crate::graphics::render::Canvas;
+use crate::graphics::render::sdl::SdlCanvas;
+use crate::graphics::render::software::{Backend, OffscreenCanvas};
+use crate::graphics::render::software::screenshot::{next_screenshot_path, ImageFormat};
+use crate::headless::InputScript;
+use crate::profile::Profile;
+use crate::sound::{Mixer, Sound};
+use crate::sound::output::{NullOutput, Output, SdlOutput};
+use crate::state::{AppEvent, AppState, Update, HandleAppEvent};
 use crate::ui::Ui;
+use crate::ui::command::UiCommand;
+
+const FRAME_TIME: Duration = Duration::from_nanos(1_000_000_000 / 60);
 
 const VERSION: &str = env!("CARGO_PKG_VERSION");
 const GIT_HASH: &str = env!("GIT_HASH");
@@ -69,21 +82,140 @@ fn args() -> clap::Command {
         .version(VERSION)
         .long_version(Box::leak(format!("{} ({})", VERSION, GIT_DATE).into_boxed_str()) as &_)
         .arg(Arg::new("RESOURCE_DIR")
-            .help("Resource directory where master.dat, critter.dat and patchXXX.dat \
-                   can be found")
+            .help("Resource directory of Fallout 1 or Fallout 2 where master.dat, critter.dat \
+                   and patchXXX.dat can be found")
             .required_unless_present("version"))
+        .arg(Arg::new("game")
+            .long("game")
+            .value_name("GAME")
+            .value_parser(["fallout1", "fallout2"])
+            .help("Game the resources are from [default: detected by master.dat format]"))
         .arg(Arg::new("MAP")
-            .help("Map name to load. For example: artemple")
-            .required_unless_present("version"))
+            .help("Map name to start the new game on skipping the main menu. For debugging. \
+                   For example: artemple"))
+        .arg(Arg::new("load")
+            .long("load")
+            .value_name("SLOT_DIR")
+            .help("Load saved game from the save slot directory. For example: \
+                   /path/to/fallout2/data/savegame/slot01")
+            .conflicts_with("MAP"))
+        .arg(Arg::new("dump-movie")
+            .long("dump-movie")
+            .value_names(["MOVIE", "OUT_DIR"])
+            .num_args(2)
+            .help("Decode movie into OUT_DIR as PPM frames and WAV sound and exit. For example: \
+                   art/cuts/intro.mve /tmp/intro")
+            .conflicts_with_all(["MAP", "load"]))
+        .arg(Arg::new("headless")
+            .long("headless")
+            .action(ArgAction::SetTrue)
+            .help("Run without window and sound using fixed frame time. Game randomness is \
+                   seeded so runs with the same input are reproducible"))
+        .arg(Arg::new("ticks")
+            .long("ticks")
+            .value_name("N")
+            .value_parser(value_parser!(u64))
+            .requires("headless")
+            .help("Number of frames to simulate in headless mode before exiting [default: 600]"))
+        .arg(Arg::new("input")
+            .long("input")
+            .value_name("FILE")
+            .requires("headless")
+            .help("Input script to feed in headless mode. Each line is \
+                   `<tick> <command> <args>...`, see `headless::InputScript` for commands"))
+        .arg(Arg::new("seed")
+            .long("seed")
+            .value_name("SEED")
+            .value_parser(value_parser!(u64))
+            .requires("headless")
+            .help("Random seed for headless mode [default: 0]"))
+        .arg(Arg::new("screenshot")
+            .long("screenshot")
+            .value_name("FILE")
+            .help("Save the last rendered frame into FILE on exit. The format is chosen by the \
+                   file extension: .png or .bmp"))
+        .arg(Arg::new("record")
+            .long("record")
+            .value_names(["N", "DIR"])
+            .num_args(2)
+            .help("Save the first N rendered frames into DIR as numbered PNG files"))
+        .arg(Arg::new("debug-scripts")
+            .long("debug-scripts")
+            .action(ArgAction::SetTrue)
+            .help("Enable script debugger reading commands from stdin. The game is blocked \
+                   while the debugger is paused. Press ` in game to pause at the next script \
+                   instruction"))
+        .arg(Arg::new("break")
+            .long("break")
+            .value_name("LOCATION")
+            .action(ArgAction::Append)
+            .requires("debug-scripts")
+            .help("Set script debugger breakpoint at `[<program>:]<procedure>|<offset>`. \
+                   For example: artemple:talk_p_proc"))
+        .subcommand(script_command("disasm")
+            .about("Print procedures and instruction listing of compiled script and exit"))
+        .subcommand(script_command("decompile")
+            .about("Print SSL source reconstructed from compiled script and exit"))
+        .subcommand_negates_reqs(true)
+        .args_conflicts_with_subcommands(true)
         .after_help(
             "EXAMPLE:\n\
-          \x20   vault13 /path/to/fallout2 artemple")
+          \x20   vault13 /path/to/fallout2\n\
+          \x20   vault13 /path/to/fallout2 artemple\n\
+          \x20   vault13 /path/to/fallout1 v13ent\n\
+          \x20   vault13 /path/to/fallout2 --load /path/to/fallout2/data/savegame/slot01\n\
+          \x20   vault13 /path/to/fallout2 artemple --headless --ticks 300 --input input.txt\n\
+          \x20   vault13 /path/to/fallout2 artemple --debug-scripts --break map_update_p_proc\n\
+          \x20   vault13 disasm scripts/artemple.int\n\
+          \x20   vault13 decompile scripts/artemple.int")
+}
+
+fn script_command(name: &'static str) -> clap::Command {
+    use clap::*;
+
+    Command::new(name)
+        .arg(Arg::new("SCRIPT")
+            .help("Path to compiled script (.int) file")
+            .required(true))
+        .arg(Arg::new("game")
+            .long("game")
+            .value_name("GAME")
+            .value_parser(["fallout1", "fallout2"])
+            .default_value("fallout2")
+            .help("Game the script is from"))
+}
+
+fn load_script(args: &clap::ArgMatches) -> std::io::Result<vm::Program> {
+    use std::io::{Error, ErrorKind};
+
+    let path = Path::new(args.get_one::<String>("SCRIPT").unwrap());
+    let profile = Profile::from_name(args.get_one::<String>("game").unwrap()).unwrap();
+    let code = std::fs::read(path)?;
+    let vm = vm::Vm::new(Rc::new(vm::VmConfig::new(profile)));
+    vm.load(path.display().to_string(), code.into())
+        .map_err(|e| Error::new(ErrorKind::InvalidData, format!("{:?}", e)))
 }
 
-fn setup_file_system(fs: &mut fs::FileSystem, args: &clap::ArgMatches) {
+fn disasm(args: &clap::ArgMatches) -> std::io::Result<()> {
+    vm::disasm::disassemble(&load_script(args)?, &mut std::io::stdout().lock())
+}
+
+fn decompile(args: &clap::ArgMatches) -> std::io::Result<()> {
+    use std::io::Write;
+
+    let script = vm::ssl::decompile::decompile(&load_script(args)?);
+    write!(std::io::stdout().lock(), "{}", script)
+}
+
+fn setup_file_system(fs: &mut fs::FileSystem, args: &clap::ArgMatches) -> Profile {
     let res_dir = Path::new(args.get_one::<String>("RESOURCE_DIR").unwrap());
     info!("Using resources dir: {}", res_dir.display());
 
+    let profile = args.get_one::<String>("game")
+        .map(|s| Profile::from_name(s).unwrap())
+        .unwrap_or_else(|| Profile::detect(res_dir));
+    info!("Using game profile: {:?}", profile);
+
     let mut dat_files = Vec::new();
 
     // Add patchXXX.dat files.
@@ -114,8 +246,10 @@ fn setup_file_system(fs: &mut fs::FileSystem, args: &clap::ArgMatches) {
     }
 
     for dat_file in dat_files.iter().rev() {
-        fs.register_provider(fs::dat::v2::new_provider(dat_file).unwrap());
+        fs.register_provider(fs::dat::new_provider(dat_file, profile.dat_version()).unwrap());
     }
+
+    profile
 }
 
 struct Timer {
@@ -178,6 +312,72 @@ fn log_sdl_info() {
     }
 }
 
+enum AppCanvas {
+    Window(SdlCanvas),
+    Offscreen(OffscreenCanvas),
+}
+
+impl AppCanvas {
+    fn as_mut(&mut self) -> &mut dyn Canvas {
+        match self {
+            Self::Window(v) => v,
+            Self::Offscreen(v) => v,
+        }
+    }
+
+    fn offscreen(&self) -> &OffscreenCanvas {
+        match self {
+            Self::Window(v) => v.inner(),
+            Self::Offscreen(v) => v,
+        }
+    }
+}
+
+fn save_screenshot(canvas: &OffscreenCanvas, path: &Path) {
+    match canvas.save_screenshot(path) {
+        Ok(()) => info!("Saved screenshot to {}", path.display()),
+        Err(e) => error!("couldn't save screenshot to {}: {}", path.display(), e),
+    }
+}
+
+fn init_sdl(mixer: Mixer)
+    -> (sdl2::Sdl, sdl2::EventPump, Box<dyn Output>, sdl2::render::WindowCanvas)
+{
+    log_sdl_info();
+
+    let sdl = sdl2::init().unwrap();
+    let event_pump = sdl.event_pump().unwrap();
+    let video = sdl.video().unwrap();
+    info!("Using video driver: {}", video.current_video_driver());
+
+    let window = video.window("Vault13", 640, 480)
+        .position_centered()
+        .allow_highdpi()
+        .build()
+        .unwrap();
+
+    let sound_output: Box<dyn Output> = match sdl.audio()
+        .and_then(|audio| SdlOutput::new(&audio, mixer))
+    {
+        Ok(v) => Box::new(v),
+        Err(e) => {
+            warn!("couldn't open audio device, sound is disabled: {}", e);
+            Box::new(NullOutput::new())
+        }
+    };
+
+    let mouse = sdl.mouse();
+    mouse.set_relative_mouse_mode(true);
+
+    let canvas = window
+        .into_canvas()
+        .build()
+        .unwrap();
+    info!("Using render driver: {}", canvas.info().name);
+
+    (sdl, event_pump, sound_output, canvas)
+}
+
 fn main() {
     unsafe {
         std::env::set_var("RUST_BACKTRACE", "1");
@@ -193,51 +393,125 @@ fn main() {
 
     let mut fs = fs::FileSystem::new();
 
-    let map_name: String;
+    let profile: Profile;
+    let map_name: Option<String>;
+    let load_dir: Option<PathBuf>;
+    let save_dir: PathBuf;
+    let headless: bool;
+    let ticks: u64;
+    let mut input = InputScript::new();
+    let screenshot_path: Option<PathBuf>;
+    let mut record: Option<(u64, PathBuf)> = None;
+    let mut script_debugger: Option<vm::debug::Debugger> = None;
     {
         let args = &args().get_matches();
 
-        setup_file_system(&mut fs, args);
+        if let Some(args) = args.subcommand_matches("disasm") {
+            if let Err(e) = disasm(args) {
+                error!("couldn't disassemble {}: {}", args.get_one::<String>("SCRIPT").unwrap(), e);
+            }
+            return;
+        }
+        if let Some(args) = args.subcommand_matches("decompile") {
+            if let Err(e) = decompile(args) {
+                error!("couldn't decompile {}: {}", args.get_one::<String>("SCRIPT").unwrap(), e);
+            }
+            return;
+        }
 
-        let s = args.get_one::<String>("MAP").unwrap().to_lowercase();
-        map_name = if s.ends_with(".map") {
-            s[..s.len() - 4].into()
-        } else {
-            s
-        };
-    }
+        profile = setup_file_system(&mut fs, args);
 
-    let language = "english";
+        map_name = args.get_one::<String>("MAP").map(|s| {
+            let s = s.to_lowercase();
+            if s.ends_with(".map") {
+                s[..s.len() - 4].into()
+            } else {
+                s
+            }
+        });
+        load_dir = args.get_one::<String>("load").map(PathBuf::from);
+        save_dir = [args.get_one::<String>("RESOURCE_DIR").unwrap(), "data", "savegame"]
+            .iter().collect();
+
+        headless = args.get_flag("headless");
+        ticks = args.get_one::<u64>("ticks").copied().unwrap_or(600);
+        if let Some(path) = args.get_one::<String>("input") {
+            match std::fs::read_to_string(path).and_then(|s| InputScript::parse(&s)) {
+                Ok(v) => input = v,
+                Err(e) => {
+                    error!("couldn't read input script {}: {}", path, e);
+                    return;
+                }
+            }
+        }
+        screenshot_path = args.get_one::<String>("screenshot").map(PathBuf::from);
+        if let Some(path) = &screenshot_path
+            && ImageFormat::from_path(path).is_none()
+        {
+            error!("unsupported screenshot file format: {}", path.display());
+            return;
+        }
+        if let Some(mut v) = args.get_many::<String>("record") {
+            let Ok(frame_count) = v.next().unwrap().parse() else {
+                error!("bad number of frames to record");
+                return;
+            };
+            let dir = PathBuf::from(v.next().unwrap());
+            if let Err(e) = std::fs::create_dir_all(&dir) {
+                error!("couldn't create {}: {}", dir.display(), e);
+                return;
+            }
+            record = Some((frame_count, dir));
+        }
 
-    let fs = Rc::new(fs);
+        if args.get_flag("debug-scripts") {
+            let mut debugger = vm::debug::Debugger::new(Box::new(vm::debug::StdioFrontend));
+            for loc in args.get_many::<String>("break").into_iter().flatten() {
+                let Some(bp) = vm::debug::Breakpoint::parse(loc) else {
+                    error!("bad breakpoint location: {}", loc);
+                    return;
+                };
+                debugger.add_breakpoint(bp);
+            }
+            script_debugger = Some(debugger);
+        }
 
-    let proto_db = Rc::new(ProtoDb::new(fs.clone(), language).unwrap());
+        if headless {
+            util::random::set_seed(args.get_one::<u64>("seed").copied().unwrap_or(0));
+        }
 
-    let pal = read_palette(&mut fs.reader("color.pal").unwrap()).unwrap();
+        if let Some(mut v) = args.get_many::<String>("dump-movie") {
+            let movie = v.next().unwrap();
+            let out_dir = Path::new(v.next().unwrap());
+            match fs.reader(movie).and_then(|rd| asset::movie::dump(rd, out_dir)) {
+                Ok(frame_count) => info!("Dumped {} frames of {} into {}",
+                    frame_count, movie, out_dir.display()),
+                Err(e) => error!("couldn't dump movie {}: {}", movie, e),
+            }
+            return;
+        }
+    }
 
-    log_sdl_info();
+    let language = "english";
 
-    let sdl = sdl2::init().unwrap();
-    let mut event_pump = sdl.event_pump().unwrap();
-    let video = sdl.video().unwrap();
-    info!("Using video driver: {}", video.current_video_driver());
+    let fs = Rc::new(fs);
 
-    let window = video.window("Vault13", 640, 480)
-        .position_centered()
-        .allow_highdpi()
-        .build()
-        .unwrap();
+    let proto_db = Rc::new(ProtoDb::new(fs.clone(), language, profile).unwrap());
 
-    let mouse = sdl.mouse();
-    mouse.set_relative_mouse_mode(true);
+    let pal = read_palette(&mut fs.reader("color.pal").unwrap()).unwrap();
 
-    let canvas = window
-        .into_canvas()
-        .build()
-        .unwrap();
-    info!("Using render driver: {}", canvas.info().name);
+    let mixer = Mixer::new();
+    let (_sdl, mut event_pump, sound_output, window_canvas) = if headless {
+        info!("Running headless for {} ticks", ticks);
+        let sound_output: Box<dyn Output> = Box::new(NullOutput::new());
+        (None, None, sound_output, None)
+    } else {
+        let (sdl, event_pump, sound_output, window_canvas) = init_sdl(mixer.clone());
+        (Some(sdl), Some(event_pump), sound_output, Some(window_canvas))
+    };
+    let sound = Sound::new(fs.clone(), mixer, sound_output);
 
-    let gfx_backend: Backend = Backend::new(canvas, Box::new(pal), PaletteOverlay::standard());
+    let gfx_backend = Backend::new(Box::new(pal), PaletteOverlay::standard());
     let texture_factory = gfx_backend.new_texture_factory();
 
     let frm_db = Rc::new(FrameDb::new(fs.clone(), language, texture_factory.clone()).unwrap());
@@ -255,65 +529,149 @@ fn main() {
 
     let fonts = Rc::new(load_fonts(&fs, &texture_factory));
 
-    let mut canvas = gfx_backend.into_canvas(fonts.clone());
-    let canvas = canvas.as_mut();
+    let canvas = gfx_backend.into_canvas(640, 480, fonts.clone());
+    let app_canvas = if let Some(window_canvas) = window_canvas {
+        AppCanvas::Window(SdlCanvas::new(window_canvas, canvas))
+    } else {
+        AppCanvas::Offscreen(canvas)
+    };
 
     let start = Instant::now();
-    let mut timer = Timer::new(start);
 
-    let ui = &mut Ui::new(frm_db.clone(), fonts.clone(), 640, 480);
+    let mut ui = Ui::new(frm_db.clone(), fonts.clone(), 640, 480);
     ui.set_cursor(ui::Cursor::Arrow);
     ui.set_cursor_pos(Point::new(640 / 2, 480 / 2));
 
     let misc_msgs = Rc::new(Messages::read_file(&fs, language, "game/misc.msg").unwrap());
     let mut state = GameState::new(
+        profile,
         fs,
         language,
         proto_db,
         frm_db,
         fonts,
         misc_msgs,
+        save_dir,
         start,
-        ui,
+        sound,
+        &mut ui,
     );
+    if let Some(debugger) = script_debugger {
+        state.set_script_debugger(debugger);
+    }
 
-    state.new_game();
-    state.switch_map(&map_name, ui);
+    if let Some(load_dir) = &load_dir {
+        if let Err(e) = state.load_game(load_dir, &mut ui) {
+            error!("couldn't load game from {}: {}", load_dir.display(), e);
+            std::process::exit(1);
+        }
+    } else if let Some(map_name) = &map_name {
+        state.new_game();
+        state.switch_map(map_name, &mut ui);
+    } else {
+        state.show_main_menu(true, &mut ui);
+    }
+
+    let mut app = App::new(state, ui, app_canvas, start);
+    app.record = record;
 
-    let mut draw_debug = true;
+    if headless {
+        let tick = headless::run(&mut app, &mut input, ticks);
+        if app.state.in_game() {
+            let world = app.state.world().borrow();
+            let dude = world.objects().get(world.objects().dude());
+            info!("Simulated {} ticks, game time: {:?}, dude at {:?} facing {:?}",
+                tick, world.game_time, dude.try_pos(), dude.direction);
+        }
+    } else {
+        let event_pump = event_pump.as_mut().unwrap();
+        loop {
+            let events: Vec<_> = event_pump.poll_iter().collect();
+            if !app.frame(events) {
+                break;
+            }
+            std::thread::sleep(FRAME_TIME);
+            app.timer.tick(Instant::now());
+        }
+    }
+
+    if let Some(path) = &screenshot_path {
+        save_screenshot(app.canvas.offscreen(), path);
+    }
+}
+
+/// State of the main loop shared by the windowed and headless modes.
+struct App {
+    state: GameState,
+    ui: Ui,
+    canvas: AppCanvas,
+    timer: Timer,
+    draw_debug: bool,
+    take_screenshot: bool,
+    /// Number of the first frames to save and the directory to save them to.
+    record: Option<(u64, PathBuf)>,
+    ui_commands: Vec<UiCommand>,
+    app_events: Vec<AppEvent>,
+    /// Number of frames run so far.
+    tick: u64,
+}
+
+impl App {
+    fn new(state: GameState, ui: Ui, canvas: AppCanvas, start: Instant) -> Self {
+        Self {
+            state,
+            ui,
+            canvas,
+            timer: Timer::new(start),
+            draw_debug: true,
+            take_screenshot: false,
+            record: None,
+            ui_commands: Vec::new(),
+            app_events: Vec::new(),
+            tick: 0,
+        }
+    }
 
-    let ui_commands = &mut Vec::new();
-    let app_events = &mut Vec::new();
+    /// Handles the input `events`, updates and renders a single frame.
+    /// Returns `false` if the app should quit.
+    fn frame(&mut self, events: Vec<Event>) -> bool {
+        let ui = &mut self.ui;
 
-    'running: loop {
         // Handle app events.
 
-        for event in app_events.drain(..) {
-            state.handle_app_event(HandleAppEvent {
+        for event in self.app_events.drain(..) {
+            if event == AppEvent::Quit {
+                return false;
+            }
+            self.state.handle_app_event(HandleAppEvent {
                 event,
                 ui,
+                screen: self.canvas.offscreen(),
             });
         }
 
         // Handle input.
 
-        for event in event_pump.poll_iter() {
+        for event in events {
             let mut handled = ui.handle_input(ui::HandleInput {
-                now: timer.time(),
+                now: self.timer.time(),
                 event: &event,
-                out: ui_commands,
+                out: &mut self.ui_commands,
             });
             if !handled {
-                handled = state.handle_input(&event, ui);
+                handled = self.state.handle_input(&event, ui);
             }
             if !handled {
                 match event {
                     Event::KeyDown { keycode: Some(Keycode::Backquote), .. } => {
-                        draw_debug = !draw_debug;
+                        self.draw_debug = !self.draw_debug;
+                    }
+                    Event::KeyDown { keycode: Some(Keycode::F12), .. } => {
+                        self.take_screenshot = true;
                     }
                     Event::Quit { .. } | Event::KeyDown { keycode: Some(Keycode::Escape), .. } => {
-                        break 'running
-                    },
+                        return false;
+                    }
                     _ => {}
                 }
             }
@@ -321,22 +679,24 @@ fn main() {
 
         // Update.
 
-        ui.update(timer.time(), ui_commands);
+        ui.update(self.timer.time(), &mut self.ui_commands);
 
-        for event in ui_commands.drain(..) {
-            state.handle_ui_command(event, ui);
+        for event in self.ui_commands.drain(..) {
+            self.state.handle_ui_command(event, ui);
         }
 
-        state.update(Update {
-            time: timer.time(),
-            delta: timer.delta(),
+        self.state.update(Update {
+            time: self.timer.time(),
+            delta: self.timer.delta(),
             ui,
-            out: app_events,
+            out: &mut self.app_events,
         });
 
         ui.sync();
 
-        canvas.update(timer.time());
+        let canvas = self.canvas.as_mut();
+        canvas.set_brightness(self.state.brightness());
+        canvas.update(self.timer.time());
 
         // Render
 
@@ -344,50 +704,68 @@ fn main() {
 
         ui.render(canvas);
 
-        if draw_debug {
-            let world = state.world().borrow();
-            let world_view = ui.widget_ref::<WorldView>(state.world_view());
-            let (mouse_hex_pos, mouse_sqr_pos) = if let Some(EPoint { point, .. }) = world_view.hex_cursor_pos() {
-                (point, world.camera().sqr().screen_to_tile(
-                    world.camera().hex().center_to_screen(point)))
-            } else {
-                (Point::new(-1, -1), Point::new(-1, -1))
-            };
-            let (dude_pos, dude_dir) = {
-                let dude_obj = world.objects().get(world.objects().dude());
-                (dude_obj.pos().point, dude_obj.direction)
-            };
-            let msg = format!(
-                "mouse: {}, {}\n\
-                 mouse hex: {}, {} ({})\n\
-                 mouse sqr: {}, {} ({})\n\
-                 dude pos: {}, {} ({}) {:?}\n\
-                 ambient: 0x{:x}\n\
-                 paused: {}",
-                ui.cursor_pos().x, ui.cursor_pos().y,
-                mouse_hex_pos.x, mouse_hex_pos.y,
-                world.hex_grid().rect_to_linear_inv(mouse_hex_pos).map(|v| v.to_string()).unwrap_or_else(|| "N/A".into()),
-                mouse_sqr_pos.x, mouse_sqr_pos.y,
-                sqr::TileGrid::default().rect_to_linear_inv(mouse_sqr_pos).map(|v| v.to_string()).unwrap_or_else(|| "N/A".into()),
-                dude_pos.x, dude_pos.y,
-                world.hex_grid().rect_to_linear_inv(dude_pos).map(|v| v.to_string()).unwrap_or_else(|| "N/A".into()),
-                dude_dir,
-                world.ambient_light,
-                state.time().is_paused(),
-            );
-            canvas.draw_text(msg.as_bytes().into(), Point::new(2, 1), FontKey::antialiased(1), GREEN,
-                &font::DrawOptions {
-                    dst_color: Some(BLACK),
-                    outline: Some(graphics::render::Outline::Fixed { color: BLACK, trans_color: None }),
-                    .. Default::default()
-                });
+        if self.draw_debug && self.state.in_game() {
+            draw_debug_info(&self.state, ui, canvas);
+        }
+
+        if self.take_screenshot {
+            self.take_screenshot = false;
+            if let Some(path) = next_screenshot_path(Path::new("."), ImageFormat::Png) {
+                save_screenshot(self.canvas.offscreen(), &path);
+            }
+        }
+        if let Some((frame_count, dir)) = &self.record
+            && self.tick < *frame_count
+        {
+            save_screenshot(self.canvas.offscreen(),
+                &dir.join(format!("{:05}.png", self.tick)));
         }
 
+        let canvas = self.canvas.as_mut();
         canvas.present();
         canvas.cleanup();
 
-        std::thread::sleep(Duration::new(0, 1_000_000_000u32 / 60));
+        self.tick += 1;
 
-        timer.tick(Instant::now());
+        true
     }
 }
+
+fn draw_debug_info(state: &GameState, ui: &Ui, canvas: &mut dyn Canvas) {
+    let world = state.world().borrow();
+    let world_view = ui.widget_ref::<WorldView>(state.world_view());
+    let (mouse_hex_pos, mouse_sqr_pos) = if let Some(EPoint { point, .. }) = world_view.hex_cursor_pos() {
+        (point, world.camera().sqr().screen_to_tile(
+            world.camera().hex().center_to_screen(point)))
+    } else {
+        (Point::new(-1, -1), Point::new(-1, -1))
+    };
+    let (dude_pos, dude_dir) = {
+        let dude_obj = world.objects().get(world.objects().dude());
+        (dude_obj.pos().point, dude_obj.direction)
+    };
+    let msg = format!(
+        "mouse: {}, {}\n\
+         mouse hex: {}, {} ({})\n\
+         mouse sqr: {}, {} ({})\n\
+         dude pos: {}, {} ({}) {:?}\n\
+         ambient: 0x{:x}\n\
+         paused: {}",
+        ui.cursor_pos().x, ui.cursor_pos().y,
+        mouse_hex_pos.x, mouse_hex_pos.y,
+        world.hex_grid().rect_to_linear_inv(mouse_hex_pos).map(|v| v.to_string()).unwrap_or_else(|| "N/A".into()),
+        mouse_sqr_pos.x, mouse_sqr_pos.y,
+        sqr::TileGrid::default().rect_to_linear_inv(mouse_sqr_pos).map(|v| v.to_string()).unwrap_or_else(|| "N/A".into()),
+        dude_pos.x, dude_pos.y,
+        world.hex_grid().rect_to_linear_inv(dude_pos).map(|v| v.to_string()).unwrap_or_else(|| "N/A".into()),
+        dude_dir,
+        world.ambient_light,
+        state.time().is_paused(),
+    );
+    canvas.draw_text(msg.as_bytes().into(), Point::new(2, 1), FontKey::antialiased(1), GREEN,
+        &font::DrawOptions {
+            dst_color: Some(BLACK),
+            outline: Some(graphics::render::Outline::Fixed { color: BLACK, trans_color: None }),
+            .. Default::default()
+        });
+}
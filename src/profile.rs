@@ -0,0 +1,65 @@
+use log::*;
+use std::fs::File;
+use std::path::Path;
+
+use crate::fs::dat;
+
+/// Game the resources come from. Decides the formats of the data files and the set of script
+/// opcodes.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Profile {
+    Fallout1,
+    Fallout2,
+}
+
+impl Profile {
+    /// Detects the game by the format of `master.dat` in `res_dir`. Falls back to Fallout 2 if
+    /// there's no readable `master.dat`.
+    pub fn detect(res_dir: &Path) -> Self {
+        let path = res_dir.join("master.dat");
+        match File::open(&path).and_then(|mut f| dat::detect_version(&mut f)) {
+            Ok(dat::Version::V1) => Self::Fallout1,
+            Ok(dat::Version::V2) => Self::Fallout2,
+            Err(e) => {
+                info!("couldn't detect game by {}, assuming Fallout 2: {}", path.display(), e);
+                Self::Fallout2
+            }
+        }
+    }
+
+    pub fn from_name(s: &str) -> Option<Self> {
+        Some(match s {
+            "fo1" | "fallout1" => Self::Fallout1,
+            "fo2" | "fallout2" => Self::Fallout2,
+            _ => return None,
+        })
+    }
+
+    /// Returns the game the map file of `version` comes from.
+    pub fn from_map_version(version: u32) -> Option<Self> {
+        Some(match version {
+            19 => Self::Fallout1,
+            20 => Self::Fallout2,
+            _ => return None,
+        })
+    }
+
+    pub fn dat_version(self) -> dat::Version {
+        match self {
+            Self::Fallout1 => dat::Version::V1,
+            Self::Fallout2 => dat::Version::V2,
+        }
+    }
+
+    pub fn map_version(self) -> u32 {
+        match self {
+            Self::Fallout1 => 19,
+            Self::Fallout2 => 20,
+        }
+    }
+
+    /// Whether the critter protos store the damage kind of unarmed attacks.
+    pub fn has_critter_damage_kind(self) -> bool {
+        self == Self::Fallout2
+    }
+}
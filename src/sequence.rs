@@ -1,6 +1,7 @@
 pub mod cancellable;
 pub mod chain;
 pub mod event;
+pub mod wait;
 
 use std::time::Instant;
 
@@ -5,11 +5,22 @@ use super::*;
 
 #[derive(Clone, Debug)]
 pub enum Event {
+    Attack {
+        attack: crate::game::combat::Attack,
+    },
     ObjectMoved {
         obj: object::Handle,
         old_pos: EPoint,
         new_pos: EPoint,
     },
+    PickUp {
+        picker: object::Handle,
+        item: object::Handle,
+    },
+    PlaySfx {
+        obj: object::Handle,
+        name: String,
+    },
     SetDoorState {
         door: object::Handle,
         open: bool,
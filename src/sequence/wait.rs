@@ -0,0 +1,29 @@
+use std::time::{Duration, Instant};
+
+use super::*;
+
+/// Does nothing for the `duration` counted from the first update.
+pub struct Wait {
+    duration: Duration,
+    until: Option<Instant>,
+}
+
+impl Wait {
+    pub fn new(duration: Duration) -> Self {
+        Self {
+            duration,
+            until: None,
+        }
+    }
+}
+
+impl Sequence for Wait {
+    fn update(&mut self, ctx: &mut Update) -> Result {
+        let until = *self.until.get_or_insert(ctx.time + self.duration);
+        if ctx.time >= until {
+            Result::Done
+        } else {
+            Result::Running(Running::NotLagging)
+        }
+    }
+}
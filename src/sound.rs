@@ -0,0 +1,478 @@
+pub mod output;
+
+use linearize::{static_map, Linearize, StaticMap};
+use log::*;
+use std::cmp;
+use std::collections::{HashMap, VecDeque};
+use std::io;
+use std::rc::Rc;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use crate::asset::lip::{read_lips, Lips};
+use crate::asset::sound::{read_acm, Pcm};
+use crate::fs::FileSystem;
+use output::Output;
+
+/// Sample rate of the mixed output.
+pub const SAMPLE_RATE: u32 = 22050;
+
+/// Number of interleaved channels in the mixed output.
+pub const CHANNELS: usize = 2;
+
+#[derive(Clone, Copy, Debug, Eq, Hash, Linearize, PartialEq)]
+pub enum Channel {
+    Music,
+    Ambient,
+    Sfx,
+    Speech,
+}
+
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct VoiceId(u32);
+
+impl VoiceId {
+    pub fn from_raw(v: u32) -> Self {
+        Self(v)
+    }
+
+    pub fn into_raw(self) -> u32 {
+        self.0
+    }
+}
+
+/// Mixes the playing voices into the 16-bit stereo stream. Cloned values refer to the same
+/// mixer so it can be shared with the output running on the audio thread.
+#[derive(Clone)]
+pub struct Mixer(Arc<Mutex<MixerInner>>);
+
+impl Mixer {
+    pub fn new() -> Self {
+        Self(Arc::new(Mutex::new(MixerInner {
+            voices: Vec::new(),
+            volumes: static_map! { _ => 1.0 },
+            master_volume: 1.0,
+            last_id: 0,
+            buf: Vec::new(),
+        })))
+    }
+
+    pub fn play(&self, channel: Channel, pcm: Arc<Pcm>, looping: bool, volume: f32) -> VoiceId {
+        let sample_rate = pcm.sample_rate;
+        self.add_voice(channel, Source::Pcm(pcm), sample_rate, looping, volume)
+    }
+
+    /// Starts a voice playing the samples fed with `queue()`. The voice keeps playing silence
+    /// when it runs out of samples until it's stopped.
+    pub fn play_stream(&self, channel: Channel, channels: u16, sample_rate: u32, volume: f32)
+        -> VoiceId
+    {
+        self.add_voice(channel, Source::Stream {
+            channels,
+            samples: VecDeque::new(),
+        }, sample_rate, false, volume)
+    }
+
+    /// Appends interleaved `samples` to the stream voice started with `play_stream()`.
+    pub fn queue(&self, id: VoiceId, samples: &[i16]) {
+        self.with_voice(id, |v| if let Source::Stream { samples: q, .. } = &mut v.source {
+            q.extend(samples);
+        });
+    }
+
+    pub fn is_playing(&self, id: VoiceId) -> bool {
+        self.0.lock().unwrap().voices.iter().any(|v| v.id == id)
+    }
+
+    pub fn stop(&self, id: VoiceId) {
+        self.0.lock().unwrap().voices.retain(|v| v.id != id);
+    }
+
+    pub fn stop_channel(&self, channel: Channel) {
+        self.0.lock().unwrap().voices.retain(|v| v.channel != channel);
+    }
+
+    pub fn set_paused(&self, id: VoiceId, paused: bool) {
+        self.with_voice(id, |v| v.paused = paused);
+    }
+
+    pub fn rewind(&self, id: VoiceId) {
+        self.with_voice(id, |v| v.pos = 0);
+    }
+
+    pub fn volume(&self, channel: Channel) -> f32 {
+        self.0.lock().unwrap().volumes[channel]
+    }
+
+    pub fn set_volume(&self, channel: Channel, volume: f32) {
+        self.0.lock().unwrap().volumes[channel] = volume;
+    }
+
+    pub fn set_master_volume(&self, volume: f32) {
+        self.0.lock().unwrap().master_volume = volume;
+    }
+
+    /// Mixes `out.len() / CHANNELS` frames advancing the playing voices.
+    pub fn mix(&self, out: &mut [i16]) {
+        let mut inner = self.0.lock().unwrap();
+        let inner = &mut *inner;
+
+        inner.buf.clear();
+        inner.buf.resize(out.len(), 0);
+
+        for voice in &mut inner.voices {
+            if voice.paused {
+                continue;
+            }
+            let volume = voice.volume * inner.volumes[voice.channel] * inner.master_volume;
+            voice.mix(&mut inner.buf, (volume.clamp(0.0, 1.0) * 256.0) as i32);
+        }
+        inner.voices.retain(|v| !v.done);
+
+        for (dst, &src) in out.iter_mut().zip(&inner.buf) {
+            *dst = (src >> 8).clamp(i16::MIN as i32, i16::MAX as i32) as i16;
+        }
+    }
+
+    fn add_voice(&self, channel: Channel, source: Source, sample_rate: u32, looping: bool,
+        volume: f32) -> VoiceId
+    {
+        let mut inner = self.0.lock().unwrap();
+        inner.last_id = inner.last_id.checked_add(1).unwrap_or(1);
+        let id = VoiceId(inner.last_id);
+        let step = ((if sample_rate > 0 { sample_rate } else { SAMPLE_RATE } as u64) << 16)
+            / SAMPLE_RATE as u64;
+        inner.voices.push(Voice {
+            id,
+            channel,
+            source,
+            pos: 0,
+            step,
+            looping,
+            paused: false,
+            volume,
+            done: false,
+        });
+        id
+    }
+
+    fn with_voice(&self, id: VoiceId, f: impl FnOnce(&mut Voice)) {
+        if let Some(v) = self.0.lock().unwrap().voices.iter_mut().find(|v| v.id == id) {
+            f(v);
+        }
+    }
+}
+
+impl Default for Mixer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+struct MixerInner {
+    voices: Vec<Voice>,
+    volumes: StaticMap<Channel, f32>,
+    master_volume: f32,
+    last_id: u32,
+    buf: Vec<i32>,
+}
+
+enum Source {
+    Pcm(Arc<Pcm>),
+    Stream {
+        channels: u16,
+        samples: VecDeque<i16>,
+    },
+}
+
+struct Voice {
+    id: VoiceId,
+    channel: Channel,
+    source: Source,
+    /// Position in source frames in 16.16 fixed point.
+    pos: u64,
+    step: u64,
+    looping: bool,
+    paused: bool,
+    volume: f32,
+    done: bool,
+}
+
+impl Voice {
+    fn mix(&mut self, out: &mut [i32], gain: i32) {
+        match &mut self.source {
+            Source::Pcm(pcm) => {
+                let frame_count = pcm.frame_count() as u64;
+                if frame_count == 0 {
+                    self.done = true;
+                    return;
+                }
+                for frame in out.chunks_exact_mut(CHANNELS) {
+                    if self.pos >> 16 >= frame_count {
+                        if self.looping {
+                            self.pos %= frame_count << 16;
+                        } else {
+                            break;
+                        }
+                    }
+                    mix_frame(frame, &pcm.samples, pcm.channels, self.pos, gain);
+                    self.pos += self.step;
+                }
+                self.done = !self.looping && self.pos >> 16 >= frame_count;
+            }
+            Source::Stream { channels, samples } => {
+                let channels = *channels;
+                let samples = samples.make_contiguous();
+                let frame_count = (samples.len() / channels as usize) as u64;
+                for frame in out.chunks_exact_mut(CHANNELS) {
+                    if self.pos >> 16 >= frame_count {
+                        break;
+                    }
+                    mix_frame(frame, samples, channels, self.pos, gain);
+                    self.pos += self.step;
+                }
+            }
+        }
+        // Drop the played samples of the stream.
+        if let Source::Stream { channels, samples } = &mut self.source {
+            let frames = cmp::min(self.pos >> 16, (samples.len() / *channels as usize) as u64);
+            samples.drain(..frames as usize * *channels as usize);
+            self.pos -= frames << 16;
+        }
+    }
+}
+
+fn mix_frame(out: &mut [i32], samples: &[i16], channels: u16, pos: u64, gain: i32) {
+    let i = (pos >> 16) as usize * channels as usize;
+    let (l, r) = if channels > 1 {
+        (samples[i], samples[i + 1])
+    } else {
+        (samples[i], samples[i])
+    };
+    out[0] += l as i32 * gain;
+    out[1] += r as i32 * gain;
+}
+
+/// Plays sounds from the game data through the mixer.
+pub struct Sound {
+    fs: Rc<FileSystem>,
+    mixer: Mixer,
+    output: Box<dyn Output>,
+    cache: HashMap<String, Option<Arc<Pcm>>>,
+    music: Option<(String, VoiceId)>,
+}
+
+impl Sound {
+    pub fn new(fs: Rc<FileSystem>, mixer: Mixer, output: Box<dyn Output>) -> Self {
+        Self {
+            fs,
+            mixer,
+            output,
+            cache: HashMap::new(),
+            music: None,
+        }
+    }
+
+    pub fn mixer(&self) -> &Mixer {
+        &self.mixer
+    }
+
+    /// Plays the sound file at `path`. Decoded sounds are cached.
+    /// Returns `None` if the file couldn't be loaded.
+    pub fn play(&mut self, channel: Channel, path: &str, looping: bool, volume: f32)
+        -> Option<VoiceId>
+    {
+        let key = path.to_ascii_lowercase();
+        let pcm = if let Some(pcm) = self.cache.get(&key) {
+            pcm.clone()
+        } else {
+            let pcm = self.load(path)
+                .map_err(|e| warn!("couldn't load sound `{}`: {}", path, e))
+                .ok()
+                .map(Arc::new);
+            self.cache.insert(key, pcm.clone());
+            pcm
+        };
+        Some(self.mixer.play(channel, pcm?, looping, volume))
+    }
+
+    /// Plays sound effect by its name like the ones built by `game::sfx`.
+    pub fn play_sfx(&mut self, name: &str, volume: f32) -> Option<VoiceId> {
+        self.play(Channel::Sfx, &format!("sound/sfx/{}.acm", name), false, volume)
+    }
+
+    pub fn play_ambient(&mut self, name: &str) -> Option<VoiceId> {
+        self.play(Channel::Ambient, &format!("sound/sfx/{}.acm", name), false, 1.0)
+    }
+
+    pub fn play_speech(&mut self, name: &str) -> Option<VoiceId> {
+        self.mixer.stop_channel(Channel::Speech);
+        self.play(Channel::Speech, &format!("sound/speech/{}.acm", name), false, 1.0)
+    }
+
+    /// Loads lip sync data for the speech `name`. Returns `None` if there's no such data.
+    pub fn load_lips(&self, name: &str) -> Option<Lips> {
+        let path = format!("sound/speech/{}.lip", name);
+        let mut rd = self.fs.reader(&path).ok()?;
+        read_lips(&mut rd)
+            .map_err(|e| warn!("couldn't load lips `{}`: {}", path, e))
+            .ok()
+    }
+
+    pub fn music(&self) -> Option<&str> {
+        self.music.as_ref().map(|(n, _)| &n[..])
+    }
+
+    /// Starts looping the music track `name` replacing the current one. Does nothing if the
+    /// track is already playing. Music tracks aren't cached.
+    pub fn play_music(&mut self, name: &str) {
+        if let Some((cur, id)) = &self.music
+            && cur.eq_ignore_ascii_case(name) && self.mixer.is_playing(*id)
+        {
+            return;
+        }
+        self.stop_music();
+        let path = format!("sound/music/{}.acm", name);
+        match self.load(&path) {
+            Ok(pcm) => {
+                let id = self.mixer.play(Channel::Music, Arc::new(pcm), true, 1.0);
+                self.music = Some((name.into(), id));
+            }
+            Err(e) => warn!("couldn't load music `{}`: {}", path, e),
+        }
+    }
+
+    pub fn stop_music(&mut self) {
+        if let Some((_, id)) = self.music.take() {
+            self.mixer.stop(id);
+        }
+    }
+
+    pub fn set_music_paused(&mut self, paused: bool) {
+        if let Some((_, id)) = &self.music {
+            self.mixer.set_paused(*id, paused);
+        }
+    }
+
+    pub fn update(&mut self, time: Instant) {
+        if let Err(e) = self.output.update(&self.mixer, time) {
+            warn!("sound output error: {}", e);
+        }
+    }
+
+    fn load(&self, path: &str) -> io::Result<Pcm> {
+        read_acm(self.fs.reader(path)?)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::fs::memory::MemoryFileSystem;
+    use crate::util::test::acm;
+    use output::NullOutput;
+
+    fn pcm(channels: u16, samples: &[i16]) -> Arc<Pcm> {
+        Arc::new(Pcm {
+            channels,
+            sample_rate: SAMPLE_RATE,
+            samples: samples.into(),
+        })
+    }
+
+    fn mix(mixer: &Mixer, frames: usize) -> Vec<i16> {
+        let mut r = vec![0; frames * CHANNELS];
+        mixer.mix(&mut r);
+        r
+    }
+
+    #[test]
+    fn mix_voices() {
+        let m = Mixer::new();
+        let mono = m.play(Channel::Sfx, pcm(1, &[100, 200]), false, 1.0);
+        let stereo = m.play(Channel::Music, pcm(2, &[1, 2, 3, 4, 5, 6]), false, 1.0);
+        m.set_volume(Channel::Music, 0.0);
+        assert_eq!(mix(&m, 2), &[100, 100, 200, 200]);
+        assert!(!m.is_playing(mono));
+        assert!(m.is_playing(stereo));
+
+        m.set_volume(Channel::Music, 1.0);
+        m.play(Channel::Sfx, pcm(1, &[i16::MAX]), false, 1.0);
+        assert_eq!(mix(&m, 2), &[i16::MAX, i16::MAX, 0, 0]);
+        assert!(!m.is_playing(stereo));
+    }
+
+    #[test]
+    fn loop_pause_rewind_stop() {
+        let m = Mixer::new();
+        let id = m.play(Channel::Ambient, pcm(1, &[1, 2, 3]), true, 1.0);
+        assert_eq!(mix(&m, 4), &[1, 1, 2, 2, 3, 3, 1, 1]);
+
+        m.set_paused(id, true);
+        assert_eq!(mix(&m, 1), &[0, 0]);
+        m.set_paused(id, false);
+        assert_eq!(mix(&m, 1), &[2, 2]);
+
+        m.rewind(id);
+        assert_eq!(mix(&m, 1), &[1, 1]);
+
+        m.stop_channel(Channel::Ambient);
+        assert!(!m.is_playing(id));
+        assert_eq!(mix(&m, 1), &[0, 0]);
+    }
+
+    #[test]
+    fn resample() {
+        let m = Mixer::new();
+        m.play(Channel::Sfx, Arc::new(Pcm {
+            channels: 1,
+            sample_rate: SAMPLE_RATE / 2,
+            samples: vec![10, 20],
+        }), false, 1.0);
+        assert_eq!(mix(&m, 5), &[10, 10, 10, 10, 20, 20, 20, 20, 0, 0]);
+    }
+
+    #[test]
+    fn stream() {
+        let m = Mixer::new();
+        let id = m.play_stream(Channel::Music, 2, SAMPLE_RATE, 1.0);
+        m.queue(id, &[1, 2, 3, 4]);
+        assert_eq!(mix(&m, 3), &[1, 2, 3, 4, 0, 0]);
+        assert!(m.is_playing(id));
+
+        m.queue(id, &[5, 6]);
+        assert_eq!(mix(&m, 1), &[5, 6]);
+
+        let id = m.play_stream(Channel::Music, 1, SAMPLE_RATE / 2, 1.0);
+        m.queue(id, &[7, 8, 9]);
+        assert_eq!(mix(&m, 3), &[7, 7, 7, 7, 8, 8]);
+        assert_eq!(mix(&m, 3), &[8, 8, 9, 9, 9, 9]);
+    }
+
+    #[test]
+    fn sound_music() {
+        let mut fs = MemoryFileSystem::new();
+        fs.insert("sound/music/07desert.acm", acm(2, 22050, &[1, 2]));
+        fs.insert("sound/sfx/sodoorsa.acm", acm(1, 22050, &[3]));
+        let mut files = FileSystem::new();
+        files.register_provider(Box::new(fs));
+
+        let mixer = Mixer::new();
+        let mut sound = Sound::new(Rc::new(files), mixer.clone(), Box::new(NullOutput::new()));
+
+        sound.play_music("07DESERT");
+        assert_eq!(sound.music(), Some("07DESERT"));
+        let id = sound.music.as_ref().unwrap().1;
+        sound.play_music("07desert");
+        assert!(mixer.is_playing(id));
+        assert_eq!(sound.music.as_ref().unwrap().1, id);
+
+        assert!(sound.play_sfx("SODOORSA", 1.0).is_some());
+        assert!(sound.play_sfx("missing", 1.0).is_none());
+        assert_eq!(mix(&mixer, 2), &[4, 5, 1, 2]);
+
+        sound.play_music("missing");
+        assert_eq!(sound.music(), None);
+        assert!(!mixer.is_playing(id));
+    }
+}
@@ -0,0 +1,198 @@
+use byteorder::{LittleEndian, WriteBytesExt};
+use sdl2::AudioSubsystem;
+use sdl2::audio::{AudioCallback, AudioDevice, AudioSpecDesired};
+use std::fs::File;
+use std::io::{self, prelude::*, BufWriter, SeekFrom};
+use std::path::Path;
+use std::time::Instant;
+
+use crate::asset::sound::{write_wav_header, WAV_HEADER_LEN};
+use super::{Mixer, CHANNELS, SAMPLE_RATE};
+
+pub trait Output {
+    /// Called once per frame by the main loop. Outputs that pull the samples on their own
+    /// (like `SdlOutput`) don't need to do anything here.
+    fn update(&mut self, mixer: &Mixer, time: Instant) -> io::Result<()>;
+}
+
+/// Plays the mixed stream through the SDL audio device.
+pub struct SdlOutput {
+    _device: AudioDevice<SdlCallback>,
+}
+
+impl SdlOutput {
+    pub fn new(audio: &AudioSubsystem, mixer: Mixer) -> Result<Self, String> {
+        let spec = AudioSpecDesired {
+            freq: Some(SAMPLE_RATE as i32),
+            channels: Some(CHANNELS as u8),
+            samples: Some(1024),
+        };
+        let device = audio.open_playback(None, &spec, |_| SdlCallback { mixer })?;
+        device.resume();
+        Ok(Self {
+            _device: device,
+        })
+    }
+}
+
+impl Output for SdlOutput {
+    fn update(&mut self, _mixer: &Mixer, _time: Instant) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+struct SdlCallback {
+    mixer: Mixer,
+}
+
+impl AudioCallback for SdlCallback {
+    type Channel = i16;
+
+    fn callback(&mut self, out: &mut [i16]) {
+        self.mixer.mix(out);
+    }
+}
+
+/// Computes the number of frames due to be mixed by the specified time.
+#[derive(Default)]
+struct Clock {
+    start: Option<Instant>,
+    frames: u64,
+}
+
+impl Clock {
+    fn advance(&mut self, time: Instant) -> usize {
+        let start = *self.start.get_or_insert(time);
+        let elapsed = time.saturating_duration_since(start);
+        let due = (elapsed.as_nanos() * SAMPLE_RATE as u128 / 1_000_000_000) as u64;
+        let r = due.saturating_sub(self.frames);
+        self.frames += r;
+        r as usize
+    }
+}
+
+/// Consumes the mixed stream in the real time (or whatever time is passed to `update()`)
+/// discarding it.
+#[derive(Default)]
+pub struct NullOutput {
+    clock: Clock,
+    buf: Vec<i16>,
+}
+
+impl NullOutput {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl Output for NullOutput {
+    fn update(&mut self, mixer: &Mixer, time: Instant) -> io::Result<()> {
+        let frames = self.clock.advance(time);
+        self.buf.resize(frames * CHANNELS, 0);
+        mixer.mix(&mut self.buf);
+        Ok(())
+    }
+}
+
+/// Writes the mixed stream into a WAV file. The header is kept up to date after every update so
+/// the file is valid even if the program didn't exit cleanly.
+pub struct WavOutput<W: Write + Seek> {
+    writer: W,
+    clock: Clock,
+    buf: Vec<i16>,
+    data_len: u32,
+}
+
+impl WavOutput<BufWriter<File>> {
+    pub fn create(path: &Path) -> io::Result<Self> {
+        Self::new(BufWriter::new(File::create(path)?))
+    }
+}
+
+impl<W: Write + Seek> WavOutput<W> {
+    pub fn new(mut writer: W) -> io::Result<Self> {
+        write_wav_header(&mut writer, CHANNELS as u16, SAMPLE_RATE, 0)?;
+
+        Ok(Self {
+            writer,
+            clock: Clock::default(),
+            buf: Vec::new(),
+            data_len: 0,
+        })
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+
+    fn update_header(&mut self) -> io::Result<()> {
+        self.writer.seek(SeekFrom::Start(4))?;
+        self.writer.write_u32::<LittleEndian>(WAV_HEADER_LEN - 8 + self.data_len)?;
+        self.writer.seek(SeekFrom::Start(WAV_HEADER_LEN as u64 - 4))?;
+        self.writer.write_u32::<LittleEndian>(self.data_len)?;
+        self.writer.seek(SeekFrom::End(0))?;
+        self.writer.flush()
+    }
+}
+
+impl<W: Write + Seek> Output for WavOutput<W> {
+    fn update(&mut self, mixer: &Mixer, time: Instant) -> io::Result<()> {
+        let frames = self.clock.advance(time);
+        if frames == 0 {
+            return Ok(());
+        }
+        self.buf.resize(frames * CHANNELS, 0);
+        mixer.mix(&mut self.buf);
+        for &v in &self.buf {
+            self.writer.write_i16::<LittleEndian>(v)?;
+        }
+        self.data_len += self.buf.len() as u32 * 2;
+        self.update_header()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Cursor;
+    use std::sync::Arc;
+    use std::time::Duration;
+    use crate::asset::sound::Pcm;
+    use crate::sound::Channel;
+
+    #[test]
+    fn clock() {
+        let mut c = Clock::default();
+        let t = Instant::now();
+        assert_eq!(c.advance(t), 0);
+        assert_eq!(c.advance(t + Duration::from_millis(100)), 2205);
+        assert_eq!(c.advance(t + Duration::from_millis(100)), 0);
+        assert_eq!(c.advance(t + Duration::from_secs(1)), 22050 - 2205);
+    }
+
+    #[test]
+    fn wav() {
+        let mixer = Mixer::new();
+        mixer.play(Channel::Sfx, Arc::new(Pcm {
+            channels: 1,
+            sample_rate: SAMPLE_RATE,
+            samples: vec![1, -2],
+        }), false, 1.0);
+
+        let mut out = WavOutput::new(Cursor::new(Vec::new())).unwrap();
+        let t = Instant::now();
+        out.update(&mixer, t).unwrap();
+        out.update(&mixer, t + Duration::from_micros(3 * 1_000_000 / 22050 + 1)).unwrap();
+
+        let data = out.into_inner().into_inner();
+        assert_eq!(data.len(), 44 + 12);
+        assert_eq!(&data[..4], b"RIFF");
+        assert_eq!(&data[4..8], &(36u32 + 12).to_le_bytes());
+        assert_eq!(&data[8..16], b"WAVEfmt ");
+        assert_eq!(&data[22..24], &2u16.to_le_bytes());
+        assert_eq!(&data[24..28], &22050u32.to_le_bytes());
+        assert_eq!(&data[36..40], b"data");
+        assert_eq!(&data[40..44], &12u32.to_le_bytes());
+        assert_eq!(&data[44..], &[1, 0, 1, 0, 0xfe, 0xff, 0xfe, 0xff, 0, 0, 0, 0]);
+    }
+}
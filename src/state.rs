@@ -3,6 +3,7 @@ mod event;
 use sdl2::event::{Event as SdlEvent};
 use std::time::{Duration, Instant};
 
+use crate::graphics::render::software::OffscreenCanvas;
 use crate::ui::Ui;
 use crate::ui::command::UiCommand;
 
@@ -11,6 +12,8 @@ pub use event::AppEvent;
 pub struct HandleAppEvent<'a> {
     pub event: AppEvent,
     pub ui: &'a mut Ui,
+    /// Last rendered frame.
+    pub screen: &'a OffscreenCanvas,
 }
 
 pub struct Update<'a> {
@@ -9,4 +9,10 @@ pub enum AppEvent {
         pos: EPoint,
         direction: Direction,
     },
+    /// Show the options menu. The current screen is captured for the save game thumbnail.
+    ShowOptions,
+    /// The dude died. Ends the game and returns to the main menu.
+    GameOver,
+    /// Exit the application.
+    Quit,
 }
\ No newline at end of file
@@ -2,8 +2,11 @@ pub mod button;
 pub mod command;
 pub mod image_text;
 pub mod message_panel;
+pub mod movie;
 pub mod panel;
+pub mod picture;
 pub mod sequence;
+pub mod slider;
 
 pub use sdl2::mouse::MouseButton;
 pub use sdl2::keyboard::Keycode;
@@ -30,6 +30,17 @@ pub enum UiCommandData {
     Skilldex(SkilldexCommand),
     Inventory(inventory::Command),
     MoveWindow(move_window::Command),
+    MovieDone,
+    WorldMap(world_map::Command),
+    Dialog(dialog::Command),
+    Barter(barter::Command),
+    Loot(loot::Command),
+    CharacterEditor(character_editor::Command),
+    Pipboy(pipboy::Command),
+    MainMenu(main_menu::Command),
+    CharSelect(char_select::Command),
+    SaveLoad(save_load::Command),
+    Options(options::Command),
 }
 
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
@@ -78,6 +89,132 @@ pub mod inventory {
     }
 }
 
+pub mod dialog {
+    #[derive(Clone, Copy, Debug, Eq, PartialEq)]
+    pub enum Command {
+        Review,
+        ReviewDone,
+        Barter,
+    }
+}
+
+pub mod barter {
+    #[derive(Clone, Copy, Debug, Eq, PartialEq)]
+    pub enum Command {
+        /// Offer the trade.
+        Offer,
+        /// Return to the dialog.
+        Talk,
+    }
+}
+
+pub mod loot {
+    #[derive(Clone, Copy, Debug, Eq, PartialEq)]
+    pub enum Command {
+        TakeAll,
+        Done,
+        /// Return from the nested container to its owner.
+        CloseContainer {
+            dude: bool,
+        },
+    }
+}
+
+pub mod character_editor {
+    use crate::asset::{Skill, Stat, Trait};
+    use crate::game::character_editor::Item;
+
+    #[derive(Clone, Copy, Debug, Eq, PartialEq)]
+    pub enum Command {
+        Show,
+        Done,
+        Cancel,
+        /// Show the item in the card.
+        Select(Item),
+        IncStat(Stat),
+        DecStat(Stat),
+        ToggleTrait(Trait),
+        ToggleTag(Skill),
+        /// Spend skill point on the selected skill.
+        IncSkill,
+        /// Return skill point spent on the selected skill.
+        DecSkill,
+        ScrollPerks {
+            up: bool,
+        },
+        /// Add the perk selected in the perk picker.
+        PickPerk,
+        CancelPerk,
+    }
+}
+
+pub mod pipboy {
+    use crate::game::pipboy::Page;
+
+    #[derive(Clone, Copy, Debug, Eq, PartialEq)]
+    pub enum Command {
+        Show,
+        Hide,
+        Page(Page),
+    }
+}
+
+pub mod main_menu {
+    #[derive(Clone, Copy, Debug, Eq, PartialEq)]
+    pub enum Command {
+        Intro,
+        NewGame,
+        LoadGame,
+        Options,
+        Credits,
+        Exit,
+    }
+}
+
+pub mod char_select {
+    #[derive(Clone, Copy, Debug, Eq, PartialEq)]
+    pub enum Command {
+        Prev,
+        Next,
+        Take,
+        Modify,
+        Create,
+        Back,
+    }
+}
+
+pub mod save_load {
+    #[derive(Clone, Copy, Debug, Eq, PartialEq)]
+    pub enum Command {
+        Done,
+        Cancel,
+    }
+}
+
+pub mod options {
+    use crate::game::options::Pref;
+
+    #[derive(Clone, Copy, Debug, Eq, PartialEq)]
+    pub enum Command {
+        Show,
+        SaveGame,
+        LoadGame,
+        Preferences,
+        ExitGame,
+        Done,
+        /// Switch the knob to the next position.
+        Cycle(Pref),
+        /// Set the slider value.
+        Set {
+            pref: Pref,
+            value: i32,
+        },
+        PrefsDefault,
+        PrefsDone,
+        PrefsCancel,
+    }
+}
+
 pub mod move_window {
     #[derive(Clone, Copy, Debug, Eq, PartialEq)]
     pub enum Command {
@@ -88,4 +225,24 @@ pub mod move_window {
         Dec,
         Max,
     }
-}
\ No newline at end of file
+}
+pub mod world_map {
+    use crate::graphics::Point;
+
+    #[derive(Clone, Copy, Debug, Eq, PartialEq)]
+    pub enum Command {
+        /// Travel to the world map position.
+        Travel {
+            pos: Point,
+        },
+        /// Travel to the area.
+        Area {
+            id: u32,
+        },
+        /// Enter the map through the area entrance.
+        Entrance {
+            area: u32,
+            entrance: u32,
+        },
+    }
+}
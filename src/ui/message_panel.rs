@@ -125,6 +125,12 @@ impl MessagePanel {
     }
 
     pub fn push_message(&mut self, message: impl AsRef<bstr>) {
+        self.push_message_with_color(message, None);
+    }
+
+    /// Same as `push_message()` but the message is rendered with `color` instead of the panel's
+    /// default color.
+    pub fn push_message_with_color(&mut self, message: impl AsRef<bstr>, color: Option<Rgb15>) {
         self.ensure_capacity(1);
 
         let message = message.as_ref();
@@ -140,6 +146,7 @@ impl MessagePanel {
         self.messages.push_back(Message {
             text: message.into(),
             line_count: new_lines.len(),
+            color,
         });
         for range in new_lines {
             self.lines.push_back(Line {
@@ -317,6 +324,7 @@ impl Line {
 struct Message {
     text: BString,
     line_count: usize,
+    color: Option<Rgb15>,
 }
 
 #[derive(Clone, Copy, Debug)]
@@ -410,7 +418,7 @@ impl Widget for MessagePanel {
                 let color = if Some(line.message) == self.highlighted {
                     self.highlight_color
                 } else {
-                    self.color
+                    self.messages[line.message].color.unwrap_or(self.color)
                 };
 
                 if last_message.is_some() && Some(line.message) != last_message {
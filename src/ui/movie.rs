@@ -0,0 +1,139 @@
+use log::*;
+use std::io::prelude::*;
+use std::time::Instant;
+
+use crate::asset::movie::MveDecoder;
+use crate::graphics::color::palette::Palette;
+use crate::graphics::render::{TextureFactory, TextureHandle};
+use crate::sound::{Channel, Mixer, VoiceId};
+use super::*;
+use super::command::UiCommandData;
+
+/// Plays MVE movie scaled to the widget rect. Any key or mouse button press skips the movie.
+/// Emits `UiCommandData::MovieDone` when the movie ends or is skipped.
+pub struct Movie {
+    decoder: MveDecoder<Box<dyn Read>>,
+    texture_factory: TextureFactory,
+    mixer: Mixer,
+    voice: Option<VoiceId>,
+    start: Option<Instant>,
+    frame_count: u32,
+    frame_changed: bool,
+    texture: Option<TextureHandle>,
+    done: bool,
+}
+
+impl Movie {
+    pub fn new(decoder: MveDecoder<Box<dyn Read>>, texture_factory: TextureFactory,
+        mixer: Mixer) -> Self
+    {
+        Self {
+            decoder,
+            texture_factory,
+            mixer,
+            voice: None,
+            start: None,
+            frame_count: 0,
+            frame_changed: false,
+            texture: None,
+            done: false,
+        }
+    }
+
+    fn finish(&mut self, ctx: &mut HandleEvent) {
+        if !self.done {
+            self.done = true;
+            if let Some(voice) = self.voice.take() {
+                self.mixer.stop(voice);
+            }
+            ctx.out(UiCommandData::MovieDone);
+        }
+    }
+
+    /// Decodes all frames due by `now`. Returns `false` if the movie has ended.
+    fn advance(&mut self, now: Instant) -> bool {
+        let start = *self.start.get_or_insert(now);
+        let elapsed = now.saturating_duration_since(start);
+        let frame_duration = self.decoder.frame_duration().as_micros().max(1);
+        let due = (elapsed.as_micros() / frame_duration) as u32 + 1;
+        while self.frame_count < due {
+            match self.decoder.next_frame() {
+                Ok(true) => {}
+                Ok(false) => return false,
+                Err(e) => {
+                    warn!("error decoding movie: {}", e);
+                    return false;
+                }
+            }
+            self.frame_count += 1;
+            self.frame_changed = true;
+            self.queue_audio();
+        }
+        true
+    }
+
+    fn queue_audio(&mut self) {
+        let format = if let Some(v) = self.decoder.audio_format() {
+            v
+        } else {
+            return;
+        };
+        let samples = self.decoder.take_audio();
+        let voice = *self.voice.get_or_insert_with(||
+            self.mixer.play_stream(Channel::Music, format.channels, format.sample_rate, 1.0));
+        self.mixer.queue(voice, &samples);
+    }
+
+    fn update_texture(&mut self, palette: &Palette) {
+        let mut colors = [0; 256];
+        for (c, &rgb) in colors.iter_mut().zip(self.decoder.palette()) {
+            *c = palette.color_idx(rgb);
+            // Color 0 is transparent.
+            if *c == 0 {
+                *c = (1..=255)
+                    .min_by_key(|&i| {
+                        let (r1, g1, b1) = palette.rgb18(i).colors_u32();
+                        let (r2, g2, b2) = rgb.colors_u32();
+                        r1.abs_diff(r2).pow(2) + g1.abs_diff(g2).pow(2) + b1.abs_diff(b2).pow(2)
+                    })
+                    .unwrap();
+            }
+        }
+        let pixels: Box<[u8]> = self.decoder.pixels().iter()
+            .map(|&i| colors[i as usize])
+            .collect();
+        self.texture = Some(self.texture_factory.new_texture(
+            self.decoder.width() as i32, self.decoder.height() as i32, pixels));
+    }
+}
+
+impl Widget for Movie {
+    fn handle_event(&mut self, mut ctx: HandleEvent) {
+        if self.done {
+            return;
+        }
+        match ctx.event {
+            Event::Tick if !self.advance(ctx.now) => self.finish(&mut ctx),
+            Event::KeyDown { .. } | Event::MouseDown { .. } => self.finish(&mut ctx),
+            _ => {}
+        }
+    }
+
+    fn render(&mut self, ctx: Render) {
+        if self.frame_changed {
+            self.frame_changed = false;
+            self.update_texture(ctx.canvas.palette());
+        }
+        if let Some(tex) = &self.texture {
+            ctx.canvas.draw_scaled(tex, ctx.base.unwrap().rect());
+        }
+    }
+}
+
+impl Drop for Movie {
+    fn drop(&mut self) {
+        if let Some(voice) = self.voice {
+            self.mixer.stop(voice);
+        }
+    }
+}
@@ -0,0 +1,44 @@
+use crate::graphics::render::{TextureFactory, TextureHandle};
+use super::*;
+
+/// 8-bit palette-indexed picture drawn at the top left corner of the widget rect.
+pub struct Picture {
+    texture_factory: TextureFactory,
+    image: Option<(i32, i32, Box<[u8]>)>,
+    texture: Option<TextureHandle>,
+}
+
+impl Picture {
+    pub fn new(texture_factory: TextureFactory) -> Self {
+        Self {
+            texture_factory,
+            image: None,
+            texture: None,
+        }
+    }
+
+    /// Sets the `width` x `height` picture.
+    pub fn set(&mut self, width: i32, height: i32, pixels: Box<[u8]>) {
+        assert_eq!(pixels.len(), (width * height) as usize);
+        self.image = Some((width, height, pixels));
+        self.texture = None;
+    }
+
+    pub fn clear(&mut self) {
+        self.image = None;
+        self.texture = None;
+    }
+}
+
+impl Widget for Picture {
+    fn handle_event(&mut self, _ctx: HandleEvent) {}
+
+    fn render(&mut self, ctx: Render) {
+        let Some((width, height, pixels)) = &self.image else {
+            return;
+        };
+        let texture = self.texture.get_or_insert_with(||
+            self.texture_factory.new_texture(*width, *height, pixels.clone()));
+        ctx.canvas.draw(texture, ctx.base.unwrap().rect().top_left(), 0x10000);
+    }
+}
@@ -0,0 +1,105 @@
+use sdl2::mouse::MouseButton;
+use std::cmp;
+
+use crate::asset::frame::FrameId;
+use crate::graphics::sprite::Sprite;
+use crate::ui::command::UiCommandData;
+use super::*;
+
+/// Horizontal slider with a thumb that can be dragged along the widget rect. The thumb's left
+/// edge is at the rect left when the value is at `min` and the thumb's right edge is at the rect
+/// right when the value is at `max`. Emits the command made by `command` whenever the value
+/// changes.
+pub struct Slider {
+    thumb_up: FrameId,
+    thumb_down: FrameId,
+    thumb_width: i32,
+    min: i32,
+    max: i32,
+    value: i32,
+    command: fn(i32) -> UiCommandData,
+    /// Offset of the cursor from the thumb's left edge while dragging.
+    grab: Option<i32>,
+}
+
+impl Slider {
+    pub fn new(frm_db: &FrameDb, thumb_up: FrameId, thumb_down: FrameId, min: i32, max: i32,
+        command: fn(i32) -> UiCommandData) -> Self
+    {
+        assert!(min < max);
+        Self {
+            thumb_up,
+            thumb_down,
+            thumb_width: frm_db.get(thumb_up).unwrap().first().width,
+            min,
+            max,
+            value: min,
+            command,
+            grab: None,
+        }
+    }
+
+    pub fn value(&self) -> i32 {
+        self.value
+    }
+
+    pub fn set_value(&mut self, value: i32) {
+        self.value = cmp::min(cmp::max(value, self.min), self.max);
+    }
+
+    fn track_width(&self, rect: Rect) -> i32 {
+        cmp::max(rect.width() - self.thumb_width, 1)
+    }
+
+    fn thumb_x(&self, rect: Rect) -> i32 {
+        rect.left + (self.value - self.min) * self.track_width(rect) / (self.max - self.min)
+    }
+
+    fn drag_to(&mut self, ctx: &mut HandleEvent, x: i32) {
+        let rect = ctx.base.rect;
+        let track_width = self.track_width(rect);
+        let range = self.max - self.min;
+        let offset = cmp::min(cmp::max(x - rect.left, 0), track_width);
+        let value = self.min + (offset * range + track_width / 2) / track_width;
+        if value != self.value {
+            self.value = value;
+            ctx.out((self.command)(value));
+        }
+    }
+}
+
+impl Widget for Slider {
+    fn handle_event(&mut self, mut ctx: HandleEvent) {
+        match ctx.event {
+            Event::MouseDown { pos, button: MouseButton::Left } => {
+                let thumb_x = self.thumb_x(ctx.base.rect);
+                let grab = if pos.x >= thumb_x && pos.x < thumb_x + self.thumb_width {
+                    pos.x - thumb_x
+                } else {
+                    // Clicking the track centers the thumb on the cursor.
+                    self.thumb_width / 2
+                };
+                self.grab = Some(grab);
+                self.drag_to(&mut ctx, pos.x - grab);
+                ctx.capture();
+            }
+            Event::MouseMove { pos } if ctx.is_captured() => {
+                if let Some(grab) = self.grab {
+                    self.drag_to(&mut ctx, pos.x - grab);
+                }
+            }
+            Event::MouseUp { button: MouseButton::Left, .. } if ctx.is_captured() => {
+                self.grab = None;
+                ctx.release();
+            }
+            _ => {}
+        }
+    }
+
+    fn render(&mut self, ctx: Render) {
+        let rect = ctx.base.unwrap().rect();
+        let fid = if self.grab.is_some() { self.thumb_down } else { self.thumb_up };
+        let x = self.thumb_x(rect);
+        Sprite::new_with_pos(fid, Point::new(x, rect.top)).render(ctx.canvas, ctx.frm_db);
+    }
+}
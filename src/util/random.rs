@@ -1,8 +1,23 @@
-use rand::{rng, Rng};
+use rand::{rng, Rng, SeedableRng};
+use rand::rngs::StdRng;
+use std::cell::RefCell;
+
+thread_local! {
+    static SEEDED_RNG: RefCell<Option<StdRng>> = const { RefCell::new(None) };
+}
+
+/// Makes `random()` on the current thread deterministic by using generator seeded with `seed`.
+pub fn set_seed(seed: u64) {
+    SEEDED_RNG.with(|r| *r.borrow_mut() = Some(StdRng::seed_from_u64(seed)));
+}
 
 // roll_random()
 pub fn random(from_inclusive: i32, to_inclusive: i32) -> i32 {
-    rng().random_range(from_inclusive..=to_inclusive)
+    SEEDED_RNG.with(|r| if let Some(rng) = r.borrow_mut().as_mut() {
+        rng.random_range(from_inclusive..=to_inclusive)
+    } else {
+        rng().random_range(from_inclusive..=to_inclusive)
+    })
 }
 
 #[derive(Clone, Copy, Debug, PartialEq, enum_primitive_derive::Primitive)]
@@ -57,3 +72,18 @@ impl RollChecker {
         (r, roll)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn seeded() {
+        set_seed(42);
+        let a: Vec<_> = (0..10).map(|_| random(1, 100)).collect();
+        set_seed(42);
+        let b: Vec<_> = (0..10).map(|_| random(1, 100)).collect();
+        assert_eq!(a, b);
+        assert!(a.iter().all(|&v| (1..=100).contains(&v)));
+    }
+}
@@ -1,8 +1,549 @@
+use bstring::BString;
+use byteorder::{BigEndian, WriteBytesExt};
 use flate2::bufread::GzDecoder;
+use std::collections::HashMap;
 use std::io::Read;
+use std::rc::Rc;
+use std::time::Instant;
+
+use crate::asset::{CritterAnim, EntityKind, Stat, WeaponKind};
+use crate::asset::frame::{FrameDb, FrameId};
+use crate::asset::map::db::MapDb;
+use crate::asset::message::Messages;
+use crate::asset::proto::{ProtoDb, ProtoId};
+use crate::asset::script::db::ScriptDb;
+use crate::fs::FileSystem;
+use crate::fs::memory::MemoryFileSystem;
+use crate::game::combat::Combat;
+use crate::game::dialog::Dialog;
+use crate::game::movie::Movies;
+use crate::game::party::Party;
+use crate::game::queue::Queue;
+use crate::game::rpg::Rpg;
+use crate::game::script::{NewScripts, Scripts};
+use crate::game::sequence::ObjSequencer;
+use crate::game::world::World;
+use crate::game::worldmap::WorldMap;
+use crate::graphics::Rect;
+use crate::graphics::font::{Font, FontKey, Fonts, Glyph};
+use crate::graphics::geometry::hex;
+use crate::graphics::render::TextureFactory;
+use crate::graphics::render::software::new_test_texture_factory;
+use crate::profile::Profile;
+use crate::sound::{Mixer, Sound};
+use crate::sound::output::NullOutput;
+use crate::ui::{self, Ui};
+use crate::util::EnumExt;
+use crate::vm::{self, Vm};
+use crate::vm::value::Value;
 
 pub fn ungz(buf: &[u8]) -> Vec<u8> {
     let mut r = Vec::new();
     GzDecoder::new(buf).read_to_end(&mut r).unwrap();
     r
-}
\ No newline at end of file
+}
+
+/// Minimal synthetic game data for tests that need the asset databases.
+pub struct Assets {
+    pub fs: Rc<FileSystem>,
+    pub proto_db: Rc<ProtoDb>,
+    pub frm_db: Rc<FrameDb>,
+}
+
+impl Assets {
+    pub const MISC_ITEM: u32 = 0x00000001;
+    pub const WEAPON: u32 = 0x00000002;
+    pub const AMMO: u32 = 0x00000003;
+    pub const KEY: u32 = 0x00000004;
+    pub const CONTAINER: u32 = 0x00000005;
+    /// +2 Strength and +1 Agility, wearing off after an hour.
+    pub const DRUG: u32 = 0x00000006;
+    pub const CRITTER: u32 = 0x01000001;
+    pub const DOOR: u32 = 0x02000001;
+    pub const STAIRS: u32 = 0x02000002;
+    pub const ELEVATOR: u32 = 0x02000003;
+    pub const LADDER_DOWN: u32 = 0x02000004;
+    pub const SCENERY: u32 = 0x02000005;
+    pub const WALL: u32 = 0x03000001;
+    pub const EXIT_AREA: u32 = 0x05000010;
+
+    /// Script program with local variables (program ID 1).
+    pub const PROGRAM: u32 = 1;
+    pub const PROGRAM_LOCAL_VAR_COUNT: usize = 3;
+
+    pub fn new() -> Self {
+        Self::with_files(|_| {})
+    }
+
+    /// Same as `new()` but allows adding extra files or overriding the default ones.
+    pub fn with_files(f: impl FnOnce(&mut MemoryFileSystem)) -> Self {
+        let mut mfs = MemoryFileSystem::new();
+        Self::populate(&mut mfs);
+        f(&mut mfs);
+
+        let mut fs = FileSystem::new();
+        fs.register_provider(Box::new(mfs));
+        let fs = Rc::new(fs);
+
+        let proto_db = Rc::new(ProtoDb::new(fs.clone(), "english", Profile::Fallout2).unwrap());
+        let frm_db = Rc::new(FrameDb::new(fs.clone(), "english", new_test_texture_factory())
+            .unwrap());
+        Self {
+            fs,
+            proto_db,
+            frm_db,
+        }
+    }
+
+    pub fn pid(packed: u32) -> ProtoId {
+        ProtoId::from_packed(packed).unwrap()
+    }
+
+    pub fn fid(kind: EntityKind) -> FrameId {
+        if kind == EntityKind::Critter {
+            FrameId::new_critter(None, CritterAnim::Stand, WeaponKind::Unarmed, 0).unwrap()
+        } else {
+            FrameId::new_generic(kind, 0).unwrap()
+        }
+    }
+
+    pub fn scripts(&self) -> Scripts {
+        Scripts::new(
+            self.proto_db.clone(),
+            ScriptDb::new(self.fs.clone(), "english").unwrap(),
+            Vm::default())
+    }
+
+    pub fn rpg(&self) -> Rpg {
+        Rpg::new(&self.fs, "english").unwrap()
+    }
+
+    fn populate(fs: &mut MemoryFileSystem) {
+        for kind in EntityKind::iter() {
+            let dir = kind.dir();
+            let (lst, file) = if kind == EntityKind::Critter {
+                ("hmtest\n", "hmtestaa.frm")
+            } else {
+                ("test.frm\n", "test.frm")
+            };
+            fs.insert(&format!("art/{0}/{0}.lst", dir), lst);
+            fs.insert(&format!("art/{}/{}", dir, file), frm());
+        }
+
+        let items = [
+            ("misc.pro", item_proto(Self::MISC_ITEM, 5, &misc_item())),
+            ("weapon.pro", item_proto(Self::WEAPON, 3, &weapon())),
+            ("ammo.pro", item_proto(Self::AMMO, 4, &ammo())),
+            ("key.pro", item_proto(Self::KEY, 6, &key())),
+            ("bag.pro", item_proto(Self::CONTAINER, 1, &container())),
+            ("drug.pro", item_proto(Self::DRUG, 2, &drug())),
+        ];
+        let scenery = [
+            ("door.pro", scenery_proto(Self::DOOR, 0, &[0, 0, 0, 0, 0xff, 0xff, 0xff, 0xff])),
+            ("stairs.pro", scenery_proto(Self::STAIRS, 1, &[0xff; 8])),
+            ("elevator.pro", scenery_proto(Self::ELEVATOR, 2, &[0; 8])),
+            ("ladder.pro", scenery_proto(Self::LADDER_DOWN, 3, &[0xff; 4])),
+            ("scenery.pro", scenery_proto(Self::SCENERY, 5, &[0; 4])),
+        ];
+        Self::insert_protos(fs, EntityKind::Item, &items);
+        Self::insert_protos(fs, EntityKind::Critter, &[("critter.pro", critter_proto())]);
+        Self::insert_protos(fs, EntityKind::Scenery, &scenery);
+        Self::insert_protos(fs, EntityKind::Wall, &[("wall.pro", wall_proto())]);
+        Self::insert_protos(fs, EntityKind::SqrTile, &[]);
+        // Exit areas have fixed PIDs starting at 0x10.
+        let misc: Vec<_> = (1..=0x18)
+            .map(|i| (format!("misc{}.pro", i), common_proto(0x05000000 | i, EntityKind::Misc)))
+            .collect();
+        let misc: Vec<_> = misc.iter().map(|(n, d)| (n.as_str(), d.clone())).collect();
+        Self::insert_protos(fs, EntityKind::Misc, &misc);
+
+        fs.insert("text/english/game/proto.msg", "");
+        for kind in &["item", "crit", "scen", "wall", "tile", "misc"] {
+            fs.insert(&format!("text/english/game/pro_{}.msg", kind), "");
+        }
+        for f in &["stat", "skill", "perk", "trait"] {
+            fs.insert(&format!("text/english/game/{}.msg", f), "");
+        }
+
+        // Needed by `GameState`.
+        for f in &["scrname", "skilldex", "inventry", "editor", "pipboy", "map", "lsgame",
+            "options", "misc"]
+        {
+            fs.insert(&format!("text/english/game/{}.msg", f), "");
+        }
+        fs.insert("data/ai.txt", "");
+        fs.insert("data/vault13.gam", "");
+
+        fs.insert("scripts/scripts.lst",
+            format!("test.int ; Test # local_vars={}\n", Self::PROGRAM_LOCAL_VAR_COUNT));
+        fs.insert("scripts/test.int", program());
+    }
+
+    fn insert_protos(fs: &mut MemoryFileSystem, kind: EntityKind, protos: &[(&str, Vec<u8>)]) {
+        let dir = kind.dir();
+        let mut lst = String::new();
+        for (name, data) in protos {
+            lst.push_str(name);
+            lst.push('\n');
+            fs.insert(&format!("proto/{}/{}", dir, name), data.clone());
+        }
+        fs.insert(&format!("proto/{0}/{0}.lst", dir), lst);
+    }
+}
+
+/// Fonts for all font keys used by the UI. Every glyph is a single blank pixel.
+pub fn fonts(texture_factory: &TextureFactory) -> Fonts {
+    let mut fonts = Fonts::new();
+    let keys = (0..10).map(|id| FontKey { id, antialiased: false })
+        .chain((0..16).map(|id| FontKey { id, antialiased: true }));
+    for key in keys {
+        let glyphs = (0..256)
+            .map(|_| Glyph {
+                width: 1,
+                height: 1,
+                texture: texture_factory.new_texture(1, 1, vec![0].into()),
+            })
+            .collect();
+        fonts.insert(key, Font {
+            height: 1,
+            horz_spacing: 0,
+            vert_spacing: 0,
+            glyphs,
+        });
+    }
+    fonts
+}
+
+/// Game state needed to build `vm::Context` for running programs in tests.
+pub struct VmEnv {
+    pub assets: Assets,
+    pub local_vars: Vec<i32>,
+    pub map_vars: Vec<i32>,
+    pub global_vars: Vec<i32>,
+    pub external_vars: HashMap<Rc<BString>, Option<Value>>,
+    pub queue: Queue,
+    pub ui: Ui,
+    pub world: World,
+    pub obj_sequencer: ObjSequencer,
+    pub dialog: Option<Dialog>,
+    pub message_panel: ui::Handle,
+    pub script_db: ScriptDb,
+    pub scripts: Scripts,
+    pub rpg: Rpg,
+    pub sound: Sound,
+    pub map_db: MapDb,
+    pub movies: Movies,
+    pub combat: Combat,
+    pub world_map: WorldMap,
+    pub party: Party,
+}
+
+impl VmEnv {
+    pub fn new() -> Self {
+        Self::with_assets(Assets::new())
+    }
+
+    pub fn with_assets(assets: Assets) -> Self {
+        let fs = assets.fs.clone();
+        let fonts = Rc::new(Fonts::new());
+        let mut ui = Ui::new(assets.frm_db.clone(), fonts.clone(), 640, 480);
+        let message_panel = ui.new_window(Rect::new(0, 0, 640, 480), None);
+        let world = World::new(
+            assets.proto_db.clone(),
+            assets.frm_db.clone(),
+            Messages::read(&mut &b""[..]).unwrap(),
+            hex::TileGrid::default(),
+            Rect::new(0, 0, 640, 380),
+            Instant::now(),
+            fonts);
+        Self {
+            local_vars: vec![0; 8],
+            map_vars: vec![0; 8],
+            global_vars: vec![0; 8],
+            external_vars: HashMap::new(),
+            queue: Queue::new(),
+            ui,
+            world,
+            obj_sequencer: ObjSequencer::new(Instant::now()),
+            dialog: None,
+            message_panel,
+            script_db: ScriptDb::new(fs.clone(), "english").unwrap(),
+            scripts: assets.scripts(),
+            rpg: assets.rpg(),
+            sound: Sound::new(fs.clone(), Mixer::new(), Box::new(NullOutput::new())),
+            map_db: MapDb::new(&fs).unwrap(),
+            movies: Movies::new(fs.clone()),
+            combat: Combat::new(),
+            world_map: WorldMap::new(&fs).unwrap(),
+            party: Party::new(&fs).unwrap(),
+            assets,
+        }
+    }
+
+    pub fn ctx(&mut self) -> vm::Context<'_> {
+        vm::Context {
+            local_vars: &mut self.local_vars,
+            map_vars: &mut self.map_vars,
+            global_vars: &mut self.global_vars,
+            external_vars: &mut self.external_vars,
+            queue: &mut self.queue,
+            self_obj: None,
+            source_obj: None,
+            target_obj: None,
+            skill: None,
+            fixed_param: 0,
+            ui: &mut self.ui,
+            world: &mut self.world,
+            obj_sequencer: &mut self.obj_sequencer,
+            dialog: &mut self.dialog,
+            message_panel: self.message_panel,
+            script_db: &mut self.script_db,
+            new_scripts: NewScripts::new(&self.scripts),
+            proto_db: &self.assets.proto_db,
+            map_id: 0,
+            rpg: &mut self.rpg,
+            sound: &mut self.sound,
+            map_db: &mut self.map_db,
+            movies: &mut self.movies,
+            combat: &mut self.combat,
+            world_map: &mut self.world_map,
+            party: &mut self.party,
+        }
+    }
+}
+
+/// Single 1x1 frame for all directions.
+fn frm() -> Vec<u8> {
+    let mut r = Vec::new();
+    r.write_u32::<BigEndian>(4).unwrap();
+    r.write_u16::<BigEndian>(10).unwrap();
+    r.write_u16::<BigEndian>(0).unwrap();
+    r.write_u16::<BigEndian>(1).unwrap();
+    r.extend_from_slice(&[0; 6 * 2 * 2 + 6 * 4]);
+    r.write_u32::<BigEndian>(13).unwrap();
+    r.write_i16::<BigEndian>(1).unwrap();
+    r.write_i16::<BigEndian>(1).unwrap();
+    r.write_u32::<BigEndian>(1).unwrap();
+    r.write_i16::<BigEndian>(0).unwrap();
+    r.write_i16::<BigEndian>(0).unwrap();
+    r.push(1);
+    r
+}
+
+/// Program without procedures.
+fn program() -> Vec<u8> {
+    let mut r = vec![0; 42];
+    r.write_u32::<BigEndian>(0).unwrap();
+    r.write_u32::<BigEndian>(0xffff_ffff).unwrap();
+    r.write_u32::<BigEndian>(0xffff_ffff).unwrap();
+    r
+}
+
+fn common_proto(pid: u32, kind: EntityKind) -> Vec<u8> {
+    let mut r = Vec::new();
+    r.write_u32::<BigEndian>(pid).unwrap();
+    r.write_i32::<BigEndian>(100).unwrap();
+    r.write_u32::<BigEndian>(Assets::fid(kind).packed()).unwrap();
+    r.write_i32::<BigEndian>(0).unwrap();
+    r.write_i32::<BigEndian>(0).unwrap();
+    r.write_u32::<BigEndian>(0).unwrap();
+    r.write_u32::<BigEndian>(0).unwrap();
+    r
+}
+
+fn item_proto(pid: u32, kind: u32, sub: &[u8]) -> Vec<u8> {
+    let mut r = common_proto(pid, EntityKind::Item);
+    if kind == 3 {
+        // Primary attack: FireSingle, secondary attack: Swing.
+        r[6 * 4..7 * 4].copy_from_slice(&0x36u32.to_be_bytes());
+    }
+    r.write_i32::<BigEndian>(-1).unwrap();
+    r.write_u32::<BigEndian>(kind).unwrap();
+    r.write_u32::<BigEndian>(1).unwrap();
+    r.write_i32::<BigEndian>(1).unwrap();
+    r.write_i32::<BigEndian>(2).unwrap();
+    r.write_i32::<BigEndian>(100).unwrap();
+    r.write_i32::<BigEndian>(-1).unwrap();
+    r.push(0);
+    r.extend_from_slice(sub);
+    r
+}
+
+fn misc_item() -> Vec<u8> {
+    let mut r = Vec::new();
+    r.write_i32::<BigEndian>(-1).unwrap();
+    r.write_u32::<BigEndian>(0).unwrap();
+    r.write_i32::<BigEndian>(0).unwrap();
+    r
+}
+
+fn weapon() -> Vec<u8> {
+    let mut r = Vec::new();
+    for v in &[
+        WeaponKind::Pistol as i32,
+        // damage
+        5, 12,
+        // damage kind
+        0,
+        // max ranges
+        20, 1,
+        // projectile
+        -1,
+        // min strength
+        3,
+        // AP costs
+        5, 4,
+        // crit failure table
+        0,
+        // perk
+        -1,
+        // burst
+        0,
+        // caliber
+        1,
+        // ammo
+        Assets::AMMO as i32,
+        // max ammo count
+        12,
+    ] {
+        r.write_i32::<BigEndian>(*v).unwrap();
+    }
+    r.push(0);
+    r
+}
+
+fn ammo() -> Vec<u8> {
+    let mut r = Vec::new();
+    for v in &[1, 24, 0, 0, 1, 1] {
+        r.write_i32::<BigEndian>(*v).unwrap();
+    }
+    r
+}
+
+fn key() -> Vec<u8> {
+    42i32.to_be_bytes().to_vec()
+}
+
+fn drug() -> Vec<u8> {
+    let mut r = Vec::new();
+    for v in &[
+        // stats
+        Stat::Strength as i32, Stat::Agility as i32, -1,
+        // immediate modifiers
+        2, 1, 0,
+        // first delayed modifiers
+        60, -2, -1, 0,
+        // second delayed modifiers
+        0, 0, 0, 0,
+        // addiction chance, perk, delay
+        0, -1, 0,
+    ] {
+        r.write_i32::<BigEndian>(*v).unwrap();
+    }
+    r
+}
+
+fn container() -> Vec<u8> {
+    let mut r = Vec::new();
+    r.write_i32::<BigEndian>(100).unwrap();
+    r.write_u32::<BigEndian>(0).unwrap();
+    r
+}
+
+fn critter_proto() -> Vec<u8> {
+    let mut r = common_proto(Assets::CRITTER, EntityKind::Critter);
+    r.write_i32::<BigEndian>(-1).unwrap();
+    // head fid
+    r.write_i32::<BigEndian>(-1).unwrap();
+    // AI packet
+    r.write_i32::<BigEndian>(1).unwrap();
+    // team
+    r.write_i32::<BigEndian>(2).unwrap();
+    // flags
+    r.write_u32::<BigEndian>(0).unwrap();
+    let mut base_stats = [0; 35];
+    base_stats[..7].copy_from_slice(&[5; 7]);
+    // Hit points, action points, armor class, melee damage, carry weight.
+    base_stats[7..12].copy_from_slice(&[30, 7, 5, 1, 150]);
+    for v in &base_stats {
+        r.write_i32::<BigEndian>(*v).unwrap();
+    }
+    r.extend_from_slice(&[0; (35 + 18) * 4]);
+    // body, experience, kill, damage
+    for v in &[0, 50, 0, 0] {
+        r.write_i32::<BigEndian>(*v).unwrap();
+    }
+    r
+}
+
+fn scenery_proto(pid: u32, kind: u32, sub: &[u8]) -> Vec<u8> {
+    let mut r = common_proto(pid, EntityKind::Scenery);
+    r.write_i32::<BigEndian>(-1).unwrap();
+    r.write_u32::<BigEndian>(kind).unwrap();
+    r.write_u32::<BigEndian>(0).unwrap();
+    r.push(0);
+    r.extend_from_slice(sub);
+    r
+}
+
+fn wall_proto() -> Vec<u8> {
+    let mut r = common_proto(Assets::WALL, EntityKind::Wall);
+    r.write_i32::<BigEndian>(-1).unwrap();
+    r.write_u32::<BigEndian>(0).unwrap();
+    r
+}
+
+/// Writes bits LSB first like they're read by the ACM decoder.
+pub struct BitWriter {
+    out: Vec<u8>,
+    data: u32,
+    bits: u32,
+}
+
+impl BitWriter {
+    pub fn new() -> Self {
+        Self {
+            out: Vec::new(),
+            data: 0,
+            bits: 0,
+        }
+    }
+
+    pub fn write(&mut self, v: u32, count: u32) {
+        assert!(count <= 16 && v >> count == 0);
+        self.data |= v << self.bits;
+        self.bits += count;
+        while self.bits >= 8 {
+            self.out.push(self.data as u8);
+            self.data >>= 8;
+            self.bits -= 8;
+        }
+    }
+
+    pub fn finish(mut self) -> Vec<u8> {
+        if self.bits > 0 {
+            self.out.push(self.data as u8);
+        }
+        self.out
+    }
+}
+
+/// Lossless ACM stream with a single block of raw 16-bit values.
+pub fn acm(channels: u16, sample_rate: u16, samples: &[i16]) -> Vec<u8> {
+    let mut w = BitWriter::new();
+    for v in [0x2897, 0x0103, samples.len() as u32, 0, channels as u32, sample_rate as u32] {
+        w.write(v, 16);
+    }
+    // Level 0, i.e. no transform and a single column.
+    w.write(0, 4);
+    w.write(samples.len() as u32, 12);
+    w.write(0, 4);
+    // Amplitude.
+    w.write(1, 16);
+    // Linear filler with 16 bits per value.
+    w.write(16, 5);
+    for &v in samples {
+        w.write((v as i32 + 0x8000) as u32, 16);
+    }
+    w.finish()
+}
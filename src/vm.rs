@@ -85,8 +85,12 @@
 //!
 //! Stored in `save.dat`. Defined in `vault13.gam`.
 
+pub mod debug;
+pub mod disasm;
 mod error;
 mod instruction;
+mod sched;
+pub mod ssl;
 mod stack;
 pub mod value;
 
@@ -96,6 +100,7 @@ use enumflags2::{bitflags, BitFlags};
 use log::*;
 use matches::matches;
 use slotmap::{SecondaryMap, SlotMap};
+use std::cell::RefCell;
 use std::collections::HashMap;
 use std::fmt;
 use std::io::{self, Cursor};
@@ -105,11 +110,15 @@ use std::time::Duration;
 
 use crate::game::object;
 use crate::game::script::{NewScripts, ScriptKind};
+use crate::profile::Profile;
 
-use instruction::{Instruction, instruction_map, Opcode};
+use instruction::{Instruction, instruction_map};
+use sched::Sched;
+pub use sched::{Request, StartMode};
 use stack::{Stack, StackId};
 
 pub use error::*;
+pub use instruction::Opcode;
 pub use value::Value;
 
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
@@ -143,9 +152,9 @@ impl PredefinedProc {
     pub fn name(self) -> &'static str {
         use PredefinedProc::*;
         match self {
-            Combat => "combat_is_over_p_proc",
-            CombatIsOver => "combat_is_starting_p_proc",
-            CombatIsStarting => "combat_p_proc",
+            Combat => "combat_p_proc",
+            CombatIsOver => "combat_is_over_p_proc",
+            CombatIsStarting => "combat_is_starting_p_proc",
             Create => "create_p_proc",
             Critter => "critter_p_proc",
             Damage => "damage_p_proc",
@@ -216,10 +225,14 @@ pub struct Context<'a> {
     /// External variables.
     pub external_vars: &'a mut HashMap<Rc<BString>, Option<Value>>,
 
+    /// Game time event queue.
+    pub queue: &'a mut crate::game::queue::Queue,
+
     pub self_obj: Option<object::Handle>,
     pub source_obj: Option<object::Handle>,
     pub target_obj: Option<object::Handle>,
     pub skill: Option<crate::asset::Skill>,
+    pub fixed_param: i32,
     pub ui: &'a mut crate::ui::Ui,
     pub world: &'a mut crate::game::world::World,
     pub obj_sequencer: &'a mut crate::game::sequence::ObjSequencer,
@@ -230,6 +243,12 @@ pub struct Context<'a> {
     pub proto_db: &'a crate::asset::proto::ProtoDb,
     pub map_id: crate::asset::map::MapId,
     pub rpg: &'a mut crate::game::rpg::Rpg,
+    pub sound: &'a mut crate::sound::Sound,
+    pub map_db: &'a mut crate::asset::map::db::MapDb,
+    pub movies: &'a mut crate::game::movie::Movies,
+    pub combat: &'a mut crate::game::combat::Combat,
+    pub world_map: &'a mut crate::game::worldmap::WorldMap,
+    pub party: &'a mut crate::game::party::Party,
 }
 
 pub struct VmConfig {
@@ -237,15 +256,21 @@ pub struct VmConfig {
     max_stack_len: usize,
 }
 
-impl Default for VmConfig {
-    fn default() -> Self {
+impl VmConfig {
+    pub fn new(profile: Profile) -> Self {
         Self {
-            instructions: instruction_map(),
+            instructions: instruction_map(profile),
             max_stack_len: 2000,
         }
     }
 }
 
+impl Default for VmConfig {
+    fn default() -> Self {
+        Self::new(Profile::Fallout2)
+    }
+}
+
 pub struct StringMap {
     map: HashMap<usize, Rc<BString>>,
 }
@@ -307,11 +332,19 @@ pub struct Program {
     names: StringMap,
     strings: StringMap,
     procs: Procs,
+    /// Start of the code following the procedure, name and string tables.
+    code_start: usize,
 }
 
 impl Program {
+    /// Length of the program startup code preceding the procedure table.
+    pub const HEADER_LEN: usize = 42;
+
+    /// Position in the program startup code where the invoked procedures return to.
+    const RETURN_POS: usize = 24;
+
     fn new(name: String, code: Box<[u8]>, config: Rc<VmConfig>) -> Result<Self> {
-        const PROC_TABLE_START: usize = 42;
+        const PROC_TABLE_START: usize = Program::HEADER_LEN;
         const PROC_TABLE_HEADER_LEN: usize = 4;
         const PROC_ENTRY_LEN: usize = 24;
 
@@ -329,8 +362,9 @@ impl Program {
 
         let string_table_start = name_table_start + name_table_len_bytes;
         debug!("reading string table at 0x{:04x}", string_table_start);
-        let (strings, _) =
+        let (strings, string_table_len_bytes) =
             Self::read_string_table(&code[string_table_start..])?;
+        let code_start = string_table_start + string_table_len_bytes;
 
         debug!("reading procedure table at 0x{:04x}", PROC_TABLE_START);
         let procs = Self::read_proc_table(&code[PROC_TABLE_START..], &names)?;
@@ -342,6 +376,7 @@ impl Program {
             names,
             strings,
             procs,
+            code_start,
         })
     }
 
@@ -472,12 +507,15 @@ pub struct ProgramState {
     instr_state: instruction::State,
     /// Stack of code positions where suspend requested.
     suspend_stack: Vec<usize>,
+    debugger: Option<Rc<RefCell<debug::Debugger>>>,
+    sched: Sched,
 }
 
 impl ProgramState {
-    fn new(program: Rc<Program>) -> Self {
+    fn new(program: Rc<Program>, debugger: Option<Rc<RefCell<debug::Debugger>>>) -> Self {
         let data_stack = Stack::new(program.config.max_stack_len);
         let return_stack = Stack::new(program.config.max_stack_len);
+        let sched = Sched::new(&program);
 
         Self {
             program,
@@ -489,6 +527,8 @@ impl ProgramState {
             global_base: None,
             instr_state: instruction::State::new(),
             suspend_stack: Vec::new(),
+            debugger,
+            sched,
         }
     }
 
@@ -507,6 +547,7 @@ impl ProgramState {
     fn run(&mut self, ctx: &mut Context) -> Result<InvocationResult> {
         self.instr_state.script_overrides = false;
         let suspend = loop {
+            self.before_instruction(ctx);
             match self.step(ctx) {
                 Ok(r) => {
                     if let Some(s) = r {
@@ -514,9 +555,22 @@ impl ProgramState {
                         self.suspend_stack.push(self.code_pos);
                         break Some(s);
                     }
+                    if let Some(wake) = self.take_block() {
+                        self.park(wake)?;
+                        break None;
+                    }
+                }
+                Err(ref e) if matches!(e, Error::Halted) => {
+                    self.end_invocation();
+                    break None;
+                }
+                Err(e) => {
+                    self.end_invocation();
+                    if let Some(debugger) = self.debugger.clone() {
+                        debugger.borrow_mut().on_error(self, ctx, &e);
+                    }
+                    return Err(e);
                 }
-                Err(ref e) if matches!(e, Error::Halted) => break None,
-                Err(e) => return Err(e),
             }
         };
         Ok(InvocationResult {
@@ -529,24 +583,41 @@ impl ProgramState {
         &self.program
     }
 
+    pub fn code_pos(&self) -> usize {
+        self.code_pos
+    }
+
     pub fn execute_proc(&mut self, id: ProcedureId, ctx: &mut Context) -> Result<InvocationResult> {
         let proc_pos = self.program.proc(id)
             .ok_or(Error::BadProcedureId(id))?
             .body_pos;
 
-        // setupCallWithReturnVal()
+        if self.is_stopped() {
+            debug!("{}: ignoring call of procedure {} in stopped program", self.program.name, id);
+            return Ok(InvocationResult::default());
+        }
+
+        self.setup_call(proc_pos)?;
+
+        self.run(ctx)
+    }
+
+    // setupCallWithReturnVal()
+    fn setup_call(&mut self, pos: usize) -> Result<()> {
+        self.begin_invocation();
+
         self.return_stack.push(Value::Int(self.code_pos as i32))?;
         // TODO How important is this? The value varies in different call places.
-        self.return_stack.push(Value::Int(24))?;
+        self.return_stack.push(Value::Int(Program::RETURN_POS as i32))?;
         self.data_stack.push(Value::Int(0))?; // flags
         self.data_stack.push(Value::Int(0))?; //unk17_
         self.data_stack.push(Value::Int(0))?; //unk19_
 
         self.data_stack.push(Value::Int(0))?;
 
-        self.code_pos = proc_pos;
+        self.code_pos = pos;
 
-        self.run(ctx)
+        Ok(())
     }
 
     pub fn can_resume(&self) -> bool {
@@ -558,6 +629,12 @@ impl ProgramState {
         self.run(ctx)
     }
 
+    fn before_instruction(&mut self, ctx: &mut Context) {
+        if let Some(debugger) = self.debugger.clone() {
+            debugger.borrow_mut().before_instruction(self, ctx);
+        }
+    }
+
     fn step(&mut self, ctx: &mut Context) -> Result<Option<Suspend>> {
         trace!("code_pos: 0x{:04x}", self.code_pos);
         let opcode_pos = self.code_pos;
@@ -661,12 +738,12 @@ impl ProgramState {
 
     fn global(&self, id: usize) -> Result<&Value> {
         let base = self.global_base()?;
-        self.data_stack.get(base + id)
+        self.global_stack().get(base + id)
     }
 
     fn global_mut(&mut self, id: usize) -> Result<&mut Value> {
         let base = self.global_base()?;
-        self.data_stack.get_mut(base + id)
+        self.global_stack_mut().get_mut(base + id)
     }
 }
 
@@ -691,6 +768,9 @@ pub struct Vm {
     config: Rc<VmConfig>,
     program_handles: SlotMap<Handle, ()>,
     program_states: SecondaryMap<Handle, ProgramState>,
+    debugger: Option<Rc<RefCell<debug::Debugger>>>,
+    /// Parents of the processes that called `exec`, to be passed to the new processes.
+    exec_parents: SecondaryMap<Handle, Handle>,
 }
 
 impl Vm {
@@ -699,6 +779,8 @@ impl Vm {
             config,
             program_handles: SlotMap::with_key(),
             program_states: SecondaryMap::new(),
+            debugger: None,
+            exec_parents: SecondaryMap::new(),
         }
     }
 
@@ -707,12 +789,30 @@ impl Vm {
     }
 
     pub fn insert(&mut self, program: Rc<Program>) -> Handle {
-        let program_state = ProgramState::new(program);
+        let program_state = ProgramState::new(program, self.debugger.clone());
         let h = self.program_handles.insert(());
         self.program_states.insert(h, program_state);
         h
     }
 
+    pub fn remove(&mut self, handle: Handle) {
+        self.program_handles.remove(handle);
+        self.program_states.remove(handle);
+        self.exec_parents.remove(handle);
+    }
+
+    pub fn debugger(&self) -> Option<&Rc<RefCell<debug::Debugger>>> {
+        self.debugger.as_ref()
+    }
+
+    /// Attaches the `debugger` to all current and future programs.
+    pub fn set_debugger(&mut self, debugger: Option<Rc<RefCell<debug::Debugger>>>) {
+        for prg in self.program_states.values_mut() {
+            prg.debugger = debugger.clone();
+        }
+        self.debugger = debugger;
+    }
+
     pub fn run(&mut self, program: Handle, ctx: &mut Context) -> Result<InvocationResult> {
         self.program_state_mut(program).run(ctx)
     }
@@ -0,0 +1,550 @@
+//! Interactive script debugger.
+//!
+//! `ProgramState` consults the debugger before executing each instruction and when an instruction
+//! fails. When the debugger decides to pause it reads commands from its `Frontend` until one of
+//! the commands resumes execution. The whole game is blocked while paused.
+
+use bstring::BString;
+use matches::matches;
+use std::cell::RefCell;
+use std::fmt::{self, Write as _};
+use std::io::{self, prelude::*};
+use std::rc::Rc;
+
+use super::*;
+use super::disasm::{self, Operand};
+
+const HELP: &str = "\
+s, step                 execute single instruction
+n, next                 same as `step` but doesn't stop inside called procedure
+c, continue             continue until breakpoint or watch is triggered
+b, break <location>     set breakpoint at `[<program>:]<procedure>|<offset>`
+d, delete [<n>]         delete breakpoint number <n> or all breakpoints
+w, watch <kind> <id>    watch `global`, `map` or `local` variable
+unwatch [<n>]           delete watch number <n> or all watches
+i, info                 list breakpoints and watches
+x, where                print current instruction
+stack                   dump data and return stacks
+h, help                 print this help
+";
+
+/// Source of the debugger commands and sink for its output.
+pub trait Frontend {
+    /// Returns the next command line or `None` if there's no more input.
+    fn read_line(&mut self) -> Option<String>;
+
+    fn write(&mut self, s: &str);
+}
+
+/// Line-based frontend that reads commands from stdin and writes to stdout.
+pub struct StdioFrontend;
+
+impl Frontend for StdioFrontend {
+    fn read_line(&mut self) -> Option<String> {
+        print!("(vdb) ");
+        io::stdout().flush().ok()?;
+        let mut line = String::new();
+        match io::stdin().read_line(&mut line) {
+            Ok(0) | Err(_) => None,
+            Ok(_) => Some(line),
+        }
+    }
+
+    fn write(&mut self, s: &str) {
+        print!("{}", s);
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Location {
+    /// Start of the procedure body.
+    Proc(BString),
+    /// Code offset.
+    Pos(usize),
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Breakpoint {
+    /// Name of the program without extension. `None` matches all programs.
+    pub program: Option<String>,
+    pub location: Location,
+}
+
+impl Breakpoint {
+    /// Parses `[<program>:]<procedure>|<offset>`. The offset is decimal or `0x` prefixed hex.
+    pub fn parse(s: &str) -> Option<Self> {
+        let (program, loc) = match s.rsplit_once(':') {
+            Some((p, l)) => (Some(p.to_owned()), l),
+            None => (None, s),
+        };
+        if loc.is_empty() || program.as_ref().is_some_and(|p| p.is_empty()) {
+            return None;
+        }
+        let location = if loc.starts_with(|c: char| c.is_ascii_digit()) {
+            let pos = match loc.strip_prefix("0x") {
+                Some(hex) => usize::from_str_radix(hex, 16),
+                None => loc.parse(),
+            };
+            Location::Pos(pos.ok()?)
+        } else {
+            Location::Proc(loc.into())
+        };
+        Some(Self { program, location })
+    }
+
+    fn matches(&self, prg: &ProgramState) -> bool {
+        if let Some(name) = &self.program
+            && !prg.program.name.eq_ignore_ascii_case(name)
+        {
+            return false;
+        }
+        match &self.location {
+            Location::Proc(name) => prg.program.procs.by_id.iter()
+                .any(|p| !p.flags.contains(ProcedureFlag::Import)
+                    && p.body_pos == prg.code_pos
+                    && p.name.as_bytes().eq_ignore_ascii_case(name.as_bytes())),
+            &Location::Pos(pos) => pos == prg.code_pos,
+        }
+    }
+}
+
+impl fmt::Display for Breakpoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(program) = &self.program {
+            write!(f, "{}:", program)?;
+        }
+        match &self.location {
+            Location::Proc(name) => write!(f, "{}", name.display()),
+            Location::Pos(pos) => write!(f, "0x{:04x}", pos),
+        }
+    }
+}
+
+/// Variable that can be watched.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Var {
+    Global(usize),
+    Map(usize),
+    /// Local variable of the currently running script.
+    Local(usize),
+}
+
+impl Var {
+    pub fn parse(kind: &str, id: &str) -> Option<Self> {
+        let id = id.parse().ok()?;
+        Some(match kind {
+            "global" | "gvar" => Var::Global(id),
+            "map" | "mvar" => Var::Map(id),
+            "local" | "lvar" => Var::Local(id),
+            _ => return None,
+        })
+    }
+
+    fn get(self, ctx: &Context) -> Option<i32> {
+        match self {
+            Var::Global(id) => ctx.global_vars.get(id),
+            Var::Map(id) => ctx.map_vars.get(id),
+            Var::Local(id) => ctx.local_vars.get(id),
+        }.copied()
+    }
+}
+
+impl fmt::Display for Var {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Var::Global(id) => write!(f, "global {}", id),
+            Var::Map(id) => write!(f, "map {}", id),
+            Var::Local(id) => write!(f, "local {}", id),
+        }
+    }
+}
+
+struct Watch {
+    var: Var,
+    /// Value seen before the last executed instruction.
+    value: Option<i32>,
+}
+
+enum Mode {
+    Run,
+    Step,
+    /// Pause on return to `pos` of the `program` with the return stack not deeper than `depth`.
+    StepOver {
+        program: Rc<Program>,
+        pos: usize,
+        depth: usize,
+    },
+}
+
+pub struct Debugger {
+    frontend: Box<dyn Frontend>,
+    breakpoints: Vec<Breakpoint>,
+    watches: Vec<Watch>,
+    mode: Mode,
+}
+
+impl Debugger {
+    pub fn new(frontend: Box<dyn Frontend>) -> Self {
+        Self {
+            frontend,
+            breakpoints: Vec::new(),
+            watches: Vec::new(),
+            mode: Mode::Run,
+        }
+    }
+
+    pub fn into_shared(self) -> Rc<RefCell<Self>> {
+        Rc::new(RefCell::new(self))
+    }
+
+    /// Adds breakpoint and returns its number.
+    pub fn add_breakpoint(&mut self, breakpoint: Breakpoint) -> usize {
+        self.breakpoints.push(breakpoint);
+        self.breakpoints.len()
+    }
+
+    /// Adds watch and returns its number. Watched global and map variables pause execution
+    /// when their value changes. All watched variables are printed on pause.
+    pub fn add_watch(&mut self, var: Var) -> usize {
+        self.watches.push(Watch {
+            var,
+            value: None,
+        });
+        self.watches.len()
+    }
+
+    /// Makes the debugger pause before the next executed instruction.
+    pub fn interrupt(&mut self) {
+        self.mode = Mode::Step;
+    }
+
+    pub(in crate::vm) fn before_instruction(&mut self, prg: &ProgramState, ctx: &Context) {
+        let mut reasons = Vec::new();
+        let mut pause = match &self.mode {
+            Mode::Run => false,
+            Mode::Step => true,
+            Mode::StepOver { program, pos, depth } => Rc::ptr_eq(program, &prg.program)
+                && prg.code_pos == *pos
+                && prg.return_stack.len() <= *depth,
+        };
+        for (i, bp) in self.breakpoints.iter().enumerate() {
+            if bp.matches(prg) {
+                reasons.push(format!("breakpoint {}: {}", i + 1, bp));
+                pause = true;
+            }
+        }
+        for (i, watch) in self.watches.iter_mut().enumerate() {
+            let value = watch.var.get(ctx);
+            if !matches!(watch.var, Var::Local(_))
+                && watch.value.is_some()
+                && value != watch.value
+            {
+                reasons.push(format!("watch {}: {}: {} -> {}", i + 1, watch.var,
+                    OptValue(watch.value), OptValue(value)));
+                pause = true;
+            }
+            watch.value = value;
+        }
+        if pause {
+            self.pause(prg, ctx, prg.code_pos, &reasons);
+        }
+    }
+
+    pub(in crate::vm) fn on_error(&mut self, prg: &ProgramState, ctx: &Context, error: &Error) {
+        let pos = prg.opcode.map(|(_, pos)| pos).unwrap_or(prg.code_pos);
+        self.pause(prg, ctx, pos, &[format!("error: {:?}", error)]);
+    }
+
+    fn pause(&mut self, prg: &ProgramState, ctx: &Context, pos: usize, reasons: &[String]) {
+        let mut out = String::new();
+        for reason in reasons {
+            writeln!(out, "{}", reason).unwrap();
+        }
+        write_where(&mut out, prg, pos);
+        self.write_watches(&mut out, ctx);
+        self.frontend.write(&out);
+
+        loop {
+            let Some(line) = self.frontend.read_line() else {
+                // No more input, detach.
+                self.mode = Mode::Run;
+                self.breakpoints.clear();
+                self.watches.clear();
+                return;
+            };
+            let mut out = String::new();
+            let resume = self.command(&line, prg, ctx, pos, &mut out);
+            self.frontend.write(&out);
+            if resume {
+                break;
+            }
+        }
+    }
+
+    /// Executes the command and returns `true` if the execution should be resumed.
+    fn command(&mut self, line: &str, prg: &ProgramState, ctx: &Context, pos: usize,
+        out: &mut String) -> bool
+    {
+        let mut args = line.split_whitespace();
+        let Some(cmd) = args.next() else {
+            return false;
+        };
+        let args: Vec<_> = args.collect();
+        match (cmd, &args[..]) {
+            ("s" | "step", []) => {
+                self.mode = Mode::Step;
+                return true;
+            }
+            ("n" | "next", []) => {
+                // The caller pushes the return address before `call`.
+                let ret_pos = disasm::decode(&prg.program, pos).ok()
+                    .filter(|i| i.opcode == Opcode::Call)
+                    .and_then(|_| prg.return_stack.top()?.clone().into_int().ok());
+                self.mode = if let Some(ret_pos) = ret_pos {
+                    Mode::StepOver {
+                        program: prg.program.clone(),
+                        pos: ret_pos as usize,
+                        depth: prg.return_stack.len(),
+                    }
+                } else {
+                    Mode::Step
+                };
+                return true;
+            }
+            ("c" | "continue", []) => {
+                self.mode = Mode::Run;
+                return true;
+            }
+            ("b" | "break", &[loc]) => if let Some(bp) = Breakpoint::parse(loc) {
+                writeln!(out, "breakpoint {}: {}", self.breakpoints.len() + 1, bp).unwrap();
+                self.add_breakpoint(bp);
+            } else {
+                writeln!(out, "bad breakpoint location: {}", loc).unwrap();
+            }
+            ("d" | "delete", []) => self.breakpoints.clear(),
+            ("d" | "delete", &[n]) => if let Some(i) = parse_number(n, self.breakpoints.len()) {
+                self.breakpoints.remove(i);
+            } else {
+                writeln!(out, "no breakpoint number {}", n).unwrap();
+            }
+            ("w" | "watch", &[kind, id]) => if let Some(var) = Var::parse(kind, id) {
+                let n = self.add_watch(var);
+                self.watches[n - 1].value = var.get(ctx);
+                writeln!(out, "watch {}: {} = {}", n, var, OptValue(var.get(ctx))).unwrap();
+            } else {
+                writeln!(out, "bad variable: {} {}", kind, id).unwrap();
+            }
+            ("unwatch", []) => self.watches.clear(),
+            ("unwatch", &[n]) => if let Some(i) = parse_number(n, self.watches.len()) {
+                self.watches.remove(i);
+            } else {
+                writeln!(out, "no watch number {}", n).unwrap();
+            }
+            ("i" | "info", []) => {
+                for (i, bp) in self.breakpoints.iter().enumerate() {
+                    writeln!(out, "breakpoint {}: {}", i + 1, bp).unwrap();
+                }
+                self.write_watches(out, ctx);
+            }
+            ("x" | "where", []) => write_where(out, prg, pos),
+            ("stack", []) => write_stacks(out, prg),
+            ("h" | "help", []) => out.push_str(HELP),
+            _ => writeln!(out, "bad command: {}\ntry `help`", line.trim()).unwrap(),
+        }
+        false
+    }
+
+    fn write_watches(&self, out: &mut String, ctx: &Context) {
+        for (i, watch) in self.watches.iter().enumerate() {
+            writeln!(out, "watch {}: {} = {}", i + 1, watch.var, OptValue(watch.var.get(ctx)))
+                .unwrap();
+        }
+    }
+}
+
+struct OptValue(Option<i32>);
+
+impl fmt::Display for OptValue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.0 {
+            Some(v) => write!(f, "{}", v),
+            None => write!(f, "<undefined>"),
+        }
+    }
+}
+
+/// Parses 1-based number of an item in list of `len` items into index.
+fn parse_number(s: &str, len: usize) -> Option<usize> {
+    s.parse::<usize>().ok()
+        .filter(|&n| n >= 1 && n <= len)
+        .map(|n| n - 1)
+}
+
+/// Writes program name, procedure and the instruction at `pos`.
+fn write_where(out: &mut String, prg: &ProgramState, pos: usize) {
+    let program = &prg.program;
+    write!(out, "{} ", program.name).unwrap();
+    let proc = program.procs.by_id.iter()
+        .filter(|p| !p.flags.contains(ProcedureFlag::Import) && p.body_pos <= pos)
+        .max_by_key(|p| p.body_pos);
+    if pos >= program.code_start && let Some(proc) = proc {
+        write!(out, "{}+0x{:x} ", proc.name().display(), pos - proc.body_pos).unwrap();
+    }
+    write!(out, "0x{:04x}: ", pos).unwrap();
+    match disasm::decode(program, pos) {
+        Ok(instr) => {
+            out.push_str(&disasm::opcode_name(instr.opcode));
+            match instr.operand {
+                Some(Operand::Int(v)) => write!(out, " {}", v).unwrap(),
+                Some(Operand::Float(v)) => write!(out, " {:?}", v).unwrap(),
+                Some(Operand::String(v)) => {
+                    let name = disasm::decode(program, instr.next_pos())
+                        .is_ok_and(|i| disasm::takes_name(i.opcode));
+                    let strings = if name { &program.names } else { &program.strings };
+                    write!(out, " {}", v).unwrap();
+                    if let Some(s) = strings.get(v) {
+                        write!(out, "  ; {:?}", s.display().to_string()).unwrap();
+                    }
+                }
+                None => {}
+            }
+        }
+        Err(e) => write!(out, "<{:?}>", e).unwrap(),
+    }
+    out.push('\n');
+}
+
+/// Writes data and return stacks from the bottom to the top.
+fn write_stacks(out: &mut String, prg: &ProgramState) {
+    writeln!(out, "data stack:").unwrap();
+    for i in 0..prg.data_stack.len() {
+        let v = prg.data_stack.get(i).unwrap();
+        write!(out, "  {:3}: {}", i, value_str(v, &prg.program.strings)).unwrap();
+        if prg.global_base == Some(i) {
+            out.push_str("  <- global base");
+        }
+        if prg.base == Some(i) {
+            out.push_str("  <- base");
+        }
+        out.push('\n');
+    }
+    writeln!(out, "return stack:").unwrap();
+    for i in 0..prg.return_stack.len() {
+        let v = prg.return_stack.get(i).unwrap();
+        writeln!(out, "  {:3}: {}", i, value_str(v, &prg.program.strings)).unwrap();
+    }
+}
+
+fn value_str(v: &Value, strings: &StringMap) -> String {
+    match v {
+        Value::Int(v) => v.to_string(),
+        Value::Float(v) => format!("{:?}", v),
+        Value::String(s) => match s.clone().resolve(strings) {
+            Ok(s) => format!("{:?}", s.display().to_string()),
+            Err(_) => format!("{:?}", s),
+        },
+        Value::Object(h) => format!("{:?}", h),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::VecDeque;
+
+    use super::*;
+    use crate::util::test::VmEnv;
+    use crate::vm::ssl::compile::compile_file;
+
+    struct TestFrontend {
+        input: VecDeque<&'static str>,
+        output: Rc<RefCell<String>>,
+    }
+
+    impl Frontend for TestFrontend {
+        fn read_line(&mut self) -> Option<String> {
+            let line = self.input.pop_front()?;
+            writeln!(self.output.borrow_mut(), "> {}", line).unwrap();
+            Some(line.into())
+        }
+
+        fn write(&mut self, s: &str) {
+            self.output.borrow_mut().push_str(s);
+        }
+    }
+
+    const SRC: &str = "
+        procedure add(variable a, variable b) begin
+            return a + b;
+        end
+
+        procedure start begin
+            variable x := add(1, 2);
+            set_global_var(1, x);
+            set_global_var(2, add(x, 4));
+        end
+    ";
+
+    /// Runs `start` procedure with the debugger attached. Returns the debugger output.
+    fn run(input: &[&'static str], setup: impl FnOnce(&Program, &mut Debugger)) -> String {
+        let code = compile_file("test.ssl", &mut |_| Ok(SRC.into())).unwrap();
+        let mut vm = Vm::default();
+        let prg = Rc::new(vm.load("test".into(), code.into()).unwrap());
+        let start = prg.proc_id(&Rc::new("start".into())).unwrap();
+
+        let output = Rc::new(RefCell::new(String::new()));
+        let mut debugger = Debugger::new(Box::new(TestFrontend {
+            input: input.iter().copied().collect(),
+            output: output.clone(),
+        }));
+        setup(&prg, &mut debugger);
+        vm.set_debugger(Some(debugger.into_shared()));
+
+        let h = vm.insert(prg);
+        let mut env = VmEnv::new();
+        vm.run(h, &mut env.ctx()).unwrap().assert_no_suspend();
+        vm.program_state_mut(h).execute_proc(start, &mut env.ctx()).unwrap().assert_no_suspend();
+        assert_eq!(&env.global_vars[..3], &[0, 3, 7]);
+
+        output.take()
+    }
+
+    #[test]
+    fn breakpoints_and_watches() {
+        let out = run(&["stack", "watch global 1", "c", "info", "delete 1", "c"], |_, dbg| {
+            dbg.add_breakpoint(Breakpoint::parse("TEST:add").unwrap());
+        });
+        let lines: Vec<_> = out.lines().collect();
+        assert_eq!(lines[0], "breakpoint 1: TEST:add");
+        assert!(lines[1].starts_with("test add+0x0 0x"), "{}", out);
+        assert!(lines[1].ends_with(": push_base"), "{}", out);
+        assert!(out.contains("    3: 0  <- base\n"), "{}", out);
+        assert!(out.contains("    6: 1\n    7: 2\n    8: 2\nreturn stack:\n"), "{}", out);
+        assert!(out.contains("> watch global 1\nwatch 1: global 1 = 0\n> c\n\
+            watch 1: global 1: 0 -> 3\ntest start+0x"), "{}", out);
+        assert!(out.contains("> info\nbreakpoint 1: TEST:add\nwatch 1: global 1 = 3\n\
+            > delete 1\n> c\n"), "{}", out);
+        assert!(out.ends_with("> c\n"), "{}", out);
+    }
+
+    #[test]
+    fn step_over() {
+        let mut call_pos = 0;
+        let out = run(&["next", "s", "bad", "c"], |prg, dbg| {
+            let start = prg.proc(prg.proc_id(&Rc::new("start".into())).unwrap()).unwrap();
+            call_pos = disasm::decode_range(prg, start.body_pos, prg.code.len()).0.iter()
+                .find(|i| i.opcode == Opcode::Call)
+                .unwrap()
+                .pos;
+            dbg.add_breakpoint(Breakpoint::parse(&format!("{}", call_pos)).unwrap());
+        });
+        assert!(out.starts_with(&format!("breakpoint 1: 0x{0:04x}\ntest start+0x", call_pos)),
+            "{}", out);
+        // Stops right after the call returns.
+        let after_call = format!("0x{:04x}: call\n> next\n", call_pos);
+        let i = out.find(&after_call).unwrap_or_else(|| panic!("{}", out)) + after_call.len();
+        let line = out[i..].lines().next().unwrap();
+        assert!(line.ends_with(&format!("0x{:04x}: a_to_d", call_pos + 2)), "{}", out);
+        assert!(out[i..].starts_with(&format!("{}\n> s\ntest start+0x", line)), "{}", out);
+        assert!(out.contains("> bad\nbad command: bad\ntry `help`\n> c\n"), "{}", out);
+    }
+}
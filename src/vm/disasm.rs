@@ -0,0 +1,460 @@
+use byteorder::{BigEndian, ByteOrder};
+use matches::matches;
+use std::collections::HashMap;
+use std::io::{self, prelude::*};
+
+use super::*;
+use super::ssl::{builtin, BinaryOp, UnaryOp};
+
+/// Immediate operand of the instruction.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Operand {
+    Int(i32),
+    Float(f32),
+    /// Offset into the string or name table.
+    String(usize),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Decoded {
+    pub pos: usize,
+    pub opcode: Opcode,
+    pub operand: Option<Operand>,
+}
+
+impl Decoded {
+    /// Encoded length in bytes.
+    pub fn len(&self) -> usize {
+        Opcode::SIZE + if self.operand.is_some() { 4 } else { 0 }
+    }
+
+    pub fn next_pos(&self) -> usize {
+        self.pos + self.len()
+    }
+}
+
+/// Decodes the instruction at `pos`. Only the opcodes known to the VM config of the program are
+/// recognized.
+pub fn decode(program: &Program, pos: usize) -> Result<Decoded> {
+    let code = &program.code;
+    if pos + Opcode::SIZE > code.len() {
+        return Err(Error::UnexpectedEof);
+    }
+    let v = BigEndian::read_u16(&code[pos..]);
+    let opcode = program.config.instructions.get(&v)
+        .ok_or(Error::BadOpcode(v))?
+        .opcode();
+    let operand = if opcode.has_operand() {
+        let p = pos + Opcode::SIZE;
+        if p + 4 > code.len() {
+            return Err(Error::UnexpectedEof);
+        }
+        let v = BigEndian::read_i32(&code[p..]);
+        Some(match opcode {
+            Opcode::ConstFloat => Operand::Float(f32::from_bits(v as u32)),
+            Opcode::ConstString if v >= 0 => Operand::String(v as usize),
+            Opcode::ConstString => return Err(Error::BadInstruction),
+            _ => Operand::Int(v),
+        })
+    } else {
+        None
+    };
+    Ok(Decoded { pos, opcode, operand })
+}
+
+/// Decodes instructions in `start..end`. Stops at the first undecodable instruction and returns
+/// its position along with the error.
+pub fn decode_range(program: &Program, start: usize, end: usize)
+    -> (Vec<Decoded>, Option<(usize, Error)>)
+{
+    let mut r = Vec::new();
+    let mut pos = start;
+    while pos < end {
+        match decode(program, pos) {
+            Ok(d) => {
+                pos = d.next_pos();
+                r.push(d);
+            }
+            Err(e) => return (r, Some((pos, e))),
+        }
+    }
+    (r, None)
+}
+
+/// Returns SSL-style name of the `opcode`: `ObjIsCarryingObjPid` -> `obj_is_carrying_obj_pid`.
+pub fn opcode_name(opcode: Opcode) -> String {
+    let s = format!("{:?}", opcode);
+    let mut r = String::with_capacity(s.len() + 8);
+    for (i, c) in s.chars().enumerate() {
+        if c.is_ascii_uppercase() {
+            if i > 0 {
+                r.push('_');
+            }
+            r.push(c.to_ascii_lowercase());
+        } else {
+            r.push(c);
+        }
+    }
+    r
+}
+
+/// Returns whether the string operand of `opcode` is resolved through the name table.
+pub fn takes_name(opcode: Opcode) -> bool {
+    use Opcode::*;
+    matches!(opcode,
+        FetchExternal | StoreExternal | ExportVar | ExportProc | LookupStringProc)
+}
+
+fn flags_str(flags: BitFlags<ProcedureFlag>) -> String {
+    flags.iter()
+        .map(|f| match f {
+            ProcedureFlag::Timed => "timed",
+            ProcedureFlag::Conditional => "conditional",
+            ProcedureFlag::Import => "import",
+            ProcedureFlag::Export => "export",
+            ProcedureFlag::Critical => "critical",
+        })
+        .collect::<Vec<_>>()
+        .join("|")
+}
+
+/// How an integer constant is used by the instruction consuming it.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum IntUse {
+    Address,
+    Procedure,
+}
+
+/// Follows the constants through the data stack to find the ones used as code addresses or
+/// procedure IDs. Returns the uses keyed by the index into `instrs`. The stack is reset at
+/// procedure starts and after instructions with unknown stack effect.
+fn int_uses(instrs: &[Decoded], proc_starts: &HashMap<usize, Vec<&bstr>>)
+    -> HashMap<usize, IntUse>
+{
+    use Opcode::*;
+
+    fn pop(stack: &mut Vec<Option<usize>>) -> Option<usize> {
+        stack.pop().flatten()
+    }
+
+    let mut r = HashMap::new();
+    let mut mark = |i: Option<usize>, u| if let Some(i) = i {
+        r.insert(i, u);
+    };
+    // Index of the integer constant or `None` for other values.
+    let mut stack: Vec<Option<usize>> = Vec::new();
+    for (i, instr) in instrs.iter().enumerate() {
+        if proc_starts.contains_key(&instr.pos) {
+            stack.clear();
+        }
+        match instr.opcode {
+            ConstShort | ConstLong => stack.push(Some(i)),
+            ConstFloat | ConstString | AToD => stack.push(None),
+            Jmp => mark(pop(&mut stack), IntUse::Address),
+            If | While => {
+                pop(&mut stack);
+                mark(pop(&mut stack), IntUse::Address);
+            }
+            Call => {
+                mark(pop(&mut stack), IntUse::Procedure);
+                let arg_count = pop(&mut stack).and_then(|i| match instrs[i].operand {
+                    Some(Operand::Int(v)) => usize::try_from(v).ok(),
+                    _ => None,
+                });
+                if let Some(n) = arg_count {
+                    // Arguments and flags.
+                    stack.truncate(stack.len().saturating_sub(n + 3));
+                } else {
+                    stack.clear();
+                }
+            }
+            Fetch | FetchGlobal | FetchExternal => {
+                pop(&mut stack);
+                stack.push(None);
+            }
+            Store | StoreGlobal | StoreExternal => {
+                stack.truncate(stack.len().saturating_sub(2));
+            }
+            Pop | DToA => {
+                pop(&mut stack);
+            }
+            Dup => stack.push(stack.last().copied().flatten()),
+            Swap => if stack.len() >= 2 {
+                let len = stack.len();
+                stack.swap(len - 1, len - 2);
+            }
+            opcode => if BinaryOp::from_opcode(opcode).is_some() {
+                stack.truncate(stack.len().saturating_sub(2));
+                stack.push(None);
+            } else if UnaryOp::from_opcode(opcode).is_some() {
+                pop(&mut stack);
+                stack.push(None);
+            } else if let Some(b) = builtin::by_opcode(opcode) {
+                let args = stack.split_off(stack.len().saturating_sub(b.arg_count));
+                let arg = |i: usize| args.get(i).copied().flatten();
+                match opcode {
+                    CallAt => mark(arg(0), IntUse::Procedure),
+                    CallCondition => {
+                        mark(arg(0), IntUse::Procedure);
+                        mark(arg(1), IntUse::Address);
+                    }
+                    _ => {}
+                }
+                if b.returns {
+                    stack.push(None);
+                }
+            } else {
+                stack.clear();
+            }
+        }
+    }
+    r
+}
+
+fn instr_line(program: &Program, instrs: &[Decoded], i: usize,
+    int_uses: &HashMap<usize, IntUse>) -> String
+{
+    use std::fmt::Write;
+
+    let instr = &instrs[i];
+    let mut r = format!("0x{:04x}: {:04x} {}",
+        instr.pos, instr.opcode as u16, opcode_name(instr.opcode));
+    let next = instrs.get(i + 1).map(|i| i.opcode);
+    match instr.operand {
+        Some(Operand::Int(v)) => {
+            write!(r, " {}", v).unwrap();
+            match int_uses.get(&i) {
+                Some(IntUse::Address) => write!(r, "  ; -> 0x{:04x}", v).unwrap(),
+                Some(IntUse::Procedure) => if let Some(proc) = program.proc(v as ProcedureId) {
+                    write!(r, "  ; {}", proc.name().display()).unwrap();
+                }
+                None => {}
+            }
+        }
+        Some(Operand::Float(v)) => write!(r, " {:?}", v).unwrap(),
+        Some(Operand::String(v)) => {
+            write!(r, " {}", v).unwrap();
+            let s = if next.map(takes_name).unwrap_or(false) {
+                program.names.get(v)
+            } else {
+                program.strings.get(v)
+            };
+            if let Some(s) = s {
+                write!(r, "  ; {:?}", s.display().to_string()).unwrap();
+            }
+        }
+        None => {}
+    }
+    r
+}
+
+/// Returns the listing of the instructions in `start..end` in the same format as
+/// `disassemble()`, one line per instruction.
+pub fn listing(program: &Program, start: usize, end: usize) -> Vec<String> {
+    let (instrs, err) = decode_range(program, start, end);
+    let int_uses = int_uses(&instrs, &HashMap::new());
+    let mut r: Vec<_> = (0..instrs.len())
+        .map(|i| instr_line(program, &instrs, i, &int_uses))
+        .collect();
+    if let Some((pos, e)) = err {
+        r.push(format!("0x{:04x}: <{:?}>", pos, e));
+    }
+    r
+}
+
+/// Writes the human-readable listing of the `program`: the procedure table followed by
+/// the instructions of the startup code and the code section. String operands are resolved
+/// to the string or name table depending on the consuming instruction, integer operands
+/// used as jump targets (`jmp`, `if`, `while`, `call_condition`) and procedures (`call`,
+/// `call_at`, `call_condition`) are resolved to the addresses and procedure names.
+pub fn disassemble(program: &Program, out: &mut impl Write) -> io::Result<()> {
+    writeln!(out, "; {}", program.name)?;
+    writeln!(out, "; procedures:")?;
+    for (i, proc) in program.procs.by_id.iter().enumerate() {
+        write!(out, ";   {:3} {:<24} args: {}  body: 0x{:04x}",
+            i, proc.name.display(), proc.arg_count, proc.body_pos)?;
+        if proc.flags.contains(ProcedureFlag::Conditional) {
+            write!(out, "  condition: 0x{:04x}", proc.condition_pos)?;
+        }
+        if proc.flags.contains(ProcedureFlag::Timed) {
+            write!(out, "  delay: {} ms", proc.delay.as_millis())?;
+        }
+        if !proc.flags.is_empty() {
+            write!(out, "  flags: {}", flags_str(proc.flags))?;
+        }
+        writeln!(out)?;
+    }
+
+    let mut labels: HashMap<usize, Vec<&bstr>> = HashMap::new();
+    for proc in &program.procs.by_id {
+        if !proc.flags.contains(ProcedureFlag::Import) {
+            labels.entry(proc.body_pos).or_default().push(proc.name());
+        }
+    }
+
+    for &(start, end) in &[(0, Program::HEADER_LEN), (program.code_start, program.code.len())] {
+        writeln!(out)?;
+        let (instrs, err) = decode_range(program, start, end);
+        let int_uses = int_uses(&instrs, &labels);
+        for (i, instr) in instrs.iter().enumerate() {
+            if let Some(names) = labels.get(&instr.pos) {
+                for name in names {
+                    writeln!(out, "{}:", name.display())?;
+                }
+            }
+            writeln!(out, "{}", instr_line(program, &instrs, i, &int_uses))?;
+        }
+        if let Some((pos, e)) = err {
+            writeln!(out, "0x{:04x}: <{:?}>", pos, e)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+pub(in crate::vm) mod test {
+    use super::*;
+    use byteorder::WriteBytesExt;
+
+    fn write_string_table(w: &mut Vec<u8>, strings: &[&str]) -> Vec<usize> {
+        let start = w.len();
+        let mut body = Vec::new();
+        let mut offsets = Vec::new();
+        for s in strings {
+            let len = (s.len() + 2) & !1;
+            body.write_u16::<BigEndian>(len as u16).unwrap();
+            offsets.push(4 + body.len());
+            body.extend_from_slice(s.as_bytes());
+            body.resize(body.len() + len - s.len(), 0);
+        }
+        w.write_u32::<BigEndian>(body.len() as u32).unwrap();
+        w.extend_from_slice(&body);
+        w.write_u16::<BigEndian>(0xffff).unwrap();
+        w.write_u16::<BigEndian>(0).unwrap();
+        assert_eq!(w.len() - start, body.len() + 8);
+        offsets
+    }
+
+    pub fn emit(code: &mut Vec<u8>, opcode: Opcode, operand: Option<i32>) {
+        code.write_u16::<BigEndian>(opcode as u16).unwrap();
+        if let Some(v) = operand {
+            code.write_i32::<BigEndian>(v).unwrap();
+        }
+    }
+
+    /// Builds a program with procedures `(name, flags, arg_count)` and `body` emitting the code
+    /// section. `body` receives the start offset of the code section and the offsets of `names`
+    /// and `strings` and returns the body positions of the procedures.
+    pub fn build(
+        procs: &[(&str, BitFlags<ProcedureFlag>, usize)],
+        names: &[&str],
+        strings: &[&str],
+        body: impl FnOnce(&mut Vec<u8>, usize, &[usize], &[usize]) -> Vec<usize>,
+    ) -> Program {
+        let mut names = names.to_vec();
+        for &(name, _, _) in procs {
+            if !names.contains(&name) {
+                names.push(name);
+            }
+        }
+
+        let mut tables = Vec::new();
+        let proc_table_len = 4 + procs.len() * 24;
+        tables.resize(proc_table_len, 0);
+        let name_offsets = write_string_table(&mut tables, &names);
+        let string_offsets = if strings.is_empty() {
+            tables.write_u32::<BigEndian>(0xffff_ffff).unwrap();
+            Vec::new()
+        } else {
+            write_string_table(&mut tables, strings)
+        };
+        let code_start = Program::HEADER_LEN + tables.len();
+
+        let mut code = Vec::new();
+        let body_pos = body(&mut code, code_start, &name_offsets, &string_offsets);
+
+        let mut proc_table = Vec::new();
+        proc_table.write_u32::<BigEndian>(procs.len() as u32).unwrap();
+        for (i, &(name, flags, arg_count)) in procs.iter().enumerate() {
+            let name = name_offsets[names.iter().position(|&n| n == name).unwrap()];
+            for v in [name, flags.bits() as usize, 0, 0, body_pos[i], arg_count] {
+                proc_table.write_u32::<BigEndian>(v as u32).unwrap();
+            }
+        }
+        tables[..proc_table_len].copy_from_slice(&proc_table);
+
+        let mut header = Vec::new();
+        emit(&mut header, Opcode::CriticalStart, None);
+        emit(&mut header, Opcode::ConstLong, Some(code_start as i32));
+        emit(&mut header, Opcode::Jmp, None);
+        while header.len() < Program::HEADER_LEN {
+            emit(&mut header, Opcode::Noop8000, None);
+        }
+
+        let mut r = header;
+        r.extend(tables);
+        r.extend(code);
+        Program::new("test.int".into(), r.into(), Default::default()).unwrap()
+    }
+
+    #[test]
+    fn disassemble_() {
+        let prg = build(&[("start", BitFlags::empty(), 0), ("foo", ProcedureFlag::Critical.into(), 1)],
+            &["ext"], &["hello"],
+            |c, start, names, strings| {
+                let start_pos = start + c.len();
+                emit(c, Opcode::ConstString, Some(strings[0] as i32));
+                emit(c, Opcode::ConstString, Some(names[0] as i32));
+                emit(c, Opcode::StoreExternal, None);
+                emit(c, Opcode::ConstShort, Some(0));
+                emit(c, Opcode::ConstShort, Some(1));
+                emit(c, Opcode::Call, None);
+                // if 1 then call_at(foo, 10);
+                emit(c, Opcode::ConstLong, Some((start_pos + 56) as i32));
+                emit(c, Opcode::ConstShort, Some(1));
+                emit(c, Opcode::If, None);
+                emit(c, Opcode::ConstShort, Some(1));
+                emit(c, Opcode::ConstShort, Some(10));
+                emit(c, Opcode::CallAt, None);
+                emit(c, Opcode::ExitProg, None);
+                let foo_pos = start + c.len();
+                emit(c, Opcode::ConstFloat, Some(1.5f32.to_bits() as i32));
+                emit(c, Opcode::ConstLong, Some(start_pos as i32));
+                emit(c, Opcode::Jmp, None);
+                c.extend_from_slice(&[0xff, 0xff]);
+                vec![start_pos, foo_pos]
+            });
+
+        let mut out = Vec::new();
+        disassemble(&prg, &mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        let start = prg.proc(0).unwrap().body_pos;
+        let foo = prg.proc(1).unwrap().body_pos;
+
+        assert!(out.contains(&format!(";     1 foo                      args: 1  body: 0x{:04x}  \
+            flags: critical\n", foo)));
+        assert!(out.contains("0x0000: 8002 critical_start\n"));
+        assert!(out.contains(&format!("0x0002: c001 const_long {}  ; -> 0x{:04x}\n",
+            start, start)));
+        assert!(out.contains(&format!("start:\n0x{:04x}: 9001 const_string 6  ; \"hello\"\n",
+            start)));
+        assert!(out.contains(&format!("0x{:04x}: 9001 const_string 6  ; \"ext\"\n", start + 6)));
+        assert!(out.contains(&format!("0x{:04x}: 8001 const_short 1  ; foo\n", start + 20)));
+        assert!(out.contains(&format!("0x{:04x}: c001 const_long {}  ; -> 0x{:04x}\n",
+            start + 28, start + 56, start + 56)));
+        assert!(out.contains(&format!("0x{:04x}: 8001 const_short 1\n", start + 34)));
+        assert!(out.contains(&format!("0x{:04x}: 8001 const_short 1  ; foo\n", start + 42)));
+        assert!(out.contains(&format!("0x{:04x}: 8001 const_short 10\n", start + 48)));
+        assert!(out.contains(&format!("0x{:04x}: 8006 call_at\n0x{:04x}: 8010 exit_prog\n",
+            start + 54, start + 56)));
+        assert!(out.contains(&format!("foo:\n0x{:04x}: a001 const_float 1.5\n", foo)));
+        assert!(out.ends_with(&format!("0x{:04x}: <BadOpcode(65535)>\n", foo + 14)));
+    }
+
+    #[test]
+    fn opcode_name_() {
+        assert_eq!(opcode_name(Opcode::ObjIsCarryingObjPid), "obj_is_carrying_obj_pid");
+        assert_eq!(opcode_name(Opcode::AToD), "a_to_d");
+        assert_eq!(opcode_name(Opcode::Noop8000), "noop8000");
+    }
+}
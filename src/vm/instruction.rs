@@ -2,10 +2,12 @@ mod impls;
 
 use linearize::Linearize;
 use enum_primitive_derive::Primitive;
+use matches::matches;
 use std::collections::HashMap;
 
 use super::*;
 use crate::game::object;
+use crate::profile::Profile;
 use crate::sequence::chain::Chain;
 
 pub struct State {
@@ -380,6 +382,19 @@ pub enum Opcode {
 
 impl Opcode {
     pub const SIZE: usize = 2;
+
+    /// Whether the opcode is followed by 4-byte immediate operand.
+    pub fn has_operand(self) -> bool {
+        use Opcode::*;
+        matches!(self, ConstShort | ConstString | ConstFloat | ConstLong)
+    }
+
+    /// Whether the Fallout 1 interpreter knows the opcode. Fallout 2 added the game opcodes
+    /// following `endgame_movie`.
+    pub fn is_fallout1(self) -> bool {
+        let v = self as u16;
+        v <= Self::EndgameMovie as u16 || v > Self::CritterStopAttacking as u16
+    }
 }
 
 macro_rules! is {
@@ -414,7 +429,7 @@ pub(in super) mod instructions {
         i!(Addbuttontext,               unimplemented),
         i!(Addkey,                      unimplemented),
         i!(AddMultObjsToInven,          add_mult_objs_to_inven),
-        i!(Addnamedevent,               unimplemented),
+        i!(Addnamedevent,               addnamedevent),
         i!(Addnamedhandler,             unimplemented),
         i!(AddObjToInven,               add_obj_to_inven),
         i!(Addregion,                   unimplemented),
@@ -431,23 +446,23 @@ pub(in super) mod instructions {
         i!(AnimBusy,                    unimplemented),
         i!(ArtAnim,                     unimplemented),
         i!(AToD,                        atod),
-        i!(Attack,                      unimplemented),
+        i!(Attack,                      attack),
         i!(Attack80dd,                  unimplemented),
-        i!(AttackSetup,                 unimplemented),
+        i!(AttackSetup,                 attack_setup),
         i!(Bwand,                       bwand),
         i!(Bwnot,                       bwnot),
         i!(Bwor,                        bwor),
         i!(Bwxor,                       bwxor),
         i!(Call,                        call),
-        i!(CallAt,                      unimplemented),
-        i!(CallCondition,               unimplemented),
-        i!(Callstart,                   unimplemented),
-        i!(Cancel,                      unimplemented),
-        i!(Cancelall,                   unimplemented),
+        i!(CallAt,                      call_at),
+        i!(CallCondition,               call_condition),
+        i!(Callstart,                   callstart),
+        i!(Cancel,                      cancel),
+        i!(Cancelall,                   cancelall),
         i!(CheckArgCount,               unimplemented),
         i!(Checkregion,                 unimplemented),
         i!(Clearnamed,                  unimplemented),
-        i!(CombatDifficulty,            unimplemented),
+        i!(CombatDifficulty,            combat_difficulty),
         i!(CombatIsInitialized,         combat_is_initialized),
         i!(ConstFloat,                  const_float),
         i!(ConstLong,                   const_int),
@@ -461,18 +476,18 @@ pub(in super) mod instructions {
         i!(CriticalStart804a,           noop),
         i!(CritterAddTrait,             critter_add_trait),
         i!(CritterAttemptPlacement,     critter_attempt_placement),
-        i!(CritterDamage,               unimplemented),
+        i!(CritterDamage,               critter_damage),
         i!(CritterHeal,                 unimplemented),
         i!(CritterInjure,               unimplemented),
         i!(CritterInvenObj,             critter_inven_obj),
-        i!(CritterIsFleeing,            unimplemented),
+        i!(CritterIsFleeing,            critter_is_fleeing),
         i!(CritterModSkill,             unimplemented),
         i!(CritterRmTrait,              unimplemented),
-        i!(CritterSetFleeState,         unimplemented),
+        i!(CritterSetFleeState,         critter_set_flee_state),
         i!(CritterState,                unimplemented),
         i!(CritterStopAttacking,        unimplemented),
         i!(CurMapIndex,                 cur_map_index),
-        i!(DaysSinceVisited,            unimplemented),
+        i!(DaysSinceVisited,            days_since_visited),
         i!(DebugMsg,                    debug_msg),
         i!(Deletebutton,                unimplemented),
         i!(Deletekey,                   unimplemented),
@@ -480,10 +495,10 @@ pub(in super) mod instructions {
         i!(Deletewin,                   unimplemented),
         i!(DestroyMultObjs,             unimplemented),
         i!(DestroyObject,               destroy_object),
-        i!(Detach,                      unimplemented),
-        i!(DialogueReaction,            unimplemented),
+        i!(Detach,                      detach),
+        i!(DialogueReaction,            dialogue_reaction),
         i!(DialogueSystemEnter,         unimplemented),
-        i!(DifficultyLevel,             unimplemented),
+        i!(DifficultyLevel,             difficulty_level),
         i!(Display,                     unimplemented),
         i!(Displaygfx,                  unimplemented),
         i!(DisplayMsg,                  display_msg),
@@ -497,10 +512,10 @@ pub(in super) mod instructions {
         i!(Dup,                         dup),
         i!(Elevation,                   elevation),
         i!(EndDialogue,                 end_dialogue),
-        i!(EndgameMovie,                unimplemented),
+        i!(EndgameMovie,                endgame_movie),
         i!(EndgameSlideshow,            unimplemented),
         i!(Equal,                       equal),
-        i!(Exec,                        unimplemented),
+        i!(Exec,                        exec),
         i!(Exit,                        unimplemented),
         i!(ExitProg,                    exit_prog),
         i!(Explosion,                   unimplemented),
@@ -515,14 +530,14 @@ pub(in super) mod instructions {
         i!(Fillrect,                    unimplemented),
         i!(Fillwin,                     unimplemented),
         i!(Fillwin3X3,                  unimplemented),
-        i!(FixedParam,                  unimplemented),
+        i!(FixedParam,                  fixed_param),
         i!(FloatMsg,                    float_msg),
         i!(Floor,                       unimplemented),
-        i!(Fork,                        unimplemented),
+        i!(Fork,                        fork),
         i!(Format,                      unimplemented),
         i!(GameTicks,                   game_ticks),
         i!(GameTime,                    game_time),
-        i!(GameTimeAdvance,             unimplemented),
+        i!(GameTimeAdvance,             game_time_advance),
         i!(GameTimeHour,                game_time_hour),
         i!(GameTimeInSeconds,           game_time_in_seconds),
         i!(GameUiDisable,               unimplemented),
@@ -534,7 +549,7 @@ pub(in super) mod instructions {
         i!(GetDay,                      get_day),
         i!(GetMonth,                    get_month),
         i!(GetPcStat,                   unimplemented),
-        i!(GetPoison,                   unimplemented),
+        i!(GetPoison,                   get_poison),
         i!(GfadeIn,                     unimplemented),
         i!(GfadeOut,                    unimplemented),
         i!(GiqOption,                   giq_option),
@@ -545,7 +560,7 @@ pub(in super) mod instructions {
         i!(GreaterEqual,                greater_equal),
         is!(GsayEnd,                    gsay_end),
         i!(GsayMessage,                 gsay_message),
-        i!(GsayOption,                  unimplemented),
+        i!(GsayOption,                  gsay_option),
         i!(GsayReply,                   gsay_reply),
         i!(GsayStart,                   gsay_start),
         i!(HasSkill,                    has_skill),
@@ -557,11 +572,11 @@ pub(in super) mod instructions {
         i!(InvenUnwield,                unimplemented),
         i!(IsCritical,                  is_critical),
         i!(IsSuccess,                   is_success),
-        i!(ItemCapsAdjust,              unimplemented),
+        i!(ItemCapsAdjust,              item_caps_adjust),
         i!(ItemCapsTotal,               item_caps_total),
         i!(JamLock,                     jam_lock),
         i!(Jmp,                         jmp),
-        i!(KillCritter,                 unimplemented),
+        i!(KillCritter,                 kill_critter),
         i!(KillCritterType,             unimplemented),
         i!(Less,                        less),
         i!(LessEqual,                   less_equal),
@@ -570,7 +585,7 @@ pub(in super) mod instructions {
         i!(LocalVar,                    local_var),
         i!(LookupStringProc,            unimplemented),
         i!(MapVar,                      map_var),
-        i!(MarkAreaKnown,               unimplemented),
+        i!(MarkAreaKnown,               mark_area_known),
         i!(MessageStr,                  message_str),
         i!(Metarule,                    metarule),
         i!(Metarule3,                   metarule3),
@@ -578,7 +593,7 @@ pub(in super) mod instructions {
         i!(Mouseshape,                  unimplemented),
         i!(MoveObjInvenToObj,           move_obj_inven_to_obj),
         i!(MoveTo,                      move_to),
-        i!(Movieflags,                  unimplemented),
+        i!(Movieflags,                  movieflags),
         i!(Mul,                         mul),
         i!(Negate,                      negate),
         i!(Noop80d1,                    noop),
@@ -605,15 +620,15 @@ pub(in super) mod instructions {
         i!(ObjUnlock,                   obj_unlock),
         i!(Or,                          or),
         i!(OverrideMapStart,            override_map_start),
-        i!(PartyAdd,                    unimplemented),
+        i!(PartyAdd,                    party_add),
         i!(PartyMemberObj,              party_member_obj),
-        i!(PartyRemove,                 unimplemented),
+        i!(PartyRemove,                 party_remove),
         i!(PickupObj,                   unimplemented),
-        i!(PlayGmovie,                  unimplemented),
-        i!(Playmovie,                   unimplemented),
-        i!(Playmovierect,               unimplemented),
-        i!(PlaySfx,                     unimplemented),
-        i!(Poison,                      unimplemented),
+        i!(PlayGmovie,                  play_gmovie),
+        i!(Playmovie,                   playmovie),
+        i!(Playmovierect,               playmovierect),
+        i!(PlaySfx,                     play_sfx),
+        i!(Poison,                      poison),
         i!(Pop,                         pop),
         i!(PopAddress,                  unimplemented),
         i!(PopBase,                     pop_base),
@@ -632,10 +647,10 @@ pub(in super) mod instructions {
         i!(Printrect,                   unimplemented),
         i!(ProtoData,                   unimplemented),
         i!(PushBase,                    push_base),
-        i!(RadiationDec,                unimplemented),
-        i!(RadiationInc,                unimplemented),
+        i!(RadiationDec,                radiation_dec),
+        i!(RadiationInc,                radiation_inc),
         i!(Random,                      random),
-        i!(ReactionInfluence,           unimplemented),
+        i!(ReactionInfluence,           reaction_influence),
         i!(Refreshmouse,                unimplemented),
         i!(RegAnimAnimate,              unimplemented),
         i!(RegAnimAnimateForever,       reg_anim_animate_forever),
@@ -645,7 +660,7 @@ pub(in super) mod instructions {
         i!(RegAnimObjMoveToTile,        unimplemented),
         i!(RegAnimObjRunToObj,          unimplemented),
         i!(RegAnimObjRunToTile,         unimplemented),
-        i!(RegAnimPlaySfx,              unimplemented),
+        i!(RegAnimPlaySfx,              reg_anim_play_sfx),
         i!(Resizewin,                   unimplemented),
         i!(RmMultObjsFromInven,         unimplemented),
         i!(RmObjFromInven,              unimplemented),
@@ -658,14 +673,14 @@ pub(in super) mod instructions {
         i!(Sayend,                      unimplemented),
         i!(Saygetlastpos,               unimplemented),
         i!(Saygotoreply,                unimplemented),
-        i!(Saymessage,                  unimplemented),
+        i!(Saymessage,                  say_message),
         i!(Saymessagetimeout,           unimplemented),
-        i!(Sayoption,                   unimplemented),
+        i!(Sayoption,                   say_option),
         i!(Sayoptioncolor,              unimplemented),
         i!(Sayoptionflags,              unimplemented),
         i!(Sayoptionwindow,             unimplemented),
         i!(Sayquit,                     unimplemented),
-        i!(Sayreply,                    unimplemented),
+        i!(Sayreply,                    say_reply),
         i!(Sayreplycolor,               unimplemented),
         i!(Sayreplyflags,               unimplemented),
         i!(Sayreplytitle,               unimplemented),
@@ -692,35 +707,35 @@ pub(in super) mod instructions {
         i!(Sethighlightcolor,           unimplemented),
         i!(SetLightLevel,               set_light_level),
         i!(SetLocalVar,                 set_local_var),
-        i!(SetMapMusic,                 unimplemented),
+        i!(SetMapMusic,                 set_map_music),
         i!(SetMapStart,                 unimplemented),
         i!(SetMapVar,                   set_map_var),
         i!(SetObjVisibility,            set_obj_visibility),
         i!(Setoneoptpause,              unimplemented),
         i!(Settextcolor,                unimplemented),
         i!(Settextflags,                unimplemented),
-        i!(SfxBuildAmbientName,         unimplemented),
-        i!(SfxBuildCharName,            unimplemented),
-        i!(SfxBuildInterfaceName,       unimplemented),
-        i!(SfxBuildItemName,            unimplemented),
-        i!(SfxBuildOpenName,            unimplemented),
-        i!(SfxBuildSceneryName,         unimplemented),
-        i!(SfxBuildWeaponName,          unimplemented),
+        i!(SfxBuildAmbientName,         sfx_build_ambient_name),
+        i!(SfxBuildCharName,            sfx_build_char_name),
+        i!(SfxBuildInterfaceName,       sfx_build_interface_name),
+        i!(SfxBuildItemName,            sfx_build_item_name),
+        i!(SfxBuildOpenName,            sfx_build_open_name),
+        i!(SfxBuildSceneryName,         sfx_build_scenery_name),
+        i!(SfxBuildWeaponName,          sfx_build_weapon_name),
         i!(Showmouse,                   unimplemented),
         i!(Showwin,                     unimplemented),
-        i!(Signalnamed,                 unimplemented),
+        i!(Signalnamed,                 signalnamed),
         i!(SkillContest,                unimplemented),
-        i!(Sounddelete,                 unimplemented),
-        i!(Soundpause,                  unimplemented),
-        i!(Soundplay,                   unimplemented),
-        i!(Soundresume,                 unimplemented),
-        i!(Soundrewind,                 unimplemented),
-        i!(Soundstop,                   unimplemented),
+        i!(Sounddelete,                 sounddelete),
+        i!(Soundpause,                  soundpause),
+        i!(Soundplay,                   soundplay),
+        i!(Soundresume,                 soundresume),
+        i!(Soundrewind,                 soundrewind),
+        i!(Soundstop,                   soundstop),
         i!(SourceObj,                   source_obj),
-        i!(Spawn,                       unimplemented),
+        i!(Spawn,                       spawn),
         i!(StartGdialog,                start_gdialog),
-        i!(Stopmovie,                   unimplemented),
-        i!(StopProg,                    unimplemented),
+        i!(Stopmovie,                   stopmovie),
+        i!(StopProg,                    stop_prog),
         i!(Store,                       store),
         i!(StoreExternal,               store_external),
         i!(StoreGlobal,                 store_global),
@@ -728,7 +743,7 @@ pub(in super) mod instructions {
         i!(Swap,                        swap),
         i!(Swapa,                       swapa),
         i!(TargetObj,                   target_obj),
-        i!(TerminateCombat,             unimplemented),
+        i!(TerminateCombat,             terminate_combat),
         i!(TileContainsObjPid,          tile_contains_pid_obj),
         i!(TileContainsPidObj,          tile_contains_pid_obj),
         i!(TileDistance,                tile_distance),
@@ -741,17 +756,21 @@ pub(in super) mod instructions {
         i!(UseObj,                      unimplemented),
         i!(UseObjOnObj,                 unimplemented),
         i!(UsingSkill,                  unimplemented),
-        i!(Wait,                        unimplemented),
+        i!(Wait,                        wait),
         i!(While,                       while_),
         i!(WieldObjCritter,             unimplemented),
-        i!(WmAreaSetPos,                unimplemented),
-        i!(WorldMap,                    unimplemented),
+        i!(WmAreaSetPos,                wm_area_set_pos),
+        i!(WorldMap,                    world_map),
     ];
 }
 
-pub fn instruction_map() -> HashMap<u16, Instruction> {
+/// Returns instructions known to the game of `profile`.
+pub fn instruction_map(profile: Profile) -> HashMap<u16, Instruction> {
     let mut map = HashMap::new();
     for &instr in &instructions::INSTRUCTIONS[..] {
+        if profile == Profile::Fallout1 && !instr.opcode().is_fallout1() {
+            continue;
+        }
         map.insert(instr.opcode() as u16, instr);
     }
     map
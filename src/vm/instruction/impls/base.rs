@@ -1,5 +1,6 @@
 use log::*;
 use std::cmp::Ordering;
+use std::time::Duration;
 
 use super::*;
 
@@ -36,6 +37,17 @@ impl PersistentVarScope {
     }
 }
 
+fn start(ctx: Context, mode: StartMode) -> Result<()> {
+    let name = ctx.prg.data_stack.pop()?.into_string(ctx.prg.strings())?;
+    log_a1!(ctx.prg, &name);
+    ctx.prg.start(name, mode)?;
+    if mode == StartMode::Exec {
+        Err(Error::Halted)
+    } else {
+        Ok(())
+    }
+}
+
 fn cmp_test(ctx: Context, f: impl FnOnce(Option<Ordering>) -> bool) -> Result<()> {
     binary_op(ctx, |l, r, ctx| {
         let r = f(l.partial_cmp(&r, ctx.prg.strings())?);
@@ -74,6 +86,13 @@ pub fn add(ctx: Context) -> Result<()> {
     binary_op(ctx, |l, r, ctx| l.add(r, ctx.prg.strings()))
 }
 
+pub fn addnamedevent(ctx: Context) -> Result<()> {
+    let proc_id = ctx.prg.data_stack.pop()?.into_int()? as ProcedureId;
+    let name = ctx.prg.data_stack.pop()?.into_string(ctx.prg.strings())?;
+    log_a2!(ctx.prg, &name, proc_id);
+    ctx.prg.add_named_event(name, proc_id)
+}
+
 pub fn and(ctx: Context) -> Result<()> {
     binary_op(ctx, |l, r, _| Ok((l.test() && r.test()).into()))
 }
@@ -116,6 +135,39 @@ pub fn call(ctx: Context) -> Result<()> {
     Ok(())
 }
 
+pub fn call_at(ctx: Context) -> Result<()> {
+    let delay = ctx.prg.data_stack.pop()?.into_int()?;
+    let proc_id = ctx.prg.data_stack.pop()?.into_int()? as ProcedureId;
+    log_a2!(ctx.prg, proc_id, delay);
+    ctx.prg.call_at(proc_id, Duration::from_secs(delay.max(0) as u64))
+}
+
+pub fn call_condition(ctx: Context) -> Result<()> {
+    let pos = ctx.prg.data_stack.pop()?.into_int()?;
+    let proc_id = ctx.prg.data_stack.pop()?.into_int()? as ProcedureId;
+    log_a2!(ctx.prg, proc_id, pos);
+    if pos < 0 || pos as usize >= ctx.prg.code().len() {
+        return Err(Error::BadValue(BadValue::Content));
+    }
+    ctx.prg.call_condition(proc_id, pos as usize)
+}
+
+pub fn callstart(ctx: Context) -> Result<()> {
+    start(ctx, StartMode::Callstart)
+}
+
+pub fn cancel(ctx: Context) -> Result<()> {
+    let proc_id = ctx.prg.data_stack.pop()?.into_int()? as ProcedureId;
+    log_a1!(ctx.prg, proc_id);
+    ctx.prg.cancel(proc_id)
+}
+
+pub fn cancelall(ctx: Context) -> Result<()> {
+    log_!(ctx.prg);
+    ctx.prg.cancel_all();
+    Ok(())
+}
+
 pub fn div(ctx: Context) -> Result<()> {
     binary_op(ctx, |l, r, ctx| l.div(r, ctx.prg.strings()))
 }
@@ -152,6 +204,12 @@ pub fn debug_msg(ctx: Context) -> Result<()> {
     Ok(())
 }
 
+pub fn detach(ctx: Context) -> Result<()> {
+    log_!(ctx.prg);
+    ctx.prg.detach();
+    Ok(())
+}
+
 pub fn dtoa(ctx: Context) -> Result<()> {
     let v = ctx.prg.data_stack.pop()?;
     ctx.prg.return_stack.push(v)?;
@@ -192,6 +250,10 @@ pub fn exit_prog(ctx: Context) -> Result<()> {
     Err(Error::Halted)
 }
 
+pub fn exec(ctx: Context) -> Result<()> {
+    start(ctx, StartMode::Exec)
+}
+
 pub fn export_var(ctx: Context) -> Result<()> {
     let name = ctx.prg.data_stack.pop()?;
     let name = name.into_string(ctx.prg.names())?;
@@ -204,6 +266,10 @@ pub fn export_var(ctx: Context) -> Result<()> {
     }
 }
 
+pub fn fork(ctx: Context) -> Result<()> {
+    start(ctx, StartMode::Fork)
+}
+
 pub fn fetch_external(ctx: Context) -> Result<()> {
     let name = ctx.prg.data_stack.pop()?.into_string(ctx.prg.names())?;
 
@@ -385,6 +451,23 @@ pub fn script_overrides(ctx: Context) -> Result<()> {
     Ok(())
 }
 
+pub fn signalnamed(ctx: Context) -> Result<()> {
+    let name = ctx.prg.data_stack.pop()?.into_string(ctx.prg.strings())?;
+    log_a1!(ctx.prg, &name);
+    ctx.prg.signal_named(name);
+    Ok(())
+}
+
+pub fn spawn(ctx: Context) -> Result<()> {
+    start(ctx, StartMode::Spawn)
+}
+
+pub fn stop_prog(ctx: Context) -> Result<()> {
+    log_!(ctx.prg);
+    ctx.prg.stop();
+    Err(Error::Halted)
+}
+
 pub fn store(ctx: Context) -> Result<()> {
     let id = ctx.prg.data_stack.pop()?.into_int()? as usize;
     let value = ctx.prg.data_stack.pop()?;
@@ -442,6 +525,13 @@ pub fn unimplemented(ctx: Context) -> Result<()> {
     Err(Error::UnimplementedOpcode(ctx.prg.opcode.unwrap().0))
 }
 
+pub fn wait(ctx: Context) -> Result<()> {
+    let ms = ctx.prg.data_stack.pop()?.into_int()?;
+    log_a1!(ctx.prg, ms);
+    ctx.prg.wait(Duration::from_millis(ms.max(0) as u64));
+    Ok(())
+}
+
 pub fn while_(ctx: Context) -> Result<()> {
     let done = ctx.prg.data_stack.pop()?;
     if !done.test() {
@@ -5,19 +5,28 @@ use num_traits::FromPrimitive;
 use static_assertions::const_assert;
 use std::cmp;
 use std::convert::{TryFrom, TryInto};
+use std::time::Duration;
 
 use super::*;
-use crate::asset::{ExactEntityKind, Flag, Perk, Skill, Stat, Trait};
+use crate::asset::{DamageKind, ExactEntityKind, Flag, PCStat, Perk, Skill, Stat, Trait};
 use crate::asset::proto::ProtoId;
 use crate::asset::script::ProgramId;
-use crate::game::dialog::Dialog;
+use crate::game::barter;
+use crate::game::combat;
+use crate::game::dialog::{Dialog, Head, Reaction};
+use crate::game::movie::CREDITS_MOVIE;
+use crate::game::queue::{self, EventKind};
 use crate::game::script::ScriptPid;
+use crate::game::sfx;
+use crate::game::ui::talking_head::Mood;
 use crate::game::world::floating_text;
-use crate::graphics::{EPoint, Point};
+use crate::game::worldmap::AreaState;
+use crate::graphics::{EPoint, Point, Rect};
 use crate::graphics::color::*;
 use crate::graphics::font::FontKey;
 use crate::graphics::geometry::hex::Direction;
 use crate::sequence::chain::Chain;
+use crate::sound::{Channel, VoiceId};
 use crate::util::random::{random as rand, RollCheckResult};
 
 /// This is also known as "trait" by `has_trait()`, `critter_add_trait` etc instructions.
@@ -57,6 +66,47 @@ fn resolve_script_msg(msg: Value, program_id: ProgramId, ctx: &mut Context) -> R
     })
 }
 
+/// Returns speech file name of the message if `msg` is a message ID.
+fn script_msg_audio(msg: &Value, program_id: ProgramId, ctx: &mut Context) -> Option<BString> {
+    if let &Value::Int(msg_id) = msg {
+        let msgs = ctx.ext.script_db.messages(program_id).ok()?;
+        Some(msgs.get(msg_id)?.audio.clone())
+    } else {
+        None
+    }
+}
+
+/// Resolves dialog option procedure that can be given either by ID or name.
+fn resolve_dialog_proc(proc: Value, ctx: &mut Context) -> Result<u32> {
+    match proc {
+        Value::Int(v) => u32::try_from(v).map_err(|_| Error::BadValue(BadValue::Content)),
+        Value::String(name) => {
+            let name = name.resolve(ctx.prg.strings())?;
+            ctx.prg.program().proc_id(&name).ok_or(Error::BadValue(BadValue::Content))
+        }
+        _ => Err(Error::BadValue(BadValue::Type)),
+    }
+}
+
+fn dialog_reaction(reaction: i32) -> Result<Reaction> {
+    Reaction::from_i32(reaction).ok_or(Error::BadValue(BadValue::Content))
+}
+
+fn set_dialog_reply(reply: &bstr, audio: Option<BString>, ctx: &mut Context) {
+    let dialog = ctx.ext.dialog.as_mut().unwrap();
+    dialog.set_reply(ctx.ext.ui, reply);
+    dialog.clear_options(ctx.ext.ui);
+    if let Some(audio) = audio {
+        dialog.speak(ctx.ext.ui, ctx.ext.sound, &audio);
+    }
+}
+
+// gdialogOption(), gdialogOptionStr()
+fn add_dialog_option(msg: &bstr, proc_id: u32, reaction: Reaction, ctx: &mut Context) {
+    let dialog = ctx.ext.dialog.as_mut().unwrap();
+    dialog.add_option(ctx.ext.ui, msg, Some(proc_id), reaction);
+}
+
 fn to_tile_num(ctx: &Context, p: Point) -> Option<i32> {
     ctx.ext.world.hex_grid().rect_to_linear_inv(p).map(|v| v as i32)
 }
@@ -158,8 +208,10 @@ pub fn add_timer_event(ctx: Context) -> Result<()> {
     let obj = ctx.prg.data_stack.pop()?.coerce_into_object()?
         .ok_or(Error::BadValue(BadValue::Content))?;
 
+    let time = ctx.ext.world.game_time.add_decis(cmp::max(time, 0) as u32);
+    ctx.ext.queue.push(time, Some(obj), queue::Event::Script { fixed_param: info });
+
     log_a3!(ctx.prg, obj, time, info);
-    log_stub!(ctx.prg);
 
     Ok(())
 }
@@ -179,11 +231,76 @@ pub fn anim(ctx: Context) -> Result<()> {
     Ok(())
 }
 
+pub fn attack(ctx: Context) -> Result<()> {
+    let target_results = ctx.prg.data_stack.pop()?.into_int()?;
+    let attacker_results = ctx.prg.data_stack.pop()?.into_int()?;
+    let max_damage = ctx.prg.data_stack.pop()?.into_int()?;
+    let min_damage = ctx.prg.data_stack.pop()?.into_int()?;
+    let bonus = ctx.prg.data_stack.pop()?.into_int()?;
+    let num_attacks = ctx.prg.data_stack.pop()?.into_int()?;
+    let called_shot = ctx.prg.data_stack.pop()?.into_int()?;
+    let target = ctx.prg.data_stack.pop()?.coerce_into_object()?
+        .ok_or(Error::BadValue(BadValue::Content))?;
+
+    log_a5!(ctx.prg, target, called_shot, num_attacks, bonus, (min_damage, max_damage));
+    debug!("  attacker_results={} target_results={}", attacker_results, target_results);
+
+    // The `attack` macro passes 0 which means no called shot rather than the head.
+    let called_shot = match called_shot {
+        0 => combat::HitLocation::Uncalled,
+        v => combat::HitLocation::from_i32(v).unwrap_or_default(),
+    };
+    let scripted = combat::ScriptedAttack {
+        called_shot,
+        num_attacks: cmp::max(num_attacks, 1) as u32,
+        hit_chance_bonus: bonus,
+        min_damage,
+        max_damage,
+    };
+    if let Some(attacker) = ctx.ext.self_obj {
+        ctx.ext.combat.request(combat::Request::Attack {
+            attacker,
+            target,
+            scripted: Some(scripted),
+        });
+    } else {
+        log_error!(ctx.prg, "no self_obj");
+    }
+
+    Ok(())
+}
+
+pub fn attack_setup(ctx: Context) -> Result<()> {
+    let target = ctx.prg.data_stack.pop()?.coerce_into_object()?
+        .ok_or(Error::BadValue(BadValue::Content))?;
+    let attacker = ctx.prg.data_stack.pop()?.coerce_into_object()?
+        .ok_or(Error::BadValue(BadValue::Content))?;
+
+    log_a2!(ctx.prg, attacker, target);
+
+    ctx.ext.combat.request(combat::Request::Attack { attacker, target, scripted: None });
+
+    Ok(())
+}
+
+pub fn combat_difficulty(ctx: Context) -> Result<()> {
+    let r = ctx.ext.combat.difficulty() as i32;
+    ctx.prg.data_stack.push(r.into())?;
+    log_r1!(ctx.prg, r);
+    Ok(())
+}
+
+pub fn difficulty_level(ctx: Context) -> Result<()> {
+    let r = ctx.ext.rpg.game_difficulty() as i32;
+    ctx.prg.data_stack.push(r.into())?;
+    log_r1!(ctx.prg, r);
+    Ok(())
+}
+
 pub fn combat_is_initialized(ctx: Context) -> Result<()> {
-    let r = false;
+    let r = ctx.ext.combat.is_active();
     ctx.prg.data_stack.push(r.into())?;
     log_r1!(ctx.prg, r);
-    log_stub!(ctx.prg);
     Ok(())
 }
 
@@ -248,7 +365,28 @@ pub fn critter_add_trait(ctx: Context) -> Result<()> {
     ctx.prg.data_stack.push(r.into())?;
 
     log_a4r1!(ctx.prg, obj, kind, sub_kind, value, r);
-    log_stub!(ctx.prg);
+
+    let otr = (Attribute::from_i32(kind) == Some(Attribute::Object))
+        .then(|| ObjectTrait::from_i32(sub_kind))
+        .flatten();
+    match (obj, otr) {
+        (Some(obj), Some(otr @ (ObjectTrait::AiPacket | ObjectTrait::TeamId))) => {
+            // The party members stay on the dude's team.
+            let party_member = ctx.ext.party.contains(obj);
+            if let Some(c) = ctx.ext.world.objects().get_mut(obj).sub.as_critter_mut() {
+                match otr {
+                    ObjectTrait::AiPacket => c.combat.ai_packet = value,
+                    _ if !party_member => c.combat.team_id = value,
+                    _ => {}
+                }
+            } else {
+                log_error!(ctx.prg, "object is not a Critter");
+            }
+        }
+        (None, _) => { log_error!(ctx.prg, "object is null"); }
+        _ => { log_stub!(ctx.prg); }
+    }
+
     Ok(())
 }
 
@@ -267,6 +405,30 @@ pub fn critter_attempt_placement(ctx: Context) -> Result<()> {
     Ok(())
 }
 
+pub fn critter_damage(ctx: Context) -> Result<()> {
+    const BYPASS_ARMOR: i32 = 0x100;
+    const NO_ANIMATE: i32 = 0x200;
+
+    let kind = ctx.prg.data_stack.pop()?.into_int()?;
+    let damage = ctx.prg.data_stack.pop()?.into_int()?;
+    let target = ctx.prg.data_stack.pop()?.coerce_into_object()?
+        .ok_or(Error::BadValue(BadValue::Content))?;
+
+    log_a3!(ctx.prg, target, damage, kind);
+
+    let damage_kind = DamageKind::from_i32(kind & !(BYPASS_ARMOR | NO_ANIMATE))
+        .ok_or(Error::BadValue(BadValue::Content))?;
+    ctx.ext.combat.request(combat::Request::Damage {
+        target,
+        damage,
+        damage_kind,
+        bypass_armor: kind & BYPASS_ARMOR != 0,
+        animate: kind & NO_ANIMATE == 0,
+    });
+
+    Ok(())
+}
+
 pub fn critter_inven_obj(ctx: Context) -> Result<()> {
     let query = ctx.prg.data_stack.pop()?.into_int()?;
     let obj = ctx.prg.data_stack.pop()?.coerce_into_object()?;
@@ -279,6 +441,30 @@ pub fn critter_inven_obj(ctx: Context) -> Result<()> {
     Ok(())
 }
 
+pub fn critter_is_fleeing(ctx: Context) -> Result<()> {
+    let obj = ctx.prg.data_stack.pop()?.coerce_into_object()?
+        .ok_or(Error::BadValue(BadValue::Content))?;
+    let r = ctx.ext.world.objects().get(obj).sub.as_critter()
+        .map(|c| c.combat.fleeing)
+        .unwrap_or_else(|| { log_error!(ctx.prg, "object is not a Critter"); false });
+    ctx.prg.data_stack.push(r.into())?;
+    log_a1r1!(ctx.prg, obj, r);
+    Ok(())
+}
+
+pub fn critter_set_flee_state(ctx: Context) -> Result<()> {
+    let fleeing = ctx.prg.data_stack.pop()?.into_int()? != 0;
+    let obj = ctx.prg.data_stack.pop()?.coerce_into_object()?
+        .ok_or(Error::BadValue(BadValue::Content))?;
+    log_a2!(ctx.prg, obj, fleeing);
+    if let Some(c) = ctx.ext.world.objects().get_mut(obj).sub.as_critter_mut() {
+        c.combat.fleeing = fleeing;
+    } else {
+        log_error!(ctx.prg, "object is not a Critter");
+    }
+    Ok(())
+}
+
 pub fn cur_map_index(ctx: Context) -> Result<()> {
     let r = ctx.ext.map_id;
     ctx.prg.data_stack.push(r.try_into().unwrap())?;
@@ -286,6 +472,13 @@ pub fn cur_map_index(ctx: Context) -> Result<()> {
     Ok(())
 }
 
+pub fn days_since_visited(ctx: Context) -> Result<()> {
+    let r = ctx.ext.world_map.days_since_visited(ctx.ext.world.game_time) as i32;
+    ctx.prg.data_stack.push(r.into())?;
+    log_r1!(ctx.prg, r);
+    Ok(())
+}
+
 pub fn destroy_object(ctx: Context) -> Result<()> {
     let obj = ctx.prg.data_stack.pop()?.coerce_into_object()?;
     log_a1!(ctx.prg, obj);
@@ -293,6 +486,23 @@ pub fn destroy_object(ctx: Context) -> Result<()> {
     Ok(())
 }
 
+pub fn dialogue_reaction(ctx: Context) -> Result<()> {
+    let reaction = ctx.prg.data_stack.pop()?.into_int()?;
+    let mood = match reaction {
+        0 => Mood::Good,
+        1 => Mood::Neutral,
+        2 => Mood::Bad,
+        _ => return Err(Error::BadValue(BadValue::Content)),
+    };
+    if let Some(dialog) = ctx.ext.dialog.as_ref() {
+        dialog.react(ctx.ext.ui, mood);
+    } else {
+        log_error!(ctx.prg, "not in dialog");
+    }
+    log_a1!(ctx.prg, reaction);
+    Ok(())
+}
+
 pub fn display_msg(ctx: Context) -> Result<()> {
     use crate::ui::message_panel::MessagePanel;
 
@@ -345,11 +555,19 @@ pub fn elevation(ctx: Context) -> Result<()> {
 }
 
 pub fn end_dialogue(ctx: Context) -> Result<()> {
+    ctx.ext.sound.mixer().stop_channel(Channel::Speech);
     ctx.ext.dialog.take().unwrap().hide(ctx.ext.ui, ctx.ext.world);
     log_!(ctx.prg);
     Ok(())
 }
 
+pub fn endgame_movie(ctx: Context) -> Result<()> {
+    // TODO The original rolls credits.txt over the "akiss" music instead.
+    ctx.ext.movies.play_game_movie(CREDITS_MOVIE);
+    log_!(ctx.prg);
+    Ok(())
+}
+
 #[derive(Clone, Copy, Debug, Linearize, Eq, PartialEq, Primitive)]
 #[repr(u32)]
 enum FloatingTextStyle {
@@ -377,6 +595,13 @@ impl FloatingTextStyle {
 
 const_assert!(FloatingTextStyle::SEQ_MIN <= FloatingTextStyle::SEQ_MAX);
 
+pub fn fixed_param(ctx: Context) -> Result<()> {
+    let r = ctx.ext.fixed_param;
+    ctx.prg.data_stack.push(r.into())?;
+    log_r1!(ctx.prg, r);
+    Ok(())
+}
+
 pub fn float_msg(ctx: Context) -> Result<()> {
     let style = FloatingTextStyle::from_i32(ctx.prg.data_stack.pop()?.into_int()?);
     let msg = ctx.prg.data_stack.pop()?.into_string(ctx.prg.strings())?;
@@ -452,6 +677,16 @@ pub fn game_time(ctx: Context) -> Result<()> {
     Ok(())
 }
 
+/// Due queue events are fired by the game loop after the script returns.
+// op_game_time_advance()
+pub fn game_time_advance(ctx: Context) -> Result<()> {
+    let ticks = ctx.prg.data_stack.pop()?.into_int()?;
+    let world = &mut ctx.ext.world;
+    world.game_time = world.game_time.add_decis(cmp::max(ticks, 0) as u32);
+    log_a1!(ctx.prg, ticks);
+    Ok(())
+}
+
 pub fn game_time_hour(ctx: Context) -> Result<()> {
     let time = ctx.ext.world.game_time;
     let r = 100 * time.hour() as u32 + time.minute() as u32;
@@ -468,18 +703,28 @@ pub fn game_time_in_seconds(ctx: Context) -> Result<()> {
 }
 
 pub fn gdialog_barter(ctx: Context) -> Result<()> {
-    let r = 0;
+    let barter_mod = ctx.prg.data_stack.pop()?.into_int()?;
+    let r = if let Some(dialog) = ctx.ext.dialog.as_mut() {
+        dialog.barter_mod = barter_mod;
+        dialog.barter();
+        0
+    } else {
+        log_error!(ctx.prg, "not in dialog");
+        -1
+    };
     ctx.prg.data_stack.push(r.into())?;
-    log_r1!(ctx.prg, r);
-    log_stub!(ctx.prg);
+    log_a1r1!(ctx.prg, barter_mod, r);
     Ok(())
 }
 
 pub fn gdialog_set_barter_mod(ctx: Context) -> Result<()> {
     let val = ctx.prg.data_stack.pop()?.into_int()?;
-
+    if let Some(dialog) = ctx.ext.dialog.as_mut() {
+        dialog.barter_mod = val;
+    } else {
+        log_error!(ctx.prg, "not in dialog");
+    }
     log_a1!(ctx.prg, val);
-    log_stub!(ctx.prg);
     Ok(())
 }
 
@@ -487,10 +732,14 @@ pub fn get_critter_stat(ctx: Context) -> Result<()> {
     let stat = Stat::from_i32(ctx.prg.data_stack.pop()?.coerce_into_int()?)
         .ok_or(Error::BadValue(BadValue::Content))?;
     let obj = ctx.prg.data_stack.pop()?.coerce_into_object()?;
-    let r = 5;
+    let r = if let Some(obj) = obj {
+        ctx.ext.rpg.stat(stat, &ctx.ext.world.objects().get(obj), ctx.ext.world.objects())
+    } else {
+        log_error!(ctx.prg, "object is null");
+        0
+    };
     ctx.prg.data_stack.push(Value::Int(r))?;
     log_a2r1!(ctx.prg, obj, stat, r);
-    log_stub!(ctx.prg);
     Ok(())
 }
 
@@ -508,8 +757,16 @@ pub fn get_month(ctx: Context) -> Result<()> {
     Ok(())
 }
 
+pub fn get_poison(ctx: Context) -> Result<()> {
+    let obj = ctx.prg.data_stack.pop()?.coerce_into_object()?
+        .ok_or(Error::BadValue(BadValue::Content))?;
+    let r = ctx.ext.world.objects().get(obj).sub.as_critter().map(|c| c.poison).unwrap_or(0);
+    ctx.prg.data_stack.push(Value::Int(r))?;
+    log_a1r1!(ctx.prg, obj, r);
+    Ok(())
+}
+
 pub fn giq_option(mut ctx: Context) -> Result<()> {
-    // FIXME display reaction with Empathy perk.
     let reaction = ctx.prg.data_stack.pop()?.into_int()?;
     let proc = ctx.prg.data_stack.pop()?;
     let msg = ctx.prg.data_stack.pop()?;
@@ -517,20 +774,21 @@ pub fn giq_option(mut ctx: Context) -> Result<()> {
     let min_or_max_iq = ctx.prg.data_stack.pop()?.into_int()?;
 
     let msg = resolve_script_msg(msg, program_id, &mut ctx)?;
-
-    let iq = 5_i32; // FIXME stat_level_(g_obj_dude, STAT_INT);
-    let smooth_talker = 0; // FIXME perk_level_(g_obj_dude, PERK_smooth_talker);
-    let iq = iq + smooth_talker;
-
-    // FIXME proc can also be a string
-    let proc_id = proc.into_int()?;
+    let proc_id = resolve_dialog_proc(proc, &mut ctx)?;
+    let reaction_ = dialog_reaction(reaction)?;
+
+    let iq = {
+        let objs = ctx.ext.world.objects();
+        let dude = objs.get(objs.dude());
+        ctx.ext.rpg.stat(Stat::Intelligence, &dude, objs)
+            + ctx.ext.rpg.perk(Perk::SmoothTalker, dude.proto_id().unwrap()) as i32
+    };
 
     assert!(ctx.ext.dialog.is_some());
 
     // If negative it defines upper bound, otherwise it's the lower bound.
     if min_or_max_iq < 0 && -iq >= min_or_max_iq || min_or_max_iq >= 0 && iq >= min_or_max_iq {
-        let dialog = ctx.ext.dialog.as_mut().unwrap();
-        dialog.add_option(ctx.ext.ui, &*msg, Some(proc_id as u32));
+        add_dialog_option(&msg, proc_id, reaction_, &mut ctx);
     }
 
     log_a5!(ctx.prg, min_or_max_iq, program_id, msg, proc_id, reaction);
@@ -542,26 +800,35 @@ pub fn give_exp_points(ctx: Context) -> Result<()> {
     let points = ctx.prg.data_stack.pop()?.into_int()?;
 
     log_a1!(ctx.prg, points);
-    log_stub!(ctx.prg);
+
+    let objs = ctx.ext.world.objects();
+    let levels = ctx.ext.rpg.add_experience(points, &mut objs.get_mut(objs.dude()), objs);
+    if levels > 0 {
+        debug!("  dude gained {} level(s)", levels);
+        let level = ctx.ext.rpg.pc_stat(PCStat::Level);
+        for level in level + 1 - levels as i32..=level {
+            ctx.ext.party.level_up(level, ctx.ext.proto_db, ctx.ext.rpg, objs);
+        }
+    }
 
     Ok(())
 }
 
 pub fn gsay_message(mut ctx: Context) -> Result<()> {
-    // FIXME display reaction with Empathy perk.
     let reaction = ctx.prg.data_stack.pop()?.into_int()?;
     let msg = ctx.prg.data_stack.pop()?;
     let program_id = pop_program_id(&mut ctx)?;
 
+    let audio = script_msg_audio(&msg, program_id, &mut ctx);
     let reply = resolve_script_msg(msg, program_id, &mut ctx)?;
+    let reaction_ = dialog_reaction(reaction)?;
     let option = &ctx.ext.proto_db.messages().get(650).unwrap().text;
 
     assert!(ctx.ext.dialog.is_some());
 
+    set_dialog_reply(&reply, audio, &mut ctx);
     let dialog = ctx.ext.dialog.as_mut().unwrap();
-    dialog.set_reply(ctx.ext.ui, &*reply);
-    dialog.clear_options(ctx.ext.ui);
-    dialog.add_option(ctx.ext.ui, option, None);
+    dialog.add_option(ctx.ext.ui, option, None, reaction_);
 
     log_a3!(ctx.prg, program_id, reply, reaction);
 
@@ -582,23 +849,33 @@ pub fn gsay_start(ctx: Context) -> Result<()> {
     Ok(())
 }
 
+pub fn gsay_option(mut ctx: Context) -> Result<()> {
+    let reaction = ctx.prg.data_stack.pop()?.into_int()?;
+    let proc = ctx.prg.data_stack.pop()?;
+    let msg = ctx.prg.data_stack.pop()?;
+    let program_id = pop_program_id(&mut ctx)?;
+
+    let msg = resolve_script_msg(msg, program_id, &mut ctx)?;
+    let proc_id = resolve_dialog_proc(proc, &mut ctx)?;
+    let reaction_ = dialog_reaction(reaction)?;
+
+    assert!(ctx.ext.dialog.is_some());
+    add_dialog_option(&msg, proc_id, reaction_, &mut ctx);
+
+    log_a4!(ctx.prg, program_id, msg, proc_id, reaction);
+
+    Ok(())
+}
+
 pub fn gsay_reply(mut ctx: Context) -> Result<()> {
     let reply = ctx.prg.data_stack.pop()?;
     let program_id = pop_program_id(&mut ctx)?;
 
-    let reply_str = match reply {
-        Value::Int(msg_id) => {
-            let msgs = ctx.ext.script_db.messages(program_id).unwrap();
-            Rc::new(msgs.get(msg_id).unwrap().text.clone())
-        },
-        Value::String(s) => s.resolve(ctx.prg.strings())?,
-        _ => return Err(Error::BadValue(BadValue::Content)),
-    };
+    let audio = script_msg_audio(&reply, program_id, &mut ctx);
+    let reply_str = resolve_script_msg(reply, program_id, &mut ctx)?;
 
     assert!(ctx.ext.dialog.is_some());
-    let dialog = ctx.ext.dialog.as_mut().unwrap();
-    dialog.set_reply(ctx.ext.ui, &*reply_str);
-    dialog.clear_options(ctx.ext.ui);
+    set_dialog_reply(&reply_str, audio, &mut ctx);
 
     log_a2!(ctx.prg, reply_str, program_id);
 
@@ -682,12 +959,54 @@ pub fn has_trait(ctx: Context) -> Result<()> {
     Ok(())
 }
 
+// item_caps_adjust()
+pub fn item_caps_adjust(ctx: Context) -> Result<()> {
+    let amount = ctx.prg.data_stack.pop()?.into_int()?;
+    let obj = ctx.prg.data_stack.pop()?.coerce_into_object()?
+        .ok_or(Error::BadValue(BadValue::Content))?;
+
+    let objs = ctx.ext.world.objects_mut();
+    let r = if amount >= 0 {
+        if amount > 0 {
+            let proto = ctx.ext.proto_db.proto(ProtoId::BOTTLE_CAPS)
+                .map_err(|e| {
+                    error!("error loading caps proto: {:?}", e);
+                    Error::BadValue(BadValue::Content)
+                })?;
+            let caps = objs.create(None, Some(proto), None, None).handle();
+            objs.move_into_inventory(obj, caps, amount as u32);
+        }
+        0
+    } else if objs.get(obj).inventory.caps(objs) < amount.unsigned_abs() {
+        -1
+    } else {
+        let mut left = amount.unsigned_abs();
+        while left > 0 {
+            let item = objs.get(obj).inventory.items.iter()
+                .find(|i| objs.get(i.object).proto_id() == Some(ProtoId::BOTTLE_CAPS))
+                .map(|i| (i.object, i.count))
+                .unwrap();
+            let count = cmp::min(item.1, left);
+            if objs.remove_from_inventory(obj, item.0, count) == Some(0) {
+                objs.remove(item.0);
+            }
+            left -= count;
+        }
+        0
+    };
+    ctx.prg.data_stack.push(r.into())?;
+    log_a2r1!(ctx.prg, obj, amount, r);
+    Ok(())
+}
+
+// item_caps_total()
 pub fn item_caps_total(ctx: Context) -> Result<()> {
-    let obj = ctx.prg.data_stack.pop()?.coerce_into_object()?;
-    let r = 0;
+    let obj = ctx.prg.data_stack.pop()?.coerce_into_object()?
+        .ok_or(Error::BadValue(BadValue::Content))?;
+    let objs = ctx.ext.world.objects();
+    let r = objs.get(obj).inventory.caps(objs) as i32;
     ctx.prg.data_stack.push(r.into())?;
-    log_a1r1!(ctx.prg, obj, ctx.prg.data_stack.top().unwrap());
-    log_stub!(ctx.prg);
+    log_a1r1!(ctx.prg, obj, r);
     Ok(())
 }
 
@@ -727,6 +1046,63 @@ pub fn jam_lock(ctx: Context) -> Result<()> {
     Ok(())
 }
 
+pub fn kill_critter(ctx: Context) -> Result<()> {
+    use crate::asset::CritterAnim;
+
+    let death_frame = ctx.prg.data_stack.pop()?.into_int()?;
+    let obj = ctx.prg.data_stack.pop()?.coerce_into_object()?
+        .ok_or(Error::BadValue(BadValue::Content))?;
+
+    log_a2!(ctx.prg, obj, death_frame);
+
+    if !ctx.ext.world.objects().get(obj).sub.is_critter() {
+        log_error!(ctx.prg, "object is not a critter");
+        return Err(Error::BadValue(BadValue::Content));
+    }
+    let anim = CritterAnim::from_i32(death_frame).unwrap_or(CritterAnim::FallBackSf);
+    ctx.ext.obj_sequencer.cancel(obj);
+    ctx.ext.combat.remove(obj);
+    combat::kill_critter(ctx.ext.world, obj, anim);
+
+    Ok(())
+}
+
+pub fn mark_area_known(ctx: Context) -> Result<()> {
+    const MARK_TYPE_TOWN: i32 = 0;
+    const MARK_TYPE_MAP: i32 = 1;
+
+    let state = ctx.prg.data_stack.pop()?.into_int()?;
+    let area = ctx.prg.data_stack.pop()?.into_int()?;
+    let kind = ctx.prg.data_stack.pop()?.into_int()?;
+
+    log_a3!(ctx.prg, kind, area, state);
+
+    let ok = match kind {
+        MARK_TYPE_TOWN => {
+            let state = match state {
+                0 => AreaState::Unknown,
+                1 => AreaState::Known,
+                2 => AreaState::Visited,
+                _ => return Err(Error::BadValue(BadValue::Content)),
+            };
+            ctx.ext.world_map.set_area_state(area as u32, state)
+        }
+        MARK_TYPE_MAP => {
+            if let Some(map_def) = ctx.ext.map_db.get(area as u32) {
+                ctx.ext.world_map.set_map_known(&map_def.lookup_name, state != 0)
+            } else {
+                false
+            }
+        }
+        _ => return Err(Error::BadValue(BadValue::Content)),
+    };
+    if !ok {
+        log_error!(ctx.prg, "unknown area or map");
+    }
+
+    Ok(())
+}
+
 pub fn message_str(mut ctx: Context) -> Result<()> {
     let msg_id = ctx.prg.data_stack.pop()?.into_int()?;
     let program_id = pop_program_id(&mut ctx)?;
@@ -753,8 +1129,16 @@ pub fn metarule(ctx: Context) -> Result<()> {
             SignalEndGame   => 0.into(),
             TestFirstrun    => 1.into(),
             Elevator        => 0.into(),
-            PartyCount      => 0.into(),
-            AreaKnown       => 1.into(),
+            PartyCount      => {
+                stub = false;
+                (ctx.ext.party.count(ctx.ext.world.objects()) as i32).into()
+            }
+            AreaKnown       => {
+                stub = false;
+                ctx.ext.world_map.area(arg.coerce_into_int()? as u32)
+                    .is_some_and(|a| a.state != AreaState::Unknown)
+                    .into()
+            }
             WhoOnDrugs      => 0.into(),
             MapKnown        => 1.into(),
             IsLoadgame      => 0.into(),
@@ -768,13 +1152,27 @@ pub fn metarule(ctx: Context) -> Result<()> {
             }
             DropAllInven    => 0.into(),
             InvenUnwieldWho => 0.into(),
-            GetWorldmapXpos => 0.into(),
-            GetWorldmapYpos => 0.into(),
-            CurrentTown     => 0.into(),
+            GetWorldmapXpos => {
+                stub = false;
+                ctx.ext.world_map.pos().x.into()
+            }
+            GetWorldmapYpos => {
+                stub = false;
+                ctx.ext.world_map.pos().y.into()
+            }
+            CurrentTown     => {
+                stub = false;
+                ctx.ext.world_map.current_area().map(|v| v as i32).unwrap_or(-1).into()
+            }
             LanguageFilter  => 0.into(),
             ViolenceFilter  => 0.into(),
             WDamageType     => 0.into(),
-            CritterBarters  => 0.into(),
+            CritterBarters  => {
+                stub = false;
+                let obj = arg.coerce_into_object()?
+                    .ok_or(Error::BadValue(BadValue::Content))?;
+                barter::can_barter(&ctx.ext.world.objects().get(obj), ctx.ext.rpg).into()
+            }
             CritterKillType => 0.into(),
             CarTrunkSetAnim => 0.into(),
             CarTrunkGetAnim => 0.into(),
@@ -838,6 +1236,13 @@ pub fn metarule3(ctx: Context) -> Result<()> {
     Ok(())
 }
 
+pub fn movieflags(ctx: Context) -> Result<()> {
+    let flags = ctx.prg.data_stack.pop()?.into_int()?;
+    ctx.ext.movies.set_flags(flags as u32);
+    log_a1!(ctx.prg, flags);
+    Ok(())
+}
+
 pub fn move_obj_inven_to_obj(ctx: Context) -> Result<()> {
     let dst = ctx.prg.data_stack.pop()?.coerce_into_object()?;
     let src = ctx.prg.data_stack.pop()?.coerce_into_object()?;
@@ -1032,15 +1437,115 @@ pub fn override_map_start(ctx: Context) -> Result<()> {
     Ok(())
 }
 
+pub fn party_add(ctx: Context) -> Result<()> {
+    let obj = ctx.prg.data_stack.pop()?.coerce_into_object()?
+        .ok_or(Error::BadValue(BadValue::Content))?;
+    log_a1!(ctx.prg, obj);
+    if !ctx.ext.party.add(obj, ctx.ext.world.objects(), ctx.ext.rpg) {
+        log_error!(ctx.prg, "object can't join the party");
+    }
+    Ok(())
+}
+
 pub fn party_member_obj(ctx: Context) -> Result<()> {
     let pid = ctx.prg.data_stack.pop()?.into_int()?;
-    let r = Value::Object(None);
-    ctx.prg.data_stack.push(r)?;
+    let r = u32::try_from(pid).ok()
+        .and_then(ProtoId::from_packed)
+        .and_then(|pid| ctx.ext.party.find(pid, ctx.ext.world.objects()));
+    ctx.prg.data_stack.push(Value::Object(r))?;
     log_a1r1!(ctx.prg, pid, ctx.prg.data_stack.top().unwrap());
-    log_stub!(ctx.prg);
     Ok(())
 }
 
+pub fn party_remove(ctx: Context) -> Result<()> {
+    let obj = ctx.prg.data_stack.pop()?.coerce_into_object()?
+        .ok_or(Error::BadValue(BadValue::Content))?;
+    log_a1!(ctx.prg, obj);
+    if !ctx.ext.party.remove(obj) {
+        log_error!(ctx.prg, "object is not a party member");
+    }
+    Ok(())
+}
+
+pub fn play_gmovie(ctx: Context) -> Result<()> {
+    let id = ctx.prg.data_stack.pop()?.into_int()?;
+    if !ctx.ext.movies.play_game_movie(id as u32) {
+        return Err(Error::BadValue(BadValue::Content));
+    }
+    log_a1!(ctx.prg, id);
+    Ok(())
+}
+
+pub fn play_sfx(ctx: Context) -> Result<()> {
+    let name = ctx.prg.data_stack.pop()?.into_string(ctx.prg.strings())?;
+
+    ctx.ext.sound.play_sfx(&name.display().to_string(), 1.0);
+
+    log_a1!(ctx.prg, name);
+
+    Ok(())
+}
+
+pub fn playmovie(ctx: Context) -> Result<()> {
+    let path = ctx.prg.data_stack.pop()?.into_string(ctx.prg.strings())?;
+    ctx.ext.movies.play(path.display().to_string(), None);
+    log_a1!(ctx.prg, path);
+    Ok(())
+}
+
+pub fn playmovierect(ctx: Context) -> Result<()> {
+    let height = ctx.prg.data_stack.pop()?.into_int()?;
+    let width = ctx.prg.data_stack.pop()?.into_int()?;
+    let y = ctx.prg.data_stack.pop()?.into_int()?;
+    let x = ctx.prg.data_stack.pop()?.into_int()?;
+    let path = ctx.prg.data_stack.pop()?.into_string(ctx.prg.strings())?;
+    ctx.ext.movies.play(path.display().to_string(), Some(Rect::with_size(x, y, width, height)));
+    log_a5!(ctx.prg, path, x, y, width, height);
+    Ok(())
+}
+
+pub fn poison(ctx: Context) -> Result<()> {
+    let amount = ctx.prg.data_stack.pop()?.into_int()?;
+    let obj = ctx.prg.data_stack.pop()?.coerce_into_object()?
+        .ok_or(Error::BadValue(BadValue::Content))?;
+
+    let objs = ctx.ext.world.objects();
+    if let Some(poison) = ctx.ext.rpg.adjust_poison(&mut objs.get_mut(obj), amount, objs) {
+        ctx.ext.queue.schedule_poison(ctx.ext.world.game_time, obj, poison);
+    }
+
+    log_a2!(ctx.prg, obj, amount);
+    Ok(())
+}
+
+fn adjust_radiation(ctx: Context, sign: i32) -> Result<()> {
+    let amount = ctx.prg.data_stack.pop()?.into_int()?;
+    let obj = ctx.prg.data_stack.pop()?.coerce_into_object()?
+        .ok_or(Error::BadValue(BadValue::Content))?;
+
+    let level = {
+        let objs = ctx.ext.world.objects();
+        let mut o = objs.get_mut(obj);
+        ctx.ext.rpg.adjust_radiation(&mut o, sign * amount, objs)
+            .filter(|&v| v > 0)
+            .map(|_| ctx.ext.rpg.roll_radiation_level(&o, objs))
+    };
+    if let Some(level) = level {
+        ctx.ext.queue.schedule_radiation(ctx.ext.world.game_time, obj, level);
+    }
+
+    log_a2!(ctx.prg, obj, amount);
+    Ok(())
+}
+
+pub fn radiation_dec(ctx: Context) -> Result<()> {
+    adjust_radiation(ctx, -1)
+}
+
+pub fn radiation_inc(ctx: Context) -> Result<()> {
+    adjust_radiation(ctx, 1)
+}
+
 pub fn random(ctx: Context) -> Result<()> {
     let to_incl = ctx.prg.data_stack.pop()?.into_int()?;
     let from_incl = ctx.prg.data_stack.pop()?.into_int()?;
@@ -1055,6 +1560,17 @@ pub fn random(ctx: Context) -> Result<()> {
     Ok(())
 }
 
+pub fn reaction_influence(ctx: Context) -> Result<()> {
+    let c = ctx.prg.data_stack.pop()?;
+    let b = ctx.prg.data_stack.pop()?;
+    let a = ctx.prg.data_stack.pop()?;
+    // The original always returns 0 too.
+    let r = 0;
+    ctx.prg.data_stack.push(r.into())?;
+    log_a3r1!(ctx.prg, a, b, c, r);
+    Ok(())
+}
+
 pub fn reg_anim_animate_forever(ctx: Context) -> Result<()> {
     use crate::asset::CritterAnim;
     use crate::game::sequence::frame_anim::*;
@@ -1109,16 +1625,92 @@ pub fn reg_anim_func(ctx: Context) -> Result<()> {
     Ok(())
 }
 
+pub fn reg_anim_play_sfx(ctx: Context) -> Result<()> {
+    use crate::sequence::event::{Event, PushEvent};
+    use crate::sequence::wait::Wait;
+
+    // In game ticks.
+    let delay = ctx.prg.data_stack.pop()?.into_int()?;
+    let name = ctx.prg.data_stack.pop()?.into_string(ctx.prg.strings())?;
+    let obj = ctx.prg.data_stack.pop()?.coerce_into_object()?;
+    if let Some(obj) = obj {
+        let seqs = &mut ctx.prg.instr_state.sequences;
+        if !seqs.contains_key(obj) {
+            seqs.insert(obj, Chain::new());
+        }
+        let control = seqs[obj].control();
+        if delay > 0 {
+            control.cancellable(Wait::new(Duration::from_millis(delay as u64 * 100)));
+        }
+        control.cancellable(PushEvent::new(Event::PlaySfx {
+            obj,
+            name: name.display().to_string(),
+        }));
+    }
+    log_a3!(ctx.prg, obj, name, delay);
+    Ok(())
+}
+
 pub fn rm_timer_event(ctx: Context) -> Result<()> {
     let obj = ctx.prg.data_stack.pop()?.coerce_into_object()?
         .ok_or(Error::BadValue(BadValue::Content))?;
 
+    ctx.ext.queue.remove(|e| e.obj == Some(obj) && e.event.kind() == EventKind::Script);
+
     log_a1!(ctx.prg, obj);
-    log_stub!(ctx.prg);
 
     Ok(())
 }
 
+// Say* opcodes are the generic dialog interface of the interpreter library. They're mapped onto
+// the game dialog.
+
+pub fn say_message(mut ctx: Context) -> Result<()> {
+    let text = ctx.prg.data_stack.pop()?.into_string(ctx.prg.strings())?;
+    let title = ctx.prg.data_stack.pop()?;
+
+    if ctx.ext.dialog.is_some() {
+        set_dialog_reply(&text, None, &mut ctx);
+        let option = &ctx.ext.proto_db.messages().get(650).unwrap().text;
+        let dialog = ctx.ext.dialog.as_mut().unwrap();
+        dialog.add_option(ctx.ext.ui, option, None, Reaction::Neutral);
+    } else {
+        log_error!(ctx.prg, "not in dialog");
+    }
+
+    log_a2!(ctx.prg, title, text);
+    Ok(())
+}
+
+pub fn say_option(mut ctx: Context) -> Result<()> {
+    let proc = ctx.prg.data_stack.pop()?;
+    let text = ctx.prg.data_stack.pop()?.into_string(ctx.prg.strings())?;
+    let proc_id = resolve_dialog_proc(proc, &mut ctx)?;
+
+    if ctx.ext.dialog.is_some() {
+        add_dialog_option(&text, proc_id, Reaction::Neutral, &mut ctx);
+    } else {
+        log_error!(ctx.prg, "not in dialog");
+    }
+
+    log_a2!(ctx.prg, text, proc_id);
+    Ok(())
+}
+
+pub fn say_reply(mut ctx: Context) -> Result<()> {
+    let text = ctx.prg.data_stack.pop()?.into_string(ctx.prg.strings())?;
+    let title = ctx.prg.data_stack.pop()?;
+
+    if ctx.ext.dialog.is_some() {
+        set_dialog_reply(&text, None, &mut ctx);
+    } else {
+        log_error!(ctx.prg, "not in dialog");
+    }
+
+    log_a2!(ctx.prg, title, text);
+    Ok(())
+}
+
 pub fn set_light_level(ctx: Context) -> Result<()> {
     let v = (ctx.prg.data_stack.pop()?.into_int()?).clamp(0, 100) as u32;
 
@@ -1141,6 +1733,23 @@ pub fn set_light_level(ctx: Context) -> Result<()> {
     Ok(())
 }
 
+pub fn set_map_music(ctx: Context) -> Result<()> {
+    let name = ctx.prg.data_stack.pop()?.into_string(ctx.prg.strings())?;
+    let map_id = ctx.prg.data_stack.pop()?.into_int()?;
+
+    let map_def = u32::try_from(map_id).ok()
+        .and_then(|id| ctx.ext.map_db.get_mut(id))
+        .ok_or(Error::BadValue(BadValue::Content))?;
+    map_def.music = Some(name.display().to_string());
+    if map_id as u32 == ctx.ext.map_id {
+        ctx.ext.sound.play_music(&name.display().to_string());
+    }
+
+    log_a2!(ctx.prg, map_id, name);
+
+    Ok(())
+}
+
 pub fn set_obj_visibility(ctx: Context) -> Result<()> {
     let visible = ctx.prg.data_stack.pop()?.into_bool()?;
     let obj = ctx.prg.data_stack.pop()?.coerce_into_object()?
@@ -1152,6 +1761,152 @@ pub fn set_obj_visibility(ctx: Context) -> Result<()> {
     Ok(())
 }
 
+fn push_sfx_name(ctx: &mut Context, name: Option<String>) -> Result<Rc<BString>> {
+    let r = Rc::new(BString::from(name.unwrap_or_default()));
+    ctx.prg.data_stack.push(r.clone().into())?;
+    Ok(r)
+}
+
+pub fn sfx_build_ambient_name(mut ctx: Context) -> Result<()> {
+    let name = ctx.prg.data_stack.pop()?.into_string(ctx.prg.strings())?;
+    let r = push_sfx_name(&mut ctx, Some(sfx::build_ambient_name(&name.display().to_string())))?;
+    log_a1r1!(ctx.prg, name, r);
+    Ok(())
+}
+
+pub fn sfx_build_char_name(mut ctx: Context) -> Result<()> {
+    use crate::asset::CritterAnim;
+
+    let kind = sfx::CharSfx::from_i32(ctx.prg.data_stack.pop()?.into_int()?)
+        .ok_or(Error::BadValue(BadValue::Content))?;
+    let anim = CritterAnim::from_i32(ctx.prg.data_stack.pop()?.into_int()?)
+        .ok_or(Error::BadValue(BadValue::Content))?;
+    let obj = ctx.prg.data_stack.pop()?.coerce_into_object()?
+        .ok_or(Error::BadValue(BadValue::Content))?;
+
+    let name = sfx::build_char_name(ctx.ext.world, obj, anim, kind);
+    let r = push_sfx_name(&mut ctx, name)?;
+
+    log_a3r1!(ctx.prg, obj, anim, kind, r);
+
+    Ok(())
+}
+
+pub fn sfx_build_interface_name(mut ctx: Context) -> Result<()> {
+    let name = ctx.prg.data_stack.pop()?.into_string(ctx.prg.strings())?;
+    let r = push_sfx_name(&mut ctx, Some(sfx::build_interface_name(&name.display().to_string())))?;
+    log_a1r1!(ctx.prg, name, r);
+    Ok(())
+}
+
+pub fn sfx_build_item_name(mut ctx: Context) -> Result<()> {
+    let name = ctx.prg.data_stack.pop()?.into_string(ctx.prg.strings())?;
+    let r = push_sfx_name(&mut ctx, Some(sfx::build_item_name(&name.display().to_string())))?;
+    log_a1r1!(ctx.prg, name, r);
+    Ok(())
+}
+
+pub fn sfx_build_open_name(mut ctx: Context) -> Result<()> {
+    let action = sfx::SceneryAction::from_i32(ctx.prg.data_stack.pop()?.into_int()?)
+        .ok_or(Error::BadValue(BadValue::Content))?;
+    let obj = ctx.prg.data_stack.pop()?.coerce_into_object()?
+        .ok_or(Error::BadValue(BadValue::Content))?;
+
+    let name = sfx::build_open_name(ctx.ext.world, obj, action);
+    let r = push_sfx_name(&mut ctx, Some(name))?;
+
+    log_a2r1!(ctx.prg, obj, action, r);
+
+    Ok(())
+}
+
+pub fn sfx_build_scenery_name(mut ctx: Context) -> Result<()> {
+    let name = ctx.prg.data_stack.pop()?.into_string(ctx.prg.strings())?;
+    let action = sfx::SceneryAction::from_i32(ctx.prg.data_stack.pop()?.into_int()?)
+        .ok_or(Error::BadValue(BadValue::Content))?;
+    // The original passes this one as "action type" where 1 means passive.
+    let passive = ctx.prg.data_stack.pop()?.into_int()? == 1;
+
+    let r = push_sfx_name(&mut ctx,
+        Some(sfx::build_scenery_name(passive, action, &name.display().to_string())))?;
+
+    log_a3r1!(ctx.prg, passive, action, name, r);
+
+    Ok(())
+}
+
+pub fn sfx_build_weapon_name(mut ctx: Context) -> Result<()> {
+    let target = ctx.prg.data_stack.pop()?.coerce_into_object()?;
+    let hit_mode = ctx.prg.data_stack.pop()?.into_int()?;
+    let weapon = ctx.prg.data_stack.pop()?.coerce_into_object()?
+        .ok_or(Error::BadValue(BadValue::Content))?;
+    let kind = sfx::WeaponSfx::from_i32(ctx.prg.data_stack.pop()?.into_int()?)
+        .ok_or(Error::BadValue(BadValue::Content))?;
+
+    // Left primary, right primary and punch.
+    let primary = hit_mode == 0 || hit_mode == 2 || hit_mode == 4;
+    let name = sfx::build_weapon_name(ctx.ext.world, kind, weapon, primary, target);
+    let r = push_sfx_name(&mut ctx, Some(name))?;
+
+    log_a4r1!(ctx.prg, kind, weapon, hit_mode, target, r);
+
+    Ok(())
+}
+
+fn pop_voice(ctx: &mut Context) -> Result<VoiceId> {
+    let v = ctx.prg.data_stack.pop()?.into_int()?;
+    Ok(VoiceId::from_raw(v as u32))
+}
+
+pub fn sounddelete(mut ctx: Context) -> Result<()> {
+    let voice = pop_voice(&mut ctx)?;
+    ctx.ext.sound.mixer().stop(voice);
+    log_a1!(ctx.prg, voice);
+    Ok(())
+}
+
+pub fn soundpause(mut ctx: Context) -> Result<()> {
+    let voice = pop_voice(&mut ctx)?;
+    ctx.ext.sound.mixer().set_paused(voice, true);
+    log_a1!(ctx.prg, voice);
+    Ok(())
+}
+
+pub fn soundplay(ctx: Context) -> Result<()> {
+    let mode = ctx.prg.data_stack.pop()?.into_int()?;
+    let path = ctx.prg.data_stack.pop()?.into_string(ctx.prg.strings())?;
+
+    let looping = mode & 1 != 0;
+    let voice = ctx.ext.sound.play(Channel::Sfx, &path.display().to_string(), looping, 1.0);
+    let r = voice.map(|v| v.into_raw() as i32).unwrap_or(0);
+    ctx.prg.data_stack.push(r.into())?;
+
+    log_a2r1!(ctx.prg, path, mode, r);
+
+    Ok(())
+}
+
+pub fn soundresume(mut ctx: Context) -> Result<()> {
+    let voice = pop_voice(&mut ctx)?;
+    ctx.ext.sound.mixer().set_paused(voice, false);
+    log_a1!(ctx.prg, voice);
+    Ok(())
+}
+
+pub fn soundrewind(mut ctx: Context) -> Result<()> {
+    let voice = pop_voice(&mut ctx)?;
+    ctx.ext.sound.mixer().rewind(voice);
+    log_a1!(ctx.prg, voice);
+    Ok(())
+}
+
+pub fn soundstop(mut ctx: Context) -> Result<()> {
+    let voice = pop_voice(&mut ctx)?;
+    ctx.ext.sound.mixer().stop(voice);
+    log_a1!(ctx.prg, voice);
+    Ok(())
+}
+
 pub fn start_gdialog(mut ctx: Context) -> Result<()> {
     let background = ctx.prg.data_stack.pop()?.into_int()?;
     let head_id = ctx.prg.data_stack.pop()?.into_int()?;
@@ -1160,17 +1915,38 @@ pub fn start_gdialog(mut ctx: Context) -> Result<()> {
     let program_id = pop_program_id(&mut ctx)?;
 
     // TODO disallow in combat state
-    // TODO handle head_id
     // TODO check for can_talk() (or can_talk_now()?)
 
+    let head = if head_id != -1 {
+        let idx = head_id.try_into().map_err(|_| Error::BadValue(BadValue::Content))?;
+        Some(Head {
+            idx,
+            mood: Mood::from_fidget_anim(reaction).unwrap_or(Mood::Neutral),
+            background: background.try_into().ok(),
+        })
+    } else {
+        None
+    };
+    let empathy = {
+        let objs = ctx.ext.world.objects();
+        let dude_pid = objs.get(objs.dude()).proto_id().unwrap();
+        ctx.ext.rpg.has_perk(Perk::Empathy, dude_pid)
+    };
+
     assert!(ctx.ext.dialog.is_none());
-    *ctx.ext.dialog = Some(Dialog::show(ctx.ext.ui, ctx.ext.world, objh));
+    *ctx.ext.dialog = Some(Dialog::show(ctx.ext.ui, ctx.ext.world, objh, head, empathy));
 
     log_a5!(ctx.prg, program_id, objh, reaction, head_id, background);
 
     Ok(())
 }
 
+pub fn terminate_combat(ctx: Context) -> Result<()> {
+    ctx.ext.combat.request(combat::Request::End);
+    log_!(ctx.prg);
+    Ok(())
+}
+
 pub fn tile_contains_pid_obj(ctx: Context) -> Result<()> {
     let pid = ctx.prg.data_stack.pop()?.into_int()?;
     let pid = ProtoId::from_packed(pid as u32)
@@ -1288,6 +2064,12 @@ pub fn source_obj(ctx: Context) -> Result<()> {
     Ok(())
 }
 
+pub fn stopmovie(ctx: Context) -> Result<()> {
+    ctx.ext.movies.stop(ctx.ext.ui, ctx.ext.sound);
+    log_!(ctx.prg);
+    Ok(())
+}
+
 pub fn target_obj(ctx: Context) -> Result<()> {
     ctx.prg.data_stack.push(ctx.ext.target_obj.into())?;
     log_r1!(ctx.prg, ctx.prg.data_stack.top().unwrap());
@@ -1342,3 +2124,81 @@ pub fn tile_num_in_direction(ctx: Context) -> Result<()> {
 
     Ok(())
 }
+
+pub fn wm_area_set_pos(ctx: Context) -> Result<()> {
+    let y = ctx.prg.data_stack.pop()?.into_int()?;
+    let x = ctx.prg.data_stack.pop()?.into_int()?;
+    let area = ctx.prg.data_stack.pop()?.into_int()?;
+
+    log_a3!(ctx.prg, area, x, y);
+
+    if !ctx.ext.world_map.set_area_pos(area as u32, Point::new(x, y)) {
+        log_error!(ctx.prg, "unknown area");
+        return Err(Error::BadValue(BadValue::Content));
+    }
+
+    Ok(())
+}
+
+pub fn world_map(ctx: Context) -> Result<()> {
+    ctx.ext.world_map.request_show();
+    log_!(ctx.prg);
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::rc::Rc;
+
+    use super::*;
+    use crate::game::drug;
+    use crate::graphics::Point;
+    use crate::util::test::{Assets, VmEnv};
+    use crate::vm::Vm;
+    use crate::vm::ssl::compile::compile_file;
+
+    #[test]
+    fn get_critter_stat_after_drug() {
+        let code = compile_file("test.ssl", &mut |_| Ok("
+            procedure start begin
+                set_global_var(0, get_critter_stat(dude_obj, 0));
+                set_global_var(1, get_critter_stat(dude_obj, 5));
+            end
+        ".into())).unwrap();
+        let mut vm = Vm::default();
+        let prg = Rc::new(vm.load("test.int".into(), code.into()).unwrap());
+        let start = prg.proc_id(&Rc::new("start".into())).unwrap();
+        let h = vm.insert(prg);
+
+        let mut env = VmEnv::new();
+        {
+            let proto = env.assets.proto_db.dude();
+            let mut proto = proto.borrow_mut();
+            let critter = proto.sub.as_critter_mut().unwrap();
+            critter.base_stats[Stat::Strength] = 5;
+            critter.base_stats[Stat::Agility] = 5;
+        }
+        let (dude, pill) = {
+            let objs = env.world.objects_mut();
+            let dude = objs.create(None, Some(env.assets.proto_db.dude()),
+                Some(Point::new(12, 34).elevated(0)), Some(&env.rpg)).handle();
+            let pill = objs.create(None,
+                Some(env.assets.proto_db.proto(Assets::pid(Assets::DRUG)).unwrap()), None, None)
+                .handle();
+            (dude, pill)
+        };
+        let (strength, agility) = {
+            let objs = env.world.objects();
+            let dude = &objs.get(dude);
+            (env.rpg.stat(Stat::Strength, dude, objs), env.rpg.stat(Stat::Agility, dude, objs))
+        };
+
+        drug::take_drug(dude, pill, &env.rpg, env.world.objects(), &mut env.queue,
+            env.world.game_time);
+
+        vm.run(h, &mut env.ctx()).unwrap().assert_no_suspend();
+        vm.program_state_mut(h).execute_proc(start, &mut env.ctx()).unwrap().assert_no_suspend();
+        assert_eq!((strength, agility), (5, 5));
+        assert_eq!(&env.global_vars[..2], &[7, 6]);
+    }
+}
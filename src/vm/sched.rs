@@ -0,0 +1,697 @@
+//! Cooperative scheduling of the program execution.
+//!
+//! Besides the synchronous invocations made by the game, program state can have suspended
+//! execution contexts, pending timed and conditional procedure calls and named event handlers.
+//! These are driven by the embedder which periodically calls `ProgramState::update()` for each
+//! program and carries out the requests returned by `Vm::take_requests()`.
+//!
+//! * `wait(ms)` suspends the running procedure. If the procedure was invoked by the game, its
+//!   frames are moved into a new execution context with own stacks and the invocation returns
+//!   to the caller as if the procedure has returned. The context is resumed by `update()` once
+//!   the time has passed. Program global variables are shared by all contexts.
+//! * `call_at(proc, secs)` and `call_condition(proc, condition_pos)` schedule a single call of
+//!   the procedure after the delay or once the condition code returns true. The condition code
+//!   is invoked the same way as procedure. Procedures flagged as timed or conditional in
+//!   the procedure table are scheduled when the program is loaded. `cancel(proc)` and
+//!   `cancelall()` remove the pending calls.
+//! * `addnamedevent(name, proc)` registers the procedure as handler of the named event.
+//!   `signalnamed(name)` makes the embedder call the handlers of all programs.
+//! * `fork(name)`, `spawn(name)`, `callstart(name)` and `exec(name)` start program `name` as
+//!   a new process, see `StartMode`. Process exits once it has no suspended contexts and pending
+//!   procedure calls left. The child process can `detach()` from the parent.
+//! * `stop_prog()` halts the program and cancels all of its scheduled code. Stopped program
+//!   ignores procedure invocations.
+
+use bstring::BString;
+use log::*;
+use std::collections::BTreeMap;
+use std::mem;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use super::*;
+
+/// How the new process relates to the process that starts it.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum StartMode {
+    /// Independent process.
+    Fork,
+    /// Child process running concurrently with the parent.
+    Spawn,
+    /// Child process. The parent is blocked until the child exits or detaches.
+    Callstart,
+    /// Process replacing the current one, which is stopped. The new process becomes a child of
+    /// the parent of the current process.
+    Exec,
+}
+
+/// Request made by program that must be carried out by the VM embedder.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Request {
+    /// Instantiate program `name` and register it with `Vm::start_process()`.
+    Start {
+        name: Rc<BString>,
+        mode: StartMode,
+    },
+    /// Invoke the handlers returned by `Vm::event_handlers()`.
+    Signal(Rc<BString>),
+    /// The process has exited and can be removed.
+    Exit,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub(super) enum Deadline {
+    /// Relative to the first `poll()`.
+    After(Duration),
+    At(Instant),
+}
+
+impl Deadline {
+    fn resolve(&mut self, now: Instant) -> Instant {
+        let at = match *self {
+            Deadline::After(d) => now + d,
+            Deadline::At(t) => t,
+        };
+        *self = Deadline::At(at);
+        at
+    }
+
+    fn poll(&mut self, now: Instant) -> bool {
+        self.resolve(now) <= now
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub(super) enum Wake {
+    Time(Deadline),
+    /// When the child process exits or detaches.
+    Child,
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Trigger {
+    Time(Deadline),
+    /// Position of the condition code.
+    Condition(usize),
+}
+
+/// Registers of execution context.
+struct Registers {
+    code_pos: usize,
+    data_stack: Stack<DataStackId>,
+    return_stack: Stack<ReturnStackId>,
+    base: Option<usize>,
+}
+
+struct Parked {
+    regs: Registers,
+    wake: Wake,
+}
+
+/// State of the main context at the start of procedure invocation.
+struct Invocation {
+    code_pos: usize,
+    data_len: usize,
+    return_len: usize,
+    base: Option<usize>,
+}
+
+#[derive(Default)]
+pub(super) struct Sched {
+    /// Suspended execution contexts.
+    parked: Vec<Parked>,
+    /// Registers of the main context while a suspended context is running.
+    main: Option<Registers>,
+    /// Procedure invocations in progress in the main context.
+    invocations: Vec<Invocation>,
+    /// Set by instruction to suspend the running context.
+    block: Option<Wake>,
+    triggers: BTreeMap<ProcedureId, Trigger>,
+    named_events: Vec<(Rc<BString>, ProcedureId)>,
+    requests: Vec<Request>,
+    /// Whether this is a process started by `Request::Start`.
+    process: bool,
+    parent: Option<Handle>,
+    /// Whether there's a child process started by `spawn` or `callstart`.
+    has_child: bool,
+    detach: bool,
+    stopped: bool,
+}
+
+impl Sched {
+    pub fn new(program: &Program) -> Self {
+        let mut triggers = BTreeMap::new();
+        for (id, proc) in program.procs.by_id.iter().enumerate() {
+            let trigger = if proc.flags.contains(ProcedureFlag::Import) {
+                continue;
+            } else if proc.flags.contains(ProcedureFlag::Conditional) {
+                Trigger::Condition(proc.condition_pos)
+            } else if proc.flags.contains(ProcedureFlag::Timed) {
+                Trigger::Time(Deadline::After(proc.delay))
+            } else {
+                continue;
+            };
+            triggers.insert(id as ProcedureId, trigger);
+        }
+        Self {
+            triggers,
+            ..Default::default()
+        }
+    }
+
+    fn is_idle(&self) -> bool {
+        self.parked.is_empty() && self.triggers.is_empty()
+    }
+}
+
+impl ProgramState {
+    pub fn is_stopped(&self) -> bool {
+        self.sched.stopped
+    }
+
+    /// Resumes the suspended contexts and calls the scheduled procedures that are due.
+    pub fn update(&mut self, now: Instant, ctx: &mut Context) -> Result<()> {
+        if self.sched.stopped {
+            return Ok(());
+        }
+
+        // Contexts suspended in the process are checked on the next update.
+        let mut i = 0;
+        let mut count = self.sched.parked.len();
+        while i < count {
+            let has_child = self.sched.has_child;
+            let ready = match &mut self.sched.parked[i].wake {
+                Wake::Time(deadline) => deadline.poll(now),
+                Wake::Child => !has_child,
+            };
+            if ready {
+                let parked = self.sched.parked.remove(i);
+                count -= 1;
+                self.resume_parked(parked.regs, ctx)?;
+                if self.sched.stopped {
+                    return Ok(());
+                }
+            } else {
+                i += 1;
+            }
+        }
+
+        let ids: Vec<_> = self.sched.triggers.keys().copied().collect();
+        for id in ids {
+            let ready = match self.sched.triggers.get_mut(&id) {
+                Some(Trigger::Time(deadline)) => deadline.poll(now),
+                Some(Trigger::Condition(pos)) => {
+                    let pos = *pos;
+                    self.eval_condition(pos, ctx)?
+                }
+                None => continue,
+            };
+            if ready {
+                debug!("{}: calling scheduled procedure {}", self.program.name, id);
+                self.sched.triggers.remove(&id);
+                let r = self.execute_proc(id, ctx)?;
+                if r.suspend.is_some() {
+                    self.suspend_stack.pop();
+                    return Err(Error::BadState("can't suspend in scheduled procedure".into()));
+                }
+                if self.sched.stopped {
+                    return Ok(());
+                }
+            }
+        }
+
+        // Delays of the code scheduled during this update count from now.
+        for p in &mut self.sched.parked {
+            if let Wake::Time(deadline) = &mut p.wake {
+                deadline.resolve(now);
+            }
+        }
+        for trigger in self.sched.triggers.values_mut() {
+            if let Trigger::Time(deadline) = trigger {
+                deadline.resolve(now);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Records the state of the main context before procedure invocation.
+    pub(super) fn begin_invocation(&mut self) {
+        self.sched.invocations.push(Invocation {
+            code_pos: self.code_pos,
+            data_len: self.data_stack.len(),
+            return_len: self.return_stack.len(),
+            base: self.base,
+        });
+    }
+
+    /// Called when the running context halts.
+    pub(super) fn end_invocation(&mut self) {
+        if self.sched.main.is_none() {
+            self.sched.invocations.pop();
+        }
+    }
+
+    /// Suspends the running context after the current instruction.
+    pub(super) fn block(&mut self, wake: Wake) {
+        self.sched.block = Some(wake);
+    }
+
+    pub(super) fn take_block(&mut self) -> Option<Wake> {
+        self.sched.block.take()
+    }
+
+    pub(super) fn wait(&mut self, delay: Duration) {
+        self.block(Wake::Time(Deadline::After(delay)));
+    }
+
+    /// Moves the running context into a new suspended context. If it's the main context only
+    /// the frames of the current invocation are moved out.
+    pub(super) fn park(&mut self, wake: Wake) -> Result<()> {
+        let regs = if self.sched.main.is_some() {
+            let mut regs = self.empty_registers();
+            self.swap_registers(&mut regs);
+            regs
+        } else {
+            let inv = self.sched.invocations.pop()
+                .ok_or_else(|| Error::BadState("can't suspend outside of procedure".into()))?;
+            // The frames below the invocation are copied too to keep the stack offsets valid.
+            // They're never accessed by the suspended context.
+            let regs = Registers {
+                code_pos: self.code_pos,
+                data_stack: self.data_stack.clone(),
+                return_stack: self.return_stack.clone(),
+                base: self.base,
+            };
+            self.data_stack.truncate(inv.data_len)?;
+            self.return_stack.truncate(inv.return_len)?;
+            self.code_pos = inv.code_pos;
+            self.base = inv.base;
+            regs
+        };
+        debug!("{}: suspending context at 0x{:x} until {:?}",
+            self.program.name, regs.code_pos, wake);
+        self.sched.parked.push(Parked { regs, wake });
+        Ok(())
+    }
+
+    fn resume_parked(&mut self, mut regs: Registers, ctx: &mut Context) -> Result<()> {
+        debug!("{}: resuming context at 0x{:x}", self.program.name, regs.code_pos);
+        self.swap_registers(&mut regs);
+        self.sched.main = Some(regs);
+        let r = self.run(ctx);
+        let mut main = self.sched.main.take().unwrap();
+        self.swap_registers(&mut main);
+        if r?.suspend.is_some() {
+            self.suspend_stack.pop();
+            return Err(Error::BadState("can't suspend in resumed context".into()));
+        }
+        Ok(())
+    }
+
+    /// Runs the condition code and returns its result.
+    fn eval_condition(&mut self, pos: usize, ctx: &mut Context) -> Result<bool> {
+        let return_len = self.return_stack.len();
+        self.setup_call(pos)?;
+        // Run until the condition code returns the value into the program startup code.
+        while self.code_pos != Program::RETURN_POS || self.return_stack.len() != return_len + 2 {
+            self.before_instruction(ctx);
+            match self.step(ctx) {
+                Ok(None) if self.sched.block.is_none() => {}
+                Ok(_) => {
+                    self.sched.block = None;
+                    self.end_invocation();
+                    return Err(Error::BadState("can't suspend in condition".into()));
+                }
+                Err(e) => {
+                    self.end_invocation();
+                    return if e.is_halted() { Ok(false) } else { Err(e) };
+                }
+            }
+        }
+        let r = self.return_stack.top().unwrap().test();
+        self.run(ctx)?.assert_no_suspend();
+        Ok(r)
+    }
+
+    /// Returns the global variable storage which is always in the main context.
+    pub(super) fn global_stack(&self) -> &Stack<DataStackId> {
+        self.sched.main.as_ref().map(|m| &m.data_stack).unwrap_or(&self.data_stack)
+    }
+
+    pub(super) fn global_stack_mut(&mut self) -> &mut Stack<DataStackId> {
+        match &mut self.sched.main {
+            Some(m) => &mut m.data_stack,
+            None => &mut self.data_stack,
+        }
+    }
+
+    pub(super) fn call_at(&mut self, id: ProcedureId, delay: Duration) -> Result<()> {
+        self.check_proc_id(id)?;
+        self.sched.triggers.insert(id, Trigger::Time(Deadline::After(delay)));
+        Ok(())
+    }
+
+    pub(super) fn call_condition(&mut self, id: ProcedureId, condition_pos: usize) -> Result<()> {
+        self.check_proc_id(id)?;
+        self.sched.triggers.insert(id, Trigger::Condition(condition_pos));
+        Ok(())
+    }
+
+    pub(super) fn cancel(&mut self, id: ProcedureId) -> Result<()> {
+        self.check_proc_id(id)?;
+        self.sched.triggers.remove(&id);
+        Ok(())
+    }
+
+    pub(super) fn cancel_all(&mut self) {
+        self.sched.triggers.clear();
+    }
+
+    pub(super) fn add_named_event(&mut self, name: Rc<BString>, id: ProcedureId) -> Result<()> {
+        self.check_proc_id(id)?;
+        if !self.sched.named_events.iter().any(|(n, i)| n == &name && *i == id) {
+            self.sched.named_events.push((name, id));
+        }
+        Ok(())
+    }
+
+    pub(super) fn signal_named(&mut self, name: Rc<BString>) {
+        self.sched.requests.push(Request::Signal(name));
+    }
+
+    /// Requests starting of the new process. `Callstart` blocks the running context.
+    pub(super) fn start(&mut self, name: Rc<BString>, mode: StartMode) -> Result<()> {
+        match mode {
+            StartMode::Fork => {}
+            StartMode::Spawn | StartMode::Callstart => {
+                if self.sched.has_child {
+                    return Err(Error::BadState("already have a child process".into()));
+                }
+                self.sched.has_child = true;
+                if mode == StartMode::Callstart {
+                    self.block(Wake::Child);
+                }
+            }
+            StartMode::Exec => self.stop(),
+        }
+        self.sched.requests.push(Request::Start { name, mode });
+        Ok(())
+    }
+
+    pub(super) fn detach(&mut self) {
+        self.sched.detach = true;
+    }
+
+    /// Cancels all scheduled code. The running context should be halted.
+    pub(super) fn stop(&mut self) {
+        let s = &mut self.sched;
+        s.stopped = true;
+        s.parked.clear();
+        s.block = None;
+        s.triggers.clear();
+        s.named_events.clear();
+    }
+
+    fn check_proc_id(&self, id: ProcedureId) -> Result<()> {
+        self.program.proc(id)
+            .map(|_| ())
+            .ok_or(Error::BadProcedureId(id))
+    }
+
+    fn empty_registers(&self) -> Registers {
+        Registers {
+            code_pos: 0,
+            data_stack: Stack::new(self.program.config.max_stack_len),
+            return_stack: Stack::new(self.program.config.max_stack_len),
+            base: None,
+        }
+    }
+
+    fn swap_registers(&mut self, regs: &mut Registers) {
+        mem::swap(&mut self.code_pos, &mut regs.code_pos);
+        mem::swap(&mut self.data_stack, &mut regs.data_stack);
+        mem::swap(&mut self.return_stack, &mut regs.return_stack);
+        mem::swap(&mut self.base, &mut regs.base);
+    }
+}
+
+impl Vm {
+    /// Collects the requests made by the programs. Detached and exited processes release their
+    /// parents, `Request::Exit` is returned for the exited processes.
+    pub fn take_requests(&mut self) -> Vec<(Handle, Request)> {
+        let mut r = Vec::new();
+        let mut released = Vec::new();
+        for (h, prg) in self.program_states.iter_mut() {
+            let s = &mut prg.sched;
+            for req in s.requests.drain(..) {
+                if let Request::Start { mode: StartMode::Exec, .. } = req {
+                    // The new process takes over the parent.
+                    if let Some(parent) = s.parent.take() {
+                        self.exec_parents.insert(h, parent);
+                    }
+                }
+                r.push((h, req));
+            }
+            if mem::take(&mut s.detach) {
+                released.extend(s.parent.take());
+            }
+            if s.process && (s.stopped || s.is_idle()) {
+                s.process = false;
+                released.extend(s.parent.take());
+                r.push((h, Request::Exit));
+            }
+        }
+        for h in released {
+            if let Some(prg) = self.program_states.get_mut(h) {
+                prg.sched.has_child = false;
+            }
+        }
+        r
+    }
+
+    /// Registers the `child` process started on `Request::Start` made by `parent`.
+    /// If the process couldn't be started `child` is `None`.
+    pub fn start_process(&mut self, parent: Handle, mode: StartMode, child: Option<Handle>) {
+        let child_parent = match mode {
+            StartMode::Fork => None,
+            StartMode::Spawn | StartMode::Callstart => Some(parent),
+            StartMode::Exec => self.exec_parents.remove(parent),
+        };
+        if let Some(child) = child {
+            let s = &mut self.program_state_mut(child).sched;
+            s.process = true;
+            s.parent = child_parent;
+        } else if let Some(p) = child_parent
+            && let Some(prg) = self.program_states.get_mut(p)
+        {
+            prg.sched.has_child = false;
+        }
+    }
+
+    /// Returns the procedures registered as handlers of the named event.
+    pub fn event_handlers(&self, name: &bstr) -> Vec<(Handle, ProcedureId)> {
+        let mut r = Vec::new();
+        for (h, prg) in self.program_states.iter() {
+            for (n, id) in &prg.sched.named_events {
+                if n.as_bstr() == name {
+                    r.push((h, *id));
+                }
+            }
+        }
+        r
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::util::test::VmEnv;
+    use crate::vm::ssl::compile::compile_file;
+
+    fn load(vm: &mut Vm, name: &str, src: &str) -> Handle {
+        let code = compile_file("test.ssl", &mut |_| Ok(src.into())).unwrap();
+        let prg = Rc::new(vm.load(name.into(), code.into()).unwrap());
+        vm.insert(prg)
+    }
+
+    fn execute(vm: &mut Vm, h: Handle, proc: &str, env: &mut VmEnv) {
+        let prg = vm.program_state_mut(h);
+        let id = prg.program().proc_id(&Rc::new(proc.into())).unwrap();
+        prg.execute_proc(id, &mut env.ctx()).unwrap().assert_no_suspend();
+    }
+
+    fn update(vm: &mut Vm, h: Handle, now: Instant, env: &mut VmEnv) {
+        vm.program_state_mut(h).update(now, &mut env.ctx()).unwrap();
+    }
+
+    #[test]
+    fn wait() {
+        let mut vm = Vm::default();
+        let h = load(&mut vm, "test", "
+            variable counter := 0;
+
+            procedure start begin
+                variable i := 10;
+                counter := 1;
+                wait(100);
+                set_global_var(0, counter + i);
+                wait(100);
+                set_global_var(1, counter);
+            end
+
+            procedure other begin
+                counter := counter + 1;
+            end
+        ");
+        let mut env = VmEnv::new();
+        vm.run(h, &mut env.ctx()).unwrap().assert_no_suspend();
+        let stack_len = vm.program_state(h).data_stack.len();
+
+        execute(&mut vm, h, "start", &mut env);
+        assert_eq!(vm.program_state(h).data_stack.len(), stack_len);
+        assert!(vm.program_state(h).return_stack.is_empty());
+        execute(&mut vm, h, "other", &mut env);
+
+        let now = Instant::now();
+        update(&mut vm, h, now, &mut env);
+        assert_eq!(&env.global_vars[..2], &[0, 0]);
+        update(&mut vm, h, now + Duration::from_millis(99), &mut env);
+        assert_eq!(&env.global_vars[..2], &[0, 0]);
+        update(&mut vm, h, now + Duration::from_millis(100), &mut env);
+        assert_eq!(&env.global_vars[..2], &[12, 0]);
+
+        execute(&mut vm, h, "other", &mut env);
+        update(&mut vm, h, now + Duration::from_millis(200), &mut env);
+        assert_eq!(&env.global_vars[..2], &[12, 3]);
+        assert_eq!(vm.program_state(h).data_stack.len(), stack_len);
+        assert!(vm.program_state(h).sched.is_idle());
+    }
+
+    #[test]
+    fn scheduled_calls() {
+        let mut vm = Vm::default();
+        let h = load(&mut vm, "test", "
+            procedure foo begin
+                set_global_var(0, global_var(0) + 1);
+            end
+
+            procedure bar begin
+                set_global_var(1, global_var(1) + 1);
+            end
+
+            procedure cond begin
+                return global_var(2) == 5;
+            end
+
+            procedure baz begin
+                set_global_var(3, global_var(3) + 1);
+            end
+
+            procedure start begin
+                call_at(foo, 1);
+                call_at(bar, 2);
+                cancel(bar);
+                call_condition(baz, global_var(4));
+            end
+        ");
+        let mut env = VmEnv::new();
+        vm.run(h, &mut env.ctx()).unwrap().assert_no_suspend();
+        let prg = vm.program_state(h).program();
+        env.global_vars[4] = prg.proc(prg.proc_id(&Rc::new("cond".into())).unwrap())
+            .unwrap().body_pos as i32;
+        execute(&mut vm, h, "start", &mut env);
+
+        let now = Instant::now();
+        update(&mut vm, h, now, &mut env);
+        assert_eq!(&env.global_vars[..4], &[0, 0, 0, 0]);
+        env.global_vars[2] = 5;
+        update(&mut vm, h, now + Duration::from_secs(1), &mut env);
+        assert_eq!(&env.global_vars[..4], &[1, 0, 5, 1]);
+        update(&mut vm, h, now + Duration::from_secs(3), &mut env);
+        assert_eq!(&env.global_vars[..4], &[1, 0, 5, 1]);
+        assert!(vm.program_state(h).sched.is_idle());
+        assert!(vm.program_state(h).return_stack.is_empty());
+    }
+
+    #[test]
+    fn named_events() {
+        let mut vm = Vm::default();
+        let h1 = load(&mut vm, "test1", r#"
+            procedure start begin
+                signalnamed("ping");
+            end
+        "#);
+        let h2 = load(&mut vm, "test2", r#"
+            procedure on_ping begin
+            end
+
+            procedure start begin
+                addnamedevent("ping", on_ping);
+                addnamedevent("ping", on_ping);
+            end
+        "#);
+        let mut env = VmEnv::new();
+        for h in [h1, h2] {
+            vm.run(h, &mut env.ctx()).unwrap().assert_no_suspend();
+            execute(&mut vm, h, "start", &mut env);
+        }
+        assert_eq!(vm.take_requests(), vec![(h1, Request::Signal(Rc::new("ping".into())))]);
+        assert_eq!(vm.event_handlers("ping".into()), vec![(h2, 0)]);
+        assert!(vm.event_handlers("pong".into()).is_empty());
+    }
+
+    #[test]
+    fn processes() {
+        let mut vm = Vm::default();
+        let parent = load(&mut vm, "parent", r#"
+            procedure start begin
+                callstart("child");
+                set_global_var(0, 1);
+                spawn("child");
+                fork("child");
+                stop_prog;
+                set_global_var(1, 1);
+            end
+        "#);
+        let child_src = "
+            procedure start begin
+                detach;
+                wait(10);
+                set_global_var(2, global_var(2) + 1);
+            end
+        ";
+        let mut env = VmEnv::new();
+        vm.run(parent, &mut env.ctx()).unwrap().assert_no_suspend();
+        execute(&mut vm, parent, "start", &mut env);
+        let start = |mode| Request::Start { name: Rc::new("child".into()), mode };
+        assert_eq!(vm.take_requests(), vec![(parent, start(StartMode::Callstart))]);
+
+        let child = load(&mut vm, "child", child_src);
+        vm.start_process(parent, StartMode::Callstart, Some(child));
+        vm.run(child, &mut env.ctx()).unwrap().assert_no_suspend();
+        execute(&mut vm, child, "start", &mut env);
+        let now = Instant::now();
+        update(&mut vm, child, now, &mut env);
+        update(&mut vm, parent, now, &mut env);
+        assert_eq!(env.global_vars[0], 0);
+
+        // Detaching releases the parent.
+        assert!(vm.take_requests().is_empty());
+        update(&mut vm, parent, now, &mut env);
+        assert_eq!(&env.global_vars[..2], &[1, 0]);
+        assert!(vm.program_state(parent).is_stopped());
+        assert_eq!(vm.take_requests(), vec![
+            (parent, start(StartMode::Spawn)),
+            (parent, start(StartMode::Fork)),
+        ]);
+
+        update(&mut vm, child, now + Duration::from_millis(10), &mut env);
+        assert_eq!(env.global_vars[2], 1);
+        assert_eq!(vm.take_requests(), vec![(child, Request::Exit)]);
+
+        // Stopped program ignores invocations.
+        execute(&mut vm, parent, "start", &mut env);
+        assert_eq!(&env.global_vars[..2], &[1, 0]);
+    }
+}
@@ -0,0 +1,524 @@
+//! Fallout script source language (SSL).
+//!
+//! The bytecode produced and recognized here follows these conventions:
+//!
+//! * Procedure body starts with `push_base` followed by the initializers of the procedure
+//!   variables.
+//! * Procedure returns with `<value> d_to_a swapa pop_to_base pop_base swapa pop_flags_return`.
+//!   The caller gets the return value on the return stack.
+//! * Procedure call is `<return address> d_to_a 0 0 0 <args> <arg count> <procedure> call` and
+//!   the return address points at `a_to_d`.
+//! * Procedure reference is pushed by `const_short`, all other integers are `const_long`.
+//! * `if` and `while` are `<else address> <condition> if <then block> [<end address> jmp
+//!   <else block>]` and `<end address> <condition> if <body> <start address> jmp`.
+//! * Program header is `critical_start <code start> jmp` padded with `noop`, followed by
+//!   the `a_to_d pop a_to_d pop exit_prog` stub at offset 24.
+//! * Initialization code is `set_global <global initializers> [<name> export_var
+//!   [<initializer> <name> store_external]]... exit_prog`.
+//!
+//! The decompiler also accepts what the original compiler emits differently: `if` without `else`
+//! may end with a jump to the next instruction and an explicit `return` at the end of procedure
+//! is followed by the implicit `return 0`. Procedures that still can't be decompiled are written
+//! as a comment with the instruction listing.
+
+pub mod builtin;
+pub mod compile;
+pub mod decompile;
+pub mod lex;
+pub mod parse;
+
+use bstring::BString;
+use enumflags2::BitFlags;
+use std::fmt::{self, Write};
+
+use super::{Opcode, ProcedureFlag};
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum UnaryOp {
+    Neg,
+    Not,
+    BwNot,
+}
+
+impl UnaryOp {
+    pub fn opcode(self) -> Opcode {
+        match self {
+            Self::Neg => Opcode::Negate,
+            Self::Not => Opcode::Not,
+            Self::BwNot => Opcode::Bwnot,
+        }
+    }
+
+    pub fn from_opcode(opcode: Opcode) -> Option<Self> {
+        Some(match opcode {
+            Opcode::Negate => Self::Neg,
+            Opcode::Not => Self::Not,
+            Opcode::Bwnot => Self::BwNot,
+            _ => return None,
+        })
+    }
+
+    fn token(self) -> &'static str {
+        match self {
+            Self::Neg => "-",
+            Self::Not => "not ",
+            Self::BwNot => "bwnot ",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum BinaryOp {
+    Or,
+    And,
+    BwOr,
+    BwXor,
+    BwAnd,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+}
+
+impl BinaryOp {
+    pub const ALL: [Self; 16] = [
+        Self::Or, Self::And, Self::BwOr, Self::BwXor, Self::BwAnd,
+        Self::Eq, Self::Ne, Self::Lt, Self::Le, Self::Gt, Self::Ge,
+        Self::Add, Self::Sub, Self::Mul, Self::Div, Self::Mod,
+    ];
+
+    pub fn opcode(self) -> Opcode {
+        use Opcode::*;
+        match self {
+            Self::Or => Or,
+            Self::And => And,
+            Self::BwOr => Bwor,
+            Self::BwXor => Bwxor,
+            Self::BwAnd => Bwand,
+            Self::Eq => Equal,
+            Self::Ne => NotEqual,
+            Self::Lt => Less,
+            Self::Le => LessEqual,
+            Self::Gt => Greater,
+            Self::Ge => GreaterEqual,
+            Self::Add => Add,
+            Self::Sub => Sub,
+            Self::Mul => Mul,
+            Self::Div => Div,
+            Self::Mod => Mod,
+        }
+    }
+
+    pub fn from_opcode(opcode: Opcode) -> Option<Self> {
+        Self::ALL.iter().copied().find(|op| op.opcode() == opcode)
+    }
+
+    pub fn token(self) -> &'static str {
+        match self {
+            Self::Or => "or",
+            Self::And => "and",
+            Self::BwOr => "bwor",
+            Self::BwXor => "bwxor",
+            Self::BwAnd => "bwand",
+            Self::Eq => "==",
+            Self::Ne => "!=",
+            Self::Lt => "<",
+            Self::Le => "<=",
+            Self::Gt => ">",
+            Self::Ge => ">=",
+            Self::Add => "+",
+            Self::Sub => "-",
+            Self::Mul => "*",
+            Self::Div => "/",
+            Self::Mod => "%",
+        }
+    }
+
+    /// Binding power. Operators of the same precedence are left-associative.
+    pub fn precedence(self) -> u32 {
+        match self {
+            Self::Or => 1,
+            Self::And => 2,
+            Self::BwOr | Self::BwXor => 3,
+            Self::BwAnd => 4,
+            Self::Eq | Self::Ne | Self::Lt | Self::Le | Self::Gt | Self::Ge => 5,
+            Self::Add | Self::Sub => 6,
+            Self::Mul | Self::Div | Self::Mod => 7,
+        }
+    }
+}
+
+/// Precedence of unary operators and primary expressions.
+const UNARY_PRECEDENCE: u32 = 8;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Expr {
+    Int(i32),
+    Float(f32),
+    String(BString),
+    /// Variable or procedure reference.
+    Var(String),
+    Unary(UnaryOp, Box<Expr>),
+    Binary(BinaryOp, Box<Expr>, Box<Expr>),
+    Builtin(Opcode, Vec<Expr>),
+    /// Call of the script procedure.
+    Call(String, Vec<Expr>),
+}
+
+impl Expr {
+    fn precedence(&self) -> u32 {
+        match self {
+            Self::Binary(op, ..) => op.precedence(),
+            Self::Int(v) if *v < 0 => UNARY_PRECEDENCE,
+            Self::Float(v) if v.is_sign_negative() => UNARY_PRECEDENCE,
+            Self::Unary(..) => UNARY_PRECEDENCE,
+            _ => UNARY_PRECEDENCE + 1,
+        }
+    }
+
+    fn rename(&mut self, f: &impl Fn(&str) -> Option<String>) {
+        match self {
+            Self::Var(name) => if let Some(n) = f(name) {
+                *name = n;
+            }
+            Self::Unary(_, e) => e.rename(f),
+            Self::Binary(_, l, r) => {
+                l.rename(f);
+                r.rename(f);
+            }
+            Self::Builtin(_, args) | Self::Call(_, args) => {
+                for a in args {
+                    a.rename(f);
+                }
+            }
+            Self::Int(_) | Self::Float(_) | Self::String(_) => {}
+        }
+    }
+
+    fn fmt_operand(&self, f: &mut fmt::Formatter, min_precedence: u32) -> fmt::Result {
+        if self.precedence() < min_precedence {
+            write!(f, "({})", self)
+        } else {
+            write!(f, "{}", self)
+        }
+    }
+}
+
+fn fmt_args(f: &mut fmt::Formatter, args: &[Expr]) -> fmt::Result {
+    f.write_char('(')?;
+    for (i, a) in args.iter().enumerate() {
+        if i > 0 {
+            f.write_str(", ")?;
+        }
+        write!(f, "{}", a)?;
+    }
+    f.write_char(')')
+}
+
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Int(v) => write!(f, "{}", v),
+            Self::Float(v) => write!(f, "{:?}", v),
+            Self::String(v) => {
+                f.write_char('"')?;
+                for c in v.display().to_string().chars() {
+                    if c == '"' || c == '\\' {
+                        f.write_char('\\')?;
+                    }
+                    f.write_char(c)?;
+                }
+                f.write_char('"')
+            }
+            Self::Var(v) => f.write_str(v),
+            Self::Unary(op, e) => {
+                f.write_str(op.token())?;
+                // Negated literal is parsed as negative literal.
+                if *op == UnaryOp::Neg
+                    && matches!(**e, Self::Int(_) | Self::Float(_) | Self::Unary(UnaryOp::Neg, _))
+                {
+                    write!(f, "({})", e)
+                } else {
+                    e.fmt_operand(f, UNARY_PRECEDENCE)
+                }
+            }
+            Self::Binary(op, l, r) => {
+                l.fmt_operand(f, op.precedence())?;
+                write!(f, " {} ", op.token())?;
+                r.fmt_operand(f, op.precedence() + 1)
+            }
+            Self::Builtin(opcode, args) => {
+                f.write_str(builtin::by_opcode(*opcode).map(|b| b.name).unwrap_or("?"))?;
+                if !args.is_empty() {
+                    fmt_args(f, args)?;
+                }
+                Ok(())
+            }
+            Self::Call(name, args) => {
+                f.write_str(name)?;
+                fmt_args(f, args)
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Stmt {
+    Assign(String, Expr),
+    Expr(Expr),
+    If(Expr, Vec<Stmt>, Vec<Stmt>),
+    While(Expr, Vec<Stmt>),
+    Return(Expr),
+    Break,
+    Continue,
+}
+
+impl Stmt {
+    fn rename(&mut self, f: &impl Fn(&str) -> Option<String>) {
+        let block = |b: &mut Vec<Stmt>| for s in b {
+            s.rename(f);
+        };
+        match self {
+            Self::Assign(name, e) => {
+                if let Some(n) = f(name) {
+                    *name = n;
+                }
+                e.rename(f);
+            }
+            Self::Expr(e) | Self::Return(e) => e.rename(f),
+            Self::If(c, t, e) => {
+                c.rename(f);
+                block(t);
+                block(e);
+            }
+            Self::While(c, b) => {
+                c.rename(f);
+                block(b);
+            }
+            Self::Break | Self::Continue => {}
+        }
+    }
+
+    fn write(&self, out: &mut String, indent: usize) -> fmt::Result {
+        let pad = |out: &mut String, indent| {
+            for _ in 0..indent {
+                out.push_str("   ");
+            }
+        };
+        pad(out, indent);
+        match self {
+            Self::Assign(name, e) => writeln!(out, "{} := {};", name, e),
+            Self::Expr(e @ Expr::Call(..)) => writeln!(out, "call {};", e),
+            Self::Expr(e) => writeln!(out, "{};", e),
+            Self::If(cond, then, else_) => {
+                let mut cond = cond;
+                let mut then = then;
+                let mut else_ = else_;
+                loop {
+                    writeln!(out, "if ({}) then begin", cond)?;
+                    write_block(out, then, indent + 1)?;
+                    pad(out, indent);
+                    out.push_str("end\n");
+                    match &else_[..] {
+                        [] => break,
+                        [Self::If(c, t, e)] => {
+                            pad(out, indent);
+                            out.push_str("else ");
+                            cond = c;
+                            then = t;
+                            else_ = e;
+                        }
+                        _ => {
+                            pad(out, indent);
+                            out.push_str("else begin\n");
+                            write_block(out, else_, indent + 1)?;
+                            pad(out, indent);
+                            out.push_str("end\n");
+                            break;
+                        }
+                    }
+                }
+                Ok(())
+            }
+            Self::While(cond, body) => {
+                writeln!(out, "while ({}) do begin", cond)?;
+                write_block(out, body, indent + 1)?;
+                pad(out, indent);
+                out.push_str("end\n");
+                Ok(())
+            }
+            Self::Return(e) => writeln!(out, "return {};", e),
+            Self::Break => writeln!(out, "break;"),
+            Self::Continue => writeln!(out, "continue;"),
+        }
+    }
+}
+
+fn write_block(out: &mut String, stmts: &[Stmt], indent: usize) -> fmt::Result {
+    for s in stmts {
+        s.write(out, indent)?;
+    }
+    Ok(())
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct VarDecl {
+    pub name: String,
+    pub init: Option<Expr>,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ExternKind {
+    Import,
+    Export,
+}
+
+/// External variable shared between the programs.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Extern {
+    pub name: String,
+    pub kind: ExternKind,
+    pub init: Option<Expr>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Body {
+    Stmts {
+        vars: Vec<VarDecl>,
+        stmts: Vec<Stmt>,
+    },
+    /// The body couldn't be decompiled. Holds the reason and the instruction listing of the body.
+    Error {
+        error: String,
+        listing: Vec<String>,
+    },
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Procedure {
+    pub name: String,
+    pub flags: BitFlags<ProcedureFlag>,
+    pub args: Vec<String>,
+    /// `None` for imported procedure.
+    pub body: Option<Body>,
+}
+
+impl Procedure {
+    fn write_header(&self, out: &mut String, flags: bool) -> fmt::Result {
+        for (flag, s) in [
+            (ProcedureFlag::Import, "import "),
+            (ProcedureFlag::Export, "export "),
+            (ProcedureFlag::Critical, "critical "),
+        ] {
+            if flags && self.flags.contains(flag) {
+                out.push_str(s);
+            }
+        }
+        write!(out, "procedure {}", self.name)?;
+        if !self.args.is_empty() {
+            out.push('(');
+            for (i, a) in self.args.iter().enumerate() {
+                if i > 0 {
+                    out.push_str(", ");
+                }
+                write!(out, "variable {}", a)?;
+            }
+            out.push(')');
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Script {
+    /// Procedures in the order of their ids.
+    pub procs: Vec<Procedure>,
+    pub vars: Vec<VarDecl>,
+    pub externs: Vec<Extern>,
+    /// Set if the initialization code couldn't be decompiled.
+    pub init_error: Option<String>,
+}
+
+impl Script {
+    /// Renames variables in the procedure bodies.
+    fn rename(&mut self, proc: usize, f: impl Fn(&str) -> Option<String>) {
+        if let Some(Body::Stmts { vars, stmts }) = &mut self.procs[proc].body {
+            for v in vars {
+                if let Some(init) = &mut v.init {
+                    init.rename(&f);
+                }
+            }
+            for s in stmts {
+                s.rename(&f);
+            }
+        }
+    }
+}
+
+fn write_var(out: &mut String, v: &VarDecl) -> fmt::Result {
+    write!(out, "variable {}", v.name)?;
+    if let Some(init) = &v.init {
+        write!(out, " := {}", init)?;
+    }
+    out.push_str(";\n");
+    Ok(())
+}
+
+impl fmt::Display for Script {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut out = String::new();
+        if let Some(e) = &self.init_error {
+            writeln!(out, "/* couldn't decompile initialization: {} */\n", e)?;
+        }
+        for p in &self.procs {
+            p.write_header(&mut out, true)?;
+            out.push_str(";\n");
+        }
+        if !self.externs.is_empty() || !self.vars.is_empty() {
+            out.push('\n');
+        }
+        for e in &self.externs {
+            out.push_str(match e.kind {
+                ExternKind::Import => "import ",
+                ExternKind::Export => "export ",
+            });
+            write_var(&mut out, &VarDecl { name: e.name.clone(), init: e.init.clone() })?;
+        }
+        for v in &self.vars {
+            write_var(&mut out, v)?;
+        }
+        for p in &self.procs {
+            let Some(body) = &p.body else {
+                continue;
+            };
+            out.push('\n');
+            p.write_header(&mut out, false)?;
+            out.push_str(" begin\n");
+            match body {
+                Body::Stmts { vars, stmts } => {
+                    for v in vars {
+                        out.push_str("   ");
+                        write_var(&mut out, v)?;
+                    }
+                    write_block(&mut out, stmts, 1)?;
+                }
+                Body::Error { error, listing } => {
+                    writeln!(out, "   /* couldn't decompile: {}", error)?;
+                    for line in listing {
+                        writeln!(out, "      {}", line.replace("*/", "* /"))?;
+                    }
+                    out.push_str("   */\n");
+                }
+            }
+            out.push_str("end\n");
+        }
+        f.write_str(&out)
+    }
+}
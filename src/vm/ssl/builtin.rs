@@ -0,0 +1,337 @@
+use std::collections::HashMap;
+use std::sync::OnceLock;
+
+use crate::vm::Opcode;
+
+/// Instruction callable from SSL as a function.
+#[derive(Clone, Copy, Debug)]
+pub struct Builtin {
+    pub opcode: Opcode,
+    pub name: &'static str,
+    pub arg_count: usize,
+    /// Whether the instruction pushes the result.
+    pub returns: bool,
+}
+
+macro_rules! builtins {
+    ($($opcode:ident $name:literal $arg_count:literal $($returns:ident)?,)*) => {
+        &[$(Builtin {
+            opcode: Opcode::$opcode,
+            name: $name,
+            arg_count: $arg_count,
+            returns: builtins!(@returns $($returns)?),
+        },)*]
+    };
+    (@returns ret) => { true };
+    (@returns) => { false };
+}
+
+pub static BUILTINS: &[Builtin] = builtins![
+    CallAt                  "call_at"                   2,
+    CallCondition           "call_condition"            2,
+    Callstart               "callstart"                 1,
+    Exec                    "exec"                      1,
+    Spawn                   "spawn"                     1,
+    Fork                    "fork"                      1,
+    Detach                  "detach"                    0,
+    StopProg                "stop_prog"                 0,
+    Wait                    "wait"                      1,
+    Cancel                  "cancel"                    1,
+    Cancelall               "cancelall"                 0,
+    Floor                   "floor"                     1 ret,
+    Sayquit                 "sayquit"                   0,
+    Sayend                  "sayend"                    0,
+    Saystart                "saystart"                  0,
+    Saystartpos             "saystartpos"               1,
+    Sayreplytitle           "sayreplytitle"             1,
+    Saygotoreply            "saygotoreply"              1,
+    Sayreply                "sayreply"                  2,
+    Sayoption               "sayoption"                 2,
+    Saymessage              "saymessage"                2,
+    Sayreplywindow          "sayreplywindow"            5,
+    Sayoptionwindow         "sayoptionwindow"           5,
+    Sayborder               "sayborder"                 2,
+    Sayscrollup             "sayscrollup"               6,
+    Sayscrolldown           "sayscrolldown"             6,
+    Saysetspacing           "saysetspacing"             1,
+    Sayoptioncolor          "sayoptioncolor"            3,
+    Sayreplycolor           "sayreplycolor"             3,
+    Sayrestart              "sayrestart"                0,
+    Saygetlastpos           "saygetlastpos"             0 ret,
+    Sayreplyflags           "sayreplyflags"             1,
+    Sayoptionflags          "sayoptionflags"            1,
+    Saymessagetimeout       "saymessagetimeout"         1,
+    Createwin               "createwin"                 5,
+    Deletewin               "deletewin"                 1,
+    Selectwin               "selectwin"                 1,
+    Resizewin               "resizewin"                 5,
+    Scalewin                "scalewin"                  5,
+    Showwin                 "showwin"                   0,
+    Fillwin                 "fillwin"                   3,
+    Fillrect                "fillrect"                  7,
+    Fillwin3X3              "fillwin3x3"                1,
+    Display                 "display"                   1,
+    Displaygfx              "displaygfx"                5,
+    Displayraw              "displayraw"                1,
+    Loadpalettetable        "loadpalettetable"          1,
+    Fadein                  "fadein"                    1,
+    Fadeout                 "fadeout"                   1,
+    Gotoxy                  "gotoxy"                    2,
+    Print                   "print"                     1,
+    Format                  "format"                    6,
+    Printrect               "printrect"                 3,
+    Setfont                 "setfont"                   1,
+    Settextflags            "settextflags"              1,
+    Settextcolor            "settextcolor"              3,
+    Sethighlightcolor       "sethighlightcolor"         3,
+    Stopmovie               "stopmovie"                 0,
+    Playmovie               "playmovie"                 1,
+    Movieflags              "movieflags"                1,
+    Playmovierect           "playmovierect"             5,
+    Addregionflag           "addregionflag"             2,
+    Addregionproc           "addregionproc"             5,
+    Addregionrightproc      "addregionrightproc"        3,
+    Deleteregion            "deleteregion"              1,
+    Activateregion          "activateregion"            2,
+    Checkregion             "checkregion"               1 ret,
+    Addbutton               "addbutton"                 6,
+    Addbuttontext           "addbuttontext"             2,
+    Addbuttonflag           "addbuttonflag"             2,
+    Addbuttongfx            "addbuttongfx"              4,
+    Addbuttonproc           "addbuttonproc"             5,
+    Addbuttonrightproc      "addbuttonrightproc"        3,
+    Deletebutton            "deletebutton"              1,
+    Hidemouse               "hidemouse"                 0,
+    Showmouse               "showmouse"                 0,
+    Mouseshape              "mouseshape"                3,
+    Refreshmouse            "refreshmouse"              1,
+    Setglobalmousefunc      "setglobalmousefunc"        1,
+    Addnamedevent           "addnamedevent"             2,
+    Addnamedhandler         "addnamedhandler"           2,
+    Clearnamed              "clearnamed"                1,
+    Signalnamed             "signalnamed"               1,
+    Addkey                  "addkey"                    2,
+    Deletekey               "deletekey"                 1,
+    Soundplay               "soundplay"                 2 ret,
+    Soundpause              "soundpause"                1,
+    Soundresume             "soundresume"               1,
+    Soundstop               "soundstop"                 1,
+    Soundrewind             "soundrewind"               1,
+    Sounddelete             "sounddelete"               1,
+    Setoneoptpause          "setoneoptpause"            1,
+    Selectfilelist          "selectfilelist"            2 ret,
+    Tokenize                "tokenize"                  3 ret,
+    GiveExpPoints           "give_exp_points"           1,
+    ScrReturn               "scr_return"                1,
+    PlaySfx                 "play_sfx"                  1,
+    ObjName                 "obj_name"                  1 ret,
+    SfxBuildOpenName        "sfx_build_open_name"       2 ret,
+    GetPcStat               "get_pc_stat"               1 ret,
+    TileContainsPidObj      "tile_contains_pid_obj"     3 ret,
+    SetMapStart             "set_map_start"             4,
+    OverrideMapStart        "override_map_start"        4,
+    HasSkill                "has_skill"                 2 ret,
+    UsingSkill              "using_skill"               2 ret,
+    RollVsSkill             "roll_vs_skill"             3 ret,
+    SkillContest            "skill_contest"             3 ret,
+    DoCheck                 "do_check"                  3 ret,
+    IsSuccess               "is_success"                1 ret,
+    IsCritical              "is_critical"               1 ret,
+    HowMuch                 "how_much"                  1 ret,
+    MarkAreaKnown           "mark_area_known"           3,
+    ReactionInfluence       "reaction_influence"        3 ret,
+    Random                  "random"                    2 ret,
+    RollDice                "roll_dice"                 2 ret,
+    MoveTo                  "move_to"                   3 ret,
+    CreateObjectSid         "create_object_sid"         4 ret,
+    DisplayMsg              "display_msg"               1,
+    ScriptOverrides         "script_overrides"          0,
+    ObjIsCarryingObjPid     "obj_is_carrying_obj_pid"   2 ret,
+    TileContainsObjPid      "tile_contains_obj_pid"     3 ret,
+    SelfObj                 "self_obj"                  0 ret,
+    SourceObj               "source_obj"                0 ret,
+    TargetObj               "target_obj"                0 ret,
+    DudeObj                 "dude_obj"                  0 ret,
+    ObjBeingUsedWith        "obj_being_used_with"       0 ret,
+    LocalVar                "local_var"                 1 ret,
+    SetLocalVar             "set_local_var"             2,
+    MapVar                  "map_var"                   1 ret,
+    SetMapVar               "set_map_var"               2,
+    GlobalVar               "global_var"                1 ret,
+    SetGlobalVar            "set_global_var"            2,
+    ScriptAction            "script_action"             0 ret,
+    ObjType                 "obj_type"                  1 ret,
+    ObjItemSubtype          "obj_item_subtype"          1 ret,
+    GetCritterStat          "get_critter_stat"          2 ret,
+    SetCritterStat          "set_critter_stat"          3 ret,
+    AnimateStandObj         "animate_stand_obj"         1,
+    AnimateStandReverseObj  "animate_stand_reverse_obj" 1,
+    AnimateMoveObjToTile    "animate_move_obj_to_tile"  3,
+    TileInTileRect          "tile_in_tile_rect"         5 ret,
+    Attack                  "attack_complex"            8,
+    Noop80d1                "make_daytime"              0,
+    TileDistance            "tile_distance"             2 ret,
+    TileDistanceObjs        "tile_distance_objs"        2 ret,
+    TileNum                 "tile_num"                  1 ret,
+    TileNumInDirection      "tile_num_in_direction"     3 ret,
+    PickupObj               "pickup_obj"                1,
+    DropObj                 "drop_obj"                  1,
+    AddObjToInven           "add_obj_to_inven"          2,
+    RmObjFromInven          "rm_obj_from_inven"         2,
+    WieldObjCritter         "wield_obj_critter"         2,
+    UseObj                  "use_obj"                   1,
+    ObjCanSeeObj            "obj_can_see_obj"           2 ret,
+    Attack80dd              "attack"                    8,
+    StartGdialog            "start_gdialog"             5,
+    EndDialogue             "end_dialogue"              0,
+    DialogueReaction        "dialogue_reaction"         1,
+    Metarule3               "metarule3"                 4 ret,
+    SetMapMusic             "set_map_music"             2,
+    SetObjVisibility        "set_obj_visibility"        2,
+    LoadMap                 "load_map"                  2,
+    WmAreaSetPos            "wm_area_set_pos"           3,
+    SetExitGrids            "set_exit_grids"            5,
+    AnimBusy                "anim_busy"                 1 ret,
+    CritterHeal             "critter_heal"              2 ret,
+    SetLightLevel           "set_light_level"           1,
+    GameTime                "game_time"                 0 ret,
+    GameTimeInSeconds       "game_time_in_seconds"      0 ret,
+    Elevation               "elevation"                 1 ret,
+    KillCritter             "kill_critter"              2,
+    KillCritterType         "kill_critter_type"         2,
+    CritterDamage           "critter_damage"            3,
+    AddTimerEvent           "add_timer_event"           3,
+    RmTimerEvent            "rm_timer_event"            1,
+    GameTicks               "game_ticks"                1 ret,
+    HasTrait                "has_trait"                 3 ret,
+    DestroyObject           "destroy_object"            1,
+    ObjCanHearObj           "obj_can_hear_obj"          2 ret,
+    GameTimeHour            "game_time_hour"            0 ret,
+    FixedParam              "fixed_param"               0 ret,
+    TileIsVisible           "tile_is_visible"           1 ret,
+    DialogueSystemEnter     "dialogue_system_enter"     0,
+    ActionBeingUsed         "action_being_used"         0 ret,
+    CritterState            "critter_state"             1 ret,
+    GameTimeAdvance         "game_time_advance"         1,
+    RadiationInc            "radiation_inc"             2,
+    RadiationDec            "radiation_dec"             2,
+    CritterAttemptPlacement "critter_attempt_placement" 3 ret,
+    ObjPid                  "obj_pid"                   1 ret,
+    CurMapIndex             "cur_map_index"             0 ret,
+    CritterAddTrait         "critter_add_trait"         4 ret,
+    CritterRmTrait          "critter_rm_trait"          4 ret,
+    ProtoData               "proto_data"                2 ret,
+    MessageStr              "message_str"               2 ret,
+    CritterInvenObj         "critter_inven_obj"         2 ret,
+    ObjSetLightLevel        "obj_set_light_level"       3,
+    WorldMap                "world_map"                 0,
+    InvenCmds               "inven_cmds"                3 ret,
+    FloatMsg                "float_msg"                 3,
+    Metarule                "metarule"                  2 ret,
+    Anim                    "anim"                      3,
+    ObjCarryingPidObj       "obj_carrying_pid_obj"      2 ret,
+    RegAnimFunc             "reg_anim_func"             2,
+    RegAnimAnimate          "reg_anim_animate"          3,
+    RegAnimAnimateReverse   "reg_anim_animate_reverse"  3,
+    RegAnimObjMoveToObj     "reg_anim_obj_move_to_obj"  3,
+    RegAnimObjRunToObj      "reg_anim_obj_run_to_obj"   3,
+    RegAnimObjMoveToTile    "reg_anim_obj_move_to_tile" 3,
+    RegAnimObjRunToTile     "reg_anim_obj_run_to_tile"  3,
+    PlayGmovie              "play_gmovie"               1,
+    AddMultObjsToInven      "add_mult_objs_to_inven"    3,
+    RmMultObjsFromInven     "rm_mult_objs_from_inven"   3 ret,
+    GetMonth                "get_month"                 0 ret,
+    GetDay                  "get_day"                   0 ret,
+    Explosion               "explosion"                 3,
+    DaysSinceVisited        "days_since_visited"        0 ret,
+    GsayStart               "gsay_start"                0,
+    GsayEnd                 "gsay_end"                  0,
+    GsayReply               "gsay_reply"                2,
+    GsayOption              "gsay_option"               4,
+    GsayMessage             "gsay_message"              3,
+    GiqOption               "giq_option"                5,
+    Poison                  "poison"                    2,
+    GetPoison               "get_poison"                1 ret,
+    PartyAdd                "party_add"                 1,
+    PartyRemove             "party_remove"              1,
+    RegAnimAnimateForever   "reg_anim_animate_forever"  2,
+    CritterInjure           "critter_injure"            2,
+    CombatIsInitialized     "combat_is_initialized"     0 ret,
+    GdialogBarter           "gdialog_mod_barter"        1 ret,
+    DifficultyLevel         "difficulty_level"          0 ret,
+    RunningBurningGuy       "running_burning_guy"       0 ret,
+    InvenUnwield            "inven_unwield"             1,
+    ObjIsLocked             "obj_is_locked"             1 ret,
+    ObjLock                 "obj_lock"                  1,
+    ObjUnlock               "obj_unlock"                1,
+    ObjIsOpen               "obj_is_open"               1 ret,
+    ObjOpen                 "obj_open"                  1,
+    ObjClose                "obj_close"                 1,
+    GameUiDisable           "game_ui_disable"           0,
+    GameUiEnable            "game_ui_enable"            0,
+    GameUiIsDisabled        "game_ui_is_disabled"       0 ret,
+    GfadeOut                "gfade_out"                 1,
+    GfadeIn                 "gfade_in"                  1,
+    ItemCapsTotal           "item_caps_total"           1 ret,
+    ItemCapsAdjust          "item_caps_adjust"          2 ret,
+    AnimActionFrame         "anim_action_frame"         2 ret,
+    RegAnimPlaySfx          "reg_anim_play_sfx"         3,
+    CritterModSkill         "critter_mod_skill"         3 ret,
+    SfxBuildCharName        "sfx_build_char_name"       3 ret,
+    SfxBuildAmbientName     "sfx_build_ambient_name"    1 ret,
+    SfxBuildInterfaceName   "sfx_build_interface_name"  1 ret,
+    SfxBuildItemName        "sfx_build_item_name"       1 ret,
+    SfxBuildWeaponName      "sfx_build_weapon_name"     4 ret,
+    SfxBuildSceneryName     "sfx_build_scenery_name"    3 ret,
+    AttackSetup             "attack_setup"              2,
+    DestroyMultObjs         "destroy_mult_objs"         2 ret,
+    UseObjOnObj             "use_obj_on_obj"            2,
+    EndgameSlideshow        "endgame_slideshow"         0,
+    MoveObjInvenToObj       "move_obj_inven_to_obj"     2,
+    EndgameMovie            "endgame_movie"             0,
+    ObjArtFid               "obj_art_fid"               1 ret,
+    ArtAnim                 "art_anim"                  1 ret,
+    PartyMemberObj          "party_member_obj"          1 ret,
+    RotationToTile          "rotation_to_tile"          2 ret,
+    JamLock                 "jam_lock"                  1,
+    GdialogSetBarterMod     "gdialog_set_barter_mod"    1,
+    CombatDifficulty        "combat_difficulty"         0 ret,
+    ObjOnScreen             "obj_on_screen"             1 ret,
+    CritterIsFleeing        "critter_is_fleeing"        1 ret,
+    CritterSetFleeState     "critter_set_flee_state"    2,
+    TerminateCombat         "terminate_combat"          0,
+    DebugMsg                "debug_msg"                 1,
+    CritterStopAttacking    "critter_stop_attacking"    1,
+];
+
+struct Index {
+    by_opcode: HashMap<Opcode, &'static Builtin>,
+    by_name: HashMap<&'static str, &'static Builtin>,
+}
+
+fn index() -> &'static Index {
+    static INDEX: OnceLock<Index> = OnceLock::new();
+    INDEX.get_or_init(|| Index {
+        by_opcode: BUILTINS.iter().map(|b| (b.opcode, b)).collect(),
+        by_name: BUILTINS.iter().map(|b| (b.name, b)).collect(),
+    })
+}
+
+pub fn by_opcode(opcode: Opcode) -> Option<&'static Builtin> {
+    index().by_opcode.get(&opcode).copied()
+}
+
+pub fn by_name(name: &str) -> Option<&'static Builtin> {
+    index().by_name.get(name).copied()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn unique() {
+        assert_eq!(index().by_opcode.len(), BUILTINS.len());
+        assert_eq!(index().by_name.len(), BUILTINS.len());
+    }
+}
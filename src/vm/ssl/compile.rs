@@ -0,0 +1,561 @@
+use bstring::bstr;
+use byteorder::{BigEndian, ByteOrder, WriteBytesExt};
+use std::collections::HashMap;
+use std::io;
+
+use crate::vm::Program;
+use super::*;
+
+fn error<T>(msg: impl fmt::Display) -> io::Result<T> {
+    Err(io::Error::new(io::ErrorKind::InvalidData, msg.to_string()))
+}
+
+/// Name or string table.
+#[derive(Default)]
+struct StringTable {
+    body: Vec<u8>,
+    offsets: HashMap<BString, usize>,
+}
+
+impl StringTable {
+    /// Appends the string and returns its offset.
+    fn push(&mut self, s: &bstr) -> usize {
+        let len = (s.len() + 2) & !1;
+        self.body.write_u16::<BigEndian>(len as u16).unwrap();
+        let offset = 4 + self.body.len();
+        self.body.extend_from_slice(s.as_bytes());
+        self.body.resize(self.body.len() + len - s.len(), 0);
+        offset
+    }
+
+    /// Like `push()` but reuses the existing entry.
+    fn intern(&mut self, s: &bstr) -> usize {
+        if let Some(&offset) = self.offsets.get(s) {
+            return offset;
+        }
+        let offset = self.push(s);
+        self.offsets.insert(s.into(), offset);
+        offset
+    }
+
+    fn write(&self, out: &mut Vec<u8>) {
+        if self.body.is_empty() {
+            out.write_u32::<BigEndian>(0xffff_ffff).unwrap();
+        } else {
+            out.write_u32::<BigEndian>(self.body.len() as u32).unwrap();
+            out.extend_from_slice(&self.body);
+            out.write_u16::<BigEndian>(0xffff).unwrap();
+            out.write_u16::<BigEndian>(0).unwrap();
+        }
+    }
+}
+
+fn collect_strings(e: &Expr, strings: &mut StringTable) {
+    match e {
+        Expr::String(s) => {
+            strings.intern(s.as_bstr());
+        }
+        Expr::Unary(_, e) => collect_strings(e, strings),
+        Expr::Binary(_, l, r) => {
+            collect_strings(l, strings);
+            collect_strings(r, strings);
+        }
+        Expr::Builtin(_, args) | Expr::Call(_, args) => for a in args {
+            collect_strings(a, strings);
+        }
+        Expr::Int(_) | Expr::Float(_) | Expr::Var(_) => {}
+    }
+}
+
+fn collect_strings_stmts(stmts: &[Stmt], strings: &mut StringTable) {
+    for s in stmts {
+        match s {
+            Stmt::Assign(_, e) | Stmt::Expr(e) | Stmt::Return(e) => collect_strings(e, strings),
+            Stmt::If(c, t, e) => {
+                collect_strings(c, strings);
+                collect_strings_stmts(t, strings);
+                collect_strings_stmts(e, strings);
+            }
+            Stmt::While(c, b) => {
+                collect_strings(c, strings);
+                collect_strings_stmts(b, strings);
+            }
+            Stmt::Break | Stmt::Continue => {}
+        }
+    }
+}
+
+struct Loop {
+    start: usize,
+    /// Positions of `break` jump addresses to patch with the loop end.
+    breaks: Vec<usize>,
+}
+
+struct Codegen<'a> {
+    out: Vec<u8>,
+    strings: StringTable,
+    procs: HashMap<&'a str, usize>,
+    globals: HashMap<&'a str, usize>,
+    /// Name table offsets of external variables.
+    externs: HashMap<&'a str, usize>,
+    /// Arguments and variables of the current procedure.
+    locals: HashMap<&'a str, usize>,
+    loops: Vec<Loop>,
+}
+
+impl<'a> Codegen<'a> {
+    fn pos(&self) -> usize {
+        self.out.len()
+    }
+
+    fn op(&mut self, opcode: Opcode) {
+        self.out.write_u16::<BigEndian>(opcode as u16).unwrap();
+    }
+
+    fn op_operand(&mut self, opcode: Opcode, v: i32) {
+        self.op(opcode);
+        self.out.write_i32::<BigEndian>(v).unwrap();
+    }
+
+    fn int(&mut self, v: i32) {
+        self.op_operand(Opcode::ConstLong, v);
+    }
+
+    /// Emits address constant to be patched later. Returns position of the address.
+    fn addr(&mut self) -> usize {
+        self.int(0);
+        self.pos() - 4
+    }
+
+    fn patch(&mut self, at: usize, pos: usize) {
+        BigEndian::write_i32(&mut self.out[at..], pos as i32);
+    }
+
+    fn jmp(&mut self, pos: usize) {
+        self.int(pos as i32);
+        self.op(Opcode::Jmp);
+    }
+
+    fn expr(&mut self, e: &'a Expr) -> io::Result<()> {
+        match e {
+            &Expr::Int(v) => self.int(v),
+            &Expr::Float(v) => self.op_operand(Opcode::ConstFloat, v.to_bits() as i32),
+            Expr::String(s) => {
+                let offset = self.strings.intern(s.as_bstr());
+                self.op_operand(Opcode::ConstString, offset as i32);
+            }
+            Expr::Var(name) => {
+                if let Some(&id) = self.locals.get(name.as_str()) {
+                    self.int(id as i32);
+                    self.op(Opcode::Fetch);
+                } else if let Some(&id) = self.globals.get(name.as_str()) {
+                    self.int(id as i32);
+                    self.op(Opcode::FetchGlobal);
+                } else if let Some(&offset) = self.externs.get(name.as_str()) {
+                    self.op_operand(Opcode::ConstString, offset as i32);
+                    self.op(Opcode::FetchExternal);
+                } else if let Some(&id) = self.procs.get(name.as_str()) {
+                    self.op_operand(Opcode::ConstShort, id as i32);
+                } else {
+                    return error(format_args!("undefined identifier `{}`", name));
+                }
+            }
+            Expr::Unary(op, e) => {
+                self.expr(e)?;
+                self.op(op.opcode());
+            }
+            Expr::Binary(op, l, r) => {
+                self.expr(l)?;
+                self.expr(r)?;
+                self.op(op.opcode());
+            }
+            Expr::Builtin(opcode, args) => {
+                for a in args {
+                    self.expr(a)?;
+                }
+                self.op(*opcode);
+            }
+            Expr::Call(name, args) => {
+                let Some(&id) = self.procs.get(name.as_str()) else {
+                    return error(format_args!("undefined procedure `{}`", name));
+                };
+                let ret = self.addr();
+                self.op(Opcode::DToA);
+                for _ in 0..3 {
+                    self.int(0);
+                }
+                for a in args {
+                    self.expr(a)?;
+                }
+                self.int(args.len() as i32);
+                self.op_operand(Opcode::ConstShort, id as i32);
+                self.op(Opcode::Call);
+                let pos = self.pos();
+                self.patch(ret, pos);
+                self.op(Opcode::AToD);
+            }
+        }
+        Ok(())
+    }
+
+    fn ret(&mut self, e: &'a Expr) -> io::Result<()> {
+        self.expr(e)?;
+        for op in [Opcode::DToA, Opcode::Swapa, Opcode::PopToBase, Opcode::PopBase, Opcode::Swapa,
+            Opcode::PopFlagsReturn]
+        {
+            self.op(op);
+        }
+        Ok(())
+    }
+
+    fn block(&mut self, stmts: &'a [Stmt]) -> io::Result<()> {
+        for s in stmts {
+            self.stmt(s)?;
+        }
+        Ok(())
+    }
+
+    fn stmt(&mut self, stmt: &'a Stmt) -> io::Result<()> {
+        match stmt {
+            Stmt::Assign(name, e) => {
+                self.expr(e)?;
+                if let Some(&id) = self.locals.get(name.as_str()) {
+                    self.int(id as i32);
+                    self.op(Opcode::Store);
+                } else if let Some(&id) = self.globals.get(name.as_str()) {
+                    self.int(id as i32);
+                    self.op(Opcode::StoreGlobal);
+                } else if let Some(&offset) = self.externs.get(name.as_str()) {
+                    self.op_operand(Opcode::ConstString, offset as i32);
+                    self.op(Opcode::StoreExternal);
+                } else {
+                    return error(format_args!("undefined variable `{}`", name));
+                }
+            }
+            Stmt::Expr(e) => {
+                self.expr(e)?;
+                let void = matches!(e, Expr::Builtin(opcode, _)
+                    if builtin::by_opcode(*opcode).is_some_and(|b| !b.returns));
+                if !void {
+                    self.op(Opcode::Pop);
+                }
+            }
+            Stmt::If(cond, then, else_) => {
+                let else_addr = self.addr();
+                self.expr(cond)?;
+                self.op(Opcode::If);
+                self.block(then)?;
+                if else_.is_empty() {
+                    let pos = self.pos();
+                    self.patch(else_addr, pos);
+                } else {
+                    let end_addr = self.addr();
+                    self.op(Opcode::Jmp);
+                    let pos = self.pos();
+                    self.patch(else_addr, pos);
+                    self.block(else_)?;
+                    let pos = self.pos();
+                    self.patch(end_addr, pos);
+                }
+            }
+            Stmt::While(cond, body) => {
+                let start = self.pos();
+                let end_addr = self.addr();
+                self.expr(cond)?;
+                self.op(Opcode::If);
+                self.loops.push(Loop { start, breaks: Vec::new() });
+                self.block(body)?;
+                let lp = self.loops.pop().unwrap();
+                self.jmp(start);
+                let pos = self.pos();
+                for at in lp.breaks.into_iter().chain(Some(end_addr)) {
+                    self.patch(at, pos);
+                }
+            }
+            Stmt::Return(e) => self.ret(e)?,
+            Stmt::Break => {
+                if self.loops.is_empty() {
+                    return error("break outside of loop");
+                }
+                let at = self.addr();
+                self.op(Opcode::Jmp);
+                self.loops.last_mut().unwrap().breaks.push(at);
+            }
+            Stmt::Continue => {
+                let Some(lp) = self.loops.last() else {
+                    return error("continue outside of loop");
+                };
+                self.jmp(lp.start);
+            }
+        }
+        Ok(())
+    }
+
+    fn init(&mut self, script: &'a Script) -> io::Result<()> {
+        self.op(Opcode::SetGlobal);
+        for v in &script.vars {
+            self.expr(v.init.as_ref().unwrap_or(&Expr::Int(0)))?;
+        }
+        for e in &script.externs {
+            if e.kind != ExternKind::Export {
+                continue;
+            }
+            let offset = self.externs[e.name.as_str()] as i32;
+            self.op_operand(Opcode::ConstString, offset);
+            self.op(Opcode::ExportVar);
+            if let Some(init) = &e.init {
+                self.expr(init)?;
+                self.op_operand(Opcode::ConstString, offset);
+                self.op(Opcode::StoreExternal);
+            }
+        }
+        self.op(Opcode::ExitProg);
+        Ok(())
+    }
+
+    fn procedure(&mut self, proc: &'a Procedure, vars: &'a [VarDecl], stmts: &'a [Stmt])
+        -> io::Result<()>
+    {
+        self.locals = proc.args.iter().map(|a| a.as_str())
+            .chain(vars.iter().map(|v| v.name.as_str()))
+            .enumerate()
+            .map(|(i, n)| (n, i))
+            .collect();
+        self.op(Opcode::PushBase);
+        for v in vars {
+            self.expr(v.init.as_ref().unwrap_or(&Expr::Int(0)))?;
+        }
+        self.block(stmts)?;
+        if !matches!(stmts.last(), Some(Stmt::Return(_))) {
+            self.ret(&Expr::Int(0))?;
+        }
+        Ok(())
+    }
+}
+
+/// Generates bytecode of the `script` following the conventions described in the module docs.
+pub fn compile(script: &Script) -> io::Result<Vec<u8>> {
+    const PROC_ENTRY_LEN: usize = 24;
+
+    let mut names = StringTable::default();
+    let proc_names: Vec<_> = script.procs.iter().map(|p| names.push(p.name.as_bytes().into()))
+        .collect();
+    for v in &script.vars {
+        names.push(v.name.as_bytes().into());
+    }
+    for p in &script.procs {
+        if let Some(Body::Stmts { vars, .. }) = &p.body {
+            for n in p.args.iter().chain(vars.iter().map(|v| &v.name)) {
+                names.push(n.as_bytes().into());
+            }
+        }
+    }
+    let externs = script.externs.iter()
+        .map(|e| (e.name.as_str(), names.intern(e.name.as_bytes().into())))
+        .collect();
+
+    let mut strings = StringTable::default();
+    for init in script.vars.iter().filter_map(|v| v.init.as_ref())
+        .chain(script.externs.iter().filter_map(|e| e.init.as_ref()))
+    {
+        collect_strings(init, &mut strings);
+    }
+    for p in &script.procs {
+        if let Some(Body::Stmts { vars, stmts }) = &p.body {
+            for init in vars.iter().filter_map(|v| v.init.as_ref()) {
+                collect_strings(init, &mut strings);
+            }
+            collect_strings_stmts(stmts, &mut strings);
+        }
+    }
+
+    let code_start = Program::HEADER_LEN + 4 + script.procs.len() * PROC_ENTRY_LEN
+        + names.body.len() + 8 + if strings.body.is_empty() { 4 } else { strings.body.len() + 8 };
+
+    let mut cg = Codegen {
+        out: Vec::new(),
+        strings,
+        procs: script.procs.iter().enumerate().map(|(i, p)| (p.name.as_str(), i)).collect(),
+        globals: script.vars.iter().enumerate().map(|(i, v)| (v.name.as_str(), i)).collect(),
+        externs,
+        locals: HashMap::new(),
+        loops: Vec::new(),
+    };
+
+    // Header. The position 24 is the return address of procedures invoked by the engine.
+    cg.op(Opcode::CriticalStart);
+    cg.jmp(code_start);
+    while cg.pos() < 24 {
+        cg.op(Opcode::Noop8000);
+    }
+    for op in [Opcode::AToD, Opcode::Pop, Opcode::AToD, Opcode::Pop, Opcode::ExitProg] {
+        cg.op(op);
+    }
+    while cg.pos() < Program::HEADER_LEN {
+        cg.op(Opcode::Noop8000);
+    }
+
+    // Tables.
+    cg.out.resize(Program::HEADER_LEN + 4 + script.procs.len() * PROC_ENTRY_LEN, 0);
+    names.write(&mut cg.out);
+    let strings = std::mem::take(&mut cg.strings);
+    strings.write(&mut cg.out);
+    cg.strings = strings;
+    assert_eq!(cg.pos(), code_start);
+
+    cg.init(script)?;
+    let mut body_pos = Vec::with_capacity(script.procs.len());
+    for p in &script.procs {
+        body_pos.push(match &p.body {
+            Some(Body::Stmts { vars, stmts }) => {
+                let pos = cg.pos();
+                cg.procedure(p, vars, stmts)?;
+                pos
+            }
+            Some(Body::Error { .. }) => return error(format_args!("procedure `{}` has no body", p.name)),
+            None if p.flags.contains(ProcedureFlag::Import) => 0,
+            None => return error(format_args!("procedure `{}` is not defined", p.name)),
+        });
+    }
+
+    let mut proc_table = Vec::new();
+    proc_table.write_u32::<BigEndian>(script.procs.len() as u32).unwrap();
+    for (i, p) in script.procs.iter().enumerate() {
+        for v in [proc_names[i], p.flags.bits() as usize, 0, 0, body_pos[i], p.args.len()] {
+            proc_table.write_u32::<BigEndian>(v as u32).unwrap();
+        }
+    }
+    cg.out[Program::HEADER_LEN..Program::HEADER_LEN + proc_table.len()]
+        .copy_from_slice(&proc_table);
+
+    Ok(cg.out)
+}
+
+/// Parses and compiles SSL source file at `path`. The files are read with the `read` function.
+pub fn compile_file(path: &str, read: &mut dyn FnMut(&str) -> io::Result<Vec<u8>>)
+    -> io::Result<Vec<u8>>
+{
+    compile(&parse::parse(path, read)?)
+}
+
+#[cfg(test)]
+mod test {
+    use std::rc::Rc;
+
+    use super::*;
+    use crate::util::test::VmEnv;
+    use crate::vm::Vm;
+    use crate::vm::ssl::decompile::decompile;
+
+    fn compile_src(files: &[(&str, &str)]) -> io::Result<Vec<u8>> {
+        compile_file(files[0].0, &mut |p| files.iter().find(|f| f.0 == p)
+            .map(|f| f.1.as_bytes().to_vec())
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, p.to_owned())))
+    }
+
+    /// Runs the initialization code and `start` procedure. Returns the game global variables.
+    fn run(code: Vec<u8>) -> Vec<i32> {
+        let mut vm = Vm::default();
+        let prg = Rc::new(vm.load("test.int".into(), code.into()).unwrap());
+        let start = prg.proc_id(&Rc::new("start".into())).unwrap();
+        let h = vm.insert(prg);
+        let mut env = VmEnv::new();
+        vm.run(h, &mut env.ctx()).unwrap().assert_no_suspend();
+        vm.program_state_mut(h).execute_proc(start, &mut env.ctx()).unwrap().assert_no_suspend();
+        env.global_vars
+    }
+
+    const SRC: &str = r#"
+        #include "headers/define.h"
+
+        procedure start;
+        procedure fact(variable n);
+        procedure sum_odd(variable n);
+
+        export variable shared := 5;
+        variable counter := 2, ratio := -1.5;
+
+        procedure fact(variable n) begin
+            if n <= 1 then
+                return 1;
+            return n * fact(n - 1);
+        end
+
+        procedure sum_odd(variable n) begin
+            variable i, s := 0;
+            while true do begin
+                i++;
+                if (i % 2 == 0) then continue;
+                if (i > n) then break;
+                s += i;
+            end
+            return s;
+        end
+
+        procedure start begin
+            variable s := "abc";
+            set_global_var(0, fact(5));
+            set_global_var(1, sum_odd(9));
+            counter := counter * 10 + shared;
+            set_global_var(2, counter);
+            if (s == "abc" and not(counter < 0)) then begin
+                set_global_var(3, 1);
+            end else
+                set_global_var(3, 2);
+            variable sq := SQR(3) + ONE;
+            SET(4, sq);
+            set_global_var(5, 7 / 2.0 > -ratio * 2 - 0.6);
+            set_global_var(6, -(1 - 4) bwand 6);
+            call sum_odd(1);
+        end
+    "#;
+
+    const DEFINE_H: &str = "
+        #ifndef DEFINE_H
+        #define DEFINE_H
+        #define true  1
+        #define ONE   1
+        #define SQR(x)  ((x) * (x))
+        #define SET(i, v)  set_global_var(i, v)
+        #endif
+    ";
+
+    #[test]
+    fn compile_and_run() {
+        let code = compile_src(&[("scripts/test.ssl", SRC), ("scripts/headers/define.h", DEFINE_H)])
+            .unwrap();
+        assert_eq!(run(code), vec![120, 25, 25, 1, 10, 1, 2, 0]);
+    }
+
+    #[test]
+    fn decompile_roundtrip() {
+        let code = compile_src(&[("test.ssl", SRC), ("headers/define.h", DEFINE_H)]).unwrap();
+        let prg = Vm::default().load("test.int".into(), code.clone().into()).unwrap();
+        let src = decompile(&prg).to_string();
+        assert!(src.contains("procedure sum_odd(variable n) begin\n   variable i := 0;\n"), "{}", src);
+
+        let recompiled = compile_src(&[("test.ssl", &src)]).unwrap();
+        assert_eq!(recompiled, code);
+    }
+
+    #[test]
+    fn errors() {
+        fn err(src: &str) -> String {
+            compile_src(&[("test.ssl", src)]).unwrap_err().to_string()
+        }
+        assert_eq!(err("procedure start begin\n  x := 1;\nend"),
+            "test.ssl:2: undefined identifier `x`");
+        assert_eq!(err("procedure foo(variable a) begin end\nprocedure start begin call foo; end"),
+            "test.ssl:2: `foo` expects 1 arguments, got 0");
+        assert_eq!(err("procedure start begin\nvariable a := display_msg(\"x\"); end"),
+            "test.ssl:2: `display_msg` doesn't return a value");
+        assert_eq!(err("procedure start;"),
+            "test.ssl:1: procedure `start` is declared but not defined");
+        assert_eq!(err("procedure start begin break; end"),
+            "test.ssl:1: `break` outside of loop");
+        assert_eq!(err("variable x;\nprocedure x begin end"),
+            "test.ssl:2: `x` is already defined");
+        assert_eq!(err("procedure start begin if 1 then end"),
+            "test.ssl:1: expected identifier, found `end`");
+    }
+}
@@ -0,0 +1,911 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+use std::rc::Rc;
+
+use crate::vm::{Program, ProcedureFlag, ProcedureId};
+use crate::vm::disasm::{self, Decoded, Operand};
+use super::*;
+
+type DResult<T> = std::result::Result<T, String>;
+
+fn err<T>(pos: usize, msg: impl fmt::Display) -> DResult<T> {
+    Err(format!("0x{:04x}: {}", pos, msg))
+}
+
+/// Targets of `continue` and `break`.
+#[derive(Clone, Copy)]
+struct Loop {
+    start: usize,
+    end: usize,
+}
+
+struct Decompiler<'a> {
+    prg: &'a Program,
+    instrs: Vec<Decoded>,
+    /// Instruction index by position. Includes the position past the last instruction.
+    index: HashMap<usize, usize>,
+    /// Name table offsets of the external variables.
+    externs: HashMap<String, usize>,
+    /// Argument count of the current procedure.
+    arg_count: usize,
+}
+
+const RETURN_SEQ: [Opcode; 5] = [
+    Opcode::Swapa, Opcode::PopToBase, Opcode::PopBase, Opcode::Swapa, Opcode::PopFlagsReturn];
+
+fn global_name(id: usize) -> String {
+    format!("GVar{}", id)
+}
+
+fn is_ident(s: &str) -> bool {
+    let mut chars = s.chars();
+    chars.next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+impl Decompiler<'_> {
+    fn local_name(&self, id: usize) -> String {
+        if id < self.arg_count {
+            format!("arg{}", id)
+        } else {
+            format!("LVar{}", id - self.arg_count)
+        }
+    }
+
+    fn idx(&self, pos: usize) -> DResult<usize> {
+        self.index.get(&pos).copied()
+            .map_or_else(|| err(pos, "not an instruction boundary"), Ok)
+    }
+
+    fn pos(&self, idx: usize) -> usize {
+        self.instrs.get(idx).map(|i| i.pos).unwrap_or(self.prg.code.len())
+    }
+
+    fn int_operand(&self, idx: usize) -> Option<i32> {
+        match self.instrs.get(idx) {
+            Some(&Decoded { opcode: Opcode::ConstLong, operand: Some(Operand::Int(v)), .. }) =>
+                Some(v),
+            _ => None,
+        }
+    }
+
+    fn name(&self, pos: usize, offset: usize) -> DResult<String> {
+        match self.prg.names.get(offset) {
+            Some(s) => Ok(s.display().to_string()),
+            None => err(pos, format_args!("bad name offset {}", offset)),
+        }
+    }
+
+    fn proc_name(&self, id: i32) -> Option<String> {
+        self.prg.proc(id as ProcedureId).map(|p| p.name().display().to_string())
+    }
+
+    /// Decompiles statements in `start..end` instruction index range. The values left on the stack
+    /// by the first statement become procedure variables if `vars` is set.
+    fn block(&mut self, start: usize, end: usize, lp: Option<Loop>,
+        mut vars: Option<&mut Vec<VarDecl>>) -> DResult<Vec<Stmt>>
+    {
+        use Opcode::*;
+
+        let mut stmts = Vec::new();
+        // Expressions along with the index of their first instruction.
+        let mut stack: Vec<(Expr, usize)> = Vec::new();
+        let mut rstack: Vec<(Expr, usize)> = Vec::new();
+        let mut i = start;
+        while i < end {
+            let instr = self.instrs[i];
+            let pos = instr.pos;
+            let mut next = i + 1;
+
+            macro_rules! pop {
+                () => {
+                    match stack.pop() {
+                        Some(v) => v,
+                        None => return err(pos, "stack underflow"),
+                    }
+                };
+            }
+            macro_rules! pop_int {
+                () => {
+                    match pop!() {
+                        (Expr::Int(v), idx) => (v, idx),
+                        (e, _) => return err(pos, format_args!("expected integer, got {}", e)),
+                    }
+                };
+            }
+
+            let stmt = match instr.opcode {
+                ConstLong | ConstShort | ConstFloat | ConstString => {
+                    let e = match instr.operand.unwrap() {
+                        Operand::Int(v) if instr.opcode == ConstShort =>
+                            self.proc_name(v).map(Expr::Var).unwrap_or(Expr::Int(v)),
+                        Operand::Int(v) => Expr::Int(v),
+                        Operand::Float(v) => Expr::Float(v),
+                        Operand::String(v) => {
+                            if self.instrs.get(i + 1).is_some_and(|i| disasm::takes_name(i.opcode)) {
+                                let name = self.name(pos, v)?;
+                                self.externs.insert(name.clone(), v);
+                                Expr::Var(name)
+                            } else {
+                                match self.prg.strings.get(v) {
+                                    Some(s) => Expr::String((**s).clone()),
+                                    None => return err(pos, format_args!("bad string offset {}", v)),
+                                }
+                            }
+                        }
+                    };
+                    stack.push((e, i));
+                    None
+                }
+                Fetch | FetchGlobal => {
+                    let (id, idx) = pop_int!();
+                    let name = if instr.opcode == Fetch {
+                        self.local_name(id as usize)
+                    } else {
+                        global_name(id as usize)
+                    };
+                    stack.push((Expr::Var(name), idx));
+                    None
+                }
+                Store | StoreGlobal => {
+                    let (id, _) = pop_int!();
+                    let (v, _) = pop!();
+                    let name = if instr.opcode == Store {
+                        self.local_name(id as usize)
+                    } else {
+                        global_name(id as usize)
+                    };
+                    Some(Stmt::Assign(name, v))
+                }
+                FetchExternal => {
+                    let (e, idx) = pop!();
+                    stack.push((e, idx));
+                    None
+                }
+                StoreExternal => {
+                    let Expr::Var(name) = pop!().0 else {
+                        return err(pos, "expected variable name");
+                    };
+                    let (v, _) = pop!();
+                    Some(Stmt::Assign(name, v))
+                }
+                Pop => Some(Stmt::Expr(pop!().0)),
+                DToA => {
+                    if self.instrs.get(i + 1..i + 1 + RETURN_SEQ.len())
+                        .is_some_and(|s| s.iter().map(|i| i.opcode).eq(RETURN_SEQ))
+                    {
+                        next = i + 1 + RETURN_SEQ.len();
+                        Some(Stmt::Return(pop!().0))
+                    } else {
+                        rstack.push(pop!());
+                        None
+                    }
+                }
+                Call => {
+                    let (proc, _) = pop!();
+                    let name = match proc {
+                        Expr::Var(name) if self.prg.proc_id(&Rc::new(name.as_str().into())).is_some()
+                            => name,
+                        _ => return err(pos, format_args!("bad procedure {}", proc)),
+                    };
+                    let (arg_count, _) = pop_int!();
+                    if arg_count < 0 || arg_count as usize > stack.len() {
+                        return err(pos, format_args!("bad argument count {}", arg_count));
+                    }
+                    let args = stack.split_off(stack.len() - arg_count as usize)
+                        .into_iter().map(|(e, _)| e).collect();
+                    for _ in 0..3 {
+                        if pop_int!().0 != 0 {
+                            return err(pos, "expected zero flags");
+                        }
+                    }
+                    let ret = rstack.pop();
+                    match ret {
+                        Some((Expr::Int(ret), idx)) if ret as usize == self.pos(i + 1)
+                            && self.instrs.get(i + 1).is_some_and(|i| i.opcode == AToD) =>
+                        {
+                            stack.push((Expr::Call(name, args), idx));
+                            next = i + 2;
+                        }
+                        _ => return err(pos, "bad return address"),
+                    }
+                    None
+                }
+                If | While => {
+                    let (cond, _) = pop!();
+                    let (target, start_idx) = pop_int!();
+                    let start_pos = self.pos(start_idx);
+                    let t = self.idx(target as usize)?;
+                    if t <= i || t > end {
+                        return err(pos, format_args!("jump target 0x{:04x} out of block", target));
+                    }
+                    let jmp = |d: &Self| if t >= i + 3 && d.instrs[t - 1].opcode == Jmp {
+                        d.int_operand(t - 2)
+                    } else {
+                        None
+                    };
+                    match jmp(self) {
+                        Some(back) if back as usize == start_pos => {
+                            let body = self.block(i + 1, t - 2,
+                                Some(Loop { start: start_pos, end: target as usize }), None)?;
+                            next = t;
+                            Some(Stmt::While(cond, body))
+                        }
+                        // The original compiler emits the jump even if there's no `else`.
+                        Some(fwd) if fwd == target => {
+                            let then = self.block(i + 1, t - 2, lp, None)?;
+                            next = t;
+                            Some(Stmt::If(cond, then, Vec::new()))
+                        }
+                        Some(fwd) if fwd > target
+                            && self.index.get(&(fwd as usize)).is_some_and(|&e| e <= end)
+                            && lp.is_none_or(|lp| fwd as usize != lp.end) =>
+                        {
+                            let e = self.idx(fwd as usize)?;
+                            let then = self.block(i + 1, t - 2, lp, None)?;
+                            let else_ = self.block(t, e, lp, None)?;
+                            next = e;
+                            Some(Stmt::If(cond, then, else_))
+                        }
+                        _ => {
+                            let then = self.block(i + 1, t, lp, None)?;
+                            next = t;
+                            Some(Stmt::If(cond, then, Vec::new()))
+                        }
+                    }
+                }
+                Jmp => {
+                    let (target, _) = pop_int!();
+                    match lp {
+                        Some(lp) if target as usize == lp.end => Some(Stmt::Break),
+                        Some(lp) if target as usize == lp.start => Some(Stmt::Continue),
+                        _ => return err(pos, format_args!("unstructured jump to 0x{:04x}", target)),
+                    }
+                }
+                Noop8000 | CriticalStart | CriticalDone | CriticalStart804a | CriticalDone804b =>
+                    None,
+                opcode => if let Some(op) = BinaryOp::from_opcode(opcode) {
+                    let (r, _) = pop!();
+                    let (l, idx) = pop!();
+                    stack.push((Expr::Binary(op, Box::new(l), Box::new(r)), idx));
+                    None
+                } else if let Some(op) = UnaryOp::from_opcode(opcode) {
+                    let (e, idx) = pop!();
+                    stack.push((Expr::Unary(op, Box::new(e)), idx));
+                    None
+                } else if let Some(b) = builtin::by_opcode(opcode) {
+                    if b.arg_count > stack.len() {
+                        return err(pos, "stack underflow");
+                    }
+                    let args = stack.split_off(stack.len() - b.arg_count);
+                    let idx = args.first().map(|&(_, idx)| idx).unwrap_or(i);
+                    let e = Expr::Builtin(opcode, args.into_iter().map(|(e, _)| e).collect());
+                    if b.returns {
+                        stack.push((e, idx));
+                        None
+                    } else {
+                        Some(Stmt::Expr(e))
+                    }
+                } else {
+                    return err(pos, format_args!("unsupported instruction {}",
+                        disasm::opcode_name(opcode)));
+                }
+            };
+
+            if let Some(stmt) = stmt {
+                if !rstack.is_empty() {
+                    return err(pos, "unbalanced return stack");
+                }
+                if let Some(vars) = vars.take() {
+                    for (e, _) in stack.drain(..) {
+                        let name = self.local_name(self.arg_count + vars.len());
+                        vars.push(VarDecl { name, init: Some(e) });
+                    }
+                }
+                if !stack.is_empty() {
+                    return err(pos, "unbalanced stack");
+                }
+                stmts.push(stmt);
+            }
+            i = next;
+        }
+        if !stack.is_empty() || !rstack.is_empty() {
+            return err(self.pos(end), "unbalanced stack at the end of block");
+        }
+        Ok(stmts)
+    }
+
+    fn body(&mut self, start: usize, end: usize) -> DResult<(Vec<VarDecl>, Vec<Stmt>)> {
+        let start_pos = self.pos(start);
+        if start >= end || self.instrs[start].opcode != Opcode::PushBase {
+            return err(start_pos, "expected push_base");
+        }
+        let mut vars = Vec::new();
+        let mut stmts = self.block(start + 1, end, None, Some(&mut vars))?;
+        match stmts.last() {
+            Some(Stmt::Return(Expr::Int(0))) => {
+                stmts.pop();
+            }
+            Some(Stmt::Return(_)) => {}
+            _ => return err(self.pos(end), "expected return at the end of procedure"),
+        }
+        Ok((vars, stmts))
+    }
+
+    /// Returns global variable initializers and exported variables.
+    fn init(&mut self, start: usize, end: usize) -> DResult<(Vec<VarDecl>, Vec<Extern>)> {
+        use Opcode::*;
+
+        let mut vars = Vec::new();
+        let mut externs = Vec::new();
+        let mut i = start;
+        let opcode = |d: &Self, i: usize| d.instrs.get(i).filter(|_| i < end).map(|i| i.opcode);
+        let constant = |d: &Self, i: usize| -> Option<Expr> {
+            let instr = d.instrs.get(i).filter(|_| i < end)?;
+            Some(match instr.operand? {
+                Operand::Int(v) if instr.opcode == ConstLong => Expr::Int(v),
+                Operand::Float(v) => Expr::Float(v),
+                Operand::String(v) => Expr::String((**d.prg.strings.get(v)?).clone()),
+                _ => return None,
+            })
+        };
+
+        if opcode(self, i) != Some(SetGlobal) {
+            return err(self.pos(i), "expected set_global");
+        }
+        i += 1;
+        while opcode(self, i + 1) != Some(ExportVar)
+            && let Some(e) = constant(self, i)
+        {
+            vars.push(VarDecl { name: global_name(vars.len()), init: Some(e) });
+            i += 1;
+        }
+        while opcode(self, i + 1) == Some(ExportVar) {
+            let Some(Operand::String(offset)) = self.instrs[i].operand else {
+                return err(self.pos(i), "expected variable name");
+            };
+            let name = self.name(self.pos(i), offset)?;
+            self.externs.insert(name.clone(), offset);
+            i += 2;
+            let init = if opcode(self, i + 2) == Some(StoreExternal)
+                && self.instrs[i + 1].operand == Some(Operand::String(offset))
+                && let Some(e) = constant(self, i)
+            {
+                i += 3;
+                Some(e)
+            } else {
+                None
+            };
+            externs.push(Extern { name, kind: ExternKind::Export, init });
+        }
+        if opcode(self, i) != Some(ExitProg) || i + 1 != end {
+            return err(self.pos(i), "expected exit_prog");
+        }
+        Ok((vars, externs))
+    }
+}
+
+/// Replaces the generated variable names with the names from the name table if the table lists
+/// exactly the global variables followed by the arguments and variables of each procedure.
+fn apply_names(script: &mut Script, prg: &Program, reserved: &HashSet<usize>) {
+    let mut offsets: Vec<_> = prg.names.map.keys().copied()
+        .filter(|o| !reserved.contains(o))
+        .collect();
+    offsets.sort_unstable();
+    let names: Vec<String> = offsets.iter()
+        .map(|&o| prg.names.get(o).unwrap().display().to_string())
+        .collect();
+
+    let mut expected = script.vars.len();
+    for p in &script.procs {
+        match &p.body {
+            Some(Body::Stmts { vars, .. }) => expected += p.args.len() + vars.len(),
+            Some(Body::Error { .. }) => return,
+            None => {}
+        }
+    }
+    if names.len() != expected || !names.iter().all(|n| is_ident(n)) {
+        return;
+    }
+
+    let taken: HashSet<&str> = script.procs.iter().map(|p| p.name.as_str())
+        .chain(script.externs.iter().map(|e| e.name.as_str()))
+        .collect();
+    let unique = |names: &[String]| {
+        let set: HashSet<&str> = names.iter().map(|s| s.as_str()).collect();
+        set.len() == names.len() && names.iter().all(|n| !taken.contains(n.as_str())
+            && builtin::by_name(n).is_none())
+    };
+
+    let (globals, mut rest) = names.split_at(script.vars.len());
+    if !unique(globals) {
+        return;
+    }
+    let mut locals = Vec::new();
+    for p in &script.procs {
+        if let Some(Body::Stmts { vars, .. }) = &p.body {
+            let (l, r) = rest.split_at(p.args.len() + vars.len());
+            if !unique(l) {
+                return;
+            }
+            locals.push(l);
+            rest = r;
+        } else {
+            locals.push(&[]);
+        }
+    }
+
+    let global_map: HashMap<String, String> = globals.iter().enumerate()
+        .map(|(i, n)| (global_name(i), n.clone()))
+        .collect();
+    for (v, n) in script.vars.iter_mut().zip(globals) {
+        v.name = n.clone();
+    }
+    for (i, l) in locals.into_iter().enumerate() {
+        let p = &mut script.procs[i];
+        let Some(Body::Stmts { vars, .. }) = &mut p.body else {
+            continue;
+        };
+        let arg_count = p.args.len();
+        let mut map = global_map.clone();
+        for (j, n) in l.iter().enumerate() {
+            let generic = if j < arg_count {
+                format!("arg{}", j)
+            } else {
+                format!("LVar{}", j - arg_count)
+            };
+            map.insert(generic, n.clone());
+        }
+        for (a, n) in p.args.iter_mut().zip(l) {
+            *a = n.clone();
+        }
+        for (v, n) in vars.iter_mut().zip(&l[arg_count..]) {
+            v.name = n.clone();
+        }
+        script.rename(i, |n| map.get(n).cloned());
+    }
+}
+
+/// Reconstructs SSL source of the `program`. The code is expected to follow the conventions
+/// described in the module docs. Procedures that don't follow them are decompiled into
+/// `Body::Error` stating the reason.
+pub fn decompile(prg: &Program) -> Script {
+    let (instrs, decode_err) = disasm::decode_range(prg, prg.code_start, prg.code.len());
+    let mut index: HashMap<_, _> = instrs.iter().enumerate().map(|(i, d)| (d.pos, i)).collect();
+    let end_pos = decode_err.as_ref().map(|&(pos, _)| pos).unwrap_or(prg.code.len());
+    index.insert(end_pos, instrs.len());
+    let mut d = Decompiler {
+        prg,
+        instrs,
+        index,
+        externs: HashMap::new(),
+        arg_count: 0,
+    };
+
+    let mut bodies: Vec<_> = prg.procs.by_id.iter()
+        .filter(|p| !p.flags.contains(ProcedureFlag::Import))
+        .map(|p| p.body_pos)
+        .collect();
+    bodies.sort_unstable();
+    let range_end = |pos: usize| bodies.iter().copied().find(|&p| p > pos).unwrap_or(end_pos);
+
+    let mut script = Script::default();
+    let init_end = bodies.first().copied().unwrap_or(end_pos);
+    let init = d.idx(prg.code_start)
+        .and_then(|s| Ok((s, d.idx(init_end)?)))
+        .and_then(|(s, e)| d.init(s, e));
+    match init {
+        Ok((vars, externs)) => {
+            script.vars = vars;
+            script.externs = externs;
+        }
+        Err(e) => script.init_error = Some(e),
+    }
+
+    for p in &prg.procs.by_id {
+        let name = p.name().display().to_string();
+        d.arg_count = p.arg_count;
+        let args = (0..p.arg_count).map(|i| d.local_name(i)).collect();
+        let body = if p.flags.contains(ProcedureFlag::Import) {
+            None
+        } else {
+            let r = if let Some((pos, e)) = &decode_err
+                && p.body_pos <= *pos && *pos < range_end(p.body_pos)
+            {
+                err(*pos, format_args!("{:?}", e))
+            } else {
+                d.idx(p.body_pos)
+                    .and_then(|s| Ok((s, d.idx(range_end(p.body_pos))?)))
+                    .and_then(|(s, e)| d.body(s, e))
+            };
+            Some(match r {
+                Ok((vars, stmts)) => Body::Stmts { vars, stmts },
+                Err(error) => Body::Error {
+                    error,
+                    listing: disasm::listing(prg, p.body_pos, range_end(p.body_pos)),
+                },
+            })
+        };
+        script.procs.push(Procedure { name, flags: p.flags, args, body });
+    }
+
+    let exported: HashSet<String> = script.externs.iter().map(|e| e.name.clone()).collect();
+    let mut imported: Vec<_> = d.externs.iter()
+        .filter(|(n, _)| !exported.contains(*n))
+        .map(|(n, &o)| (o, n.clone()))
+        .collect();
+    imported.sort_unstable();
+    script.externs.extend(imported.into_iter()
+        .map(|(_, name)| Extern { name, kind: ExternKind::Import, init: None }));
+    script.externs.sort_by_key(|e| d.externs[&e.name]);
+
+    let reserved: HashSet<usize> = prg.procs.by_id.iter()
+        .filter_map(|p| prg.names.map.iter().find(|(_, n)| **n == p.name).map(|(&o, _)| o))
+        .chain(d.externs.values().copied())
+        .collect();
+    apply_names(&mut script, prg, &reserved);
+
+    script
+}
+
+#[cfg(test)]
+mod test {
+    use enumflags2::BitFlags;
+
+    use super::*;
+    use crate::vm::disasm::test::{build, emit};
+
+    use Opcode::*;
+
+    fn c(code: &mut Vec<u8>, v: i32) {
+        emit(code, ConstLong, Some(v));
+    }
+
+    fn ret(code: &mut Vec<u8>) {
+        for op in [DToA, Swapa, PopToBase, PopBase, Swapa, PopFlagsReturn] {
+            emit(code, op, None);
+        }
+    }
+
+    #[test]
+    fn decompile_() {
+        let none = BitFlags::empty();
+        let prg = build(
+            &[("start", none, 0), ("add", ProcedureFlag::Critical.into(), 2), ("ext_proc", ProcedureFlag::Import.into(), 0)],
+            &["counter", "shared", "total", "a", "b", "sum", "i"], &["hello"],
+            |code, start, names, strings| {
+                let pos = |code: &Vec<u8>| (start + code.len()) as i32;
+                let patch = |code: &mut Vec<u8>, at: usize, v: i32| {
+                    code[at + 2..at + 6].copy_from_slice(&v.to_be_bytes());
+                };
+
+                // Init.
+                emit(code, SetGlobal, None);
+                c(code, 7);
+                emit(code, ConstString, Some(names[1] as i32));
+                emit(code, ExportVar, None);
+                c(code, 1);
+                emit(code, ConstString, Some(names[1] as i32));
+                emit(code, StoreExternal, None);
+                emit(code, ExitProg, None);
+
+                // procedure start
+                let start_pos = pos(code);
+                emit(code, PushBase, None);
+                c(code, 0); // variable total := 0;
+                // display_msg("hello");
+                emit(code, ConstString, Some(strings[0] as i32));
+                emit(code, DisplayMsg, None);
+                // total := add(global_var(2), 3);
+                let call_at = code.len();
+                c(code, 0);
+                emit(code, DToA, None);
+                c(code, 0);
+                c(code, 0);
+                c(code, 0);
+                c(code, 2);
+                emit(code, GlobalVar, None);
+                c(code, 3);
+                c(code, 2);
+                emit(code, ConstShort, Some(1));
+                emit(code, Call, None);
+                let ret_pos = pos(code);
+                patch(code, call_at, ret_pos);
+                emit(code, AToD, None);
+                c(code, 0);
+                emit(code, Store, None);
+                // if (total > 5) then counter := total; else counter := -total;
+                let if_at = code.len();
+                c(code, 0);
+                c(code, 0);
+                emit(code, Fetch, None);
+                c(code, 5);
+                emit(code, Greater, None);
+                emit(code, If, None);
+                c(code, 0);
+                emit(code, Fetch, None);
+                c(code, 0);
+                emit(code, StoreGlobal, None);
+                let jmp_at = code.len();
+                c(code, 0);
+                emit(code, Jmp, None);
+                let else_pos = pos(code);
+                patch(code, if_at, else_pos);
+                c(code, 0);
+                emit(code, Fetch, None);
+                emit(code, Negate, None);
+                c(code, 0);
+                emit(code, StoreGlobal, None);
+                let end_pos = pos(code);
+                patch(code, jmp_at, end_pos);
+                c(code, 0);
+                ret(code);
+
+                // procedure add(a, b)
+                let add_pos = pos(code);
+                emit(code, PushBase, None);
+                c(code, 0); // variable sum := 0;
+                c(code, 1); // variable i := 1;
+                // while (i <= b) do begin
+                let top = pos(code);
+                let while_at = code.len();
+                c(code, 0);
+                c(code, 3);
+                emit(code, Fetch, None);
+                c(code, 1);
+                emit(code, Fetch, None);
+                emit(code, LessEqual, None);
+                emit(code, If, None);
+                // sum := sum + a * i;
+                c(code, 2);
+                emit(code, Fetch, None);
+                c(code, 0);
+                emit(code, Fetch, None);
+                c(code, 3);
+                emit(code, Fetch, None);
+                emit(code, Mul, None);
+                emit(code, Add, None);
+                c(code, 2);
+                emit(code, Store, None);
+                // if (shared) then break;
+                let if_at = code.len();
+                c(code, 0);
+                emit(code, ConstString, Some(names[1] as i32));
+                emit(code, FetchExternal, None);
+                emit(code, If, None);
+                let break_at = code.len();
+                c(code, 0);
+                emit(code, Jmp, None);
+                let if_end = pos(code);
+                patch(code, if_at, if_end);
+                // i := i + 1;
+                c(code, 3);
+                emit(code, Fetch, None);
+                c(code, 1);
+                emit(code, Add, None);
+                c(code, 3);
+                emit(code, Store, None);
+                c(code, top);
+                emit(code, Jmp, None);
+                let loop_end = pos(code);
+                patch(code, while_at, loop_end);
+                patch(code, break_at, loop_end);
+                // return sum;
+                c(code, 2);
+                emit(code, Fetch, None);
+                ret(code);
+
+                vec![start_pos as usize, add_pos as usize, 0]
+            });
+
+        let actual = decompile(&prg).to_string();
+        assert_eq!(actual, "\
+procedure start;
+critical procedure add(variable a, variable b);
+import procedure ext_proc;
+
+export variable shared := 1;
+variable counter := 7;
+
+procedure start begin
+   variable total := 0;
+   display_msg(\"hello\");
+   total := add(global_var(2), 3);
+   if (total > 5) then begin
+      counter := total;
+   end
+   else begin
+      counter := -total;
+   end
+end
+
+procedure add(variable a, variable b) begin
+   variable sum := 0;
+   variable i := 1;
+   while (i <= b) do begin
+      sum := sum + a * i;
+      if (shared) then begin
+         break;
+      end
+      i := i + 1;
+   end
+   return sum;
+end
+");
+    }
+
+    #[test]
+    fn fallback() {
+        let none = BitFlags::empty();
+        let prg = build(&[("start", none, 1), ("bad", none, 0)], &["unused"], &[],
+            |code, start, _, _| {
+                emit(code, SetGlobal, None);
+                emit(code, ExitProg, None);
+
+                let start_pos = start + code.len();
+                emit(code, PushBase, None);
+                c(code, 0);
+                emit(code, Fetch, None);
+                emit(code, Pop, None);
+                c(code, 0);
+                ret(code);
+
+                let bad_pos = start + code.len();
+                emit(code, PushBase, None);
+                c(code, 1);
+                emit(code, Dup, None);
+                emit(code, Pop, None);
+                emit(code, Pop, None);
+                c(code, 0);
+                ret(code);
+
+                vec![start_pos, bad_pos]
+            });
+        let bad_pos = prg.proc(1).unwrap().body_pos;
+
+        assert_eq!(decompile(&prg).to_string(), format!("\
+procedure start(variable arg0);
+procedure bad;
+
+procedure start(variable arg0) begin
+   arg0;
+end
+
+procedure bad begin
+   /* couldn't decompile: 0x{0:04x}: unsupported instruction dup
+      0x{1:04x}: 802b push_base
+      0x{2:04x}: c001 const_long 1
+      0x{0:04x}: 801b dup
+      0x{3:04x}: 801a pop
+      0x{4:04x}: 801a pop
+      0x{5:04x}: c001 const_long 0
+      0x{6:04x}: 800d d_to_a
+      0x{7:04x}: 8019 swapa
+      0x{8:04x}: 802a pop_to_base
+      0x{9:04x}: 8029 pop_base
+      0x{10:04x}: 8019 swapa
+      0x{11:04x}: 8020 pop_flags_return
+   */
+end
+", bad_pos + 8, bad_pos, bad_pos + 2, bad_pos + 10, bad_pos + 12, bad_pos + 14, bad_pos + 20,
+    bad_pos + 22, bad_pos + 24, bad_pos + 26, bad_pos + 28, bad_pos + 30));
+    }
+
+    /// Code shapes of the original compiler that differ from the conventions of `compile`.
+    #[test]
+    fn original_compiler() {
+        let none = BitFlags::empty();
+        let prg = build(&[("start", none, 1), ("nested", none, 0)], &[], &["done"],
+            |code, start, _, strings| {
+                let pos = |code: &Vec<u8>| (start + code.len()) as i32;
+                let patch = |code: &mut Vec<u8>, at: usize, v: i32| {
+                    code[at + 2..at + 6].copy_from_slice(&v.to_be_bytes());
+                };
+
+                emit(code, SetGlobal, None);
+                emit(code, ExitProg, None);
+
+                // procedure start(variable arg0)
+                let start_pos = pos(code);
+                emit(code, PushBase, None);
+                // if (arg0) then display_msg("done");
+                // The jump over the missing `else` is still emitted.
+                let if_at = code.len();
+                c(code, 0);
+                c(code, 0);
+                emit(code, Fetch, None);
+                emit(code, If, None);
+                emit(code, ConstString, Some(strings[0] as i32));
+                emit(code, DisplayMsg, None);
+                let jmp_at = code.len();
+                c(code, 0);
+                emit(code, Jmp, None);
+                let end = pos(code);
+                patch(code, if_at, end);
+                patch(code, jmp_at, end);
+                // return arg0; followed by the implicit return 0.
+                c(code, 0);
+                emit(code, Fetch, None);
+                ret(code);
+                c(code, 0);
+                ret(code);
+
+                // procedure nested
+                // Jump out of two loops at once has no SSL equivalent.
+                let nested_pos = pos(code);
+                emit(code, PushBase, None);
+                let outer = pos(code);
+                let outer_at = code.len();
+                c(code, 0);
+                c(code, 1);
+                emit(code, While, None);
+                let inner = pos(code);
+                let inner_at = code.len();
+                c(code, 0);
+                c(code, 1);
+                emit(code, While, None);
+                let exit_at = code.len();
+                c(code, 0);
+                emit(code, Jmp, None);
+                c(code, inner);
+                emit(code, Jmp, None);
+                let inner_end = pos(code);
+                patch(code, inner_at, inner_end);
+                c(code, outer);
+                emit(code, Jmp, None);
+                let outer_end = pos(code);
+                patch(code, outer_at, outer_end);
+                patch(code, exit_at, outer_end);
+                c(code, 0);
+                ret(code);
+
+                vec![start_pos as usize, nested_pos as usize]
+            });
+        let p = prg.proc(1).unwrap().body_pos;
+
+        assert_eq!(decompile(&prg).to_string(), format!("\
+procedure start(variable arg0);
+procedure nested;
+
+procedure start(variable arg0) begin
+   if (arg0) then begin
+      display_msg(\"done\");
+   end
+   return arg0;
+end
+
+procedure nested begin
+   /* couldn't decompile: 0x{:04x}: unstructured jump to 0x{:04x}
+      0x{:04x}: 802b push_base
+      0x{:04x}: c001 const_long {}  ; -> 0x{:04x}
+      0x{:04x}: c001 const_long 1
+      0x{:04x}: 8030 while
+      0x{:04x}: c001 const_long {}  ; -> 0x{:04x}
+      0x{:04x}: c001 const_long 1
+      0x{:04x}: 8030 while
+      0x{:04x}: c001 const_long {}  ; -> 0x{:04x}
+      0x{:04x}: 8004 jmp
+      0x{:04x}: c001 const_long {}  ; -> 0x{:04x}
+      0x{:04x}: 8004 jmp
+      0x{:04x}: c001 const_long {}  ; -> 0x{:04x}
+      0x{:04x}: 8004 jmp
+      0x{:04x}: c001 const_long 0
+      0x{:04x}: 800d d_to_a
+      0x{:04x}: 8019 swapa
+      0x{:04x}: 802a pop_to_base
+      0x{:04x}: 8029 pop_base
+      0x{:04x}: 8019 swapa
+      0x{:04x}: 8020 pop_flags_return
+   */
+end
+",
+            p + 36, p + 54,
+            p, p + 2, p + 54, p + 54, p + 8, p + 14,
+            p + 16, p + 46, p + 46, p + 22, p + 28,
+            p + 30, p + 54, p + 54, p + 36,
+            p + 38, p + 16, p + 16, p + 44,
+            p + 46, p + 2, p + 2, p + 52,
+            p + 54, p + 60, p + 62, p + 64, p + 66, p + 68, p + 70));
+    }
+}
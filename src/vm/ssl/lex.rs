@@ -0,0 +1,537 @@
+use bstring::BString;
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::rc::Rc;
+
+/// Source location.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Loc {
+    pub file: Rc<str>,
+    pub line: u32,
+}
+
+impl fmt::Display for Loc {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}", self.file, self.line)
+    }
+}
+
+pub fn error(loc: &Loc, msg: impl fmt::Display) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", loc, msg))
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Token {
+    Ident(String),
+    Int(i32),
+    Float(f32),
+    String(BString),
+    Punct(&'static str),
+    /// Preprocessor directive: `#name` at the start of line.
+    Directive(String),
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Ident(v) => write!(f, "`{}`", v),
+            Self::Int(v) => write!(f, "`{}`", v),
+            Self::Float(v) => write!(f, "`{:?}`", v),
+            Self::String(v) => write!(f, "\"{}\"", v.display()),
+            Self::Punct(v) => write!(f, "`{}`", v),
+            Self::Directive(v) => write!(f, "`#{}`", v),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Tok {
+    pub token: Token,
+    pub loc: Loc,
+    /// Whether this is the first token of a line. Escaped line breaks don't start a new line.
+    line_start: bool,
+    /// Whether the token is preceded by whitespace or comment.
+    space_before: bool,
+}
+
+/// Longer punctuators go first.
+const PUNCTS: &[&str] = &[
+    ":=", "==", "!=", "<=", ">=", "+=", "-=", "*=", "/=", "++", "--", "&&", "||",
+    "(", ")", ",", ";", "=", "<", ">", "+", "-", "*", "/", "%", "!", ":",
+];
+
+fn tokenize(file: &Rc<str>, src: &[u8]) -> io::Result<Vec<Tok>> {
+    let mut r = Vec::new();
+    let mut i = 0;
+    let mut line = 1;
+    let mut line_start = true;
+    let mut space_before = false;
+    while i < src.len() {
+        let loc = Loc { file: file.clone(), line };
+        let c = src[i];
+        match c {
+            b'\n' => {
+                line += 1;
+                line_start = true;
+                space_before = true;
+                i += 1;
+                continue;
+            }
+            b'\\' if src[i + 1..].starts_with(b"\n") || src[i + 1..].starts_with(b"\r\n") => {
+                line += 1;
+                space_before = true;
+                i += if src[i + 1] == b'\r' { 3 } else { 2 };
+                continue;
+            }
+            _ if c.is_ascii_whitespace() => {
+                space_before = true;
+                i += 1;
+                continue;
+            }
+            b'/' if src[i + 1..].starts_with(b"/") => {
+                while i < src.len() && src[i] != b'\n' {
+                    i += 1;
+                }
+                space_before = true;
+                continue;
+            }
+            b'/' if src[i + 1..].starts_with(b"*") => {
+                i += 2;
+                loop {
+                    if i >= src.len() {
+                        return Err(error(&loc, "unterminated comment"));
+                    }
+                    if src[i..].starts_with(b"*/") {
+                        i += 2;
+                        break;
+                    }
+                    if src[i] == b'\n' {
+                        line += 1;
+                    }
+                    i += 1;
+                }
+                space_before = true;
+                continue;
+            }
+            _ => {}
+        }
+
+        let token = if c == b'#' && line_start {
+            i += 1;
+            let start = i;
+            while i < src.len() && src[i].is_ascii_alphabetic() {
+                i += 1;
+            }
+            Token::Directive(String::from_utf8_lossy(&src[start..i]).to_ascii_lowercase())
+        } else if c.is_ascii_alphabetic() || c == b'_' {
+            let start = i;
+            while i < src.len() && (src[i].is_ascii_alphanumeric() || src[i] == b'_') {
+                i += 1;
+            }
+            Token::Ident(String::from_utf8_lossy(&src[start..i]).into_owned())
+        } else if c.is_ascii_digit() {
+            let start = i;
+            let hex = src[i..].starts_with(b"0x") || src[i..].starts_with(b"0X");
+            if hex {
+                i += 2;
+            }
+            while i < src.len() && (src[i].is_ascii_alphanumeric() || src[i] == b'.'
+                || (matches!(src[i], b'+' | b'-') && matches!(src[i - 1], b'e' | b'E') && !hex))
+            {
+                i += 1;
+            }
+            let s = std::str::from_utf8(&src[start..i]).unwrap();
+            let bad = || error(&loc, format_args!("bad number `{}`", s));
+            if hex {
+                Token::Int(u32::from_str_radix(&s[2..], 16).map_err(|_| bad())? as i32)
+            } else if s.contains(['.', 'e', 'E']) {
+                Token::Float(s.parse().map_err(|_| bad())?)
+            } else {
+                // Values up to u32::MAX are accepted and wrap around.
+                Token::Int(s.parse::<u32>().map_err(|_| bad())? as i32)
+            }
+        } else if c == b'"' {
+            i += 1;
+            let mut s = BString::new();
+            loop {
+                match src.get(i) {
+                    None => return Err(error(&loc, "unterminated string")),
+                    Some(b'"') => break,
+                    Some(b'\\') if matches!(src.get(i + 1), Some(b'"' | b'\\')) => {
+                        s.push(src[i + 1]);
+                        i += 2;
+                    }
+                    Some(&c) => {
+                        if c == b'\n' {
+                            line += 1;
+                        }
+                        s.push(c);
+                        i += 1;
+                    }
+                }
+            }
+            i += 1;
+            Token::String(s)
+        } else if let Some(&p) = PUNCTS.iter().find(|p| src[i..].starts_with(p.as_bytes())) {
+            i += p.len();
+            Token::Punct(p)
+        } else {
+            return Err(error(&loc, format_args!("unexpected character `{}`", c as char)));
+        };
+        r.push(Tok { token, loc, line_start, space_before });
+        line_start = false;
+        space_before = false;
+    }
+    Ok(r)
+}
+
+/// Resolves `path` relative to the directory of `base`. Both `/` and `\` are accepted as
+/// separators.
+fn resolve_path(base: &str, path: &str) -> String {
+    let path = path.replace('\\', "/");
+    let mut parts: Vec<&str> = if path.starts_with('/') {
+        Vec::new()
+    } else {
+        base.split('/').collect()
+    };
+    parts.pop();
+    for p in path.split('/') {
+        match p {
+            "" | "." => {}
+            ".." => if parts.last().is_some_and(|&l| l != "..") {
+                parts.pop();
+            } else {
+                parts.push(p);
+            }
+            _ => parts.push(p),
+        }
+    }
+    parts.join("/")
+}
+
+struct Macro {
+    /// Parameters of function-like macro.
+    params: Option<Vec<String>>,
+    body: Vec<Tok>,
+}
+
+struct Cond {
+    active: bool,
+    /// Whether the `#else` branch was seen.
+    else_: bool,
+    loc: Loc,
+}
+
+const MAX_INCLUDE_DEPTH: usize = 32;
+
+struct Preprocessor<'a> {
+    read: &'a mut dyn FnMut(&str) -> io::Result<Vec<u8>>,
+    defines: HashMap<String, Rc<Macro>>,
+    out: Vec<Tok>,
+    depth: usize,
+}
+
+impl Preprocessor<'_> {
+    fn file(&mut self, path: &str, loc: Option<&Loc>) -> io::Result<()> {
+        if self.depth >= MAX_INCLUDE_DEPTH {
+            return Err(error(loc.unwrap(), "too many nested includes"));
+        }
+        let src = (self.read)(path).map_err(|e| match loc {
+            Some(loc) => error(loc, format_args!("couldn't read `{}`: {}", path, e)),
+            None => e,
+        })?;
+        let file: Rc<str> = path.into();
+        let toks = tokenize(&file, &src)?;
+
+        self.depth += 1;
+        let mut conds: Vec<Cond> = Vec::new();
+        let mut pending = Vec::new();
+        let mut i = 0;
+        while i < toks.len() {
+            let tok = &toks[i];
+            let Token::Directive(name) = &tok.token else {
+                if conds.iter().all(|c| c.active) {
+                    pending.push(tok.clone());
+                }
+                i += 1;
+                continue;
+            };
+            let loc = &tok.loc;
+            let end = toks[i + 1..].iter().position(|t| t.line_start).map(|p| i + 1 + p)
+                .unwrap_or(toks.len());
+            let args = &toks[i + 1..end];
+            i = end;
+
+            let active = conds.iter().all(|c| c.active);
+            match name.as_str() {
+                "ifdef" | "ifndef" => {
+                    let ifdef = name == "ifdef";
+                    let name = Self::macro_name(loc, args)?;
+                    conds.push(Cond {
+                        active: self.defines.contains_key(name) == ifdef,
+                        else_: false,
+                        loc: loc.clone(),
+                    });
+                    continue;
+                }
+                "else" => {
+                    match conds.last_mut() {
+                        Some(c) if !c.else_ => {
+                            c.active = !c.active;
+                            c.else_ = true;
+                        }
+                        _ => return Err(error(loc, "unexpected #else")),
+                    }
+                    continue;
+                }
+                "endif" => {
+                    if conds.pop().is_none() {
+                        return Err(error(loc, "unexpected #endif"));
+                    }
+                    continue;
+                }
+                _ if !active => continue,
+                _ => {}
+            }
+
+            let pending = std::mem::take(&mut pending);
+            self.expand(&pending, &mut Vec::new())?;
+            match name.as_str() {
+                "include" => {
+                    let [Tok { token: Token::String(p), .. }] = args else {
+                        return Err(error(loc, "expected file name in quotes"));
+                    };
+                    let p = resolve_path(path, &p.display().to_string());
+                    self.file(&p, Some(loc))?;
+                }
+                "define" => {
+                    let Some(Tok { token: Token::Ident(name), .. }) = args.first() else {
+                        return Err(error(loc, "expected macro name"));
+                    };
+                    let mut body = &args[1..];
+                    let params = if let Some(Tok { token: Token::Punct("("), space_before: false, .. })
+                        = body.first()
+                    {
+                        let close = body.iter().position(|t| t.token == Token::Punct(")"))
+                            .ok_or_else(|| error(loc, "expected `)`"))?;
+                        let mut params = Vec::new();
+                        for (j, t) in body[1..close].iter().enumerate() {
+                            match &t.token {
+                                Token::Ident(p) if j % 2 == 0 => params.push(p.clone()),
+                                Token::Punct(",") if j % 2 == 1 => {}
+                                _ => return Err(error(loc, format_args!(
+                                    "unexpected {} in macro parameters", t.token))),
+                            }
+                        }
+                        body = &body[close + 1..];
+                        Some(params)
+                    } else {
+                        None
+                    };
+                    self.defines.insert(name.clone(), Rc::new(Macro { params, body: body.to_vec() }));
+                }
+                "undef" => {
+                    let name = Self::macro_name(loc, args)?;
+                    self.defines.remove(name);
+                }
+                _ => return Err(error(loc, format_args!("unsupported directive #{}", name))),
+            }
+        }
+        if let Some(c) = conds.last() {
+            return Err(error(&c.loc, "unterminated conditional directive"));
+        }
+        self.expand(&pending, &mut Vec::new())?;
+        self.depth -= 1;
+        Ok(())
+    }
+
+    fn macro_name<'t>(loc: &Loc, args: &'t [Tok]) -> io::Result<&'t str> {
+        match args {
+            [Tok { token: Token::Ident(name), .. }] => Ok(name),
+            _ => Err(error(loc, "expected macro name")),
+        }
+    }
+
+    /// Expands macros in `toks` and appends the result to `out`. Macros listed in `hidden` are
+    /// being expanded and are not expanded again.
+    fn expand(&mut self, toks: &[Tok], hidden: &mut Vec<String>) -> io::Result<()> {
+        let mut out = std::mem::take(&mut self.out);
+        let r = self.expand_into(toks, hidden, &mut out);
+        self.out = out;
+        r
+    }
+
+    fn expand_into(&self, toks: &[Tok], hidden: &mut Vec<String>, out: &mut Vec<Tok>)
+        -> io::Result<()>
+    {
+        let mut i = 0;
+        while i < toks.len() {
+            let tok = &toks[i];
+            i += 1;
+            let m = match &tok.token {
+                Token::Ident(name) if !hidden.contains(name) => self.defines.get(name),
+                _ => None,
+            };
+            let Some(m) = m else {
+                out.push(tok.clone());
+                continue;
+            };
+            let Token::Ident(name) = &tok.token else { unreachable!() };
+
+            let body = if let Some(params) = &m.params {
+                if toks.get(i).map(|t| &t.token) != Some(&Token::Punct("(")) {
+                    out.push(tok.clone());
+                    continue;
+                }
+                i += 1;
+                let mut args = vec![Vec::new()];
+                let mut depth = 0;
+                loop {
+                    let Some(t) = toks.get(i) else {
+                        return Err(error(&tok.loc,
+                            format_args!("unterminated invocation of macro `{}`", name)));
+                    };
+                    i += 1;
+                    match t.token {
+                        Token::Punct(")") if depth == 0 => break,
+                        Token::Punct(",") if depth == 0 => {
+                            args.push(Vec::new());
+                            continue;
+                        }
+                        Token::Punct("(") => depth += 1,
+                        Token::Punct(")") => depth -= 1,
+                        _ => {}
+                    }
+                    args.last_mut().unwrap().push(t.clone());
+                }
+                if params.is_empty() && args.len() == 1 && args[0].is_empty() {
+                    args.clear();
+                }
+                if args.len() != params.len() {
+                    return Err(error(&tok.loc, format_args!(
+                        "macro `{}` expects {} arguments, got {}", name, params.len(), args.len())));
+                }
+                let mut expanded = Vec::with_capacity(args.len());
+                for a in &args {
+                    let mut e = Vec::new();
+                    self.expand_into(a, hidden, &mut e)?;
+                    expanded.push(e);
+                }
+                let mut body = Vec::new();
+                for t in &m.body {
+                    match &t.token {
+                        Token::Ident(p) if params.contains(p) => {
+                            let j = params.iter().position(|v| v == p).unwrap();
+                            body.extend(expanded[j].iter().cloned());
+                        }
+                        _ => body.push(t.clone()),
+                    }
+                }
+                body
+            } else {
+                m.body.clone()
+            };
+            let body: Vec<_> = body.into_iter()
+                .map(|t| Tok { loc: tok.loc.clone(), ..t })
+                .collect();
+
+            hidden.push(name.clone());
+            let r = self.expand_into(&body, hidden, out);
+            hidden.pop();
+            r?;
+        }
+        Ok(())
+    }
+}
+
+/// Reads the source file at `path` with the `read` function, runs the preprocessor and returns
+/// the resulting tokens. Supported directives are `#include`, `#define` (including function-like
+/// macros), `#undef`, `#ifdef`, `#ifndef`, `#else` and `#endif`. Included files are resolved
+/// relative to the including file.
+pub fn preprocess(path: &str, read: &mut dyn FnMut(&str) -> io::Result<Vec<u8>>)
+    -> io::Result<Vec<Tok>>
+{
+    let mut pp = Preprocessor {
+        read,
+        defines: HashMap::new(),
+        out: Vec::new(),
+        depth: 0,
+    };
+    pp.file(path, None)?;
+    Ok(pp.out)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn pp(files: &[(&str, &str)]) -> io::Result<Vec<Token>> {
+        let mut read = |p: &str| files.iter().find(|f| f.0 == p)
+            .map(|f| f.1.as_bytes().to_vec())
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, p.to_owned()));
+        preprocess(files[0].0, &mut read).map(|v| v.into_iter().map(|t| t.token).collect())
+    }
+
+    fn idents(s: &str) -> Vec<Token> {
+        s.split(' ').map(|s| if let Ok(v) = s.parse() {
+            Token::Int(v)
+        } else if let Some(&p) = PUNCTS.iter().find(|&&p| p == s) {
+            Token::Punct(p)
+        } else {
+            Token::Ident(s.into())
+        }).collect()
+    }
+
+    #[test]
+    fn tokenize_() {
+        assert_eq!(pp(&[("a.ssl", "x:=0x1F+ 2.5e1 // c\n/* \n */ \"a\\\"b\" <= -3")]).unwrap(), vec![
+            Token::Ident("x".into()),
+            Token::Punct(":="),
+            Token::Int(31),
+            Token::Punct("+"),
+            Token::Float(25.0),
+            Token::String("a\"b".into()),
+            Token::Punct("<="),
+            Token::Punct("-"),
+            Token::Int(3),
+        ]);
+        assert_eq!(pp(&[("a.ssl", "\"abc")]).unwrap_err().to_string(),
+            "a.ssl:1: unterminated string");
+        assert_eq!(pp(&[("a.ssl", "\n\n x $")]).unwrap_err().to_string(),
+            "a.ssl:3: unexpected character `$`");
+    }
+
+    #[test]
+    fn preprocess_() {
+        let files = &[
+            ("scripts/test.ssl", "\
+                #include \"..\\headers\\define.h\"\n\
+                #ifdef DEBUG\n\
+                not_included\n\
+                #else\n\
+                ADD(ONE, TWO) SQR(ADD(1, 2))\n\
+                #endif\n\
+                #undef ONE\n\
+                ONE LOOP"),
+            ("headers/define.h", "\
+                #ifndef DEFINE_H\n\
+                #define DEFINE_H\n\
+                #define ONE 1\n\
+                #define TWO ONE + \\\n   ONE\n\
+                #define ADD(a, b) (a + b)\n\
+                #define SQR(x) x * x\n\
+                #define LOOP LOOP ONE\n\
+                #endif"),
+        ];
+        assert_eq!(pp(files).unwrap(),
+            idents("( 1 + 1 + 1 ) ( 1 + 2 ) * ( 1 + 2 ) ONE LOOP ONE"));
+
+        assert_eq!(pp(&[("a.ssl", "#define F(x) x\nF(1, 2)")]).unwrap_err().to_string(),
+            "a.ssl:2: macro `F` expects 1 arguments, got 2");
+        assert_eq!(pp(&[("a/b.ssl", "\n#include \"c.h\"")]).unwrap_err().to_string(),
+            "a/b.ssl:2: couldn't read `a/c.h`: a/c.h");
+        assert_eq!(pp(&[("a.ssl", "#ifdef X\n")]).unwrap_err().to_string(),
+            "a.ssl:1: unterminated conditional directive");
+    }
+}
@@ -0,0 +1,609 @@
+use std::collections::HashMap;
+use std::io;
+
+use super::*;
+use super::lex::{self, Loc, Tok, Token};
+
+type Result<T> = io::Result<T>;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Global {
+    Var,
+    Extern,
+    Proc(usize),
+}
+
+struct Parser {
+    toks: Vec<Tok>,
+    pos: usize,
+    /// Location reported for errors at the end of input.
+    eof: Loc,
+    script: Script,
+    /// Top-level names.
+    globals: HashMap<String, Global>,
+    /// Location of the first procedure declaration by procedure index.
+    proc_locs: Vec<Loc>,
+    /// Names of the arguments and variables of the current procedure.
+    locals: Vec<String>,
+    /// Nesting level of `while` loops in the current procedure.
+    loop_depth: u32,
+    /// Whether no statements were parsed in the current procedure yet.
+    prologue: bool,
+}
+
+/// Checks `e` doesn't contain builtins without return value.
+fn check_value(loc: &Loc, e: &Expr) -> Result<()> {
+    match e {
+        Expr::Builtin(opcode, args) => {
+            let b = builtin::by_opcode(*opcode).unwrap();
+            if !b.returns {
+                return Err(lex::error(loc, format_args!("`{}` doesn't return a value", b.name)));
+            }
+            args.iter().try_for_each(|a| check_value(loc, a))
+        }
+        Expr::Call(_, args) => args.iter().try_for_each(|a| check_value(loc, a)),
+        Expr::Unary(_, e) => check_value(loc, e),
+        Expr::Binary(_, l, r) => {
+            check_value(loc, l)?;
+            check_value(loc, r)
+        }
+        Expr::Int(_) | Expr::Float(_) | Expr::String(_) | Expr::Var(_) => Ok(()),
+    }
+}
+
+fn is_keyword(s: &str, kw: &str) -> bool {
+    s.eq_ignore_ascii_case(kw)
+}
+
+const KEYWORDS: &[&str] = &[
+    "and", "begin", "break", "bwand", "bwnot", "bwor", "bwxor", "call", "continue", "critical",
+    "do", "else", "end", "export", "if", "import", "not", "or", "procedure", "return", "then",
+    "variable", "while",
+];
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.toks.get(self.pos).map(|t| &t.token)
+    }
+
+    fn peek_at(&self, offset: usize) -> Option<&Token> {
+        self.toks.get(self.pos + offset).map(|t| &t.token)
+    }
+
+    fn loc(&self) -> &Loc {
+        self.toks.get(self.pos).map(|t| &t.loc).unwrap_or(&self.eof)
+    }
+
+    fn error<T>(&self, msg: impl fmt::Display) -> Result<T> {
+        Err(lex::error(self.loc(), msg))
+    }
+
+    fn unexpected<T>(&self, expected: &str) -> Result<T> {
+        match self.peek() {
+            Some(t) => self.error(format_args!("expected {}, found {}", expected, t)),
+            None => self.error(format_args!("expected {}, found end of file", expected)),
+        }
+    }
+
+    fn is_kw(&self, kw: &str) -> bool {
+        matches!(self.peek(), Some(Token::Ident(s)) if is_keyword(s, kw))
+    }
+
+    fn is_punct(&self, p: &str) -> bool {
+        matches!(self.peek(), Some(Token::Punct(v)) if *v == p)
+    }
+
+    fn eat_kw(&mut self, kw: &str) -> bool {
+        let r = self.is_kw(kw);
+        if r {
+            self.pos += 1;
+        }
+        r
+    }
+
+    fn eat_punct(&mut self, p: &str) -> bool {
+        let r = self.is_punct(p);
+        if r {
+            self.pos += 1;
+        }
+        r
+    }
+
+    fn expect_kw(&mut self, kw: &str) -> Result<()> {
+        if self.eat_kw(kw) {
+            Ok(())
+        } else {
+            self.unexpected(&format!("`{}`", kw))
+        }
+    }
+
+    fn expect_punct(&mut self, p: &str) -> Result<()> {
+        if self.eat_punct(p) {
+            Ok(())
+        } else {
+            self.unexpected(&format!("`{}`", p))
+        }
+    }
+
+    fn ident(&mut self) -> Result<String> {
+        match self.peek() {
+            Some(Token::Ident(s)) if !KEYWORDS.iter().any(|kw| is_keyword(s, kw)) => {
+                let s = s.clone();
+                self.pos += 1;
+                Ok(s)
+            }
+            _ => self.unexpected("identifier"),
+        }
+    }
+
+    /// Parses a new name checking it doesn't clash with the existing ones.
+    fn new_name(&mut self, local: bool) -> Result<String> {
+        let clash = match self.peek() {
+            Some(Token::Ident(s)) => (local && self.locals.contains(s))
+                || (!local && self.globals.contains_key(s))
+                || builtin::by_name(s).is_some(),
+            _ => false,
+        };
+        if clash {
+            return self.error(format_args!("{} is already defined", self.peek().unwrap()));
+        }
+        self.ident()
+    }
+
+    fn script(&mut self) -> Result<()> {
+        while self.peek().is_some() {
+            if self.eat_punct(";") {
+                continue;
+            }
+            let mut flags = BitFlags::empty();
+            let mut extern_kind = None;
+            loop {
+                if self.eat_kw("import") {
+                    flags |= ProcedureFlag::Import;
+                    extern_kind = Some(ExternKind::Import);
+                } else if self.eat_kw("export") {
+                    flags |= ProcedureFlag::Export;
+                    extern_kind = Some(ExternKind::Export);
+                } else if self.eat_kw("critical") {
+                    flags |= ProcedureFlag::Critical;
+                } else {
+                    break;
+                }
+            }
+            if self.is_kw("procedure") {
+                self.procedure(flags)?;
+            } else if self.is_kw("variable") && !flags.contains(ProcedureFlag::Critical) {
+                self.global_vars(extern_kind)?;
+            } else {
+                self.unexpected("`procedure` or `variable`")?;
+            }
+        }
+        for (p, loc) in self.script.procs.iter().zip(&self.proc_locs) {
+            if p.body.is_none() && !p.flags.contains(ProcedureFlag::Import) {
+                return Err(lex::error(loc,
+                    format_args!("procedure `{}` is declared but not defined", p.name)));
+            }
+        }
+        Ok(())
+    }
+
+    /// Parses list of declarations following `variable` keyword: `a [:= init], ...;` or
+    /// `begin a [:= init]; ... end`.
+    fn var_list(&mut self, mut f: impl FnMut(&mut Self) -> Result<()>) -> Result<()> {
+        self.expect_kw("variable")?;
+        if self.eat_kw("begin") {
+            while !self.eat_kw("end") {
+                if !self.eat_punct(";") {
+                    f(self)?;
+                }
+            }
+        } else {
+            f(self)?;
+            while self.eat_punct(",") {
+                f(self)?;
+            }
+            self.expect_punct(";")?;
+        }
+        Ok(())
+    }
+
+    fn eat_assign(&mut self) -> bool {
+        self.eat_punct(":=") || self.eat_punct("=")
+    }
+
+    /// Parses literal allowed as initializer of global and external variables.
+    fn constant(&mut self) -> Result<Expr> {
+        let neg = self.eat_punct("-");
+        let r = match (self.peek(), neg) {
+            (Some(Token::Int(v)), _) => Expr::Int(if neg { v.wrapping_neg() } else { *v }),
+            (Some(Token::Float(v)), _) => Expr::Float(if neg { -v } else { *v }),
+            (Some(Token::String(v)), false) => Expr::String(v.clone()),
+            _ => return self.unexpected("constant"),
+        };
+        self.pos += 1;
+        Ok(r)
+    }
+
+    fn global_vars(&mut self, kind: Option<ExternKind>) -> Result<()> {
+        self.var_list(|p| {
+            let name = p.new_name(false)?;
+            let init = if p.eat_assign() {
+                if kind == Some(ExternKind::Import) {
+                    return p.error("imported variable can't have initializer");
+                }
+                Some(p.constant()?)
+            } else {
+                None
+            };
+            if let Some(kind) = kind {
+                p.globals.insert(name.clone(), Global::Extern);
+                p.script.externs.push(Extern { name, kind, init });
+            } else {
+                p.globals.insert(name.clone(), Global::Var);
+                p.script.vars.push(VarDecl { name, init });
+            }
+            Ok(())
+        })
+    }
+
+    fn procedure(&mut self, flags: BitFlags<ProcedureFlag>) -> Result<()> {
+        let loc = self.loc().clone();
+        self.expect_kw("procedure")?;
+        let existing = match self.peek() {
+            Some(Token::Ident(s)) => self.globals.get(s).copied(),
+            _ => None,
+        };
+        let (idx, name) = match existing {
+            Some(Global::Proc(idx)) => {
+                self.pos += 1;
+                (Some(idx), self.script.procs[idx].name.clone())
+            }
+            _ => (None, self.new_name(false)?),
+        };
+
+        let mut args = Vec::new();
+        if self.eat_punct("(") {
+            if !self.is_punct(")") {
+                loop {
+                    self.eat_kw("variable");
+                    let arg = self.ident()?;
+                    if args.contains(&arg) {
+                        return self.error(format_args!("duplicate argument `{}`", arg));
+                    }
+                    args.push(arg);
+                    if !self.eat_punct(",") {
+                        break;
+                    }
+                }
+            }
+            self.expect_punct(")")?;
+        }
+        if self.is_kw("in") || self.is_kw("when") {
+            return self.error("timed and conditional procedures are not supported");
+        }
+
+        let idx = if let Some(idx) = idx {
+            let p = &mut self.script.procs[idx];
+            if p.args.len() != args.len() {
+                return Err(lex::error(&loc, format_args!(
+                    "procedure `{}` was declared with {} arguments", name, p.args.len())));
+            }
+            p.flags |= flags;
+            p.args = args;
+            idx
+        } else {
+            let idx = self.script.procs.len();
+            self.globals.insert(name.clone(), Global::Proc(idx));
+            self.proc_locs.push(loc.clone());
+            self.script.procs.push(Procedure { name: name.clone(), flags, args, body: None });
+            idx
+        };
+
+        if self.eat_punct(";") {
+            return Ok(());
+        }
+        if self.script.procs[idx].flags.contains(ProcedureFlag::Import) {
+            return self.error("imported procedure can't have body");
+        }
+        if self.script.procs[idx].body.is_some() {
+            return Err(lex::error(&loc, format_args!("procedure `{}` is already defined", name)));
+        }
+
+        self.expect_kw("begin")?;
+        self.locals = self.script.procs[idx].args.clone();
+        self.loop_depth = 0;
+        self.prologue = true;
+        let mut vars = Vec::new();
+        let mut stmts = Vec::new();
+        while !self.eat_kw("end") {
+            self.stmt(&mut vars, &mut stmts)?;
+        }
+        self.script.procs[idx].body = Some(Body::Stmts { vars, stmts });
+        Ok(())
+    }
+
+    /// Parses statement or procedure variable declaration. Variables declared before the first
+    /// statement of procedure are initialized on entry, initializers of the rest are turned into
+    /// assignments.
+    fn stmt(&mut self, vars: &mut Vec<VarDecl>, out: &mut Vec<Stmt>) -> Result<()> {
+        if self.eat_punct(";") {
+            return Ok(());
+        }
+        if self.is_kw("variable") {
+            return self.var_list(|p| {
+                let name = p.new_name(true)?;
+                let init = if p.eat_assign() {
+                    Some(p.value()?)
+                } else {
+                    None
+                };
+                p.locals.push(name.clone());
+                if p.prologue {
+                    vars.push(VarDecl { name, init });
+                } else {
+                    if let Some(init) = init {
+                        out.push(Stmt::Assign(name.clone(), init));
+                    }
+                    vars.push(VarDecl { name, init: None });
+                }
+                Ok(())
+            });
+        }
+        self.prologue = false;
+
+        let stmt = if self.eat_kw("if") {
+            let cond = self.value()?;
+            self.expect_kw("then")?;
+            let then = self.branch(vars)?;
+            // Tolerate `;` after `end` of the then branch.
+            if self.is_punct(";") && matches!(self.peek_at(1), Some(Token::Ident(s)) if is_keyword(s, "else")) {
+                self.pos += 1;
+            }
+            let else_ = if self.eat_kw("else") {
+                self.branch(vars)?
+            } else {
+                Vec::new()
+            };
+            Stmt::If(cond, then, else_)
+        } else if self.eat_kw("while") {
+            let cond = self.value()?;
+            self.expect_kw("do")?;
+            self.loop_depth += 1;
+            let body = self.branch(vars)?;
+            self.loop_depth -= 1;
+            Stmt::While(cond, body)
+        } else if self.is_kw("break") || self.is_kw("continue") {
+            if self.loop_depth == 0 {
+                return self.error(format_args!("{} outside of loop", self.peek().unwrap()));
+            }
+            let s = if self.eat_kw("break") { Stmt::Break } else { self.pos += 1; Stmt::Continue };
+            self.expect_punct(";")?;
+            s
+        } else if self.eat_kw("return") {
+            let e = if self.is_punct(";") {
+                Expr::Int(0)
+            } else {
+                self.value()?
+            };
+            self.expect_punct(";")?;
+            Stmt::Return(e)
+        } else if self.eat_kw("call") {
+            let loc = self.loc().clone();
+            let name = self.ident()?;
+            let Some(&Global::Proc(idx)) = self.globals.get(&name) else {
+                return Err(lex::error(&loc, format_args!("`{}` is not a procedure", name)));
+            };
+            let args = if self.is_punct("(") {
+                self.args()?
+            } else {
+                Vec::new()
+            };
+            self.check_arg_count(&loc, &name, self.script.procs[idx].args.len(), args.len())?;
+            self.expect_punct(";")?;
+            Stmt::Expr(Expr::Call(name, args))
+        } else if let Some(Token::Ident(name)) = self.peek()
+            && self.is_var(name)
+            && matches!(self.peek_at(1), Some(Token::Punct(":=" | "=" | "+=" | "-=" | "*=" | "/="
+                | "++" | "--")))
+        {
+            let name = name.clone();
+            self.pos += 1;
+            let Some(Token::Punct(op)) = self.peek().cloned() else { unreachable!() };
+            self.pos += 1;
+            let var = || Box::new(Expr::Var(name.clone()));
+            let e = match op {
+                ":=" | "=" => self.value()?,
+                "++" => Expr::Binary(BinaryOp::Add, var(), Box::new(Expr::Int(1))),
+                "--" => Expr::Binary(BinaryOp::Sub, var(), Box::new(Expr::Int(1))),
+                _ => {
+                    let op = match op {
+                        "+=" => BinaryOp::Add,
+                        "-=" => BinaryOp::Sub,
+                        "*=" => BinaryOp::Mul,
+                        _ => BinaryOp::Div,
+                    };
+                    Expr::Binary(op, var(), Box::new(self.value()?))
+                }
+            };
+            self.expect_punct(";")?;
+            Stmt::Assign(name, e)
+        } else {
+            let loc = self.loc().clone();
+            let e = self.expr()?;
+            match &e {
+                Expr::Builtin(_, args) => for a in args {
+                    check_value(&loc, a)?;
+                }
+                _ => check_value(&loc, &e)?,
+            }
+            self.expect_punct(";")?;
+            Stmt::Expr(e)
+        };
+        out.push(stmt);
+        Ok(())
+    }
+
+    /// Parses `begin ... end` block or a single statement.
+    fn branch(&mut self, vars: &mut Vec<VarDecl>) -> Result<Vec<Stmt>> {
+        let mut r = Vec::new();
+        if self.eat_kw("begin") {
+            while !self.eat_kw("end") {
+                self.stmt(vars, &mut r)?;
+            }
+        } else {
+            self.stmt(vars, &mut r)?;
+        }
+        Ok(r)
+    }
+
+    fn is_var(&self, name: &str) -> bool {
+        self.locals.iter().any(|l| l == name)
+            || matches!(self.globals.get(name), Some(Global::Var | Global::Extern))
+    }
+
+    fn check_arg_count(&self, loc: &Loc, name: &str, expected: usize, actual: usize) -> Result<()> {
+        if expected == actual {
+            Ok(())
+        } else {
+            Err(lex::error(loc, format_args!("`{}` expects {} arguments, got {}",
+                name, expected, actual)))
+        }
+    }
+
+    fn args(&mut self) -> Result<Vec<Expr>> {
+        self.expect_punct("(")?;
+        let mut r = Vec::new();
+        if !self.eat_punct(")") {
+            loop {
+                r.push(self.value()?);
+                if self.eat_punct(")") {
+                    break;
+                }
+                self.expect_punct(",")?;
+            }
+        }
+        Ok(r)
+    }
+
+    fn binary_op(&self) -> Option<BinaryOp> {
+        Some(match self.peek()? {
+            Token::Punct("||") => BinaryOp::Or,
+            Token::Punct("&&") => BinaryOp::And,
+            Token::Punct(p) => *BinaryOp::ALL.iter().find(|op| op.token() == *p)?,
+            Token::Ident(s) => *BinaryOp::ALL.iter()
+                .find(|op| op.token().as_bytes()[0].is_ascii_alphabetic() && is_keyword(s, op.token()))?,
+            _ => return None,
+        })
+    }
+
+    fn expr(&mut self) -> Result<Expr> {
+        self.binary(1)
+    }
+
+    /// Parses expression checking it doesn't use builtins without return value.
+    fn value(&mut self) -> Result<Expr> {
+        let loc = self.loc().clone();
+        let e = self.expr()?;
+        check_value(&loc, &e)?;
+        Ok(e)
+    }
+
+    /// Parses binary expression with operators of at least `min_precedence`.
+    fn binary(&mut self, min_precedence: u32) -> Result<Expr> {
+        let mut l = self.unary()?;
+        while let Some(op) = self.binary_op().filter(|op| op.precedence() >= min_precedence) {
+            self.pos += 1;
+            let r = self.binary(op.precedence() + 1)?;
+            l = Expr::Binary(op, Box::new(l), Box::new(r));
+        }
+        Ok(l)
+    }
+
+    fn unary(&mut self) -> Result<Expr> {
+        if self.eat_punct("-") {
+            // Negative literal.
+            match self.peek() {
+                Some(&Token::Int(v)) => {
+                    self.pos += 1;
+                    return Ok(Expr::Int(v.wrapping_neg()));
+                }
+                Some(&Token::Float(v)) => {
+                    self.pos += 1;
+                    return Ok(Expr::Float(-v));
+                }
+                _ => return Ok(Expr::Unary(UnaryOp::Neg, Box::new(self.unary()?))),
+            }
+        }
+        let op = if self.eat_kw("not") || self.eat_punct("!") {
+            UnaryOp::Not
+        } else if self.eat_kw("bwnot") {
+            UnaryOp::BwNot
+        } else {
+            return self.primary();
+        };
+        Ok(Expr::Unary(op, Box::new(self.unary()?)))
+    }
+
+    fn primary(&mut self) -> Result<Expr> {
+        let loc = self.loc().clone();
+        let r = match self.peek() {
+            Some(&Token::Int(v)) => Expr::Int(v),
+            Some(&Token::Float(v)) => Expr::Float(v),
+            Some(Token::String(v)) => Expr::String(v.clone()),
+            Some(Token::Punct("(")) => {
+                self.pos += 1;
+                let e = self.expr()?;
+                self.expect_punct(")")?;
+                return Ok(e);
+            }
+            Some(Token::Ident(_)) => {
+                let name = self.ident()?;
+                if self.is_var(&name) {
+                    return Ok(Expr::Var(name));
+                }
+                if let Some(&Global::Proc(idx)) = self.globals.get(&name) {
+                    return Ok(if self.is_punct("(") {
+                        let args = self.args()?;
+                        self.check_arg_count(&loc, &name, self.script.procs[idx].args.len(),
+                            args.len())?;
+                        Expr::Call(name, args)
+                    } else {
+                        Expr::Var(name)
+                    });
+                }
+                let Some(b) = builtin::by_name(&name) else {
+                    return Err(lex::error(&loc, format_args!("undefined identifier `{}`", name)));
+                };
+                let args = if self.is_punct("(") {
+                    self.args()?
+                } else {
+                    Vec::new()
+                };
+                self.check_arg_count(&loc, &name, b.arg_count, args.len())?;
+                return Ok(Expr::Builtin(b.opcode, args));
+            }
+            _ => return self.unexpected("expression"),
+        };
+        self.pos += 1;
+        Ok(r)
+    }
+}
+
+/// Parses SSL source file at `path`. The files are read with the `read` function.
+pub fn parse(path: &str, read: &mut dyn FnMut(&str) -> io::Result<Vec<u8>>) -> Result<Script> {
+    let toks = lex::preprocess(path, read)?;
+    let eof = toks.last().map(|t| t.loc.clone()).unwrap_or(Loc { file: path.into(), line: 1 });
+    let mut p = Parser {
+        toks,
+        pos: 0,
+        eof,
+        script: Script::default(),
+        globals: HashMap::new(),
+        proc_locs: Vec::new(),
+        locals: Vec::new(),
+        loop_depth: 0,
+        prologue: false,
+    };
+    p.script()?;
+    Ok(p.script)
+}
@@ -16,6 +16,16 @@ pub struct Stack<Id> {
     _id: PhantomData<Id>,
 }
 
+impl<Id> Clone for Stack<Id> {
+    fn clone(&self) -> Self {
+        Self {
+            vec: self.vec.clone(),
+            max_len: self.max_len,
+            _id: PhantomData,
+        }
+    }
+}
+
 impl<Id: StackId> Stack<Id> {
     pub fn new(max_len: usize) -> Self {
         Self {